@@ -1,8 +1,8 @@
-use super::{ASTSpec, Reference};
+use super::{ASTSpec, Reference, SyntaxCategory};
 use crate::node_map::NodeMap;
 
 /// How many spaces corespond to one indentation level
-const INDENT_WIDTH: usize = 4;
+pub const INDENT_WIDTH: usize = 4;
 
 /// A single piece of a node that can be rendered to the screen
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -23,6 +23,57 @@ pub enum DisplayToken<Ref: Reference> {
     InvalidRef,
 }
 
+/// The whitespace that is written for each level of indentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IndentStyle {
+    /// Indent every level with a given number of spaces
+    Spaces(usize),
+    /// Indent every level with one tab character
+    Tabs,
+}
+
+impl IndentStyle {
+    /// Push the whitespace for a given number of indentation levels onto a [`String`]
+    pub fn push_levels(&self, string: &mut String, levels: usize) {
+        match self {
+            IndentStyle::Spaces(width) => {
+                for _ in 0..levels * width {
+                    string.push(' ');
+                }
+            }
+            IndentStyle::Tabs => {
+                for _ in 0..levels {
+                    string.push('\t');
+                }
+            }
+        }
+    }
+
+    /// Returns the number of columns that one level of indentation takes up on the screen, where
+    /// a tab is [`INDENT_WIDTH`] columns wide
+    pub fn width(&self) -> usize {
+        match self {
+            IndentStyle::Spaces(width) => *width,
+            IndentStyle::Tabs => INDENT_WIDTH,
+        }
+    }
+
+    /// Parses an indent style as given on the command line: either a number of spaces (e.g.
+    /// `2`) or `tab`.  Returns [`None`] if `arg` is neither.
+    pub fn from_arg(arg: &str) -> Option<IndentStyle> {
+        match arg {
+            "tab" | "tabs" => Some(IndentStyle::Tabs),
+            _ => arg.parse().ok().map(IndentStyle::Spaces),
+        }
+    }
+}
+
+impl Default for IndentStyle {
+    fn default() -> IndentStyle {
+        IndentStyle::Spaces(INDENT_WIDTH)
+    }
+}
+
 /// Write a stream of display tokens to a string
 pub fn write_tokens<Ref: Reference, Node: ASTSpec<Ref>>(
     root: Ref,
@@ -30,76 +81,295 @@ pub fn write_tokens<Ref: Reference, Node: ASTSpec<Ref>>(
     string: &mut String,
     format_style: &Node::FormatStyle,
 ) {
-    let mut indentation_string = String::new();
+    write_tokens_indented(
+        root,
+        node_map,
+        string,
+        format_style,
+        &IndentStyle::default(),
+    );
+}
+
+/// Write a stream of display tokens to a string, using a given [`IndentStyle`] for the
+/// indentation.  This only changes the whitespace at the start of each line, never the structure
+/// of the text.
+pub fn write_tokens_indented<Ref: Reference, Node: ASTSpec<Ref>>(
+    root: Ref,
+    node_map: &impl NodeMap<Ref, Node>,
+    string: &mut String,
+    format_style: &Node::FormatStyle,
+    indent_style: &IndentStyle,
+) {
+    write_spans(root, node_map, format_style, indent_style, |s, _| {
+        string.push_str(s)
+    });
+}
+
+/// Generate the text of the tree rooted at `root` as a sequence of spans, each with the
+/// [`SyntaxCategory`] of the node that generated it (or [`None`] for the whitespace between
+/// tokens).  Adjacent spans with the same category are merged, and concatenating the spans
+/// gives exactly the text written by [`write_tokens_indented`].
+pub fn styled_spans<Ref: Reference, Node: ASTSpec<Ref>>(
+    root: Ref,
+    node_map: &impl NodeMap<Ref, Node>,
+    format_style: &Node::FormatStyle,
+    indent_style: &IndentStyle,
+) -> Vec<(String, Option<SyntaxCategory>)> {
+    let mut spans: Vec<(String, Option<SyntaxCategory>)> = Vec::new();
+    write_spans(
+        root,
+        node_map,
+        format_style,
+        indent_style,
+        |s, category| match spans.last_mut() {
+            Some((text, c)) if *c == category => text.push_str(s),
+            _ => spans.push((s.to_string(), category)),
+        },
+    );
+    spans
+}
+
+/// Call `push` with every piece of the text of the tree rooted at `root`, along with the
+/// [`SyntaxCategory`] of the node that generated it (or [`None`] for whitespace)
+fn write_spans<Ref: Reference, Node: ASTSpec<Ref>>(
+    root: Ref,
+    node_map: &impl NodeMap<Ref, Node>,
+    format_style: &Node::FormatStyle,
+    indent_style: &IndentStyle,
+    mut push: impl FnMut(&str, Option<SyntaxCategory>),
+) {
+    let mut indentation_level = 0usize;
+    let mut whitespace = String::new();
     // Process the token string
     for (id, token) in flat_tokens(node_map, root, format_style) {
         match token {
             DisplayToken::Text(s) => {
                 // Push the string we've been given
-                string.push_str(&s);
+                let category = node_map.get_node(id).map(|node| node.syntax_category());
+                push(&s, category);
             }
             DisplayToken::Child(_c) => {
                 unreachable!();
             }
             DisplayToken::Whitespace(n) => {
-                // Push 'n' many spaces
-                for _ in 0..n {
-                    string.push(' ');
-                }
+                // Push 'n' many spaces, reusing the whitespace buffer so that rendering doesn't
+                // allocate for every space between tokens
+                whitespace.clear();
+                whitespace.extend(std::iter::repeat_n(' ', n));
+                push(&whitespace, None);
             }
             DisplayToken::Newline => {
                 // Push a newline and keep indentation
-                string.push('\n');
-                string.push_str(&indentation_string);
+                whitespace.clear();
+                whitespace.push('\n');
+                indent_style.push_levels(&mut whitespace, indentation_level);
+                push(&whitespace, None);
             }
             DisplayToken::Indent => {
-                // Add `INDENT_WIDTH` spaces to the indentation_string
-                for _ in 0..INDENT_WIDTH {
-                    indentation_string.push(' ');
-                }
+                indentation_level += 1;
             }
             DisplayToken::Dedent => {
-                // Remove `INDENT_WIDTH` spaces to the indentation_string
-                for _ in 0..INDENT_WIDTH {
-                    let popped_char = indentation_string.pop();
-                    debug_assert_eq!(popped_char, Some(' '));
-                }
+                debug_assert!(indentation_level > 0);
+                indentation_level = indentation_level.saturating_sub(1);
             }
             DisplayToken::InvalidRef => {
                 // Add a helpful error string
-                string.push_str(&format!("<INVALID REF {:?}>", id));
+                push(&format!("<INVALID REF {:?}>", id), None);
             }
         }
     }
 }
 
+/// If we every encounter a newline followed by an indent/dedent, we should swap them round so
+/// that the indent/dedent is always first (and so applies to the line after the newline).
+fn move_indents_before_newlines<Ref: Reference>(tokens: &mut [DisplayToken<Ref>]) {
+    for i in 0..tokens.len().saturating_sub(1) {
+        if tokens[i] == DisplayToken::Newline
+            && (tokens[i + 1] == DisplayToken::Indent || tokens[i + 1] == DisplayToken::Dedent)
+        {
+            tokens.swap(i, i + 1);
+        }
+    }
+}
+
+/// A position in some text, counting lines and columns (in [`char`]s, like [`Size`]) from 0
+///
+/// [`Size`]: super::size::Size
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TextPos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl TextPos {
+    /// Creates a `TextPos` at a given line and column
+    pub fn new(line: usize, col: usize) -> Self {
+        TextPos { line, col }
+    }
+
+    /// Move this position past `text`
+    fn advance(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 0;
+            } else {
+                self.col += 1;
+            }
+        }
+    }
+}
+
+/// The range of some text occupied by the text of a node, from `start` up to (but not including)
+/// `end`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub start: TextPos,
+    pub end: TextPos,
+}
+
+impl Span {
+    /// Returns `true` if the text at `pos` is part of this `Span`
+    pub fn contains(&self, pos: TextPos) -> bool {
+        self.start <= pos && pos < self.end
+    }
+}
+
+/// Where the text of every node landed in the text of a tree, as generated by
+/// [`write_text_with_layout`].  A node that appears more than once in the tree (because it's
+/// shared) has a [`Span`] for every appearance.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextLayout<Ref: Reference> {
+    /// The span of every node, in the order that the nodes start in the text (so every node comes
+    /// before its descendants)
+    spans: Vec<(Ref, Span)>,
+}
+
+impl<Ref: Reference> TextLayout<Ref> {
+    /// Returns the [`Span`] of the text generated by the node at `id` and its descendants, or
+    /// [`None`] if it isn't in the text.  If the node appears more than once, this is the first
+    /// appearance.
+    pub fn span_of(&self, id: Ref) -> Option<Span> {
+        self.spans.iter().find(|(r, _)| *r == id).map(|(_, s)| *s)
+    }
+
+    /// Returns the innermost node whose text covers `pos`, or [`None`] if `pos` is outside the
+    /// text of the tree.  Whitespace between the children of a node is covered by that node.
+    pub fn node_at(&self, pos: TextPos) -> Option<Ref> {
+        // The spans of a node's descendants come after (and are inside) its own span, and the
+        // spans of siblings don't overlap, so the last span covering `pos` is the innermost
+        self.spans
+            .iter()
+            .rev()
+            .find(|(_, span)| span.contains(pos))
+            .map(|(r, _)| *r)
+    }
+
+    /// Returns the span of every node, in the order that the nodes start in the text
+    pub fn spans(&self) -> &[(Ref, Span)] {
+        &self.spans
+    }
+}
+
+/// Write the text of the tree rooted at `root` to `string`, exactly as
+/// [`write_tokens_indented`] does, and return the [`TextLayout`] recording where the text of each
+/// node was written.  Positions are relative to the start of the text written by this call.
+/// This doesn't recurse, so works for arbitrarily deep trees.
+pub fn write_text_with_layout<Ref: Reference, Node: ASTSpec<Ref>>(
+    root: Ref,
+    node_map: &impl NodeMap<Ref, Node>,
+    string: &mut String,
+    format_style: &Node::FormatStyle,
+    indent_style: &IndentStyle,
+) -> TextLayout<Ref> {
+    let mut layout = TextLayout { spans: Vec::new() };
+    let mut pos = TextPos::new(0, 0);
+    let mut indentation_level = 0usize;
+    let mut push = |text: &str, pos: &mut TextPos| {
+        string.push_str(text);
+        pos.advance(text);
+    };
+    // The nodes whose tokens are being written, as the index of their span in `layout` and
+    // their tokens which are still to be written
+    let mut stack: Vec<(usize, std::vec::IntoIter<DisplayToken<Ref>>)> = Vec::new();
+    let mut next_child = Some(root);
+    loop {
+        if let Some(id) = next_child.take() {
+            layout.spans.push((
+                id,
+                Span {
+                    start: pos,
+                    end: pos,
+                },
+            ));
+            match node_map.get_node(id) {
+                Some(node) => {
+                    let mut tokens = node.display_tokens_in(node_map, format_style);
+                    move_indents_before_newlines(&mut tokens);
+                    stack.push((layout.spans.len() - 1, tokens.into_iter()));
+                }
+                None => {
+                    push(&format!("<INVALID REF {:?}>", id), &mut pos);
+                    layout.spans.last_mut().unwrap().1.end = pos;
+                }
+            }
+        }
+        let (span_index, tokens) = match stack.last_mut() {
+            Some(entry) => entry,
+            None => break,
+        };
+        match tokens.next() {
+            Some(DisplayToken::Text(s)) => push(&s, &mut pos),
+            Some(DisplayToken::Child(c)) => next_child = Some(c),
+            Some(DisplayToken::Whitespace(n)) => push(&" ".repeat(n), &mut pos),
+            Some(DisplayToken::Newline) => {
+                let mut newline = String::from("\n");
+                indent_style.push_levels(&mut newline, indentation_level);
+                push(&newline, &mut pos);
+            }
+            Some(DisplayToken::Indent) => indentation_level += 1,
+            Some(DisplayToken::Dedent) => indentation_level = indentation_level.saturating_sub(1),
+            Some(DisplayToken::InvalidRef) => push("<INVALID REF>", &mut pos),
+            None => {
+                layout.spans[*span_index].1.end = pos;
+                stack.pop();
+            }
+        }
+    }
+    layout
+}
+
+/// Recursively flatten the tokens of the node at `id` onto the end of `output_vec`.  Returns
+/// `false` once `lines_remaining` has run out, at which point no more tokens will be generated.
 fn flat_tokens_rec<Ref: Reference, Node: ASTSpec<Ref>>(
     node_map: &impl NodeMap<Ref, Node>,
     id: Ref,
     output_vec: &mut Vec<(Ref, DisplayToken<Ref>)>,
-    format_style: &Node::FormatStyle,
-) {
+    tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+    lines_remaining: &mut usize,
+) -> bool {
     if let Some(node) = node_map.get_node(id) {
-        let mut token_vec = node.display_tokens(format_style);
-        // If we every encounter a newline followed by an indent/dedent, we should swap them round so
-        // that the indent/dedent is always first.
-        for i in 0..token_vec.len() - 1 {
-            if token_vec[i] == DisplayToken::Newline
-                && (token_vec[i + 1] == DisplayToken::Indent
-                    || token_vec[i + 1] == DisplayToken::Dedent)
-            {
-                token_vec.swap(i, i + 1);
-            }
-        }
+        let mut token_vec = tokens_for(id, node);
+        move_indents_before_newlines(&mut token_vec);
 
         for tok in token_vec {
             match tok {
                 // If a node is a child, we should flatten its tree first
                 DisplayToken::Child(c) => {
-                    flat_tokens_rec(node_map, c, output_vec, format_style);
+                    if !flat_tokens_rec(node_map, c, output_vec, tokens_for, lines_remaining) {
+                        return false;
+                    }
+                }
+                // Stop generating tokens once the last line has been finished
+                DisplayToken::Newline if *lines_remaining <= 1 => {
+                    *lines_remaining = 0;
+                    return false;
                 }
                 // If it isn't a child, we can just copy it as-is
                 x => {
+                    if x == DisplayToken::Newline {
+                        *lines_remaining -= 1;
+                    }
                     output_vec.push((id, x));
                 }
             }
@@ -107,6 +377,7 @@ fn flat_tokens_rec<Ref: Reference, Node: ASTSpec<Ref>>(
     } else {
         output_vec.push((id, DisplayToken::InvalidRef));
     }
+    true
 }
 
 /// Return a flat vector of [`DisplayToken`] along with references to the nodes that own them
@@ -114,8 +385,575 @@ pub fn flat_tokens<Ref: Reference, Node: ASTSpec<Ref>>(
     node_map: &impl NodeMap<Ref, Node>,
     id: Ref,
     format_style: &Node::FormatStyle,
+) -> Vec<(Ref, DisplayToken<Ref>)> {
+    flat_tokens_with(node_map, id, &|_, node: &Node| {
+        node.display_tokens_in(node_map, format_style)
+    })
+}
+
+/// Same as [`flat_tokens`], but the tokens of every node are generated by `tokens_for` rather than
+/// [`ASTSpec::display_tokens_in`].  This lets the display replace the tokens of some nodes (e.g. to
+/// hide the contents of folded nodes) without any changes to the [`ASTSpec`].
+pub fn flat_tokens_with<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    id: Ref,
+    tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+) -> Vec<(Ref, DisplayToken<Ref>)> {
+    flat_tokens_for_lines(node_map, id, tokens_for, usize::MAX)
+}
+
+/// Same as [`flat_tokens_with`], but only generates the tokens for the first `max_lines` lines of
+/// text.  The nodes that would be rendered after those lines are never visited, so the cost of
+/// this is proportional to the amount of text generated rather than the size of the tree (which
+/// matters for containers with huge numbers of children).
+pub fn flat_tokens_for_lines<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    id: Ref,
+    tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+    max_lines: usize,
 ) -> Vec<(Ref, DisplayToken<Ref>)> {
     let mut flat_vec = Vec::new();
-    flat_tokens_rec(node_map, id, &mut flat_vec, format_style);
+    let mut lines_remaining = max_lines;
+    if lines_remaining > 0 {
+        flat_tokens_rec(
+            node_map,
+            id,
+            &mut flat_vec,
+            tokens_for,
+            &mut lines_remaining,
+        );
+    }
     flat_vec
 }
+
+/// Returns the line (counting from 0) of the text of the tree rooted at `root` on which the node
+/// at `id` starts, or [`None`] if that node isn't in the tree.  The tokens of every node are
+/// generated by `tokens_for`, as in [`flat_tokens_with`].  This stops as soon as the node is
+/// found, so its cost is proportional to the amount of text before the node.
+pub fn line_of_node<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    root: Ref,
+    id: Ref,
+    tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+) -> Option<usize> {
+    /// Count the lines before `id` in the subtree at `current`, returning `true` if `id` was found
+    fn count_lines<Ref: Reference, Node: ASTSpec<Ref>>(
+        node_map: &impl NodeMap<Ref, Node>,
+        current: Ref,
+        id: Ref,
+        tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+        lines: &mut usize,
+    ) -> bool {
+        if current == id {
+            return true;
+        }
+        let node = match node_map.get_node(current) {
+            Some(n) => n,
+            None => return false,
+        };
+        for tok in tokens_for(current, node) {
+            match tok {
+                DisplayToken::Child(c) if count_lines(node_map, c, id, tokens_for, lines) => {
+                    return true;
+                }
+                DisplayToken::Newline => *lines += 1,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    let mut lines = 0;
+    if count_lines(node_map, root, id, tokens_for, &mut lines) {
+        Some(lines)
+    } else {
+        None
+    }
+}
+
+/// Returns the range of indices into `tokens` that were generated by the node at `id` or any of
+/// its descendants.  `tokens` must have been generated by [`flat_tokens_for_lines`] using the
+/// same `tokens_for`.  Since the tokens of a subtree are always contiguous, this only has to look
+/// at the first and last tokens of each node - so its cost doesn't depend on the size of the
+/// subtree.  If `tokens` was cut off part way through the subtree, the range extends to the end
+/// of `tokens`.  If none of the subtree's tokens were generated, the range is empty.
+pub fn subtree_token_range<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    tokens: &[(Ref, DisplayToken<Ref>)],
+    id: Ref,
+    tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+) -> std::ops::Range<usize> {
+    // The subtree starts with the first token of the first node that doesn't start with a child
+    let mut first = id;
+    while let Some(node) = node_map.get_node(first) {
+        match tokens_for(first, node).first() {
+            Some(DisplayToken::Child(c)) => first = *c,
+            _ => break,
+        }
+    }
+    let start = match tokens.iter().position(|(r, _)| *r == first) {
+        Some(i) => i,
+        None => return tokens.len()..tokens.len(),
+    };
+    // Similarly, the subtree ends with the last token of the last node that doesn't end with a
+    // child
+    let mut last = id;
+    while let Some(node) = node_map.get_node(last) {
+        let own_tokens = tokens_for(last, node);
+        let mut generated = tokens.iter().enumerate().filter(|(_, (r, _))| *r == last);
+        let num_generated = generated.clone().count();
+        let num_expected = own_tokens
+            .iter()
+            .filter(|t| !matches!(t, DisplayToken::Child(_)))
+            .count();
+        if num_generated < num_expected {
+            // The tokens were cut off before the end of this node
+            return start..tokens.len();
+        }
+        match own_tokens.last() {
+            Some(DisplayToken::Child(c)) => last = *c,
+            _ => return start..generated.next_back().map_or(start, |(i, _)| i + 1),
+        }
+    }
+    start..tokens.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        flat_tokens, flat_tokens_for_lines, styled_spans, subtree_token_range,
+        write_text_with_layout, write_tokens_indented, DisplayToken, IndentStyle, Span, TextPos,
+    };
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::SyntaxCategory;
+    use crate::ast_spec::{check_tree, ASTSpec};
+    use crate::node_map::traversal::PreOrder;
+    use crate::node_map::{NodeMap, NodeMapMut};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// An allocator that counts the allocations made by each thread, so that tests can check how
+    /// much rendering allocates without being disturbed by the tests running alongside them
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            // The counter can't be accessed while the thread is being torn down
+            let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Returns the number of allocations made by this thread while running `f`
+    fn count_allocations(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(|a| a.get());
+        f();
+        ALLOCATIONS.with(|a| a.get()) - before
+    }
+
+    for_each_node_map! {
+        #[test]
+        fn syntax_highlighted_spans() {
+            let node_map: Map<JSON<Ref>> = TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Object(vec![("key".to_string(), TestJSON::Str("s".to_string()))]),
+            ])
+            .build_node_map();
+            let spans = styled_spans(
+                node_map.root(),
+                &node_map,
+                &JSONFormat::Compact,
+                &IndentStyle::default(),
+            );
+            let expected: Vec<(&str, Option<SyntaxCategory>)> = vec![
+                ("[", Some(SyntaxCategory::Punctuation)),
+                ("true", Some(SyntaxCategory::Literal)),
+                (",", Some(SyntaxCategory::Punctuation)),
+                (" ", None),
+                ("{", Some(SyntaxCategory::Punctuation)),
+                (r#""key""#, Some(SyntaxCategory::String)),
+                (": ", Some(SyntaxCategory::Punctuation)),
+                (r#""s""#, Some(SyntaxCategory::String)),
+                ("}]", Some(SyntaxCategory::Punctuation)),
+            ];
+            assert_eq!(
+                spans,
+                expected
+                    .into_iter()
+                    .map(|(s, c)| (s.to_string(), c))
+                    .collect::<Vec<_>>()
+            );
+
+            // The spans should always make up the plain text, with no styling mixed in
+            let pretty = styled_spans(
+                node_map.root(),
+                &node_map,
+                &JSONFormat::Pretty,
+                &IndentStyle::Tabs,
+            );
+            let mut plain = String::new();
+            write_tokens_indented(
+                node_map.root(),
+                &node_map,
+                &mut plain,
+                &JSONFormat::Pretty,
+                &IndentStyle::Tabs,
+            );
+            assert_eq!(
+                pretty.into_iter().map(|(s, _)| s).collect::<String>(),
+                plain
+            );
+        }
+
+        #[test]
+        fn indent_styles() {
+            let node_map: Map<JSON<Ref>> = TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Object(vec![("value".to_string(), TestJSON::False)]),
+            ])
+            .build_node_map();
+            let write_with = |indent_style: IndentStyle| {
+                let mut s = String::new();
+                write_tokens_indented(
+                    node_map.root(),
+                    &node_map,
+                    &mut s,
+                    &JSONFormat::Pretty,
+                    &indent_style,
+                );
+                s
+            };
+
+            // The default indentation should be the same as the one used for display
+            assert_eq!(
+                write_with(IndentStyle::default()),
+                node_map.to_text(&JSONFormat::Pretty)
+            );
+            assert_eq!(
+                write_with(IndentStyle::default()),
+                "[\n    true,\n    {\n        \"value\": false\n    }\n]"
+            );
+            assert_eq!(
+                write_with(IndentStyle::Spaces(2)),
+                "[\n  true,\n  {\n    \"value\": false\n  }\n]"
+            );
+            assert_eq!(
+                write_with(IndentStyle::Tabs),
+                "[\n\ttrue,\n\t{\n\t\t\"value\": false\n\t}\n]"
+            );
+            // Compact text contains no indentation, so should be unaffected
+            let mut s = String::new();
+            write_tokens_indented(
+                node_map.root(),
+                &node_map,
+                &mut s,
+                &JSONFormat::Compact,
+                &IndentStyle::Tabs,
+            );
+            assert_eq!(s, node_map.to_text(&JSONFormat::Compact));
+
+            // Arrays nested inside objects are indented by their depth, not by their key
+            let node_map: Map<JSON<Ref>> = TestJSON::Object(vec![(
+                "list".to_string(),
+                TestJSON::Array(vec![TestJSON::Array(vec![TestJSON::Null]), TestJSON::True]),
+            )])
+            .build_node_map();
+            let write_with = |indent_style: IndentStyle| {
+                let mut s = String::new();
+                write_tokens_indented(
+                    node_map.root(),
+                    &node_map,
+                    &mut s,
+                    &JSONFormat::Pretty,
+                    &indent_style,
+                );
+                s
+            };
+            assert_eq!(
+                write_with(IndentStyle::Spaces(2)),
+                "{\n  \"list\": [\n    [\n      null\n    ],\n    true\n  ]\n}"
+            );
+            assert_eq!(
+                write_with(IndentStyle::Spaces(4)),
+                "{\n    \"list\": [\n        [\n            null\n        ],\n        true\n    \
+                 ]\n}"
+            );
+            assert_eq!(
+                write_with(IndentStyle::Tabs),
+                "{\n\t\"list\": [\n\t\t[\n\t\t\tnull\n\t\t],\n\t\ttrue\n\t]\n}"
+            );
+        }
+
+        #[test]
+        fn indent_style_args() {
+            assert_eq!(IndentStyle::from_arg("2"), Some(IndentStyle::Spaces(2)));
+            assert_eq!(IndentStyle::from_arg("0"), Some(IndentStyle::Spaces(0)));
+            assert_eq!(IndentStyle::from_arg("tab"), Some(IndentStyle::Tabs));
+            assert_eq!(IndentStyle::from_arg("-1"), None);
+            assert_eq!(IndentStyle::from_arg("wide"), None);
+        }
+
+        #[test]
+        fn rendering_large_trees_only_allocates_tokens() {
+            // 12,500 objects with one field each, which is 50,001 nodes in total
+            let node_map: Map<JSON<Ref>> = TestJSON::Array(
+                (0..12_500)
+                    .map(|_| TestJSON::Object(vec![("k".to_string(), TestJSON::True)]))
+                    .collect(),
+            )
+            .build_node_map();
+            let root = node_map.root();
+            let nodes: Vec<&JSON<Ref>> = PreOrder::new(&node_map, root)
+                .map(|id| node_map.get_node(id).unwrap())
+                .collect();
+            assert_eq!(nodes.len(), 50_001);
+
+            // Walking the tree only grows the traversal's stacks, and never allocates per node
+            let traversal =
+                count_allocations(|| assert_eq!(PreOrder::new(&node_map, root).count(), 50_001));
+            assert!(traversal < 64, "traversal made {} allocations", traversal);
+            let validation = count_allocations(|| check_tree(&node_map).unwrap());
+            assert!(
+                validation < 64,
+                "validation made {} allocations",
+                validation
+            );
+
+            // Rendering allocates the tokens of each node, but nothing else per node
+            for format_style in &[JSONFormat::Pretty, JSONFormat::Compact] {
+                let tokens = count_allocations(|| {
+                    for node in &nodes {
+                        drop(node.display_tokens(format_style));
+                    }
+                });
+                let rendering = count_allocations(|| drop(node_map.to_text(format_style)));
+                assert!(
+                    rendering < tokens + 64,
+                    "rendering made {} allocations, but the tokens only need {}",
+                    rendering,
+                    tokens
+                );
+            }
+        }
+
+        #[test]
+        fn flat_tokens_line_limit() {
+            let node_map: Map<JSON<Ref>> =
+                TestJSON::Array((0..1000).map(|_| TestJSON::True).collect()).build_node_map();
+            let format_style = JSONFormat::Pretty;
+            let tokens_for = |_, node: &JSON<Ref>| node.display_tokens(&format_style);
+            let count_lines = |tokens: &[(Ref, DisplayToken<Ref>)]| {
+                1 + tokens
+                    .iter()
+                    .filter(|(_, t)| *t == DisplayToken::Newline)
+                    .count()
+            };
+
+            assert_eq!(
+                flat_tokens_for_lines(&node_map, node_map.root(), &tokens_for, usize::MAX),
+                flat_tokens(&node_map, node_map.root(), &format_style)
+            );
+            assert!(flat_tokens_for_lines(&node_map, node_map.root(), &tokens_for, 0).is_empty());
+            for &max_lines in &[1, 2, 10, 40] {
+                let root = node_map.root();
+                let tokens = flat_tokens_for_lines(&node_map, root, &tokens_for, max_lines);
+                assert_eq!(count_lines(&tokens), max_lines);
+                // Only the first few children should have been rendered
+                let trues = tokens
+                    .iter()
+                    .filter(|(_, t)| *t == DisplayToken::Text("true".to_string()))
+                    .count();
+                assert_eq!(trues, max_lines - 1);
+            }
+        }
+
+        #[test]
+        fn subtree_ranges() {
+            let node_map: Map<JSON<Ref>> = TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Object(vec![("value".to_string(), TestJSON::False)]),
+            ])
+            .build_node_map();
+            let format_style = JSONFormat::Pretty;
+            let tokens_for = |_, node: &JSON<Ref>| node.display_tokens(&format_style);
+            let object = node_map.root_node().children()[1];
+            let field = node_map.get_node(object).unwrap().children()[0];
+            let text_of = |tokens: &[(Ref, DisplayToken<Ref>)]| {
+                tokens
+                    .iter()
+                    .map(|(_, t)| match t {
+                        DisplayToken::Text(s) => s.clone(),
+                        DisplayToken::Whitespace(n) => " ".repeat(*n),
+                        DisplayToken::Newline => "\n".to_string(),
+                        _ => String::new(),
+                    })
+                    .collect::<String>()
+            };
+
+            let tokens = flat_tokens(&node_map, node_map.root(), &format_style);
+            let range = |id| subtree_token_range(&node_map, &tokens, id, &tokens_for);
+            assert_eq!(range(node_map.root()), 0..tokens.len());
+            // A multi-line subtree should be covered entirely
+            assert_eq!(text_of(&tokens[range(object)]), "{\n\"value\": false\n}");
+            // Fields start with their key and end with their value, neither of which are tokens of
+            // the field itself
+            assert_eq!(text_of(&tokens[range(field)]), "\"value\": false");
+
+            // If the tokens are cut off inside a subtree, the range should run to the end
+            let tokens = flat_tokens_for_lines(&node_map, node_map.root(), &tokens_for, 4);
+            let range = |id| subtree_token_range(&node_map, &tokens, id, &tokens_for);
+            assert_eq!(range(object).end, tokens.len());
+            assert_eq!(text_of(&tokens[range(field)]), "\"value\": false");
+        }
+
+        #[test]
+        fn text_layouts() {
+            let node_map: Map<JSON<Ref>> = TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Object(vec![("key".to_string(), TestJSON::Str("s".to_string()))]),
+            ])
+            .build_node_map();
+            let root = node_map.root();
+            let tru = node_map.root_node().children()[0];
+            let object = node_map.root_node().children()[1];
+            let field = node_map.get_node(object).unwrap().children()[0];
+            let key = node_map.get_node(field).unwrap().children()[0];
+            let value = node_map.get_node(field).unwrap().children()[1];
+            let layout_of = |format_style| {
+                let mut text = String::new();
+                let layout = write_text_with_layout(
+                    root,
+                    &node_map,
+                    &mut text,
+                    &format_style,
+                    &IndentStyle::default(),
+                );
+                (text, layout)
+            };
+            let span = |start_line, start_col, end_line, end_col| Span {
+                start: TextPos::new(start_line, start_col),
+                end: TextPos::new(end_line, end_col),
+            };
+
+            let (text, layout) = layout_of(JSONFormat::Compact);
+            assert_eq!(text, r#"[true, {"key": "s"}]"#);
+            assert_eq!(layout.span_of(root), Some(span(0, 0, 0, 20)));
+            assert_eq!(layout.span_of(tru), Some(span(0, 1, 0, 5)));
+            assert_eq!(layout.span_of(object), Some(span(0, 7, 0, 19)));
+            assert_eq!(layout.span_of(field), Some(span(0, 8, 0, 18)));
+            assert_eq!(layout.span_of(key), Some(span(0, 8, 0, 13)));
+            assert_eq!(layout.span_of(value), Some(span(0, 15, 0, 18)));
+            assert_eq!(layout.spans().len(), 6);
+            assert_eq!(layout.node_at(TextPos::new(0, 0)), Some(root));
+            assert_eq!(layout.node_at(TextPos::new(0, 4)), Some(tru));
+            assert_eq!(layout.node_at(TextPos::new(0, 5)), Some(root));
+            assert_eq!(layout.node_at(TextPos::new(0, 13)), Some(field));
+            assert_eq!(layout.node_at(TextPos::new(0, 18)), Some(object));
+            assert_eq!(layout.node_at(TextPos::new(0, 20)), None);
+
+            let (text, layout) = layout_of(JSONFormat::Pretty);
+            assert_eq!(text, "[\n    true,\n    {\n        \"key\": \"s\"\n    }\n]");
+            assert_eq!(layout.span_of(root), Some(span(0, 0, 5, 1)));
+            assert_eq!(layout.span_of(tru), Some(span(1, 4, 1, 8)));
+            assert_eq!(layout.span_of(object), Some(span(2, 4, 4, 5)));
+            assert_eq!(layout.span_of(field), Some(span(3, 8, 3, 18)));
+            assert_eq!(layout.span_of(key), Some(span(3, 8, 3, 13)));
+            assert_eq!(layout.span_of(value), Some(span(3, 15, 3, 18)));
+            assert_eq!(layout.node_at(TextPos::new(1, 0)), Some(root));
+            assert_eq!(layout.node_at(TextPos::new(1, 8)), Some(root));
+            // The indentation inside a multi-line node is covered by that node
+            assert_eq!(layout.node_at(TextPos::new(3, 0)), Some(object));
+            assert_eq!(layout.node_at(TextPos::new(3, 14)), Some(field));
+            assert_eq!(layout.node_at(TextPos::new(3, 16)), Some(value));
+            assert_eq!(layout.node_at(TextPos::new(4, 4)), Some(object));
+            assert_eq!(layout.node_at(TextPos::new(5, 0)), Some(root));
+            assert_eq!(layout.node_at(TextPos::new(5, 1)), None);
+            assert_eq!(layout.node_at(TextPos::new(6, 0)), None);
+        }
+
+        #[test]
+        fn text_layouts_match_written_text() {
+            let mut node_map: Map<JSON<Ref>> = TestJSON::Object(vec![
+                ("list".to_string(), TestJSON::Array(vec![TestJSON::Null; 3])),
+                ("empty".to_string(), TestJSON::Object(vec![])),
+            ])
+            .build_node_map();
+            // Share one node in two places, and include a reference to a node that doesn't exist
+            let null = node_map.add_node(JSON::Null);
+            let missing = node_map.add_node(JSON::Null);
+            assert!(node_map.remove_node(missing).is_ok());
+            let list = node_map.get_node(node_map.root()).unwrap().children()[0];
+            let list = node_map.get_node(list).unwrap().children()[1];
+            let list_node = node_map.get_node_mut(list).unwrap();
+            list_node.children_mut()[0] = null;
+            list_node.children_mut()[2] = null;
+            list_node.children_mut()[1] = missing;
+
+            for format_style in &[JSONFormat::Pretty, JSONFormat::Compact, JSONFormat::Minified] {
+                for indent_style in &[IndentStyle::Spaces(2), IndentStyle::Tabs] {
+                    let text_of = |id| {
+                        let mut s = String::new();
+                        write_tokens_indented(id, &node_map, &mut s, format_style, indent_style);
+                        s
+                    };
+                    let root = node_map.root();
+                    let mut text = String::new();
+                    let layout = write_text_with_layout(
+                        root,
+                        &node_map,
+                        &mut text,
+                        format_style,
+                        indent_style,
+                    );
+                    assert_eq!(text, text_of(root));
+
+                    // Every span should cover exactly the text of its node
+                    let lines: Vec<Vec<char>> =
+                        text.split('\n').map(|l| l.chars().collect()).collect();
+                    let text_in = |span: Span| {
+                        let mut s = String::new();
+                        let mut pos = span.start;
+                        while pos < span.end {
+                            match lines[pos.line].get(pos.col) {
+                                Some(c) => {
+                                    s.push(*c);
+                                    pos.col += 1;
+                                }
+                                None => {
+                                    s.push('\n');
+                                    pos = TextPos::new(pos.line + 1, 0);
+                                }
+                            }
+                        }
+                        s
+                    };
+                    for (id, span) in layout.spans() {
+                        // Whitespace is ignored, since the lines of a node are also indented by
+                        // its ancestors
+                        assert_eq!(
+                            text_in(*span).replace(char::is_whitespace, ""),
+                            text_of(*id).replace(char::is_whitespace, "")
+                        );
+                    }
+                    assert_eq!(layout.spans().iter().filter(|(id, _)| *id == null).count(), 2);
+                    assert_eq!(
+                        text_in(layout.span_of(missing).unwrap()),
+                        format!("<INVALID REF {:?}>", missing)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,716 @@
+//! An [`ASTSpec`] for a small language of arithmetic and boolean expressions, such as
+//! `(x + 1) * 2 == y && !done`.  Unlike the other languages, most nodes have a fixed number of
+//! children, and brackets aren't nodes of their own: they're written wherever the precedence and
+//! associativity of the operators need them, and nowhere else.
+
+use super::size::Size;
+use super::{ASTSpec, DisplayToken, LiteralValidity, Reference, SyntaxCategory};
+use crate::node_map::{NodeMap, NodeMapMut};
+
+/// The different ways that an expression can be formatted
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum ExprFormat {
+    /// Binary operators are surrounded by spaces.  E.g. `(x + 1) * -y`
+    #[default]
+    Spaced,
+    /// No whitespace at all.  E.g. `(x+1)*-y`
+    Compact,
+}
+
+const CHAR_INT: char = 'n';
+const CHAR_IDENT: char = 'i';
+
+/// The operators that apply to a single operand
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum UnaryOp {
+    /// Arithmetic negation, written `-x`
+    Neg,
+    /// Logical negation, written `!x`
+    Not,
+}
+
+/// The operators that apply to two operands
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Eq,
+}
+
+/// How tightly unary operators bind, which is tighter than any [`BinaryOp`]
+const UNARY_PRECEDENCE: u8 = 6;
+/// How tightly literals bind, i.e. tighter than any operator.  Literals never need brackets.
+const LITERAL_PRECEDENCE: u8 = 7;
+
+impl UnaryOp {
+    /// Every unary operator
+    pub const ALL: [UnaryOp; 2] = [UnaryOp::Neg, UnaryOp::Not];
+
+    /// Returns the text of this operator, which is written before its operand
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+
+    /// Returns the [`char`] that creates this operator in the replace and wrap commands.  `-`
+    /// already means subtraction, so negation uses `~`.
+    fn char(self) -> char {
+        match self {
+            UnaryOp::Neg => '~',
+            UnaryOp::Not => '!',
+        }
+    }
+
+    fn from_char(c: char) -> Option<UnaryOp> {
+        Self::ALL.iter().copied().find(|op| op.char() == c)
+    }
+}
+
+impl BinaryOp {
+    /// Every binary operator
+    pub const ALL: [BinaryOp; 7] = [
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Mul,
+        BinaryOp::Div,
+        BinaryOp::And,
+        BinaryOp::Or,
+        BinaryOp::Eq,
+    ];
+
+    /// Returns the text of this operator, which is written between its operands
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Eq => "==",
+        }
+    }
+
+    /// Returns how tightly this operator binds its operands: operators with a higher precedence
+    /// are applied first, so `a + b * c` means `a + (b * c)`.  Every binary operator is
+    /// left-associative, so `a - b - c` means `(a - b) - c`.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div => 5,
+        }
+    }
+
+    /// Returns the [`char`] that creates this operator in the replace and wrap commands, which
+    /// is the first char of its symbol
+    fn char(self) -> char {
+        self.symbol().chars().next().unwrap()
+    }
+
+    fn from_char(c: char) -> Option<BinaryOp> {
+        Self::ALL.iter().copied().find(|op| op.char() == c)
+    }
+}
+
+/// The sapling representation of an expression
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Expr<Ref: Reference> {
+    /// A non-negative integer, such as `42`, stored as its text.  Negative numbers are written by
+    /// [negating](UnaryOp::Neg) a positive one.
+    Int(String),
+    /// A variable, such as `x` or `max_len`.  This must be a letter or `_` followed by any number
+    /// of letters, digits and `_`s.
+    Ident(String),
+    /// A unary operator applied to its only child, e.g. `-x`
+    Unary(UnaryOp, [Ref; 1]),
+    /// A binary operator applied to its two children, e.g. `a + b`
+    Binary(BinaryOp, [Ref; 2]),
+}
+
+/// Returns `true` if `text` can be the digits of an [`Int`](Expr::Int)
+pub fn is_valid_int(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Returns `true` if `c` can be the first char of an [`Ident`](Expr::Ident)
+pub(super) fn is_ident_start_char(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Returns `true` if `c` can come after the first char of an [`Ident`](Expr::Ident)
+pub(super) fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns `true` if `text` can be the name of an [`Ident`](Expr::Ident)
+pub fn is_valid_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(is_ident_start_char) && chars.all(is_ident_char)
+}
+
+impl<Ref: Reference> Expr<Ref> {
+    /// Return an iterator over all the possible chars that could represent expression nodes
+    fn all_chars() -> Box<dyn Iterator<Item = char>> {
+        Box::new(
+            [CHAR_INT, CHAR_IDENT]
+                .iter()
+                .copied()
+                .chain(UnaryOp::ALL.iter().map(|op| op.char()))
+                .chain(BinaryOp::ALL.iter().map(|op| op.char())),
+        )
+    }
+
+    /// Returns how tightly this node binds its operands (see [`BinaryOp::precedence`])
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Int(_) | Expr::Ident(_) => LITERAL_PRECEDENCE,
+            Expr::Unary(..) => UNARY_PRECEDENCE,
+            Expr::Binary(op, _) => op.precedence(),
+        }
+    }
+
+    /// Returns `true` if `operand` has to be written in brackets to be the `index`th child of
+    /// this node
+    fn needs_brackets(&self, index: usize, operand: &Self) -> bool {
+        let inner = operand.precedence();
+        match self {
+            Expr::Int(_) | Expr::Ident(_) => false,
+            Expr::Unary(..) => inner < UNARY_PRECEDENCE,
+            // The operators are left-associative, so an equally tight operation on the right
+            // needs brackets, e.g. `a - (b - c)`
+            Expr::Binary(op, _) => {
+                inner < op.precedence() || (index == 1 && inner == op.precedence())
+            }
+        }
+    }
+
+    /// Generate the tokens of this node, bracketing the `index`th child `c` if
+    /// `is_bracketed(index, c)` returns `true`
+    fn tokens(
+        &self,
+        format_style: &ExprFormat,
+        is_bracketed: impl Fn(usize, Ref) -> bool,
+    ) -> Vec<DisplayToken<Ref>> {
+        let push_operand = |tokens: &mut Vec<DisplayToken<Ref>>, index: usize, child: Ref| {
+            let bracketed = is_bracketed(index, child);
+            if bracketed {
+                tokens.push(DisplayToken::Text("(".to_string()));
+            }
+            tokens.push(DisplayToken::Child(child));
+            if bracketed {
+                tokens.push(DisplayToken::Text(")".to_string()));
+            }
+        };
+        match self {
+            Expr::Int(text) | Expr::Ident(text) => vec![DisplayToken::Text(text.clone())],
+            Expr::Unary(op, [operand]) => {
+                let mut tokens = vec![DisplayToken::Text(op.symbol().to_string())];
+                push_operand(&mut tokens, 0, *operand);
+                tokens
+            }
+            Expr::Binary(op, [left, right]) => {
+                let mut tokens = Vec::with_capacity(9);
+                push_operand(&mut tokens, 0, *left);
+                if format_style == &ExprFormat::Spaced {
+                    tokens.push(DisplayToken::Whitespace(1));
+                }
+                tokens.push(DisplayToken::Text(op.symbol().to_string()));
+                if format_style == &ExprFormat::Spaced {
+                    tokens.push(DisplayToken::Whitespace(1));
+                }
+                push_operand(&mut tokens, 1, *right);
+                tokens
+            }
+        }
+    }
+}
+
+impl<Ref: Reference> Default for Expr<Ref> {
+    fn default() -> Expr<Ref> {
+        Expr::Int("0".to_string())
+    }
+}
+
+impl<Ref: Reference> ASTSpec<Ref> for Expr<Ref> {
+    type FormatStyle = ExprFormat;
+
+    /* FORMATTING FUNCTIONS */
+
+    /// Without the rest of the tree, there's no way to tell which operands need brackets, so
+    /// every operand is bracketed.  This means the same as the text from
+    /// [`display_tokens_in`](ASTSpec::display_tokens_in), but is usually longer.
+    fn display_tokens(&self, format_style: &Self::FormatStyle) -> Vec<DisplayToken<Ref>> {
+        self.tokens(format_style, |_, _| true)
+    }
+
+    fn display_tokens_in(
+        &self,
+        node_map: &impl NodeMap<Ref, Self>,
+        format_style: &Self::FormatStyle,
+    ) -> Vec<DisplayToken<Ref>> {
+        self.tokens(format_style, |index, child| {
+            node_map
+                .get_node(child)
+                .is_some_and(|operand| self.needs_brackets(index, operand))
+        })
+    }
+
+    fn size(&self, node_map: &impl NodeMap<Ref, Self>, format_style: &Self::FormatStyle) -> Size {
+        let mut size = Size::new(0, 0);
+        for token in self.display_tokens_in(node_map, format_style) {
+            size += match token {
+                DisplayToken::Text(text) => Size::from(text.as_str()),
+                DisplayToken::Whitespace(n) => Size::new(0, n),
+                DisplayToken::Child(c) => {
+                    node_map.get_node(c).unwrap().size(node_map, format_style)
+                }
+                _ => Size::new(0, 0),
+            };
+        }
+        size
+    }
+
+    fn next_format_style(style: &ExprFormat) -> Option<ExprFormat> {
+        Some(match style {
+            ExprFormat::Spaced => ExprFormat::Compact,
+            ExprFormat::Compact => ExprFormat::Spaced,
+        })
+    }
+
+    /* DEBUG VIEW FUNCTIONS */
+
+    fn children(&self) -> &[Ref] {
+        match self {
+            Expr::Int(_) | Expr::Ident(_) => &[],
+            Expr::Unary(_, children) => children,
+            Expr::Binary(_, children) => children,
+        }
+    }
+
+    fn children_mut(&mut self) -> &mut [Ref] {
+        match self {
+            Expr::Int(_) | Expr::Ident(_) => &mut [],
+            Expr::Unary(_, children) => children,
+            Expr::Binary(_, children) => children,
+        }
+    }
+
+    /// The children of operators are stored inline
+    fn heap_size(&self) -> usize {
+        match self {
+            Expr::Int(text) | Expr::Ident(text) => text.capacity(),
+            Expr::Unary(..) | Expr::Binary(..) => 0,
+        }
+    }
+
+    fn display_name(&self) -> String {
+        match self {
+            Expr::Int(text) | Expr::Ident(text) => text.clone(),
+            Expr::Unary(op, _) => op.symbol().to_string(),
+            Expr::Binary(op, _) => op.symbol().to_string(),
+        }
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match self {
+            Expr::Int(_) => "integer",
+            Expr::Ident(_) => "identifier",
+            Expr::Unary(UnaryOp::Neg, _) => "negation",
+            Expr::Unary(UnaryOp::Not, _) => "not",
+            Expr::Binary(BinaryOp::Add, _) => "addition",
+            Expr::Binary(BinaryOp::Sub, _) => "subtraction",
+            Expr::Binary(BinaryOp::Mul, _) => "multiplication",
+            Expr::Binary(BinaryOp::Div, _) => "division",
+            Expr::Binary(BinaryOp::And, _) => "and",
+            Expr::Binary(BinaryOp::Or, _) => "or",
+            Expr::Binary(BinaryOp::Eq, _) => "equality",
+        }
+    }
+
+    /* AST EDITING FUNCTIONS */
+
+    fn replace_chars(&self) -> Box<dyn Iterator<Item = char>> {
+        Self::all_chars()
+    }
+
+    fn char_descriptions() -> Vec<(char, &'static str)> {
+        vec![
+            (CHAR_INT, "zero"),
+            (CHAR_IDENT, "identifier"),
+            ('~', "negation"),
+            ('!', "logical not"),
+            ('+', "addition"),
+            ('-', "subtraction"),
+            ('*', "multiplication"),
+            ('/', "division"),
+            ('&', "logical and (&&)"),
+            ('|', "logical or (||)"),
+            ('=', "equality (==)"),
+        ]
+    }
+
+    /// Only literals can be made without any other nodes.  Operators are made by
+    /// [`from_char_with_children`](ASTSpec::from_char_with_children).
+    fn from_char(&self, c: char) -> Option<Self> {
+        match c {
+            CHAR_INT => Some(Expr::Int("0".to_string())),
+            CHAR_IDENT => Some(Expr::Ident("x".to_string())),
+            _ => None,
+        }
+    }
+
+    /// Operators start with every operand set to `0`.  Replacing an operator with another that
+    /// has the same number of operands keeps the operands instead, so that e.g. `a + b` can be
+    /// turned into `a * b`.
+    fn from_char_with_children(
+        &self,
+        c: char,
+        node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Self> {
+        let mut zero = || node_map.add_node(Expr::default());
+        if let Some(op) = UnaryOp::from_char(c) {
+            return Some(match self {
+                Expr::Unary(_, operand) => Expr::Unary(op, *operand),
+                _ => Expr::Unary(op, [zero()]),
+            });
+        }
+        if let Some(op) = BinaryOp::from_char(c) {
+            return Some(match self {
+                Expr::Binary(_, operands) => Expr::Binary(op, *operands),
+                _ => Expr::Binary(op, [zero(), zero()]),
+            });
+        }
+        self.from_char(c)
+    }
+
+    /// Every operator has a fixed number of operands, so nothing can be inserted
+    fn insert_chars(&self) -> Box<dyn Iterator<Item = char>> {
+        Box::new(std::iter::empty())
+    }
+
+    /// Wrapping a node in a binary operator makes it the left operand, with `0` on the right
+    fn wrap_from_char(
+        &self,
+        c: char,
+        this: Ref,
+        node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Ref> {
+        let wrapper = if let Some(op) = UnaryOp::from_char(c) {
+            Expr::Unary(op, [this])
+        } else {
+            let op = BinaryOp::from_char(c)?;
+            Expr::Binary(op, [this, node_map.add_node(Expr::default())])
+        };
+        Some(node_map.add_node(wrapper))
+    }
+
+    fn insert_child(&mut self, _child: Ref, _index: usize) -> bool {
+        false
+    }
+
+    fn remove_child(&mut self, _index: usize) -> bool {
+        false
+    }
+
+    /// Operands can still be swapped, even though they can't be removed
+    fn swap_children(&mut self, i: usize, j: usize) -> bool {
+        let children = self.children_mut();
+        if i >= children.len() || j >= children.len() {
+            return false;
+        }
+        children.swap(i, j);
+        true
+    }
+
+    fn syntax_category(&self) -> SyntaxCategory {
+        match self {
+            Expr::Int(_) => SyntaxCategory::Literal,
+            Expr::Ident(_) => SyntaxCategory::Other,
+            Expr::Unary(..) | Expr::Binary(..) => SyntaxCategory::Punctuation,
+        }
+    }
+
+    fn literal(&self) -> Option<&str> {
+        match self {
+            Expr::Int(text) | Expr::Ident(text) => Some(text),
+            Expr::Unary(..) | Expr::Binary(..) => None,
+        }
+    }
+
+    fn with_literal(&self, text: &str) -> Option<Self> {
+        match self {
+            Expr::Int(_) => Some(Expr::Int(text.to_string())),
+            Expr::Ident(_) => Some(Expr::Ident(text.to_string())),
+            Expr::Unary(..) | Expr::Binary(..) => None,
+        }
+    }
+
+    fn check_literal(&self, text: &str) -> LiteralValidity {
+        match self {
+            Expr::Int(_) if is_valid_int(text) => LiteralValidity::Valid,
+            Expr::Ident(_) if is_valid_ident(text) => LiteralValidity::Valid,
+            // Both kinds of literal can be finished by typing their first char
+            Expr::Int(_) | Expr::Ident(_) if text.is_empty() => LiteralValidity::Incomplete,
+            _ => LiteralValidity::Invalid,
+        }
+    }
+
+    fn check_valid(&self, _node_map: &impl NodeMap<Ref, Self>) -> Result<(), String> {
+        match self {
+            Expr::Int(text) if !is_valid_int(text) => Err(format!("invalid integer '{}'", text)),
+            Expr::Ident(text) if !is_valid_ident(text) => {
+                Err(format!("invalid identifier '{}'", text))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_ident, is_valid_int, BinaryOp, Expr, ExprFormat, UnaryOp};
+    use crate::ast_spec::size::Size;
+    use crate::ast_spec::test_expr::TestExpr;
+    use crate::ast_spec::{ASTSpec, DisplayToken, LiteralValidity};
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    fn int(n: u32) -> TestExpr {
+        TestExpr::Int(n.to_string())
+    }
+
+    fn ident(name: &str) -> TestExpr {
+        TestExpr::Ident(name.to_string())
+    }
+
+    fn unary(op: UnaryOp, operand: TestExpr) -> TestExpr {
+        TestExpr::Unary(op, Box::new(operand))
+    }
+
+    fn binary(op: BinaryOp, left: TestExpr, right: TestExpr) -> TestExpr {
+        TestExpr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn to_text() {
+        use BinaryOp::*;
+        use UnaryOp::*;
+        for (tree, expected_spaced, expected_compact) in vec![
+            (int(12), "12", "12"),
+            (ident("max_len"), "max_len", "max_len"),
+            (binary(Add, int(1), int(2)), "1 + 2", "1+2"),
+            // Tighter operators inside looser ones don't need brackets, but looser ones do
+            (
+                binary(Mul, binary(Add, int(1), int(2)), int(3)),
+                "(1 + 2) * 3",
+                "(1+2)*3",
+            ),
+            (
+                binary(Add, binary(Mul, int(1), int(2)), int(3)),
+                "1 * 2 + 3",
+                "1*2+3",
+            ),
+            (
+                binary(Mul, int(1), binary(Sub, int(2), int(3))),
+                "1 * (2 - 3)",
+                "1*(2-3)",
+            ),
+            (
+                binary(Sub, int(1), binary(Div, int(2), int(3))),
+                "1 - 2 / 3",
+                "1-2/3",
+            ),
+            // Operators are left-associative, so only equal operators on the right are bracketed
+            (
+                binary(Sub, binary(Sub, int(1), int(2)), int(3)),
+                "1 - 2 - 3",
+                "1-2-3",
+            ),
+            (
+                binary(Sub, int(1), binary(Sub, int(2), int(3))),
+                "1 - (2 - 3)",
+                "1-(2-3)",
+            ),
+            (
+                binary(Add, int(1), binary(Sub, int(2), int(3))),
+                "1 + (2 - 3)",
+                "1+(2-3)",
+            ),
+            (
+                binary(Sub, binary(Add, int(1), int(2)), int(3)),
+                "1 + 2 - 3",
+                "1+2-3",
+            ),
+            (
+                binary(Div, binary(Mul, int(1), int(2)), int(3)),
+                "1 * 2 / 3",
+                "1*2/3",
+            ),
+            (
+                binary(Eq, binary(Eq, ident("a"), ident("b")), ident("c")),
+                "a == b == c",
+                "a==b==c",
+            ),
+            (
+                binary(Eq, ident("a"), binary(Eq, ident("b"), ident("c"))),
+                "a == (b == c)",
+                "a==(b==c)",
+            ),
+            // `&&` binds tighter than `||`, and both are looser than `==`
+            (
+                binary(
+                    Or,
+                    binary(And, ident("a"), ident("b")),
+                    binary(Eq, ident("c"), int(0)),
+                ),
+                "a && b || c == 0",
+                "a&&b||c==0",
+            ),
+            (
+                binary(
+                    And,
+                    binary(Or, ident("a"), ident("b")),
+                    binary(Or, ident("c"), ident("d")),
+                ),
+                "(a || b) && (c || d)",
+                "(a||b)&&(c||d)",
+            ),
+            (
+                binary(Eq, binary(Add, ident("x"), int(1)), int(2)),
+                "x + 1 == 2",
+                "x+1==2",
+            ),
+            (
+                binary(Add, ident("x"), binary(Eq, int(1), int(2))),
+                "x + (1 == 2)",
+                "x+(1==2)",
+            ),
+            // Unary operators bind tighter than any binary operator
+            (unary(Neg, int(1)), "-1", "-1"),
+            (unary(Neg, unary(Neg, ident("x"))), "--x", "--x"),
+            (
+                unary(Not, binary(And, ident("a"), ident("b"))),
+                "!(a && b)",
+                "!(a&&b)",
+            ),
+            (
+                binary(And, unary(Not, ident("a")), ident("b")),
+                "!a && b",
+                "!a&&b",
+            ),
+            (binary(Sub, int(1), unary(Neg, int(1))), "1 - -1", "1--1"),
+            (
+                unary(Neg, binary(Mul, int(2), unary(Neg, int(3)))),
+                "-(2 * -3)",
+                "-(2*-3)",
+            ),
+        ] {
+            println!("Testing {}", expected_spaced);
+
+            let node_map: VecNodeMap<Expr<Index>> = tree.build_node_map();
+            for (style, expected) in &[
+                (ExprFormat::Spaced, expected_spaced),
+                (ExprFormat::Compact, expected_compact),
+            ] {
+                assert_eq!(node_map.to_text(style), *expected);
+                assert_eq!(
+                    node_map.root_node().size(&node_map, style),
+                    Size::from(*expected)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tokens_without_the_tree() {
+        let node_map: VecNodeMap<Expr<Index>> =
+            binary(BinaryOp::Add, int(1), int(2)).build_node_map();
+        // Without the node map, every operand has to be bracketed to be safe
+        let text: String = node_map
+            .root_node()
+            .display_tokens(&ExprFormat::Compact)
+            .into_iter()
+            .map(|token| match token {
+                DisplayToken::Text(s) => s,
+                DisplayToken::Child(_) => "_".to_string(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(text, "(_)+(_)");
+    }
+
+    #[test]
+    fn validity() {
+        for text in &["0", "42", "007"] {
+            assert!(is_valid_int(text), "{}", text);
+        }
+        for text in &["", "-1", "1.5", "1e3", "x"] {
+            assert!(!is_valid_int(text), "{}", text);
+        }
+        for text in &["x", "_", "max_len", "x1", "Δt"] {
+            assert!(is_valid_ident(text), "{}", text);
+        }
+        for text in &["", "1x", "a b", "a-b", "(x)"] {
+            assert!(!is_valid_ident(text), "{}", text);
+        }
+        let int = Expr::<Index>::default();
+        let ident = Expr::<Index>::Ident("x".to_string());
+        for (node, text, expected) in &[
+            (&int, "12", LiteralValidity::Valid),
+            (&int, "", LiteralValidity::Incomplete),
+            (&int, "1a", LiteralValidity::Invalid),
+            (&ident, "y2", LiteralValidity::Valid),
+            (&ident, "", LiteralValidity::Incomplete),
+            (&ident, "2y", LiteralValidity::Invalid),
+        ] {
+            assert_eq!(node.check_literal(text), *expected, "{:?}", text);
+        }
+        assert_eq!(int.with_literal("7"), Some(Expr::Int("7".to_string())));
+    }
+
+    #[test]
+    fn operators() {
+        let mut node_map: VecNodeMap<Expr<Index>> =
+            binary(BinaryOp::Add, ident("a"), ident("b")).build_node_map();
+        let operands = node_map.root_node().children().to_vec();
+        let sum = node_map.root_node().clone();
+        // Operators have a fixed number of operands
+        let mut node = sum.clone();
+        assert!(!node.insert_child(operands[0], 0));
+        assert!(!node.remove_child(0));
+        assert_eq!(node.insert_chars().count(), 0);
+        assert!(node.swap_children(0, 1));
+        assert_eq!(node.children(), &[operands[1], operands[0]]);
+        assert!(!node.swap_children(0, 2));
+
+        // Replacing an operator with another keeps the operands if it can
+        let product = sum.from_char_with_children('*', &mut node_map).unwrap();
+        assert_eq!(
+            product,
+            Expr::Binary(BinaryOp::Mul, [operands[0], operands[1]])
+        );
+        let nodes = node_map.iter_all().count();
+        let negation = sum.from_char_with_children('~', &mut node_map).unwrap();
+        let zero = negation.children()[0];
+        assert_eq!(node_map.get_node(zero), Some(&Expr::Int("0".to_string())));
+        assert_eq!(node_map.iter_all().count(), nodes + 1);
+        assert_eq!(sum.from_char('+'), None);
+        assert_eq!(sum.from_char_with_children('x', &mut node_map), None);
+
+        // Wrapping makes the node the left operand
+        let a = node_map.get_node(operands[0]).unwrap().clone();
+        let wrapper = a.wrap_from_char('=', operands[0], &mut node_map).unwrap();
+        node_map.set_root(wrapper).unwrap();
+        assert_eq!(node_map.to_text(&ExprFormat::Spaced), "a == 0");
+        assert_eq!(a.wrap_from_char('n', operands[0], &mut node_map), None);
+    }
+}
@@ -0,0 +1,175 @@
+//! A parser which reads expressions (see [`Expr`]) directly into a [`NodeMap`].  Operators are
+//! read with the same precedence and associativity that decide where brackets are written, so
+//! writing a tree and reading it back in gives the same tree.
+
+use super::expr::{is_ident_char, is_ident_start_char, BinaryOp, Expr, UnaryOp};
+use super::lexer::{Lexer, ParseError};
+use crate::node_map::{NodeMapMut, Reference};
+
+// Import used only for doc comments
+#[allow(unused_imports)]
+use crate::node_map::NodeMap;
+
+/// The state of a partially complete parse
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser {
+            lexer: Lexer::new(text),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.lexer.take_while(char::is_whitespace);
+    }
+
+    /// Parse an expression made of operators that bind at least as tightly as `min_precedence`,
+    /// adding it (and its operands) to `node_map`
+    fn parse_expr<Ref: Reference, M: NodeMapMut<Ref, Expr<Ref>>>(
+        &mut self,
+        node_map: &mut M,
+        min_precedence: u8,
+    ) -> Result<Ref, ParseError> {
+        let mut left = self.parse_operand(node_map)?;
+        loop {
+            self.skip_whitespace();
+            let op = BinaryOp::ALL.iter().copied().find(|op| {
+                op.precedence() >= min_precedence && self.lexer.starts_with(op.symbol())
+            });
+            let op = match op {
+                Some(op) => op,
+                None => return Ok(left),
+            };
+            self.lexer.eat(op.symbol());
+            // Operators are left-associative, so the right operand can't contain another
+            // operator that binds as loosely as this one
+            let right = self.parse_expr(node_map, op.precedence() + 1)?;
+            left = node_map.add_node(Expr::Binary(op, [left, right]));
+        }
+    }
+
+    /// Parse a literal, a bracketed expression or a unary operator and its operand
+    fn parse_operand<Ref: Reference, M: NodeMapMut<Ref, Expr<Ref>>>(
+        &mut self,
+        node_map: &mut M,
+    ) -> Result<Ref, ParseError> {
+        self.skip_whitespace();
+        if let Some(op) = UnaryOp::ALL
+            .iter()
+            .find(|op| self.lexer.starts_with(op.symbol()))
+        {
+            self.lexer.eat(op.symbol());
+            let operand = self.parse_operand(node_map)?;
+            return Ok(node_map.add_node(Expr::Unary(*op, [operand])));
+        }
+        let node = match self.lexer.peek() {
+            Some('(') => {
+                self.lexer.advance();
+                let inner = self.parse_expr(node_map, 0)?;
+                self.skip_whitespace();
+                if !self.lexer.eat(")") {
+                    return Err(self.lexer.error("expected ')'"));
+                }
+                return Ok(inner);
+            }
+            Some(c) if c.is_ascii_digit() => {
+                Expr::Int(self.lexer.take_while(|c| c.is_ascii_digit()).to_string())
+            }
+            Some(c) if is_ident_start_char(c) => {
+                Expr::Ident(self.lexer.take_while(is_ident_char).to_string())
+            }
+            Some(c) => return Err(self.lexer.error(format!("unexpected '{}'", c))),
+            None => return Err(self.lexer.error("unexpected end of input")),
+        };
+        Ok(node_map.add_node(node))
+    }
+}
+
+impl<Ref: Reference> Expr<Ref> {
+    /// Parse `text` as an expression, building a new [`NodeMap`] with the parsed expression as
+    /// its root.  Brackets only group operators, so aren't kept in the tree.
+    pub fn parse_into<M: NodeMapMut<Ref, Expr<Ref>>>(text: &str) -> Result<M, ParseError> {
+        let mut parser = Parser::new(text);
+        let mut node_map = M::with_default_root();
+        let root = parser.parse_expr(&mut node_map, 0)?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.lexer.peek() {
+            return Err(parser.lexer.error(format!("unexpected '{}'", c)));
+        }
+        node_map.set_root(root).unwrap();
+        Ok(node_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParseError;
+    use crate::ast_spec::expr::{Expr, ExprFormat};
+    use crate::node_map::NodeMapMut;
+
+    for_each_node_map! {
+        fn parse(text: &str) -> Result<Map<Expr<Ref>>, ParseError> {
+            Expr::parse_into(text)
+        }
+
+        #[test]
+        fn round_trip() {
+            for text in &[
+                "0",
+                "x_1",
+                "1 + 2 * 3",
+                "(1 + 2) * 3",
+                "1 * 2 + 3",
+                "1 - 2 - 3",
+                "1 - (2 - 3)",
+                "a / b / (c * d)",
+                "a && b || c == 0",
+                "(a || b) && !(c == d)",
+                "a == b == c",
+                "a == (b == c)",
+                "x + (1 == 2)",
+                "--x",
+                "-(2 * -3) - -1",
+                "!a && !!b",
+                "((x))",
+            ] {
+                let tree = parse(text).unwrap();
+                // Redundant brackets aren't kept
+                let expected = if *text == "((x))" { "x" } else { text };
+                assert_eq!(tree.to_text(&ExprFormat::Spaced), expected);
+                // The compact text has no whitespace, but means the same
+                let compact = tree.to_text(&ExprFormat::Compact);
+                assert_eq!(compact, expected.replace(' ', ""));
+                assert_eq!(
+                    parse(&compact).unwrap().to_text(&ExprFormat::Spaced),
+                    expected
+                );
+            }
+            // Whitespace shouldn't matter
+            let tree = parse(" (\n a+b )\t*c ").unwrap();
+            assert_eq!(tree.to_text(&ExprFormat::Spaced), "(a + b) * c");
+        }
+
+        #[test]
+        fn errors() {
+            for (text, line, column) in &[
+                ("", 1, 1),
+                ("1 +", 1, 4),
+                ("(1 + 2", 1, 7),
+                ("1 2", 1, 3),
+                ("a & b", 1, 3),
+                ("a = b", 1, 3),
+                ("1 +\n  * 2", 2, 3),
+                ("x)", 1, 2),
+                ("2x", 1, 2),
+                ("#", 1, 1),
+            ] {
+                let error = parse(text).unwrap_err();
+                assert_eq!((error.line, error.column), (*line, *column), "{}", text);
+            }
+        }
+    }
+}
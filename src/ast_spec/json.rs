@@ -1,32 +1,43 @@
+use super::display_token::IndentStyle;
 use super::size::Size;
-use super::{ASTSpec, DisplayToken, Reference};
-use crate::node_map::NodeMap;
+use super::{name_path_segment, ASTSpec, DisplayToken, LiteralValidity, Reference, SyntaxCategory};
+use crate::node_map::{NodeMap, NodeMapMut};
+use std::borrow::Cow;
 
 /// An enum to hold the different ways that a JSON AST can be formatted
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Default)]
 pub enum JSONFormat {
     /// The most compact representation, has minimal whitespace.
     /// E.g. `[{"foo": true, "bar": false}, true]`
     Compact,
     /// A prettified representation, with pretty indenting and every element on a newline.
+    #[default]
     Pretty,
+    /// Like [`Compact`](JSONFormat::Compact), but without any whitespace at all.  This is
+    /// intended for writing minified files.
+    /// E.g. `[{"foo":true,"bar":false},true]`
+    Minified,
 }
 
 const CHAR_TRUE: char = 't';
 const CHAR_FALSE: char = 'f';
+const CHAR_NULL: char = 'z';
 const CHAR_ARRAY: char = 'a';
 const CHAR_OBJECT: char = 'o';
 const CHAR_FIELD: char = 'i';
 const CHAR_STRING: char = 's';
+const CHAR_NUMBER: char = 'n';
 
-/// The sapling representation of the AST for a subset of JSON (where all values are either 'true'
-/// or 'false', and keys only contain ASCII).
-#[derive(Debug, Eq, PartialEq, Clone)]
+/// The sapling representation of the AST for JSON
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum JSON<Ref: Reference> {
     /// The JSON value for 'true'.  Corresponds to the string `true`.
     True,
     /// The JSON value 'false'.  Corresponds to the string `false`.
     False,
+    /// The JSON value 'null'.  Corresponds to the string `null`.
+    Null,
     /// A JSON array of multiple values.
     /// Corresponds to a string `[<v1>, <v2>, ...]` where `v1`, `v2`, ... are JSON values.
     Array(Vec<Ref>),
@@ -38,19 +49,155 @@ pub enum JSON<Ref: Reference> {
     /// A JSON object field.  The first `Ref` must be a [`Str`](JSON::Str), and the second is any
     /// JSON object
     Field([Ref; 2]),
-    /// A JSON string
+    /// A JSON string.  The contents are stored exactly as they appear between the quotes, i.e.
+    /// including any escape sequences.
     Str(String),
+    /// A JSON number.  This is stored as the text of the number (e.g. `1e10` or `-0.50`), which
+    /// must be [valid](is_valid_number) so that it can be written back exactly as it was read.
+    Number(String),
+}
+
+/// Returns `true` if `text` is a number according to the JSON grammar, i.e. an optional minus
+/// sign, an integer part without leading zeros, then an optional fraction and exponent.  This
+/// rules out values like `NaN`, `inf`, `+1` and `.5`.
+pub fn is_valid_number(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    // Returns the index after a run of digits starting at `i`
+    let skip_digits = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+    if bytes.get(i) == Some(&b'-') {
+        i += 1;
+    }
+    match bytes.get(i) {
+        Some(b'0') => i += 1,
+        Some(b'1'..=b'9') => i = skip_digits(i),
+        _ => return false,
+    }
+    if bytes.get(i) == Some(&b'.') {
+        let end = skip_digits(i + 1);
+        if end == i + 1 {
+            return false;
+        }
+        i = end;
+    }
+    if matches!(bytes.get(i), Some(b'e') | Some(b'E')) {
+        i += 1;
+        if matches!(bytes.get(i), Some(b'+') | Some(b'-')) {
+            i += 1;
+        }
+        let end = skip_digits(i);
+        if end == i {
+            return false;
+        }
+        i = end;
+    }
+    i == bytes.len()
+}
+
+/// Decode the escape sequences in the contents of a JSON string (e.g. `\u0041` becomes `A`).
+/// Returns [`None`] if `s` contains an invalid escape sequence.
+pub fn unescape_string(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let c = match chars.next()? {
+            '"' => '"',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let code = read_hex_code(&mut chars)?;
+                if (0xD800..0xDC00).contains(&code) {
+                    // This is the first half of a surrogate pair, which must be followed by the
+                    // second half
+                    if chars.next()? != '\\' || chars.next()? != 'u' {
+                        return None;
+                    }
+                    let low = read_hex_code(&mut chars)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return None;
+                    }
+                    std::char::from_u32(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00))?
+                } else {
+                    std::char::from_u32(code)?
+                }
+            }
+            _ => return None,
+        };
+        unescaped.push(c);
+    }
+    Some(unescaped)
+}
+
+/// Read the four hex digits of a `\u` escape sequence
+fn read_hex_code(chars: &mut std::str::Chars) -> Option<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        code = code * 16 + chars.next()?.to_digit(16)?;
+    }
+    Some(code)
+}
+
+/// Generate the contents of a JSON string that represents `s`, using as few escape sequences as
+/// possible.  Only quotes, backslashes and control characters are escaped.
+pub fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 impl<Ref: Reference> JSON<Ref> {
     /// Return an iterator over all the possible chars that could represent JSON nodes
     fn all_object_chars() -> Box<dyn Iterator<Item = char>> {
         Box::new(
-            [CHAR_TRUE, CHAR_FALSE, CHAR_ARRAY, CHAR_OBJECT, CHAR_STRING]
-                .iter()
-                .copied(),
+            [
+                CHAR_TRUE,
+                CHAR_FALSE,
+                CHAR_NULL,
+                CHAR_ARRAY,
+                CHAR_OBJECT,
+                CHAR_STRING,
+                CHAR_NUMBER,
+            ]
+            .iter()
+            .copied(),
         )
     }
+
+    /// Creates a [`Number`](JSON::Number) with a given value, or returns [`None`] if the value
+    /// can't be written in JSON (i.e. it's infinite or `NaN`)
+    pub fn from_f64(value: f64) -> Option<JSON<Ref>> {
+        if value.is_finite() {
+            Some(JSON::Number(value.to_string()))
+        } else {
+            None
+        }
+    }
 }
 
 impl<Ref: Reference> Default for JSON<Ref> {
@@ -66,90 +213,122 @@ impl<Ref: Reference> ASTSpec<Ref> for JSON<Ref> {
 
     fn display_tokens(&self, format_style: &Self::FormatStyle) -> Vec<DisplayToken<Ref>> {
         let is_pretty = format_style == &JSONFormat::Pretty;
+        let is_minified = format_style == &JSONFormat::Minified;
+        // Minified containers are the same as compact ones, without the spaces after commas
+        let container = |open, children: &[Ref], close| {
+            let mut tokens = Self::container_tokens(open, children, close, is_pretty);
+            if is_minified {
+                tokens.retain(|tok| !matches!(tok, DisplayToken::Whitespace(_)));
+            }
+            tokens
+        };
         match self {
             JSON::True => vec![DisplayToken::Text("true".to_string())],
             JSON::False => vec![DisplayToken::Text("false".to_string())],
+            JSON::Null => vec![DisplayToken::Text("null".to_string())],
             JSON::Str(string) => vec![DisplayToken::Text(format!(r#""{}""#, string))],
+            JSON::Number(number) => vec![DisplayToken::Text(number.clone())],
             JSON::Field([key, value]) => vec![
                 DisplayToken::Child(*key),
-                DisplayToken::Text(": ".to_string()),
+                DisplayToken::Text(if is_minified { ":" } else { ": " }.to_string()),
                 DisplayToken::Child(*value),
             ],
-            JSON::Array(children) => {
-                // Special case: if this array is empty, render it as '[]'
-                if children.is_empty() {
-                    return vec![DisplayToken::Text("[]".to_string())];
-                }
+            JSON::Array(children) => container("[", children, "]"),
+            JSON::Object(fields) => container("{", fields, "}"),
+        }
+    }
 
-                let mut tokens = Vec::with_capacity(6 + 3 * children.len());
-                // Push some initial tokens
-                tokens.push(DisplayToken::Text("[".to_string()));
-                if is_pretty {
-                    tokens.push(DisplayToken::Newline);
-                    tokens.push(DisplayToken::Indent);
+    fn write_text_indented(
+        root: Ref,
+        node_map: &impl NodeMap<Ref, Self>,
+        string: &mut String,
+        format_style: &Self::FormatStyle,
+        indent_style: &IndentStyle,
+    ) {
+        /// A piece of text that is still to be written
+        enum Piece<Ref> {
+            /// The node with a given reference, at a given indentation level
+            Node(Ref, usize),
+            Text(&'static str),
+            /// A newline, followed by the indentation for a given level
+            Newline(usize),
+        }
+
+        let is_pretty = format_style == &JSONFormat::Pretty;
+        let (field_separator, child_separator) = match format_style {
+            JSONFormat::Minified => (":", ","),
+            _ => (": ", ", "),
+        };
+        // The pieces are written in a loop rather than recursively, so deep trees can't overflow
+        // the stack.  Every piece is popped off the end, so the pieces of each node are pushed in
+        // reverse order.
+        let mut pieces = vec![Piece::Node(root, 0)];
+        while let Some(piece) = pieces.pop() {
+            let (id, depth) = match piece {
+                Piece::Node(id, depth) => (id, depth),
+                Piece::Text(text) => {
+                    string.push_str(text);
+                    continue;
                 }
-                // Push the children, delimited by commas
-                let mut is_first_child = true;
-                for c in children {
-                    // Push the delimiting
-                    if !is_first_child {
-                        tokens.push(DisplayToken::Text(",".to_string()));
-                        if is_pretty {
-                            tokens.push(DisplayToken::Newline);
-                        } else {
-                            tokens.push(DisplayToken::Whitespace(1));
-                        }
-                    }
-                    is_first_child = false;
-                    // Push the single child
-                    tokens.push(DisplayToken::Child(*c));
+                Piece::Newline(depth) => {
+                    string.push('\n');
+                    indent_style.push_levels(string, depth);
+                    continue;
                 }
-                // Push the closing bracket
-                if is_pretty {
-                    tokens.push(DisplayToken::Newline);
-                    tokens.push(DisplayToken::Dedent);
+            };
+            let (open, children, close) = match node_map.get_node(id) {
+                Some(JSON::True) => {
+                    string.push_str("true");
+                    continue;
                 }
-                tokens.push(DisplayToken::Text("]".to_string()));
-                // Return the token stream
-                tokens
-            }
-            JSON::Object(fields) => {
-                // Special case: if this object is empty, render it as '{}'
-                if fields.is_empty() {
-                    return vec![DisplayToken::Text("{}".to_string())];
+                Some(JSON::False) => {
+                    string.push_str("false");
+                    continue;
                 }
-
-                let mut tokens = Vec::with_capacity(6 + 3 * fields.len());
-                // Push some initial tokens
-                tokens.push(DisplayToken::Text("{".to_string()));
-                if is_pretty {
-                    tokens.push(DisplayToken::Newline);
-                    tokens.push(DisplayToken::Indent);
+                Some(JSON::Null) => {
+                    string.push_str("null");
+                    continue;
                 }
-                // Push the children, delimited by commas
-                let mut is_first_child = true;
-                for f in fields {
-                    // Push the delimiting
-                    if !is_first_child {
-                        tokens.push(DisplayToken::Text(",".to_string()));
-                        if is_pretty {
-                            tokens.push(DisplayToken::Newline);
-                        } else {
-                            tokens.push(DisplayToken::Whitespace(1));
-                        }
-                    }
-                    is_first_child = false;
-                    // Push the single child
-                    tokens.push(DisplayToken::Child(*f));
+                Some(JSON::Str(content)) => {
+                    string.push('"');
+                    string.push_str(content);
+                    string.push('"');
+                    continue;
+                }
+                Some(JSON::Number(number)) => {
+                    string.push_str(number);
+                    continue;
+                }
+                Some(JSON::Field([key, value])) => {
+                    pieces.push(Piece::Node(*value, depth));
+                    pieces.push(Piece::Text(field_separator));
+                    pieces.push(Piece::Node(*key, depth));
+                    continue;
+                }
+                Some(JSON::Array(children)) => ("[", children, "]"),
+                Some(JSON::Object(fields)) => ("{", fields, "}"),
+                None => {
+                    string.push_str(&format!("<INVALID REF {:?}>", id));
+                    continue;
                 }
-                // Push the closing bracket
+            };
+            string.push_str(open);
+            if children.is_empty() {
+                string.push_str(close);
+                continue;
+            }
+            pieces.push(Piece::Text(close));
+            if is_pretty {
+                pieces.push(Piece::Newline(depth));
+            }
+            for (i, child) in children.iter().enumerate().rev() {
+                pieces.push(Piece::Node(*child, depth + 1));
                 if is_pretty {
-                    tokens.push(DisplayToken::Newline);
-                    tokens.push(DisplayToken::Dedent);
+                    pieces.push(Piece::Newline(depth + 1));
+                }
+                if i > 0 {
+                    pieces.push(Piece::Text(if is_pretty { "," } else { child_separator }));
                 }
-                tokens.push(DisplayToken::Text("}".to_string()));
-                // Return the token stream
-                tokens
             }
         }
     }
@@ -163,16 +342,18 @@ impl<Ref: Reference> ASTSpec<Ref> for JSON<Ref> {
                     .unwrap()
                     .size(node_map, format_style)
             };
-        };
+        }
 
         match format_style {
             JSONFormat::Pretty => {
                 match self {
                     JSON::True => Size::new(0, 4),  // same as Size::from("true")
                     JSON::False => Size::new(0, 5), // same as Size::from("false")
+                    JSON::Null => Size::new(0, 4),  // same as Size::from("null")
                     JSON::Str(string) => {
                         Size::new(0, 1) + Size::from(string.as_str()) + Size::new(0, 1)
                     }
+                    JSON::Number(number) => Size::from(number.as_str()),
                     JSON::Field([key, value]) => {
                         get_size!(*key) + Size::new(0, 2) + get_size!(*value)
                     }
@@ -212,15 +393,24 @@ impl<Ref: Reference> ASTSpec<Ref> for JSON<Ref> {
                     }
                 }
             }
-            JSONFormat::Compact => {
+            JSONFormat::Compact | JSONFormat::Minified => {
+                // The width of the ", " or ": " between children, which is just the ',' or ':'
+                // when minified
+                let separator_width = if format_style == &JSONFormat::Minified {
+                    1
+                } else {
+                    2
+                };
                 match self {
                     JSON::True => Size::new(0, 4),  // same as Size::from("true")
                     JSON::False => Size::new(0, 5), // same as Size::from("false")
+                    JSON::Null => Size::new(0, 4),  // same as Size::from("null")
                     JSON::Str(string) => {
                         Size::new(0, 1) + Size::from(string.as_str()) + Size::new(0, 1)
                     }
+                    JSON::Number(number) => Size::from(number.as_str()),
                     JSON::Field([key, value]) => {
-                        get_size!(*key) + Size::new(0, 2) + get_size!(*value)
+                        get_size!(*key) + Size::new(0, separator_width) + get_size!(*value)
                     }
                     JSON::Object(fields) => {
                         // Size accumulator - starts with just the size of "{"
@@ -230,7 +420,7 @@ impl<Ref: Reference> ASTSpec<Ref> for JSON<Ref> {
                         for f in fields {
                             // If we're not on the first child, add a ", "
                             if !is_first_child {
-                                size += Size::new(0, 2);
+                                size += Size::new(0, separator_width);
                             }
                             is_first_child = false;
                             size += get_size!(*f);
@@ -246,7 +436,7 @@ impl<Ref: Reference> ASTSpec<Ref> for JSON<Ref> {
                         for c in children {
                             // If we're not on the first child, add a ", "
                             if !is_first_child {
-                                size += Size::new(0, 2);
+                                size += Size::new(0, separator_width);
                             }
                             is_first_child = false;
                             size += get_size!(*c);
@@ -263,92 +453,616 @@ impl<Ref: Reference> ASTSpec<Ref> for JSON<Ref> {
 
     fn children(&self) -> &[Ref] {
         match self {
-            JSON::True | JSON::False | JSON::Str(_) => &[],
-            JSON::Array(children) => &children,
-            JSON::Object(fields) => &fields,
+            JSON::True | JSON::False | JSON::Null | JSON::Str(_) | JSON::Number(_) => &[],
+            JSON::Array(children) => children,
+            JSON::Object(fields) => fields,
             JSON::Field(key_value) => &key_value[..],
         }
     }
 
     fn children_mut(&mut self) -> &mut [Ref] {
         match self {
-            JSON::True | JSON::False | JSON::Str(_) => &mut [],
+            JSON::True | JSON::False | JSON::Null | JSON::Str(_) | JSON::Number(_) => &mut [],
             JSON::Array(children) => children,
             JSON::Object(fields) => fields,
             JSON::Field(key_value) => &mut key_value[..],
         }
     }
 
+    fn heap_size(&self) -> usize {
+        match self {
+            JSON::True | JSON::False | JSON::Null | JSON::Field(_) => 0,
+            JSON::Array(children) | JSON::Object(children) => {
+                children.capacity() * std::mem::size_of::<Ref>()
+            }
+            JSON::Str(content) | JSON::Number(content) => content.capacity(),
+        }
+    }
+
     fn display_name(&self) -> String {
         match self {
             JSON::True => "true".to_string(),
             JSON::False => "false".to_string(),
+            JSON::Null => "null".to_string(),
             JSON::Array(_) => "array".to_string(),
             JSON::Object(_) => "object".to_string(),
             JSON::Field(_) => "field".to_string(),
             JSON::Str(content) => format!(r#""{}""#, content),
+            JSON::Number(number) => number.clone(),
+        }
+    }
+
+    /// Containers are summarised by their brackets and size, and fields by their key
+    fn fold_summary(&self, node_map: &impl NodeMap<Ref, Self>, marker: &str) -> String {
+        match self {
+            JSON::Array(values) => format!(
+                "[{}] {} item{}",
+                marker,
+                values.len(),
+                if values.len() == 1 { "" } else { "s" }
+            ),
+            JSON::Object(fields) => format!(
+                "{{{}}} {} entr{}",
+                marker,
+                fields.len(),
+                if fields.len() == 1 { "y" } else { "ies" }
+            ),
+            JSON::Field([key, _]) => match node_map.get_node(*key) {
+                Some(JSON::Str(key)) => format!(r#""{}": {}"#, key, marker),
+                _ => format!("field {}", marker),
+            },
+            _ => format!("{} {}", self.display_name(), marker),
+        }
+    }
+
+    fn record_entries(&self, node_map: &impl NodeMap<Ref, Self>) -> Option<Vec<(String, String)>> {
+        let fields = match self {
+            JSON::Object(fields) => fields,
+            _ => return None,
+        };
+        let mut entries = Vec::with_capacity(fields.len());
+        for f in fields {
+            let (key, value) = match node_map.get_node(*f)? {
+                JSON::Field([key, value]) => (*key, *value),
+                _ => return None,
+            };
+            let key = match node_map.get_node(key)? {
+                JSON::Str(key) => key.clone(),
+                _ => return None,
+            };
+            let value = match node_map.get_node(value)? {
+                // Strings are given without their quotes
+                JSON::Str(content) => content.clone(),
+                _ => Self::to_text(value, node_map, &JSONFormat::Compact),
+            };
+            entries.push((key, value));
+        }
+        Some(entries)
+    }
+
+    fn key_index(&self) -> Option<usize> {
+        match self {
+            JSON::Field(_) => Some(0),
+            _ => None,
         }
     }
 
+    fn kind_name(&self) -> &'static str {
+        match self {
+            JSON::True | JSON::False => "bool",
+            JSON::Null => "null",
+            JSON::Array(_) => "array",
+            JSON::Object(_) => "object",
+            JSON::Field(_) => "field",
+            JSON::Str(_) => "string",
+            JSON::Number(_) => "number",
+        }
+    }
+
+    fn next_format_style(style: &JSONFormat) -> Option<JSONFormat> {
+        Some(match style {
+            JSONFormat::Pretty => JSONFormat::Compact,
+            JSONFormat::Compact => JSONFormat::Minified,
+            JSONFormat::Minified => JSONFormat::Pretty,
+        })
+    }
+
     /* AST EDITING FUNCTIONS */
 
     fn replace_chars(&self) -> Box<dyn Iterator<Item = char>> {
-        Self::all_object_chars()
+        match self {
+            // A field can be reset to a placeholder, but nothing else can contain a field
+            JSON::Field(_) => Box::new(Self::all_object_chars().chain(std::iter::once(CHAR_FIELD))),
+            _ => Self::all_object_chars(),
+        }
+    }
+
+    fn char_descriptions() -> Vec<(char, &'static str)> {
+        vec![
+            (CHAR_TRUE, "true"),
+            (CHAR_FALSE, "false"),
+            (CHAR_NULL, "null"),
+            (CHAR_ARRAY, "empty array"),
+            (CHAR_OBJECT, "empty object"),
+            (CHAR_STRING, "empty string"),
+            (CHAR_NUMBER, "zero"),
+            (CHAR_FIELD, "object field (with a placeholder key)"),
+        ]
     }
 
     fn from_char(&self, c: char) -> Option<Self> {
         match c {
             CHAR_TRUE => Some(JSON::True),
             CHAR_FALSE => Some(JSON::False),
+            CHAR_NULL => Some(JSON::Null),
             CHAR_ARRAY => Some(JSON::Array(vec![])),
             CHAR_OBJECT => Some(JSON::Object(vec![])),
             CHAR_STRING => Some(JSON::Str("".to_string())),
+            CHAR_NUMBER => Some(JSON::Number("0".to_string())),
             _ => None,
         }
     }
 
+    /// Fields are made with the placeholder key `"key"` and the value `true`, which can then be
+    /// edited
+    fn from_char_with_children(
+        &self,
+        c: char,
+        node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Self> {
+        if c != CHAR_FIELD {
+            return self.from_char(c);
+        }
+        let key = node_map.add_node(JSON::Str(String::from("key")));
+        let value = node_map.add_node(JSON::True);
+        Some(JSON::Field([key, value]))
+    }
+
     fn insert_chars(&self) -> Box<dyn Iterator<Item = char>> {
         match self {
-            JSON::True | JSON::False | JSON::Field(_) | JSON::Str(_) => {
-                Box::new(std::iter::empty())
-            }
+            JSON::True
+            | JSON::False
+            | JSON::Null
+            | JSON::Field(_)
+            | JSON::Str(_)
+            | JSON::Number(_) => Box::new(std::iter::empty()),
             JSON::Object(_) => Box::new(std::iter::once(CHAR_FIELD)),
             JSON::Array(_) => Self::all_object_chars(),
         }
     }
+
+    fn sibling_from_char(&self, c: char, node_map: &mut impl NodeMapMut<Ref, Self>) -> Option<Ref> {
+        match self {
+            // A value opened next to a field becomes the value of a new field with a placeholder
+            // key, since objects can only contain fields
+            JSON::Object(_) if c != CHAR_FIELD => {
+                let value = node_map.add_node(self.from_char(c)?);
+                let key = node_map.add_node(JSON::Str(String::from("key")));
+                Some(node_map.add_node(JSON::Field([key, value])))
+            }
+            _ => self.child_from_char(c, node_map),
+        }
+    }
+
+    fn child_from_char(&self, c: char, node_map: &mut impl NodeMapMut<Ref, Self>) -> Option<Ref> {
+        match self {
+            JSON::Object(_) | JSON::Array(_) if self.is_insert_char(c) => self
+                .from_char_with_children(c, node_map)
+                .map(|node| node_map.add_node(node)),
+            _ => None,
+        }
+    }
+
+    fn wrap_from_char(
+        &self,
+        c: char,
+        this: Ref,
+        node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Ref> {
+        let wrapper = match (self, c) {
+            // Fields can only be inside objects
+            (JSON::Field(_), _) => return None,
+            // Objects can only contain fields, so the value is given a placeholder key
+            (_, CHAR_OBJECT) => {
+                let key = node_map.add_node(JSON::Str(String::from("key")));
+                let field = node_map.add_node(JSON::Field([key, this]));
+                JSON::Object(vec![field])
+            }
+            (_, CHAR_ARRAY) => JSON::Array(vec![this]),
+            _ => return None,
+        };
+        Some(node_map.add_node(wrapper))
+    }
+
+    fn with_children_of(
+        &self,
+        old: &Self,
+        node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Self> {
+        match (self, old) {
+            (JSON::Array(_), JSON::Array(children)) => Some(JSON::Array(children.clone())),
+            (JSON::Object(_), JSON::Object(fields)) => Some(JSON::Object(fields.clone())),
+            // Arrays can't contain fields, so only the values of an object's fields are kept
+            (JSON::Array(_), JSON::Object(fields)) => Some(JSON::Array(
+                fields
+                    .iter()
+                    .filter_map(|f| match node_map.get_node(*f) {
+                        Some(JSON::Field([_, value])) => Some(*value),
+                        _ => None,
+                    })
+                    .collect(),
+            )),
+            // Objects can only contain fields, so each element is given its index as a key
+            (JSON::Object(_), JSON::Array(children)) => Some(JSON::Object(
+                children
+                    .iter()
+                    .enumerate()
+                    .map(|(i, child)| {
+                        let key = node_map.add_node(JSON::Str(i.to_string()));
+                        node_map.add_node(JSON::Field([key, *child]))
+                    })
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn wrapped_child(&self, node_map: &impl NodeMap<Ref, Self>) -> Option<Ref> {
+        match self {
+            JSON::Array(children) if children.len() == 1 => Some(children[0]),
+            // Unwrapping an object gives the value of its only field
+            JSON::Object(fields) if fields.len() == 1 => match node_map.get_node(fields[0])? {
+                JSON::Field([_, value]) => Some(*value),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Fields are named by their key, like in JavaScript
+    fn child_name<'a>(
+        &'a self,
+        index: usize,
+        node_map: &'a impl NodeMap<Ref, Self>,
+    ) -> Option<Cow<'a, str>> {
+        let field = match self {
+            JSON::Object(fields) => node_map.get_node(*fields.get(index)?)?,
+            _ => return None,
+        };
+        match node_map.get_node(*field.children().first()?)? {
+            JSON::Str(key) => Some(Cow::Borrowed(key)),
+            _ => None,
+        }
+    }
+
+    fn child_path_segment(&self, index: usize, node_map: &impl NodeMap<Ref, Self>) -> String {
+        match self {
+            // The field already names its value, so only the key needs marking
+            JSON::Field(_) if index == 0 => "(key)".to_string(),
+            JSON::Field(_) => String::new(),
+            _ => name_path_segment(self.child_name(index, node_map).as_deref(), index),
+        }
+    }
+
+    fn insert_child(&mut self, child: Ref, index: usize) -> bool {
+        match self {
+            JSON::True
+            | JSON::False
+            | JSON::Null
+            | JSON::Field(_)
+            | JSON::Str(_)
+            | JSON::Number(_) => false,
+            JSON::Array(children) | JSON::Object(children) => {
+                children.insert(index.min(children.len()), child);
+                true
+            }
+        }
+    }
+
+    fn remove_child(&mut self, index: usize) -> bool {
+        match self {
+            JSON::Array(children) | JSON::Object(children) if index < children.len() => {
+                children.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn merge_with(&self, other: &Self, separator: &str) -> Option<Self> {
+        match (self, other) {
+            (JSON::Str(first), JSON::Str(second)) => {
+                Some(JSON::Str(format!("{}{}{}", first, separator, second)))
+            }
+            _ => None,
+        }
+    }
+
+    fn normalized(&self) -> Option<Self> {
+        match self {
+            // Strings with invalid escapes are left alone, rather than being corrupted
+            JSON::Str(content) => unescape_string(content).map(|s| JSON::Str(escape_string(&s))),
+            _ => None,
+        }
+    }
+
+    fn inverted(&self) -> Option<Self> {
+        match self {
+            JSON::True => Some(JSON::False),
+            JSON::False => Some(JSON::True),
+            _ => None,
+        }
+    }
+
+    fn syntax_category(&self) -> SyntaxCategory {
+        match self {
+            JSON::True | JSON::False | JSON::Null | JSON::Number(_) => SyntaxCategory::Literal,
+            JSON::Str(_) => SyntaxCategory::String,
+            // Containers only generate brackets, commas and colons themselves
+            JSON::Array(_) | JSON::Object(_) | JSON::Field(_) => SyntaxCategory::Punctuation,
+        }
+    }
+
+    fn check_replacement(&self, index: usize, new_child: &Self) -> Result<(), String> {
+        match (self, new_child) {
+            (JSON::Field(_), JSON::Str(_)) => Ok(()),
+            (JSON::Field(_), _) if index == 0 => {
+                Err("the key of a field must be a string".to_string())
+            }
+            (JSON::Object(_), JSON::Field(_)) => Ok(()),
+            (JSON::Object(_), _) => Err("objects can only contain fields".to_string()),
+            (_, JSON::Field(_)) => Err("fields can only be inside objects".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_valid(&self, node_map: &impl NodeMap<Ref, Self>) -> Result<(), String> {
+        let is_field = |r: &Ref| matches!(node_map.get_node(*r), Some(JSON::Field(_)));
+        match self {
+            JSON::Object(fields)
+                if !fields
+                    .iter()
+                    .all(|f| is_field(f) || node_map.get_node(*f).is_none()) =>
+            {
+                Err("objects can only contain fields".to_string())
+            }
+            JSON::Field([key, _])
+                if !matches!(node_map.get_node(*key), Some(JSON::Str(_)) | None) =>
+            {
+                Err("the key of a field must be a string".to_string())
+            }
+            JSON::Field([_, value]) if is_field(value) => {
+                Err("fields can only be inside objects".to_string())
+            }
+            JSON::Array(children) if children.iter().any(is_field) => {
+                Err("fields can only be inside objects".to_string())
+            }
+            // Leaves have nowhere to store children, so they're always valid
+            _ => Ok(()),
+        }
+    }
+
+    fn literal(&self) -> Option<&str> {
+        match self {
+            JSON::Str(content) | JSON::Number(content) => Some(content),
+            _ => None,
+        }
+    }
+
+    fn with_literal(&self, text: &str) -> Option<Self> {
+        match self {
+            JSON::Str(_) => Some(JSON::Str(text.to_string())),
+            JSON::Number(_) if is_valid_number(text) => Some(JSON::Number(text.to_string())),
+            _ => None,
+        }
+    }
+
+    fn check_literal(&self, text: &str) -> LiteralValidity {
+        match self {
+            JSON::Str(_) => {}
+            // Every unfinished number (e.g. `-`, `1.` or `2e+`) can be finished with a digit
+            JSON::Number(_) if is_valid_number(text) => return LiteralValidity::Valid,
+            JSON::Number(_) if is_valid_number(&format!("{}0", text)) => {
+                return LiteralValidity::Incomplete
+            }
+            _ => return LiteralValidity::Invalid,
+        }
+        // Quotes and control characters can't appear unescaped in a string, no matter what is
+        // typed after them
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => return LiteralValidity::Invalid,
+                c if (c as u32) < 0x20 => return LiteralValidity::Invalid,
+                _ => {}
+            }
+        }
+        if unescape_string(text).is_some() {
+            return LiteralValidity::Valid;
+        }
+        // Otherwise the string must end with an escape sequence, which might be finished by
+        // typing one of these
+        let completions = [
+            "n", "0", "00", "000", "0000", "c00", "dc00", "udc00", "\\udc00",
+        ];
+        if completions
+            .iter()
+            .any(|end| unescape_string(&format!("{}{}", text, end)).is_some())
+        {
+            LiteralValidity::Incomplete
+        } else {
+            LiteralValidity::Invalid
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{JSONFormat, JSON};
+    use super::{escape_string, is_valid_number, unescape_string, JSONFormat, JSON};
+    use crate::ast_spec::display_token::{write_tokens_indented, IndentStyle};
     use crate::ast_spec::size::Size;
     use crate::ast_spec::test_json::TestJSON;
-    use crate::ast_spec::ASTSpec;
+    use crate::ast_spec::{
+        check_tree, display_path, resolve_path, subtree_hash, subtrees_equal, ASTSpec,
+        InvalidTreeError, LiteralValidity,
+    };
     use crate::node_map::vec::{Index, VecNodeMap};
     use crate::node_map::{NodeMap, NodeMapMut};
+    use std::collections::HashMap;
 
     /// Non-generic version of [`TestJSON::build_node_map`] that always returns a [`VecNodeMap`].
     fn build_vec_node_map(tree: &TestJSON) -> VecNodeMap<JSON<Index>> {
         tree.build_node_map::<Index, VecNodeMap<JSON<Index>>>()
     }
 
+    #[test]
+    fn writing_text_matches_display_tokens() {
+        let styles = [
+            JSONFormat::Pretty,
+            JSONFormat::Compact,
+            JSONFormat::Minified,
+        ];
+        let indent_styles = [
+            IndentStyle::default(),
+            IndentStyle::Spaces(1),
+            IndentStyle::Tabs,
+        ];
+        let check = |node_map: &VecNodeMap<JSON<Index>>| -> Result<(), String> {
+            for (style, indent_style) in styles
+                .iter()
+                .flat_map(|s| indent_styles.iter().map(move |i| (s, i)))
+            {
+                let mut expected = String::new();
+                write_tokens_indented(
+                    node_map.root(),
+                    node_map,
+                    &mut expected,
+                    style,
+                    indent_style,
+                );
+                let mut text = String::new();
+                JSON::write_text_indented(
+                    node_map.root(),
+                    node_map,
+                    &mut text,
+                    style,
+                    indent_style,
+                );
+                if text != expected {
+                    return Err(format!("wrote {:?} instead of {:?}", text, expected));
+                }
+            }
+            Ok(())
+        };
+        TestJSON::check_property(300, 4, 4, |tree, _| check(&build_vec_node_map(tree)));
+        // Invalid references are written the same way too
+        let mut node_map = build_vec_node_map(&TestJSON::Array(vec![
+            TestJSON::Object(vec![("k".to_string(), TestJSON::True)]),
+            TestJSON::Null,
+        ]));
+        let null = node_map.root_node().children()[1];
+        node_map.remove_node(null).unwrap();
+        check(&node_map).unwrap();
+        assert!(node_map
+            .to_text(&JSONFormat::Compact)
+            .contains("<INVALID REF"));
+    }
+
+    #[test]
+    fn writing_deep_trees() {
+        // A chain of 100,000 nested arrays, which would overflow the stack if written recursively
+        let mut node_map: VecNodeMap<JSON<Index>> = VecNodeMap::with_root(JSON::True);
+        for _ in 0..100_000 {
+            let root = node_map.root();
+            node_map.add_as_root(JSON::Array(vec![root]));
+        }
+        let text = node_map.to_text(&JSONFormat::Minified);
+        assert_eq!(
+            text,
+            format!("{}true{}", "[".repeat(100_000), "]".repeat(100_000))
+        );
+    }
+
+    /// Compares the speed of writing a large tree with [`JSON::write_text_indented`] against
+    /// going through the [`DisplayToken`](super::DisplayToken)s of every node.  Run with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn writing_text_speed() {
+        let record = TestJSON::Object(vec![
+            ("name".to_string(), TestJSON::Str("sapling".to_string())),
+            ("count".to_string(), TestJSON::Number("42".to_string())),
+            (
+                "tags".to_string(),
+                TestJSON::Array(vec![TestJSON::True, TestJSON::Null]),
+            ),
+        ]);
+        // 10 nodes per record
+        let node_map = build_vec_node_map(&TestJSON::Array(vec![record; 10_000]));
+        let time = |write: &dyn Fn(&mut String)| {
+            let start = std::time::Instant::now();
+            let mut text = String::new();
+            for _ in 0..10 {
+                text.clear();
+                write(&mut text);
+            }
+            (start.elapsed() / 10, text)
+        };
+        let style = JSONFormat::Pretty;
+        let indent_style = IndentStyle::default();
+        let (token_time, token_text) = time(&|text| {
+            write_tokens_indented(node_map.root(), &node_map, text, &style, &indent_style)
+        });
+        let (direct_time, direct_text) = time(&|text| {
+            JSON::write_text_indented(node_map.root(), &node_map, text, &style, &indent_style)
+        });
+        assert_eq!(direct_text, token_text);
+        println!(
+            "Writing {} nodes: {:?} with display tokens, {:?} directly",
+            node_map.iter_all().count(),
+            token_time,
+            direct_time
+        );
+    }
+
     #[test]
     fn to_text() {
         for (tree, expected_compact_string, expected_pretty_string, tree_string) in &[
             (TestJSON::True, "true", "true", "true"),
             (TestJSON::False, "false", "false", "false"),
+            (TestJSON::Null, "null", "null", "null"),
             (TestJSON::Array(vec![]), "[]", "[]", "array"),
             (TestJSON::Object(vec![]), "{}", "{}", "object"),
+            (TestJSON::Number("1e10".to_string()), "1e10", "1e10", "1e10"),
             (
-                TestJSON::Array(vec![TestJSON::True, TestJSON::False]),
-                "[true, false]",
+                TestJSON::Array(vec![
+                    TestJSON::Number("0.1".to_string()),
+                    TestJSON::Number("-0".to_string()),
+                ]),
+                "[0.1, -0]",
+                "[
+    0.1,
+    -0
+]",
+                "array
+  0.1
+  -0",
+            ),
+            (
+                TestJSON::Array(vec![TestJSON::True, TestJSON::False, TestJSON::Null]),
+                "[true, false, null]",
                 "[
     true,
-    false
+    false,
+    null
 ]",
                 "array
   true
-  false",
+  false
+  null",
             ),
             (
                 TestJSON::Object(vec![
@@ -429,4 +1143,604 @@ mod tests {
             assert_eq!(s, *tree_string);
         }
     }
+
+    #[test]
+    fn paths() {
+        let node_map = build_vec_node_map(&TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::False,
+            TestJSON::Object(vec![
+                ("value".to_string(), TestJSON::Array(vec![TestJSON::True])),
+                ("two words".to_string(), TestJSON::True),
+            ]),
+        ]));
+        for (path, expected) in &[
+            (&[][..], Some("root")),
+            (&[1], Some("root[1]")),
+            (&[2, 0], Some("root[2].value")),
+            (&[2, 0, 1, 0], Some("root[2].value[0]")),
+            (&[2, 0, 0], Some("root[2].value(key)")),
+            (&[2, 1, 1], Some(r#"root[2]["two words"]"#)),
+            (&[3], None),
+            (&[0, 0], None),
+        ] {
+            assert_eq!(display_path(&node_map, path).as_deref(), *expected);
+        }
+    }
+
+    #[test]
+    fn child_names_and_resolving_paths() {
+        let object = |fields: Vec<(&str, TestJSON)>| {
+            TestJSON::Object(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+            )
+        };
+        let node_map = build_vec_node_map(&object(vec![
+            (
+                "settings",
+                object(vec![
+                    ("enabled", TestJSON::True),
+                    (
+                        "tags",
+                        TestJSON::Array(vec![object(vec![("id", TestJSON::Null)])]),
+                    ),
+                ]),
+            ),
+            ("two words", TestJSON::Array(vec![TestJSON::False])),
+            ("val", TestJSON::Null),
+            ("value", TestJSON::True),
+        ]));
+        // Only the fields of objects have names
+        let root = node_map.root_node();
+        assert_eq!(root.child_name(1, &node_map).as_deref(), Some("two words"));
+        assert_eq!(root.child_name(4, &node_map), None);
+        let field = node_map.get_node(root.children()[0]).unwrap();
+        assert_eq!(field.child_name(1, &node_map), None);
+
+        for (path, text) in &[
+            (&[][..], "root"),
+            (&[0], "root.settings"),
+            (&[0, 1, 0], "root.settings.enabled"),
+            (&[0, 1, 0, 0], "root.settings.enabled(key)"),
+            (&[0, 1, 1, 1, 0], "root.settings.tags[0]"),
+            (&[0, 1, 1, 1, 0, 0], "root.settings.tags[0].id"),
+            (&[1, 1, 0], r#"root["two words"][0]"#),
+            (&[2], "root.val"),
+            (&[3], "root.value"),
+        ] {
+            assert_eq!(display_path(&node_map, path).as_deref(), Some(*text));
+            assert_eq!(resolve_path(&node_map, text).as_deref(), Some(*path));
+        }
+        // A field and its value have the same path, which is resolved to the field
+        assert_eq!(
+            display_path(&node_map, &[0, 1, 0, 1]).as_deref(),
+            Some("root.settings.enabled")
+        );
+        for text in &[
+            "root.nope",
+            "root.settings.enab",
+            "root[9]",
+            "settings",
+            "root.val.x",
+        ] {
+            assert_eq!(resolve_path(&node_map, text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn record_entries() {
+        let node_map = build_vec_node_map(&TestJSON::Object(vec![
+            ("name".to_string(), TestJSON::Str("sapling".to_string())),
+            ("enabled".to_string(), TestJSON::True),
+            (
+                "list".to_string(),
+                TestJSON::Array(vec![TestJSON::True, TestJSON::False]),
+            ),
+        ]));
+        assert_eq!(
+            node_map.root_node().record_entries(&node_map),
+            Some(vec![
+                ("name".to_string(), "sapling".to_string()),
+                ("enabled".to_string(), "true".to_string()),
+                ("list".to_string(), "[true, false]".to_string()),
+            ])
+        );
+        // Arrays aren't records
+        let list_field = node_map.root_node().children()[2];
+        let list = node_map.get_node(list_field).unwrap().children()[1];
+        assert_eq!(
+            node_map.get_node(list).unwrap().record_entries(&node_map),
+            None
+        );
+    }
+
+    #[test]
+    fn summaries() {
+        let node_map = build_vec_node_map(&TestJSON::Object(vec![(
+            "k".to_string(),
+            TestJSON::Array(vec![TestJSON::Null]),
+        )]));
+        let field = node_map.root_node().children()[0];
+        let field_node = node_map.get_node(field).unwrap();
+        assert_eq!(node_map.root_node().summary(), "object");
+        assert_eq!(field_node.summary(), "field");
+        // Containers aren't described by their children
+        let array = node_map.get_node(field_node.children()[1]).unwrap();
+        assert_eq!(array.summary(), "array");
+        for (node, expected) in &[
+            (JSON::<Index>::True, "true"),
+            (JSON::False, "false"),
+            (JSON::Null, "null"),
+            (JSON::Str("foo".to_string()), r#"string "foo""#),
+            (JSON::Str(String::new()), r#"string """#),
+            (JSON::Number("-1.5e3".to_string()), "number -1.5e3"),
+            // Long literals are cut short
+            (
+                JSON::Str("a very long string indeed".to_string()),
+                r#"string "a very long string…"#,
+            ),
+        ] {
+            assert_eq!(node.summary(), *expected);
+        }
+        assert_eq!(
+            TestJSON::Number("12".to_string()).summary(),
+            "number 12".to_string()
+        );
+    }
+
+    #[test]
+    fn char_table() {
+        let node = JSON::<Index>::True;
+        let replace_chars: Vec<char> = node.replace_chars().collect();
+        // Every described char except the field's makes a node, and can be used to replace
+        // anything but a field
+        for (c, description) in JSON::<Index>::char_descriptions() {
+            if c == 'i' {
+                assert_eq!(description, "object field (with a placeholder key)");
+                assert_eq!(node.from_char(c), None);
+                assert!(!replace_chars.contains(&c));
+            } else {
+                assert!(node.from_char(c).is_some(), "{}", description);
+                assert!(replace_chars.contains(&c));
+            }
+        }
+        assert_eq!(
+            replace_chars.len(),
+            JSON::<Index>::char_descriptions().len() - 1
+        );
+        assert_eq!(node.from_char('q'), None);
+
+        // Field keys can only be replaced by strings
+        let node_map =
+            build_vec_node_map(&TestJSON::Object(vec![("k".to_string(), TestJSON::True)]));
+        let field = node_map
+            .get_node(node_map.root_node().children()[0])
+            .unwrap();
+        // ... but fields can be reset to a placeholder
+        assert!(field.is_replace_char('i'));
+        assert_eq!(
+            field.check_replacement(0, &JSON::True),
+            Err("the key of a field must be a string".to_string())
+        );
+        assert_eq!(
+            field.check_replacement(0, &JSON::Str("k".to_string())),
+            Ok(())
+        );
+        assert_eq!(field.check_replacement(1, &JSON::True), Ok(()));
+        assert_eq!(
+            JSON::<Index>::Array(vec![]).check_replacement(0, &JSON::Null),
+            Ok(())
+        );
+        // Fields can only replace fields
+        let object = node_map.root_node();
+        assert_eq!(
+            object.check_replacement(0, &JSON::Null),
+            Err("objects can only contain fields".to_string())
+        );
+        assert_eq!(object.check_replacement(0, field), Ok(()));
+        assert_eq!(
+            JSON::<Index>::Array(vec![]).check_replacement(0, field),
+            Err("fields can only be inside objects".to_string())
+        );
+    }
+
+    #[test]
+    fn tree_validity() {
+        let valid = build_vec_node_map(&TestJSON::Array(vec![
+            TestJSON::Null,
+            TestJSON::Object(vec![("k".to_string(), TestJSON::Array(vec![]))]),
+        ]));
+        assert_eq!(check_tree(&valid), Ok(()));
+        let array = valid.root();
+        let object = valid.root_node().children()[1];
+        let field = valid.get_node(object).unwrap().children()[0];
+        let [key, value] = match valid.get_node(field) {
+            Some(JSON::Field(key_value)) => *key_value,
+            _ => unreachable!(),
+        };
+
+        // Every reference must point to a node
+        let mut node_map = valid.clone();
+        node_map.remove_node(value).unwrap();
+        assert_eq!(
+            check_tree(&node_map),
+            Err(InvalidTreeError::MissingNode {
+                parent: Some(field),
+                id: value
+            })
+        );
+        // No node can contain itself
+        let mut node_map = valid.clone();
+        node_map
+            .overwrite_node(value, JSON::Array(vec![array]))
+            .unwrap();
+        assert_eq!(check_tree(&node_map), Err(InvalidTreeError::Cycle(array)));
+        let mut node_map = valid.clone();
+        node_map
+            .overwrite_node(value, JSON::Array(vec![object]))
+            .unwrap();
+        assert_eq!(check_tree(&node_map), Err(InvalidTreeError::Cycle(object)));
+        // Shared nodes are fine, as long as they don't form a cycle
+        let mut node_map = valid.clone();
+        let shared = node_map.add_node(JSON::True);
+        node_map
+            .overwrite_node(value, JSON::Array(vec![shared, shared]))
+            .unwrap();
+        assert_eq!(check_tree(&node_map), Ok(()));
+
+        // JSON's own rules about which nodes can go where
+        let invalid_node = |id, reason: &str| {
+            Err(InvalidTreeError::InvalidNode {
+                id,
+                reason: reason.to_string(),
+            })
+        };
+        let mut node_map = valid.clone();
+        node_map.overwrite_node(key, JSON::True).unwrap();
+        assert_eq!(
+            check_tree(&node_map),
+            invalid_node(field, "the key of a field must be a string")
+        );
+        let mut node_map = valid.clone();
+        node_map
+            .overwrite_node(object, JSON::Object(vec![value]))
+            .unwrap();
+        assert_eq!(
+            check_tree(&node_map),
+            invalid_node(object, "objects can only contain fields")
+        );
+        let mut node_map = valid;
+        node_map
+            .overwrite_node(array, JSON::Array(vec![field]))
+            .unwrap();
+        assert_eq!(
+            check_tree(&node_map),
+            invalid_node(array, "fields can only be inside objects")
+        );
+    }
+
+    /// Copy every node of `node_map` into a new map in the reverse order, so that the copy has the
+    /// same tree stored under completely different references
+    fn reversed_copy(node_map: &VecNodeMap<JSON<Index>>) -> VecNodeMap<JSON<Index>> {
+        let ids: Vec<Index> = node_map.iter_all().map(|(id, _)| id).collect();
+        // The copy's first node is never used, so that not even the root keeps its reference
+        let mut copy = VecNodeMap::with_root(JSON::Null);
+        let mut new_ids = HashMap::new();
+        for &id in ids.iter().rev() {
+            new_ids.insert(id, copy.add_node(node_map.get_node(id).unwrap().clone()));
+        }
+        for new_id in new_ids.values() {
+            for child in copy.get_node_mut(*new_id).unwrap().children_mut() {
+                *child = new_ids[child];
+            }
+        }
+        copy.set_root(new_ids[&node_map.root()]).unwrap();
+        copy
+    }
+
+    #[test]
+    fn subtree_equality() {
+        let tree = TestJSON::Array(vec![
+            TestJSON::Null,
+            TestJSON::Object(vec![
+                ("k".to_string(), TestJSON::Array(vec![TestJSON::True])),
+                ("l".to_string(), TestJSON::Number("1.5".to_string())),
+            ]),
+        ]);
+        let node_map = build_vec_node_map(&tree);
+        let root = node_map.root();
+        let copy = reversed_copy(&node_map);
+        assert_ne!(copy.root(), root);
+        assert!(subtrees_equal(&node_map, root, &copy, copy.root()));
+        assert!(subtrees_equal(&copy, copy.root(), &node_map, root));
+        assert_eq!(
+            subtree_hash(&node_map, root),
+            subtree_hash(&copy, copy.root())
+        );
+
+        // The same subtree at a different place in a bigger tree
+        let bigger =
+            build_vec_node_map(&TestJSON::Object(vec![("inner".to_string(), tree.clone())]));
+        let field = bigger.root_node().children()[0];
+        let inner = bigger.get_node(field).unwrap().children()[1];
+        assert!(subtrees_equal(&node_map, root, &bigger, inner));
+        assert!(!subtrees_equal(&node_map, root, &bigger, bigger.root()));
+        assert_eq!(subtree_hash(&node_map, root), subtree_hash(&bigger, inner));
+
+        // Trees which only differ in a deep leaf
+        let mut deep_change = tree.clone();
+        if let TestJSON::Array(children) = &mut deep_change {
+            if let TestJSON::Object(fields) = &mut children[1] {
+                fields[0].1 = TestJSON::Array(vec![TestJSON::False]);
+            }
+        }
+        let changed = build_vec_node_map(&deep_change);
+        assert!(!subtrees_equal(&node_map, root, &changed, changed.root()));
+        assert_ne!(
+            subtree_hash(&node_map, root),
+            subtree_hash(&changed, changed.root())
+        );
+        // Differing keys or numbers of children are also noticed
+        for other in &[
+            TestJSON::Array(vec![TestJSON::Null]),
+            TestJSON::Array(vec![
+                TestJSON::Null,
+                TestJSON::Object(vec![
+                    ("k".to_string(), TestJSON::Array(vec![TestJSON::True])),
+                    ("m".to_string(), TestJSON::Number("1.5".to_string())),
+                ]),
+            ]),
+        ] {
+            let other_map = build_vec_node_map(other);
+            assert!(!subtrees_equal(
+                &node_map,
+                root,
+                &other_map,
+                other_map.root()
+            ));
+        }
+
+        // Sharing a node is the same as having two copies of it
+        let mut shared = VecNodeMap::with_root(JSON::Array(vec![]));
+        let leaf = shared.add_node(JSON::True);
+        shared
+            .overwrite_node(shared.root(), JSON::Array(vec![leaf, leaf]))
+            .unwrap();
+        let unshared = build_vec_node_map(&TestJSON::Array(vec![TestJSON::True, TestJSON::True]));
+        assert!(subtrees_equal(
+            &shared,
+            shared.root(),
+            &unshared,
+            unshared.root()
+        ));
+        assert_eq!(
+            subtree_hash(&shared, shared.root()),
+            subtree_hash(&unshared, unshared.root())
+        );
+    }
+
+    #[test]
+    fn subtree_equality_of_corrupt_maps() {
+        let node_map = build_vec_node_map(&TestJSON::Array(vec![TestJSON::Array(vec![])]));
+        let root = node_map.root();
+        let inner = node_map.root_node().children()[0];
+
+        // Cycles must be detected rather than followed forever, even when both maps contain the
+        // same cycle
+        let mut cyclic = node_map.clone();
+        cyclic
+            .overwrite_node(inner, JSON::Array(vec![root]))
+            .unwrap();
+        assert!(!subtrees_equal(&cyclic, root, &cyclic, root));
+        assert!(!subtrees_equal(&cyclic, root, &node_map, root));
+        assert_eq!(subtree_hash(&cyclic, root), None);
+        // Invalid references are never equal, even to other invalid references
+        let mut missing = node_map.clone();
+        missing.remove_node(inner).unwrap();
+        assert!(!subtrees_equal(&missing, root, &missing, root));
+        assert!(!subtrees_equal(&missing, inner, &missing, inner));
+        assert_eq!(subtree_hash(&missing, root), None);
+        assert!(subtrees_equal(&node_map, root, &node_map, root));
+    }
+
+    #[test]
+    fn minified() {
+        for (tree, expected) in &[
+            (TestJSON::Array(vec![]), "[]"),
+            (TestJSON::Object(vec![]), "{}"),
+            (
+                TestJSON::Array(vec![
+                    TestJSON::True,
+                    TestJSON::False,
+                    TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
+                ]),
+                r#"[true,false,{"value":true}]"#,
+            ),
+            (
+                TestJSON::Object(vec![
+                    (
+                        r#"a \"b\""#.to_string(),
+                        TestJSON::Str(r#"c\n🌳"#.to_string()),
+                    ),
+                    (
+                        "deep".to_string(),
+                        TestJSON::Array(vec![TestJSON::Array(vec![TestJSON::Object(vec![(
+                            "x".to_string(),
+                            TestJSON::Array(vec![TestJSON::Null, TestJSON::Array(vec![])]),
+                        )])])]),
+                    ),
+                    ("n".to_string(), TestJSON::Number("-1.5e3".to_string())),
+                ]),
+                r#"{"a \"b\"":"c\n🌳","deep":[[{"x":[null,[]]}]],"n":-1.5e3}"#,
+            ),
+        ] {
+            let node_map = build_vec_node_map(tree);
+            let text = node_map.to_text(&JSONFormat::Minified);
+            assert_eq!(text, *expected);
+            assert_eq!(
+                node_map.root_node().size(&node_map, &JSONFormat::Minified),
+                Size::from(*expected)
+            );
+            // Minified text can be read back in to give the same tree
+            let parsed: VecNodeMap<JSON<Index>> = JSON::parse_into(&text).unwrap();
+            assert_eq!(
+                parsed.to_text(&JSONFormat::Pretty),
+                node_map.to_text(&JSONFormat::Pretty)
+            );
+        }
+    }
+
+    #[test]
+    fn cycle_format_styles() {
+        let node_map = build_vec_node_map(&TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::Object(vec![("k".to_string(), TestJSON::False)]),
+        ]));
+        let pretty = node_map.to_text(&JSONFormat::Pretty);
+        let next = JSON::<Index>::next_format_style(&JSONFormat::Pretty).unwrap();
+        assert_eq!(node_map.to_text(&next), r#"[true, {"k": false}]"#);
+        assert_ne!(node_map.to_text(&next), pretty);
+        let next = JSON::<Index>::next_format_style(&next).unwrap();
+        assert_eq!(node_map.to_text(&next), r#"[true,{"k":false}]"#);
+        // Cycling three times gets back to the original style
+        let next = JSON::<Index>::next_format_style(&next).unwrap();
+        assert_eq!(node_map.to_text(&next), pretty);
+    }
+
+    #[test]
+    fn kind_name() {
+        let node_map = build_vec_node_map(&TestJSON::Object(vec![(
+            "key".to_string(),
+            TestJSON::Array(vec![TestJSON::True, TestJSON::False]),
+        )]));
+        let object = node_map.root_node();
+        assert_eq!(object.kind_name(), "object");
+        let field = node_map.get_node(object.children()[0]).unwrap();
+        assert_eq!(field.kind_name(), "field");
+        let [key, value] = [field.children()[0], field.children()[1]];
+        assert_eq!(node_map.get_node(key).unwrap().kind_name(), "string");
+        let array = node_map.get_node(value).unwrap();
+        assert_eq!(array.kind_name(), "array");
+        for c in array.children() {
+            assert_eq!(node_map.get_node(*c).unwrap().kind_name(), "bool");
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        for (escaped, expected) in &[
+            (r#"plain"#, Some("plain")),
+            (r#"\u0041\/\"\\"#, Some(r#"A/"\"#)),
+            (r#"tab\there\n"#, Some("tab\there\n")),
+            (r#"\ud83c\udf33"#, Some("\u{1f333}")),
+            (r#"\u00e9"#, Some("\u{e9}")),
+            // Invalid escapes
+            (r#"\x"#, None),
+            (r#"trailing\"#, None),
+            (r#"\u00g1"#, None),
+            (r#"\ud83c"#, None),
+            (r#"\ud83c\u0041"#, None),
+        ] {
+            assert_eq!(unescape_string(escaped).as_deref(), *expected);
+        }
+        assert_eq!(escape_string("A/\"\\é🌳\n\u{1}"), r#"A/\"\\é🌳\n\u0001"#);
+    }
+
+    #[test]
+    fn normalized() {
+        let over_escaped = JSON::<Index>::Str(r#"\u0041\/b\u000a\""#.to_string());
+        assert_eq!(
+            over_escaped.normalized(),
+            Some(JSON::Str(r#"A/b\n\""#.to_string()))
+        );
+        // Strings with invalid escapes and non-strings have no normal form
+        assert_eq!(JSON::<Index>::Str(r#"\q"#.to_string()).normalized(), None);
+        assert_eq!(JSON::<Index>::True.normalized(), None);
+    }
+
+    #[test]
+    fn numbers() {
+        for text in &["0", "-0", "12", "0.5", "1e10", "1E+2", "-3.25e-7"] {
+            assert!(is_valid_number(text), "{}", text);
+        }
+        for text in &[
+            "", "-", "01", "+1", ".5", "1.", "1e", "1e+", "NaN", "inf", "1 ",
+        ] {
+            assert!(!is_valid_number(text), "{}", text);
+        }
+        assert_eq!(
+            JSON::<Index>::from_f64(0.1),
+            Some(JSON::Number("0.1".to_string()))
+        );
+        assert_eq!(
+            JSON::<Index>::from_f64(1e10),
+            Some(JSON::Number("10000000000".to_string()))
+        );
+        assert_eq!(JSON::<Index>::from_f64(f64::NAN), None);
+        assert_eq!(JSON::<Index>::from_f64(f64::NEG_INFINITY), None);
+        // Numbers are compared by their text, so they're written back exactly as they were read
+        assert_ne!(
+            JSON::<Index>::Number("1.0".to_string()),
+            JSON::Number("1".to_string())
+        );
+        assert_eq!(
+            JSON::<Index>::True.from_char('n'),
+            Some(JSON::Number("0".to_string()))
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let string = JSON::<Index>::Str("value".to_string());
+        assert_eq!(string.literal(), Some("value"));
+        assert_eq!(
+            string.with_literal(r#"new \n"#),
+            Some(JSON::Str(r#"new \n"#.to_string()))
+        );
+        assert_eq!(JSON::<Index>::True.literal(), None);
+        assert_eq!(JSON::<Index>::True.with_literal("x"), None);
+
+        for (text, validity) in &[
+            ("", LiteralValidity::Valid),
+            (r#"a \" b"#, LiteralValidity::Valid),
+            (r#"\ud83d\ude00"#, LiteralValidity::Valid),
+            (r#"a\"#, LiteralValidity::Incomplete),
+            (r#"\u00"#, LiteralValidity::Incomplete),
+            (r#"\ud83d\u"#, LiteralValidity::Incomplete),
+            (r#"a " b"#, LiteralValidity::Invalid),
+            ("tab\t", LiteralValidity::Invalid),
+            (r#"\q"#, LiteralValidity::Invalid),
+            (r#"\u00g"#, LiteralValidity::Invalid),
+        ] {
+            assert_eq!(string.check_literal(text), *validity, "{}", text);
+        }
+        assert_eq!(
+            JSON::<Index>::False.check_literal(""),
+            LiteralValidity::Invalid
+        );
+
+        let number = JSON::<Index>::Number("0".to_string());
+        assert_eq!(number.literal(), Some("0"));
+        assert_eq!(
+            number.with_literal("2.5e3"),
+            Some(JSON::Number("2.5e3".to_string()))
+        );
+        assert_eq!(number.with_literal("2.5e"), None);
+        for (text, validity) in &[
+            ("-12", LiteralValidity::Valid),
+            ("1.5E-3", LiteralValidity::Valid),
+            ("", LiteralValidity::Incomplete),
+            ("-", LiteralValidity::Incomplete),
+            ("1.", LiteralValidity::Incomplete),
+            ("1e+", LiteralValidity::Incomplete),
+            ("01", LiteralValidity::Invalid),
+            ("1x", LiteralValidity::Invalid),
+            ("NaN", LiteralValidity::Invalid),
+        ] {
+            assert_eq!(number.check_literal(text), *validity, "{}", text);
+        }
+    }
 }
@@ -0,0 +1,332 @@
+//! A parser which reads JSON text directly into a [`NodeMap`], without building an intermediate
+//! tree
+
+use super::json::{is_valid_number, unescape_string, JSON};
+use super::lexer::{Lexer, ParseError};
+use crate::node_map::{NodeMapMut, Reference};
+
+// Import used only for doc comments
+#[allow(unused_imports)]
+use crate::node_map::NodeMap;
+
+/// An array or object whose closing bracket hasn't been reached yet
+enum Container<Ref> {
+    /// The values read so far
+    Array(Vec<Ref>),
+    /// The fields read so far, and the key of the field whose value is being read
+    Object(Vec<Ref>, Ref),
+}
+
+/// The state of a partially complete parse
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser {
+            lexer: Lexer::new(text),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        // JSON doesn't allow any other whitespace
+        self.lexer
+            .take_while(|c| matches!(c, ' ' | '\t' | '\n' | '\r'));
+    }
+
+    /// Consume the [`char`]s of `word` (e.g. `true`), returning an error if the text doesn't match
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        for expected in word.chars() {
+            if self.lexer.peek() != Some(expected) {
+                return Err(self.lexer.error(format!("expected '{}'", word)));
+            }
+            self.lexer.advance();
+        }
+        Ok(())
+    }
+
+    /// Parse any JSON value, adding it (and its descendants) to `node_map`.  The arrays and
+    /// objects that haven't been closed yet are kept on a stack rather than by recursing, so
+    /// deeply nested documents can't overflow the call stack.
+    fn parse_value<Ref: Reference, M: NodeMapMut<Ref, JSON<Ref>>>(
+        &mut self,
+        node_map: &mut M,
+    ) -> Result<Ref, ParseError> {
+        let mut open: Vec<Container<Ref>> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let node = match self.lexer.peek() {
+                Some('t') => {
+                    self.expect_word("true")?;
+                    JSON::True
+                }
+                Some('f') => {
+                    self.expect_word("false")?;
+                    JSON::False
+                }
+                Some('n') => {
+                    self.expect_word("null")?;
+                    JSON::Null
+                }
+                Some('"') => JSON::Str(self.parse_string()?),
+                Some('-') | Some('0'..='9') => JSON::Number(self.parse_number()?),
+                Some('[') => {
+                    self.lexer.advance();
+                    self.skip_whitespace();
+                    if self.lexer.peek() == Some(']') {
+                        self.lexer.advance();
+                        JSON::Array(vec![])
+                    } else {
+                        open.push(Container::Array(vec![]));
+                        continue;
+                    }
+                }
+                Some('{') => {
+                    self.lexer.advance();
+                    self.skip_whitespace();
+                    if self.lexer.peek() == Some('}') {
+                        self.lexer.advance();
+                        JSON::Object(vec![])
+                    } else {
+                        let key = self.parse_key(node_map)?;
+                        open.push(Container::Object(vec![], key));
+                        continue;
+                    }
+                }
+                Some(c) => return Err(self.lexer.error(format!("unexpected '{}'", c))),
+                None => return Err(self.lexer.error("unexpected end of input")),
+            };
+            let mut value = node_map.add_node(node);
+            // Add the value to the innermost open container, closing every container that ends
+            // straight after it
+            loop {
+                let closed = match open.last_mut() {
+                    None => return Ok(value),
+                    Some(Container::Array(children)) => {
+                        children.push(value);
+                        self.skip_whitespace();
+                        match self.lexer.peek() {
+                            Some(',') => {
+                                self.lexer.advance();
+                                break;
+                            }
+                            Some(']') => {}
+                            _ => return Err(self.lexer.error("expected ',' or ']'")),
+                        }
+                        JSON::Array(std::mem::take(children))
+                    }
+                    Some(Container::Object(fields, key)) => {
+                        fields.push(node_map.add_node(JSON::Field([*key, value])));
+                        self.skip_whitespace();
+                        match self.lexer.peek() {
+                            Some(',') => {
+                                self.lexer.advance();
+                                *key = self.parse_key(node_map)?;
+                                break;
+                            }
+                            Some('}') => {}
+                            _ => return Err(self.lexer.error("expected ',' or '}'")),
+                        }
+                        JSON::Object(std::mem::take(fields))
+                    }
+                };
+                self.lexer.advance();
+                open.pop();
+                value = node_map.add_node(closed);
+            }
+        }
+    }
+
+    /// Parse the key of an object's field and the `:` after it, adding the key to `node_map`
+    fn parse_key<Ref: Reference, M: NodeMapMut<Ref, JSON<Ref>>>(
+        &mut self,
+        node_map: &mut M,
+    ) -> Result<Ref, ParseError> {
+        self.skip_whitespace();
+        if self.lexer.peek() != Some('"') {
+            return Err(self.lexer.error("expected a string key"));
+        }
+        let key = JSON::Str(self.parse_string()?);
+        let key = node_map.add_node(key);
+        self.skip_whitespace();
+        if self.lexer.peek() != Some(':') {
+            return Err(self.lexer.error("expected ':'"));
+        }
+        self.lexer.advance();
+        Ok(key)
+    }
+
+    /// Parse a number, returning its text exactly as it appears
+    fn parse_number(&mut self) -> Result<String, ParseError> {
+        let start = self.lexer.error("invalid number");
+        // Read every char that could be part of a number, then check them all at once
+        let text = self
+            .lexer
+            .take_while(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+        if is_valid_number(text) {
+            Ok(text.to_string())
+        } else {
+            Err(start)
+        }
+    }
+
+    /// Parse a string, returning its contents exactly as they appear between the quotes
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        let start = self.lexer.error("invalid escape sequence in string");
+        self.lexer.advance();
+        let mut contents = String::new();
+        loop {
+            match self.lexer.advance() {
+                Some('"') => break,
+                Some('\\') => {
+                    // Push the escaped char without looking at it, so that `\"` doesn't end the
+                    // string.  The escape itself is checked once the whole string is read.
+                    contents.push('\\');
+                    match self.lexer.advance() {
+                        Some(c) => contents.push(c),
+                        None => return Err(self.lexer.error("unterminated string")),
+                    }
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.lexer.error("control character in string"));
+                }
+                Some(c) => contents.push(c),
+                None => return Err(self.lexer.error("unterminated string")),
+            }
+        }
+        match unescape_string(&contents) {
+            Some(_) => Ok(contents),
+            None => Err(start),
+        }
+    }
+}
+
+/// Returns roughly how many nodes parsing `text` will make, without parsing it.  Every value
+/// after the first follows a `,` or `:`, every field has a `:` (and a key), and a non-empty
+/// array or object has its first value after a `[` or `{`.  This is exact for documents whose
+/// strings don't contain any of these characters, and otherwise an overestimate.
+fn estimate_node_count(text: &str) -> usize {
+    1 + text
+        .bytes()
+        .map(|b| match b {
+            b'[' | b'{' | b',' => 1,
+            b':' => 2,
+            _ => 0,
+        })
+        .sum::<usize>()
+}
+
+impl<Ref: Reference> JSON<Ref> {
+    /// Parse `text` as a JSON document, building a new [`NodeMap`] with the parsed value as its
+    /// root.  Strings are stored exactly as they appear in `text`, so escape sequences are
+    /// preserved.
+    pub fn parse_into<M: NodeMapMut<Ref, JSON<Ref>>>(text: &str) -> Result<M, ParseError> {
+        let mut parser = Parser::new(text);
+        let mut node_map = M::with_capacity(estimate_node_count(text) + 1);
+        let root = parser.parse_value(&mut node_map)?;
+        parser.skip_whitespace();
+        if parser.lexer.peek().is_some() {
+            return Err(parser
+                .lexer
+                .error("unexpected text after the end of the document"));
+        }
+        node_map.set_root(root).unwrap();
+        Ok(node_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_node_count, ParseError};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::node_map::NodeMapMut;
+
+    for_each_node_map! {
+        fn parse(text: &str) -> Result<Map<JSON<Ref>>, ParseError> {
+            JSON::parse_into(text)
+        }
+
+        #[test]
+        fn round_trip() {
+            for text in &[
+                "true",
+                "false",
+                "null",
+                "[]",
+                "{}",
+                r#""a \"quoted\" é string""#,
+                r#"[true, false, null, [[]], {"value": true}]"#,
+                r#"{"a": {"b": [false, "c"]}, "d": {}}"#,
+                "[0, -1, 0.25, 1e10, -2.5E-3, 1E+2]",
+                r#"{"id": 12, "tags": [], "ratio": 0.1, "owner": null}"#,
+                r#"["\"", "line\nbreak\\", "🌳 🌳", "\u0001"]"#,
+            ] {
+                assert_eq!(parse(text).unwrap().to_text(&JSONFormat::Compact), *text);
+            }
+            // Strings keep their escapes in the pretty style too
+            let text = "{\n    \"q\\\"\": \"🌳\\n\"\n}";
+            assert_eq!(parse(text).unwrap().to_text(&JSONFormat::Pretty), text);
+            // Whitespace shouldn't matter
+            let tree = parse(" [\n  true ,\r\n\t{ \"k\" :false } ] \n").unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, {"k": false}]"#
+            );
+        }
+
+        #[test]
+        fn errors() {
+            for (text, line, column) in &[
+                ("", 1, 1),
+                ("{", 1, 2),
+                ("[true,]", 1, 7),
+                ("[true false]", 1, 7),
+                ("\"unterminated", 1, 14),
+                ("[\n  tru]", 2, 6),
+                ("nul", 1, 4),
+                ("[1,]", 1, 4),
+                ("[01]", 1, 2),
+                ("[1.]", 1, 2),
+                ("-", 1, 1),
+                ("[1 2]", 1, 4),
+                ("{true: false}", 1, 2),
+                (r#"{"a" true}"#, 1, 6),
+                (r#""bad \q escape""#, 1, 1),
+                ("true false", 1, 6),
+            ] {
+                let error = parse(text).unwrap_err();
+                assert_eq!((error.line, error.column), (*line, *column), "{}", text);
+            }
+        }
+
+        #[test]
+        fn deep_documents() {
+            // Deep enough to overflow the stack if the parser recursed
+            let depth = 100_000;
+            let text = format!("{}true{}", "[".repeat(depth), "]".repeat(depth));
+            assert_eq!(parse(&text).unwrap().to_text(&JSONFormat::Minified), text);
+            let text = format!("{}{}", r#"{"k":"#.repeat(depth), "}".repeat(depth));
+            assert!(parse(&text).is_err());
+            let text = format!("{}null{}", r#"{"k":"#.repeat(depth), "}".repeat(depth));
+            assert_eq!(parse(&text).unwrap().to_text(&JSONFormat::Minified), text);
+        }
+    }
+
+    #[test]
+    fn estimating_node_counts() {
+        use crate::node_map::vec::{Index, VecNodeMap};
+        use crate::node_map::NodeMap;
+        for (text, estimate) in &[
+            ("true", 1),
+            ("[1, 2, 3]", 4),
+            (r#"{"a": [null], "b": {}}"#, 9),
+            (r#"["a, b: c"]"#, 5),
+        ] {
+            assert_eq!(estimate_node_count(text), *estimate, "{}", text);
+            let node_map: VecNodeMap<JSON<Index>> = JSON::parse_into(text).unwrap();
+            // Never an underestimate (the placeholder root isn't part of the document)
+            assert!(node_map.iter_all().count() <= estimate + 1, "{}", text);
+        }
+    }
+}
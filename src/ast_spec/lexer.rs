@@ -0,0 +1,121 @@
+//! The parts of parsing that every language's parser shares: consuming text one [`char`] at a
+//! time, and keeping track of the line and column so that errors can say where they happened
+
+use std::error::Error;
+use std::fmt;
+
+/// The error generated when some text isn't valid in the language being parsed (e.g. JSON)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+    /// The line where the error was found, starting from 1
+    pub line: usize,
+    /// The column (in [`char`]s) where the error was found, starting from 1
+    pub column: usize,
+    /// A description of what went wrong
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// The text that a parser hasn't consumed yet, along with where it starts
+pub struct Lexer<'a> {
+    /// The text that hasn't been consumed yet
+    rest: &'a str,
+    /// The line of the next [`char`] to be consumed
+    line: usize,
+    /// The column of the next [`char`] to be consumed
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Lexer {
+            rest: text,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Generate a [`ParseError`] at the position of the next unconsumed [`char`]
+    pub fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    /// Returns `true` if the unconsumed text starts with `text`
+    pub fn starts_with(&self, text: &str) -> bool {
+        self.rest.starts_with(text)
+    }
+
+    /// Consume the next [`char`], keeping track of the current position
+    pub fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Consume `text` if the unconsumed text starts with it, returning `true` if it did
+    pub fn eat(&mut self, text: &str) -> bool {
+        if !self.starts_with(text) {
+            return false;
+        }
+        for _ in text.chars() {
+            self.advance();
+        }
+        true
+    }
+
+    /// Consume chars for as long as `is_part` accepts them, returning the text consumed
+    pub fn take_while(&mut self, is_part: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest;
+        while self.peek().is_some_and(&is_part) {
+            self.advance();
+        }
+        &rest[..rest.len() - self.rest.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lexer;
+
+    #[test]
+    fn positions() {
+        let mut lexer = Lexer::new("ab\n🌳 c");
+        assert_eq!(lexer.take_while(|c| c != '\n'), "ab");
+        assert_eq!((lexer.line, lexer.column), (1, 3));
+        assert!(!lexer.eat("\n🌳c"));
+        assert!(lexer.eat("\n🌳"));
+        // Columns count chars, not bytes
+        let error = lexer.error("here");
+        assert_eq!((error.line, error.column), (2, 2));
+        assert_eq!(lexer.peek(), Some(' '));
+        assert_eq!(lexer.take_while(char::is_whitespace), " ");
+        assert_eq!(lexer.advance(), Some('c'));
+        assert_eq!(lexer.advance(), None);
+        assert_eq!(lexer.take_while(|_| true), "");
+    }
+}
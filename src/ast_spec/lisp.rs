@@ -0,0 +1,462 @@
+//! An [`ASTSpec`] for S-expressions, as used by Lisp-like languages
+
+use super::size::Size;
+use super::{ASTSpec, DisplayToken, LiteralValidity, Reference, SyntaxCategory};
+use crate::node_map::NodeMap;
+
+/// The different ways that an S-expression can be formatted
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum LispFormat {
+    /// Everything on one line, with the items of every list separated by spaces.
+    /// E.g. `(define (square x) (* x x))`
+    Compact,
+    /// The first item of every non-empty list stays next to the opening bracket, and every other
+    /// item goes on its own indented line.  E.g.
+    /// ```text
+    /// (define
+    ///     (square
+    ///         x)
+    ///     (*
+    ///         x
+    ///         x))
+    /// ```
+    #[default]
+    Indented,
+}
+
+const CHAR_SYMBOL: char = 'a';
+const CHAR_NUMBER: char = 'n';
+const CHAR_STRING: char = 's';
+const CHAR_LIST: char = 'l';
+
+/// The sapling representation of an S-expression
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Lisp<Ref: Reference> {
+    /// A symbol, such as `define` or `+`.  This can't be empty, or contain whitespace, brackets
+    /// or quotes.
+    Symbol(String),
+    /// An integer or decimal number, such as `-12` or `0.5`, stored as its text
+    Number(String),
+    /// A string.  The contents are stored exactly as they appear between the quotes, i.e.
+    /// including any escape sequences.
+    Str(String),
+    /// A list of other S-expressions, written `(<v1> <v2> ...)`
+    List(Vec<Ref>),
+}
+
+/// Returns `true` if `c` can be part of the name of a [`Symbol`](Lisp::Symbol)
+fn is_symbol_char(c: char) -> bool {
+    !c.is_whitespace() && !"()\"';".contains(c)
+}
+
+/// Returns `true` if `text` can be the name of a [`Symbol`](Lisp::Symbol).  Symbols made only
+/// of digits aren't allowed, since they would be read back as numbers.
+fn is_valid_symbol(text: &str) -> bool {
+    text.chars().all(is_symbol_char) && !text.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Returns `true` if `text` is an integer or decimal number, with an optional minus sign
+fn is_valid_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (whole, fraction) = match digits.find('.') {
+        Some(i) => (&digits[..i], Some(&digits[i + 1..])),
+        None => (digits, None),
+    };
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    is_digits(whole) && fraction.is_none_or(is_digits)
+}
+
+impl<Ref: Reference> Lisp<Ref> {
+    /// Return an iterator over all the possible chars that could represent Lisp nodes
+    fn all_chars() -> Box<dyn Iterator<Item = char>> {
+        Box::new(
+            [CHAR_SYMBOL, CHAR_NUMBER, CHAR_STRING, CHAR_LIST]
+                .iter()
+                .copied(),
+        )
+    }
+}
+
+impl<Ref: Reference> Default for Lisp<Ref> {
+    fn default() -> Lisp<Ref> {
+        Lisp::List(vec![])
+    }
+}
+
+impl<Ref: Reference> ASTSpec<Ref> for Lisp<Ref> {
+    type FormatStyle = LispFormat;
+
+    /* FORMATTING FUNCTIONS */
+
+    fn display_tokens(&self, format_style: &Self::FormatStyle) -> Vec<DisplayToken<Ref>> {
+        let children = match self {
+            Lisp::Symbol(text) | Lisp::Number(text) => {
+                return vec![DisplayToken::Text(text.clone())]
+            }
+            Lisp::Str(content) => return vec![DisplayToken::Text(format!(r#""{}""#, content))],
+            Lisp::List(children) => children,
+        };
+        let (first, rest) = match children.split_first() {
+            Some(split) => split,
+            None => return vec![DisplayToken::Text("()".to_string())],
+        };
+        let mut tokens = vec![
+            DisplayToken::Text("(".to_string()),
+            DisplayToken::Child(*first),
+        ];
+        let is_indented = format_style == &LispFormat::Indented;
+        if is_indented && !rest.is_empty() {
+            tokens.push(DisplayToken::Indent);
+        }
+        for c in rest {
+            tokens.push(if is_indented {
+                DisplayToken::Newline
+            } else {
+                DisplayToken::Whitespace(1)
+            });
+            tokens.push(DisplayToken::Child(*c));
+        }
+        if is_indented && !rest.is_empty() {
+            tokens.push(DisplayToken::Dedent);
+        }
+        tokens.push(DisplayToken::Text(")".to_string()));
+        tokens
+    }
+
+    /// The size of an indented list doesn't include the indentation of its last line, since that
+    /// depends on where the list is in the tree
+    fn size(&self, node_map: &impl NodeMap<Ref, Self>, format_style: &Self::FormatStyle) -> Size {
+        match self {
+            Lisp::Symbol(text) | Lisp::Number(text) => Size::from(text.as_str()),
+            Lisp::Str(content) => Size::new(0, 1) + Size::from(content.as_str()) + Size::new(0, 1),
+            Lisp::List(children) => {
+                // The size of the space or newline between two items
+                let separator = match format_style {
+                    LispFormat::Compact => Size::new(0, 1),
+                    LispFormat::Indented => Size::new(1, 0),
+                };
+                // Start with the size of "("
+                let mut size = Size::new(0, 1);
+                for (i, c) in children.iter().enumerate() {
+                    if i > 0 {
+                        size += separator;
+                    }
+                    size += node_map.get_node(*c).unwrap().size(node_map, format_style);
+                }
+                // Add one more char for the ")"
+                size + Size::new(0, 1)
+            }
+        }
+    }
+
+    fn next_format_style(style: &LispFormat) -> Option<LispFormat> {
+        Some(match style {
+            LispFormat::Compact => LispFormat::Indented,
+            LispFormat::Indented => LispFormat::Compact,
+        })
+    }
+
+    /* DEBUG VIEW FUNCTIONS */
+
+    fn children(&self) -> &[Ref] {
+        match self {
+            Lisp::Symbol(_) | Lisp::Number(_) | Lisp::Str(_) => &[],
+            Lisp::List(children) => children,
+        }
+    }
+
+    fn children_mut(&mut self) -> &mut [Ref] {
+        match self {
+            Lisp::Symbol(_) | Lisp::Number(_) | Lisp::Str(_) => &mut [],
+            Lisp::List(children) => children,
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        match self {
+            Lisp::Symbol(text) | Lisp::Number(text) | Lisp::Str(text) => text.capacity(),
+            Lisp::List(children) => children.capacity() * std::mem::size_of::<Ref>(),
+        }
+    }
+
+    fn display_name(&self) -> String {
+        match self {
+            Lisp::Symbol(text) | Lisp::Number(text) => text.clone(),
+            Lisp::Str(content) => format!(r#""{}""#, content),
+            Lisp::List(_) => "list".to_string(),
+        }
+    }
+
+    /// Lists are summarised by their first element (e.g. the function being called), which is
+    /// usually what identifies them
+    fn fold_summary(&self, node_map: &impl NodeMap<Ref, Self>, marker: &str) -> String {
+        match self {
+            Lisp::List(children) => match children.first().and_then(|&c| node_map.get_node(c)) {
+                Some(Lisp::Symbol(name)) => format!("({} {})", name, marker),
+                _ => format!("({})", marker),
+            },
+            _ => format!("{} {}", self.display_name(), marker),
+        }
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match self {
+            Lisp::Symbol(_) => "symbol",
+            Lisp::Number(_) => "number",
+            Lisp::Str(_) => "string",
+            Lisp::List(_) => "list",
+        }
+    }
+
+    /* AST EDITING FUNCTIONS */
+
+    fn replace_chars(&self) -> Box<dyn Iterator<Item = char>> {
+        Self::all_chars()
+    }
+
+    fn char_descriptions() -> Vec<(char, &'static str)> {
+        vec![
+            (CHAR_SYMBOL, "symbol"),
+            (CHAR_NUMBER, "zero"),
+            (CHAR_STRING, "empty string"),
+            (CHAR_LIST, "empty list"),
+        ]
+    }
+
+    fn from_char(&self, c: char) -> Option<Self> {
+        match c {
+            CHAR_SYMBOL => Some(Lisp::Symbol("nil".to_string())),
+            CHAR_NUMBER => Some(Lisp::Number("0".to_string())),
+            CHAR_STRING => Some(Lisp::Str(String::new())),
+            CHAR_LIST => Some(Lisp::List(vec![])),
+            _ => None,
+        }
+    }
+
+    fn insert_chars(&self) -> Box<dyn Iterator<Item = char>> {
+        match self {
+            Lisp::Symbol(_) | Lisp::Number(_) | Lisp::Str(_) => Box::new(std::iter::empty()),
+            Lisp::List(_) => Self::all_chars(),
+        }
+    }
+
+    fn insert_child(&mut self, child: Ref, index: usize) -> bool {
+        match self {
+            Lisp::Symbol(_) | Lisp::Number(_) | Lisp::Str(_) => false,
+            Lisp::List(children) => {
+                children.insert(index.min(children.len()), child);
+                true
+            }
+        }
+    }
+
+    fn remove_child(&mut self, index: usize) -> bool {
+        match self {
+            Lisp::List(children) if index < children.len() => {
+                children.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn syntax_category(&self) -> SyntaxCategory {
+        match self {
+            Lisp::Symbol(_) => SyntaxCategory::Other,
+            Lisp::Number(_) => SyntaxCategory::Literal,
+            Lisp::Str(_) => SyntaxCategory::String,
+            Lisp::List(_) => SyntaxCategory::Punctuation,
+        }
+    }
+
+    fn literal(&self) -> Option<&str> {
+        match self {
+            Lisp::Symbol(text) | Lisp::Number(text) | Lisp::Str(text) => Some(text),
+            Lisp::List(_) => None,
+        }
+    }
+
+    fn with_literal(&self, text: &str) -> Option<Self> {
+        let text = text.to_string();
+        match self {
+            Lisp::Symbol(_) => Some(Lisp::Symbol(text)),
+            Lisp::Number(_) => Some(Lisp::Number(text)),
+            Lisp::Str(_) => Some(Lisp::Str(text)),
+            Lisp::List(_) => None,
+        }
+    }
+
+    fn check_literal(&self, text: &str) -> LiteralValidity {
+        match self {
+            Lisp::Symbol(_) if is_valid_symbol(text) => LiteralValidity::Valid,
+            // Empty or all-digit symbols can still be finished by typing more symbol chars
+            Lisp::Symbol(_) if text.chars().all(is_symbol_char) => LiteralValidity::Incomplete,
+            Lisp::Number(_) if is_valid_number(text) => LiteralValidity::Valid,
+            // Unfinished numbers like `-` or `1.` can be finished with a digit
+            Lisp::Number(_) if is_valid_number(&format!("{}0", text)) => {
+                LiteralValidity::Incomplete
+            }
+            Lisp::Str(_) => {
+                // Quotes must be escaped, and the string can't end half way through an escape
+                let mut chars = text.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' if chars.next().is_none() => return LiteralValidity::Incomplete,
+                        '"' => return LiteralValidity::Invalid,
+                        _ => {}
+                    }
+                }
+                LiteralValidity::Valid
+            }
+            _ => LiteralValidity::Invalid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_number, is_valid_symbol, Lisp, LispFormat};
+    use crate::ast_spec::size::Size;
+    use crate::ast_spec::test_lisp::TestLisp;
+    use crate::ast_spec::{ASTSpec, LiteralValidity};
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    /// Non-generic version of [`TestLisp::build_node_map`] that always returns a [`VecNodeMap`].
+    fn build_vec_node_map(tree: &TestLisp) -> VecNodeMap<Lisp<Index>> {
+        tree.build_node_map::<Index, VecNodeMap<Lisp<Index>>>()
+    }
+
+    fn sym(name: &str) -> TestLisp {
+        TestLisp::Symbol(name.to_string())
+    }
+
+    #[test]
+    fn to_text() {
+        for (tree, expected_compact_string, expected_indented_string) in &[
+            (sym("nil"), "nil", "nil"),
+            (TestLisp::Number("-1.5".to_string()), "-1.5", "-1.5"),
+            (
+                TestLisp::Str(r#"a \"b\""#.to_string()),
+                r#""a \"b\"""#,
+                r#""a \"b\"""#,
+            ),
+            (TestLisp::List(vec![]), "()", "()"),
+            (TestLisp::List(vec![sym("f")]), "(f)", "(f)"),
+            (
+                TestLisp::List(vec![
+                    sym("define"),
+                    TestLisp::List(vec![sym("square"), sym("x")]),
+                    TestLisp::List(vec![sym("*"), sym("x"), sym("x")]),
+                ]),
+                "(define (square x) (* x x))",
+                "(define
+    (square
+        x)
+    (*
+        x
+        x))",
+            ),
+            (
+                TestLisp::List(vec![
+                    TestLisp::List(vec![]),
+                    TestLisp::Str("s".to_string()),
+                    TestLisp::Number("2".to_string()),
+                ]),
+                r#"(() "s" 2)"#,
+                r#"(()
+    "s"
+    2)"#,
+            ),
+        ] {
+            println!("Testing {}", expected_compact_string);
+
+            let node_map = build_vec_node_map(tree);
+            let compact_string = node_map.to_text(&LispFormat::Compact);
+            assert_eq!(compact_string, *expected_compact_string);
+            assert_eq!(
+                node_map.root_node().size(&node_map, &LispFormat::Compact),
+                Size::from(*expected_compact_string)
+            );
+            let indented_string = node_map.to_text(&LispFormat::Indented);
+            assert_eq!(indented_string, *expected_indented_string);
+            // The size doesn't include the indentation of the last line
+            let lines = expected_indented_string.lines().count() - 1;
+            let last_line = expected_indented_string
+                .lines()
+                .last()
+                .unwrap()
+                .trim_start();
+            assert_eq!(
+                node_map.root_node().size(&node_map, &LispFormat::Indented),
+                Size::new(lines, 0) + Size::from(last_line)
+            );
+        }
+    }
+
+    #[test]
+    fn validity() {
+        for text in &["nil", "+", "list->vector", "x1", "1x"] {
+            assert!(is_valid_symbol(text), "{}", text);
+        }
+        for text in &["", "12", "a b", "(", "f)", "'x", r#"""#, "a;b"] {
+            assert!(!is_valid_symbol(text), "{}", text);
+        }
+        for text in &["0", "-12", "0.5", "10.25"] {
+            assert!(is_valid_number(text), "{}", text);
+        }
+        for text in &["", "-", "1.", ".5", "1e3", "--1", "1.2.3"] {
+            assert!(!is_valid_number(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn literals() {
+        let symbol = Lisp::<Index>::Symbol("nil".to_string());
+        let number = Lisp::<Index>::Number("0".to_string());
+        let string = Lisp::<Index>::Str(String::new());
+        for (node, text, expected) in &[
+            (&symbol, "car", LiteralValidity::Valid),
+            (&symbol, "", LiteralValidity::Incomplete),
+            (&symbol, "42", LiteralValidity::Incomplete),
+            (&symbol, "a b", LiteralValidity::Invalid),
+            (&number, "-3", LiteralValidity::Valid),
+            (&number, "-", LiteralValidity::Incomplete),
+            (&number, "3.", LiteralValidity::Incomplete),
+            (&number, "3x", LiteralValidity::Invalid),
+            (&string, "", LiteralValidity::Valid),
+            (&string, r#"say \"hi\""#, LiteralValidity::Valid),
+            (&string, r"ends with \", LiteralValidity::Incomplete),
+            (&string, r#"un"escaped"#, LiteralValidity::Invalid),
+        ] {
+            assert_eq!(node.check_literal(text), *expected, "{:?}", text);
+        }
+        assert_eq!(symbol.literal(), Some("nil"));
+        assert_eq!(
+            number.with_literal("7"),
+            Some(Lisp::Number("7".to_string()))
+        );
+        assert_eq!(Lisp::<Index>::List(vec![]).literal(), None);
+        assert_eq!(Lisp::<Index>::List(vec![]).with_literal("x"), None);
+    }
+
+    #[test]
+    fn lists() {
+        let mut node_map = build_vec_node_map(&TestLisp::List(vec![sym("a"), sym("b")]));
+        let children = node_map.root_node().children().to_vec();
+        let mut list = node_map.root_node().clone();
+        assert!(list.insert_child(children[0], 5));
+        assert_eq!(list.children(), &[children[0], children[1], children[0]]);
+        assert!(list.remove_child(0));
+        assert!(!list.remove_child(2));
+        assert_eq!(list.children(), &[children[1], children[0]]);
+        // Atoms can't have children
+        let mut atom = node_map.get_node(children[0]).unwrap().clone();
+        assert!(!atom.insert_child(children[1], 0));
+        assert!(!atom.remove_child(0));
+        assert_eq!(atom.insert_chars().count(), 0);
+        assert_eq!(list.insert_chars().count(), 4);
+        *node_map.root_node_mut() = list;
+        assert_eq!(node_map.to_text(&LispFormat::Compact), "(b a)");
+    }
+}
@@ -1,29 +1,435 @@
 //! A module to contain Rust representations of ASTs in a format that sapling can work with.
 
 pub mod display_token;
+pub mod expr;
+pub mod expr_parser;
 pub mod json;
+pub mod json_parser;
+pub mod lexer;
+pub mod lisp;
 pub mod size;
+pub mod test_expr;
 pub mod test_json;
+pub mod test_lisp;
+pub mod test_xml;
+pub mod xml;
 
-use crate::node_map::{NodeMap, Reference};
-use display_token::{write_tokens, DisplayToken};
+/// Why some text couldn't be parsed into a tree, with the line and column of the problem
+pub use lexer::ParseError;
+
+use crate::node_map::{NodeMap, NodeMapMut, Reference};
+use display_token::{write_tokens_indented, DisplayToken, IndentStyle};
 use size::Size;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 // Import used only for doc comments
 #[allow(unused_imports)]
 use crate::editable_tree::EditableTree;
 
+/// Generate a readable description of the path from the root of `node_map` to the node reached
+/// by following the child indices in `path` (e.g. `root[2].value`), built from the
+/// [`child_path_segment`](ASTSpec::child_path_segment)s of the nodes along the way.  Returns
+/// [`None`] if there is no node at `path`.
+pub fn display_path<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    path: &[usize],
+) -> Option<String> {
+    let mut description = String::from("root");
+    let mut node = node_map.root_node();
+    for index in path {
+        let child = *node.children().get(*index)?;
+        description.push_str(&node.child_path_segment(*index, node_map));
+        node = node_map.get_node(child)?;
+    }
+    Some(description)
+}
+
+/// Returns the part of a [path](display_path) that leads to a child with this
+/// [name](ASTSpec::child_name), or to the `index`th child if it has no name.  Names that look
+/// like identifiers are written after a `.` (e.g. `.value`), and any others are quoted in
+/// brackets (e.g. `["two words"]`).
+pub fn name_path_segment(name: Option<&str>, index: usize) -> String {
+    match name {
+        Some(name)
+            if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            format!(".{}", name)
+        }
+        Some(name) => format!(r#"["{}"]"#, name),
+        None => format!("[{}]", index),
+    }
+}
+
+/// Find the node described by a [path](display_path) (e.g. `root.settings.enabled` or
+/// `root[2]["two words"]`), returning its child indices from the root of `node_map`.  Each part
+/// of the path is matched against the [`child_path_segment`](ASTSpec::child_path_segment)s of
+/// the children of the node reached so far, so this accepts every path that [`display_path`]
+/// generates.  Children with an empty segment (e.g. the value of a JSON field, which is named by
+/// the field) are passed through to reach the rest of the path.  Returns [`None`] if no node
+/// matches the path.
+pub fn resolve_path<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    text: &str,
+) -> Option<Vec<usize>> {
+    let mut rest = text.trim().strip_prefix("root")?;
+    let mut path = Vec::new();
+    let mut node = node_map.root_node();
+    while !rest.is_empty() {
+        let segments: Vec<String> = (0..node.children().len())
+            .map(|i| node.child_path_segment(i, node_map))
+            .collect();
+        // A segment only matches if the next one starts straight after it, so `.val` doesn't
+        // match the start of `.value`
+        let matches = |segment: &str| {
+            !segment.is_empty()
+                && rest.starts_with(segment)
+                && (rest.len() == segment.len()
+                    || rest[segment.len()..].starts_with(['.', '[', '(']))
+        };
+        let (index, len) = match segments.iter().position(|s| matches(s)) {
+            Some(index) => (index, segments[index].len()),
+            None => (segments.iter().position(String::is_empty)?, 0),
+        };
+        rest = &rest[len..];
+        path.push(index);
+        node = node_map.get_node(node.children()[index])?;
+    }
+    Some(path)
+}
+
+/// The ways in which the tree stored in a [`NodeMap`] can be structurally broken
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum InvalidTreeError<Ref: Reference> {
+    /// A node refers to a child that isn't in the [`NodeMap`].  `parent` is [`None`] if the
+    /// missing node is the root.
+    MissingNode { parent: Option<Ref>, id: Ref },
+    /// The node at this reference is one of its own descendants
+    Cycle(Ref),
+    /// The node at `id` has children that its language doesn't allow, as explained by `reason`
+    /// (from [`ASTSpec::check_valid`])
+    InvalidNode { id: Ref, reason: String },
+}
+
+impl<Ref: Reference> fmt::Display for InvalidTreeError<Ref> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidTreeError::MissingNode { parent: None, id } => {
+                write!(f, "the root ({:?}) doesn't exist", id)
+            }
+            InvalidTreeError::MissingNode {
+                parent: Some(parent),
+                id,
+            } => write!(f, "{:?} has a child ({:?}) that doesn't exist", parent, id),
+            InvalidTreeError::Cycle(id) => write!(f, "{:?} is its own descendant", id),
+            InvalidTreeError::InvalidNode { id, reason } => {
+                write!(f, "{:?} is invalid: {}", id, reason)
+            }
+        }
+    }
+}
+
+impl<Ref: Reference> Error for InvalidTreeError<Ref> {}
+
+/// Check that the tree in `node_map` is structurally sound: every reference reachable from the
+/// root must point to a node, no node can be its own descendant, and every node must pass
+/// [`ASTSpec::check_valid`].  Nodes that are shared between several parents are allowed.
+pub fn check_tree<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+) -> Result<(), InvalidTreeError<Ref>> {
+    /// Whether a node's descendants are still being checked (i.e. it's an ancestor of the nodes
+    /// being checked), or have all been checked
+    #[derive(PartialEq)]
+    enum State {
+        InProgress,
+        Done,
+    }
+
+    let root = node_map.root();
+    if node_map.get_node(root).is_none() {
+        return Err(InvalidTreeError::MissingNode {
+            parent: None,
+            id: root,
+        });
+    }
+    let mut states: HashMap<Ref, State> = HashMap::new();
+    // A depth-first traversal, where each entry is a node and the index of its next unchecked
+    // child.  This is iterative, so that very deep trees can't overflow the stack.
+    let mut stack: Vec<(Ref, usize)> = vec![(root, 0)];
+    states.insert(root, State::InProgress);
+    while let Some((id, child_index)) = stack.last_mut() {
+        // Every node on the stack is known to exist
+        let node = node_map.get_node(*id).unwrap();
+        if *child_index == 0 {
+            node.check_valid(node_map)
+                .map_err(|reason| InvalidTreeError::InvalidNode { id: *id, reason })?;
+        }
+        let child = match node.children().get(*child_index) {
+            Some(child) => *child,
+            None => {
+                states.insert(*id, State::Done);
+                stack.pop();
+                continue;
+            }
+        };
+        *child_index += 1;
+        let parent = *id;
+        match states.get(&child) {
+            Some(State::InProgress) => return Err(InvalidTreeError::Cycle(child)),
+            Some(State::Done) => {}
+            None if node_map.get_node(child).is_none() => {
+                return Err(InvalidTreeError::MissingNode {
+                    parent: Some(parent),
+                    id: child,
+                })
+            }
+            None => {
+                states.insert(child, State::InProgress);
+                stack.push((child, 0));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if the subtree of `map_a` rooted at `root_a` is the same tree as the subtree of
+/// `map_b` rooted at `root_b`, i.e. every pair of corresponding nodes have equal contents and the
+/// same number of children.  The references themselves are never compared, so the subtrees can
+/// live at different places in different maps.  Subtrees containing invalid references or cycles
+/// are never equal to anything.
+pub fn subtrees_equal<Ref: Reference, Node: ASTSpec<Ref>>(
+    map_a: &impl NodeMap<Ref, Node>,
+    root_a: Ref,
+    map_b: &impl NodeMap<Ref, Node>,
+    root_b: Ref,
+) -> bool {
+    /// Returns `true` if two nodes are equal, ignoring the references to their children
+    fn contents_equal<Ref: Reference, Node: ASTSpec<Ref>>(a: &Node, b: &Node) -> bool {
+        if a.children().len() != b.children().len() {
+            return false;
+        }
+        let mut b = b.clone();
+        b.children_mut().copy_from_slice(a.children());
+        *a == b
+    }
+
+    let contents_match = |a: Ref, b: Ref| match (map_a.get_node(a), map_b.get_node(b)) {
+        (Some(node_a), Some(node_b)) => contents_equal(node_a, node_b),
+        _ => false,
+    };
+    if !contents_match(root_a, root_b) {
+        return false;
+    }
+    // Pairs of subtrees which are known to be equal, so that shared nodes are only compared once
+    let mut equal_pairs: HashSet<(Ref, Ref)> = HashSet::new();
+    // The nodes currently on the stack, which would form a cycle if they were visited again
+    let mut ancestors_a: HashSet<Ref> = HashSet::new();
+    let mut ancestors_b: HashSet<Ref> = HashSet::new();
+    // A depth-first traversal of both trees in lockstep, where each entry is a pair of nodes and
+    // the index of their next unchecked children
+    let mut stack: Vec<(Ref, Ref, usize)> = vec![(root_a, root_b, 0)];
+    ancestors_a.insert(root_a);
+    ancestors_b.insert(root_b);
+    while let Some((a, b, child_index)) = stack.last_mut() {
+        // Every pair on the stack is known to exist and have the same number of children
+        let children_a = map_a.get_node(*a).unwrap().children();
+        let children_b = map_b.get_node(*b).unwrap().children();
+        let (child_a, child_b) = match (children_a.get(*child_index), children_b.get(*child_index))
+        {
+            (Some(child_a), Some(child_b)) => (*child_a, *child_b),
+            _ => {
+                ancestors_a.remove(a);
+                ancestors_b.remove(b);
+                equal_pairs.insert((*a, *b));
+                stack.pop();
+                continue;
+            }
+        };
+        *child_index += 1;
+        if equal_pairs.contains(&(child_a, child_b)) {
+            continue;
+        }
+        if ancestors_a.contains(&child_a)
+            || ancestors_b.contains(&child_b)
+            || !contents_match(child_a, child_b)
+        {
+            return false;
+        }
+        ancestors_a.insert(child_a);
+        ancestors_b.insert(child_b);
+        stack.push((child_a, child_b, 0));
+    }
+    true
+}
+
+/// Returns a hash of the subtree of `node_map` rooted at `root`, or [`None`] if the subtree
+/// contains invalid references or cycles.  Subtrees which are [equal](subtrees_equal) always have
+/// the same hash, wherever they are stored, so this can be used to find candidates for sharing
+/// nodes.  The hash only depends on the nodes' [kinds](ASTSpec::kind_name), [display
+/// names](ASTSpec::display_name) and [literals](ASTSpec::literal), so it's stable between runs.
+pub fn subtree_hash<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    root: Ref,
+) -> Option<u64> {
+    node_map.get_node(root)?;
+    // The hashes of every finished subtree, so that shared nodes are only hashed once
+    let mut hashes: HashMap<Ref, u64> = HashMap::new();
+    let mut ancestors: HashSet<Ref> = HashSet::new();
+    // A post-order traversal, where each entry is a node and the index of its next unhashed child
+    let mut stack: Vec<(Ref, usize)> = vec![(root, 0)];
+    ancestors.insert(root);
+    while let Some((id, child_index)) = stack.last_mut() {
+        let id = *id;
+        // Every node on the stack is known to exist
+        let node = node_map.get_node(id).unwrap();
+        if let Some(&child) = node.children().get(*child_index) {
+            *child_index += 1;
+            if hashes.contains_key(&child) {
+                continue;
+            }
+            if ancestors.contains(&child) || node_map.get_node(child).is_none() {
+                return None;
+            }
+            ancestors.insert(child);
+            stack.push((child, 0));
+            continue;
+        }
+        // All the children have been hashed, so this node can be hashed
+        let mut hasher = DefaultHasher::new();
+        node.kind_name().hash(&mut hasher);
+        node.display_name().hash(&mut hasher);
+        node.literal().hash(&mut hasher);
+        node.children().len().hash(&mut hasher);
+        for child in node.children() {
+            hashes[child].hash(&mut hasher);
+        }
+        hashes.insert(id, hasher.finish());
+        ancestors.remove(&id);
+        stack.pop();
+    }
+    hashes.get(&root).copied()
+}
+
+/// The kinds of text that are displayed in different colours
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SyntaxCategory {
+    /// Words with a fixed meaning in the language (e.g. `if`)
+    Keyword,
+    /// Literal values other than strings (e.g. `true` or `42`)
+    Literal,
+    /// String literals, including the keys of JSON objects
+    String,
+    /// Brackets, commas and other symbols that give the text its structure
+    Punctuation,
+    /// Text that isn't in any other category.  Each node of this category is given its own
+    /// colour, so that neighbouring nodes can be told apart.
+    Other,
+}
+
+/// Whether some text is a valid [literal value](ASTSpec::literal) for a node
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LiteralValidity {
+    /// The text is a complete, valid literal
+    Valid,
+    /// The text isn't valid yet, but could be made valid by typing more characters (e.g. a string
+    /// ending with half of an escape sequence)
+    Incomplete,
+    /// The text isn't valid, and can't be made valid by typing more characters
+    Invalid,
+}
+
+/// The maximum number of chars of a node's display name that its [summary](ASTSpec::summary)
+/// includes
+pub const SUMMARY_PREVIEW_LENGTH: usize = 20;
+
 /// The specification of an AST that sapling can edit
 pub trait ASTSpec<Ref: Reference>: std::fmt::Debug + Clone + Eq + Default {
-    /// A type parameter that will represent the different ways this AST can be rendered
-    type FormatStyle;
+    /// A type parameter that will represent the different ways this AST can be rendered.  The
+    /// default style is used by editors that aren't given one.
+    type FormatStyle: Default;
 
     /* FORMATTING FUNCTIONS */
 
+    /// Returns the format style that comes after `style` when the user cycles through the ways
+    /// of displaying this AST, or [`None`] if there is only one style.  Repeatedly cycling must
+    /// eventually return to `style`.  The default implementation returns [`None`].
+    fn next_format_style(_style: &Self::FormatStyle) -> Option<Self::FormatStyle> {
+        None
+    }
+
     /// Returns an iterator of all the items that need to be rendered to the screen to make up this
     /// node, along with their on-screen locations.
     fn display_tokens(&self, format_style: &Self::FormatStyle) -> Vec<DisplayToken<Ref>>;
 
+    /// Like [`display_tokens`](ASTSpec::display_tokens), but can also look at the nodes in
+    /// `node_map`, for languages where the way a node is written depends on its children (e.g.
+    /// whether an operand needs brackets).  This is what is used to write and display trees.  The
+    /// default implementation ignores `node_map` and returns `display_tokens`.
+    fn display_tokens_in(
+        &self,
+        _node_map: &impl NodeMap<Ref, Self>,
+        format_style: &Self::FormatStyle,
+    ) -> Vec<DisplayToken<Ref>> {
+        self.display_tokens(format_style)
+    }
+
+    /// Generates the [`DisplayToken`]s for a container node whose contents are `children`,
+    /// delimited by commas and surrounded by `open` and `close` (e.g. `"["` and `"]"`).  If
+    /// `is_pretty` is `true`, every child is put on its own indented line, otherwise the children
+    /// are put on one line separated by spaces.  Empty containers are rendered as `open` directly
+    /// followed by `close`.
+    ///
+    /// This is intended to be used by implementations of
+    /// [`display_tokens`](ASTSpec::display_tokens), so that only leaf nodes need custom rendering.
+    fn container_tokens(
+        open: &str,
+        children: &[Ref],
+        close: &str,
+        is_pretty: bool,
+    ) -> Vec<DisplayToken<Ref>> {
+        // Special case: if this container is empty, render it as e.g. '[]'
+        if children.is_empty() {
+            return vec![DisplayToken::Text(format!("{}{}", open, close))];
+        }
+
+        let mut tokens = Vec::with_capacity(6 + 3 * children.len());
+        // Push some initial tokens
+        tokens.push(DisplayToken::Text(open.to_string()));
+        if is_pretty {
+            tokens.push(DisplayToken::Newline);
+            tokens.push(DisplayToken::Indent);
+        }
+        // Push the children, delimited by commas
+        let mut is_first_child = true;
+        for c in children {
+            // Push the delimiting
+            if !is_first_child {
+                tokens.push(DisplayToken::Text(",".to_string()));
+                if is_pretty {
+                    tokens.push(DisplayToken::Newline);
+                } else {
+                    tokens.push(DisplayToken::Whitespace(1));
+                }
+            }
+            is_first_child = false;
+            // Push the single child
+            tokens.push(DisplayToken::Child(*c));
+        }
+        // Push the closing bracket
+        if is_pretty {
+            tokens.push(DisplayToken::Newline);
+            tokens.push(DisplayToken::Dedent);
+        }
+        tokens.push(DisplayToken::Text(close.to_string()));
+        // Return the token stream
+        tokens
+    }
+
     /// Determine the space on the screen occupied by this node in an AST
     fn size(&self, node_map: &impl NodeMap<Ref, Self>, format_style: &Self::FormatStyle) -> Size;
 
@@ -34,7 +440,28 @@ pub trait ASTSpec<Ref: Reference>: std::fmt::Debug + Clone + Eq + Default {
         string: &mut String,
         format_style: &Self::FormatStyle,
     ) {
-        write_tokens(root, node_map, string, format_style);
+        Self::write_text_indented(
+            root,
+            node_map,
+            string,
+            format_style,
+            &IndentStyle::default(),
+        );
+    }
+
+    /// Write the textual representation of this AST to a string, using `indent_style` for the
+    /// whitespace at the start of each line.  By default, this flattens the
+    /// [`display_tokens_in`](ASTSpec::display_tokens_in) of every node (see
+    /// [`write_tokens_indented`]).  Languages can override this to write straight into `string`
+    /// without allocating anything per node, as long as the text is exactly the same.
+    fn write_text_indented(
+        root: Ref,
+        node_map: &impl NodeMap<Ref, Self>,
+        string: &mut String,
+        format_style: &Self::FormatStyle,
+        indent_style: &IndentStyle,
+    ) {
+        write_tokens_indented(root, node_map, string, format_style, indent_style);
     }
 
     /// Make a [`String`] representing this AST.
@@ -52,7 +479,14 @@ pub trait ASTSpec<Ref: Reference>: std::fmt::Debug + Clone + Eq + Default {
     /* DEBUG VIEW FUNCTIONS */
 
     /// Get a slice over the direct children of this node.  This operation is expected to be
-    /// cheap - it will be used a lot of times without caching the results.
+    /// cheap - it will be used a lot of times without caching the results - so it borrows the
+    /// children rather than collecting them, and traversing, rendering or validating a tree never
+    /// allocates per node just to find its children.
+    ///
+    /// Nodes whose children aren't naturally a single sequence must flatten them into one
+    /// contiguous slice.  For example, the entries of a JSON object are stored as
+    /// [`Field`](json::JSON::Field) nodes with children `[key, value]`, so an object's children
+    /// are its fields and the keys and values are one level further down.
     fn children(&self) -> &[Ref];
 
     /// Get a mutable slice over the direct children of this node.  Like
@@ -60,9 +494,65 @@ pub trait ASTSpec<Ref: Reference>: std::fmt::Debug + Clone + Eq + Default {
     /// cheap - it will be used a lot of times without caching the results.
     fn children_mut(&mut self) -> &mut [Ref];
 
+    /// Returns the approximate number of bytes that this node owns on the heap, not counting the
+    /// node itself or its children, which is used to report how much memory a tree uses.  By
+    /// default, this assumes that the children are stored in a [`Vec`] of their own, so nodes
+    /// that store their children inline or own other data (e.g. the contents of a string) should
+    /// override it.
+    fn heap_size(&self) -> usize {
+        std::mem::size_of_val(self.children())
+    }
+
     /// Get the display name of this node
     fn display_name(&self) -> String;
 
+    /// Returns the one-line summary that is displayed in place of this node when it's folded,
+    /// which should contain `marker` to show that the node's contents are hidden.  By default,
+    /// this is the node's [display name](ASTSpec::display_name) followed by `marker`.
+    fn fold_summary(&self, _node_map: &impl NodeMap<Ref, Self>, marker: &str) -> String {
+        format!("{} {}", self.display_name(), marker)
+    }
+
+    /// If this node is a record of key-value entries (e.g. a JSON object), returns the text of the
+    /// key and value of each entry, in order.  Leaf values are given as their raw contents (e.g.
+    /// without quotes around strings), and any other values are given as their text on a single
+    /// line.  Returns [`None`] if this node isn't a record, which is the case unless this is
+    /// overridden.
+    fn record_entries(&self, _node_map: &impl NodeMap<Ref, Self>) -> Option<Vec<(String, String)>> {
+        None
+    }
+
+    /// If this node is an entry of a record (e.g. a JSON object field), returns the index of the
+    /// child that holds its key, so that the key can be renamed.  Returns [`None`] unless this is
+    /// overridden.
+    fn key_index(&self) -> Option<usize> {
+        None
+    }
+
+    /// Get a short name for the kind of this node (e.g. `"array"` or `"bool"`).  Unlike
+    /// [`display_name`](ASTSpec::display_name), this never includes the node's contents, so all
+    /// nodes of the same kind share the same name.
+    fn kind_name(&self) -> &'static str;
+
+    /// Get a short human-readable name for this node, for use in the status bar and in messages
+    /// (e.g. `object`, `true` or `string "foo"`).  This never describes the node's children.
+    /// Nodes with a [literal](ASTSpec::literal) value are named by their
+    /// [kind](ASTSpec::kind_name) followed by their [display name](ASTSpec::display_name), which
+    /// is cut short if it's longer than [`SUMMARY_PREVIEW_LENGTH`] chars.
+    fn summary(&self) -> String {
+        let name = self.display_name();
+        if self.literal().is_none() {
+            return name;
+        }
+        let preview = if name.chars().count() > SUMMARY_PREVIEW_LENGTH {
+            let start: String = name.chars().take(SUMMARY_PREVIEW_LENGTH - 1).collect();
+            format!("{}…", start)
+        } else {
+            name
+        };
+        format!("{} {}", self.kind_name(), preview)
+    }
+
     fn write_tree_view_recursive(
         &self,
         node_map: &impl NodeMap<Ref, Self>,
@@ -119,8 +609,25 @@ pub trait ASTSpec<Ref: Reference>: std::fmt::Debug + Clone + Eq + Default {
     /// Generate a new node from a [`char`] that a user typed as part of the `r` command.  If `c` is
     /// an element of [`get_replace_chars`](ASTSpec::replace_chars), this must return [`Some`] node,
     /// if it isn't, then this should return [`None`].
+    #[allow(clippy::wrong_self_convention)]
     fn from_char(&self, c: char) -> Option<Self>;
 
+    /// Like [`from_char`](ASTSpec::from_char), but also adds placeholder nodes to `node_map` for
+    /// the children that every node of this kind must have (e.g. the key and value of a JSON
+    /// object field), so that the new node is [valid](ASTSpec::check_valid) as soon as it's in
+    /// the tree.  The placeholders should be easy to spot, so that the user knows to edit them.
+    /// This is used for the nodes created by the replace and insert commands.  The default
+    /// implementation adds no children, so only needs to be overridden by languages with nodes
+    /// that can't be empty.
+    #[allow(clippy::wrong_self_convention)]
+    fn from_char_with_children(
+        &self,
+        c: char,
+        _node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Self> {
+        self.from_char(c)
+    }
+
     /// Generate an iterator over the possible shorthand [`char`]s that a user could type to insert
     /// other nodes into this one
     fn insert_chars(&self) -> Box<dyn Iterator<Item = char>>;
@@ -129,4 +636,347 @@ pub trait ASTSpec<Ref: Reference>: std::fmt::Debug + Clone + Eq + Default {
     fn is_insert_char(&self, c: char) -> bool {
         self.insert_chars().any(|x| x == c)
     }
+
+    /// Returns every shorthand [`char`] that this language uses in the replace and insert
+    /// commands, along with a description of the node it creates, for the help screen
+    fn char_descriptions() -> Vec<(char, &'static str)> {
+        Vec::new()
+    }
+
+    /// Add the nodes for a new child of this node, generated from a [`char`] that a user typed as
+    /// part of an insert command, to `node_map`.  Returns the reference to the new child, or
+    /// [`None`] if `c` isn't one of [`insert_chars`](ASTSpec::insert_chars).  The default
+    /// implementation adds the node given by
+    /// [`from_char_with_children`](ASTSpec::from_char_with_children), along with its placeholder
+    /// children.
+    fn child_from_char(&self, c: char, node_map: &mut impl NodeMapMut<Ref, Self>) -> Option<Ref> {
+        if !self.is_insert_char(c) {
+            return None;
+        }
+        self.from_char_with_children(c, node_map)
+            .map(|node| node_map.add_node(node))
+    }
+
+    /// Like [`child_from_char`](ASTSpec::child_from_char), but for a child that is being added
+    /// next to one of this node's existing children (by the `o` and `O` commands).  The default
+    /// implementation is the same as `child_from_char`, but records whose entries need keys can
+    /// give the new child a placeholder key, so that a value can be typed in either case.
+    fn sibling_from_char(&self, c: char, node_map: &mut impl NodeMapMut<Ref, Self>) -> Option<Ref> {
+        self.child_from_char(c, node_map)
+    }
+
+    /// Add the nodes for a new node of the kind given by `c` to `node_map`, so that this node
+    /// (which is stored at `this`) is its only descendant that isn't new.  Returns the reference
+    /// to the new node, or [`None`] if this node can't be wrapped in a node of that kind.  The
+    /// default implementation makes this node the only child of the node given by
+    /// [`from_char`](ASTSpec::from_char), which must be able to
+    /// [have children inserted](ASTSpec::insert_child).
+    fn wrap_from_char(
+        &self,
+        c: char,
+        this: Ref,
+        node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Ref> {
+        let mut wrapper = self.from_char(c)?;
+        if !wrapper.insert_child(this, 0) {
+            return None;
+        }
+        Some(node_map.add_node(wrapper))
+    }
+
+    /// Returns a copy of this node (which is about to replace `old`) that takes over the children
+    /// of `old`, adding any extra nodes needed to hold them to `node_map`.  Returns [`None`] if
+    /// this node can't hold those children, in which case they are dropped along with `old`.  The
+    /// default implementation only keeps the children if both nodes have the same
+    /// [kind](ASTSpec::kind_name) and this node has no children of its own, and
+    /// [inserts](ASTSpec::insert_child) them unchanged.
+    fn with_children_of(
+        &self,
+        old: &Self,
+        _node_map: &mut impl NodeMapMut<Ref, Self>,
+    ) -> Option<Self> {
+        if self.kind_name() != old.kind_name() || !self.children().is_empty() {
+            return None;
+        }
+        let mut new_node = self.clone();
+        for (i, child) in old.children().iter().enumerate() {
+            if !new_node.insert_child(*child, i) {
+                return None;
+            }
+        }
+        Some(new_node)
+    }
+
+    /// Returns why the `index`th child of this node can't be replaced by `new_child` (e.g. because
+    /// the key of a JSON object field must be a string), or `Ok(())` if it can.  This is checked
+    /// before nodes are replaced or wrapped, so that edits can't make the tree
+    /// [invalid](ASTSpec::check_valid).  The default implementation allows every replacement.
+    fn check_replacement(&self, _index: usize, _new_child: &Self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Returns the descendant that would replace this node if it were unwrapped (the inverse of
+    /// [`wrap_from_char`](ASTSpec::wrap_from_char)), or [`None`] if this node doesn't wrap a
+    /// single node.  The default implementation returns this node's child if it has exactly one.
+    fn wrapped_child(&self, _node_map: &impl NodeMap<Ref, Self>) -> Option<Ref> {
+        match self.children() {
+            [child] => Some(*child),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of this node's `index`th child, if it has one (e.g. the key of a field in
+    /// a JSON object).  Names describe the child in [paths](display_path), and are found by
+    /// searches.  The default implementation doesn't name any children, so children are only
+    /// known by their index.
+    fn child_name<'a>(
+        &'a self,
+        _index: usize,
+        _node_map: &'a impl NodeMap<Ref, Self>,
+    ) -> Option<Cow<'a, str>> {
+        None
+    }
+
+    /// Returns the part of a [path](display_path) that leads from this node to its `index`th
+    /// child (e.g. `.key` or `[3]`).  The default implementation uses the child's
+    /// [name](ASTSpec::child_name) if it has one (see [`name_path_segment`]), or its index in
+    /// brackets.
+    fn child_path_segment(&self, index: usize, node_map: &impl NodeMap<Ref, Self>) -> String {
+        name_path_segment(self.child_name(index, node_map).as_deref(), index)
+    }
+
+    /// Add `child` to this node's children so that it becomes the `index`th child (or the last
+    /// child if `index` is larger than the current number of children).  Returns `false` and
+    /// leaves the node unchanged if this node can't have a variable number of children.
+    fn insert_child(&mut self, child: Ref, index: usize) -> bool;
+
+    /// Remove the `index`th child of this node, returning `false` and leaving the node unchanged if
+    /// there is no such child or this node can't have a variable number of children.
+    fn remove_child(&mut self, index: usize) -> bool;
+
+    /// Swap the `i`th and `j`th children of this node, returning `false` and leaving the node
+    /// unchanged if either child doesn't exist or this node's children can't be reordered.  The
+    /// default implementation only reorders the children of nodes that allow children to be
+    /// [removed](ASTSpec::remove_child) and [inserted](ASTSpec::insert_child), so nodes with
+    /// fixed children (like JSON object fields) keep them in order.
+    fn swap_children(&mut self, i: usize, j: usize) -> bool {
+        let (first, second) = (i.min(j), i.max(j));
+        let (first_ref, second_ref) =
+            match (self.children().get(first), self.children().get(second)) {
+                (Some(a), Some(b)) => (*a, *b),
+                _ => return false,
+            };
+        if first == second {
+            return true;
+        }
+        // Remove the later child first so that the earlier child's index doesn't change
+        if !self.remove_child(second) {
+            return false;
+        }
+        self.remove_child(first);
+        self.insert_child(second_ref, first);
+        self.insert_child(first_ref, second);
+        true
+    }
+
+    /// Rearrange the children of this node so that the `i`th child is the one that was at index
+    /// `order[i]`.  Returns `false` and leaves the node unchanged if `order` isn't a permutation of
+    /// the child indices, or this node's children can't be [swapped](ASTSpec::swap_children).
+    fn reorder_children(&mut self, order: &[usize]) -> bool {
+        let len = self.children().len();
+        let mut is_used = vec![false; len];
+        if order.len() != len
+            || !order
+                .iter()
+                .all(|&i| i < len && !std::mem::replace(&mut is_used[i], true))
+        {
+            return false;
+        }
+        // Fill each position in turn, swapping in its child from wherever it has ended up
+        let original = self.clone();
+        let mut child_at: Vec<usize> = (0..len).collect();
+        let mut position_of: Vec<usize> = (0..len).collect();
+        for (i, &child) in order.iter().enumerate() {
+            let j = position_of[child];
+            if i == j {
+                continue;
+            }
+            if !self.swap_children(i, j) {
+                *self = original;
+                return false;
+            }
+            let displaced = child_at[i];
+            child_at.swap(i, j);
+            position_of[displaced] = j;
+            position_of[child] = i;
+        }
+        true
+    }
+
+    /// Generate a single node that combines this node with `other`, with `separator` between their
+    /// contents (e.g. merging the strings `"a"` and `"b"` with separator `", "` gives `"a, b"`).
+    /// Returns [`None`] if the two nodes can't be merged, which is the case unless this is
+    /// overridden.
+    fn merge_with(&self, _other: &Self, _separator: &str) -> Option<Self> {
+        None
+    }
+
+    /// Returns a copy of this node rewritten into its canonical form (e.g. with string escapes
+    /// minimised), or [`None`] if this node has no canonical form.  The canonical form must
+    /// represent the same value and have the same children.  The default implementation returns
+    /// [`None`].
+    fn normalized(&self) -> Option<Self> {
+        None
+    }
+
+    /// Returns the opposite of this node if it is a boolean-like value (e.g. `false` for a JSON
+    /// `true`), or [`None`] otherwise.  The default implementation returns [`None`].
+    fn inverted(&self) -> Option<Self> {
+        None
+    }
+
+    /// Returns the [`SyntaxCategory`] of the text generated by this node's
+    /// [`display_tokens`](ASTSpec::display_tokens), which decides the colour it is displayed in.
+    /// The default implementation returns [`SyntaxCategory::Other`].
+    fn syntax_category(&self) -> SyntaxCategory {
+        SyntaxCategory::Other
+    }
+
+    /// Check that this node's children are of the kinds that this language allows, returning a
+    /// description of the problem if they aren't.  Children that aren't in `node_map` should be
+    /// skipped, since they are reported by [`check_tree`].  The default implementation accepts
+    /// any children.
+    fn check_valid(&self, _node_map: &impl NodeMap<Ref, Self>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Returns the text of this node's value if it is a literal that the user can type freely
+    /// (e.g. the contents of a string), or [`None`] otherwise.  The default implementation returns
+    /// [`None`].
+    fn literal(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns a copy of this node with its [literal](ASTSpec::literal) replaced by `text`, or
+    /// [`None`] if this node has no literal.  `text` doesn't have to be
+    /// [valid](ASTSpec::check_literal), so that partly typed values can be displayed.
+    fn with_literal(&self, _text: &str) -> Option<Self> {
+        None
+    }
+
+    /// Returns whether `text` would be a valid [literal](ASTSpec::literal) for this node.  The
+    /// default implementation treats everything as [`LiteralValidity::Invalid`].
+    fn check_literal(&self, _text: &str) -> LiteralValidity {
+        LiteralValidity::Invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::display_token::DisplayToken;
+    use super::size::Size;
+    use super::ASTSpec;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut, Reference};
+
+    /// A minimal AST of nested lists, which only provides custom rendering for its leaves and
+    /// relies on [`ASTSpec::container_tokens`] for everything else.  `FormatStyle` is `true` for
+    /// pretty printing.
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    enum List<Ref> {
+        Atom(String),
+        List(Vec<Ref>),
+    }
+
+    impl<Ref> Default for List<Ref> {
+        fn default() -> Self {
+            List::List(vec![])
+        }
+    }
+
+    impl<Ref: Reference> ASTSpec<Ref> for List<Ref> {
+        type FormatStyle = bool;
+
+        fn display_tokens(&self, is_pretty: &bool) -> Vec<DisplayToken<Ref>> {
+            match self {
+                List::Atom(name) => vec![DisplayToken::Text(name.clone())],
+                List::List(children) => Self::container_tokens("(", children, ")", *is_pretty),
+            }
+        }
+
+        fn size(&self, _node_map: &impl NodeMap<Ref, Self>, _is_pretty: &bool) -> Size {
+            unimplemented!();
+        }
+
+        fn children(&self) -> &[Ref] {
+            match self {
+                List::Atom(_) => &[],
+                List::List(children) => children,
+            }
+        }
+
+        fn children_mut(&mut self) -> &mut [Ref] {
+            match self {
+                List::Atom(_) => &mut [],
+                List::List(children) => children,
+            }
+        }
+
+        fn display_name(&self) -> String {
+            match self {
+                List::Atom(name) => name.clone(),
+                List::List(_) => "list".to_string(),
+            }
+        }
+
+        fn kind_name(&self) -> &'static str {
+            match self {
+                List::Atom(_) => "atom",
+                List::List(_) => "list",
+            }
+        }
+
+        fn replace_chars(&self) -> Box<dyn Iterator<Item = char>> {
+            Box::new(std::iter::empty())
+        }
+
+        fn from_char(&self, _c: char) -> Option<Self> {
+            None
+        }
+
+        fn insert_chars(&self) -> Box<dyn Iterator<Item = char>> {
+            Box::new(std::iter::empty())
+        }
+
+        fn insert_child(&mut self, _child: Ref, _index: usize) -> bool {
+            false
+        }
+
+        fn remove_child(&mut self, _index: usize) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn default_container_rendering() {
+        let mut node_map: VecNodeMap<List<Index>> = VecNodeMap::with_default_root();
+        let empty_list = node_map.add_node(List::List(vec![]));
+        let foo = node_map.add_node(List::Atom("foo".to_string()));
+        let bar = node_map.add_node(List::Atom("bar".to_string()));
+        let inner_list = node_map.add_node(List::List(vec![foo, bar]));
+        node_map.add_as_root(List::List(vec![inner_list, empty_list, foo]));
+
+        assert_eq!(node_map.to_text(&false), "((foo, bar), (), foo)");
+        assert_eq!(
+            node_map.to_text(&true),
+            "(
+    (
+        foo,
+        bar
+    ),
+    (),
+    foo
+)"
+        );
+    }
 }
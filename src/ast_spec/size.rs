@@ -113,6 +113,8 @@ mod tests {
     }
 
     #[test]
+    // We deliberately test `Add` separately from `AddAssign`
+    #[allow(clippy::assign_op_pattern)]
     fn add() {
         let tests: &[&[&str]] = &[
             &["[", "]"],
@@ -0,0 +1,59 @@
+use super::expr::{BinaryOp, Expr, UnaryOp};
+use crate::node_map::{NodeMapMut, Reference, TreeBuilder};
+
+// Import used only for doc comments
+#[allow(unused_imports)]
+use crate::node_map::NodeMap;
+
+/// A copy of [`Expr`] that does not rely on a [`NodeMap`] for recursive types
+pub enum TestExpr {
+    Int(String),
+    Ident(String),
+    Unary(UnaryOp, Box<TestExpr>),
+    Binary(BinaryOp, Box<TestExpr>, Box<TestExpr>),
+}
+
+impl TestExpr {
+    /// Returns the number of [`Expr`] nodes that this tree becomes when it's added to a
+    /// [`NodeMap`]
+    pub fn node_count(&self) -> usize {
+        match self {
+            TestExpr::Int(_) | TestExpr::Ident(_) => 1,
+            TestExpr::Unary(_, operand) => 1 + operand.node_count(),
+            TestExpr::Binary(_, left, right) => 1 + left.node_count() + right.node_count(),
+        }
+    }
+
+    /// Build a [`NodeMap`] which contains this tree, with the corresponding [`Expr`] node as its
+    /// root
+    pub fn build_node_map<Ref: Reference, M: NodeMapMut<Ref, Expr<Ref>>>(&self) -> M {
+        // The extra node is the default root that the map starts with
+        let mut node_map = M::with_capacity(self.node_count() + 1);
+        let root = node_map.add_tree(self);
+        node_map.set_root(root).unwrap();
+        node_map
+    }
+}
+
+impl<Ref: Reference> TreeBuilder<Ref, Expr<Ref>> for &TestExpr {
+    fn node_count(&self) -> usize {
+        TestExpr::node_count(self)
+    }
+
+    fn children(&self) -> Vec<Self> {
+        match self {
+            TestExpr::Int(_) | TestExpr::Ident(_) => vec![],
+            TestExpr::Unary(_, operand) => vec![operand],
+            TestExpr::Binary(_, left, right) => vec![left, right],
+        }
+    }
+
+    fn build_node(&self, children: Vec<Ref>) -> Expr<Ref> {
+        match self {
+            TestExpr::Int(text) => Expr::Int(text.clone()),
+            TestExpr::Ident(name) => Expr::Ident(name.clone()),
+            TestExpr::Unary(op, _) => Expr::Unary(*op, [children[0]]),
+            TestExpr::Binary(op, _, _) => Expr::Binary(*op, [children[0], children[1]]),
+        }
+    }
+}
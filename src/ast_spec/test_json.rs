@@ -1,33 +1,359 @@
 use super::json::JSON;
-use crate::node_map::{NodeMapMut, Reference};
+use crate::node_map::{NodeMap, NodeMapMut, Reference, TreeBuilder};
 
-// Imports used solely by doc comments.  rustc/clippy can't see that they're used, so we surpress
-// the warning because we know it's OK.
-#[allow(unused_imports)]
-use crate::node_map::vec::VecNodeMap;
-#[allow(unused_imports)]
-use crate::node_map::NodeMap;
+use crate::ast_spec::ASTSpec;
+use crate::node_map::vec::{Index, VecNodeMap};
+
+/// A small deterministic random number generator (SplitMix64), so that randomly generated trees
+/// can be reproduced from their seed without any external crates
+#[derive(Debug, Clone)]
+pub struct TestRng(u64);
+
+impl TestRng {
+    /// Creates a `TestRng` which will always generate the same numbers from the same `seed`
+    pub fn new(seed: u64) -> Self {
+        TestRng(seed)
+    }
+
+    /// Returns the next random [`u64`]
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a random number in `0..n`, which must not be empty
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Returns a random item of `items`, which must not be empty
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Pieces of the contents of JSON strings, which include escapes and wide chars that are awkward
+/// to handle.  All of them are valid on their own and when joined together.
+const STRING_PIECES: &[&str] = &[
+    "a",
+    "key",
+    "two words",
+    r#"\""#,
+    r"\\",
+    r"\n",
+    r"\/",
+    r"\u00e9",
+    r"\ud83c\udf32",
+    "好",
+    "🌲",
+    "{",
+    "]",
+    ",",
+    ":",
+];
+
+/// Valid JSON numbers, including some with awkward formatting
+const NUMBERS: &[&str] = &[
+    "0",
+    "-0",
+    "7",
+    "-12",
+    "0.5",
+    "1e10",
+    "1E-7",
+    "-3.25e+2",
+    "123456789012345678901234567890",
+];
 
 /// A copy of [`JSON`] that does not rely on a [`NodeMap`] for recursive types
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum TestJSON {
     True,
     False,
+    Null,
     Array(Vec<TestJSON>),
     Object(Vec<(String, TestJSON)>),
+    Str(String),
+    Number(String),
 }
 
 impl TestJSON {
-    fn recursive_add_node_to_map<Ref: Reference, M: NodeMapMut<Ref, JSON<Ref>>>(
-        &self,
+    /// Generates a random tree which is no more than `max_depth` containers deep, and where no
+    /// container has more than `max_children` children.  Trees from the same `rng` state are
+    /// always the same.
+    pub fn random(rng: &mut TestRng, max_depth: usize, max_children: usize) -> TestJSON {
+        // Only leaves can be generated once the depth runs out
+        let kinds = if max_depth == 0 { 5 } else { 7 };
+        match rng.below(kinds) {
+            0 => TestJSON::True,
+            1 => TestJSON::False,
+            2 => TestJSON::Null,
+            3 => TestJSON::Str(Self::random_string(rng)),
+            4 => TestJSON::Number(rng.choose(NUMBERS).to_string()),
+            5 => TestJSON::Array(
+                (0..rng.below(max_children + 1))
+                    .map(|_| Self::random(rng, max_depth - 1, max_children))
+                    .collect(),
+            ),
+            _ => TestJSON::Object(
+                (0..rng.below(max_children + 1))
+                    .map(|_| {
+                        let key = Self::random_string(rng);
+                        (key, Self::random(rng, max_depth - 1, max_children))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Generates the contents of a random JSON string, which may be empty
+    fn random_string(rng: &mut TestRng) -> String {
+        (0..rng.below(4))
+            .map(|_| *rng.choose(STRING_PIECES))
+            .collect()
+    }
+
+    /// Returns the number of [`JSON`] nodes that this tree becomes when it's added to a
+    /// [`NodeMap`].  Every object field is made of three nodes: the field, its key and its value.
+    pub fn node_count(&self) -> usize {
+        match self {
+            TestJSON::Array(values) => 1 + values.iter().map(TestJSON::node_count).sum::<usize>(),
+            TestJSON::Object(fields) => {
+                1 + fields
+                    .iter()
+                    .map(|(_, v)| 2 + v.node_count())
+                    .sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
+
+    /// Returns trees which are slightly simpler than this one, simplest first: containers are
+    /// replaced by their children, lose one of their children or have one of their children
+    /// simplified, and leaves turn into `null`.
+    pub fn shrink(&self) -> Vec<TestJSON> {
+        let mut simpler = Vec::new();
+        match self {
+            TestJSON::Null => {}
+            TestJSON::Array(values) => {
+                simpler.extend(values.iter().cloned());
+                for i in 0..values.len() {
+                    let mut removed = values.clone();
+                    removed.remove(i);
+                    simpler.push(TestJSON::Array(removed));
+                }
+                for (i, v) in values.iter().enumerate() {
+                    for shrunk in v.shrink() {
+                        let mut values = values.clone();
+                        values[i] = shrunk;
+                        simpler.push(TestJSON::Array(values));
+                    }
+                }
+            }
+            TestJSON::Object(fields) => {
+                simpler.extend(fields.iter().map(|(_, v)| v.clone()));
+                for i in 0..fields.len() {
+                    let mut removed = fields.clone();
+                    removed.remove(i);
+                    simpler.push(TestJSON::Object(removed));
+                }
+                for (i, (key, v)) in fields.iter().enumerate() {
+                    let mut fields = fields.clone();
+                    if !key.is_empty() {
+                        fields[i].0 = String::new();
+                        simpler.push(TestJSON::Object(fields.clone()));
+                        fields[i].0 = key.clone();
+                    }
+                    for shrunk in v.shrink() {
+                        fields[i].1 = shrunk;
+                        simpler.push(TestJSON::Object(fields.clone()));
+                    }
+                }
+            }
+            _ => simpler.push(TestJSON::Null),
+        }
+        simpler
+    }
+
+    /// Checks that `property` holds for `cases` random trees (see [`TestJSON::random`]), and
+    /// panics with the simplest failing tree that can be found if it doesn't.  `property` is
+    /// given the tree and a seed that it can use for any other random choices, and fails by
+    /// returning an error or panicking.
+    pub fn check_property(
+        cases: u64,
+        max_depth: usize,
+        max_children: usize,
+        property: impl Fn(&TestJSON, u64) -> Result<(), String>,
+    ) {
+        let run = |tree: &TestJSON, seed: u64| {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| property(tree, seed)))
+                .unwrap_or_else(|_| Err("panicked".to_string()))
+        };
+        for seed in 0..cases {
+            let mut tree = Self::random(&mut TestRng::new(seed), max_depth, max_children);
+            let mut error = match run(&tree, seed) {
+                Ok(()) => continue,
+                Err(e) => e,
+            };
+            // Keep replacing the tree with a simpler one that still fails, until there are none
+            'shrinking: loop {
+                for simpler in tree.shrink() {
+                    if let Err(e) = run(&simpler, seed) {
+                        tree = simpler;
+                        error = e;
+                        continue 'shrinking;
+                    }
+                }
+                break;
+            }
+            panic!(
+                "Property failed with seed {} for {:?}: {}",
+                seed, tree, error
+            );
+        }
+    }
+
+    /// Turn this node into a [`VecNodeMap`] which contains the corresponding [`JSON`] node as
+    /// root. This also adds all the children to that VecNodeMap.
+    pub fn build_node_map<Ref: Reference, M: NodeMapMut<Ref, JSON<Ref>>>(&self) -> M {
+        // Count the nodes first, so that the map only allocates once (the extra node is the
+        // default root that the map starts with)
+        let mut node_map = M::with_capacity(self.node_count() + 1);
+        let root = node_map.add_tree(TreeView::Value(self));
+        node_map.set_root(root).unwrap();
+        node_map
+    }
+
+    /// Returns the [summary](crate::ast_spec::ASTSpec::summary) of the root node of this tree
+    pub fn summary(&self) -> String {
+        let node_map: VecNodeMap<JSON<Index>> = self.build_node_map();
+        node_map.root_node().summary()
+    }
+
+    /// Rebuild the tree stored in a [`NodeMap`] (or an
+    /// [`EditableTree`](crate::editable_tree::EditableTree)), starting from its root.  Nodes that
+    /// can't be reached from the root are ignored, and nodes that are reached more than once are
+    /// copied every time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree refers to a node that doesn't exist, or contains a field that isn't
+    /// inside an object (or whose key isn't a string).
+    pub fn from_node_map<Ref: Reference>(node_map: &impl NodeMap<Ref, JSON<Ref>>) -> TestJSON {
+        Self::from_node(node_map.root(), node_map)
+    }
+
+    fn from_node<Ref: Reference>(id: Ref, node_map: &impl NodeMap<Ref, JSON<Ref>>) -> TestJSON {
+        let get_node = |id: Ref| {
+            node_map
+                .get_node(id)
+                .unwrap_or_else(|| panic!("Node {:?} doesn't exist", id))
+        };
+        match get_node(id) {
+            JSON::True => TestJSON::True,
+            JSON::False => TestJSON::False,
+            JSON::Null => TestJSON::Null,
+            JSON::Str(content) => TestJSON::Str(content.clone()),
+            JSON::Number(number) => TestJSON::Number(number.clone()),
+            JSON::Array(children) => TestJSON::Array(
+                children
+                    .iter()
+                    .map(|c| Self::from_node(*c, node_map))
+                    .collect(),
+            ),
+            JSON::Object(fields) => TestJSON::Object(
+                fields
+                    .iter()
+                    .map(|f| match get_node(*f) {
+                        JSON::Field([key, value]) => match get_node(*key) {
+                            JSON::Str(key) => (key.clone(), Self::from_node(*value, node_map)),
+                            key => panic!("Field key {:?} isn't a string", key),
+                        },
+                        node => panic!("Object contains {:?}, which isn't a field", node),
+                    })
+                    .collect(),
+            ),
+            JSON::Field(_) => panic!("Field {:?} isn't inside an object", id),
+        }
+    }
+}
+
+/// A view of a part of a [`TestJSON`] tree, which is added to a [`NodeMap`] as one [`JSON`] node.
+/// Object fields (and their keys) don't have their own [`TestJSON`] values, so need their own
+/// views.
+#[derive(Debug, Clone, Copy)]
+enum TreeView<'t> {
+    Value(&'t TestJSON),
+    Field(&'t str, &'t TestJSON),
+    Key(&'t str),
+}
+
+impl<Ref: Reference> TreeBuilder<Ref, JSON<Ref>> for TreeView<'_> {
+    fn node_count(&self) -> usize {
+        match self {
+            TreeView::Value(tree) => tree.node_count(),
+            TreeView::Field(_, value) => 2 + value.node_count(),
+            TreeView::Key(_) => 1,
+        }
+    }
+
+    fn children(&self) -> Vec<Self> {
+        match self {
+            TreeView::Value(TestJSON::Array(values)) => {
+                values.iter().map(TreeView::Value).collect()
+            }
+            TreeView::Value(TestJSON::Object(fields)) => fields
+                .iter()
+                .map(|(key, value)| TreeView::Field(key, value))
+                .collect(),
+            TreeView::Field(key, value) => vec![TreeView::Key(key), TreeView::Value(value)],
+            TreeView::Value(_) | TreeView::Key(_) => vec![],
+        }
+    }
+
+    fn build_node(&self, children: Vec<Ref>) -> JSON<Ref> {
+        match self {
+            TreeView::Value(TestJSON::True) => JSON::True,
+            TreeView::Value(TestJSON::False) => JSON::False,
+            TreeView::Value(TestJSON::Null) => JSON::Null,
+            TreeView::Value(TestJSON::Str(content)) => JSON::Str(content.clone()),
+            TreeView::Value(TestJSON::Number(number)) => JSON::Number(number.clone()),
+            TreeView::Value(TestJSON::Array(_)) => JSON::Array(children),
+            TreeView::Value(TestJSON::Object(_)) => JSON::Object(children),
+            TreeView::Field(..) => JSON::Field([children[0], children[1]]),
+            TreeView::Key(key) => JSON::Str(key.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TestJSON, TestRng};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::size::Size;
+    use crate::ast_spec::ASTSpec;
+    use crate::node_map::Reference;
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    /// Adds every node of `tree` to `map` separately, the way that
+    /// [`TestJSON::build_node_map`] used to
+    fn add_nodes_one_by_one<Ref: Reference, M: NodeMapMut<Ref, JSON<Ref>>>(
+        tree: &TestJSON,
         map: &mut M,
     ) -> Ref {
-        match self {
+        match tree {
             TestJSON::True => map.add_node(JSON::True),
             TestJSON::False => map.add_node(JSON::False),
+            TestJSON::Null => map.add_node(JSON::Null),
+            TestJSON::Str(content) => map.add_node(JSON::Str(content.clone())),
+            TestJSON::Number(number) => map.add_node(JSON::Number(number.clone())),
             TestJSON::Array(child_nodes) => {
                 let child_refs = child_nodes
                     .iter()
-                    .map(|x| x.recursive_add_node_to_map(map))
+                    .map(|x| add_nodes_one_by_one(x, map))
                     .collect::<Vec<Ref>>();
                 map.add_node(JSON::Array(child_refs))
             }
@@ -36,7 +362,7 @@ impl TestJSON {
                 for (key, value) in fields.iter() {
                     // Add both child nodes
                     let s = map.add_node(JSON::Str(key.clone()));
-                    let v = value.recursive_add_node_to_map(map);
+                    let v = add_nodes_one_by_one(value, map);
                     // Combine the two nodes into a fields
                     children.push(map.add_node(JSON::Field([s, v])));
                 }
@@ -45,12 +371,160 @@ impl TestJSON {
         }
     }
 
-    /// Turn this node into a [`VecNodeMap`] which contains the corresponding [`JSON`] node as
-    /// root. This also adds all the children to that VecNodeMap.
-    pub fn build_node_map<Ref: Reference, M: NodeMapMut<Ref, JSON<Ref>>>(&self) -> M {
-        let mut node_map = M::with_default_root();
-        let root = self.recursive_add_node_to_map(&mut node_map);
-        node_map.set_root(root);
-        node_map
+    for_each_node_map! {
+        #[test]
+        fn round_trip() {
+            for tree in &[
+                TestJSON::True,
+                TestJSON::Null,
+                TestJSON::Array(vec![]),
+                TestJSON::Object(vec![]),
+                TestJSON::Array(vec![
+                    TestJSON::Number("-1.5".to_string()),
+                    TestJSON::Str(r#"a \"b\""#.to_string()),
+                    TestJSON::Object(vec![
+                        ("k".to_string(), TestJSON::False),
+                        (
+                            "nested".to_string(),
+                            TestJSON::Array(vec![TestJSON::Object(vec![])]),
+                        ),
+                    ]),
+                ]),
+            ] {
+                let node_map: Map<JSON<Ref>> = tree.build_node_map();
+                assert_eq!(TestJSON::from_node_map(&node_map), *tree);
+            }
+        }
+
+        #[test]
+        fn garbage_and_shared_nodes() {
+            let mut node_map = Map::<JSON<Ref>>::with_default_root();
+            let shared = node_map.add_node(JSON::True);
+            let root = node_map.add_node(JSON::Array(vec![shared, shared]));
+            // This node isn't reachable from the root, so isn't part of the tree
+            node_map.add_node(JSON::Str("garbage".to_string()));
+            node_map.set_root(root).unwrap();
+            assert_eq!(
+                TestJSON::from_node_map(&node_map),
+                TestJSON::Array(vec![TestJSON::True, TestJSON::True])
+            );
+            assert_eq!(node_map.root(), root);
+        }
+
+        #[test]
+        fn random_trees() {
+            // The same seed always gives the same tree, and different seeds eventually give
+            // every kind of node
+            let trees: Vec<TestJSON> = (0..200)
+                .map(|seed| TestJSON::random(&mut TestRng::new(seed), 3, 4))
+                .collect();
+            for (seed, tree) in trees.iter().enumerate() {
+                assert_eq!(
+                    TestJSON::random(&mut TestRng::new(seed as u64), 3, 4),
+                    *tree
+                );
+            }
+            let has = |pred: fn(&TestJSON) -> bool| trees.iter().any(pred);
+            assert!(has(|t| matches!(t, TestJSON::Array(v) if v.is_empty())));
+            assert!(has(|t| matches!(t, TestJSON::Object(f) if f.is_empty())));
+            assert!(has(|t| matches!(t, TestJSON::Str(_))));
+            assert!(has(|t| matches!(t, TestJSON::Number(_))));
+            // Depth 0 only allows leaves
+            for seed in 0..20 {
+                let tree = TestJSON::random(&mut TestRng::new(seed), 0, 4);
+                assert_eq!(tree.node_count(), 1);
+            }
+        }
+
+        #[test]
+        fn shrinking() {
+            // Finds the smallest array that contains a `false`
+            let result = std::panic::catch_unwind(|| {
+                TestJSON::check_property(100, 3, 4, |tree, _| {
+                    let node_map: Map<JSON<Ref>> = tree.build_node_map();
+                    let has_false = node_map
+                        .preorder()
+                        .any(|r| node_map.get_node(r) == Some(&JSON::False));
+                    if matches!(tree, TestJSON::Array(_)) && has_false {
+                        return Err("found false".to_string());
+                    }
+                    Ok(())
+                });
+            });
+            let message = result.unwrap_err();
+            let message = message.downcast_ref::<String>().unwrap();
+            assert!(
+                message.ends_with("for Array([False]): found false"),
+                "{}",
+                message
+            );
+        }
+
+        #[test]
+        fn building_matches_adding_nodes_one_by_one() {
+            TestJSON::check_property(200, 4, 4, |tree, _| {
+                let built: Map<JSON<Ref>> = tree.build_node_map();
+                let mut added: Map<JSON<Ref>> = Map::with_default_root();
+                let root = add_nodes_one_by_one(tree, &mut added);
+                added.set_root(root).unwrap();
+                // Both add the same nodes in the same order, so they have the same references
+                if built.iter_all().collect::<Vec<_>>() != added.iter_all().collect::<Vec<_>>() {
+                    return Err("the maps are different".to_string());
+                }
+                Ok(())
+            });
+        }
+
+        #[test]
+        fn building_large_trees() {
+            let tree = TestJSON::Array(vec![
+                TestJSON::Object(vec![("k".to_string(), TestJSON::Null); 10_000]);
+                10
+            ]);
+            let node_map: Map<JSON<Ref>> = tree.build_node_map();
+            assert_eq!(node_map.iter_all().count(), 1 + tree.node_count());
+            assert_eq!(TestJSON::from_node_map(&node_map), tree);
+        }
+
+        #[test]
+        fn building_and_printing() {
+            TestJSON::check_property(200, 4, 4, |tree, _| {
+                let node_map: Map<JSON<Ref>> = tree.build_node_map();
+                if node_map.preorder().count() != tree.node_count() {
+                    return Err("wrong number of nodes".to_string());
+                }
+                for style in &[
+                    JSONFormat::Pretty,
+                    JSONFormat::Compact,
+                    JSONFormat::Minified,
+                ] {
+                    let text = node_map.to_text(style);
+                    if node_map.root_node().size(&node_map, style) != Size::from(text.as_str()) {
+                        return Err(format!("the size of {:?} is wrong", text));
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        #[test]
+        fn parsing_printed_trees() {
+            TestJSON::check_property(200, 4, 4, |tree, _| {
+                let node_map: Map<JSON<Ref>> = tree.build_node_map();
+                for style in &[
+                    JSONFormat::Pretty,
+                    JSONFormat::Compact,
+                    JSONFormat::Minified,
+                ] {
+                    let text = node_map.to_text(style);
+                    let parsed: Map<JSON<Ref>> =
+                        JSON::parse_into(&text).map_err(|e| format!("{}: {}", text, e))?;
+                    if TestJSON::from_node_map(&parsed) != *tree {
+                        return Err(format!("{:?} parsed differently", text));
+                    }
+                }
+                Ok(())
+            });
+        }
     }
 }
@@ -0,0 +1,57 @@
+use super::lisp::Lisp;
+use crate::node_map::{NodeMapMut, Reference, TreeBuilder};
+
+// Import used only for doc comments
+#[allow(unused_imports)]
+use crate::node_map::NodeMap;
+
+/// A copy of [`Lisp`] that does not rely on a [`NodeMap`] for recursive types
+pub enum TestLisp {
+    Symbol(String),
+    Number(String),
+    Str(String),
+    List(Vec<TestLisp>),
+}
+
+impl TestLisp {
+    /// Returns the number of [`Lisp`] nodes that this tree becomes when it's added to a
+    /// [`NodeMap`]
+    pub fn node_count(&self) -> usize {
+        match self {
+            TestLisp::List(items) => 1 + items.iter().map(TestLisp::node_count).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    /// Build a [`NodeMap`] which contains this tree, with the corresponding [`Lisp`] node as its
+    /// root
+    pub fn build_node_map<Ref: Reference, M: NodeMapMut<Ref, Lisp<Ref>>>(&self) -> M {
+        // The extra node is the default root that the map starts with
+        let mut node_map = M::with_capacity(self.node_count() + 1);
+        let root = node_map.add_tree(self);
+        node_map.set_root(root).unwrap();
+        node_map
+    }
+}
+
+impl<Ref: Reference> TreeBuilder<Ref, Lisp<Ref>> for &TestLisp {
+    fn node_count(&self) -> usize {
+        TestLisp::node_count(self)
+    }
+
+    fn children(&self) -> Vec<Self> {
+        match self {
+            TestLisp::List(items) => items.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    fn build_node(&self, children: Vec<Ref>) -> Lisp<Ref> {
+        match self {
+            TestLisp::Symbol(name) => Lisp::Symbol(name.clone()),
+            TestLisp::Number(number) => Lisp::Number(number.clone()),
+            TestLisp::Str(content) => Lisp::Str(content.clone()),
+            TestLisp::List(_) => Lisp::List(children),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::xml::XML;
+use crate::node_map::{NodeMapMut, Reference, TreeBuilder};
+
+// Import used only for doc comments
+#[allow(unused_imports)]
+use crate::node_map::NodeMap;
+
+/// A copy of [`XML`] that does not rely on a [`NodeMap`] for recursive types
+pub enum TestXML {
+    /// An element with the given start tag contents (e.g. `a href="x"`) and children
+    Element(String, Vec<TestXML>),
+    Text(String),
+    Comment(String),
+}
+
+impl TestXML {
+    /// Returns the number of [`XML`] nodes that this tree becomes when it's added to a
+    /// [`NodeMap`]
+    pub fn node_count(&self) -> usize {
+        match self {
+            TestXML::Element(_, children) => {
+                1 + children.iter().map(TestXML::node_count).sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
+
+    /// Build a [`NodeMap`] which contains this tree, with the corresponding [`XML`] node as its
+    /// root
+    pub fn build_node_map<Ref: Reference, M: NodeMapMut<Ref, XML<Ref>>>(&self) -> M {
+        // The extra node is the default root that the map starts with
+        let mut node_map = M::with_capacity(self.node_count() + 1);
+        let root = node_map.add_tree(self);
+        node_map.set_root(root).unwrap();
+        node_map
+    }
+}
+
+impl<Ref: Reference> TreeBuilder<Ref, XML<Ref>> for &TestXML {
+    fn node_count(&self) -> usize {
+        TestXML::node_count(self)
+    }
+
+    fn children(&self) -> Vec<Self> {
+        match self {
+            TestXML::Element(_, children) => children.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    fn build_node(&self, children: Vec<Ref>) -> XML<Ref> {
+        match self {
+            TestXML::Element(tag, _) => XML::Element {
+                tag: tag.clone(),
+                children,
+            },
+            TestXML::Text(text) => XML::Text(text.clone()),
+            TestXML::Comment(text) => XML::Comment(text.clone()),
+        }
+    }
+}
@@ -0,0 +1,726 @@
+//! An [`ASTSpec`] for small XML-like markup documents
+
+use super::size::Size;
+use super::{ASTSpec, DisplayToken, LiteralValidity, Reference, SyntaxCategory};
+use crate::node_map::NodeMap;
+
+/// The different ways that an XML document can be formatted
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum XMLFormat {
+    /// Everything on one line, with no whitespace added between nodes.  This is the only style
+    /// that keeps the whitespace of text exactly as it was.
+    /// E.g. `<p class="x">Hello <b>world</b></p>`
+    Compact,
+    /// Every child of an element goes on its own indented line, as if whitespace between nodes
+    /// didn't matter.  E.g.
+    /// ```text
+    /// <p class="x">
+    ///     Hello
+    ///     <b>
+    ///         world
+    ///     </b>
+    /// </p>
+    /// ```
+    #[default]
+    Pretty,
+}
+
+const CHAR_ELEMENT: char = 'e';
+const CHAR_TEXT: char = 't';
+const CHAR_COMMENT: char = 'c';
+
+/// The sapling representation of the AST for an XML document
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum XML<Ref: Reference> {
+    /// An element, such as `<a href="x">link</a>`.  `tag` is the text of the element's start tag
+    /// between the angle brackets (e.g. `a href="x"`), which is the element's name followed by
+    /// its attributes.  Attribute values are stored exactly as they appear between the quotes,
+    /// i.e. including any escapes, and `tag` must be [valid](check_start_tag).  Elements without
+    /// children are written as empty-element tags (e.g. `<br/>`).
+    Element { tag: String, children: Vec<Ref> },
+    /// A piece of text content.  This is stored exactly as it appears in the document, i.e.
+    /// with `&` and `<` escaped (see [`escape_text`]).
+    Text(String),
+    /// A comment, stored without the surrounding `<!--` and `-->`.  This can't contain `--` or
+    /// end with `-`.
+    Comment(String),
+}
+
+/// Returns `true` if `c` can start the name of an element or attribute
+fn is_name_start_char(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+/// Returns `true` if `c` can appear after the first char of the name of an element or attribute
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c) || c.is_numeric() || c == '-' || c == '.'
+}
+
+/// Returns the length (in bytes) of the name at the start of `text`, or 0 if `text` doesn't
+/// start with a name
+fn name_length(text: &str) -> usize {
+    match text.chars().next() {
+        Some(c) if is_name_start_char(c) => text.find(|c| !is_name_char(c)).unwrap_or(text.len()),
+        _ => 0,
+    }
+}
+
+/// Escape `text` so that it can be used as the contents of a [`Text`](XML::Text) node
+pub fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Escape `text` so that it can be used as the value of an attribute
+pub fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns the char referred to by the contents of an entity reference (i.e. the part between the
+/// `&` and the `;`), or [`None`] if it isn't a valid reference.  Only the entities predefined by
+/// XML and numeric character references are supported.
+fn decode_entity(body: &str) -> Option<char> {
+    let (digits, radix) = match body {
+        "amp" => return Some('&'),
+        "lt" => return Some('<'),
+        "gt" => return Some('>'),
+        "quot" => return Some('"'),
+        "apos" => return Some('\''),
+        _ => match body.strip_prefix("#x") {
+            Some(hex) => (hex, 16),
+            None => (body.strip_prefix('#')?, 10),
+        },
+    };
+    // `from_str_radix` would also accept a sign
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u32::from_str_radix(digits, radix)
+        .ok()
+        .and_then(std::char::from_u32)
+}
+
+/// Returns `true` if more chars could be typed after `body` to make a valid entity reference
+fn is_entity_prefix(body: &str) -> bool {
+    let named = ["amp", "lt", "gt", "quot", "apos"];
+    match body.strip_prefix('#') {
+        Some(code) => match code.strip_prefix('x') {
+            Some(hex) => hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => code.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => named.iter().any(|n| n.starts_with(body)),
+    }
+}
+
+/// Decode the entity references in some escaped text (e.g. `a &amp; b` becomes `a & b`).
+/// Returns [`None`] if `text` contains an invalid or unfinished reference.
+pub fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        let end = rest[i..].find(';')? + i;
+        unescaped.push(decode_entity(&rest[i + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
+
+/// Checks that `text` is correctly escaped, i.e. that it contains none of the chars in
+/// `forbidden` or any control chars, and that every `&` starts a valid entity reference
+fn check_escaped(text: &str, forbidden: &str) -> LiteralValidity {
+    let mut rest = text;
+    loop {
+        let i = match rest.find(|c: char| c == '&' || forbidden.contains(c) || c < ' ') {
+            Some(i) => i,
+            None => return LiteralValidity::Valid,
+        };
+        if !rest[i..].starts_with('&') {
+            return LiteralValidity::Invalid;
+        }
+        let body_and_rest = &rest[i + 1..];
+        match body_and_rest.find(';') {
+            Some(end) if decode_entity(&body_and_rest[..end]).is_some() => {
+                rest = &body_and_rest[end + 1..];
+            }
+            // The reference could be finished by typing more of it followed by a `;`
+            None if is_entity_prefix(body_and_rest) => return LiteralValidity::Incomplete,
+            _ => return LiteralValidity::Invalid,
+        }
+    }
+}
+
+/// Checks whether `text` is the valid contents of a start tag, i.e. a name followed by
+/// space-separated `name="value"` attributes with distinct names and
+/// [escaped](escape_attribute) values
+pub fn check_start_tag(text: &str) -> LiteralValidity {
+    let name_len = name_length(text);
+    if name_len == 0 {
+        return if text.is_empty() {
+            LiteralValidity::Incomplete
+        } else {
+            LiteralValidity::Invalid
+        };
+    }
+    let mut rest = &text[name_len..];
+    let mut attribute_names = Vec::new();
+    loop {
+        if rest.is_empty() {
+            return LiteralValidity::Valid;
+        }
+        // Attributes must be separated by spaces, and trailing spaces are only allowed while
+        // the next attribute is being typed
+        let after_spaces = rest.trim_start_matches(' ');
+        if after_spaces.len() == rest.len() {
+            return LiteralValidity::Invalid;
+        }
+        rest = after_spaces;
+        let name_len = name_length(rest);
+        if name_len == 0 {
+            return if rest.is_empty() {
+                LiteralValidity::Incomplete
+            } else {
+                LiteralValidity::Invalid
+            };
+        }
+        let (name, after_name) = rest.split_at(name_len);
+        rest = match after_name.strip_prefix("=\"") {
+            Some(value) => value,
+            None if "=\"".starts_with(after_name) => return LiteralValidity::Incomplete,
+            None => return LiteralValidity::Invalid,
+        };
+        if attribute_names.contains(&name) {
+            return LiteralValidity::Invalid;
+        }
+        attribute_names.push(name);
+        match rest.find('"') {
+            Some(end) if check_escaped(&rest[..end], "<") == LiteralValidity::Valid => {
+                rest = &rest[end + 1..];
+            }
+            Some(_) => return LiteralValidity::Invalid,
+            // The value hasn't been closed yet
+            None => {
+                return match check_escaped(rest, "<") {
+                    LiteralValidity::Invalid => LiteralValidity::Invalid,
+                    _ => LiteralValidity::Incomplete,
+                };
+            }
+        }
+    }
+}
+
+/// Checks whether `text` can be the contents of a [`Comment`](XML::Comment)
+fn check_comment(text: &str) -> LiteralValidity {
+    if text.contains("--") || text.chars().any(|c| c < ' ') {
+        LiteralValidity::Invalid
+    } else if text.ends_with('-') {
+        // This is fine as long as something other than a `-` is typed next
+        LiteralValidity::Incomplete
+    } else {
+        LiteralValidity::Valid
+    }
+}
+
+/// Builds the contents of a start tag from an element name and some unescaped attributes, which
+/// can be used as the `tag` of an [`Element`](XML::Element)
+pub fn start_tag(name: &str, attributes: &[(&str, &str)]) -> String {
+    let mut tag = name.to_string();
+    for (name, value) in attributes {
+        tag.push_str(&format!(r#" {}="{}""#, name, escape_attribute(value)));
+    }
+    tag
+}
+
+impl<Ref: Reference> XML<Ref> {
+    /// Return an iterator over all the possible chars that could represent XML nodes
+    fn all_chars() -> Box<dyn Iterator<Item = char>> {
+        Box::new([CHAR_ELEMENT, CHAR_TEXT, CHAR_COMMENT].iter().copied())
+    }
+
+    /// Returns the name of this node if it's an [`Element`](XML::Element)
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            XML::Element { tag, .. } => Some(&tag[..name_length(tag)]),
+            XML::Text(_) | XML::Comment(_) => None,
+        }
+    }
+
+    /// Returns the names and unescaped values of the attributes of this node, in order.  Nodes
+    /// other than elements have no attributes.
+    pub fn attributes(&self) -> Vec<(&str, String)> {
+        let tag = match self {
+            XML::Element { tag, .. } => tag,
+            XML::Text(_) | XML::Comment(_) => return vec![],
+        };
+        let mut attributes = Vec::new();
+        let mut rest = &tag[name_length(tag)..];
+        // The tag is valid, so this only has to split it up
+        while let Some(start) = rest.find(|c| c != ' ') {
+            rest = &rest[start..];
+            let name_len = name_length(rest);
+            let value = &rest[name_len + 2..];
+            let end = value.find('"').unwrap_or(value.len());
+            attributes.push((
+                &rest[..name_len],
+                unescape(&value[..end]).unwrap_or_default(),
+            ));
+            rest = &value[(end + 1).min(value.len())..];
+        }
+        attributes
+    }
+}
+
+impl<Ref: Reference> Default for XML<Ref> {
+    fn default() -> XML<Ref> {
+        XML::Element {
+            tag: "root".to_string(),
+            children: vec![],
+        }
+    }
+}
+
+impl<Ref: Reference> ASTSpec<Ref> for XML<Ref> {
+    type FormatStyle = XMLFormat;
+
+    /* FORMATTING FUNCTIONS */
+
+    fn display_tokens(&self, format_style: &Self::FormatStyle) -> Vec<DisplayToken<Ref>> {
+        let (tag, children) = match self {
+            XML::Text(text) => return vec![DisplayToken::Text(text.clone())],
+            XML::Comment(text) => return vec![DisplayToken::Text(format!("<!--{}-->", text))],
+            XML::Element { tag, children } => (tag, children),
+        };
+        if children.is_empty() {
+            return vec![DisplayToken::Text(format!("<{}/>", tag))];
+        }
+        let is_pretty = format_style == &XMLFormat::Pretty;
+        let mut tokens = vec![DisplayToken::Text(format!("<{}>", tag))];
+        if is_pretty {
+            tokens.push(DisplayToken::Newline);
+            tokens.push(DisplayToken::Indent);
+        }
+        for (i, c) in children.iter().enumerate() {
+            if is_pretty && i > 0 {
+                tokens.push(DisplayToken::Newline);
+            }
+            tokens.push(DisplayToken::Child(*c));
+        }
+        if is_pretty {
+            tokens.push(DisplayToken::Newline);
+            tokens.push(DisplayToken::Dedent);
+        }
+        tokens.push(DisplayToken::Text(format!("</{}>", self.name().unwrap())));
+        tokens
+    }
+
+    fn size(&self, node_map: &impl NodeMap<Ref, Self>, format_style: &Self::FormatStyle) -> Size {
+        let (tag, children) = match self {
+            XML::Text(text) => return Size::from(text.as_str()),
+            XML::Comment(text) => {
+                return Size::new(0, 4) + Size::from(text.as_str()) + Size::new(0, 3)
+            }
+            XML::Element { tag, children } => (tag, children),
+        };
+        if children.is_empty() {
+            return Size::new(0, 1) + Size::from(tag.as_str()) + Size::new(0, 2);
+        }
+        // Every child starts on a new line when pretty printing
+        let separator = match format_style {
+            XMLFormat::Compact => Size::new(0, 0),
+            XMLFormat::Pretty => Size::new(1, 0),
+        };
+        let mut size = Size::new(0, 1) + Size::from(tag.as_str()) + Size::new(0, 1);
+        for c in children {
+            size += separator;
+            size += node_map.get_node(*c).unwrap().size(node_map, format_style);
+        }
+        // The end tag also gets its own line, which isn't indented
+        size + separator + Size::from(format!("</{}>", self.name().unwrap()).as_str())
+    }
+
+    fn next_format_style(style: &XMLFormat) -> Option<XMLFormat> {
+        Some(match style {
+            XMLFormat::Pretty => XMLFormat::Compact,
+            XMLFormat::Compact => XMLFormat::Pretty,
+        })
+    }
+
+    /* DEBUG VIEW FUNCTIONS */
+
+    fn children(&self) -> &[Ref] {
+        match self {
+            XML::Text(_) | XML::Comment(_) => &[],
+            XML::Element { children, .. } => children,
+        }
+    }
+
+    fn children_mut(&mut self) -> &mut [Ref] {
+        match self {
+            XML::Text(_) | XML::Comment(_) => &mut [],
+            XML::Element { children, .. } => children,
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        match self {
+            XML::Element { tag, children } => {
+                tag.capacity() + children.capacity() * std::mem::size_of::<Ref>()
+            }
+            XML::Text(text) | XML::Comment(text) => text.capacity(),
+        }
+    }
+
+    fn display_name(&self) -> String {
+        match self {
+            XML::Element { .. } => format!("<{}>", self.name().unwrap()),
+            XML::Text(text) => text.clone(),
+            XML::Comment(text) => format!("<!--{}-->", text),
+        }
+    }
+
+    /// Elements are summarised as an element with the same name, containing only `marker`
+    fn fold_summary(&self, _node_map: &impl NodeMap<Ref, Self>, marker: &str) -> String {
+        match self.name() {
+            Some(name) => format!("<{}>{}</{}>", name, marker, name),
+            None => format!("{} {}", self.display_name(), marker),
+        }
+    }
+
+    /// The attributes of an element are given as its entries
+    fn record_entries(&self, _node_map: &impl NodeMap<Ref, Self>) -> Option<Vec<(String, String)>> {
+        match self {
+            XML::Element { .. } => Some(
+                self.attributes()
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+            ),
+            XML::Text(_) | XML::Comment(_) => None,
+        }
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match self {
+            XML::Element { .. } => "element",
+            XML::Text(_) => "text",
+            XML::Comment(_) => "comment",
+        }
+    }
+
+    /* AST EDITING FUNCTIONS */
+
+    fn replace_chars(&self) -> Box<dyn Iterator<Item = char>> {
+        Self::all_chars()
+    }
+
+    fn char_descriptions() -> Vec<(char, &'static str)> {
+        vec![
+            (CHAR_ELEMENT, "empty element"),
+            (CHAR_TEXT, "text"),
+            (CHAR_COMMENT, "empty comment"),
+        ]
+    }
+
+    fn from_char(&self, c: char) -> Option<Self> {
+        match c {
+            CHAR_ELEMENT => Some(XML::Element {
+                tag: "div".to_string(),
+                children: vec![],
+            }),
+            CHAR_TEXT => Some(XML::Text("text".to_string())),
+            CHAR_COMMENT => Some(XML::Comment(String::new())),
+            _ => None,
+        }
+    }
+
+    fn insert_chars(&self) -> Box<dyn Iterator<Item = char>> {
+        match self {
+            XML::Text(_) | XML::Comment(_) => Box::new(std::iter::empty()),
+            XML::Element { .. } => Self::all_chars(),
+        }
+    }
+
+    fn insert_child(&mut self, child: Ref, index: usize) -> bool {
+        match self {
+            XML::Text(_) | XML::Comment(_) => false,
+            XML::Element { children, .. } => {
+                children.insert(index.min(children.len()), child);
+                true
+            }
+        }
+    }
+
+    fn remove_child(&mut self, index: usize) -> bool {
+        match self {
+            XML::Element { children, .. } if index < children.len() => {
+                children.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn merge_with(&self, other: &Self, separator: &str) -> Option<Self> {
+        match (self, other) {
+            (XML::Text(first), XML::Text(second)) => Some(XML::Text(format!(
+                "{}{}{}",
+                first,
+                escape_text(separator),
+                second
+            ))),
+            _ => None,
+        }
+    }
+
+    fn normalized(&self) -> Option<Self> {
+        match self {
+            XML::Text(text) => unescape(text).map(|t| XML::Text(escape_text(&t))),
+            _ => None,
+        }
+    }
+
+    fn syntax_category(&self) -> SyntaxCategory {
+        match self {
+            XML::Text(_) => SyntaxCategory::String,
+            // Elements only generate their tags themselves
+            XML::Element { .. } => SyntaxCategory::Punctuation,
+            XML::Comment(_) => SyntaxCategory::Other,
+        }
+    }
+
+    fn literal(&self) -> Option<&str> {
+        match self {
+            XML::Element { tag: text, .. } | XML::Text(text) | XML::Comment(text) => Some(text),
+        }
+    }
+
+    fn with_literal(&self, text: &str) -> Option<Self> {
+        if self.check_literal(text) != LiteralValidity::Valid {
+            return None;
+        }
+        let text = text.to_string();
+        Some(match self {
+            XML::Element { children, .. } => XML::Element {
+                tag: text,
+                children: children.clone(),
+            },
+            XML::Text(_) => XML::Text(text),
+            XML::Comment(_) => XML::Comment(text),
+        })
+    }
+
+    fn check_literal(&self, text: &str) -> LiteralValidity {
+        match self {
+            XML::Element { .. } => check_start_tag(text),
+            XML::Text(_) => check_escaped(text, "<"),
+            XML::Comment(_) => check_comment(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_start_tag, escape_attribute, escape_text, start_tag, unescape, XMLFormat, XML,
+    };
+    use crate::ast_spec::size::Size;
+    use crate::ast_spec::test_xml::TestXML;
+    use crate::ast_spec::{ASTSpec, LiteralValidity};
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    /// Non-generic version of [`TestXML::build_node_map`] that always returns a [`VecNodeMap`].
+    fn build_vec_node_map(tree: &TestXML) -> VecNodeMap<XML<Index>> {
+        tree.build_node_map::<Index, VecNodeMap<XML<Index>>>()
+    }
+
+    fn element(tag: &str, children: Vec<TestXML>) -> TestXML {
+        TestXML::Element(tag.to_string(), children)
+    }
+
+    fn text(text: &str) -> TestXML {
+        TestXML::Text(text.to_string())
+    }
+
+    /// A nested document that uses every kind of node, and needs escaping in its text and
+    /// attributes
+    fn fixture() -> TestXML {
+        element(
+            &start_tag("doc", &[("title", r#"Fish & "chips""#), ("lang", "en")]),
+            vec![
+                TestXML::Comment(" generated ".to_string()),
+                element(
+                    "p",
+                    vec![
+                        text(&escape_text("1 < 2 & 3 > 2")),
+                        element("br", vec![]),
+                        element(r#"b class="x""#, vec![text("bold")]),
+                    ],
+                ),
+                element("empty", vec![]),
+            ],
+        )
+    }
+
+    #[test]
+    fn to_text() {
+        let compact = concat!(
+            r#"<doc title="Fish &amp; &quot;chips&quot;" lang="en"><!-- generated -->"#,
+            r#"<p>1 &lt; 2 &amp; 3 &gt; 2<br/><b class="x">bold</b></p><empty/></doc>"#
+        );
+        let pretty = r#"<doc title="Fish &amp; &quot;chips&quot;" lang="en">
+    <!-- generated -->
+    <p>
+        1 &lt; 2 &amp; 3 &gt; 2
+        <br/>
+        <b class="x">
+            bold
+        </b>
+    </p>
+    <empty/>
+</doc>"#;
+        let node_map = build_vec_node_map(&fixture());
+        for (style, expected) in &[(XMLFormat::Compact, compact), (XMLFormat::Pretty, pretty)] {
+            assert_eq!(node_map.to_text(style), *expected);
+            assert_eq!(
+                node_map.root_node().size(&node_map, style),
+                Size::from(*expected)
+            );
+        }
+        // Cycling through every format style comes back to the same text
+        let mut style = XMLFormat::Pretty;
+        for _ in 0..2 {
+            style = XML::<Index>::next_format_style(&style).unwrap();
+        }
+        assert_eq!(node_map.to_text(&style), pretty);
+    }
+
+    #[test]
+    fn attributes() {
+        let node_map = build_vec_node_map(&fixture());
+        let root = node_map.root_node();
+        assert_eq!(root.name(), Some("doc"));
+        assert_eq!(
+            root.attributes(),
+            vec![
+                ("title", r#"Fish & "chips""#.to_string()),
+                ("lang", "en".to_string())
+            ]
+        );
+        assert_eq!(
+            root.record_entries(&node_map).unwrap()[1],
+            ("lang".to_string(), "en".to_string())
+        );
+        let comment = node_map.get_node(root.children()[0]).unwrap();
+        assert_eq!(comment.name(), None);
+        assert!(comment.attributes().is_empty());
+    }
+
+    #[test]
+    fn escaping() {
+        for raw in &[
+            "",
+            "plain",
+            "a & b",
+            r#"<tag attr="1">"#,
+            "&amp;",
+            "'quote'",
+        ] {
+            assert_eq!(unescape(&escape_text(raw)).as_deref(), Some(*raw));
+            assert_eq!(unescape(&escape_attribute(raw)).as_deref(), Some(*raw));
+        }
+        assert_eq!(escape_attribute(r#"a"b"#), "a&quot;b");
+        assert_eq!(unescape("&#65;&#x42;&apos;").as_deref(), Some("AB'"));
+        for invalid in &["&", "&amp", "&nbsp;", "&#;", "&#x;", "&#+1;", "&#xD800;"] {
+            assert_eq!(unescape(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn literals() {
+        for (tag, expected) in &[
+            ("a", LiteralValidity::Valid),
+            (r#"a href="x" id="&lt;""#, LiteralValidity::Valid),
+            ("svg:rect data-x.y=\"1\"", LiteralValidity::Valid),
+            ("", LiteralValidity::Incomplete),
+            ("a ", LiteralValidity::Incomplete),
+            ("a href", LiteralValidity::Incomplete),
+            ("a href=", LiteralValidity::Incomplete),
+            (r#"a href="x"#, LiteralValidity::Incomplete),
+            (r#"a href="&am"#, LiteralValidity::Incomplete),
+            ("1a", LiteralValidity::Invalid),
+            ("a>", LiteralValidity::Invalid),
+            (r#"a href='x'"#, LiteralValidity::Invalid),
+            (r#"a x="1"y="2""#, LiteralValidity::Invalid),
+            (r#"a x="1" x="2""#, LiteralValidity::Invalid),
+            (r#"a x="<""#, LiteralValidity::Invalid),
+            (r#"a x="&am;""#, LiteralValidity::Invalid),
+        ] {
+            assert_eq!(check_start_tag(tag), *expected, "{:?}", tag);
+        }
+
+        let text = XML::<Index>::Text(String::new());
+        let comment = XML::<Index>::Comment(String::new());
+        for (node, literal, expected) in &[
+            (&text, "a &gt; b", LiteralValidity::Valid),
+            (&text, r#"say "hi""#, LiteralValidity::Valid),
+            (&text, "a &", LiteralValidity::Incomplete),
+            (&text, "a &#x4", LiteralValidity::Incomplete),
+            (&text, "a < b", LiteralValidity::Invalid),
+            (&text, "a & b", LiteralValidity::Invalid),
+            (&text, "two\nlines", LiteralValidity::Invalid),
+            (&comment, " note ", LiteralValidity::Valid),
+            (&comment, "a-b", LiteralValidity::Valid),
+            (&comment, "a-", LiteralValidity::Incomplete),
+            (&comment, "a--b", LiteralValidity::Invalid),
+        ] {
+            assert_eq!(node.check_literal(literal), *expected, "{:?}", literal);
+        }
+
+        // Editing an element's tag keeps its children
+        let node_map = build_vec_node_map(&fixture());
+        let p = node_map
+            .get_node(node_map.root_node().children()[1])
+            .unwrap();
+        let edited = p.with_literal(r#"p id="intro""#).unwrap();
+        assert_eq!(edited.children(), p.children());
+        assert_eq!(edited.name(), Some("p"));
+        assert_eq!(p.with_literal("p id="), None);
+        assert_eq!(text.with_literal("a < b"), None);
+    }
+
+    #[test]
+    fn char_table() {
+        let node = XML::<Index>::Text(String::new());
+        let root = XML::<Index>::default();
+        for (c, _) in XML::<Index>::char_descriptions() {
+            assert!(node.replace_chars().any(|r| r == c));
+            assert!(root.insert_chars().any(|r| r == c));
+            // Every node made from a char can be written out
+            let new_node = node.from_char(c).unwrap();
+            assert_eq!(
+                new_node.check_literal(new_node.literal().unwrap()),
+                LiteralValidity::Valid
+            );
+        }
+        // Only elements can have children
+        assert_eq!(node.insert_chars().count(), 0);
+        assert_eq!(node.from_char('x'), None);
+    }
+
+    #[test]
+    fn merge_and_normalize() {
+        let a = XML::<Index>::Text("a".to_string());
+        let b = XML::<Index>::Text("&#98;".to_string());
+        assert_eq!(
+            a.merge_with(&b, " & "),
+            Some(XML::Text("a &amp; &#98;".to_string()))
+        );
+        assert_eq!(b.normalized(), Some(XML::Text("b".to_string())));
+        assert_eq!(a.merge_with(&XML::Comment(String::new()), ""), None);
+    }
+}
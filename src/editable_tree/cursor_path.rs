@@ -1,4 +1,5 @@
-use crate::node_map::Reference;
+use crate::ast_spec::ASTSpec;
+use crate::node_map::{NodeMap, Reference};
 
 // Imports solely for doc comments
 #[allow(unused_imports)]
@@ -62,3 +63,157 @@ impl<Ref: Reference> Segment<Ref> {
         Self::new(node_index, 0)
     }
 }
+
+/// Extend a cursor path so that it points to the `index`th child of the node it currently points
+/// to.  Returns `false` and leaves `path` unchanged if no such child exists.
+pub(super) fn move_to_child<Ref: Reference, Node: ASTSpec<Ref>>(
+    path: &mut Vec<Segment<Ref>>,
+    node_map: &impl NodeMap<Ref, Node>,
+    index: usize,
+) -> bool {
+    // We require that `path` is never empty, so this unwrap is fine
+    let current = path.last().unwrap().node;
+    match node_map
+        .get_node(current)
+        .and_then(|n| n.children().get(index))
+    {
+        Some(child) => {
+            path.push(Segment::new(*child, index));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Shorten a cursor path so that it points to the parent of the node it currently points to.
+/// Returns `false` if `path` already points to the root.
+/// Returns the index of every node in `path` within its siblings, excluding the root
+pub(super) fn child_indices<Ref: Reference>(path: &[Segment<Ref>]) -> Vec<usize> {
+    path.iter().skip(1).map(|s| s.sibling_index).collect()
+}
+
+/// Returns the index of the node that `path` points to within its siblings, or [`None`] if it
+/// points to the root
+pub(super) fn sibling_index<Ref: Reference>(path: &[Segment<Ref>]) -> Option<usize> {
+    match path {
+        [_, .., last] => Some(last.sibling_index),
+        _ => None,
+    }
+}
+
+pub(super) fn move_to_parent<Ref: Reference>(path: &mut Vec<Segment<Ref>>) -> bool {
+    if path.len() > 1 {
+        path.pop();
+        true
+    } else {
+        false
+    }
+}
+
+/// Shorten a cursor path so that it points to the nearest strict ancestor of the current node for
+/// which `pred` returns `true`.  Returns `false` and leaves `path` unchanged if there is no such
+/// ancestor.
+pub(super) fn move_to_ancestor<Ref: Reference, Node: ASTSpec<Ref>>(
+    path: &mut Vec<Segment<Ref>>,
+    node_map: &impl NodeMap<Ref, Node>,
+    pred: impl Fn(&Node) -> bool,
+) -> bool {
+    // Search the ancestors from the closest to the root, skipping the current node
+    let ancestor_len = path[..path.len() - 1]
+        .iter()
+        .rposition(|seg| node_map.get_node(seg.node).is_some_and(&pred))
+        .map(|i| i + 1);
+    match ancestor_len {
+        Some(len) => {
+            path.truncate(len);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Move a cursor path from an entry (or a child of an entry) of a record to the corresponding node
+/// in the next record with an entry with the same key.  Here, a 'record' is a child of the
+/// cursor's great-grandparent (e.g. a JSON object in an array), an 'entry' is a child of a record
+/// (e.g. an object field) and the first child of each entry is its key.  Returns `false` and leaves
+/// `path` unchanged if no later record has a matching entry.
+pub(super) fn move_to_same_key_in_next_record<Ref: Reference, Node: ASTSpec<Ref>>(
+    path: &mut Vec<Segment<Ref>>,
+    node_map: &impl NodeMap<Ref, Node>,
+) -> bool {
+    // The path must end with [.., container, record, entry, cursor]
+    if path.len() < 4 {
+        return false;
+    }
+    let len = path.len();
+    let child_index = path[len - 1].sibling_index;
+    let record_index = path[len - 3].sibling_index;
+    let container = path[len - 4].node;
+    let get_key = |entry: Ref| {
+        node_map
+            .get_node(entry)
+            .and_then(|e| e.children().first())
+            .and_then(|k| node_map.get_node(*k))
+    };
+    let key = match get_key(path[len - 2].node) {
+        Some(k) => k,
+        None => return false,
+    };
+    let later_records = match node_map.get_node(container) {
+        Some(c) => c.children().iter().enumerate().skip(record_index + 1),
+        None => return false,
+    };
+    for (new_record_index, record) in later_records {
+        let entries = match node_map.get_node(*record) {
+            Some(r) => r.children(),
+            None => continue,
+        };
+        for (new_entry_index, entry) in entries.iter().enumerate() {
+            if get_key(*entry) != Some(key) {
+                continue;
+            }
+            let new_child = node_map
+                .get_node(*entry)
+                .and_then(|e| e.children().get(child_index));
+            if let Some(new_child) = new_child {
+                path.truncate(len - 3);
+                path.push(Segment::new(*record, new_record_index));
+                path.push(Segment::new(*entry, new_entry_index));
+                path.push(Segment::new(*new_child, child_index));
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Move a cursor path `offset` places along its current sibling list (backwards if `offset` is
+/// negative), stopping at the first or last sibling.  Returns how many places the cursor actually
+/// moved.  The root has no siblings, so a path pointing to the root is never changed.
+pub(super) fn move_by_siblings<Ref: Reference, Node: ASTSpec<Ref>>(
+    path: &mut [Segment<Ref>],
+    node_map: &impl NodeMap<Ref, Node>,
+    offset: isize,
+) -> usize {
+    if path.len() < 2 {
+        return 0;
+    }
+    let parent = path[path.len() - 2].node;
+    let siblings = match node_map.get_node(parent) {
+        Some(n) => n.children(),
+        None => return 0,
+    };
+    if siblings.is_empty() {
+        return 0;
+    }
+    let current_index = path.last().unwrap().sibling_index;
+    let new_index = if offset < 0 {
+        current_index.saturating_sub(offset.unsigned_abs())
+    } else {
+        current_index
+            .saturating_add(offset as usize)
+            .min(siblings.len() - 1)
+    };
+    *path.last_mut().unwrap() = Segment::new(siblings[new_index], new_index);
+    new_index.abs_diff(current_index)
+}
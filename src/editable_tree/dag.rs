@@ -1,8 +1,13 @@
-use super::cursor_path;
+use super::persistent::{Persistent, Sharing};
 use super::EditableTree;
 use crate::ast_spec::ASTSpec;
 use crate::node_map::vec::{Index, VecNodeMap};
-use crate::node_map::{NodeMap, NodeMapMut};
+use crate::node_map::{GcStats, NodeMap, NodeMapMut, Reference};
+use std::collections::HashMap;
+
+// Imports used only for doc comments
+#[allow(unused_imports)]
+use super::spec::Spec;
 
 /// An [`EditableTree`] that stores the history as a DAG (Directed Acyclic Graph) of **immutable**
 /// nodes.
@@ -12,68 +17,389 @@ use crate::node_map::{NodeMap, NodeMapMut};
 /// root becomes the new 'current' root.  This is very similar to the way Git stores the commits,
 /// and every edit is analogous to a Git rebase.
 ///
-/// Therefore, moving back through the history is as simple as reading a different root node from
-/// the `roots` vector, and following its descendants through the DAG of nodes.
+/// The history and the path copying are the same as the [`Spec`]'s (see [`Persistent`]).  The
+/// difference is that a node can appear more than once in the same version:
+/// [`EditableTree::replace_all`] adds its replacement once and refers to it from every place it
+/// replaces, and [`EditableTree::map_nodes`] only replaces each shared node once.  These edits
+/// are tested against the [`Spec`], which never shares nodes and so makes them differently.  For
+/// the same reason, the DAG doesn't keep track of which nodes are copies of which (see
+/// [`EditableTree::copies_since`]).
 ///
-/// This also allows for compression of identical nodes (so that an AST representing
-/// `(1 + 1) * (1 + 1)` would only use 4 nodes: `1`, `1 + 1`, `(1 + 1)`, `(1 + 1) * (1 + 1)`).
-/// This compression has not been implemented yet.
-#[derive(Debug, Clone)]
-pub struct DAG<Node: ASTSpec<Index>> {
-    node_map: VecNodeMap<Node>,
-    undo_history: Vec<Index>,
-    current_path: Vec<cursor_path::Segment<Index>>,
-}
+/// Since nodes are never changed in place, this also allows for compression of identical nodes
+/// (so that an AST representing `(1 + 1) * (1 + 1)` would only use 4 nodes: `1`, `1 + 1`,
+/// `(1 + 1)`, `(1 + 1) * (1 + 1)`).  Storing the nodes in a
+/// [`DedupNodeMap`](crate::node_map::dedup::DedupNodeMap) turns this compression on.
+pub type DAG<Node, M = VecNodeMap<Node>> = Persistent<Node, Index, M, SharedNodes>;
+
+/// The [`Sharing`] of the [`DAG`], where the same node can appear in many places and copies
+/// aren't kept track of
+#[derive(Debug, Clone, Default)]
+pub struct SharedNodes;
+
+impl<Ref: Reference, Node: ASTSpec<Ref>> Sharing<Ref, Node> for SharedNodes {
+    const SHARES_NODES: bool = true;
+
+    fn record_copy(&mut self, _copy: Ref, _original: Ref) {}
+
+    fn copies_between(
+        &self,
+        _node_map: &impl NodeMap<Ref, Node>,
+        _old_root: Ref,
+        _new_root: Ref,
+    ) -> HashMap<Ref, Ref> {
+        HashMap::new()
+    }
+
+    fn remap(&mut self, _stats: &GcStats<Ref>, _node_map: &impl NodeMap<Ref, Node>) {}
+
+    fn clear(&mut self) {}
 
-impl<Node: ASTSpec<Index>> DAG<Node> {
-    /// Makes a `DAG` that contains the tree stored inside `node_map`
-    pub fn from_tree(node_map: VecNodeMap<Node>) -> Self {
-        DAG {
-            undo_history: vec![],
-            current_path: vec![cursor_path::Segment::root(node_map.root())],
-            node_map,
+    /// The whole subtree is mapped in one pass, which copies each changed node (and its
+    /// ancestors) once however many times it appears
+    fn map_nodes_at_path<M: NodeMapMut<Ref, Node>>(
+        tree: &mut Persistent<Node, Ref, M, Self>,
+        path: &[usize],
+        f: impl Fn(&Node) -> Option<Node>,
+    ) -> usize {
+        let start = match tree.ref_at_path(path) {
+            Some(r) => r,
+            None => return 0,
+        };
+        let mut num_changed = 0;
+        let new_start = map_nodes_below(
+            &mut tree.node_map,
+            start,
+            &f,
+            &mut HashMap::new(),
+            &mut num_changed,
+        );
+        if num_changed > 0 {
+            let cursor_path = tree.cursor_path();
+            tree.change_node(path, new_start, &cursor_path);
         }
+        num_changed
     }
 }
 
-impl<Node: ASTSpec<Index>> NodeMap<Index, Node> for DAG<Node> {
-    fn get_node(&self, id: Index) -> Option<&Node> {
-        self.node_map.get_node(id)
+/// Returns the reference to a copy of the subtree at `id` in `node_map` where every node has been
+/// replaced with the result of calling `f` on it (or `id` itself if nothing changed), adding the
+/// number of changed nodes to `num_changed`.  Shared nodes are only replaced once, using
+/// `new_refs` to remember their replacements (and how many nodes changed inside them), but are
+/// counted every time they appear in the tree, just like in the [`Spec`].
+fn map_nodes_below<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &mut impl NodeMapMut<Ref, Node>,
+    id: Ref,
+    f: &impl Fn(&Node) -> Option<Node>,
+    new_refs: &mut HashMap<Ref, (Ref, usize)>,
+    num_changed: &mut usize,
+) -> Ref {
+    if let Some((new_ref, changes)) = new_refs.get(&id) {
+        *num_changed += changes;
+        return *new_ref;
     }
-
-    fn root(&self) -> Index {
-        // We require that current_path.len() >= 1, so we don't have to worry about panics
-        self.current_path[0].node
+    let changed_before = *num_changed;
+    let node = node_map.get_node(id).unwrap().clone();
+    let mut new_node = match f(&node).filter(|n| n != &node) {
+        Some(new_node) => {
+            debug_assert_eq!(new_node.children(), node.children());
+            *num_changed += 1;
+            Some(new_node)
+        }
+        None => None,
+    };
+    for (i, child) in node.children().iter().enumerate() {
+        let new_child = map_nodes_below(node_map, *child, f, new_refs, num_changed);
+        if new_child != *child {
+            new_node.get_or_insert_with(|| node.clone()).children_mut()[i] = new_child;
+        }
     }
+    let new_ref = match new_node {
+        Some(n) => node_map.add_node(n),
+        None => id,
+    };
+    new_refs.insert(id, (new_ref, *num_changed - changed_before));
+    new_ref
 }
 
-impl<Node: ASTSpec<Index>> EditableTree<Index, Node> for DAG<Node> {
-    fn new() -> Self {
-        Self::from_tree(VecNodeMap::with_default_root())
+#[cfg(test)]
+mod tests {
+    use super::DAG;
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::{TestJSON, TestRng};
+    use crate::ast_spec::{subtrees_equal, ASTSpec};
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::{EditableTree, PastePosition};
+    use crate::node_map::dedup::DedupNodeMap;
+    use crate::node_map::subtree::Subtree;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    /// Make the edit numbered `op` to `tree`, returning a description of what the edit returned
+    fn apply_edit<E: EditableTree<Index, JSON<Index>>>(
+        tree: &mut E,
+        op: usize,
+        c: char,
+        n: usize,
+        yanked: &Subtree<JSON<Index>>,
+    ) -> String {
+        match op {
+            0 => format!("{}", tree.move_to_child(n)),
+            1 => format!("{}", tree.move_to_parent()),
+            2 => format!("{}", tree.move_by_siblings(n as isize - 1)),
+            3 => format!("{:?}", tree.insert_child_from_char(c, n)),
+            4 => match tree.cursor_node().from_char(c) {
+                Some(node)
+                    if tree
+                        .check_replacement_at(&tree.cursor_path(), &node)
+                        .is_ok() =>
+                {
+                    tree.replace_cursor(node);
+                    "replaced".to_string()
+                }
+                _ => "not replaced".to_string(),
+            },
+            5 => format!("{:?}", tree.delete_siblings(n)),
+            6 => format!("{:?}", tree.wrap_cursor(c)),
+            7 => format!("{:?}", tree.unwrap_or_splice_cursor()),
+            8 => format!("{:?}", tree.swap_with_sibling(n as isize - 1)),
+            9 => format!("{:?}", tree.paste(yanked, PastePosition::After)),
+            10 => format!(
+                "{}",
+                tree.replace_all(|node| node == &JSON::True, JSON::Null)
+            ),
+            11 => format!("{:?}", tree.merge_with_next_sibling(", ")),
+            12 => format!("{}", tree.map_subtree(JSON::inverted)),
+            13 => format!("{:?}", tree.replace_from_char(c)),
+            14 => format!("{:?}", tree.insert_child_at_path(&[], n, yanked)),
+            15 => format!(
+                "{:?}",
+                tree.remove_child_at_path(&tree.cursor_path(), n).is_ok()
+            ),
+            16 => format!("{}", tree.undo()),
+            17 => {
+                let copies = tree.copy_siblings(n);
+                format!("{:?}", tree.paste_siblings(&copies, PastePosition::Before))
+            }
+            18 => format!("{:?}", tree.insert_sibling_from_char(c, n < 2)),
+            19 => {
+                // Reverse the selected node and its siblings, so that the cursor has to follow
+                let mut parent = tree.cursor_path();
+                parent.pop();
+                let len = tree.node_at_path(&parent).unwrap().children().len();
+                let order: Vec<usize> = (0..len).rev().collect();
+                format!("{:?}", tree.reorder_children(&parent, &order))
+            }
+            20 => format!("{:?}", tree.duplicate_cursor()),
+            21 => {
+                tree.begin_edit();
+                "began".to_string()
+            }
+            22 => format!("{}", tree.commit_edit()),
+            23 => format!("{}", tree.rollback_edit()),
+            24 => {
+                tree.collect_garbage();
+                "collected".to_string()
+            }
+            _ => format!("{}", tree.redo()),
+        }
+    }
+
+    /// Check that a [`DAG`] storing its nodes in an `M` makes the same random edits as the
+    /// [`Spec`]
+    fn check_against_spec<M: NodeMapMut<Index, JSON<Index>>>() {
+        TestJSON::check_property(100, 3, 3, |tree, seed| {
+            let mut spec = Spec::from_tree(tree.build_node_map::<Index, VecNodeMap<_>>());
+            let mut dag = DAG::from_tree(tree.build_node_map::<Index, M>());
+            let mut rng = TestRng::new(seed);
+            let yanked = Subtree::copy_from(&spec, spec.root()).unwrap();
+            for step in 0..60 {
+                let c = *rng.choose(&['t', 'f', 'z', 'a', 'o', 'i', 's', 'n']);
+                let op = rng.below(26);
+                let n = rng.below(4);
+                let spec_result = apply_edit(&mut spec, op, c, n, &yanked);
+                let dag_result = apply_edit(&mut dag, op, c, n, &yanked);
+                let context = format!("step {} (op {}, char {:?}, n {})", step, op, c, n);
+                dag.validate().map_err(|e| format!("{}: {}", context, e))?;
+                if dag_result != spec_result
+                    || dag.to_text(&JSONFormat::Compact) != spec.to_text(&JSONFormat::Compact)
+                    || dag.cursor_path() != spec.cursor_path()
+                    || dag.history_position() != spec.history_position()
+                {
+                    return Err(format!(
+                        "{}: the DAG gave {} {:?} at {:?}, but the spec gave {} {:?} at {:?}",
+                        context,
+                        dag_result,
+                        dag.to_text(&JSONFormat::Compact),
+                        dag.cursor_path(),
+                        spec_result,
+                        spec.to_text(&JSONFormat::Compact),
+                        spec.cursor_path()
+                    ));
+                }
+            }
+            Ok(())
+        });
     }
 
-    fn undo(&mut self) -> bool {
-        unimplemented!();
+    #[test]
+    fn behaves_like_spec() {
+        check_against_spec::<VecNodeMap<_>>();
     }
 
-    fn redo(&mut self) -> bool {
-        unimplemented!();
+    #[test]
+    fn deduplicated_behaves_like_spec() {
+        // Identical subtrees are shared, which path copying never has to know about
+        check_against_spec::<DedupNodeMap<_>>();
     }
 
-    fn cursor(&self) -> Index {
-        // We require that `self.current_path.len() >= 1, so we can unwrap without fearing panics
-        self.current_path.last().unwrap().node
+    #[test]
+    fn garbage_collection_keeps_every_version() {
+        TestJSON::check_property(100, 3, 3, |tree, seed| {
+            let mut dag: DAG<JSON<Index>> = DAG::from_tree(tree.build_node_map());
+            let mut rng = TestRng::new(seed);
+            let yanked = Subtree::copy_from(&dag, dag.root()).unwrap();
+            for step in 0..60 {
+                let c = *rng.choose(&['t', 'f', 'z', 'a', 'o', 'i', 's', 'n']);
+                let (op, n) = (rng.below(22), rng.below(4));
+                apply_edit(&mut dag, op, c, n, &yanked);
+                if rng.below(10) > 0 {
+                    continue;
+                }
+                let before = dag.clone();
+                let stats = dag.collect_garbage();
+                let context = format!("step {}", step);
+                dag.validate().map_err(|e| format!("{}: {}", context, e))?;
+                if stats.freed + stats.kept != before.stored_node_count()
+                    || stats.kept != dag.stored_node_count()
+                {
+                    return Err(format!(
+                        "{}: GC gave the wrong counts: {:?}",
+                        context, stats
+                    ));
+                }
+                // Every reference in the history now resolves to a node that is structurally
+                // identical to the node it resolved to before
+                for (old_path, new_path) in before.history.iter().zip(&dag.history) {
+                    for (old, new) in old_path.iter().zip(new_path) {
+                        if stats.remap(old.node) != new.node
+                            || old.sibling_index != new.sibling_index
+                            || !subtrees_equal(&before.node_map, old.node, &dag.node_map, new.node)
+                        {
+                            return Err(format!(
+                                "{}: {:?} was moved to {:?}, which is a different subtree",
+                                context, old.node, new.node
+                            ));
+                        }
+                    }
+                }
+                if dag.current_version != before.current_version
+                    || dag.to_text(&JSONFormat::Compact) != before.to_text(&JSONFormat::Compact)
+                {
+                    return Err(format!("{}: GC changed the current version", context));
+                }
+            }
+            Ok(())
+        });
     }
 
-    fn replace_cursor(&mut self, new_node: Node) {
-        self.node_map.add_as_root(new_node);
+    #[test]
+    fn garbage_collection_frees_abandoned_versions() {
+        let mut dag: DAG<JSON<Index>> =
+            DAG::from_tree(TestJSON::Array(vec![TestJSON::True; 3]).build_node_map());
+        // The placeholder root that the map was built around is garbage
+        assert_eq!(dag.collect_garbage().freed, 1);
+        assert_eq!(dag.collect_garbage().freed, 0);
+        // Undone edits are kept for redoing, until a new edit abandons them
+        assert!(dag.move_to_child(1));
+        dag.replace_cursor(JSON::False);
+        assert!(dag.undo());
+        assert_eq!(dag.collect_garbage().freed, 0);
+        dag.replace_cursor(JSON::Null);
+        let stats = dag.collect_garbage();
+        assert_eq!((stats.freed, stats.kept), (2, 6));
+        assert_eq!(dag.to_text(&JSONFormat::Compact), "[true, null, true]");
+        assert!(dag.undo());
+        assert_eq!(dag.to_text(&JSONFormat::Compact), "[true, true, true]");
+        assert!(dag.redo());
+        assert_eq!(dag.to_text(&JSONFormat::Compact), "[true, null, true]");
     }
 
-    fn insert_child(&mut self, _new_node: Node) {
-        unimplemented!();
+    #[test]
+    fn stats_count_old_versions_as_unreachable() {
+        let mut dag: DAG<JSON<Index>> = DAG::from_tree(
+            TestJSON::Array(vec![TestJSON::True, TestJSON::Array(vec![TestJSON::Null])])
+                .build_node_map(),
+        );
+        // The placeholder root that the map was built around is unreachable
+        let stats = dag.stats();
+        assert_eq!(
+            (stats.nodes, stats.max_depth, stats.unreachable_nodes),
+            (4, 2, 1)
+        );
+        // Every replacement copies the leaf and the root, and the old copies become garbage
+        assert!(dag.move_to_child(0));
+        for i in 1..=5 {
+            dag.replace_cursor(if i % 2 == 0 { JSON::True } else { JSON::False });
+            let stats = dag.stats();
+            assert_eq!(
+                (stats.nodes, stats.max_depth, stats.unreachable_nodes),
+                (4, 2, 1 + 2 * i)
+            );
+        }
+        assert_eq!(dag.stored_node_count(), 15);
+        // Nodes of newer versions are garbage too, and undoing doesn't free anything
+        assert!(dag.undo());
+        assert!(dag.undo());
+        assert_eq!(dag.stats().unreachable_nodes, 11);
+        assert_eq!(
+            dag.stats().kinds.into_iter().collect::<Vec<_>>(),
+            vec![("array", 2), ("bool", 1), ("null", 1)]
+        );
     }
 
-    fn write_text(&self, string: &mut String, format: &Node::FormatStyle) {
-        self.node_map.write_text(string, format);
+    #[test]
+    fn versions_share_nodes() {
+        // Ten levels of arrays, each containing a leaf and then the next level: 21 nodes in total
+        let mut tree = TestJSON::Array(vec![]);
+        for _ in 0..10 {
+            tree = TestJSON::Array(vec![TestJSON::True, tree]);
+        }
+        let mut dag: DAG<JSON<Index>> = DAG::from_tree(tree.build_node_map());
+        let node_count = |dag: &DAG<JSON<Index>>| dag.node_map.iter_all().count();
+        let initial_count = node_count(&dag);
+        assert_eq!(dag.preorder().count(), 21);
+
+        // Replace every leaf (from the top of the tree down) with `false`
+        let mut texts = vec![dag.to_text(&JSONFormat::Compact)];
+        for depth in 0..10 {
+            let mut path = vec![1; depth];
+            path.push(0);
+            assert!(dag.move_to_path(&path));
+            let nodes_before = node_count(&dag);
+            dag.replace_cursor(JSON::False);
+            // The edited node and all of its ancestors are copied, and nothing else
+            assert_eq!(node_count(&dag) - nodes_before, depth + 2);
+            assert_eq!(dag.cursor_path(), path);
+            texts.push(dag.to_text(&JSONFormat::Compact));
+        }
+        assert_eq!(node_count(&dag), initial_count + (2..12).sum::<usize>());
+        assert_eq!(
+            texts.last().unwrap(),
+            "[false, [false, [false, [false, [false, [false, [false, [false, [false, [false, []]]]]]]]]]]"
+        );
+
+        // Every version is still intact
+        for text in texts.iter().rev().skip(1) {
+            assert!(dag.undo());
+            assert_eq!(&dag.to_text(&JSONFormat::Compact), text);
+        }
+        assert!(!dag.undo());
+        for text in texts.iter().skip(1) {
+            assert!(dag.redo());
+            assert_eq!(&dag.to_text(&JSONFormat::Compact), text);
+        }
+        assert!(!dag.redo());
+        // Undoing and redoing doesn't make any new nodes
+        assert_eq!(node_count(&dag), initial_count + (2..12).sum::<usize>());
     }
 }
@@ -2,10 +2,270 @@
 
 pub mod cursor_path;
 pub mod dag;
+pub mod persistent;
 pub mod spec;
+mod transaction;
 
-use crate::ast_spec::ASTSpec;
-use crate::node_map::{NodeMap, Reference};
+use crate::ast_spec::{check_tree, ASTSpec, InvalidTreeError};
+use crate::node_map::diff::Change;
+use crate::node_map::subtree::Subtree;
+use crate::node_map::{GcStats, MemoryStats, NodeMap, Reference};
+
+// Imports used solely for doc-comments
+#[allow(unused_imports)]
+use crate::node_map::NodeMapMut;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+/// Where [`EditableTree::paste`] should add a node, relative to the selected node
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PastePosition {
+    /// As the sibling immediately before the selected node
+    Before,
+    /// As the sibling immediately after the selected node
+    After,
+    /// As the last child of the selected node
+    Child,
+}
+
+/// The reasons why the edits of an [`EditableTree`] can fail.  A failed edit always leaves the
+/// tree and the cursor unchanged.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EditError {
+    /// The edit needs the selected node to have a parent (and siblings), but the root is selected
+    IsRoot,
+    /// The selected node has no sibling `offset` places after it (or before it, if `offset` is
+    /// negative)
+    NoSibling { offset: isize },
+    /// There is no node at this path
+    NoNode(Vec<usize>),
+    /// The parent has no child at `index`, because it only has `len` children
+    IndexOutOfRange { index: usize, len: usize },
+    /// Nodes of this kind can't have children inserted (e.g. JSON `true`)
+    CannotInsert(&'static str),
+    /// Nodes of this kind can't have children removed (e.g. JSON object fields)
+    CannotRemove(&'static str),
+    /// Nodes of this kind can't have their children reordered (e.g. JSON object fields)
+    CannotReorder(&'static str),
+    /// The new order of a node's children isn't a permutation of its `len` child indices
+    NotAPermutation { len: usize },
+    /// The parent can't contain the new child, as explained by the reason (from
+    /// [`ASTSpec::check_replacement`])
+    InvalidChild(String),
+    /// The char doesn't stand for a node that can be a child of this kind of node (see
+    /// [`ASTSpec::child_from_char`] and [`ASTSpec::sibling_from_char`])
+    NoNodeForChar { parent: &'static str, c: char },
+    /// Nodes of this kind can't be replaced by the node given by the char (see
+    /// [`ASTSpec::replace_chars`])
+    CannotReplace { kind: &'static str, c: char },
+    /// Nodes of this kind can't be wrapped in the node given by the char (see
+    /// [`ASTSpec::wrap_from_char`])
+    CannotWrap { kind: &'static str, c: char },
+    /// Nodes of this kind don't [wrap exactly one child](ASTSpec::wrapped_child)
+    CannotUnwrap(&'static str),
+    /// This node has no children to [splice](EditableTree::splice_cursor) into its parent
+    NothingToSplice(&'static str),
+    /// Nodes of this kind can't have one child replaced by several (e.g. JSON object fields)
+    CannotSplice(&'static str),
+    /// A node of kind `first` can't be [merged](ASTSpec::merge_with) with one of kind `second`
+    CannotMerge {
+        first: &'static str,
+        second: &'static str,
+    },
+    /// There are no subtrees to paste
+    NothingToPaste,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::IsRoot => write!(f, "the root has no siblings"),
+            EditError::NoSibling { offset: 1 } => write!(f, "there is no next sibling"),
+            EditError::NoSibling { offset: -1 } => write!(f, "there is no previous sibling"),
+            EditError::NoSibling { offset } if *offset < 0 => write!(
+                f,
+                "there is no sibling {} places before this node",
+                offset.unsigned_abs()
+            ),
+            EditError::NoSibling { offset } => {
+                write!(f, "there is no sibling {} places after this node", offset)
+            }
+            EditError::NoNode(path) => write!(f, "there is no node at {:?}", path),
+            EditError::IndexOutOfRange { index, len } => write!(
+                f,
+                "there is no child {} of a node with {} children",
+                index, len
+            ),
+            EditError::CannotInsert(kind) => {
+                write!(f, "{} nodes can't have children inserted", kind)
+            }
+            EditError::CannotRemove(kind) => {
+                write!(f, "{} nodes can't have children removed", kind)
+            }
+            EditError::CannotReorder(kind) => {
+                write!(f, "{} nodes can't have their children reordered", kind)
+            }
+            EditError::NotAPermutation { len } => {
+                write!(
+                    f,
+                    "the new order doesn't contain each of the {} children once",
+                    len
+                )
+            }
+            EditError::InvalidChild(reason) => write!(f, "{}", reason),
+            EditError::NoNodeForChar { parent, c } => {
+                write!(f, "'{}' isn't a node that {} nodes can contain", c, parent)
+            }
+            EditError::CannotReplace { kind, c } => {
+                write!(f, "{} nodes can't be replaced by '{}'", kind, c)
+            }
+            EditError::CannotWrap { kind, c } => {
+                write!(f, "{} nodes can't be wrapped with '{}'", kind, c)
+            }
+            EditError::CannotUnwrap(kind) => {
+                write!(f, "{} nodes don't wrap exactly one child", kind)
+            }
+            EditError::NothingToSplice(kind) => {
+                write!(f, "this {} has no children to splice", kind)
+            }
+            EditError::CannotSplice(kind) => {
+                write!(f, "{} nodes can't have children spliced into them", kind)
+            }
+            EditError::CannotMerge { first, second } => {
+                write!(f, "{} nodes can't be merged with {} nodes", first, second)
+            }
+            EditError::NothingToPaste => write!(f, "there is nothing to paste"),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+/// Returns a copy of `node` with its children [reordered](ASTSpec::reorder_children) by `order`,
+/// or why they can't be
+fn reordered<Ref: Reference, Node: ASTSpec<Ref>>(
+    node: &Node,
+    order: &[usize],
+) -> Result<Node, EditError> {
+    let mut new_node = node.clone();
+    if new_node.reorder_children(order) {
+        return Ok(new_node);
+    }
+    let len = node.children().len();
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    if sorted != (0..len).collect::<Vec<_>>() {
+        Err(EditError::NotAPermutation { len })
+    } else {
+        Err(EditError::CannotReorder(node.kind_name()))
+    }
+}
+
+/// Returns a copy of `parent` where its `index`th child is replaced by `children` (the children
+/// of the child being [spliced](EditableTree::splice_cursor)), or why that isn't possible
+fn spliced<Ref: Reference, Node: ASTSpec<Ref>>(
+    parent: &Node,
+    index: usize,
+    children: &[Ref],
+    node_map: &impl NodeMap<Ref, Node>,
+) -> Result<Node, EditError> {
+    let mut new_parent = parent.clone();
+    if !new_parent.remove_child(index) {
+        return Err(EditError::CannotSplice(parent.kind_name()));
+    }
+    for (i, child) in children.iter().enumerate() {
+        if let Some(child_node) = node_map.get_node(*child) {
+            parent
+                .check_replacement(index + i, child_node)
+                .map_err(EditError::InvalidChild)?;
+        }
+        if !new_parent.insert_child(*child, index + i) {
+            return Err(EditError::CannotSplice(parent.kind_name()));
+        }
+    }
+    Ok(new_parent)
+}
+
+/// Returns the index of the child of `parent` that is `offset` places after (or before, if
+/// `offset` is negative) the child at `index`, or [`EditError::NoSibling`] if there isn't one.  A
+/// node doesn't count as its own sibling, so an `offset` of zero is always an error.
+fn sibling_at_offset<Ref: Reference, Node: ASTSpec<Ref>>(
+    index: usize,
+    offset: isize,
+    parent: &Node,
+) -> Result<usize, EditError> {
+    (index as isize)
+        .checked_add(offset)
+        .filter(|&i| offset != 0 && i >= 0 && (i as usize) < parent.children().len())
+        .map(|i| i as usize)
+        .ok_or(EditError::NoSibling { offset })
+}
+
+/// Returns why no child could be made from `c` for `parent`: either `parent` can't have children
+/// at all, or `c` doesn't stand for a node that it can contain.  `any_ref` can be any reference.
+fn no_child_from_char<Ref: Reference, Node: ASTSpec<Ref>>(
+    parent: &Node,
+    c: char,
+    any_ref: Ref,
+) -> EditError {
+    // Only the parent's arity matters here, so any reference will do for the new child
+    if parent.clone().insert_child(any_ref, 0) {
+        EditError::NoNodeForChar {
+            parent: parent.kind_name(),
+            c,
+        }
+    } else {
+        EditError::CannotInsert(parent.kind_name())
+    }
+}
+
+/// Returns `cursor_path` (a path of child indices) adjusted for the children of the node at
+/// `parent` being [reordered](ASTSpec::reorder_children) by `order`, so that it still leads to
+/// the same node
+fn reordered_path(cursor_path: &[usize], parent: &[usize], order: &[usize]) -> Vec<usize> {
+    let mut new_path = cursor_path.to_vec();
+    if cursor_path.len() > parent.len() && cursor_path.starts_with(parent) {
+        let child = &mut new_path[parent.len()];
+        *child = order.iter().position(|i| i == child).unwrap();
+    }
+    new_path
+}
+
+/// The size and shape of an [`EditableTree`], as returned by [`EditableTree::stats`]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TreeStats {
+    /// The number of nodes in the current tree.  A node that is reachable along multiple paths
+    /// from the root is counted once per path.
+    pub nodes: usize,
+    /// The length of the longest path from the root to a node, so a tree that is just a root has
+    /// depth 0
+    pub max_depth: usize,
+    /// How many nodes of each kind (as given by [`ASTSpec::kind_name`]) are in the current tree,
+    /// sorted by kind name
+    pub kinds: BTreeMap<&'static str, usize>,
+    /// The number of stored nodes that aren't in the current tree, such as the nodes replaced by
+    /// past edits
+    pub unreachable_nodes: usize,
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, depth {}, {} unreachable",
+            self.nodes, self.max_depth, self.unreachable_nodes
+        )?;
+        for (i, (kind_name, count)) in self.kinds.iter().enumerate() {
+            let separator = if i == 0 { " (" } else { ", " };
+            write!(f, "{}{}: {}", separator, kind_name, count)?;
+        }
+        if !self.kinds.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
 
 /// A trait specifying an editable, undoable buffer of trees
 pub trait EditableTree<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> + Sized {
@@ -14,6 +274,40 @@ pub trait EditableTree<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> +
     /// Build a new `EditableTree` with the default AST of the given type
     fn new() -> Self;
 
+    /// Check that the current tree is structurally sound (see [`check_tree`])
+    fn validate(&self) -> Result<(), InvalidTreeError<Ref>> {
+        check_tree(self)
+    }
+
+    /// Panic with a description of the problem if the current tree isn't
+    /// [valid](EditableTree::validate), or if the cursor's path doesn't lead to the cursor.
+    /// Implementations call this after every change in debug builds, so that editing bugs are
+    /// caught as soon as they break the tree.  This does nothing in release builds.
+    fn debug_check_invariants(&self) {
+        #[cfg(debug_assertions)]
+        {
+            if let Err(error) = self.validate() {
+                let invariant = match error {
+                    InvalidTreeError::MissingNode { .. } => "every reference points to a node",
+                    InvalidTreeError::Cycle(_) => "the tree is acyclic",
+                    InvalidTreeError::InvalidNode { .. } => "every node is valid",
+                };
+                panic!("Broken tree invariant ({}): {}", invariant, error);
+            }
+            let cursor_path = self.cursor_path();
+            let node_at_cursor_path = self.ref_at_path(&cursor_path);
+            if node_at_cursor_path != Some(self.cursor()) {
+                panic!(
+                    "Broken tree invariant (the cursor is in the tree): the cursor is {:?}, but \
+                     its path {:?} leads to {:?}",
+                    self.cursor(),
+                    cursor_path,
+                    node_at_cursor_path
+                );
+            }
+        }
+    }
+
     /* HISTORY METHODS */
 
     /// Move one step back in the tree history, returning `false` if there are no more changes
@@ -23,6 +317,62 @@ pub trait EditableTree<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> +
     /// redone
     fn redo(&mut self) -> bool;
 
+    /// Returns the position of the current version of the tree in its history, where the oldest
+    /// version is at position 0.  This is the number of times that [`undo`](Self::undo) would
+    /// succeed.
+    fn history_position(&self) -> usize;
+
+    /// Compare the version of the tree at `position` in its history with the current version,
+    /// returning the changes that turn the old version into the current one (see
+    /// [`diff`](crate::node_map::diff::diff)).  Returns [`None`] if there is no version at
+    /// `position`.
+    fn changes_since(&self, position: usize) -> Option<Vec<Change>>;
+
+    /// Returns the nodes of the version of the tree at `position` in its history that have been
+    /// copied into the current version rather than changed in place, mapped to the references of
+    /// their copies.  Trees that keep every version (like [`Spec`](spec::Spec)) copy the
+    /// ancestors of each node they change, and anything that refers to those ancestors (e.g. a
+    /// fold) should follow them to their copies.  By default, nothing is copied.
+    fn copies_since(&self, _position: usize) -> HashMap<Ref, Ref> {
+        HashMap::new()
+    }
+
+    /* TRANSACTION METHODS */
+
+    /// Start a transaction.  Every change made until the transaction is
+    /// [committed](EditableTree::commit_edit) becomes a single version in the undo history, so
+    /// that an edit made of several changes is undone in one step.  Transactions can be nested,
+    /// in which case the inner ones are flattened into the outermost one: only committing the
+    /// outermost transaction adds a version to the history, but each transaction can be
+    /// [rolled back](EditableTree::rollback_edit) on its own.  [`undo`](Self::undo) and
+    /// [`redo`](Self::redo) do nothing while a transaction is open.
+    fn begin_edit(&mut self);
+
+    /// Finish the innermost open transaction, returning `false` if there isn't one
+    fn commit_edit(&mut self) -> bool;
+
+    /// Throw away every change made since the innermost open transaction began and close it, so
+    /// that the tree, the cursor and the undo history (including the versions that could be
+    /// redone) are exactly as they were when it began.  Returns `false` if there isn't an open
+    /// transaction.
+    fn rollback_edit(&mut self) -> bool;
+
+    /// Make the changes of `edit` in a transaction, which is committed if `edit` succeeds and
+    /// rolled back if it fails
+    fn with_edit<T>(
+        &mut self,
+        edit: impl FnOnce(&mut Self) -> Result<T, EditError>,
+    ) -> Result<T, EditError> {
+        self.begin_edit();
+        let result = edit(self);
+        if result.is_ok() {
+            self.commit_edit();
+        } else {
+            self.rollback_edit();
+        }
+        result
+    }
+
     /* NAVIGATION METHODS */
 
     /// Returns a reference to the node that is currently under the cursor.  This reference must
@@ -36,15 +386,586 @@ pub trait EditableTree<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> +
         self.get_node(self.cursor()).unwrap()
     }
 
+    /// Returns the path from the root to the selected node, as the index of each node within its
+    /// siblings.  The path of the root is empty.
+    fn cursor_path(&self) -> Vec<usize>;
+
+    /// Returns the index of the selected node within its parent's children, or [`None`] if the
+    /// root is selected.  This is the last element of
+    /// [`cursor_path`](EditableTree::cursor_path), but doesn't need the whole path to be built.
+    fn sibling_index(&self) -> Option<usize> {
+        self.cursor_path().last().copied()
+    }
+
+    /// Move the cursor to the node at the end of `path` (as returned by
+    /// [`cursor_path`](EditableTree::cursor_path)).  Returns `false` (and leaves the cursor where
+    /// it is) if there is no node at `path`.
+    fn move_to_path(&mut self, path: &[usize]) -> bool {
+        // Check that the path is valid before moving the cursor
+        let mut id = self.root();
+        for index in path {
+            match self.get_node(id).and_then(|n| n.children().get(*index)) {
+                Some(child) => id = *child,
+                None => return false,
+            }
+        }
+        self.move_to_root();
+        for index in path {
+            self.move_to_child(*index);
+        }
+        true
+    }
+
+    /// Move the cursor to the `index`th child of the selected node.  Returns `false` (and leaves
+    /// the cursor where it is) if the selected node doesn't have that many children.
+    fn move_to_child(&mut self, index: usize) -> bool;
+
+    /// Move the cursor to the parent of the selected node.  Returns `false` (and leaves the cursor
+    /// where it is) if the root is selected.
+    fn move_to_parent(&mut self) -> bool;
+
+    /// Move the cursor to the nearest ancestor of the selected node for which `pred` returns
+    /// `true`.  Returns `false` (and leaves the cursor where it is) if no ancestor matches.
+    fn move_to_ancestor(&mut self, pred: impl Fn(&Node) -> bool) -> bool;
+
+    /// Move the cursor to the root of the tree, so that the whole document is selected.  Returns
+    /// `false` if the root was already selected.
+    fn move_to_root(&mut self) -> bool {
+        let mut has_moved = false;
+        while self.move_to_parent() {
+            has_moved = true;
+        }
+        has_moved
+    }
+
+    /// Move the cursor `offset` siblings forward (or backward if `offset` is negative), stopping
+    /// at the first or last sibling.  Returns the number of siblings that the cursor moved past.
+    fn move_by_siblings(&mut self, offset: isize) -> usize;
+
+    /// Move the cursor to the sibling at `index` within its parent's children, or to the last
+    /// sibling if there aren't that many.  Returns `false` if the cursor didn't move (including
+    /// when the root is selected).
+    fn move_to_sibling(&mut self, index: usize) -> bool {
+        match self.sibling_index() {
+            // Sibling indices are far smaller than `isize::MAX`, so this can't overflow
+            Some(current) => {
+                let target = index.min(isize::MAX as usize) as isize;
+                self.move_by_siblings(target - current as isize) > 0
+            }
+            None => false,
+        }
+    }
+
+    /// Move the cursor from a child of a record entry (e.g. the value of a JSON object field) to
+    /// the same child of the entry with the same key in the next sibling record that has one.  The
+    /// first child of each entry is treated as its key.  Returns `false` (and leaves the cursor
+    /// where it is) if there is no such entry.
+    fn move_to_same_key_in_next_record(&mut self) -> bool;
+
+    /// Move the cursor to the key of the selected record entry (see [`ASTSpec::key_index`]), or
+    /// to the key of the entry that contains the selected node.  Returns `false` (and leaves the
+    /// cursor where it is) if neither node is a record entry.
+    fn move_to_key(&mut self) -> bool {
+        if let Some(key_index) = self.cursor_node().key_index() {
+            return self.move_to_child(key_index);
+        }
+        let index = match self.cursor_path().last() {
+            Some(index) => *index,
+            None => return false,
+        };
+        self.move_to_parent();
+        match self.cursor_node().key_index() {
+            Some(key_index) => self.move_to_child(key_index),
+            None => {
+                self.move_to_child(index);
+                false
+            }
+        }
+    }
+
+    /// Returns the reference to the node at the end of `path` (as returned by
+    /// [`cursor_path`](EditableTree::cursor_path)), or [`None`] if there is no node there (e.g.
+    /// because the path goes past the end of a list of children, or through a leaf).  This takes
+    /// time proportional to the length of the path.  Paths don't depend on how the nodes are
+    /// stored, so they are the way to name a node across edits, undo/redo and different trees.
+    fn ref_at_path(&self, path: &[usize]) -> Option<Ref> {
+        let mut id = self.root();
+        for index in path {
+            id = *self.get_node(id)?.children().get(*index)?;
+        }
+        self.get_node(id).map(|_| id)
+    }
+
+    /// Returns the node at the end of `path` (as returned by
+    /// [`cursor_path`](EditableTree::cursor_path)), or [`None`] if there is no node there
+    fn node_at_path(&self, path: &[usize]) -> Option<&Node> {
+        self.get_node(self.ref_at_path(path)?)
+    }
+
     /* EDIT METHODS */
 
+    /// Returns why the node at the end of `path` (as returned by
+    /// [`cursor_path`](EditableTree::cursor_path)) can't be replaced by `new_node`, according to
+    /// its parent's [`ASTSpec::check_replacement`].  The root can be replaced by anything, and
+    /// paths that don't lead to a node are reported as an error.
+    fn check_replacement_at(&self, path: &[usize], new_node: &Node) -> Result<(), String> {
+        let (last_index, parent_path) = match path.split_last() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let parent = self
+            .node_at_path(parent_path)
+            .ok_or_else(|| "there is no node there".to_string())?;
+        parent.check_replacement(*last_index, new_node)
+    }
+
     /// Updates the internal state so that the tree now contains `new_node` in the position of the
     /// `cursor`.
     fn replace_cursor(&mut self, new_node: Node);
 
+    /// Replace the node under the cursor with `new_node`, which takes over the children of the
+    /// replaced node if it can hold them (see [`ASTSpec::with_children_of`]).  Otherwise, the
+    /// children are removed along with the replaced node.  This is a single undoable change, and
+    /// the cursor stays on the new node.  The tree is only changed once the new node has been
+    /// built, so the replaced node's parent never refers to a half-built node.
+    fn replace_node(&mut self, new_node: Node);
+
+    /// Like [`replace_node`](EditableTree::replace_node), but the new node is generated by
+    /// [`ASTSpec::from_char_with_children`] so that it starts with any children that it needs to
+    /// be valid.  Returns [`EditError::CannotReplace`] if `c` isn't one of the selected node's
+    /// [`replace_chars`](ASTSpec::replace_chars), or [`EditError::InvalidChild`] if the selected
+    /// node's parent can't contain the new node (see
+    /// [`check_replacement_at`](EditableTree::check_replacement_at)).  Either way, the tree is
+    /// left unchanged.
+    fn replace_from_char(&mut self, c: char) -> Result<(), EditError>;
+
+    /// Updates the internal state so that the tree now contains `new_node` inserted as the
+    /// `index`th child of the selected node (or the last child, if `index` is too large).  Also
+    /// moves the cursor so that the new node is selected.  Returns
+    /// [`EditError::CannotInsert`] (and leaves the tree unchanged) if the selected node can't
+    /// have children inserted.
+    fn insert_child_at(&mut self, new_node: Node, index: usize) -> Result<(), EditError>;
+
     /// Updates the internal state so that the tree now contains `new_node` inserted as the first
     /// child of the selected node.  Also moves the cursor so that the new node is selected.
-    fn insert_child(&mut self, new_node: Node);
+    /// Returns [`EditError::CannotInsert`] (and leaves the tree unchanged) if the selected node
+    /// can't have children inserted.
+    fn insert_child(&mut self, new_node: Node) -> Result<(), EditError> {
+        self.insert_child_at(new_node, 0)
+    }
+
+    /// Like [`insert_child_at`](EditableTree::insert_child_at), but the new child is generated by
+    /// [`ASTSpec::child_from_char`] so that it can have children of its own.  Returns
+    /// [`EditError::NoNodeForChar`] (and leaves the tree unchanged) if the selected node can't
+    /// have a child of the kind given by `c`.
+    fn insert_child_from_char(&mut self, c: char, index: usize) -> Result<(), EditError>;
+
+    /// Insert a new node (generated by [`ASTSpec::sibling_from_char`]) as the next sibling of the
+    /// selected node, or as the previous sibling if `after` is `false`, as a single undoable
+    /// change, and move the cursor to it.  Fails (leaving the tree unchanged) if the root is
+    /// selected, or its parent can't have a child of the kind given by `c`.
+    fn insert_sibling_from_char(&mut self, c: char, after: bool) -> Result<(), EditError>;
+
+    /// Remove the selected node (and its whole subtree) from its parent, as a single undoable
+    /// change.  The cursor moves to the next sibling, or the previous sibling if the removed node
+    /// was the last child, or the parent if the removed node was an only child.  Deleting the root
+    /// replaces it with the default node.  Returns [`EditError::CannotRemove`] (and leaves the
+    /// tree unchanged) if the parent can't have that child removed (e.g. the key of a JSON object
+    /// field).
+    fn delete_cursor(&mut self) -> Result<(), EditError> {
+        self.delete_siblings(1).map(|_| ())
+    }
+
+    /// Add a copy of `subtree` to the tree at a given [`PastePosition`] relative to the selected
+    /// node, as a single undoable change, and move the cursor to the new copy.  Fails (leaving the
+    /// tree unchanged) if the new node can't be added there (e.g. pasting a sibling of the root).
+    fn paste(&mut self, subtree: &Subtree<Node>, position: PastePosition) -> Result<(), EditError> {
+        self.paste_siblings(std::slice::from_ref(subtree), position)
+    }
+
+    /// Like [`paste`](EditableTree::paste), but adds copies of every subtree in `subtrees` as
+    /// consecutive siblings (in order), as one undoable change.  The cursor moves to the first new
+    /// copy.  Fails (leaving the tree unchanged) if `subtrees` is empty or any of them can't be
+    /// added there.
+    fn paste_siblings(
+        &mut self,
+        subtrees: &[Subtree<Node>],
+        position: PastePosition,
+    ) -> Result<(), EditError>;
+
+    /// Returns copies of the subtrees of the selected node and up to `count - 1` of the siblings
+    /// after it, in order.  These are the nodes that
+    /// [`delete_siblings`](EditableTree::delete_siblings) would delete, so the root is only ever
+    /// copied on its own.
+    fn copy_siblings(&self, count: usize) -> Vec<Subtree<Node>> {
+        let mut path = self.cursor_path();
+        let refs = match path.pop() {
+            Some(index) => {
+                let parent = self.node_at_path(&path).unwrap();
+                let end = index.saturating_add(count).min(parent.children().len());
+                parent.children()[index..end].to_vec()
+            }
+            None => vec![self.root()].into_iter().take(count).collect(),
+        };
+        refs.into_iter()
+            .filter_map(|id| Subtree::copy_from(self, id))
+            .collect()
+    }
+
+    /// Insert a deep copy of the selected node as its next sibling, as a single undoable change,
+    /// and move the cursor to the copy.  If the selected node is an entry of a record (e.g. a JSON
+    /// object field), the copy's key gets a `_copy` suffix (numbered if that key is also taken) so
+    /// that the record doesn't silently end up with a duplicate key.  Returns
+    /// [`EditError::IsRoot`] if the selected node is the root, which can't have siblings.
+    fn duplicate_cursor(&mut self) -> Result<(), EditError> {
+        let mut path = self.cursor_path();
+        if path.pop().is_none() {
+            return Err(EditError::IsRoot);
+        }
+        let mut copy = Subtree::copy_from(self, self.cursor())
+            .ok_or_else(|| EditError::NoNode(self.cursor_path()))?;
+        let taken_keys: Vec<String> = self
+            .node_at_path(&path)
+            .and_then(|parent| parent.record_entries(self))
+            .map(|entries| entries.into_iter().map(|(key, _)| key).collect())
+            .unwrap_or_default();
+        if let Some(key_index) = copy.node().key_index() {
+            if let Some(key) = copy.child_mut(key_index) {
+                let new_key = key.node().literal().and_then(|text| {
+                    let base = format!("{}_copy", text);
+                    let new_text = std::iter::once(base.clone())
+                        .chain((2..).map(|n| format!("{}{}", base, n)))
+                        .find(|k| !taken_keys.contains(k))
+                        .unwrap();
+                    key.node().with_literal(&new_text)
+                });
+                if let Some(new_key) = new_key {
+                    key.set_node(new_key);
+                }
+            }
+        }
+        self.paste(&copy, PastePosition::After)
+    }
+
+    /// Insert a copy of `subtree` as the `index`th child of the node at the end of `parent` (a
+    /// path as returned by [`cursor_path`](EditableTree::cursor_path)), as a single undoable
+    /// change.  If `index` is past the end of the children, the subtree is added as the last
+    /// child.  The cursor moves to the new child.  Returns why the subtree couldn't be inserted
+    /// (leaving the tree and the cursor unchanged) if there is no node at `parent`, if that node
+    /// can't have children, or if it can't have this child.
+    fn insert_child_at_path(
+        &mut self,
+        parent: &[usize],
+        index: usize,
+        subtree: &Subtree<Node>,
+    ) -> Result<(), EditError> {
+        let parent_node = self
+            .node_at_path(parent)
+            .ok_or_else(|| EditError::NoNode(parent.to_vec()))?;
+        let len = parent_node.children().len();
+        let index = index.min(len);
+        // Only the parent's arity matters here, so any reference will do for the new child
+        if !parent_node.clone().insert_child(self.root(), index) {
+            return Err(EditError::CannotInsert(parent_node.kind_name()));
+        }
+        parent_node
+            .check_replacement(index, subtree.node())
+            .map_err(EditError::InvalidChild)?;
+        // Pasting next to an existing child puts the subtree in exactly the right place
+        let mut path = parent.to_vec();
+        let position = if len == 0 {
+            PastePosition::Child
+        } else if index < len {
+            path.push(index);
+            PastePosition::Before
+        } else {
+            path.push(len - 1);
+            PastePosition::After
+        };
+        let is_moved = self.move_to_path(&path);
+        let result = self.paste(subtree, position);
+        debug_assert!(is_moved && result.is_ok());
+        Ok(())
+    }
+
+    /// Remove the `index`th child of the node at the end of `parent` (a path as returned by
+    /// [`cursor_path`](EditableTree::cursor_path)), along with its whole subtree, as a single
+    /// undoable change.  The cursor moves as it would for
+    /// [`delete_cursor`](EditableTree::delete_cursor).  Returns a copy of the removed subtree, or
+    /// why it couldn't be removed (leaving the tree and the cursor unchanged) if there is no such
+    /// child or the parent can't have it removed.
+    fn remove_child_at_path(
+        &mut self,
+        parent: &[usize],
+        index: usize,
+    ) -> Result<Subtree<Node>, EditError> {
+        let parent_node = self
+            .node_at_path(parent)
+            .ok_or_else(|| EditError::NoNode(parent.to_vec()))?;
+        let len = parent_node.children().len();
+        if index >= len {
+            return Err(EditError::IndexOutOfRange { index, len });
+        }
+        if !parent_node.clone().remove_child(index) {
+            return Err(EditError::CannotRemove(parent_node.kind_name()));
+        }
+        let mut path = parent.to_vec();
+        path.push(index);
+        let subtree = Subtree::copy_from(self, self.ref_at_path(&path).unwrap())
+            .ok_or_else(|| EditError::NoNode(path.clone()))?;
+        let is_moved = self.move_to_path(&path);
+        let result = self.delete_siblings(1);
+        debug_assert!(is_moved && result == Ok(1));
+        Ok(subtree)
+    }
+
+    /// Rearrange the children of the node at the end of `parent` (a path as returned by
+    /// [`cursor_path`](EditableTree::cursor_path)) so that its `i`th child is the one that was at
+    /// index `order[i]`, as a single undoable change.  If the cursor is inside one of the moved
+    /// children, it moves along with it.  Returns why the children couldn't be reordered (leaving
+    /// the tree and the cursor unchanged) if there is no node at `parent`, `order` isn't a
+    /// permutation of its children, or they can't be reordered.
+    fn reorder_children(&mut self, parent: &[usize], order: &[usize]) -> Result<(), EditError>;
+
+    /// Like [`delete_cursor`](EditableTree::delete_cursor), but also deletes up to `count - 1`
+    /// of the siblings after the selected node, as one undoable change.  Returns the number of
+    /// nodes that were deleted, which is never more than the number of siblings after (and
+    /// including) the selected node.  The root can only be deleted on its own.  Returns
+    /// [`EditError::CannotRemove`] (and deletes nothing) if the parent can't have these children
+    /// removed.
+    fn delete_siblings(&mut self, count: usize) -> Result<usize, EditError>;
+
+    /// Replace every node for which `pred` returns `true` with a copy of `new_node`, as a single
+    /// undoable change.  Nodes inside a replaced node are not checked, since they are replaced
+    /// along with it, and nodes that can't be replaced by `new_node` (see
+    /// [`check_replacement_at`](EditableTree::check_replacement_at)) are left alone.  Returns the
+    /// number of nodes replaced, which is the same as the number of paths returned by
+    /// [`paths_to_matching`](EditableTree::paths_to_matching) that can be replaced.  If the
+    /// cursor was inside a replaced node, it is moved to that node.
+    fn replace_all(&mut self, pred: impl Fn(&Node) -> bool, new_node: Node) -> usize;
+
+    /// Replace the selected node with the result of [merging](ASTSpec::merge_with) it with its
+    /// next sibling, and remove that sibling.  The cursor stays on the merged node.  Fails
+    /// (leaving the tree unchanged) if there is no next sibling or the two nodes can't be merged.
+    fn merge_with_next_sibling(&mut self, separator: &str) -> Result<(), EditError>;
+
+    /// Replace the selected node with a new node of the kind given by `c`, which contains the
+    /// selected node (see [`ASTSpec::wrap_from_char`]), as a single undoable change.  The cursor
+    /// moves to the new node.  Fails (leaving the tree unchanged) if the selected node can't be
+    /// wrapped in that kind of node, or if its parent can't contain that kind of node.
+    fn wrap_cursor(&mut self, c: char) -> Result<(), EditError>;
+
+    /// Replace the selected node with its [wrapped child](ASTSpec::wrapped_child), as a single
+    /// undoable change.  The cursor moves to the child.  Returns [`EditError::CannotUnwrap`] (and
+    /// leaves the tree unchanged) if the selected node doesn't wrap a single node.
+    fn unwrap_cursor(&mut self) -> Result<(), EditError>;
+
+    /// Replace the selected node with all of its children, which are spliced into its parent's
+    /// children where it was (so splicing `[2, 3]` in `[1, [2, 3], 4]` gives `[1, 2, 3, 4]`), as a
+    /// single undoable change.  The cursor moves to the first of the spliced children.  Fails
+    /// (leaving the tree unchanged) if the root is selected, if the selected node has no
+    /// children, or if its parent can't contain them.
+    fn splice_cursor(&mut self) -> Result<(), EditError>;
+
+    /// Replace the selected node with its [wrapped child](ASTSpec::wrapped_child) (see
+    /// [`unwrap_cursor`](EditableTree::unwrap_cursor)) if it has one, or else
+    /// [splice](EditableTree::splice_cursor) its children into its parent
+    fn unwrap_or_splice_cursor(&mut self) -> Result<(), EditError> {
+        match self.unwrap_cursor() {
+            Err(EditError::CannotUnwrap(_)) => self.splice_cursor(),
+            result => result,
+        }
+    }
+
+    /// Swap the selected node with the sibling `offset` places after it (or before it, if
+    /// `offset` is negative) in its parent's [children](ASTSpec::swap_children), as a single
+    /// undoable change.  The cursor moves with the selected node.  Fails (leaving the tree
+    /// unchanged) if there is no such sibling or the parent's children can't be reordered.
+    fn swap_with_sibling(&mut self, offset: isize) -> Result<(), EditError>;
+
+    /// Replace every node in the tree with the result of calling `f` on it, as a single undoable
+    /// change.  Nodes for which `f` returns [`None`] (or an identical node) are left unchanged.
+    /// The replacements must have the same children as the nodes they replace, so the cursor
+    /// never moves.  Returns the number of nodes that were changed.
+    fn map_nodes(&mut self, f: impl Fn(&Node) -> Option<Node>) -> usize;
+
+    /// Like [`map_nodes`](EditableTree::map_nodes), but only replaces the selected node and its
+    /// descendants.
+    fn map_subtree(&mut self, f: impl Fn(&Node) -> Option<Node>) -> usize;
+
+    /* QUERY METHODS */
+
+    /// Call `f` on every node in the current tree.  A node that is reachable along multiple paths
+    /// from the root is visited once per path.
+    fn for_each_node(&self, mut f: impl FnMut(&Node)) {
+        // Traverse the tree with an explicit stack, so that deep trees can't overflow the call
+        // stack
+        let mut refs_to_visit = vec![self.root()];
+        while let Some(r) = refs_to_visit.pop() {
+            if let Some(node) = self.get_node(r) {
+                f(node);
+                refs_to_visit.extend(node.children().iter().copied());
+            }
+        }
+    }
+
+    /// Returns the number of nodes in the current tree for which `pred` returns `true`.  A node
+    /// that is reachable along multiple paths from the root is counted once per path.
+    fn count_matching(&self, pred: impl Fn(&Node) -> bool) -> usize {
+        let mut count = 0;
+        self.for_each_node(|node| {
+            if pred(node) {
+                count += 1;
+            }
+        });
+        count
+    }
+
+    /// Returns the paths (as child indices from the root) of every node in the current tree for
+    /// which `pred` returns `true`, in the order that they appear in the text.  The descendants of
+    /// a matching node are not checked.  This is the set of nodes that would be changed by
+    /// [`replace_all`](EditableTree::replace_all), so can be used as a dry run of it.
+    fn paths_to_matching(&self, pred: impl Fn(&Node) -> bool) -> Vec<Vec<usize>> {
+        let mut paths = Vec::new();
+        // Traverse the tree with an explicit stack, pushing children in reverse order so that
+        // they are popped in the order they appear
+        let mut to_visit = vec![(self.root(), Vec::new())];
+        while let Some((r, path)) = to_visit.pop() {
+            let node = match self.get_node(r) {
+                Some(n) => n,
+                None => continue,
+            };
+            if pred(node) {
+                paths.push(path);
+                continue;
+            }
+            for (i, c) in node.children().iter().enumerate().rev() {
+                let mut child_path = path.clone();
+                child_path.push(i);
+                to_visit.push((*c, child_path));
+            }
+        }
+        paths
+    }
+
+    /// Returns the path (see [`cursor_path`](EditableTree::cursor_path)) of the next node after
+    /// the cursor, in the order the nodes appear in the text, for which `pred` returns `true`.  If
+    /// `forwards` is `false`, this finds the previous matching node instead.  The search wraps
+    /// around the ends of the document, so the selected node is checked last.  Returns [`None`]
+    /// if no node matches.
+    fn find_matching(&self, pred: impl Fn(&Node) -> bool, forwards: bool) -> Option<Vec<usize>> {
+        self.find_matching_path(|_, node| pred(node), forwards)
+    }
+
+    /// Like [`find_matching`](EditableTree::find_matching), but `pred` is also given the path to
+    /// each node, so that it can look at the node's surroundings (e.g. the
+    /// [name](ASTSpec::child_name) that its parent gives it)
+    fn find_matching_path(
+        &self,
+        pred: impl Fn(&[usize], &Node) -> bool,
+        forwards: bool,
+    ) -> Option<Vec<usize>> {
+        let cursor_path = self.cursor_path();
+        // Paths are ordered the same way as the text, so the previous and next matches are the
+        // matching paths either side of the cursor's path.  Only matching paths are copied.
+        let mut first = None;
+        let mut last = None;
+        let mut before_cursor = None;
+        let mut after_cursor = None;
+        let mut traversal = self.preorder();
+        while let Some(id) = traversal.next() {
+            let path = traversal.path();
+            if !self.get_node(id).is_some_and(|node| pred(path, node)) {
+                continue;
+            }
+            if first.is_none() {
+                first = Some(path.to_vec());
+            }
+            if path < &cursor_path[..] {
+                before_cursor = Some(path.to_vec());
+            } else if path > &cursor_path[..] && after_cursor.is_none() {
+                after_cursor = Some(path.to_vec());
+            }
+            last = Some(path.to_vec());
+        }
+        if forwards {
+            after_cursor.or(first)
+        } else {
+            before_cursor.or(last)
+        }
+    }
+
+    /// Returns the path (see [`cursor_path`](EditableTree::cursor_path)) of the node at `id`, or
+    /// [`None`] if that node isn't in the current tree.  This is the inverse of
+    /// [`ref_at_path`](EditableTree::ref_at_path).  Nodes don't store links to their parents, so
+    /// this searches down from the root and takes time proportional to the number of nodes before
+    /// `id` in the text.  If a node is shared, the first of its paths is returned.
+    fn path_to(&self, id: Ref) -> Option<Vec<usize>> {
+        let mut traversal = self.preorder();
+        while let Some(node) = traversal.next() {
+            if node == id {
+                return Some(traversal.path().to_vec());
+            }
+        }
+        None
+    }
+
+    /// Returns how many nodes of each kind (as given by [`ASTSpec::kind_name`]) are in the current
+    /// tree, sorted by kind name.  Kinds with no nodes are not included.
+    fn kind_histogram(&self) -> BTreeMap<&'static str, usize> {
+        let mut histogram = BTreeMap::new();
+        self.for_each_node(|node| *histogram.entry(node.kind_name()).or_insert(0) += 1);
+        histogram
+    }
+
+    /// Returns the number of nodes stored by this tree, including the nodes that aren't in the
+    /// current tree (e.g. those kept for the undo history)
+    fn stored_node_count(&self) -> usize {
+        self.iter_all().count()
+    }
+
+    /// Free the stored nodes that can't be reached from any version of the tree in the undo
+    /// history (see [`NodeMapMut::collect_garbage`]).  This may move the nodes that are kept, so
+    /// any references into the tree that are held elsewhere must be updated with
+    /// [`GcStats::remap`].  The tree itself, its history and the cursor are unchanged.
+    fn collect_garbage(&mut self) -> GcStats<Ref>;
+
+    /// Returns how much memory the tree and its undo history use (see
+    /// [`NodeMapMut::memory_usage`](crate::node_map::NodeMapMut::memory_usage)).  The reachable
+    /// nodes are those in the current tree, but every stored node is counted, including those
+    /// only kept for the undo history.
+    fn memory_usage(&self) -> MemoryStats;
+
+    /// Returns the [`TreeStats`] of the current tree, computed in one traversal
+    fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let mut distinct_nodes = HashSet::new();
+        // Traverse the tree with an explicit stack, so that deep trees can't overflow the call
+        // stack
+        let mut refs_to_visit = vec![(self.root(), 0)];
+        while let Some((r, depth)) = refs_to_visit.pop() {
+            let node = match self.get_node(r) {
+                Some(n) => n,
+                None => continue,
+            };
+            distinct_nodes.insert(r);
+            stats.nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            *stats.kinds.entry(node.kind_name()).or_insert(0) += 1;
+            refs_to_visit.extend(node.children().iter().map(|c| (*c, depth + 1)));
+        }
+        stats.unreachable_nodes = self
+            .stored_node_count()
+            .saturating_sub(distinct_nodes.len());
+        stats
+    }
+
+    /// Returns the number of bytes and the number of lines occupied by the text of the subtree
+    /// rooted at `node`, when written with a given format style.
+    fn text_size(&self, node: Ref, format: &Node::FormatStyle) -> (usize, usize) {
+        let text = Node::to_text(node, self, format);
+        (text.len(), text.lines().count().max(1))
+    }
 
     /* DISPLAY METHODS */
 
@@ -0,0 +1,696 @@
+//! The [`EditableTree`] that both the [`Spec`] and the [`DAG`] are made of, which keeps every
+//! version of the tree by copying the path to each changed node
+
+use super::cursor_path::{self, Segment};
+use super::transaction::Transactions;
+use super::{
+    no_child_from_char, reordered, reordered_path, sibling_at_offset, spliced, EditError,
+    EditableTree, PastePosition,
+};
+use crate::ast_spec::ASTSpec;
+use crate::node_map::diff::{diff, Change};
+use crate::node_map::subtree::Subtree;
+use crate::node_map::{copy_subtree, GcStats, MemoryStats, NodeMap, NodeMapMut, Reference};
+use std::collections::HashMap;
+
+// Imports used only for doc comments
+#[allow(unused_imports)]
+use super::{dag::DAG, spec::Spec};
+
+/// The ways that the trees made from a [`Persistent`] differ: whether they keep track of which
+/// nodes are copies of which, and whether one node can appear in more than one place
+pub trait Sharing<Ref: Reference, Node: ASTSpec<Ref>>: Sized + Default {
+    /// `true` if an edit that adds the same node in several places (like
+    /// [`EditableTree::replace_all`]) adds it once and refers to it from all of them
+    const SHARES_NODES: bool;
+
+    /// Records that the node at `copy` was made by copying the node at `original`
+    fn record_copy(&mut self, copy: Ref, original: Ref);
+
+    /// Returns the copy in the tree at `new_root` of every node in the tree at `old_root` that
+    /// has since been copied (see [`EditableTree::copies_since`])
+    fn copies_between(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        old_root: Ref,
+        new_root: Ref,
+    ) -> HashMap<Ref, Ref>;
+
+    /// Updates the records of copies after `node_map` has been garbage collected
+    fn remap(&mut self, stats: &GcStats<Ref>, node_map: &impl NodeMap<Ref, Node>);
+
+    /// Forgets every copy, because the whole history is being replaced
+    fn clear(&mut self);
+
+    /// Replace every node in the subtree at the end of `path` in `tree` with the result of
+    /// calling `f` on it, as a single undoable change.  Returns the number of nodes that were
+    /// changed, counting shared nodes every time they appear.
+    fn map_nodes_at_path<M: NodeMapMut<Ref, Node>>(
+        tree: &mut Persistent<Node, Ref, M, Self>,
+        path: &[usize],
+        f: impl Fn(&Node) -> Option<Node>,
+    ) -> usize;
+}
+
+/// An [`EditableTree`] where every version of the tree in the undo history lives in the same
+/// [`NodeMap`], and nodes are never changed once they have been added to it.
+///
+/// Instead, an edit adds the changed node along with a copy of each of its ancestors (path
+/// copying), so the new version shares every other subtree with the version before it and every
+/// old version stays intact.  The history is then just the root of each version (as the first
+/// segment of that version's cursor path) and the index of the current one, so an edit adds as
+/// many nodes as the depth of the edited node, and undoing and redoing only change the index.
+/// How the copies are kept track of is up to `S` (see [`Sharing`]).
+#[derive(Debug, Clone)]
+pub struct Persistent<Node: ASTSpec<Ref>, Ref: Reference, M, S> {
+    /// Every node of every version of the tree in the history.  Nodes are only ever added to
+    /// this (until they're garbage collected), never changed.
+    pub(super) node_map: M,
+    /// The cursor path of every version of the tree in the undo history, oldest first.  The first
+    /// segment of each path is that version's root.  We require that this history always
+    /// contains at least one version.
+    pub(super) history: Vec<Vec<Segment<Ref>>>,
+    /// The index of the current version.  We require that this always points to a valid index
+    /// in `history`
+    pub(super) current_version: usize,
+    /// The transactions that are open, which are merged into one version when they finish
+    transactions: Transactions<Ref, Vec<Segment<Ref>>>,
+    /// The record of which nodes are copies of which
+    sharing: S,
+    /// `Node` is only used through `M`
+    _node: std::marker::PhantomData<Node>,
+}
+
+impl<Node: ASTSpec<Ref>, Ref: Reference, M: NodeMapMut<Ref, Node>, S: Sharing<Ref, Node>>
+    Persistent<Node, Ref, M, S>
+{
+    /// Makes a tree that contains the tree stored inside `node_map`
+    pub fn from_tree(node_map: M) -> Self {
+        Persistent {
+            history: vec![vec![Segment::root(node_map.root())]],
+            current_version: 0,
+            transactions: Transactions::new(),
+            sharing: S::default(),
+            node_map,
+            _node: std::marker::PhantomData,
+        }
+    }
+
+    /// Makes a tree whose undo history contains the trees in `snapshots` (oldest first), each
+    /// with the cursor at the given path of child indices.  The tree at `current` is the one
+    /// that is being edited.  The trees are copied into one map, so don't share any nodes.
+    /// Returns [`None`] if there are no snapshots, `current` is out of range, or any of the
+    /// cursor paths don't lead to a node.
+    pub fn from_snapshots(snapshots: Vec<(M, Vec<usize>)>, current: usize) -> Option<Self> {
+        if current >= snapshots.len() {
+            return None;
+        }
+        let mut snapshots = snapshots.into_iter();
+        let (node_map, path) = snapshots.next()?;
+        let mut roots_and_paths = vec![(node_map.root(), path)];
+        let mut tree = Self::from_tree(node_map);
+        for (node_map, path) in snapshots {
+            let root = copy_subtree(&node_map, node_map.root(), &mut tree.node_map)?;
+            roots_and_paths.push((root, path));
+        }
+        tree.history.clear();
+        for (root, path) in roots_and_paths {
+            let refs = tree.try_refs_along(root, &path)?;
+            tree.history.push(cursor_path_of(&refs, &path));
+        }
+        tree.current_version = current;
+        Some(tree)
+    }
+
+    /// Discards the current tree, along with all of its undo history, and replaces it with the
+    /// tree stored inside `node_map`.  The cursor is moved to the root of the new tree.
+    pub fn load_tree(&mut self, node_map: M) {
+        self.history = vec![vec![Segment::root(node_map.root())]];
+        self.current_version = 0;
+        self.node_map = node_map;
+        self.transactions.clear();
+        self.sharing.clear();
+    }
+
+    /// Adds `node` to the map as a copy of the node at `original`, which it will replace in the
+    /// next version of the tree
+    fn add_copy(&mut self, original: Ref, node: Node) -> Ref {
+        let id = self.node_map.add_node(node);
+        self.sharing.record_copy(id, original);
+        id
+    }
+
+    /// Returns the cursor path of the current version of the tree
+    fn current_path(&self) -> &[Segment<Ref>] {
+        // We don't have to worry about bounds checks because we require that
+        // `self.current_version` is a valid index in `self.history`
+        &self.history[self.current_version]
+    }
+
+    /// Returns the cursor path of the current version of the tree mutably.  This is only used for
+    /// moving the cursor, since any other change should create a new version.
+    pub(super) fn current_path_mut(&mut self) -> &mut Vec<Segment<Ref>> {
+        &mut self.history[self.current_version]
+    }
+
+    /// Returns the references of every node along `path` (as child indices) from `root`, starting
+    /// with `root` itself, or [`None`] if the path doesn't lead to a node
+    fn try_refs_along(&self, root: Ref, path: &[usize]) -> Option<Vec<Ref>> {
+        let mut refs = Vec::with_capacity(path.len() + 1);
+        refs.push(root);
+        for &index in path {
+            let parent = self.node_map.get_node(*refs.last().unwrap())?;
+            refs.push(*parent.children().get(index)?);
+        }
+        Some(refs)
+    }
+
+    /// Like [`try_refs_along`](Self::try_refs_along), but panics if the path doesn't lead to a
+    /// node
+    fn refs_along(&self, root: Ref, path: &[usize]) -> Vec<Ref> {
+        self.try_refs_along(root, path)
+            .unwrap_or_else(|| panic!("{:?} doesn't lead to a node", path))
+    }
+
+    /// Returns the node at the end of `path` from `root`.  This panics if the path doesn't lead
+    /// to a node.
+    pub(super) fn node_at(&self, root: Ref, path: &[usize]) -> &Node {
+        let id = *self.refs_along(root, path).last().unwrap();
+        self.node_map.get_node(id).unwrap()
+    }
+
+    /// Returns the root of a new version of the tree at `root`, where the node at the end of
+    /// `path` is replaced by the node at `new_ref`.  Only the ancestors of the replaced node are
+    /// copied - everything else is shared with the old version.
+    pub(super) fn replace_at_path(&mut self, root: Ref, path: &[usize], new_ref: Ref) -> Ref {
+        let ancestors = self.refs_along(root, path);
+        let mut new_ref = new_ref;
+        for (&ancestor, &index) in ancestors.iter().zip(path).rev() {
+            let mut new_ancestor = self.node_map.get_node(ancestor).unwrap().clone();
+            new_ancestor.children_mut()[index] = new_ref;
+            new_ref = self.add_copy(ancestor, new_ancestor);
+        }
+        new_ref
+    }
+
+    /// Adds a new version of the tree to the history (deleting the current redo history if
+    /// needed), with its root at `root` and the cursor at the end of `cursor_path`
+    pub(super) fn make_change(&mut self, root: Ref, cursor_path: &[usize]) {
+        let refs = self.refs_along(root, cursor_path);
+        let new_path = cursor_path_of(&refs, cursor_path);
+        // Delete the history that happened in front of the current version
+        self.transactions
+            .discard_redo(&mut self.history, self.current_version);
+        debug_assert_eq!(self.history.len(), self.current_version + 1);
+        self.history.push(new_path);
+        self.current_version += 1;
+        // Catch editing bugs as soon as they break the tree, rather than when it's next displayed
+        self.debug_check_invariants();
+    }
+
+    /// Replace the node at the end of `path` with the node at `new_ref`, and move the cursor to
+    /// `cursor_path`, as a new version of the tree
+    pub(super) fn change_node(&mut self, path: &[usize], new_ref: Ref, cursor_path: &[usize]) {
+        let root = self.replace_at_path(self.root(), path, new_ref);
+        self.make_change(root, cursor_path);
+    }
+
+    /// Add the node at `new_ref` (which must already be in the map) as the `index`th child of the
+    /// node at `parent_path`, moving the cursor to it.  Returns [`EditError::CannotInsert`] (and
+    /// makes no change) if that node can't have children inserted.
+    fn insert_ref(
+        &mut self,
+        parent_path: &[usize],
+        new_ref: Ref,
+        index: usize,
+    ) -> Result<(), EditError> {
+        let parent = self.ref_at_path(parent_path).unwrap();
+        let mut parent_node = self.get_node(parent).unwrap().clone();
+        let index = index.min(parent_node.children().len());
+        if !parent_node.insert_child(new_ref, index) {
+            return Err(EditError::CannotInsert(parent_node.kind_name()));
+        }
+        let new_parent = self.add_copy(parent, parent_node);
+        let mut cursor_path = parent_path.to_vec();
+        cursor_path.push(index);
+        self.change_node(parent_path, new_parent, &cursor_path);
+        Ok(())
+    }
+}
+
+/// Builds the cursor path through the nodes at `refs`, which were found by following the child
+/// indices in `path` from the root (which is the first of `refs`)
+fn cursor_path_of<Ref: Reference>(refs: &[Ref], path: &[usize]) -> Vec<Segment<Ref>> {
+    let mut segments = vec![Segment::root(refs[0])];
+    for (r, &index) in refs[1..].iter().zip(path) {
+        segments.push(Segment::new(*r, index));
+    }
+    segments
+}
+
+impl<Node: ASTSpec<Ref>, Ref: Reference, M: NodeMapMut<Ref, Node>, S: Sharing<Ref, Node>>
+    NodeMap<Ref, Node> for Persistent<Node, Ref, M, S>
+{
+    fn get_node(&self, id: Ref) -> Option<&Node> {
+        self.node_map.get_node(id)
+    }
+
+    fn root(&self) -> Ref {
+        // We require that every path in the history is non-empty, so we don't have to worry
+        // about panics
+        self.current_path()[0].node
+    }
+
+    fn iter_all(&self) -> Box<dyn Iterator<Item = (Ref, &Node)> + '_> {
+        self.node_map.iter_all()
+    }
+}
+
+impl<Node: ASTSpec<Ref>, Ref: Reference, M: NodeMapMut<Ref, Node>, S: Sharing<Ref, Node>>
+    EditableTree<Ref, Node> for Persistent<Node, Ref, M, S>
+{
+    fn new() -> Self {
+        Self::from_tree(M::with_default_root())
+    }
+
+    fn undo(&mut self) -> bool {
+        if self.current_version > 0 && !self.transactions.is_open() {
+            self.current_version -= 1;
+            self.debug_check_invariants();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn redo(&mut self) -> bool {
+        if self.current_version < self.history.len() - 1 && !self.transactions.is_open() {
+            self.current_version += 1;
+            self.debug_check_invariants();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn history_position(&self) -> usize {
+        self.current_version
+    }
+
+    /// Every version is in the same [`NodeMap`] and shares its unchanged subtrees with the
+    /// others, so this only has to look at the nodes that were copied by the edits in between
+    fn changes_since(&self, position: usize) -> Option<Vec<Change>> {
+        let old_root = self.history.get(position)?[0].node;
+        Some(diff(&self.node_map, old_root, &self.node_map, self.root()))
+    }
+
+    fn copies_since(&self, position: usize) -> HashMap<Ref, Ref> {
+        match self.history.get(position) {
+            Some(path) => self
+                .sharing
+                .copies_between(&self.node_map, path[0].node, self.root()),
+            None => HashMap::new(),
+        }
+    }
+
+    fn begin_edit(&mut self) {
+        let cursor_path = self.current_path().to_vec();
+        self.transactions.begin(self.current_version, cursor_path);
+    }
+
+    fn commit_edit(&mut self) -> bool {
+        self.transactions
+            .commit(&mut self.history, &mut self.current_version)
+    }
+
+    fn rollback_edit(&mut self) -> bool {
+        let rolled_back = self.transactions.rollback(
+            &mut self.history,
+            &mut self.current_version,
+            |path, cursor_path| *path = cursor_path,
+        );
+        self.debug_check_invariants();
+        rolled_back
+    }
+
+    fn cursor(&self) -> Ref {
+        // We require that every path in the history is non-empty, so we can unwrap without
+        // fearing panics
+        self.current_path().last().unwrap().node
+    }
+
+    fn cursor_path(&self) -> Vec<usize> {
+        cursor_path::child_indices(self.current_path())
+    }
+
+    fn sibling_index(&self) -> Option<usize> {
+        cursor_path::sibling_index(self.current_path())
+    }
+
+    fn move_to_child(&mut self, index: usize) -> bool {
+        let path = &mut self.history[self.current_version];
+        cursor_path::move_to_child(path, &self.node_map, index)
+    }
+
+    fn move_to_parent(&mut self) -> bool {
+        cursor_path::move_to_parent(self.current_path_mut())
+    }
+
+    fn move_to_ancestor(&mut self, pred: impl Fn(&Node) -> bool) -> bool {
+        let path = &mut self.history[self.current_version];
+        cursor_path::move_to_ancestor(path, &self.node_map, pred)
+    }
+
+    fn move_by_siblings(&mut self, offset: isize) -> usize {
+        let path = &mut self.history[self.current_version];
+        cursor_path::move_by_siblings(path, &self.node_map, offset)
+    }
+
+    fn move_to_same_key_in_next_record(&mut self) -> bool {
+        let path = &mut self.history[self.current_version];
+        cursor_path::move_to_same_key_in_next_record(path, &self.node_map)
+    }
+
+    fn replace_cursor(&mut self, new_node: Node) {
+        let new_ref = self.node_map.add_node(new_node);
+        let cursor_path = self.cursor_path();
+        self.change_node(&cursor_path, new_ref, &cursor_path);
+    }
+
+    fn replace_node(&mut self, new_node: Node) {
+        let old_node = self.cursor_node().clone();
+        let new_node = new_node
+            .with_children_of(&old_node, &mut self.node_map)
+            .unwrap_or(new_node);
+        self.replace_cursor(new_node);
+    }
+
+    fn replace_from_char(&mut self, c: char) -> Result<(), EditError> {
+        let cursor_node = self.cursor_node().clone();
+        let cannot_replace = EditError::CannotReplace {
+            kind: cursor_node.kind_name(),
+            c,
+        };
+        if !cursor_node.is_replace_char(c) {
+            return Err(cannot_replace);
+        }
+        // If the replacement fails, the placeholder children are left unreachable until the next
+        // garbage collection
+        let new_node = cursor_node
+            .from_char_with_children(c, &mut self.node_map)
+            .ok_or(cannot_replace)?;
+        self.check_replacement_at(&self.cursor_path(), &new_node)
+            .map_err(EditError::InvalidChild)?;
+        self.replace_node(new_node);
+        Ok(())
+    }
+
+    fn insert_child_at(&mut self, new_node: Node, index: usize) -> Result<(), EditError> {
+        let new_ref = self.node_map.add_node(new_node);
+        self.insert_ref(&self.cursor_path(), new_ref, index)
+    }
+
+    fn insert_child_from_char(&mut self, c: char, index: usize) -> Result<(), EditError> {
+        let cursor_node = self.cursor_node().clone();
+        let new_ref = cursor_node
+            .child_from_char(c, &mut self.node_map)
+            .ok_or_else(|| no_child_from_char(&cursor_node, c, self.root()))?;
+        self.insert_ref(&self.cursor_path(), new_ref, index)
+    }
+
+    fn insert_sibling_from_char(&mut self, c: char, after: bool) -> Result<(), EditError> {
+        let mut parent_path = self.cursor_path();
+        // The root has no parent to insert siblings into
+        let sibling_index = parent_path.pop().ok_or(EditError::IsRoot)?;
+        let parent_node = self.node_at_path(&parent_path).unwrap().clone();
+        let new_ref = parent_node.sibling_from_char(c, &mut self.node_map).ok_or(
+            EditError::NoNodeForChar {
+                parent: parent_node.kind_name(),
+                c,
+            },
+        )?;
+        self.insert_ref(&parent_path, new_ref, sibling_index + after as usize)
+    }
+
+    fn paste_siblings(
+        &mut self,
+        subtrees: &[Subtree<Node>],
+        position: PastePosition,
+    ) -> Result<(), EditError> {
+        if subtrees.is_empty() {
+            return Err(EditError::NothingToPaste);
+        }
+        let mut parent_path = self.cursor_path();
+        let index = match position {
+            PastePosition::Child => usize::MAX,
+            PastePosition::Before | PastePosition::After => {
+                // Siblings are inserted into the parent of the selected node, which the root
+                // doesn't have
+                let sibling_index = parent_path.pop().ok_or(EditError::IsRoot)?;
+                match position {
+                    PastePosition::Before => sibling_index,
+                    _ => sibling_index + 1,
+                }
+            }
+        };
+        // The parent must allow the pasted node as a child (e.g. JSON objects can only contain
+        // fields)
+        let parent = self.ref_at_path(&parent_path).unwrap();
+        let mut parent_node = self.get_node(parent).unwrap().clone();
+        for subtree in subtrees {
+            parent_node
+                .check_replacement(index, subtree.node())
+                .map_err(EditError::InvalidChild)?;
+        }
+        let index = index.min(parent_node.children().len());
+        for (i, subtree) in subtrees.iter().enumerate() {
+            let new_ref = subtree.add_to(&mut self.node_map);
+            if !parent_node.insert_child(new_ref, index + i) {
+                return Err(EditError::CannotInsert(parent_node.kind_name()));
+            }
+        }
+        let new_parent = self.add_copy(parent, parent_node);
+        let mut cursor_path = parent_path.clone();
+        cursor_path.push(index);
+        self.change_node(&parent_path, new_parent, &cursor_path);
+        Ok(())
+    }
+
+    fn delete_siblings(&mut self, count: usize) -> Result<usize, EditError> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let mut parent_path = self.cursor_path();
+        let sibling_index = match parent_path.pop() {
+            Some(i) => i,
+            None => {
+                // The root can't be removed, so replace it with an empty node instead
+                let new_root = self.node_map.add_node(Node::default());
+                self.make_change(new_root, &[]);
+                return Ok(1);
+            }
+        };
+        let parent = self.ref_at_path(&parent_path).unwrap();
+        let mut parent_node = self.get_node(parent).unwrap().clone();
+        let num_deleted = count.min(parent_node.children().len() - sibling_index);
+        for _ in 0..num_deleted {
+            if !parent_node.remove_child(sibling_index) {
+                // The parent can't have these children removed, so the whole change is abandoned
+                return Err(EditError::CannotRemove(parent_node.kind_name()));
+            }
+        }
+        // Move the cursor to the nearest remaining sibling, or leave it on the parent if there
+        // are none
+        let num_siblings = parent_node.children().len();
+        let mut cursor_path = parent_path.clone();
+        if sibling_index < num_siblings {
+            cursor_path.push(sibling_index);
+        } else if let Some(i) = sibling_index.checked_sub(1) {
+            cursor_path.push(i);
+        }
+        let new_parent = self.add_copy(parent, parent_node);
+        self.change_node(&parent_path, new_parent, &cursor_path);
+        Ok(num_deleted)
+    }
+
+    fn reorder_children(&mut self, parent: &[usize], order: &[usize]) -> Result<(), EditError> {
+        let parent_ref = self
+            .ref_at_path(parent)
+            .ok_or_else(|| EditError::NoNode(parent.to_vec()))?;
+        let new_node = reordered(self.get_node(parent_ref).unwrap(), order)?;
+        let new_ref = self.add_copy(parent_ref, new_node);
+        // The cursor follows its node
+        let cursor_path = reordered_path(&self.cursor_path(), parent, order);
+        self.change_node(parent, new_ref, &cursor_path);
+        Ok(())
+    }
+
+    fn replace_all(&mut self, pred: impl Fn(&Node) -> bool, new_node: Node) -> usize {
+        let mut paths = self.paths_to_matching(pred);
+        paths.retain(|path| self.check_replacement_at(path, &new_node).is_ok());
+        if paths.is_empty() {
+            return 0;
+        }
+        // Every replacement makes a new root, but only the last one becomes a version in the
+        // history.  The matching paths never contain each other, so they stay valid.  Unless
+        // nodes can be shared, each replacement gets its own copy of `new_node`.
+        let shared_ref = if S::SHARES_NODES {
+            Some(self.node_map.add_node(new_node.clone()))
+        } else {
+            None
+        };
+        let mut root = self.root();
+        for path in &paths {
+            let new_ref = match shared_ref {
+                Some(r) => r,
+                None => self.node_map.add_node(new_node.clone()),
+            };
+            root = self.replace_at_path(root, path, new_ref);
+        }
+        // If the cursor was inside a replaced node, move it to the replaced node
+        let mut cursor_path = self.cursor_path();
+        if let Some(path) = paths.iter().find(|p| cursor_path.starts_with(p)) {
+            cursor_path.truncate(path.len());
+        }
+        self.make_change(root, &cursor_path);
+        paths.len()
+    }
+
+    fn merge_with_next_sibling(&mut self, separator: &str) -> Result<(), EditError> {
+        let mut parent_path = self.cursor_path();
+        // The root has no siblings
+        let sibling_index = parent_path.pop().ok_or(EditError::IsRoot)?;
+        let parent = self.ref_at_path(&parent_path).unwrap();
+        let mut parent_node = self.get_node(parent).unwrap().clone();
+        let next_sibling = parent_node
+            .children()
+            .get(sibling_index + 1)
+            .and_then(|r| self.get_node(*r))
+            .ok_or(EditError::NoSibling { offset: 1 })?;
+        let cursor_node = self.cursor_node();
+        let merged_node =
+            cursor_node
+                .merge_with(next_sibling, separator)
+                .ok_or(EditError::CannotMerge {
+                    first: cursor_node.kind_name(),
+                    second: next_sibling.kind_name(),
+                })?;
+        if !parent_node.remove_child(sibling_index + 1) {
+            return Err(EditError::CannotRemove(parent_node.kind_name()));
+        }
+        parent_node.children_mut()[sibling_index] = self.node_map.add_node(merged_node);
+        let new_parent = self.add_copy(parent, parent_node);
+        let cursor_path = self.cursor_path();
+        self.change_node(&parent_path, new_parent, &cursor_path);
+        Ok(())
+    }
+
+    fn wrap_cursor(&mut self, c: char) -> Result<(), EditError> {
+        let cursor = self.cursor();
+        let cursor_node = self.cursor_node().clone();
+        let cursor_path = self.cursor_path();
+        let new_ref = cursor_node
+            .wrap_from_char(c, cursor, &mut self.node_map)
+            .ok_or(EditError::CannotWrap {
+                kind: cursor_node.kind_name(),
+                c,
+            })?;
+        // The wrapper takes the place of the selected node, so its parent must allow it
+        self.check_replacement_at(&cursor_path, self.get_node(new_ref).unwrap())
+            .map_err(EditError::InvalidChild)?;
+        self.change_node(&cursor_path, new_ref, &cursor_path);
+        Ok(())
+    }
+
+    fn unwrap_cursor(&mut self) -> Result<(), EditError> {
+        let cursor_node = self.cursor_node();
+        let child = cursor_node
+            .wrapped_child(self)
+            .ok_or(EditError::CannotUnwrap(cursor_node.kind_name()))?;
+        let cursor_path = self.cursor_path();
+        self.change_node(&cursor_path, child, &cursor_path);
+        Ok(())
+    }
+
+    fn splice_cursor(&mut self) -> Result<(), EditError> {
+        let mut parent_path = self.cursor_path();
+        // The root has no parent to splice its children into
+        let sibling_index = parent_path.pop().ok_or(EditError::IsRoot)?;
+        let cursor_node = self.cursor_node();
+        if cursor_node.children().is_empty() {
+            return Err(EditError::NothingToSplice(cursor_node.kind_name()));
+        }
+        let parent = self.ref_at_path(&parent_path).unwrap();
+        let parent_node = self.get_node(parent).unwrap();
+        let new_parent = spliced(parent_node, sibling_index, cursor_node.children(), self)?;
+        let new_ref = self.add_copy(parent, new_parent);
+        // The first spliced child takes the selected node's place, so the cursor path is the same
+        let cursor_path = self.cursor_path();
+        self.change_node(&parent_path, new_ref, &cursor_path);
+        Ok(())
+    }
+
+    fn swap_with_sibling(&mut self, offset: isize) -> Result<(), EditError> {
+        let mut parent_path = self.cursor_path();
+        // The root has no siblings
+        let sibling_index = parent_path.pop().ok_or(EditError::IsRoot)?;
+        let parent = self.ref_at_path(&parent_path).unwrap();
+        let mut parent_node = self.get_node(parent).unwrap().clone();
+        let new_index = sibling_at_offset(sibling_index, offset, &parent_node)?;
+        if !parent_node.swap_children(sibling_index, new_index) {
+            return Err(EditError::CannotReorder(parent_node.kind_name()));
+        }
+        let new_parent = self.add_copy(parent, parent_node);
+        let mut cursor_path = parent_path.clone();
+        cursor_path.push(new_index);
+        self.change_node(&parent_path, new_parent, &cursor_path);
+        Ok(())
+    }
+
+    fn map_nodes(&mut self, f: impl Fn(&Node) -> Option<Node>) -> usize {
+        S::map_nodes_at_path(self, &[], f)
+    }
+
+    fn map_subtree(&mut self, f: impl Fn(&Node) -> Option<Node>) -> usize {
+        let cursor_path = self.cursor_path();
+        S::map_nodes_at_path(self, &cursor_path, f)
+    }
+
+    fn collect_garbage(&mut self) -> GcStats<Ref> {
+        // Every version in the history shares the same map, so all of their roots are kept (as
+        // are the versions that an open transaction could put back)
+        let (redo, checkpoints) = self.transactions.kept_mut();
+        let live_roots: Vec<Ref> = self
+            .history
+            .iter()
+            .chain(redo.iter())
+            .map(|path| path[0].node)
+            .collect();
+        let stats = self.node_map.collect_garbage(&live_roots);
+        let checkpoint_paths = checkpoints.iter_mut().map(|(_, path)| path);
+        for segment in self
+            .history
+            .iter_mut()
+            .chain(redo.iter_mut())
+            .chain(checkpoint_paths)
+            .flatten()
+        {
+            segment.node = stats.remap(segment.node);
+        }
+        self.sharing.remap(&stats, &self.node_map);
+        self.debug_check_invariants();
+        stats
+    }
+
+    fn memory_usage(&self) -> MemoryStats {
+        MemoryStats {
+            // The root of the map isn't kept up to date, so the current tree is counted here
+            nodes_reachable: self.iter_reachable(self.root()).count(),
+            ..self.node_map.memory_usage()
+        }
+    }
+
+    fn write_text(&self, string: &mut String, format: &Node::FormatStyle) {
+        Node::write_text(self.root(), self, string, format);
+    }
+}
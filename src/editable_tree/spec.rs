@@ -1,133 +1,2014 @@
-use super::{cursor_path, EditableTree};
+use super::persistent::{Persistent, Sharing};
+use super::EditableTree;
 use crate::ast_spec::ASTSpec;
+use crate::node_map::traversal::PreOrder;
 use crate::node_map::vec::{Index, VecNodeMap};
-use crate::node_map::{NodeMap, NodeMapMut};
+use crate::node_map::{GcStats, NodeMap, NodeMapMut, Reference};
+use std::collections::HashMap;
 
-/// A snapshot of the undo history of a specification [`EditableTree`].  This is cloned every time
-/// a changes is made to the [`Spec`] struct.
+/// An [`EditableTree`] that is used as a specification to test other [`EditableTree`]
+/// implementations against.  No effort is made to make `Spec` performant beyond the way that it
+/// stores its history - the important thing is that it should be difficult to introduce
+/// unintended behaviour.
+///
+/// The history is kept by path copying (see [`Persistent`]), but a `Spec` never refers to the
+/// same node from two places in one version, so its tree is always a tree.  This lets it keep
+/// track of which nodes are copies of which (see [`EditableTree::copies_since`]).  Any
+/// [`NodeMapMut`] can be used to store the nodes, but by default they are stored in a
+/// [`VecNodeMap`].
+pub type Spec<Node, Ref = Index, M = VecNodeMap<Node>> = Persistent<Node, Ref, M, Origins<Ref>>;
+
+/// The original node of every node that was made by copying another (following copies of copies
+/// back to the first), so that a node can be recognised in every version of the tree that it
+/// appears in.  Nodes that aren't copies are their own originals.
 #[derive(Debug, Clone)]
-struct Snapshot<Node: ASTSpec<Index>> {
-    /// The [`NodeMap`] containing the tree at this point in the undo history
-    pub node_map: VecNodeMap<Node>,
-    pub cursor_path: Vec<cursor_path::Segment<Index>>,
-}
+pub struct Origins<Ref: Reference>(HashMap<Ref, Ref>);
 
-impl<Node: ASTSpec<Index>> Snapshot<Node> {
-    /// Makes a `Snapshot` from a given [`VecNodeMap`] with the cursor selecting the root of that
-    /// tree
-    fn from_node_map(node_map: VecNodeMap<Node>) -> Self {
-        let cursor = node_map.root();
-        Snapshot {
-            node_map,
-            cursor_path: vec![cursor_path::Segment::new(cursor, 0)],
-        }
+impl<Ref: Reference> Origins<Ref> {
+    /// Returns the node that the node at `id` was originally copied from, or `id` if it isn't a
+    /// copy
+    fn original(&self, id: Ref) -> Ref {
+        self.0.get(&id).copied().unwrap_or(id)
     }
+}
 
-    /// Gets the [`Index`] of the current path
-    fn cursor(&self) -> Index {
-        self.cursor_path.last().unwrap().node
+impl<Ref: Reference> Default for Origins<Ref> {
+    fn default() -> Self {
+        Origins(HashMap::new())
     }
 }
 
-/// An [`EditableTree`] that is used as a specification to test other [`EditableTree`]
-/// implementations against.  No effort is made to make `Spec` performant in any way - the
-/// important thing is that it should be difficult to introduce unintended behaviour.
-///
-/// This works by storing the history as a [`Snapshot`] of [`VecNodeMap`]s for the trees along with
-/// any other metadata about that save state.  Every edit of the tree causes the last [`Snapshot`]
-/// to be cloned and the edits made on the new [`Snapshot`].  Therefore, undoing is as simple as
-/// just subtracting `1` from `history_index` to make it point to a previous [`Snapshot`].
-#[derive(Debug, Clone)]
-pub struct Spec<Node: ASTSpec<Index>> {
-    /// The sequence of [`Snapshot`]s that represents the undo history.  We require that this
-    /// history always contains at least one item.
-    history: Vec<Snapshot<Node>>,
-    /// The index of the current [`Snapshot`].  We require that this always points to a valid index
-    /// in `history`
-    current_snapshot_index: usize,
-}
+impl<Ref: Reference, Node: ASTSpec<Ref>> Sharing<Ref, Node> for Origins<Ref> {
+    const SHARES_NODES: bool = false;
 
-impl<Node: ASTSpec<Index>> Spec<Node> {
-    /// Makes a `DAG` that contains the tree stored inside `node_map`
-    pub fn from_tree(node_map: VecNodeMap<Node>) -> Self {
-        Spec {
-            history: vec![Snapshot::from_node_map(node_map)],
-            current_snapshot_index: 0,
-        }
+    fn record_copy(&mut self, copy: Ref, original: Ref) {
+        let original = self.original(original);
+        self.0.insert(copy, original);
     }
 
-    /// Returns the currently viewed [`Snapshot`]
-    fn snapshot(&self) -> &Snapshot<Node> {
-        // We don't have to worry about bounds checks because we require that
-        // `self.current_snapshot_index` is a valid index in `self.history`
-        &self.history[self.current_snapshot_index]
+    fn copies_between(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        old_root: Ref,
+        new_root: Ref,
+    ) -> HashMap<Ref, Ref> {
+        // Nodes are only copied when something below them changes, so the copies are found by
+        // following the nodes with the same original down from the roots
+        let mut copies = HashMap::new();
+        let mut pairs = vec![(old_root, new_root)];
+        while let Some((old, new)) = pairs.pop() {
+            if old == new || self.original(old) != self.original(new) {
+                continue;
+            }
+            copies.insert(old, new);
+            let new_children: HashMap<Ref, Ref> = node_map
+                .get_node(new)
+                .unwrap()
+                .children()
+                .iter()
+                .map(|&child| (self.original(child), child))
+                .collect();
+            for &child in node_map.get_node(old).unwrap().children() {
+                if let Some(&new_child) = new_children.get(&self.original(child)) {
+                    pairs.push((child, new_child));
+                }
+            }
+        }
+        copies
     }
 
-    /// Adds a new snapshot to the tree history (deleting the current redo history if needed).
-    fn make_change(&mut self, snapshot: Snapshot<Node>) {
-        // Delete the history that happened in front of the current snapshot
-        while self.history.len() > self.current_snapshot_index + 1 {
-            self.history.pop();
+    fn remap(&mut self, stats: &GcStats<Ref>, node_map: &impl NodeMap<Ref, Node>) {
+        // Copies whose originals were freed are recognised by one of the copies that was kept instead
+        let mut stand_ins = HashMap::new();
+        let copies: Vec<(Ref, Ref)> = self.0.drain().collect();
+        for (copy, original) in copies {
+            let copy = stats.remap(copy);
+            if node_map.get_node(copy).is_none() {
+                continue;
+            }
+            let original = stats.remap(original);
+            let original = if node_map.get_node(original).is_some() {
+                original
+            } else {
+                match stand_ins.get(&original) {
+                    Some(&stand_in) => stand_in,
+                    None => {
+                        stand_ins.insert(original, copy);
+                        continue;
+                    }
+                }
+            };
+            self.0.insert(copy, original);
         }
-        debug_assert_eq!(self.history.len(), self.current_snapshot_index + 1);
-        // Add the new snapshot
-        self.history.push(snapshot);
-        self.current_snapshot_index += 1;
     }
-}
 
-impl<Node: ASTSpec<Index>> NodeMap<Index, Node> for Spec<Node> {
-    fn get_node(&self, id: Index) -> Option<&Node> {
-        self.snapshot().node_map.get_node(id)
+    fn clear(&mut self) {
+        self.0.clear();
     }
 
-    fn root(&self) -> Index {
-        // We require that current_path.len() >= 1, so we don't have to worry about panics
-        self.snapshot().node_map.root()
+    /// Every change is found first and then made one at a time, each copying the path to the
+    /// node it changes
+    fn map_nodes_at_path<M: NodeMapMut<Ref, Node>>(
+        tree: &mut Spec<Node, Ref, M>,
+        path: &[usize],
+        f: impl Fn(&Node) -> Option<Node>,
+    ) -> usize {
+        let start_ref = match tree.ref_at_path(path) {
+            Some(r) => r,
+            None => return 0,
+        };
+        // Find every change before making any, so that the paths can be found in the old tree
+        let mut changes = Vec::new();
+        let mut traversal = PreOrder::new(tree, start_ref);
+        while let Some(r) = traversal.next() {
+            let node = tree.get_node(r).unwrap();
+            if let Some(new_node) = f(node).filter(|n| n != node) {
+                debug_assert_eq!(new_node.children(), node.children());
+                let mut node_path = path.to_vec();
+                node_path.extend_from_slice(traversal.path());
+                changes.push((node_path, new_node));
+            }
+        }
+        if changes.is_empty() {
+            return 0;
+        }
+        let num_changed = changes.len();
+        // Every change makes a new root, but only the last one becomes a version in the history.
+        // Changes only replace nodes with ones that have the same children, so the paths of the
+        // later changes stay valid.
+        let mut root = tree.root();
+        for (node_path, new_node) in changes {
+            // The node may already have been copied because something below it changed
+            let old_node = tree.node_at(root, &node_path);
+            let mut new_node = new_node;
+            new_node.children_mut().copy_from_slice(old_node.children());
+            let new_ref = tree.node_map.add_node(new_node);
+            root = tree.replace_at_path(root, &node_path, new_ref);
+        }
+        let cursor_path = tree.cursor_path();
+        tree.make_change(root, &cursor_path);
+        num_changed
     }
 }
 
-impl<Node: ASTSpec<Index>> EditableTree<Index, Node> for Spec<Node> {
-    fn new() -> Self {
-        Self::from_tree(VecNodeMap::with_default_root())
-    }
+#[cfg(test)]
+mod tests {
+    use super::Spec;
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::{TestJSON, TestRng};
+    use crate::ast_spec::{subtrees_equal, ASTSpec};
+    use crate::editable_tree::cursor_path;
+    use crate::editable_tree::{EditError, EditableTree, PastePosition};
+    use crate::node_map::subtree::Subtree;
+    use crate::node_map::{GcStats, NodeMap, NodeMapMut};
+    use std::collections::HashMap;
+
+    for_each_node_map! {
+        /// A [`Spec`] of JSON that uses the map being tested
+        type Tree = Spec<JSON<Ref>, Ref, Map<JSON<Ref>>>;
 
-    fn undo(&mut self) -> bool {
-        if self.current_snapshot_index > 0 {
-            self.current_snapshot_index -= 1;
-            true
-        } else {
-            false
+        /// Builds a map containing a given [`TestJSON`] tree
+        fn build_map(tree: &TestJSON) -> Map<JSON<Ref>> {
+            tree.build_node_map::<Ref, Map<JSON<Ref>>>()
         }
-    }
 
-    fn redo(&mut self) -> bool {
-        if self.current_snapshot_index < self.history.len() - 1 {
-            self.current_snapshot_index += 1;
-            true
-        } else {
-            false
+        /// Builds a [`Spec`] containing the same sample tree that Sapling starts up with
+        fn sample_tree() -> Tree {
+            Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+                TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
+            ])))
         }
-    }
 
-    fn cursor(&self) -> Index {
-        self.snapshot().cursor()
-    }
+        /// Returns the message of the panic caused by `f`, or [`None`] if it didn't panic
+        fn panic_message(f: impl FnOnce()) -> Option<String> {
+            let error = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).err()?;
+            error.downcast_ref::<String>().cloned()
+        }
 
-    fn replace_cursor(&mut self, new_node: Node) {
-        let mut new_snapshot = self.snapshot().clone();
-        // Overwrite the node under the cursor
-        new_snapshot
-            .node_map
-            .overwrite_node(new_snapshot.cursor(), new_node);
-        self.make_change(new_snapshot);
-    }
+        #[test]
+        #[cfg(debug_assertions)]
+        fn invariant_checks() {
+            let valid = build_map(&TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Object(vec![("k".to_string(), TestJSON::Null)]),
+            ]));
+            let object = valid.root_node().children()[1];
+            assert_eq!(
+                panic_message(|| Spec::from_tree(valid.clone()).debug_check_invariants()),
+                None
+            );
 
-    fn insert_child(&mut self, _new_node: Node) {
-        unimplemented!();
-    }
+            // A child reference past the end of the map, taken from a bigger map
+            let bigger = build_map(&TestJSON::Array(vec![TestJSON::True; 10]));
+            let out_of_bounds = *bigger.root_node().children().last().unwrap();
+            let mut node_map = valid.clone();
+            node_map.overwrite_node(object, JSON::Array(vec![out_of_bounds])).unwrap();
+            let message = panic_message(|| Spec::from_tree(node_map).debug_check_invariants());
+            assert_eq!(
+                message,
+                Some(format!(
+                    "Broken tree invariant (every reference points to a node): {:?} has a child \
+                     ({:?}) that doesn't exist",
+                    object, out_of_bounds
+                ))
+            );
+            // A node that contains itself
+            let mut node_map = valid.clone();
+            node_map.overwrite_node(object, JSON::Array(vec![object])).unwrap();
+            let message = panic_message(|| Spec::from_tree(node_map).debug_check_invariants());
+            assert_eq!(
+                message,
+                Some(format!(
+                    "Broken tree invariant (the tree is acyclic): {:?} is its own descendant",
+                    object
+                ))
+            );
+            // A node that breaks JSON's rules
+            let mut node_map = valid.clone();
+            node_map.overwrite_node(object, JSON::Object(vec![valid.root_node().children()[0]])).unwrap();
+            let message = panic_message(|| Spec::from_tree(node_map).debug_check_invariants());
+            assert_eq!(
+                message,
+                Some(format!(
+                    "Broken tree invariant (every node is valid): {:?} is invalid: objects can \
+                     only contain fields",
+                    object
+                ))
+            );
+            // A cursor that isn't where its path says it is
+            let mut tree = Spec::from_tree(valid.clone());
+            tree.current_path_mut()
+                .push(cursor_path::Segment::new(object, 0));
+            let message = panic_message(|| tree.debug_check_invariants());
+            assert_eq!(
+                message,
+                Some(format!(
+                    "Broken tree invariant (the cursor is in the tree): the cursor is {:?}, but \
+                     its path [0] leads to {:?}",
+                    object,
+                    Some(valid.root_node().children()[0])
+                ))
+            );
+
+            // Edits check the tree they make, so breaking the tree is caught straight away
+            let mut tree = Spec::from_tree(valid.clone());
+            let first = tree.root_node().children()[0];
+            assert!(tree.move_to_child(1));
+            let message = panic_message(|| tree.replace_cursor(JSON::Object(vec![first])));
+            assert!(message.unwrap().contains("every node is valid"));
+            // Undoing and redoing are checked too
+            let mut tree = Spec::from_tree(valid);
+            tree.replace_cursor(JSON::Null);
+            // The old version's nodes are still in the map, so break one of those
+            tree.node_map
+                .overwrite_node(object, JSON::Array(vec![object]))
+                .unwrap();
+            let message = panic_message(|| {
+                tree.undo();
+            });
+            assert!(message.unwrap().contains("the tree is acyclic"));
+        }
+
+        #[test]
+        fn garbage_collection_keeps_every_snapshot() {
+            let mut tree = sample_tree();
+            assert!(tree.move_to_path(&[1]));
+            tree.replace_cursor(JSON::Null);
+            assert!(tree.move_to_path(&[0]));
+            tree.replace_cursor(JSON::Array(vec![]));
+            assert!(tree.undo());
+            let before = tree.clone();
+            // Every version shares the same nodes, so only the placeholder root that the tree was
+            // built around is freed
+            let stats = tree.collect_garbage();
+            assert_eq!(stats.freed, 1);
+            assert_eq!(tree.collect_garbage().freed, 0);
+            assert_eq!(tree.cursor_path(), before.cursor_path());
+            assert_eq!(tree.cursor_node(), before.cursor_node());
+            assert_eq!(tree.cursor(), stats.remap(before.cursor()));
+            // Every version is unchanged, so can still be undone and redone to
+            let texts = |tree: &mut Tree| {
+                let mut texts = vec![tree.to_text(&JSONFormat::Compact)];
+                while tree.undo() {
+                    texts.push(tree.to_text(&JSONFormat::Compact));
+                }
+                while tree.redo() {
+                    texts.push(tree.to_text(&JSONFormat::Compact));
+                }
+                texts
+            };
+            assert_eq!(texts(&mut tree), texts(&mut before.clone()));
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[[], null, {"value": true}]"#
+            );
+        }
+
+        #[test]
+        fn edits_only_copy_the_path_to_the_change() {
+            let leaves = || vec![TestJSON::True, TestJSON::False, TestJSON::Null];
+            let tree = TestJSON::Array(vec![
+                TestJSON::Array(vec![TestJSON::Array(leaves()), TestJSON::Array(leaves())]),
+                TestJSON::Array(leaves()),
+            ]);
+            let mut tree = Spec::from_tree(build_map(&tree));
+            tree.collect_garbage();
+            assert!(tree.move_to_path(&[0, 1, 2]));
+            let mut texts = vec![tree.to_text(&JSONFormat::Compact)];
+            let stored_node_count = tree.stored_node_count();
+            for i in 1..=10 {
+                tree.replace_cursor(JSON::Number(i.to_string()));
+                texts.push(tree.to_text(&JSONFormat::Compact));
+                // The new node and its three ancestors are the only new nodes
+                assert_eq!(tree.stored_node_count(), stored_node_count + 4 * i);
+            }
+            assert_eq!(
+                texts[10],
+                "[[[true, false, null], [true, false, 10]], [true, false, null]]"
+            );
+            // Every version can still be undone and redone to
+            for text in texts.iter().rev() {
+                assert_eq!(&tree.to_text(&JSONFormat::Compact), text);
+                tree.undo();
+            }
+            for text in &texts[1..] {
+                assert!(tree.redo());
+                assert_eq!(&tree.to_text(&JSONFormat::Compact), text);
+            }
+            assert_eq!(tree.stored_node_count(), stored_node_count + 40);
+        }
+
+        #[test]
+        fn copies_are_followed_between_versions() {
+            let mut tree = sample_tree();
+            let old_root = tree.root();
+            let object = tree.root_node().children()[2];
+            let field = tree.get_node(object).unwrap().children()[0];
+            let first = tree.root_node().children()[0];
+            assert!(tree.move_to_path(&[2, 0, 1]));
+            tree.replace_cursor(JSON::Null);
+            // Only the ancestors of the changed node are copied
+            let copies = tree.copies_since(0);
+            assert_eq!(copies.len(), 3);
+            assert_eq!(copies[&old_root], tree.root());
+            assert_eq!(tree.path_to(copies[&object]), Some(vec![2]));
+            assert_eq!(tree.path_to(copies[&field]), Some(vec![2, 0]));
+            assert!(!copies.contains_key(&first));
+            // Copies of copies are still recognised, even after the nodes move
+            assert!(tree.move_to_path(&[2]));
+            tree.insert_sibling_from_char('t', true).unwrap();
+            tree.collect_garbage();
+            assert_eq!(tree.copies_since(2), HashMap::new());
+            let new_object = tree.ref_at_path(&[2]).unwrap();
+            assert!(tree.undo());
+            assert!(tree.undo());
+            let copies = tree.copies_since(2);
+            assert_eq!(copies[&new_object], tree.ref_at_path(&[2]).unwrap());
+            assert_eq!(tree.copies_since(5), HashMap::new());
+        }
+
+        #[test]
+        fn replaced_nodes_have_new_references() {
+            let mut tree = sample_tree();
+            let [first, second, object] = match tree.root_node().children() {
+                [a, b, c] => [*a, *b, *c],
+                _ => unreachable!(),
+            };
+            let field = tree.get_node(object).unwrap().children()[0];
+            let root_before_edits = tree.root();
+            assert!(tree.move_to_child(1));
+            tree.replace_cursor(JSON::Null);
+            // References taken before the replacement don't reach the replacement, although the
+            // old node is kept for the undo history
+            assert_eq!(tree.path_to(second), None);
+            assert_eq!(tree.get_node(second), Some(&JSON::False));
+            assert_ne!(tree.cursor(), second);
+            assert_eq!(tree.cursor_node(), &JSON::Null);
+            assert_eq!(tree.path_to(tree.cursor()), Some(vec![1]));
+            // ... but references to untouched nodes still work
+            assert_eq!(tree.get_node(first), Some(&JSON::True));
+            assert_eq!(tree.path_to(field), Some(vec![2, 0]));
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, null, {"value": true}]"#
+            );
+
+            // Changing many nodes at once gives each of them a new reference, including the
+            // selected node
+            let root = tree.root();
+            assert!(tree.move_to_path(&[2, 0, 1]));
+            let cursor = tree.cursor();
+            assert_eq!(tree.map_nodes(|node| node.inverted()), 2);
+            assert_eq!(tree.path_to(first), None);
+            assert_eq!(tree.path_to(cursor), None);
+            // The ancestors of the changed nodes are copied to hold them
+            assert_ne!(tree.root(), root);
+            assert_eq!(tree.path_to(field), None);
+            assert_eq!(tree.cursor_path(), vec![2, 0, 1]);
+            assert_eq!(tree.cursor_node(), &JSON::False);
+            // Undoing goes back to the old version, where the old references are valid
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(tree.path_to(second), Some(vec![1]));
+            assert_eq!(tree.root(), root_before_edits);
+        }
+
+        #[test]
+        fn random_edits_keep_trees_valid() {
+            TestJSON::check_property(100, 3, 3, |tree, seed| {
+                let mut tree = Spec::from_tree(build_map(tree));
+                let mut rng = TestRng::new(seed);
+                for step in 0..40 {
+                    let cursor = tree.cursor_node().clone();
+                    let c = *rng.choose(&['t', 'f', 'z', 'a', 'o', 'i', 's', 'n']);
+                    let op = rng.below(10);
+                    match op {
+                        0 => drop(tree.move_to_child(rng.below(4))),
+                        1 => drop(tree.move_to_parent()),
+                        2 => drop(tree.move_by_siblings(rng.below(3) as isize - 1)),
+                        3 => drop(tree.insert_child_from_char(c, rng.below(4))),
+                        4 => {
+                            if let Some(node) = cursor.from_char(c) {
+                                if tree
+                                    .check_replacement_at(&tree.cursor_path(), &node)
+                                    .is_ok()
+                                {
+                                    tree.replace_cursor(node);
+                                }
+                            }
+                        }
+                        5 => drop(tree.delete_cursor()),
+                        6 => drop(tree.wrap_cursor(c)),
+                        7 => drop(tree.unwrap_or_splice_cursor()),
+                        8 => drop(tree.swap_with_sibling(1)),
+                        _ => drop(if rng.below(2) == 0 {
+                            tree.undo()
+                        } else {
+                            tree.redo()
+                        }),
+                    }
+                    tree.validate()
+                        .map_err(|e| format!("step {} (op {}, char {:?}): {}", step, op, c, e))?;
+                }
+                Ok(())
+            });
+        }
+
+        #[test]
+        fn garbage_collection_keeps_random_histories() {
+            /// Checks that two trees are at the same place in the same history, and that
+            /// `held` in `before` is the same node as `stats.remap(held)` in `after`, in every
+            /// version of the tree
+            fn same_history(
+                mut before: Tree,
+                mut after: Tree,
+                held: Ref,
+                stats: &GcStats<Ref>,
+            ) -> Result<(), String> {
+                while after.undo() {
+                    assert!(before.undo());
+                }
+                loop {
+                    let position = after.history_position();
+                    if !subtrees_equal(&before, before.root(), &after, after.root()) {
+                        return Err(format!("version {} changed", position));
+                    }
+                    if after.cursor_path() != before.cursor_path() {
+                        return Err(format!("the cursor moved in version {}", position));
+                    }
+                    if after.path_to(stats.remap(held)) != before.path_to(held) {
+                        return Err(format!("{:?} moved in version {}", held, position));
+                    }
+                    if !after.redo() {
+                        break;
+                    }
+                    assert!(before.redo());
+                }
+                if before.redo() {
+                    return Err("versions were lost".to_string());
+                }
+                Ok(())
+            }
+
+            TestJSON::check_property(100, 3, 3, |tree, seed| {
+                let mut tree = Spec::from_tree(build_map(tree));
+                let mut rng = TestRng::new(seed);
+                for step in 0..40 {
+                    let c = *rng.choose(&['t', 'f', 'a', 'o', 'i']);
+                    match rng.below(7) {
+                        0 => drop(tree.move_to_child(rng.below(3))),
+                        1 => drop(tree.move_to_parent()),
+                        2 => drop(tree.insert_child_from_char(c, rng.below(3))),
+                        3 => drop(tree.delete_cursor()),
+                        4 => drop(tree.wrap_cursor(c)),
+                        5 => drop(tree.undo()),
+                        _ => drop(tree.redo()),
+                    }
+                    if rng.below(4) == 0 {
+                        let before = tree.clone();
+                        let held = tree.cursor();
+                        let stats = tree.collect_garbage();
+                        same_history(before, tree.clone(), held, &stats)
+                            .map_err(|e| format!("step {}: {}", step, e))?;
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        #[test]
+        fn edits_compared_structurally() {
+            let mut tree = sample_tree();
+            // Wrap the value of the field in an array, then delete the first element of the root
+            assert!(tree.move_to_path(&[2, 0, 1]));
+            tree.wrap_cursor('a').unwrap();
+            assert!(tree.move_to_root());
+            assert!(tree.move_to_child(0));
+            tree.delete_cursor().unwrap();
+            assert_eq!(
+                TestJSON::from_node_map(&tree),
+                TestJSON::Array(vec![
+                    TestJSON::False,
+                    TestJSON::Object(vec![(
+                        "value".to_string(),
+                        TestJSON::Array(vec![TestJSON::True])
+                    )]),
+                ])
+            );
+            // Undoing both changes gives back the original tree, even though the nodes from the
+            // edits are still stored
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(
+                TestJSON::from_node_map(&tree),
+                TestJSON::from_node_map(&sample_tree())
+            );
+        }
+
+        #[test]
+        fn load_tree() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+            ])));
+            // Make a change so that there is some history to be discarded
+            tree.replace_cursor(JSON::True);
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "true");
+
+            // Load a completely new document
+            tree.load_tree(build_map(&TestJSON::Object(vec![(
+                "value".to_string(),
+                TestJSON::False,
+            )])));
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"{"value": false}"#);
+            // The cursor should be reset to the new root, and the history should be gone
+            assert_eq!(tree.cursor(), tree.root());
+            assert!(!tree.undo());
+            assert!(!tree.redo());
+        }
+
+        #[test]
+        fn count_matching() {
+            let tree = sample_tree();
+            let is_bool = |node: &JSON<Ref>| matches!(node, JSON::True | JSON::False);
+            assert_eq!(tree.count_matching(is_bool), 3);
+            assert_eq!(tree.count_matching(|node| node == &JSON::True), 2);
+            assert_eq!(tree.count_matching(|_| true), 7);
+            assert_eq!(tree.count_matching(|_| false), 0);
+        }
+
+        #[test]
+        fn kind_histogram() {
+            let histogram = sample_tree().kind_histogram();
+            assert_eq!(
+                histogram.into_iter().collect::<Vec<_>>(),
+                vec![
+                    ("array", 1),
+                    ("bool", 3),
+                    ("field", 1),
+                    ("object", 1),
+                    ("string", 1)
+                ]
+            );
+        }
+
+        #[test]
+        fn stats() {
+            let mut tree = sample_tree();
+            let stats = tree.stats();
+            assert_eq!(stats.kinds, tree.kind_histogram());
+            // The placeholder root that the map was built around is unreachable
+            assert_eq!(
+                (stats.nodes, stats.max_depth, stats.unreachable_nodes),
+                (7, 3, 1)
+            );
+            assert_eq!(
+                stats.to_string(),
+                "7 nodes, depth 3, 1 unreachable \
+                 (array: 1, bool: 3, field: 1, object: 1, string: 1)"
+            );
+            // Deleting a node leaves it in the map, but it's no longer reachable
+            assert!(tree.move_to_child(2));
+            tree.delete_cursor().unwrap();
+            let stats = tree.stats();
+            assert_eq!(
+                (stats.nodes, stats.max_depth, stats.unreachable_nodes),
+                (3, 1, tree.stored_node_count() - 3)
+            );
+            assert!(stats.unreachable_nodes > 0);
+
+            // A chain of 100,000 nested arrays, which would overflow the stack if traversed
+            // recursively
+            let mut node_map: Map<JSON<Ref>> = Map::with_root(JSON::True);
+            for _ in 0..100_000 {
+                let root = node_map.root();
+                node_map.add_as_root(JSON::Array(vec![root]));
+            }
+            let stats = Spec::from_tree(node_map).stats();
+            assert_eq!(
+                (stats.nodes, stats.max_depth, stats.unreachable_nodes),
+                (100_001, 100_000, 0)
+            );
+            assert_eq!(
+                stats.kinds.into_iter().collect::<Vec<_>>(),
+                vec![("array", 100_000), ("bool", 1)]
+            );
+        }
+
+        #[test]
+        fn text_size() {
+            let tree = sample_tree();
+            let object = tree.root_node().children()[2];
+
+            let compact_text = JSON::to_text(object, &tree, &JSONFormat::Compact);
+            assert_eq!(compact_text, r#"{"value": true}"#);
+            assert_eq!(
+                tree.text_size(object, &JSONFormat::Compact),
+                (compact_text.len(), 1)
+            );
+            assert_eq!(tree.text_size(object, &JSONFormat::Pretty), (21, 3));
+            let pretty_text = JSON::to_text(tree.root(), &tree, &JSONFormat::Pretty);
+            assert_eq!(
+                tree.text_size(tree.root(), &JSONFormat::Pretty),
+                (pretty_text.len(), 7)
+            );
+        }
+
+        #[test]
+        fn navigation() {
+            let mut tree = sample_tree();
+            let root = tree.root();
+            // Moving up from the root or sideways from the root should do nothing
+            assert!(!tree.move_to_parent());
+            assert_eq!(tree.move_by_siblings(1), 0);
+            assert_eq!(tree.cursor(), root);
+
+            assert!(tree.move_to_child(2));
+            assert_eq!(tree.cursor(), tree.root_node().children()[2]);
+            assert!(tree.move_to_child(0));
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.cursor_node(), &JSON::True);
+            // `true` has no children
+            assert!(!tree.move_to_child(0));
+
+            assert!(tree.move_to_parent());
+            assert!(tree.move_to_parent());
+            assert_eq!(tree.move_by_siblings(-1), 1);
+            assert_eq!(tree.cursor_node(), &JSON::False);
+            assert!(tree.move_to_parent());
+            assert_eq!(tree.cursor(), root);
+        }
+
+        #[test]
+        fn replace_cursor_from_chars() {
+            let mut tree = sample_tree();
+            assert!(tree.move_to_child(1));
+            for (c, expected_text) in &[
+                ('t', r#"[true, true, {"value": true}]"#),
+                ('f', r#"[true, false, {"value": true}]"#),
+                ('a', r#"[true, [], {"value": true}]"#),
+                ('o', r#"[true, {}, {"value": true}]"#),
+            ] {
+                // Replace the node in the same way as the editor does
+                assert!(tree.cursor_node().is_replace_char(*c));
+                let new_node = tree.cursor_node().from_char(*c).unwrap();
+                tree.replace_cursor(new_node);
+                assert_eq!(tree.to_text(&JSONFormat::Compact), *expected_text);
+            }
+            // Chars that don't correspond to a node can't be used to replace anything
+            assert!(!tree.cursor_node().is_replace_char('x'));
+            assert!(tree.cursor_node().from_char('x').is_none());
+            // Each replacement should be undoable
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, [], {"value": true}]"#
+            );
+        }
+
+        #[test]
+        fn replace_from_char() {
+            for (c, expected_text) in &[
+                ('t', r#"[true, false, {"value": true}]"#),
+                ('f', r#"[false, false, {"value": true}]"#),
+                ('z', r#"[null, false, {"value": true}]"#),
+                ('a', r#"[[], false, {"value": true}]"#),
+                ('o', r#"[{}, false, {"value": true}]"#),
+                ('s', r#"["", false, {"value": true}]"#),
+                ('n', r#"[0, false, {"value": true}]"#),
+            ] {
+                let mut tree = sample_tree();
+                assert!(tree.move_to_child(0));
+                assert_eq!(tree.replace_from_char(*c), Ok(()));
+                assert_eq!(tree.to_text(&JSONFormat::Compact), *expected_text);
+                assert_eq!(tree.validate(), Ok(()));
+            }
+            // Fields are replaced by a placeholder field, rather than one without a key and value
+            let mut tree = sample_tree();
+            assert!(tree.move_to_path(&[2, 0]));
+            assert_eq!(tree.replace_from_char('i'), Ok(()));
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"key": true}]"#
+            );
+            assert_eq!(tree.validate(), Ok(()));
+            assert_eq!(tree.cursor_path(), vec![2, 0]);
+            // Anything else is rejected by the object, leaving the tree unchanged
+            assert!(matches!(
+                tree.replace_from_char('t'),
+                Err(EditError::InvalidChild(_))
+            ));
+            // Fields can't go anywhere but in an object
+            assert!(tree.move_to_path(&[0]));
+            assert_eq!(
+                tree.replace_from_char('i'),
+                Err(EditError::CannotReplace { kind: "bool", c: 'i' })
+            );
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"key": true}]"#
+            );
+            assert_eq!(tree.validate(), Ok(()));
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+        }
+
+        #[test]
+        fn transactions() {
+            let mut tree = sample_tree();
+            // Undoing puts the cursor where the first change was made, so start it there
+            assert!(tree.move_to_child(0));
+            let original = tree.clone();
+            let text = |tree: &Tree| tree.to_text(&JSONFormat::Compact);
+            let same_tree = |a: &Tree, b: &Tree| {
+                subtrees_equal(a, a.root(), b, b.root()) && a.cursor_path() == b.cursor_path()
+            };
+            assert!(!tree.commit_edit());
+            assert!(!tree.rollback_edit());
+
+            // Several changes in a transaction are undone in one go
+            tree.begin_edit();
+            tree.replace_cursor(JSON::Null);
+            tree.insert_sibling_from_char('t', true).unwrap();
+            assert!(tree.move_to_path(&[3]));
+            tree.delete_cursor().unwrap();
+            // Undo and redo can't escape the transaction
+            assert!(!tree.undo());
+            assert!(tree.commit_edit());
+            assert_eq!(text(&tree), "[null, true, false]");
+            assert_eq!(tree.history_position(), 1);
+            assert!(tree.undo());
+            assert!(same_tree(&tree, &original));
+            assert!(tree.redo());
+            assert_eq!(text(&tree), "[null, true, false]");
+
+            // Rolling back restores the tree, the cursor and the redo history exactly
+            assert!(tree.undo());
+            let before = tree.clone();
+            tree.begin_edit();
+            assert!(tree.move_to_path(&[2]));
+            tree.wrap_cursor('a').unwrap();
+            tree.replace_all(|node| node == &JSON::True, JSON::False);
+            assert!(tree.rollback_edit());
+            assert!(same_tree(&tree, &before));
+            assert!(tree.redo());
+            assert_eq!(text(&tree), "[null, true, false]");
+            assert!(tree.undo());
+
+            // Nested transactions are flattened into the outer one, but roll back on their own
+            tree.begin_edit();
+            tree.replace_cursor(JSON::Null);
+            tree.begin_edit();
+            tree.delete_cursor().unwrap();
+            assert!(tree.rollback_edit());
+            tree.begin_edit();
+            assert!(tree.move_to_path(&[1]));
+            tree.replace_cursor(JSON::True);
+            assert!(tree.commit_edit());
+            assert!(tree.commit_edit());
+            assert_eq!(text(&tree), r#"[null, true, {"value": true}]"#);
+            assert_eq!(tree.history_position(), 1);
+            assert!(!tree.redo());
+            assert!(tree.undo());
+            assert!(same_tree(&tree, &original));
+
+            // A transaction that changes nothing leaves the history alone
+            assert!(tree.redo());
+            assert!(tree.undo());
+            tree.begin_edit();
+            assert!(tree.commit_edit());
+            assert!(tree.redo());
+
+            // `with_edit` rolls back everything if any of its changes fail
+            let before = tree.clone();
+            let result = tree.with_edit(|tree| {
+                tree.move_to_root();
+                tree.replace_cursor(JSON::Object(vec![]));
+                tree.insert_sibling_from_char('t', true)
+            });
+            assert_eq!(result, Err(EditError::IsRoot));
+            assert!(same_tree(&tree, &before));
+            assert_eq!(
+                tree.with_edit(|tree| {
+                    tree.move_to_root();
+                    tree.insert_child_from_char('n', 0)?;
+                    tree.insert_sibling_from_char('f', false)
+                }),
+                Ok(())
+            );
+            assert_eq!(text(&tree), r#"[false, 0, null, true, {"value": true}]"#);
+            assert_eq!(tree.history_position(), 2);
+        }
+
+        #[test]
+        fn undo_redo_interleaving() {
+            let mut tree = sample_tree();
+            let text = |tree: &Tree| tree.to_text(&JSONFormat::Compact);
+            // Undoing or redoing with no history should do nothing
+            assert!(!tree.undo());
+            assert!(!tree.redo());
+
+            assert!(tree.move_to_child(0));
+            tree.replace_cursor(JSON::False);
+            assert_eq!(tree.move_by_siblings(2), 2);
+            let object = tree.cursor();
+            tree.delete_cursor().unwrap();
+            assert_eq!(text(&tree), "[false, false]");
+
+            // Undoing the delete should restore the object, and the cursor to where it was when the
+            // object was deleted
+            assert!(tree.undo());
+            assert_eq!(text(&tree), r#"[false, false, {"value": true}]"#);
+            assert_eq!(tree.cursor(), object);
+            assert!(tree.undo());
+            assert_eq!(text(&tree), r#"[true, false, {"value": true}]"#);
+            assert!(!tree.undo());
+            assert!(tree.redo());
+            assert!(tree.redo());
+            assert_eq!(text(&tree), "[false, false]");
+            assert!(!tree.redo());
+
+            // Making a new change after undoing should discard the redo history
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(tree.move_by_siblings(1), 1);
+            tree.replace_cursor(JSON::True);
+            assert_eq!(text(&tree), r#"[true, true, {"value": true}]"#);
+            assert!(!tree.redo());
+            assert!(tree.undo());
+            assert_eq!(text(&tree), r#"[true, false, {"value": true}]"#);
+        }
+
+        #[test]
+        fn changes_since() {
+            let mut tree = sample_tree();
+            let changes = |tree: &Tree, position| {
+                let changes = tree.changes_since(position)?;
+                Some(changes.iter().map(ToString::to_string).collect::<Vec<_>>())
+            };
+            assert_eq!(tree.history_position(), 0);
+            assert_eq!(changes(&tree, 0), Some(vec![]));
+            assert!(tree.move_to_child(0));
+            tree.delete_cursor().unwrap();
+            assert!(tree.move_to_path(&[1, 0, 1]));
+            tree.replace_cursor(JSON::Null);
+            assert_eq!(tree.history_position(), 2);
+            assert_eq!(
+                changes(&tree, 0),
+                Some(vec![
+                    "removed root[0]: true".to_string(),
+                    "replaced root[1].value: true with null".to_string()
+                ])
+            );
+            assert_eq!(
+                changes(&tree, 1),
+                Some(vec!["replaced root[1].value: true with null".to_string()])
+            );
+            // Undone versions can be compared too
+            assert!(tree.undo());
+            assert_eq!(
+                changes(&tree, 2),
+                Some(vec!["replaced root[1].value: null with true".to_string()])
+            );
+            assert_eq!(changes(&tree, 3), None);
+        }
+
+        #[test]
+        fn insert_child_from_char() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![])));
+            // Append 'true', then 'false' at the end, then an object at the start of the array
+            tree.insert_child_from_char('t', usize::MAX).unwrap();
+            assert!(tree.move_to_parent());
+            tree.insert_child_from_char('f', usize::MAX).unwrap();
+            // Leaves can't have children
+            assert_eq!(
+                tree.insert_child_from_char('t', 0),
+                Err(EditError::CannotInsert("bool"))
+            );
+            assert!(tree.move_to_parent());
+            tree.insert_child_from_char('o', 0).unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[{}, true, false]");
+
+            // Objects can only have fields inserted, which get a placeholder key and value
+            assert_eq!(
+                tree.insert_child_from_char('t', 0),
+                Err(EditError::NoNodeForChar {
+                    parent: "object",
+                    c: 't'
+                })
+            );
+            tree.insert_child_from_char('i', usize::MAX).unwrap();
+            assert_eq!(tree.cursor_node().kind_name(), "field");
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[{"key": true}, true, false]"#
+            );
+            // Arrays can't have fields inserted
+            assert!(tree.move_to_root());
+            assert_eq!(
+                tree.insert_child_from_char('i', 0),
+                Err(EditError::NoNodeForChar {
+                    parent: "array",
+                    c: 'i'
+                })
+            );
+
+            assert!(tree.undo());
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[{}, true, false]");
+        }
+
+        #[test]
+        fn insert_sibling_from_char() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![TestJSON::Null])));
+            // The root has no siblings
+            assert_eq!(
+                tree.insert_sibling_from_char('t', true),
+                Err(EditError::IsRoot)
+            );
+            assert_eq!(tree.sibling_index(), None);
+            assert!(tree.move_to_child(0));
+            tree.insert_sibling_from_char('t', true).unwrap();
+            assert_eq!(tree.sibling_index(), Some(1));
+            tree.insert_sibling_from_char('o', false).unwrap();
+            assert_eq!(tree.sibling_index(), Some(1));
+            assert_eq!(tree.cursor_node(), &JSON::Object(vec![]));
+            // Arrays can't contain fields, but values next to fields are given a placeholder key
+            assert_eq!(
+                tree.insert_sibling_from_char('i', true),
+                Err(EditError::NoNodeForChar {
+                    parent: "array",
+                    c: 'i'
+                })
+            );
+            tree.insert_child_from_char('i', 0).unwrap();
+            tree.insert_sibling_from_char('f', true).unwrap();
+            tree.insert_sibling_from_char('i', false).unwrap();
+            assert_eq!(tree.cursor_node().kind_name(), "field");
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[null, {"key": true, "key": true, "key": false}, true]"#
+            );
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[null, {"key": true, "key": false}, true]"#
+            );
+        }
+
+        #[test]
+        fn delete_cursor() {
+            let mut tree = sample_tree();
+            // Deleting a leaf should move the cursor to the next sibling
+            assert!(tree.move_to_child(0));
+            let deleted = tree.cursor();
+            tree.delete_cursor().unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[false, {"value": true}]"#
+            );
+            assert_eq!(tree.cursor_node(), &JSON::False);
+            assert_eq!(tree.path_to(deleted), None);
 
-    fn write_text(&self, string: &mut String, format: &Node::FormatStyle) {
-        self.snapshot().node_map.write_text(string, format);
+            // Deleting the last child (which has children of its own) should move the cursor to the
+            // previous sibling
+            assert_eq!(tree.move_by_siblings(1), 1);
+            let object = tree.cursor();
+            let field = tree.cursor_node().children()[0];
+            tree.delete_cursor().unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[false]");
+            assert_eq!(tree.cursor_node(), &JSON::False);
+            assert_eq!(tree.path_to(object), None);
+            assert_eq!(tree.path_to(field), None);
+
+            // Every deletion should be undoable, restoring the deleted nodes
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+            assert_eq!(tree.path_to(deleted), Some(vec![0]));
+        }
+
+        #[test]
+        fn delete_siblings() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+                TestJSON::Array(vec![TestJSON::True]),
+                TestJSON::False,
+            ])));
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.delete_siblings(0), Ok(0));
+            assert_eq!(tree.delete_siblings(2), Ok(2));
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, false]");
+            assert_eq!(tree.cursor(), tree.root_node().children()[1]);
+            // Huge counts should stop at the last sibling
+            assert!(tree.undo());
+            assert_eq!(tree.delete_siblings(usize::MAX), Ok(3));
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[true]");
+            assert_eq!(tree.cursor_node(), &JSON::True);
+            // The whole deletion should be undone at once
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                "[true, false, [true], false]"
+            );
+        }
+
+        #[test]
+        fn delete_only_child_and_root() {
+            let mut tree = sample_tree();
+            assert!(tree.move_to_child(2));
+            assert!(tree.move_to_child(0));
+            // The key and value of a field can't be removed from it
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.delete_cursor(), Err(EditError::CannotRemove("field")));
+            assert!(tree.move_to_parent());
+            // Deleting an only child should leave the cursor on the parent
+            tree.delete_cursor().unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, false, {}]");
+            assert_eq!(tree.cursor_node(), &JSON::Object(vec![]));
+
+            // Deleting the root should replace it with the default node
+            assert!(tree.move_to_parent());
+            tree.delete_cursor().unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "{}");
+            assert_eq!(tree.cursor(), tree.root());
+            assert!(tree.undo());
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, false, {}]");
+        }
+
+        #[test]
+        fn replace_node() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::Array(vec![TestJSON::True, TestJSON::Null]),
+                TestJSON::Object(vec![("k".to_string(), TestJSON::False)]),
+                TestJSON::Str("s".to_string()),
+            ])));
+            let text = |tree: &Tree| tree.to_text(&JSONFormat::Compact);
+
+            // Objects keep the elements of arrays, keyed by their indices
+            assert!(tree.move_to_child(0));
+            tree.replace_node(JSON::Object(vec![]));
+            assert_eq!(
+                text(&tree),
+                r#"[{"0": true, "1": null}, {"k": false}, "s"]"#
+            );
+            assert_eq!(tree.cursor_path(), vec![0]);
+            assert_eq!(tree.validate(), Ok(()));
+            // Arrays keep the values of objects' fields, but not their keys
+            assert_eq!(tree.move_by_siblings(1), 1);
+            tree.replace_node(JSON::Array(vec![]));
+            assert_eq!(text(&tree), r#"[{"0": true, "1": null}, [false], "s"]"#);
+            // Leaves can't hold children, so they are dropped
+            tree.replace_node(JSON::True);
+            assert_eq!(text(&tree), r#"[{"0": true, "1": null}, true, "s"]"#);
+            assert_eq!(tree.cursor_node(), &JSON::True);
+            // Leaves are simply replaced
+            assert_eq!(tree.move_by_siblings(1), 1);
+            tree.replace_node(JSON::Number("1".to_string()));
+            assert_eq!(text(&tree), r#"[{"0": true, "1": null}, true, 1]"#);
+            // The root can be replaced too
+            assert!(tree.move_to_root());
+            tree.replace_node(JSON::Object(vec![]));
+            assert_eq!(
+                text(&tree),
+                r#"{"0": {"0": true, "1": null}, "1": true, "2": 1}"#
+            );
+            assert_eq!(tree.cursor(), tree.root());
+            assert_eq!(tree.validate(), Ok(()));
+
+            // Each replacement is a single change, and the dropped nodes are still there to undo to
+            for expected in &[
+                r#"[{"0": true, "1": null}, true, 1]"#,
+                r#"[{"0": true, "1": null}, true, "s"]"#,
+                r#"[{"0": true, "1": null}, [false], "s"]"#,
+                r#"[{"0": true, "1": null}, {"k": false}, "s"]"#,
+                r#"[[true, null], {"k": false}, "s"]"#,
+            ] {
+                assert!(tree.undo());
+                assert_eq!(text(&tree), *expected);
+            }
+        }
+
+        #[test]
+        fn insert_and_remove_children_at_paths() {
+            let fixture = TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Array(vec![TestJSON::Null, TestJSON::False]),
+                TestJSON::Object(vec![("k".to_string(), TestJSON::Str("v".to_string()))]),
+            ]);
+            let leaf_map = build_map(&TestJSON::Null);
+            let leaf = Subtree::copy_from(&leaf_map, leaf_map.root()).unwrap();
+            let field_map =
+                build_map(&TestJSON::Object(vec![("new".to_string(), TestJSON::Null)]));
+            let field =
+                Subtree::copy_from(&field_map, field_map.root_node().children()[0]).unwrap();
+            let original = Spec::from_tree(build_map(&fixture));
+            let original_text = original.to_text(&JSONFormat::Compact);
+            let mut traversal = original.preorder();
+            let mut paths = Vec::new();
+            while traversal.next().is_some() {
+                paths.push(traversal.path().to_vec());
+            }
+
+            for path in &paths {
+                let node = original.node_at_path(path).unwrap();
+                let len = node.children().len();
+                let kind = node.kind_name();
+                for index in 0..len + 2 {
+                    // Insert a node that the parent can hold, so that only the arity matters
+                    let mut tree = original.clone();
+                    let subtree = if kind == "object" { &field } else { &leaf };
+                    let result = tree.insert_child_at_path(path, index, subtree);
+                    match kind {
+                        "array" | "object" => {
+                            assert_eq!(result, Ok(()));
+                            // Indices past the end append the new child
+                            let mut new_path = path.clone();
+                            new_path.push(index.min(len));
+                            assert_eq!(tree.cursor_path(), new_path);
+                            assert_eq!(tree.node_at_path(path).unwrap().children().len(), len + 1);
+                            assert_eq!(tree.validate(), Ok(()));
+                            assert!(tree.undo());
+                            assert!(!tree.undo());
+                        }
+                        _ => {
+                            assert_eq!(result, Err(EditError::CannotInsert(kind)));
+                            assert!(!tree.undo());
+                        }
+                    }
+                    assert_eq!(tree.to_text(&JSONFormat::Compact), original_text);
+
+                    let mut tree = original.clone();
+                    let result = tree.remove_child_at_path(path, index);
+                    if index >= len {
+                        assert_eq!(result, Err(EditError::IndexOutOfRange { index, len }));
+                    } else if kind == "field" {
+                        assert_eq!(result, Err(EditError::CannotRemove("field")));
+                    } else {
+                        let mut child_path = path.clone();
+                        child_path.push(index);
+                        let child = original.ref_at_path(&child_path).unwrap();
+                        let expected = Subtree::copy_from(&original, child);
+                        assert_eq!(result.ok(), expected);
+                        assert_eq!(tree.node_at_path(path).unwrap().children().len(), len - 1);
+                        assert_eq!(tree.validate(), Ok(()));
+                        assert!(tree.undo());
+                    }
+                    assert!(!tree.undo());
+                    assert_eq!(tree.to_text(&JSONFormat::Compact), original_text);
+                }
+            }
+
+            // Inserting at the start, and removing the last child
+            let mut tree = original.clone();
+            assert_eq!(tree.insert_child_at_path(&[], 0, &leaf), Ok(()));
+            assert_eq!(tree.remove_child_at_path(&[2], 1).map(|_| ()), Ok(()));
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[null, true, [null], {"k": "v"}]"#
+            );
+            // The parent must be able to hold the new child
+            assert_eq!(
+                tree.insert_child_at_path(&[3], 0, &leaf),
+                Err(EditError::InvalidChild(
+                    "objects can only contain fields".to_string()
+                ))
+            );
+            assert_eq!(
+                tree.insert_child_at_path(&[7], 0, &leaf),
+                Err(EditError::NoNode(vec![7]))
+            );
+            assert_eq!(
+                tree.remove_child_at_path(&[0, 0], 0).map(|_| ()),
+                Err(EditError::NoNode(vec![0, 0]))
+            );
+        }
+
+        #[test]
+        fn yank_and_paste() {
+            let mut tree = sample_tree();
+            assert!(tree.move_to_child(2));
+            let object = Subtree::copy_from(&tree, tree.cursor()).unwrap();
+            // Deleting the original shouldn't affect the copy
+            tree.delete_cursor().unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, false]");
+
+            tree.paste(&object, PastePosition::Before).unwrap();
+            assert_eq!(tree.cursor(), tree.root_node().children()[1]);
+            assert_eq!(tree.move_by_siblings(1), 1);
+            tree.paste(&object, PastePosition::After).unwrap();
+            assert_eq!(tree.cursor(), tree.root_node().children()[3]);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, {"value": true}, false, {"value": true}]"#
+            );
+
+            // `false` can't have children
+            assert_eq!(tree.move_by_siblings(-1), 1);
+            assert_eq!(
+                tree.paste(&object, PastePosition::Child),
+                Err(EditError::CannotInsert("bool"))
+            );
+            // The root has no siblings
+            assert!(tree.move_to_root());
+            assert_eq!(
+                tree.paste(&object, PastePosition::Before),
+                Err(EditError::IsRoot)
+            );
+            tree.paste(&object, PastePosition::Child).unwrap();
+            assert_eq!(tree.cursor(), tree.root_node().children()[4]);
+            // Objects can only contain fields
+            assert_eq!(
+                tree.paste(&object, PastePosition::Child),
+                Err(EditError::InvalidChild(
+                    "objects can only contain fields".to_string()
+                ))
+            );
+            assert!(tree.move_to_parent());
+
+            // Each paste should be undone on its own
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, {"value": true}, false]"#
+            );
+        }
+
+        #[test]
+        fn copy_and_paste_siblings() {
+            let mut tree = sample_tree();
+            assert!(tree.move_to_child(1));
+            let copies = tree.copy_siblings(usize::MAX);
+            assert_eq!(copies.len(), 2);
+            assert!(tree.move_to_root());
+            assert_eq!(tree.copy_siblings(3).len(), 1);
+            assert!(tree.copy_siblings(0).is_empty());
+
+            // The copies are pasted in order, with the cursor on the first of them
+            assert!(tree.move_to_child(0));
+            tree.paste_siblings(&copies, PastePosition::After).unwrap();
+            assert_eq!(tree.cursor(), tree.root_node().children()[1]);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}, false, {"value": true}]"#
+            );
+            // Nothing is pasted if any of the copies can't be (objects can only contain fields)
+            assert_eq!(tree.move_by_siblings(1), 1);
+            assert_eq!(
+                tree.paste_siblings(&copies, PastePosition::Child),
+                Err(EditError::InvalidChild(
+                    "objects can only contain fields".to_string()
+                ))
+            );
+            assert_eq!(
+                tree.paste_siblings(&[], PastePosition::After),
+                Err(EditError::NothingToPaste)
+            );
+            // The whole paste is undone at once
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+        }
+
+        #[test]
+        fn duplicate_cursor() {
+            let mut tree = sample_tree();
+            assert_eq!(tree.duplicate_cursor(), Err(EditError::IsRoot));
+            assert!(tree.move_to_child(2));
+            tree.duplicate_cursor().unwrap();
+            assert_eq!(tree.cursor(), tree.root_node().children()[3]);
+            assert_ne!(tree.cursor(), tree.root_node().children()[2]);
+            // Duplicated fields get a new key, which is numbered if the first choice is taken
+            assert!(tree.move_to_child(0));
+            tree.duplicate_cursor().unwrap();
+            assert!(tree.move_to_parent());
+            assert!(tree.move_to_child(0));
+            tree.duplicate_cursor().unwrap();
+            assert_eq!(tree.cursor_path(), vec![3, 1]);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}, "#.to_string()
+                    + r#"{"value": true, "value_copy2": true, "value_copy": true}]"#
+            );
+            // Keys can't have siblings, so can't be duplicated
+            assert!(tree.move_to_child(0));
+            assert_eq!(tree.duplicate_cursor(), Err(EditError::CannotInsert("field")));
+            // Each duplication is undone on its own
+            assert!(tree.undo());
+            assert_eq!(tree.cursor_path(), vec![3, 0]);
+        }
+
+        #[test]
+        fn insert_child_at() {
+            let mut tree = sample_tree();
+            tree.insert_child_at(JSON::Array(vec![]), 2).unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, [], {"value": true}]"#
+            );
+            // The cursor should be on the new node
+            assert_eq!(tree.cursor(), tree.root_node().children()[2]);
+            assert_eq!(tree.cursor_node(), &JSON::Array(vec![]));
+
+            // Indices past the end of the children should insert at the end
+            tree.insert_child_at(JSON::True, 0).unwrap();
+            assert!(tree.move_to_parent());
+            assert!(tree.move_to_parent());
+            tree.insert_child_at(JSON::False, 100).unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, [true], {"value": true}, false]"#
+            );
+            assert_eq!(tree.cursor(), tree.root_node().children()[4]);
+
+            // `false` can't have children
+            assert_eq!(
+                tree.insert_child(JSON::True),
+                Err(EditError::CannotInsert("bool"))
+            );
+            assert_eq!(tree.cursor_node(), &JSON::False);
+
+            // Every insertion should be undoable
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+            assert!(!tree.undo());
+        }
+
+        #[test]
+        fn replace_all_dry_run() {
+            let mut tree = sample_tree();
+            let is_true = |node: &JSON<Ref>| node == &JSON::True;
+            // A dry run shouldn't change the tree
+            let paths = tree.paths_to_matching(is_true);
+            assert_eq!(paths, vec![vec![0], vec![2, 0, 1]]);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+            assert!(!tree.undo());
+
+            // Replacing should change exactly the nodes from the dry run, as one change
+            assert_eq!(tree.replace_all(is_true, JSON::False), paths.len());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[false, false, {"value": false}]"#
+            );
+            assert_eq!(tree.replace_all(is_true, JSON::False), 0);
+            assert!(tree.undo());
+            assert!(!tree.undo());
+
+            // Replacing a node that contains the cursor should move the cursor to the replaced node
+            assert!(tree.move_to_child(2));
+            assert!(tree.move_to_child(0));
+            assert_eq!(
+                tree.replace_all(|node| node.kind_name() == "object", JSON::True),
+                1
+            );
+            assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, false, true]");
+            assert_eq!(tree.cursor(), tree.root_node().children()[2]);
+        }
+
+        #[test]
+        fn merge_with_next_sibling() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::Str("hello".to_string()),
+                TestJSON::Str("world".to_string()),
+                TestJSON::True,
+            ])));
+            // The root has no siblings to merge with
+            assert_eq!(tree.merge_with_next_sibling(" "), Err(EditError::IsRoot));
+
+            assert!(tree.move_to_child(0));
+            tree.merge_with_next_sibling(" ").unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"["hello world", true]"#
+            );
+            assert_eq!(tree.cursor_node(), &JSON::Str("hello world".to_string()));
+            // Strings can't be merged with non-strings
+            assert_eq!(
+                tree.merge_with_next_sibling(" "),
+                Err(EditError::CannotMerge {
+                    first: "string",
+                    second: "bool"
+                })
+            );
+            assert_eq!(tree.move_by_siblings(1), 1);
+            assert_eq!(
+                tree.merge_with_next_sibling(" "),
+                Err(EditError::NoSibling { offset: 1 })
+            );
+            assert_eq!(tree.move_by_siblings(-1), 1);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"["hello world", true]"#
+            );
+
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"["hello", "world", true]"#
+            );
+        }
+
+        #[test]
+        fn reorder_children() {
+            let mut tree = sample_tree();
+            assert!(tree.move_to_child(2));
+            assert!(tree.move_to_child(0));
+            let object = tree.root_node().children()[2];
+            // The cursor follows the node it was on
+            assert_eq!(tree.reorder_children(&[], &[2, 0, 1]), Ok(()));
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[{"value": true}, true, false]"#
+            );
+            assert_eq!(tree.cursor_path(), vec![0, 0]);
+            assert_eq!(tree.cursor_node().kind_name(), "field");
+            assert!(tree.move_to_parent());
+            assert_eq!(tree.cursor_node(), tree.get_node(object).unwrap());
+
+            assert_eq!(
+                tree.reorder_children(&[], &[0, 0, 1]),
+                Err(EditError::NotAPermutation { len: 3 })
+            );
+            assert_eq!(
+                tree.reorder_children(&[], &[0, 1]),
+                Err(EditError::NotAPermutation { len: 3 })
+            );
+            assert_eq!(
+                tree.reorder_children(&[0, 0], &[1, 0]),
+                Err(EditError::CannotReorder("field"))
+            );
+            assert_eq!(
+                tree.reorder_children(&[5], &[]),
+                Err(EditError::NoNode(vec![5]))
+            );
+            // The whole reordering is undone at once, along with the cursor
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+            assert_eq!(tree.cursor_path(), vec![2, 0]);
+        }
+
+        #[test]
+        fn swap_with_sibling() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+                TestJSON::Str("s".to_string()),
+            ])));
+            // The root has no siblings
+            assert_eq!(tree.swap_with_sibling(1), Err(EditError::IsRoot));
+
+            assert!(tree.move_to_child(0));
+            tree.swap_with_sibling(1).unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"[false, true, "s"]"#);
+            tree.swap_with_sibling(1).unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"[false, "s", true]"#);
+            // The cursor follows the moved node, so it can't move past the end
+            assert_eq!(tree.cursor_node(), &JSON::True);
+            assert_eq!(tree.cursor_path(), vec![2]);
+            assert_eq!(
+                tree.swap_with_sibling(1),
+                Err(EditError::NoSibling { offset: 1 })
+            );
+            tree.swap_with_sibling(-2).unwrap();
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"[true, "s", false]"#);
+            assert_eq!(
+                tree.swap_with_sibling(-1),
+                Err(EditError::NoSibling { offset: -1 })
+            );
+
+            // Each swap is a single undo step
+            assert!(tree.undo());
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"[false, "s", true]"#);
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"[true, false, "s"]"#);
+        }
+
+        #[test]
+        fn replacements_checked_by_parent() {
+            let mut tree: Tree =
+                Spec::from_tree(build_map(&TestJSON::Array(vec![
+                    TestJSON::Str("".to_string()),
+                    TestJSON::Object(vec![("".to_string(), TestJSON::Str("".to_string()))]),
+                ])));
+            // Keys must stay strings, so only the array element and the field's value are replaced
+            let is_empty_string = |n: &JSON<Ref>| n == &JSON::Str("".to_string());
+            assert_eq!(tree.paths_to_matching(is_empty_string).len(), 3);
+            assert_eq!(
+                tree.check_replacement_at(&[1, 0, 0], &JSON::True),
+                Err("the key of a field must be a string".to_string())
+            );
+            assert_eq!(tree.check_replacement_at(&[1, 0, 1], &JSON::True), Ok(()));
+            assert!(tree.check_replacement_at(&[5, 0], &JSON::True).is_err());
+            assert_eq!(tree.replace_all(is_empty_string, JSON::True), 2);
+            assert_eq!(tree.to_text(&JSONFormat::Compact), r#"[true, {"": true}]"#);
+            // Keys can't be wrapped either
+            assert!(tree.move_to_path(&[1, 0, 0]));
+            assert_eq!(
+                tree.wrap_cursor('a'),
+                Err(EditError::InvalidChild(
+                    "the key of a field must be a string".to_string()
+                ))
+            );
+            assert_eq!(tree.validate(), Ok(()));
+        }
+
+        #[test]
+        fn wrap_and_unwrap() {
+            let mut tree = sample_tree();
+            // Wrapping the root makes a new root
+            tree.wrap_cursor('a').unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[[true, false, {"value": true}]]"#
+            );
+            assert_eq!(tree.cursor(), tree.root());
+            assert!(tree.move_to_child(0));
+            assert!(tree.move_to_child(1));
+            tree.wrap_cursor('o').unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[[true, {"key": false}, {"value": true}]]"#
+            );
+            assert_eq!(tree.cursor_node().kind_name(), "object");
+            // Nodes that can't have children can't wrap anything, and fields can't be wrapped
+            assert_eq!(
+                tree.wrap_cursor('t'),
+                Err(EditError::CannotWrap {
+                    kind: "object",
+                    c: 't'
+                })
+            );
+            assert!(tree.move_to_child(0));
+            assert_eq!(
+                tree.wrap_cursor('a'),
+                Err(EditError::CannotWrap {
+                    kind: "field",
+                    c: 'a'
+                })
+            );
+            assert!(tree.move_to_parent());
+
+            // Unwrapping an object gives the value of its field
+            tree.unwrap_cursor().unwrap();
+            assert_eq!(tree.cursor_node(), &JSON::False);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[[true, false, {"value": true}]]"#
+            );
+            // Only nodes with a single child can be unwrapped
+            assert_eq!(tree.unwrap_cursor(), Err(EditError::CannotUnwrap("bool")));
+            assert!(tree.move_to_parent());
+            assert_eq!(tree.unwrap_cursor(), Err(EditError::CannotUnwrap("array")));
+            assert!(tree.move_to_parent());
+            tree.unwrap_cursor().unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, false, {"value": true}]"#
+            );
+            assert_eq!(tree.cursor(), tree.root());
+
+            // Every wrap and unwrap is a single undo step
+            assert!(tree.undo());
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[[true, {"key": false}, {"value": true}]]"#
+            );
+        }
+
+        #[test]
+        fn splice_cursor() {
+            let number = |n: &str| TestJSON::Number(n.to_string());
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                number("1"),
+                TestJSON::Array(vec![number("2"), number("3")]),
+                TestJSON::Object(vec![("k".to_string(), TestJSON::Array(vec![TestJSON::Null]))]),
+                TestJSON::Object(vec![
+                    ("a".to_string(), TestJSON::True),
+                    ("b".to_string(), TestJSON::False),
+                ]),
+            ])));
+            let text = |tree: &Tree| tree.to_text(&JSONFormat::Compact);
+            // Several children are spliced into the parent, and the cursor lands on the first
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.unwrap_or_splice_cursor(), Ok(()));
+            assert_eq!(
+                text(&tree),
+                r#"[1, 2, 3, {"k": [null]}, {"a": true, "b": false}]"#
+            );
+            assert_eq!(tree.cursor_path(), vec![1]);
+            assert_eq!(tree.cursor_node(), &JSON::Number("2".to_string()));
+            assert_eq!(tree.validate(), Ok(()));
+            // A single child is hoisted up a level, which for an object is its field's value
+            assert!(tree.move_to_path(&[3]));
+            assert_eq!(tree.unwrap_or_splice_cursor(), Ok(()));
+            assert_eq!(text(&tree), r#"[1, 2, 3, [null], {"a": true, "b": false}]"#);
+            assert_eq!(tree.cursor_path(), vec![3]);
+            assert!(tree.undo());
+
+            // Leaves have nothing to splice, and the root has nowhere to splice its children
+            assert!(tree.move_to_path(&[0]));
+            assert_eq!(
+                tree.unwrap_or_splice_cursor(),
+                Err(EditError::NothingToSplice("number"))
+            );
+            assert!(tree.move_to_root());
+            assert_eq!(tree.splice_cursor(), Err(EditError::IsRoot));
+            // Fields can't be spliced into an array, or keys and values into an object
+            assert!(tree.move_to_path(&[4]));
+            assert_eq!(
+                tree.unwrap_or_splice_cursor(),
+                Err(EditError::InvalidChild(
+                    "fields can only be inside objects".to_string()
+                ))
+            );
+            assert!(tree.move_to_child(0));
+            assert_eq!(
+                tree.splice_cursor(),
+                Err(EditError::InvalidChild(
+                    "objects can only contain fields".to_string()
+                ))
+            );
+            // Fields always have one key and one value, so nothing can be spliced into them
+            assert!(tree.move_to_path(&[3, 0, 1]));
+            assert_eq!(tree.splice_cursor(), Err(EditError::CannotSplice("field")));
+            assert_eq!(
+                text(&tree),
+                r#"[1, 2, 3, {"k": [null]}, {"a": true, "b": false}]"#
+            );
+            assert_eq!(tree.validate(), Ok(()));
+
+            // Each splice is a single undo step
+            assert!(tree.undo());
+            assert_eq!(
+                text(&tree),
+                r#"[1, [2, 3], {"k": [null]}, {"a": true, "b": false}]"#
+            );
+        }
+
+        #[test]
+        fn swap_object_fields() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Object(vec![
+                ("a".to_string(), TestJSON::True),
+                ("b".to_string(), TestJSON::False),
+            ])));
+            // Moving a field moves its key and value together
+            assert!(tree.move_to_child(1));
+            tree.swap_with_sibling(-1).unwrap();
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"{"b": false, "a": true}"#
+            );
+            // The key and value of a field can't be swapped
+            assert!(tree.move_to_child(0));
+            assert_eq!(
+                tree.swap_with_sibling(1),
+                Err(EditError::CannotReorder("field"))
+            );
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"{"b": false, "a": true}"#
+            );
+        }
+
+        #[test]
+        fn normalize_strings() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Object(vec![
+                (
+                    r#"\u006bey"#.to_string(),
+                    TestJSON::Str(r#"\/\u00e9"#.to_string()),
+                ),
+                ("plain".to_string(), TestJSON::Str("\\q".to_string())),
+                ("tidy".to_string(), TestJSON::Str("already".to_string())),
+            ])));
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.map_nodes(|node| node.normalized()), 2);
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"{"key": "/é", "plain": "\q", "tidy": "already"}"#
+            );
+            // The cursor shouldn't move
+            assert_eq!(tree.cursor_node().kind_name(), "field");
+            // Normalizing again should change nothing, and shouldn't create an undo step
+            assert_eq!(tree.map_nodes(|node| node.normalized()), 0);
+            // The whole normalization should be one undoable change
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"{"\u006bey": "\/\u00e9", "plain": "\q", "tidy": "already"}"#
+            );
+            assert!(!tree.undo());
+        }
+
+        #[test]
+        fn invert_booleans_in_subtree() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Array(vec![
+                    TestJSON::True,
+                    TestJSON::False,
+                    TestJSON::Str("true".to_string()),
+                ]),
+            ])));
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.map_subtree(|node| node.inverted()), 2);
+            // Only the booleans inside the selected subtree should be flipped
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, [false, true, "true"]]"#
+            );
+            assert!(tree.undo());
+            assert_eq!(
+                tree.to_text(&JSONFormat::Compact),
+                r#"[true, [true, false, "true"]]"#
+            );
+            assert!(!tree.undo());
+        }
+
+        #[test]
+        fn move_to_root() {
+            let mut tree = sample_tree();
+            assert!(!tree.move_to_root());
+            assert_eq!(tree.cursor(), tree.root());
+
+            assert!(tree.move_to_child(2));
+            assert!(tree.move_to_child(0));
+            assert!(tree.move_to_child(1));
+            assert!(tree.move_to_root());
+            assert_eq!(tree.cursor(), tree.root());
+            // Moving the cursor isn't an undoable change
+            assert!(!tree.undo());
+        }
+
+        #[test]
+        fn move_to_ancestor() {
+            let mut tree = sample_tree();
+            // Select the value of the object's only field
+            assert!(tree.move_to_child(2));
+            assert!(tree.move_to_child(0));
+            assert!(tree.move_to_child(1));
+            let value = tree.cursor();
+
+            // There's no enclosing string, so the cursor shouldn't move
+            assert!(!tree.move_to_ancestor(|node| node.kind_name() == "string"));
+            assert_eq!(tree.cursor(), value);
+            // The cursor itself is a bool, but it isn't its own ancestor
+            assert!(!tree.move_to_ancestor(|node| node.kind_name() == "bool"));
+            assert_eq!(tree.cursor(), value);
+            // Jumping to the enclosing array should skip over the object
+            assert!(tree.move_to_ancestor(|node| node.kind_name() == "array"));
+            assert_eq!(tree.cursor(), tree.root());
+        }
+
+        #[test]
+        fn find_matching_and_move_to_path() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::Str("apple".to_string()),
+                TestJSON::Object(vec![("pineapple".to_string(), TestJSON::True)]),
+                TestJSON::Str("pear".to_string()),
+            ])));
+            let contains =
+                |query: &'static str| move |node: &JSON<Ref>| node.display_name().contains(query);
+            assert_eq!(tree.cursor_path(), Vec::<usize>::new());
+            // Matches are found in document order, starting after the cursor
+            assert_eq!(tree.find_matching(contains("apple"), true), Some(vec![0]));
+            assert!(tree.move_to_path(&[0]));
+            assert_eq!(
+                tree.find_matching(contains("apple"), true),
+                Some(vec![1, 0, 0])
+            );
+            // The search wraps around the ends of the document
+            assert!(tree.move_to_path(&[2]));
+            assert_eq!(tree.find_matching(contains("apple"), true), Some(vec![0]));
+            assert_eq!(
+                tree.find_matching(contains("apple"), false),
+                Some(vec![1, 0, 0])
+            );
+            assert!(tree.move_to_path(&[0]));
+            assert_eq!(
+                tree.find_matching(contains("apple"), false),
+                Some(vec![1, 0, 0])
+            );
+            // The cursor itself is only matched once every other node has been checked
+            assert_eq!(
+                tree.find_matching(contains("ppl"), true),
+                Some(vec![1, 0, 0])
+            );
+            assert_eq!(tree.find_matching(contains("\"apple"), true), Some(vec![0]));
+            assert_eq!(tree.find_matching(contains("banana"), true), None);
+
+            // Invalid paths shouldn't move the cursor
+            assert!(tree.move_to_path(&[1, 0, 1]));
+            assert_eq!(tree.cursor_node(), &JSON::True);
+            assert!(!tree.move_to_path(&[1, 1]));
+            assert!(!tree.move_to_path(&[0, 0]));
+            assert_eq!(tree.cursor_path(), vec![1, 0, 1]);
+            // The cursor path should be correct, so moving up should reach the object
+            assert!(tree.move_to_parent());
+            assert!(tree.move_to_parent());
+            assert_eq!(tree.cursor_node().kind_name(), "object");
+            assert!(tree.move_to_path(&[]));
+            assert_eq!(tree.cursor(), tree.root());
+
+            // Paths can also be found from references
+            let field = tree.root_node().children()[1];
+            let field = tree.get_node(field).unwrap().children()[0];
+            assert_eq!(tree.path_to(field), Some(vec![1, 0]));
+            assert_eq!(tree.path_to(tree.root()), Some(vec![]));
+        }
+
+        #[test]
+        fn paths_name_nodes() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::Object(vec![
+                    ("a".to_string(), TestJSON::Array(vec![TestJSON::Null])),
+                    ("b".to_string(), TestJSON::Str("leaf".to_string())),
+                ]),
+                TestJSON::Array(vec![TestJSON::True, TestJSON::False]),
+            ])));
+            // Every node's path leads back to that node
+            let mut paths = Vec::new();
+            let mut traversal = tree.preorder();
+            while let Some(id) = traversal.next() {
+                let path = traversal.path().to_vec();
+                assert_eq!(tree.ref_at_path(&path), Some(id));
+                assert_eq!(tree.path_to(id), Some(path.clone()));
+                paths.push(path);
+            }
+            assert_eq!(paths.len(), 12);
+            // Paths past the end of a list of children or through a leaf lead nowhere
+            for invalid in &[&[2][..], &[0, 2], &[0, 1, 1, 0], &[1, 0, 0]] {
+                assert_eq!(tree.ref_at_path(invalid), None);
+                assert_eq!(tree.node_at_path(invalid), None);
+            }
+
+            // After an edit, the paths of the unchanged nodes still point to the same nodes, and
+            // undoing and redoing the edit brings back exactly the same nodes at every path
+            let nodes_at_paths = |tree: &Tree| {
+                paths
+                    .iter()
+                    .map(|path| tree.node_at_path(path).map(JSON::kind_name))
+                    .collect::<Vec<_>>()
+            };
+            let before = nodes_at_paths(&tree);
+            assert!(tree.move_to_path(&[1, 1]));
+            tree.replace_cursor(JSON::Null);
+            let after = nodes_at_paths(&tree);
+            assert_eq!(after[11], Some("null"));
+            assert_eq!(before[..11], after[..11]);
+            assert!(tree.undo());
+            assert_eq!(nodes_at_paths(&tree), before);
+            assert!(tree.redo());
+            assert_eq!(nodes_at_paths(&tree), after);
+        }
+
+        #[test]
+        fn move_to_key() {
+            let mut tree = sample_tree();
+            // Array elements have no keys
+            assert!(tree.move_to_child(1));
+            assert!(!tree.move_to_key());
+            assert_eq!(tree.cursor_path(), vec![1]);
+            assert!(tree.move_to_root());
+            assert!(!tree.move_to_key());
+            // The key can be reached from the field, its value, or itself
+            for start in &[&[2, 0][..], &[2, 0, 1], &[2, 0, 0]] {
+                assert!(tree.move_to_path(start));
+                assert!(tree.move_to_key());
+                assert_eq!(tree.cursor_path(), vec![2, 0, 0]);
+                assert_eq!(tree.cursor_node(), &JSON::Str("value".to_string()));
+            }
+            assert_eq!(tree.node_at_path(&[2, 0, 1]), Some(&JSON::True));
+            assert_eq!(tree.node_at_path(&[2, 1]), None);
+        }
+
+        #[test]
+        fn move_to_same_key_in_next_record() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(vec![
+                TestJSON::Object(vec![
+                    ("name".to_string(), TestJSON::Str("first".to_string())),
+                    ("value".to_string(), TestJSON::True),
+                ]),
+                TestJSON::Object(vec![("other".to_string(), TestJSON::True)]),
+                TestJSON::Object(vec![("value".to_string(), TestJSON::False)]),
+            ])));
+            // Select the value of the first object's "value" field
+            assert!(tree.move_to_child(0));
+            assert!(tree.move_to_child(1));
+            assert!(tree.move_to_child(1));
+            assert_eq!(tree.cursor_node(), &JSON::True);
+
+            // The second object has no "value" field, so it should be skipped
+            assert!(tree.move_to_same_key_in_next_record());
+            assert_eq!(tree.cursor_node(), &JSON::False);
+            let third_object = tree.root_node().children()[2];
+            let third_field = tree.get_node(third_object).unwrap().children()[0];
+            assert_eq!(
+                tree.cursor(),
+                tree.get_node(third_field).unwrap().children()[1]
+            );
+            // There are no more records
+            assert!(!tree.move_to_same_key_in_next_record());
+            // The cursor path should be correct, so moving up should reach the third object
+            assert!(tree.move_to_parent());
+            assert!(tree.move_to_parent());
+            assert_eq!(tree.cursor(), third_object);
+            assert_eq!(tree.move_by_siblings(-1), 1);
+        }
+
+        #[test]
+        fn move_by_pages_in_large_array() {
+            let mut tree = Spec::from_tree(build_map(&TestJSON::Array(
+                (0..1000)
+                    .map(|i| {
+                        if i % 2 == 0 {
+                            TestJSON::True
+                        } else {
+                            TestJSON::False
+                        }
+                    })
+                    .collect(),
+            )));
+            let page_size = 40;
+            let children = tree.root_node().children().to_vec();
+
+            assert!(tree.move_to_child(0));
+            assert_eq!(tree.move_by_siblings(page_size), 40);
+            assert_eq!(tree.cursor(), children[40]);
+            assert_eq!(tree.move_by_siblings(page_size), 40);
+            assert_eq!(tree.cursor(), children[80]);
+            assert_eq!(tree.move_by_siblings(-page_size), 40);
+            assert_eq!(tree.cursor(), children[40]);
+            // Paging past either end of the array should stop at the first/last element
+            assert_eq!(tree.move_by_siblings(-page_size * 2), 40);
+            assert_eq!(tree.cursor(), children[0]);
+            assert_eq!(tree.move_by_siblings(10_000), 999);
+            assert_eq!(tree.cursor(), children[999]);
+            assert_eq!(tree.move_by_siblings(page_size), 0);
+            assert_eq!(tree.cursor(), children[999]);
+        }
     }
 }
@@ -0,0 +1,121 @@
+//! The bookkeeping shared by every [`EditableTree`] for grouping changes into transactions (see
+//! [`EditableTree::begin_edit`])
+
+use super::cursor_path::Segment;
+use crate::node_map::Reference;
+
+// Imports used solely for doc-comments
+#[allow(unused_imports)]
+use super::EditableTree;
+
+/// The history position and cursor path when a transaction began
+type Checkpoint<Ref> = (usize, Vec<Segment<Ref>>);
+
+/// The transactions that are open on a tree whose undo history is a [`Vec`] of versions of type
+/// `V`.  Transactions nest, but only the outermost one decides what ends up in the history.
+#[derive(Debug, Clone)]
+pub(super) struct Transactions<Ref: Reference, V> {
+    /// The history position and cursor path when each open transaction began, outermost first.
+    /// Moving the cursor changes the current version in place, so the cursor path has to be kept
+    /// to put it back.
+    checkpoints: Vec<Checkpoint<Ref>>,
+    /// The versions that could be redone when the outermost transaction began, if a change inside
+    /// it has removed them from the history.  These are put back if the transaction doesn't end
+    /// up changing anything.
+    redo: Option<Vec<V>>,
+}
+
+impl<Ref: Reference, V> Transactions<Ref, V> {
+    /// Creates a `Transactions` with no open transactions
+    pub fn new() -> Self {
+        Transactions {
+            checkpoints: Vec::new(),
+            redo: None,
+        }
+    }
+
+    /// Returns `true` if a transaction is open
+    pub fn is_open(&self) -> bool {
+        !self.checkpoints.is_empty()
+    }
+
+    /// Start a transaction at history position `position`, with the cursor at `cursor_path`
+    pub fn begin(&mut self, position: usize, cursor_path: Vec<Segment<Ref>>) {
+        self.checkpoints.push((position, cursor_path));
+    }
+
+    /// Remove every version after `current` from `history`, because a new version is about to be
+    /// added.  Inside a transaction, the versions are kept so that they can be put back.
+    pub fn discard_redo(&mut self, history: &mut Vec<V>, current: usize) {
+        let redo = history.split_off(current + 1);
+        if self.is_open() && self.redo.is_none() {
+            self.redo = Some(redo);
+        }
+    }
+
+    /// Finish the innermost open transaction, returning `false` if there isn't one.  Finishing the
+    /// outermost transaction replaces every version it added to `history` with the last one, so
+    /// that its changes are undone in one go.
+    pub fn commit(&mut self, history: &mut Vec<V>, current: &mut usize) -> bool {
+        let (start, _) = match self.checkpoints.pop() {
+            Some(checkpoint) => checkpoint,
+            None => return false,
+        };
+        if self.is_open() {
+            return true;
+        }
+        if *current > start + 1 {
+            history.drain(start + 1..*current);
+            *current = start + 1;
+        }
+        let redo = self.redo.take();
+        // A transaction that didn't change anything leaves the redo history alone
+        if *current == start {
+            history.extend(redo.into_iter().flatten());
+        }
+        true
+    }
+
+    /// Throw away every version that the innermost open transaction added to `history`, and put
+    /// the cursor back where it was when the transaction began using `set_cursor_path`.  Returns
+    /// `false` if there isn't an open transaction.
+    pub fn rollback(
+        &mut self,
+        history: &mut Vec<V>,
+        current: &mut usize,
+        set_cursor_path: impl FnOnce(&mut V, Vec<Segment<Ref>>),
+    ) -> bool {
+        let (start, cursor_path) = match self.checkpoints.pop() {
+            Some(checkpoint) => checkpoint,
+            None => return false,
+        };
+        // Until a version is added, `history` past `start` is still the redo history
+        if self.redo.is_some() {
+            history.truncate(start + 1);
+        }
+        *current = start;
+        set_cursor_path(&mut history[start], cursor_path);
+        // The versions that could be redone belong to the outermost transaction
+        if !self.is_open() {
+            history.extend(self.redo.take().into_iter().flatten());
+        }
+        true
+    }
+
+    /// Returns every version that is being kept to be redone, and the position and cursor path
+    /// of every open transaction, so that they can be updated (e.g. by garbage collection)
+    pub fn kept_mut(&mut self) -> (&mut [V], &mut [Checkpoint<Ref>]) {
+        let redo = match &mut self.redo {
+            Some(redo) => redo.as_mut_slice(),
+            None => &mut [],
+        };
+        (redo, &mut self.checkpoints)
+    }
+
+    /// Close every open transaction without changing the history, e.g. because the history has
+    /// been replaced
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.redo = None;
+    }
+}
@@ -0,0 +1,252 @@
+//! Automatic saving of the tree, to guard against losing work if Sapling crashes
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The settings and state of automatic saving.  An auto-save becomes due once a given number of
+/// edits have been made, or once a given time has passed since the last save (whichever comes
+/// first).  With neither set, auto-saves never become due.
+///
+/// Auto-saving only writes the file - it doesn't affect the [`EditableTree`] or its undo
+/// history.
+///
+/// [`EditableTree`]: crate::editable_tree::EditableTree
+#[derive(Debug, Clone)]
+pub struct AutoSave {
+    path: PathBuf,
+    edit_threshold: Option<usize>,
+    interval: Option<Duration>,
+    edits_since_save: usize,
+    last_save: Instant,
+}
+
+impl AutoSave {
+    /// Creates an `AutoSave` which writes to `path`, but which never becomes due until an edit
+    /// threshold or interval is set
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AutoSave {
+            path: path.into(),
+            edit_threshold: None,
+            interval: None,
+            edits_since_save: 0,
+            last_save: Instant::now(),
+        }
+    }
+
+    /// Returns the path of the file that is written by auto-saves
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Make an auto-save due after every `threshold` edits, or never if `threshold` is [`None`]
+    pub fn set_edit_threshold(&mut self, threshold: Option<usize>) {
+        self.edit_threshold = threshold;
+    }
+
+    /// Make an auto-save due once `interval` has passed since the last save, or never if
+    /// `interval` is [`None`]
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+    }
+
+    /// Record that an edit has been made to the tree
+    pub fn record_edit(&mut self) {
+        self.edits_since_save += 1;
+    }
+
+    /// Returns the number of edits made since the last save
+    pub fn edits_since_save(&self) -> usize {
+        self.edits_since_save
+    }
+
+    /// Returns `true` if the tree should be saved at the time `now`.  Nothing is saved unless an
+    /// edit has been made since the last save.
+    pub fn is_due(&self, now: Instant) -> bool {
+        if self.edits_since_save == 0 {
+            return false;
+        }
+        let enough_edits = self
+            .edit_threshold
+            .is_some_and(|t| self.edits_since_save >= t);
+        let enough_time = self
+            .interval
+            .is_some_and(|i| now.duration_since(self.last_save) >= i);
+        enough_edits || enough_time
+    }
+
+    /// [Atomically write](write_atomically) `contents` to the auto-save file, and reset the edit
+    /// count and interval if that succeeded
+    pub fn save(&mut self, contents: &str, now: Instant) -> io::Result<()> {
+        write_atomically(&self.path, contents)?;
+        self.edits_since_save = 0;
+        self.last_save = now;
+        Ok(())
+    }
+}
+
+/// Returns the path that auto-saves of the file at `path` should be written to, so that a crash
+/// can be recovered from without overwriting the file itself.  This is a hidden file in the same
+/// directory, like `.data.json.sapling~` for `data.json`.  Returns [`None`] if `path` has no file
+/// name.
+pub fn backup_path(path: &Path) -> Option<PathBuf> {
+    let mut backup_name = std::ffi::OsString::from(".");
+    backup_name.push(path.file_name()?);
+    backup_name.push(".sapling~");
+    Some(path.with_file_name(backup_name))
+}
+
+/// Returns the [backup](backup_path) of the file at `path` if it was written after the file was
+/// last modified (or the file no longer exists), in which case it probably contains work that
+/// was never saved because Sapling crashed.  Returns [`None`] if there is no such backup.
+pub fn recoverable_backup(path: &Path) -> Option<PathBuf> {
+    let backup = backup_path(path)?;
+    let backup_time = std::fs::metadata(&backup).and_then(|m| m.modified()).ok()?;
+    match std::fs::metadata(path).and_then(|m| m.modified()) {
+        Ok(file_time) if file_time >= backup_time => None,
+        _ => Some(backup),
+    }
+}
+
+/// Write `contents` to the file at `path` by first writing it to a temporary file in the same
+/// directory, and then renaming the temporary file over `path`.  This way, a crash part way
+/// through writing can never leave `path` truncated.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".sapling-tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path).inspect_err(|_| {
+        // Don't leave the temporary file lying around
+        let _ = std::fs::remove_file(&temp_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backup_path, recoverable_backup, write_atomically, AutoSave};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn edit_threshold() {
+        let start = Instant::now();
+        let mut auto_save = AutoSave::new("unused.json");
+        auto_save.set_edit_threshold(Some(3));
+        for _ in 0..2 {
+            auto_save.record_edit();
+            assert!(!auto_save.is_due(start));
+        }
+        auto_save.record_edit();
+        assert!(auto_save.is_due(start));
+        assert_eq!(auto_save.edits_since_save(), 3);
+
+        // Without a threshold or interval, auto-saves are never due
+        auto_save.set_edit_threshold(None);
+        assert!(!auto_save.is_due(start + Duration::from_secs(1000)));
+    }
+
+    #[test]
+    fn interval() {
+        let mut auto_save = AutoSave::new("unused.json");
+        auto_save.set_interval(Some(Duration::from_secs(30)));
+        let later = Instant::now() + Duration::from_secs(60);
+        // Nothing needs saving until an edit is made
+        assert!(!auto_save.is_due(later));
+        auto_save.record_edit();
+        assert!(auto_save.is_due(later));
+    }
+
+    #[test]
+    fn save_resets_count() {
+        let dir = std::env::temp_dir().join(format!("sapling-auto-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tree.json");
+
+        let mut auto_save = AutoSave::new(&path);
+        auto_save.set_edit_threshold(Some(1));
+        auto_save.record_edit();
+        let now = Instant::now();
+        assert!(auto_save.is_due(now));
+        auto_save.save("[true]", now).unwrap();
+        assert!(!auto_save.is_due(now));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[true]");
+
+        // Overwriting should replace the file and leave no temporary files behind
+        write_atomically(&path, "[]").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[]");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_the_file_alone() {
+        let dir = std::env::temp_dir().join(format!("sapling-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tree.json");
+        write_atomically(&path, "[true]").unwrap();
+
+        // Renaming a file over a non-empty directory fails, and shouldn't leave the temporary
+        // file behind
+        let blocked = dir.join("blocked");
+        std::fs::create_dir_all(blocked.join("inner")).unwrap();
+        assert!(write_atomically(&blocked, "[]").is_err());
+        assert!(write_atomically(Path::new("/"), "[]").is_err());
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["blocked", "tree.json"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[true]");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_paths() {
+        assert_eq!(
+            backup_path(Path::new("dir/data.json")),
+            Some(PathBuf::from("dir/.data.json.sapling~"))
+        );
+        assert_eq!(
+            backup_path(Path::new("data.json")),
+            Some(PathBuf::from(".data.json.sapling~"))
+        );
+        assert_eq!(backup_path(Path::new("/")), None);
+    }
+
+    #[test]
+    fn recovering_backups() {
+        let dir = std::env::temp_dir().join(format!("sapling-recovery-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tree.json");
+        let backup = backup_path(&path).unwrap();
+        let set_modified = |path: &Path, secs_ago: u64| {
+            let time = SystemTime::now() - Duration::from_secs(secs_ago);
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(time).unwrap();
+        };
+
+        // No backup, so nothing to recover
+        assert_eq!(recoverable_backup(&path), None);
+        std::fs::write(&path, "[]").unwrap();
+        assert_eq!(recoverable_backup(&path), None);
+        // A backup written after the file was saved has unsaved work in it
+        write_atomically(&backup, "[true]").unwrap();
+        set_modified(&path, 60);
+        set_modified(&backup, 30);
+        assert_eq!(recoverable_backup(&path), Some(backup.clone()));
+        // ... but not once the file has been saved again
+        set_modified(&path, 10);
+        assert_eq!(recoverable_backup(&path), None);
+        // If the file has gone, the backup is all that's left
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recoverable_backup(&path), Some(backup));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
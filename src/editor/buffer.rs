@@ -0,0 +1,164 @@
+//! The state of a single document open in the editor
+
+use super::auto_save::AutoSave;
+use super::display_cache::DisplayCache;
+use super::duplicate_keys::DuplicateKeys;
+use super::folds::FoldState;
+use super::script::Script;
+use crate::ast_spec::ASTSpec;
+use crate::editable_tree::EditableTree;
+use crate::node_map::Reference;
+use std::collections::BTreeMap;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a file on disk looked like at some point, which is compared with the file later on to
+/// notice when another program has changed it
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FileStamp {
+    /// When the file was last modified
+    pub modified: SystemTime,
+    /// The length of the file in bytes, which catches changes made within the resolution of
+    /// `modified`
+    pub len: u64,
+}
+
+impl FileStamp {
+    /// Returns the current stamp of the file at `path`
+    pub fn read(path: &Path) -> io::Result<FileStamp> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileStamp {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// One of the documents open in an [`Editor`](super::Editor).  Everything that belongs to a
+/// single document (its tree and undo history, the cursor, its file and how far it's been
+/// scrolled) lives here, so switching between buffers leaves each of them exactly as it was.
+pub struct Buffer<R: Reference, T: ASTSpec<R>, E: EditableTree<R, T>> {
+    /// The [`EditableTree`] being edited, which also holds the cursor and the undo history
+    pub tree: E,
+    /// The file that the tree is written to by the `w` command, if one has been set
+    pub file_path: Option<PathBuf>,
+    /// The [stamp](FileStamp) of `file_path` when the tree was last read from it or written to
+    /// it, or [`None`] if the file didn't exist then
+    pub disk_stamp: Option<FileStamp>,
+    /// Whether the tree has been edited since it was last saved (or loaded)
+    pub dirty: bool,
+    /// The [position in the undo history](EditableTree::history_position) of the version of the
+    /// tree that was last saved (or loaded), or [`None`] if that version isn't known or has been
+    /// discarded from the history (by editing after undoing past it)
+    pub saved_version: Option<usize>,
+    /// Which nodes are folded or pinned in the display
+    pub folds: FoldState<R>,
+    /// The nodes that have been bookmarked, by the letter of their mark.  Like folds, marks are
+    /// kept as references, so they follow their node when other parts of the tree are edited.
+    /// A node that is deleted (or replaced by an edit inside it) leaves its mark behind.
+    pub marks: BTreeMap<char, R>,
+    /// The commands that have changed the tree since it was loaded
+    pub script: Script,
+    /// The settings for automatically saving the tree, if auto-saving is enabled
+    pub auto_save: Option<AutoSave>,
+    /// The line of the text that is displayed at the top of the screen
+    pub scroll_offset: usize,
+    /// The column of the text that is displayed at the left edge of the screen
+    pub horizontal_scroll: usize,
+    /// The number of nodes in the tree, or [`None`] if it needs to be recounted
+    pub node_count: Option<usize>,
+    /// The tokens of the start of the displayed text, or [`None`] if they need to be regenerated
+    pub display_cache: Option<DisplayCache<R>>,
+    /// The records that have been checked for duplicate keys.  This has to be cleared whenever
+    /// the tree is replaced or garbage collected.
+    pub duplicate_keys: DuplicateKeys<R>,
+    _node: PhantomData<T>,
+}
+
+impl<R: Reference, T: ASTSpec<R>, E: EditableTree<R, T>> Buffer<R, T, E> {
+    /// Creates a `Buffer` for editing `tree`, which isn't written to any file until one is set
+    pub fn new(tree: E, folds: FoldState<R>) -> Self {
+        Buffer {
+            saved_version: Some(tree.history_position()),
+            tree,
+            file_path: None,
+            disk_stamp: None,
+            dirty: false,
+            folds,
+            marks: BTreeMap::new(),
+            script: Script::new(),
+            auto_save: None,
+            scroll_offset: 0,
+            horizontal_scroll: 0,
+            node_count: None,
+            display_cache: None,
+            duplicate_keys: DuplicateKeys::new(),
+            _node: PhantomData,
+        }
+    }
+
+    /// Check the records that have changed since the last check for duplicate keys, returning
+    /// how many entries in the tree have a duplicate key
+    pub fn check_duplicate_keys(&mut self) -> usize {
+        let root = self.tree.root();
+        self.duplicate_keys.count(&self.tree, root)
+    }
+
+    /// Returns how many entries in the tree had a duplicate key when it was
+    /// [last checked](Buffer::check_duplicate_keys), or 0 if this version of the tree hasn't
+    /// been checked
+    pub fn duplicate_key_count(&self) -> usize {
+        self.duplicate_keys
+            .checked_count(self.tree.root())
+            .unwrap_or(0)
+    }
+
+    /// Record that the current version of the tree is the one in its file
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+        self.saved_version = Some(self.tree.history_position());
+    }
+
+    /// Record the [stamp](FileStamp) that the buffer's file has now, so that later changes to
+    /// it by other programs can be noticed.  A file that doesn't exist has no stamp.
+    pub fn record_disk_stamp(&mut self) -> io::Result<()> {
+        self.disk_stamp = None;
+        if let Some(path) = &self.file_path {
+            match FileStamp::read(path) {
+                Ok(stamp) => self.disk_stamp = Some(stamp),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the buffer's file has been changed (by another program) since its
+    /// [stamp was recorded](Buffer::record_disk_stamp).  A file that has been deleted hasn't
+    /// changed, since writing it can't overwrite anything.
+    pub fn changed_on_disk(&self) -> io::Result<bool> {
+        let (path, stamp) = match (&self.file_path, &self.disk_stamp) {
+            (Some(path), Some(stamp)) => (path, stamp),
+            _ => return Ok(false),
+        };
+        match FileStamp::read(path) {
+            Ok(current) => Ok(current != *stamp),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the name of this buffer shown to the user, which is the name of its file
+    pub fn name(&self) -> String {
+        match &self.file_path {
+            Some(path) => path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            None => "[No file]".to_string(),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! A builder for setting up an [`Editor`] before it's run, for programs that embed Sapling
+
+use super::clipboard::SystemClipboard;
+use super::config::Config;
+use super::frontend::{DeferredTerm, Frontend};
+use super::keymap::KeyMap;
+use super::{Editor, EditorError};
+use crate::ast_spec::ASTSpec;
+use crate::editable_tree::EditableTree;
+use crate::node_map::Reference;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Sets up an [`Editor`] for a tree, made by [`Editor::builder`].  Every option has a default, so
+/// only the ones that matter need to be set before calling [`build`](EditorBuilder::build).
+pub struct EditorBuilder<R: Reference, T: ASTSpec<R>, E: EditableTree<R, T>> {
+    tree: E,
+    format_style: T::FormatStyle,
+    file_path: Option<PathBuf>,
+    read_only: bool,
+    config: Option<Config>,
+    keymap: Option<KeyMap>,
+    log_target: Option<String>,
+    frontend: Option<Arc<dyn Frontend>>,
+    _node: PhantomData<R>,
+}
+
+impl<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>> EditorBuilder<Ref, Node, E> {
+    /// Creates a builder for an editor of `tree`, with every option left at its default
+    pub(super) fn new(tree: E) -> Self {
+        EditorBuilder {
+            tree,
+            format_style: Node::FormatStyle::default(),
+            file_path: None,
+            read_only: false,
+            config: None,
+            keymap: None,
+            log_target: None,
+            frontend: None,
+            _node: PhantomData,
+        }
+    }
+
+    /// Set the style that the tree is displayed in.  This defaults to the `FormatStyle`'s
+    /// [`Default`].
+    pub fn format_style(mut self, format_style: Node::FormatStyle) -> Self {
+        self.format_style = format_style;
+        self
+    }
+
+    /// Set the file that the tree is written to by `w` (see [`Editor::set_file_path`])
+    pub fn file_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file_path = Some(path.into());
+        self
+    }
+
+    /// Set whether the tree can only be browsed, not edited (see [`Editor::set_read_only`])
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Start the editor with the settings in `config` (see [`Editor::set_config`]), instead of
+    /// the [defaults](Config::default)
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Rebind the commands named in `keymap`.  If the bindings can't be applied,
+    /// [`build`](EditorBuilder::build) returns [`EditorError::Keymap`].
+    pub fn keymap(mut self, keymap: KeyMap) -> Self {
+        self.keymap = Some(keymap);
+        self
+    }
+
+    /// Set the target of the records that the editor sends to the [`log`] crate, so that the
+    /// embedding program can tell them apart from its own.  This defaults to `sapling::editor`.
+    pub fn log_target(mut self, target: impl Into<String>) -> Self {
+        self.log_target = Some(target.into());
+        self
+    }
+
+    /// Draw the editor on, and read its input from, `frontend` instead of the terminal (see
+    /// [`Editor::with_frontend`])
+    pub fn frontend(mut self, frontend: Arc<dyn Frontend>) -> Self {
+        self.frontend = Some(frontend);
+        self
+    }
+
+    /// Build the [`Editor`].  Unless another [frontend](EditorBuilder::frontend) was given, the
+    /// terminal isn't opened until the editor is [run](Editor::run), so this can only fail if the
+    /// [keymap](EditorBuilder::keymap) can't be applied.
+    pub fn build(self) -> Result<Editor<Ref, Node, E>, EditorError> {
+        let uses_terminal = self.frontend.is_none();
+        let frontend = self
+            .frontend
+            .unwrap_or_else(|| Arc::new(DeferredTerm::new()));
+        let mut editor = Editor::with_frontend(self.tree, self.format_style, frontend);
+        // The system clipboard is only useful alongside the user's own terminal
+        if uses_terminal {
+            editor.system_clipboard = SystemClipboard::detect().map(|c| Box::new(c) as Box<_>);
+        }
+        if let Some(keymap) = &self.keymap {
+            editor
+                .command_table
+                .apply_keymap(keymap)
+                .map_err(EditorError::Keymap)?;
+        }
+        if let Some(config) = self.config {
+            editor.set_config(config);
+        }
+        if let Some(target) = self.log_target {
+            editor.log_target = target;
+        }
+        editor.set_file_path(self.file_path);
+        editor.set_read_only(self.read_only);
+        Ok(editor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::frontend::TestFrontend;
+    use super::super::keymap::KeyMap;
+    use super::super::{Editor, EditorError, ExitSummary};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use crate::node_map::vec::Index;
+    use std::sync::Arc;
+
+    fn tree() -> Spec<JSON<Index>> {
+        Spec::from_tree(TestJSON::Array(vec![TestJSON::True, TestJSON::False]).build_node_map())
+    }
+
+    #[test]
+    fn default_options() {
+        let frontend = Arc::new(TestFrontend::with_keys(40, 8, "q"));
+        let mut editor = Editor::builder(tree()).frontend(frontend).build().unwrap();
+        assert!(!editor.is_read_only());
+        assert_eq!(editor.file_path(), None);
+        assert_eq!(editor.log_target, "sapling::editor");
+        assert_eq!(
+            editor.run().unwrap(),
+            ExitSummary {
+                saved: true,
+                path: None
+            }
+        );
+    }
+
+    #[test]
+    fn read_only_rejects_edits() {
+        let frontend = Arc::new(TestFrontend::with_keys(80, 8, "crfq"));
+        let mut editor = Editor::builder(tree())
+            .format_style(JSONFormat::Compact)
+            .read_only(true)
+            .frontend(frontend)
+            .build()
+            .unwrap();
+        // Nothing was edited, so `q` quits straight away
+        assert!(editor.run().unwrap().saved);
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            "[true, false]"
+        );
+        assert!(editor
+            .log
+            .iter()
+            .any(|(_, message)| message.contains("read-only")));
+    }
+
+    #[test]
+    fn file_path_and_exit_summary() {
+        let path =
+            std::env::temp_dir().join(format!("sapling-builder-{}.json", std::process::id()));
+        let build = |keys: &str| {
+            let frontend = Arc::new(TestFrontend::with_keys(40, 8, keys));
+            Editor::builder(tree())
+                .file_path(&path)
+                .format_style(JSONFormat::Compact)
+                .frontend(frontend)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            build("crfwq").run().unwrap(),
+            ExitSummary {
+                saved: true,
+                path: Some(path.clone())
+            }
+        );
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("[false"));
+        // Discarding the changes is reported, so the embedding program can react
+        assert_eq!(
+            build("crtq!").run().unwrap(),
+            ExitSummary {
+                saved: false,
+                path: Some(path.clone())
+            }
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keymaps_and_log_targets() {
+        let mut keymap = KeyMap::new();
+        keymap.bind("j", "quit");
+        let frontend = Arc::new(TestFrontend::with_keys(40, 8, "j"));
+        let mut editor = Editor::builder(tree())
+            .keymap(keymap)
+            .log_target("my-app::tree")
+            .frontend(frontend)
+            .build()
+            .unwrap();
+        assert_eq!(editor.log_target, "my-app::tree");
+        assert!(editor.run().is_ok());
+
+        // Keymaps that can't be applied stop the editor from being built
+        let mut keymap = KeyMap::new();
+        keymap.bind("k", "not-a-command");
+        let error = Editor::builder(tree())
+            .keymap(keymap)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(&error, EditorError::Keymap(_)));
+        assert_eq!(
+            error.to_string(),
+            "Couldn't apply the keymap: 'not-a-command' is not a command"
+        );
+    }
+
+    #[test]
+    fn terminal_is_opened_by_run() {
+        // Building never needs a terminal, even when one will be used
+        let mut editor = Editor::builder(tree()).build().unwrap();
+        // We can only check for the error if the tests aren't being run in a terminal
+        if std::fs::File::open("/dev/tty").is_err() {
+            assert!(matches!(editor.run(), Err(EditorError::Terminal(_))));
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Access to the system clipboard, so that subtrees can be copied between Sapling and other
+//! programs
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A clipboard that text can be copied to and pasted from.  The [`Editor`](super::Editor) only
+/// uses the clipboard through this trait, so that tests (or other frontends) can provide their
+/// own.
+pub trait Clipboard {
+    /// Replace the contents of the clipboard with `text`, returning an error message if that
+    /// failed
+    fn set_text(&mut self, text: &str) -> Result<(), String>;
+
+    /// Returns the text on the clipboard, or an error message if it couldn't be read
+    fn get_text(&mut self) -> Result<String, String>;
+}
+
+/// A program, along with the arguments it needs
+type Tool = (&'static str, &'static [&'static str]);
+
+/// The tools that can copy to and paste from the clipboard on each platform, as pairs of the
+/// copying and pasting commands.  The first one that is installed is used.
+const TOOLS: &[(Tool, Tool)] = &[
+    (("pbcopy", &[]), ("pbpaste", &[])),
+    (("wl-copy", &[]), ("wl-paste", &["--no-newline"])),
+    (
+        ("xclip", &["-selection", "clipboard"]),
+        ("xclip", &["-selection", "clipboard", "-o"]),
+    ),
+    (
+        ("xsel", &["--clipboard", "--input"]),
+        ("xsel", &["--clipboard", "--output"]),
+    ),
+    (
+        ("clip.exe", &[]),
+        (
+            "powershell.exe",
+            &["-NoProfile", "-Command", "Get-Clipboard"],
+        ),
+    ),
+];
+
+/// The system clipboard, which is used by running the platform's clipboard tools (like `pbcopy`
+/// and `pbpaste` on macOS, or `xclip` on X11).  This way, no clipboard libraries are needed and
+/// Sapling works the same without a clipboard, e.g. over SSH.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SystemClipboard {
+    copy: Tool,
+    paste: Tool,
+}
+
+impl SystemClipboard {
+    /// Returns the system clipboard, or [`None`] if none of the supported clipboard tools are
+    /// installed
+    pub fn detect() -> Option<Self> {
+        let path = std::env::var_os("PATH")?;
+        let is_installed =
+            |program: &str| std::env::split_paths(&path).any(|dir| dir.join(program).is_file());
+        TOOLS
+            .iter()
+            .find(|(copy, paste)| is_installed(copy.0) && is_installed(paste.0))
+            .map(|&(copy, paste)| SystemClipboard { copy, paste })
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn set_text(&mut self, text: &str) -> Result<(), String> {
+        let (program, args) = self.copy;
+        let mut child = Command::new(Path::new(program))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("couldn't run '{}': {}", program, e))?;
+        // Dropping stdin closes it, so the tool knows that the text is finished
+        let written = child.stdin.take().unwrap().write_all(text.as_bytes());
+        let status = child.wait().map_err(|e| e.to_string())?;
+        written.map_err(|e| format!("couldn't write to '{}': {}", program, e))?;
+        if !status.success() {
+            return Err(format!("'{}' failed ({})", program, status));
+        }
+        Ok(())
+    }
+
+    fn get_text(&mut self) -> Result<String, String> {
+        let (program, args) = self.paste;
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("couldn't run '{}': {}", program, e))?;
+        if !output.status.success() {
+            return Err(format!("'{}' failed ({})", program, output.status));
+        }
+        String::from_utf8(output.stdout).map_err(|_| "the clipboard isn't text".to_string())
+    }
+}
@@ -0,0 +1,1110 @@
+//! A data-driven table of the commands that the user can type
+
+use super::export::ExportFormat;
+use super::keymap::KeyMap;
+use super::Action;
+use crate::editable_tree::PastePosition;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// A function that builds an [`Action`] from the count prefix and the [`char`] arguments of a
+/// command.  This is shared between every key sequence bound to the same command.
+type CommandHandler = Rc<dyn Fn(Option<usize>, &[char]) -> Action>;
+
+/// A single command in a [`CommandTable`]
+#[derive(Clone)]
+struct CommandSpec {
+    /// The characters that the user types to start this command (e.g. `"gp"`)
+    name: String,
+    /// The name of this command in the built-in table.  Commands are recorded in scripts with
+    /// this name, so that scripts can be replayed whatever keys the user has bound.
+    canonical_name: String,
+    /// The name used to refer to this command in a [`KeyMap`] (e.g. `"ancestor"`)
+    action_name: String,
+    /// How many [`char`]s the user must type after the name (e.g. the `a` in `"gpa"`)
+    num_char_args: usize,
+    /// Whether this command can be given a count prefix (e.g. the `3` in `"3it"`)
+    takes_count: bool,
+    /// A one-line description of what this command does, shown in the help screen
+    description: String,
+    /// Generates the [`Action`] when the command is complete
+    handler: CommandHandler,
+}
+
+/// What an incomplete command is waiting for the user to type
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) enum Pending<'t> {
+    /// More of a command's name.  This contains the name and [`KeyMap`] name of every command
+    /// that could still be typed.
+    Name(Vec<(&'t str, &'t str)>),
+    /// A [`char`] argument of the command with the given [`KeyMap`] name
+    CharArg(&'t str),
+}
+
+/// A command that has been typed in full
+struct Completed<'t> {
+    /// The text of the count prefix (which may be empty)
+    count_str: &'t str,
+    count: Option<usize>,
+    spec: &'t CommandSpec,
+    args: Vec<char>,
+}
+
+impl Completed<'_> {
+    /// Returns the [`Action`] that this command performs
+    fn action(&self) -> Action {
+        (self.spec.handler)(self.count, &self.args)
+    }
+
+    /// Returns this command typed using the built-in key sequence
+    fn canonical(&self) -> String {
+        let mut canonical = self.count_str.to_string();
+        canonical.push_str(&self.spec.canonical_name);
+        canonical.extend(&self.args);
+        canonical
+    }
+}
+
+/// The result of matching a typed command against a [`CommandTable`]
+enum Match<'t> {
+    /// The command isn't complete yet
+    Incomplete(Pending<'t>),
+    /// The command is complete, but is also the start of a longer command (like `"q"` and
+    /// `"q!"`), so the user might still be typing
+    Ambiguous(Completed<'t>, Pending<'t>),
+    /// The command doesn't match any command in the table
+    Undefined,
+    /// The command is complete
+    Complete(Completed<'t>),
+}
+
+/// What to do with a command that hasn't been finished, depending on how long ago the user
+/// typed its last key (see [`CommandTable::resolve_timeout`])
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Resolution {
+    /// Keep waiting for the next key, for at most the given time before resolving the command
+    /// again.  [`None`] means that the command never times out.
+    Wait(Option<Duration>),
+    /// The user stopped typing after a complete command that is the start of a longer one, so
+    /// the complete command is performed.  This contains the action and the command typed with
+    /// the built-in keys (so that it can be recorded in scripts).
+    Perform(Action, String),
+    /// The user stopped typing part-way through a command name that isn't a command on its own,
+    /// so it is thrown away
+    Discard,
+}
+
+/// A table of every command that the user can type, which converts commands into [`Action`]s.
+///
+/// Every command has the form `[<count>]<name><char args>`, where `<count>` is an optional
+/// decimal number, `<name>` is a fixed string and `<char args>` is a fixed number of [`char`]s
+/// (e.g. the type of node to insert).
+pub(super) struct CommandTable {
+    commands: Vec<CommandSpec>,
+}
+
+impl CommandTable {
+    /// Creates a `CommandTable` with no commands in it
+    pub(super) fn empty() -> Self {
+        CommandTable {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Add a command to the table, replacing any command that has the same name.  Returns `true`
+    /// if a command was replaced.
+    pub(super) fn register(
+        &mut self,
+        name: &str,
+        action_name: &str,
+        num_char_args: usize,
+        takes_count: bool,
+        description: &str,
+        handler: impl Fn(Option<usize>, &[char]) -> Action + 'static,
+    ) -> bool {
+        let spec = CommandSpec {
+            name: name.to_string(),
+            canonical_name: name.to_string(),
+            action_name: action_name.to_string(),
+            num_char_args,
+            takes_count,
+            description: description.to_string(),
+            handler: Rc::new(handler),
+        };
+        match self.commands.iter_mut().find(|c| c.name == name) {
+            Some(existing) => {
+                *existing = spec;
+                true
+            }
+            None => {
+                self.commands.push(spec);
+                false
+            }
+        }
+    }
+
+    /// Add a command with no arguments and no count, which always produces `action`
+    pub(super) fn register_simple(
+        &mut self,
+        name: &str,
+        action_name: &str,
+        description: &str,
+        action: Action,
+    ) -> bool {
+        self.register(name, action_name, 0, false, description, move |_, _| {
+            action.clone()
+        })
+    }
+
+    /// Change the key sequences of the commands named in `keymap`.  Each command named by the
+    /// keymap can only be typed with the key sequences it is given there, and every other command
+    /// keeps its current keys.  Returns an error (and leaves the table unchanged) if the keymap
+    /// names a command that doesn't exist, binds a sequence that can't be typed, or binds a
+    /// sequence that is equal to or the start of another command's sequence.
+    pub(super) fn apply_keymap(&mut self, keymap: &KeyMap) -> Result<(), String> {
+        let mut commands = Vec::new();
+        // Whether each command's sequence came from the keymap
+        let mut is_rebound = Vec::new();
+        for (keys, action_name) in keymap.bindings() {
+            if !self.commands.iter().any(|c| &c.action_name == action_name) {
+                return Err(format!("'{}' is not a command", action_name));
+            }
+            if keys.is_empty() || keys.starts_with(|c: char| c.is_ascii_digit()) {
+                return Err(format!("'{}' can't be typed as a command", keys));
+            }
+        }
+        for spec in &self.commands {
+            let new_keys = keymap
+                .bindings()
+                .iter()
+                .filter(|(_, action_name)| action_name == &spec.action_name)
+                .map(|(keys, _)| keys)
+                .collect::<Vec<_>>();
+            if new_keys.is_empty() {
+                commands.push(spec.clone());
+                is_rebound.push(false);
+            }
+            for keys in new_keys {
+                commands.push(CommandSpec {
+                    name: keys.clone(),
+                    ..spec.clone()
+                });
+                is_rebound.push(true);
+            }
+        }
+        // Check that no rebound sequence clashes with any other sequence.  The built-in commands
+        // are allowed to be the start of each other (e.g. `q` and `q!`).
+        for (i, a) in commands.iter().enumerate() {
+            for (j, b) in commands.iter().enumerate() {
+                if i != j && (is_rebound[i] || is_rebound[j]) && b.name.starts_with(&a.name) {
+                    return Err(format!(
+                        "'{}' ({}) conflicts with '{}' ({})",
+                        a.name, a.action_name, b.name, b.action_name
+                    ));
+                }
+            }
+        }
+        self.commands = commands;
+        Ok(())
+    }
+
+    /// Returns the key sequence (e.g. `"[count]i<c>"`) and description of every command, in the
+    /// order they were registered
+    pub(super) fn help_entries(&self) -> Vec<(String, &str)> {
+        self.commands
+            .iter()
+            .map(|spec| {
+                let mut keys = String::new();
+                if spec.takes_count {
+                    keys.push_str("[count]");
+                }
+                keys.push_str(&spec.name);
+                for _ in 0..spec.num_char_args {
+                    keys.push_str("<c>");
+                }
+                (keys, spec.description.as_str())
+            })
+            .collect()
+    }
+
+    /// Returns the [canonical name](CommandSpec::canonical_name) of a command without arguments
+    /// that performs `action` when typed on its own, if there is one.  This lets actions that
+    /// weren't typed as keys (e.g. from the `:` prompt) be recorded in scripts.
+    pub(super) fn canonical_for_action(&self, action: &Action) -> Option<&str> {
+        self.commands
+            .iter()
+            .find(|c| c.num_char_args == 0 && (c.handler)(None, &[]) == *action)
+            .map(|c| c.canonical_name.as_str())
+    }
+
+    /// Returns the key sequence of the command with a given [`KeyMap`] name, if there is one
+    pub(super) fn keys_for(&self, action_name: &str) -> Option<&str> {
+        self.commands
+            .iter()
+            .find(|c| c.action_name == action_name)
+            .map(|c| c.name.as_str())
+    }
+
+    /// Attempt to convert a command as a `&`[`str`] into an [`Action`].
+    /// This parses the string from the start, and returns when it finds a valid command.
+    ///
+    /// Therefore, `"q489flshb"` will be treated like `"q"`, and will return `Some(Action::Quit)`
+    /// even though `"q489flshb"` is not technically valid.
+    /// This function is run every time the user types a command character, and so the user would
+    /// not be able to input `"q489flshb"` to this function because doing so would require them to
+    /// first input every possible prefix of `"q489flshb"`, including `"q"`.
+    ///
+    /// If a complete command is also the start of a longer command (like `"q"` and `"q!"`), then
+    /// the shorter command is treated as incomplete until the next keypress.  If that keypress
+    /// continues the longer command then the longer command is parsed as usual, otherwise the
+    /// shorter command is returned and the extra keypress is ignored (so `"qx"` quits).  If no
+    /// keypress comes in time, [`resolve_timeout`](CommandTable::resolve_timeout) performs the
+    /// shorter command instead.
+    ///
+    /// This returns:
+    /// - [`None`] if the command is incomplete.
+    /// - [`Action::Undefined`] if the command is not defined (like the command "X").
+    /// - The corresponding [`Action`], otherwise.
+    pub(super) fn parse(&self, command: &str) -> Option<Action> {
+        match self.find(command) {
+            Match::Incomplete(_) | Match::Ambiguous(..) => None,
+            Match::Undefined => Some(Action::Undefined),
+            Match::Complete(completed) => Some(completed.action()),
+        }
+    }
+
+    /// Converts a complete command into the same command typed using the built-in key sequences
+    /// (e.g. if `k` is bound to `quit`, then `"k"` becomes `"q"`).  Returns [`None`] if `command`
+    /// isn't a complete, defined command.
+    pub(super) fn canonical(&self, command: &str) -> Option<String> {
+        match self.find(command) {
+            Match::Complete(completed) => Some(completed.canonical()),
+            _ => None,
+        }
+    }
+
+    /// Decide what to do with the unfinished `command` if the user typed its last key `elapsed`
+    /// ago and waits at most `timeout` between the keys of a command.  Before the timeout, this
+    /// is always [`Resolution::Wait`].  Afterwards, a complete command that is the start of a
+    /// longer one (e.g. `"q"`, when `"q!"` exists) is performed, and part of a command name is
+    /// discarded.  Counts and [`char`] arguments never time out, since they can't be mistaken
+    /// for anything shorter.
+    pub(super) fn resolve_timeout(
+        &self,
+        command: &str,
+        elapsed: Duration,
+        timeout: Duration,
+    ) -> Resolution {
+        let has_name = !command
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .is_empty();
+        let on_timeout = match self.find(command) {
+            Match::Ambiguous(completed, _) => {
+                Resolution::Perform(completed.action(), completed.canonical())
+            }
+            Match::Incomplete(Pending::Name(_)) if has_name => Resolution::Discard,
+            _ => return Resolution::Wait(None),
+        };
+        match timeout.checked_sub(elapsed) {
+            Some(remaining) if !remaining.is_zero() => Resolution::Wait(Some(remaining)),
+            _ => on_timeout,
+        }
+    }
+
+    /// Returns what the user still has to type to complete `command`, or [`None`] if `command` is
+    /// already complete or can't be completed
+    pub(super) fn pending<'t>(&'t self, command: &'t str) -> Option<Pending<'t>> {
+        match self.find(command) {
+            Match::Incomplete(pending) | Match::Ambiguous(_, pending) => Some(pending),
+            _ => None,
+        }
+    }
+
+    /// Match `command` against the commands in this table (see [`CommandTable::parse`])
+    fn find<'t>(&'t self, command: &'t str) -> Match<'t> {
+        // Consume the count prefix of the command (e.g. the "3" in "3it").  Counts that are too
+        // large to fit in a `usize` are treated as `usize::MAX`, since they will be clamped anyway.
+        let count_len = command
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(command.len());
+        let (count_str, rest) = command.split_at(count_len);
+        let count = if count_str.is_empty() {
+            None
+        } else {
+            Some(count_str.parse().unwrap_or(usize::MAX))
+        };
+        // Commands that don't take counts can't be used if a count was given
+        let candidates = self
+            .commands
+            .iter()
+            .filter(|c| count.is_none() || c.takes_count);
+        let names_starting_with = |prefix: &str| {
+            candidates
+                .clone()
+                .filter(|c| c.name.starts_with(prefix))
+                .map(|c| (c.name.as_str(), c.action_name.as_str()))
+                .collect()
+        };
+        if rest.is_empty() {
+            return Match::Incomplete(Pending::Name(names_starting_with("")));
+        }
+
+        // Find the longest command name that the command starts with
+        let mut is_prefix_of_name = false;
+        let mut matched: Option<&CommandSpec> = None;
+        for spec in candidates.clone() {
+            if rest.starts_with(&spec.name) {
+                if matched.is_none_or(|m| spec.name.len() > m.name.len()) {
+                    matched = Some(spec);
+                }
+            } else if spec.name.starts_with(rest) {
+                is_prefix_of_name = true;
+            }
+        }
+        let completed = matched.and_then(|spec| {
+            let args = rest[spec.name.len()..]
+                .chars()
+                .take(spec.num_char_args)
+                .collect::<Vec<_>>();
+            (args.len() == spec.num_char_args).then_some(Completed {
+                count_str,
+                count,
+                spec,
+                args,
+            })
+        });
+        if is_prefix_of_name {
+            // A longer command might still be typed, so wait for the next keypress.  A command
+            // that has started taking its arguments waits for them instead (e.g. `"` waits for
+            // the register in `"ay`), so that it doesn't time out like part of a name would.
+            let pending = match (&completed, matched) {
+                (None, Some(spec)) => Pending::CharArg(&spec.action_name),
+                _ => Pending::Name(names_starting_with(rest)),
+            };
+            return match completed {
+                Some(completed) => Match::Ambiguous(completed, pending),
+                None => Match::Incomplete(pending),
+            };
+        }
+        match (completed, matched) {
+            (Some(completed), _) => Match::Complete(completed),
+            // The user hasn't typed all of the arguments yet
+            (None, Some(spec)) => Match::Incomplete(Pending::CharArg(&spec.action_name)),
+            (None, None) => Match::Undefined,
+        }
+    }
+}
+
+/// Converts the count prefix of a command that repeats an action into the number of repetitions.
+/// Missing counts and counts of `0` both mean 'once', and counts are clamped so that they can
+/// always be used as an [`isize`] offset.
+fn repeat_count(count: Option<usize>) -> usize {
+    count.unwrap_or(1).clamp(1, isize::MAX as usize)
+}
+
+/// How many siblings `}` and `{` move past for each repetition
+const SIBLING_JUMP: usize = 5;
+
+/// Converts the count prefix of `}` or `{` into the number of siblings to move past
+fn jump_distance(count: Option<usize>) -> usize {
+    repeat_count(count)
+        .saturating_mul(SIBLING_JUMP)
+        .min(isize::MAX as usize)
+}
+
+impl Default for CommandTable {
+    /// Creates a `CommandTable` containing all of Sapling's built-in commands
+    fn default() -> Self {
+        let mut table = CommandTable::empty();
+        table.register_simple("?", "help", "Show this help", Action::ShowHelp);
+        table.register_simple(
+            "q",
+            "quit",
+            "Quit, unless there are unsaved changes",
+            Action::Quit,
+        );
+        table.register_simple(
+            "q!",
+            "force-quit",
+            "Quit, discarding unsaved changes",
+            Action::ForceQuit,
+        );
+        table.register_simple("w", "write", "Write the tree to its file", Action::Write);
+        table.register_simple(
+            "wq",
+            "write-quit",
+            "Write the tree to its file and quit",
+            Action::WriteAndQuit,
+        );
+        table.register(
+            "i",
+            "insert-child",
+            1,
+            true,
+            "Insert a child node at index <count> (default 0)",
+            |count, args| Action::InsertChild(args[0], count.unwrap_or(0)),
+        );
+        table.register(
+            "a",
+            "append-child",
+            1,
+            false,
+            "Append a child node",
+            |_, args| Action::AppendChild(args[0]),
+        );
+        table.register(
+            "o",
+            "open-next-sibling",
+            1,
+            false,
+            "Insert a node after the selected node",
+            |_, args| Action::InsertNextSibling(args[0]),
+        );
+        table.register(
+            "O",
+            "open-prev-sibling",
+            1,
+            false,
+            "Insert a node before the selected node",
+            |_, args| Action::InsertPrevSibling(args[0]),
+        );
+        // Replacing is idempotent, so repeating it has no extra effect
+        table.register(
+            "r",
+            "replace",
+            1,
+            true,
+            "Replace the selected node",
+            |_, args| Action::Replace(args[0]),
+        );
+        table.register_simple(
+            "s",
+            "edit-value",
+            "Edit the value of the selected string (ESC to finish)",
+            Action::EditLiteral,
+        );
+        table.register_simple(
+            "k",
+            "rename-key",
+            "Rename the key of the selected object field (ESC to finish)",
+            Action::RenameKey,
+        );
+        table.register(
+            ".",
+            "repeat",
+            0,
+            true,
+            "Repeat the last edit <count> times",
+            |count, _| Action::RepeatLastEdit(repeat_count(count)),
+        );
+        table.register_simple("u", "undo", "Undo the last change", Action::Undo);
+        table.register_simple("R", "redo", "Redo the last undone change", Action::Redo);
+        table.register(
+            "du",
+            "show-changes",
+            0,
+            true,
+            "List the changes since the last save (or since <count> versions ago)",
+            |count, _| Action::ShowChanges(count),
+        );
+        table.register_simple(
+            "J",
+            "merge",
+            "Merge the selected node with its next sibling",
+            Action::MergeWithNextSibling,
+        );
+        table.register_simple(
+            "F",
+            "cycle-format",
+            "Switch between the ways of displaying the tree",
+            Action::CycleFormatStyle,
+        );
+        table.register_simple(
+            "#",
+            "line-numbers",
+            "Show or hide line numbers",
+            Action::ToggleLineNumbers,
+        );
+        table.register(
+            "W",
+            "wrap",
+            1,
+            false,
+            "Wrap the selected node in a new node",
+            |_, args| Action::Wrap(args[0]),
+        );
+        table.register_simple(
+            "U",
+            "unwrap",
+            "Replace the selected node with its children",
+            Action::Unwrap,
+        );
+        table.register_simple(
+            ">",
+            "move-down",
+            "Swap the selected node with its next sibling",
+            Action::SwapWithNextSibling,
+        );
+        table.register_simple(
+            "<",
+            "move-up",
+            "Swap the selected node with its previous sibling",
+            Action::SwapWithPrevSibling,
+        );
+        table.register(
+            "x",
+            "delete",
+            0,
+            true,
+            "Delete <count> nodes, starting with the selected node",
+            |count, _| Action::Delete(repeat_count(count)),
+        );
+        // Macros
+        table.register(
+            "Q",
+            "record-macro",
+            1,
+            false,
+            "Record a macro into register <c> (Q again stops)",
+            |_, args| Action::StartRecording(args[0]),
+        );
+        table.register(
+            "@",
+            "play-macro",
+            1,
+            true,
+            "Play the macro in register <c> <count> times",
+            |count, args| Action::PlayMacro(args[0], repeat_count(count)),
+        );
+        // Yanking and pasting subtrees
+        table.register(
+            "yy",
+            "yank",
+            0,
+            true,
+            "Yank <count> subtrees, starting with the selected node",
+            |count, _| Action::Yank(repeat_count(count)),
+        );
+        table.register(
+            "\"",
+            "register",
+            2,
+            true,
+            "Yank <count> subtrees into register <c> (\"<c>y), or paste it (\"<c>p)",
+            |count, args| match args {
+                ['"', 'y'] => Action::Yank(repeat_count(count)),
+                ['"', 'p'] => Action::Paste(PastePosition::After),
+                [c, 'y'] => Action::YankInto(*c, repeat_count(count)),
+                [c, 'p'] => Action::PasteFrom(*c),
+                _ => Action::Undefined,
+            },
+        );
+        table.register_simple(
+            "v",
+            "visual",
+            "Select a range of siblings (n/N extend it, and x or y act on all of it)",
+            Action::StartVisual,
+        );
+        table.register_simple(
+            "Pn",
+            "paste-after",
+            "Paste after the selected node",
+            Action::Paste(PastePosition::After),
+        );
+        table.register_simple(
+            "PN",
+            "paste-before",
+            "Paste before the selected node",
+            Action::Paste(PastePosition::Before),
+        );
+        table.register_simple(
+            "Pc",
+            "paste-child",
+            "Paste as the last child of the selected node",
+            Action::Paste(PastePosition::Child),
+        );
+        table.register_simple(
+            "D",
+            "duplicate",
+            "Insert a copy of the selected node after it",
+            Action::Duplicate,
+        );
+        // The system clipboard
+        table.register_simple(
+            "\"+y",
+            "copy-to-clipboard",
+            "Copy the selected subtree to the system clipboard",
+            Action::CopyToClipboard,
+        );
+        table.register_simple(
+            "\"+p",
+            "paste-from-clipboard",
+            "Paste the system clipboard after the selected node",
+            Action::PasteFromClipboard,
+        );
+        // Copying records
+        table.register_simple(
+            "yq",
+            "copy-query-string",
+            "Copy the selected record as a query string",
+            Action::CopyRecord(ExportFormat::QueryString),
+        );
+        table.register_simple(
+            "ye",
+            "copy-env-lines",
+            "Copy the selected record as environment variables",
+            Action::CopyRecord(ExportFormat::EnvLines),
+        );
+        // Movement
+        table.register_simple(
+            "c",
+            "first-child",
+            "Move to the first child",
+            Action::MoveToFirstChild,
+        );
+        table.register_simple("p", "parent", "Move to the parent", Action::MoveToParent);
+        table.register(
+            "n",
+            "next-sibling",
+            0,
+            true,
+            "Move forward <count> siblings",
+            |count, _| Action::MoveToNextSibling(repeat_count(count)),
+        );
+        table.register(
+            "N",
+            "prev-sibling",
+            0,
+            true,
+            "Move back <count> siblings",
+            |count, _| Action::MoveToPrevSibling(repeat_count(count)),
+        );
+        table.register(
+            "}",
+            "next-siblings-jump",
+            0,
+            true,
+            "Move forward 5 siblings, <count> times",
+            |count, _| Action::MoveToNextSibling(jump_distance(count)),
+        );
+        table.register(
+            "{",
+            "prev-siblings-jump",
+            0,
+            true,
+            "Move back 5 siblings, <count> times",
+            |count, _| Action::MoveToPrevSibling(jump_distance(count)),
+        );
+        table.register_simple(
+            "H",
+            "first-sibling",
+            "Move to the first sibling",
+            Action::MoveToSibling(0),
+        );
+        table.register_simple(
+            "L",
+            "last-sibling",
+            "Move to the last sibling",
+            Action::MoveToSibling(usize::MAX),
+        );
+        table.register(
+            "G",
+            "go-to-sibling",
+            0,
+            true,
+            "Move to sibling number <count> (default the last)",
+            |count, _| Action::MoveToSibling(count.map_or(usize::MAX, |n| n.max(1) - 1)),
+        );
+        // Searching
+        table.register_simple(
+            "/",
+            "search",
+            "Search for a node by its text",
+            Action::StartSearch,
+        );
+        table.register_simple(
+            "m",
+            "next-match",
+            "Move to the next search match",
+            Action::SearchNext,
+        );
+        table.register_simple(
+            "M",
+            "prev-match",
+            "Move to the previous search match",
+            Action::SearchPrev,
+        );
+        table.register_simple(
+            "]d",
+            "next-duplicate-key",
+            "Move to the next entry whose key is used more than once in its record",
+            Action::NextDuplicateKey,
+        );
+        table.register_simple(
+            "[d",
+            "prev-duplicate-key",
+            "Move to the previous entry whose key is used more than once in its record",
+            Action::PrevDuplicateKey,
+        );
+        table.register(
+            "gm",
+            "set-mark",
+            1,
+            false,
+            "Mark the selected node with a letter",
+            |_, args| Action::SetMark(args[0]),
+        );
+        table.register(
+            "'",
+            "jump-to-mark",
+            1,
+            false,
+            "Move to the node marked with a letter",
+            |_, args| Action::JumpToMark(args[0]),
+        );
+        table.register_simple(
+            "g'",
+            "list-marks",
+            "List the marked nodes",
+            Action::ListMarks,
+        );
+        // Queries and jumps
+        table.register_simple(
+            "gs",
+            "report-size",
+            "Report the size of the tree",
+            Action::ReportSize,
+        );
+        table.register_simple(
+            "gw",
+            "export-script",
+            "Write the session script to a file",
+            Action::ExportScript,
+        );
+        table.register_simple(
+            ":",
+            "named-command",
+            "Type a named command, like ':w out.json' (see below)",
+            Action::StartExCommand,
+        );
+        table.register_simple(
+            "bo",
+            "open-file",
+            "Open a file in a new buffer (also ctrl-o)",
+            Action::StartOpenFile,
+        );
+        table.register_simple(
+            "bn",
+            "next-buffer",
+            "Switch to the next buffer (also ctrl-n)",
+            Action::NextBuffer,
+        );
+        table.register_simple(
+            "bp",
+            "prev-buffer",
+            "Switch to the previous buffer",
+            Action::PrevBuffer,
+        );
+        table.register_simple(
+            "bd",
+            "close-buffer",
+            "Close the current buffer",
+            Action::CloseBuffer,
+        );
+        table.register_simple(
+            "bD",
+            "force-close-buffer",
+            "Close the current buffer, discarding unsaved changes",
+            Action::ForceCloseBuffer,
+        );
+        table.register_simple(
+            "gR",
+            "read-only",
+            "Switch read-only mode on or off",
+            Action::ToggleReadOnly,
+        );
+        table.register_simple(
+            "gS",
+            "write-session",
+            "Write the session, including the undo history, to a file",
+            Action::WriteSession,
+        );
+        table.register_simple(
+            "gt",
+            "report-kinds",
+            "Report how many nodes there are of each kind",
+            Action::ReportKindHistogram,
+        );
+        table.register_simple(
+            "gc",
+            "collect-garbage",
+            "Free the nodes that the tree and its history no longer use",
+            Action::CollectGarbage,
+        );
+        table.register_simple(
+            "gn",
+            "same-key-in-next-record",
+            "Move to the same key in the next record",
+            Action::MoveToSameKeyInNextRecord,
+        );
+        table.register_simple(
+            "ge",
+            "normalize",
+            "Normalize every node",
+            Action::NormalizeAll,
+        );
+        table.register_simple("gg", "root", "Move to the root", Action::MoveToRoot);
+        table.register_simple(
+            "g~",
+            "invert-booleans",
+            "Invert every boolean",
+            Action::InvertBooleans,
+        );
+        table.register_simple(
+            "=s",
+            "sort-children",
+            "Sort the entries of the selected record by key",
+            Action::SortChildren,
+        );
+        table.register_simple(
+            "=S",
+            "sort-children-by-text",
+            "Sort the children of the selected node by their text",
+            Action::ForceSortChildren,
+        );
+        table.register(
+            "gr",
+            "replace-all",
+            2,
+            false,
+            "Replace every node like the first char with the second",
+            |_, args| Action::ReplaceAll(args[0], args[1]),
+        );
+        table.register(
+            "gd",
+            "replace-all-dry-run",
+            2,
+            false,
+            "Report what 'gr' would replace, without replacing",
+            |_, args| Action::ReplaceAllDryRun(args[0], args[1]),
+        );
+        table.register(
+            "gp",
+            "ancestor",
+            1,
+            false,
+            "Move to the nearest ancestor of a given kind",
+            |_, args| Action::MoveToAncestor(args[0]),
+        );
+        // Folding
+        table.register_simple("zM", "fold-all", "Fold every node", Action::FoldAll);
+        table.register_simple("zR", "unfold-all", "Unfold every node", Action::UnfoldAll);
+        table.register_simple(
+            "za",
+            "toggle-fold",
+            "Fold or unfold the selected node",
+            Action::ToggleFold,
+        );
+        table.register_simple(
+            "zp",
+            "toggle-pin",
+            "Pin or unpin the selected node",
+            Action::TogglePin,
+        );
+        table.register_simple(
+            "zk",
+            "toggle-collapse",
+            "Collapse or expand the selected node to its first child",
+            Action::ToggleCollapse,
+        );
+        table.register(
+            "zl",
+            "fold-below-depth",
+            0,
+            true,
+            "Fold every node below depth <count> (default 1)",
+            |count, _| Action::FoldBelowDepth(count.unwrap_or(1)),
+        );
+        // Scrolling (`zl` already folds, so the half-screen scrolls of Vim are used instead)
+        table.register_simple(
+            "zH",
+            "scroll-left",
+            "Scroll the tree left by half a screen",
+            Action::ScrollLeft,
+        );
+        table.register_simple(
+            "zL",
+            "scroll-right",
+            "Scroll the tree right by half a screen",
+            Action::ScrollRight,
+        );
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandTable, Pending, Resolution};
+    use crate::editor::keymap::KeyMap;
+    use crate::editor::Action;
+    use std::time::Duration;
+
+    #[test]
+    fn register_new_command() {
+        let mut table = CommandTable::default();
+        assert_eq!(table.parse("gx"), Some(Action::Undefined));
+        assert_eq!(table.parse("Z"), Some(Action::Undefined));
+
+        // Commands with arguments and counts
+        assert!(!table.register("gx", "gx", 1, true, "", |count, args| {
+            Action::InsertChild(args[0], count.unwrap_or(1) * 2)
+        }));
+        assert_eq!(table.parse("gx"), None);
+        assert_eq!(table.parse("gxt"), Some(Action::InsertChild('t', 2)));
+        assert_eq!(table.parse("5gxa"), Some(Action::InsertChild('a', 10)));
+        // Commands without arguments
+        assert!(!table.register_simple("Z", "z", "", Action::Quit));
+        assert_eq!(table.parse("Z"), Some(Action::Quit));
+        assert_eq!(table.parse("3Z"), Some(Action::Undefined));
+
+        // Replacing an existing command
+        assert!(table.register_simple("u", "u", "", Action::Redo));
+        assert_eq!(table.parse("u"), Some(Action::Redo));
+
+        // Commands that are the start of other commands wait for another keypress
+        assert!(!table.register_simple("uu", "uu", "", Action::Undo));
+        assert_eq!(table.parse("u"), None);
+        assert_eq!(table.parse("uu"), Some(Action::Undo));
+        assert_eq!(table.parse("ux"), Some(Action::Redo));
+    }
+
+    #[test]
+    fn pending_commands() {
+        let table = CommandTable::default();
+        assert_eq!(table.pending("r"), Some(Pending::CharArg("replace")));
+        assert_eq!(table.pending("3i"), Some(Pending::CharArg("insert-child")));
+        assert_eq!(table.pending("gp"), Some(Pending::CharArg("ancestor")));
+        // Only the commands that start with what has been typed are listed
+        match table.pending("z") {
+            Some(Pending::Name(names)) => {
+                assert!(!names.is_empty());
+                assert!(names.iter().all(|(name, _)| name.starts_with('z')));
+            }
+            p => panic!("Expected a name, got {:?}", p),
+        }
+        // A count with no name only allows the commands that take counts
+        match table.pending("3") {
+            Some(Pending::Name(names)) => {
+                assert!(names.contains(&("i", "insert-child")));
+                assert!(!names.contains(&("q", "quit")));
+            }
+            p => panic!("Expected a name, got {:?}", p),
+        }
+        // Complete and undefined commands aren't pending
+        assert_eq!(table.pending("rt"), None);
+        assert_eq!(table.pending("X"), None);
+    }
+
+    #[test]
+    fn timing_out_commands() {
+        let table = CommandTable::default();
+        let timeout = Duration::from_millis(500);
+        let resolve = |command, elapsed| {
+            table.resolve_timeout(command, Duration::from_millis(elapsed), timeout)
+        };
+        // Waits for the rest of the timeout, and then takes the shorter command
+        assert_eq!(
+            resolve("q", 200),
+            Resolution::Wait(Some(Duration::from_millis(300)))
+        );
+        assert_eq!(
+            resolve("q", 500),
+            Resolution::Perform(Action::Quit, "q".to_string())
+        );
+        assert_eq!(
+            resolve("w", 1_000),
+            Resolution::Perform(Action::Write, "w".to_string())
+        );
+        // Part of a name that isn't a command is thrown away
+        assert_eq!(
+            resolve("g", 100),
+            Resolution::Wait(Some(Duration::from_millis(400)))
+        );
+        assert_eq!(resolve("g", 600), Resolution::Discard);
+        assert_eq!(resolve("3z", 600), Resolution::Discard);
+        // Counts, arguments and finished commands wait for as long as it takes
+        for command in &["", "3", "r", "3i", "gr", "gp"] {
+            assert_eq!(
+                resolve(command, 10_000),
+                Resolution::Wait(None),
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn help_entries() {
+        let table = CommandTable::default();
+        let entries = table.help_entries();
+        assert_eq!(entries.len(), table.commands.len());
+        assert!(entries.contains(&("q!".to_string(), "Quit, discarding unsaved changes")));
+        assert!(entries.iter().any(|(keys, _)| keys == "[count]i<c>"));
+        assert!(entries.iter().any(|(keys, _)| keys == "gr<c><c>"));
+        // Every built-in command should be documented
+        assert!(entries
+            .iter()
+            .all(|(_, description)| !description.is_empty()));
+    }
+
+    #[test]
+    fn apply_keymap() {
+        let mut table = CommandTable::default();
+        // Every command should have its own name in a keymap
+        for (i, a) in table.commands.iter().enumerate() {
+            assert!(table.commands[..i]
+                .iter()
+                .all(|b| a.action_name != b.action_name));
+        }
+
+        let mut keymap = KeyMap::new();
+        keymap.bind("j", "quit");
+        keymap.bind("K", "quit");
+        keymap.bind("ins", "insert-child");
+        assert_eq!(table.apply_keymap(&keymap), Ok(()));
+        assert_eq!(table.parse("j"), Some(Action::Quit));
+        assert_eq!(table.parse("K"), Some(Action::Quit));
+        assert_eq!(table.parse("3inst"), Some(Action::InsertChild('t', 3)));
+        // The old keys are unbound, but other commands keep theirs
+        assert_eq!(table.parse("qx"), Some(Action::Undefined));
+        assert_eq!(table.parse("q!"), Some(Action::ForceQuit));
+        assert_eq!(table.keys_for("quit"), Some("j"));
+        // Commands are recorded with their default keys
+        assert_eq!(table.canonical("3inst"), Some("3it".to_string()));
+        assert_eq!(table.canonical("j"), Some("q".to_string()));
+        assert_eq!(table.canonical("in"), None);
+
+        // Invalid keymaps leave the table unchanged
+        for (keys, action_name) in &[
+            ("z", "not-a-command"),
+            ("", "undo"),
+            ("3x", "undo"),
+            ("K", "undo"),
+            ("gsx", "undo"),
+            ("g", "undo"),
+        ] {
+            let mut keymap = KeyMap::new();
+            keymap.bind(keys, action_name);
+            assert!(table.apply_keymap(&keymap).is_err(), "{}", keys);
+        }
+        assert_eq!(table.parse("u"), Some(Action::Undo));
+        assert_eq!(table.parse("K"), Some(Action::Quit));
+    }
+
+    #[test]
+    fn empty_table() {
+        let table = CommandTable::empty();
+        assert_eq!(table.parse(""), None);
+        assert_eq!(table.parse("q"), Some(Action::Undefined));
+    }
+}
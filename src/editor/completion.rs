@@ -0,0 +1,193 @@
+//! Completion of the text typed into the prompt, which is used by pressing `Tab`.  Completing is
+//! kept separate from the [`Editor`](super::Editor): [`complete`] only chooses between names
+//! that it is given, and file paths are listed through a [`PathSource`] so that tests don't
+//! depend on the real filesystem.
+
+use std::path::Path;
+
+/// The ways that some text could be completed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Completion {
+    /// The longest text that every candidate starts with, which is never shorter than the text
+    /// that was completed
+    pub common_prefix: String,
+    /// Every candidate that starts with the completed text, sorted and without duplicates
+    pub candidates: Vec<String>,
+}
+
+/// Complete `prefix` to the `candidates` that start with it
+pub fn complete<S: Into<String>>(
+    prefix: &str,
+    candidates: impl IntoIterator<Item = S>,
+) -> Completion {
+    let mut candidates: Vec<String> = candidates
+        .into_iter()
+        .map(Into::into)
+        .filter(|c| c.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    let common_prefix = match candidates.split_first() {
+        Some((first, rest)) => {
+            let length = rest
+                .iter()
+                .map(|c| common_prefix_len(first, c))
+                .min()
+                .unwrap_or(first.len());
+            first[..length].to_string()
+        }
+        None => prefix.to_string(),
+    };
+    Completion {
+        common_prefix,
+        candidates,
+    }
+}
+
+/// Returns the length (in bytes) of the longest prefix shared by `a` and `b`, which always ends
+/// on a character boundary
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, c_a), c_b)| c_a != c_b)
+        .map_or_else(|| a.len().min(b.len()), |((i, _), _)| i)
+}
+
+/// Something that can list the contents of directories, so that file paths can be completed
+pub trait PathSource {
+    /// Returns the names of the entries in `dir`, each paired with whether it is a directory.
+    /// A directory that can't be read has no entries.
+    fn entries(&self, dir: &Path) -> Vec<(String, bool)>;
+}
+
+/// The real filesystem
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct FileSystem;
+
+impl PathSource for FileSystem {
+    fn entries(&self, dir: &Path) -> Vec<(String, bool)> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                // `metadata` follows symlinks, so a link to a directory can be completed into
+                let is_dir = entry.path().metadata().is_ok_and(|m| m.is_dir());
+                Some((name, is_dir))
+            })
+            .collect()
+    }
+}
+
+/// Complete the file path `prefix`, which is relative to the current directory unless it's
+/// absolute.  Each candidate is a whole path (starting with the directories in `prefix`), and
+/// directories end with a `/` so that their contents can be completed next.  Hidden files are
+/// only candidates if the last part of `prefix` starts with a `.`.
+pub fn complete_path(source: &dyn PathSource, prefix: &str) -> Completion {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    };
+    let entries = source.entries(Path::new(if dir.is_empty() { "." } else { dir }));
+    let show_hidden = name.starts_with('.');
+    complete(
+        prefix,
+        entries
+            .into_iter()
+            .filter(|(entry, _)| show_hidden || !entry.starts_with('.'))
+            .map(|(entry, is_dir)| format!("{}{}{}", dir, entry, if is_dir { "/" } else { "" })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete, complete_path, Completion, FileSystem, PathSource};
+    use std::path::Path;
+
+    fn completion(common_prefix: &str, candidates: &[&str]) -> Completion {
+        Completion {
+            common_prefix: common_prefix.to_string(),
+            candidates: candidates.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn completing_names() {
+        let names = ["w", "wq", "q", "q!", "sort", "sort!", "stats", "set"];
+        for (prefix, expected) in [
+            ("so", completion("sort", &["sort", "sort!"])),
+            ("st", completion("stats", &["stats"])),
+            ("s", completion("s", &["set", "sort", "sort!", "stats"])),
+            ("w", completion("w", &["w", "wq"])),
+            ("x", completion("x", &[])),
+            ("sort!", completion("sort!", &["sort!"])),
+        ] {
+            assert_eq!(complete(prefix, names.iter().copied()), expected);
+        }
+        // Duplicates are removed, and shared prefixes don't split characters
+        assert_eq!(
+            complete("", vec!["éa", "éb", "éa"]),
+            completion("é", &["éa", "éb"])
+        );
+    }
+
+    /// A [`PathSource`] with a fixed set of directories
+    struct FakeFiles;
+
+    impl PathSource for FakeFiles {
+        fn entries(&self, dir: &Path) -> Vec<(String, bool)> {
+            let entries: &[(&str, bool)] = match dir.to_str().unwrap() {
+                "." => &[
+                    ("data", true),
+                    ("data.json", false),
+                    ("notes.txt", false),
+                    (".hidden", false),
+                ],
+                "data/" => &[("a.json", false), ("b.json", false)],
+                _ => &[],
+            };
+            entries.iter().map(|&(n, d)| (n.to_string(), d)).collect()
+        }
+    }
+
+    #[test]
+    fn completing_paths() {
+        for (prefix, expected) in [
+            ("n", completion("notes.txt", &["notes.txt"])),
+            ("da", completion("data", &["data.json", "data/"])),
+            (
+                "data/",
+                completion("data/", &["data/a.json", "data/b.json"]),
+            ),
+            ("data/b", completion("data/b.json", &["data/b.json"])),
+            ("", completion("", &["data.json", "data/", "notes.txt"])),
+            (".", completion(".hidden", &[".hidden"])),
+            ("missing/", completion("missing/", &[])),
+        ] {
+            assert_eq!(complete_path(&FakeFiles, prefix), expected, "{:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn file_system() {
+        let dir = std::env::temp_dir().join(format!("sapling-complete-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("folder")).unwrap();
+        std::fs::write(dir.join("file.json"), "[]").unwrap();
+
+        let prefix = format!("{}/f", dir.display());
+        let result = complete_path(&FileSystem, &prefix);
+        assert_eq!(
+            result.candidates,
+            vec![
+                format!("{}/file.json", dir.display()),
+                format!("{}/folder/", dir.display())
+            ]
+        );
+        assert!(FileSystem.entries(&dir.join("missing")).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,457 @@
+//! The editor's settings, which can be loaded from a config file when Sapling starts and changed
+//! with `:set` while it's running
+//!
+//! A config file uses the same subset of TOML as a [keymap file](super::keymap), where each line
+//! gives a setting a value:
+//!
+//! ```toml
+//! # Settings that aren't mentioned keep their defaults
+//! indent = 2
+//! number = true
+//! timeoutlen = 300
+//! autosave = 0  # never back up on a timer
+//! colors = "plain"
+//! maxchildren = 500
+//! ```
+
+use super::keymap::{parse_key, parse_string};
+use crate::ast_spec::display_token::{IndentStyle, INDENT_WIDTH};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How the text of the tree is coloured
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorScheme {
+    /// Colour text by its [syntax category](crate::ast_spec::SyntaxCategory), giving each node
+    /// its own colour if its category has none
+    Syntax,
+    /// Give each node its own colour, regardless of its syntax
+    Rainbow,
+    /// Draw all of the text in one colour
+    Plain,
+}
+
+impl ColorScheme {
+    /// Every colour scheme, in the order they're listed to the user
+    const ALL: [ColorScheme; 3] = [
+        ColorScheme::Syntax,
+        ColorScheme::Rainbow,
+        ColorScheme::Plain,
+    ];
+
+    /// Returns the name that this colour scheme is given in `:set colors=<name>`
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorScheme::Syntax => "syntax",
+            ColorScheme::Rainbow => "rainbow",
+            ColorScheme::Plain => "plain",
+        }
+    }
+
+    /// Returns the colour scheme called `name`, or [`None`] if there isn't one
+    pub fn from_name(name: &str) -> Option<ColorScheme> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|scheme| scheme.name() == name)
+    }
+}
+
+/// A change to one of the settings in a [`Config`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Setting {
+    /// The indentation of the tree, both on the screen and when it's written to a file
+    /// (`indent=4` or `indent=tab`)
+    Indent(IndentStyle),
+    /// Whether line numbers are drawn next to the tree (`number`, `nonumber` or
+    /// `number=<true|false>`)
+    LineNumbers(bool),
+    /// How long to wait for the next key of a command that could be the start of a longer one,
+    /// before taking the shorter one (`timeoutlen=<milliseconds>`)
+    KeyTimeout(Duration),
+    /// How often the tree is backed up while editing a file, or [`None`] to never back up on a
+    /// timer (`autosave=<seconds>`, where `0` turns it off)
+    AutoSaveInterval(Option<Duration>),
+    /// How the text of the tree is coloured (`colors=<syntax|rainbow|plain>`)
+    Colors(ColorScheme),
+    /// How many children of a node are displayed at once before the rest are hidden behind a
+    /// marker, or [`None`] to always display every child (`maxchildren=<count>`, where `0` turns
+    /// the limit off)
+    ChildLimit(Option<usize>),
+}
+
+/// The name of each setting that can be changed, along with the values it expects (for error
+/// messages) and an example of setting it
+const SETTINGS: &[(&str, &str, &str)] = &[
+    ("indent", "a number of spaces or 'tab'", "indent=4"),
+    ("number", "'true' or 'false'", "number"),
+    ("timeoutlen", "a number of milliseconds", "timeoutlen=500"),
+    ("autosave", "a number of seconds (or 0)", "autosave=60"),
+    ("colors", "'syntax', 'rainbow' or 'plain'", "colors=syntax"),
+    (
+        "maxchildren",
+        "a number of children (or 0)",
+        "maxchildren=1000",
+    ),
+];
+
+/// Returns the name that `name` is listed under in [`SETTINGS`], or an error if it isn't a
+/// setting
+pub(super) fn setting_name(name: &str) -> Result<&'static str, SettingsError> {
+    SETTINGS
+        .iter()
+        .map(|(n, _, _)| *n)
+        .find(|n| *n == name)
+        .ok_or_else(|| SettingsError::Unknown(name.to_string()))
+}
+
+impl Setting {
+    /// Parse the change made by `:set name=value` (or `:set name`, if `value` is [`None`])
+    pub fn parse(name: &str, value: Option<&str>) -> Result<Setting, SettingsError> {
+        // `nonumber` is the only setting whose name isn't listed, since it's `number=false`
+        if name == "nonumber" {
+            return match value {
+                None => Ok(Setting::LineNumbers(false)),
+                Some(_) => Err(SettingsError::UnexpectedValue(name.to_string())),
+            };
+        }
+        let name = setting_name(name)?;
+        let value = match (name, value) {
+            ("number", None) => return Ok(Setting::LineNumbers(true)),
+            (_, None) => return Err(SettingsError::MissingValue(name)),
+            (_, Some(value)) => value,
+        };
+        let setting = match name {
+            "indent" => IndentStyle::from_arg(value).map(Setting::Indent),
+            "number" => value.parse().ok().map(Setting::LineNumbers),
+            "timeoutlen" => value
+                .parse()
+                .ok()
+                .map(|millis| Setting::KeyTimeout(Duration::from_millis(millis))),
+            "autosave" => value.parse().ok().map(|secs| {
+                Setting::AutoSaveInterval(Some(secs).filter(|s| *s > 0).map(Duration::from_secs))
+            }),
+            "colors" => ColorScheme::from_name(value).map(Setting::Colors),
+            "maxchildren" => value
+                .parse()
+                .ok()
+                .map(|limit| Setting::ChildLimit(Some(limit).filter(|l| *l > 0))),
+            _ => unreachable!("'{}' is a setting but can't be parsed", name),
+        };
+        setting.ok_or_else(|| SettingsError::InvalidValue {
+            name,
+            value: value.to_string(),
+        })
+    }
+
+    /// Returns the name of the setting that this changes
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::Indent(_) => "indent",
+            Setting::LineNumbers(_) => "number",
+            Setting::KeyTimeout(_) => "timeoutlen",
+            Setting::AutoSaveInterval(_) => "autosave",
+            Setting::Colors(_) => "colors",
+            Setting::ChildLimit(_) => "maxchildren",
+        }
+    }
+}
+
+/// The reason that a setting couldn't be changed or shown
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SettingsError {
+    /// There's no setting with the given name
+    Unknown(String),
+    /// The setting needs a value, but wasn't given one
+    MissingValue(&'static str),
+    /// The setting was given a value, but it doesn't take one
+    UnexpectedValue(String),
+    /// The value given to a setting can't be parsed
+    InvalidValue {
+        /// The name of the setting
+        name: &'static str,
+        /// The value that it was given
+        value: String,
+    },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let setting = |name: &str| SETTINGS.iter().find(|(n, _, _)| *n == name).unwrap();
+        match self {
+            SettingsError::Unknown(name) => write!(f, "Unknown setting '{}'", name),
+            SettingsError::MissingValue(name) => {
+                write!(f, "'{}' needs a value, like '{}'", name, setting(name).2)
+            }
+            SettingsError::UnexpectedValue(name) => write!(f, "'{}' doesn't take a value", name),
+            SettingsError::InvalidValue { name, value } => {
+                write!(f, "'{}' must be {}, not '{}'", name, setting(name).1, value)
+            }
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+/// The settings that tune how the editor behaves, each of which can be changed with `:set`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Config {
+    /// The indentation of the tree, both on the screen and when it's written to a file
+    pub indent: IndentStyle,
+    /// Whether each line of the display is prefixed by its line number
+    pub line_numbers: bool,
+    /// How long to wait for the next key of an unfinished command before giving up on it
+    pub key_timeout: Duration,
+    /// How often the tree is backed up while editing a file, or [`None`] to never back up on a
+    /// timer
+    pub auto_save_interval: Option<Duration>,
+    /// How the text of the tree is coloured
+    pub color_scheme: ColorScheme,
+    /// How many children of a node are displayed at once, or [`None`] to display every child.
+    /// Only a window of the children of larger nodes is rendered, so that huge files stay quick
+    /// to draw.
+    pub child_limit: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            indent: IndentStyle::Spaces(INDENT_WIDTH),
+            line_numbers: false,
+            key_timeout: Duration::from_millis(500),
+            auto_save_interval: Some(Duration::from_secs(60)),
+            color_scheme: ColorScheme::Syntax,
+            child_limit: Some(1000),
+        }
+    }
+}
+
+impl Config {
+    /// Returns this config with `setting` changed
+    pub fn apply(mut self, setting: Setting) -> Config {
+        match setting {
+            Setting::Indent(indent) => self.indent = indent,
+            Setting::LineNumbers(line_numbers) => self.line_numbers = line_numbers,
+            Setting::KeyTimeout(timeout) => self.key_timeout = timeout,
+            Setting::AutoSaveInterval(interval) => self.auto_save_interval = interval,
+            Setting::Colors(scheme) => self.color_scheme = scheme,
+            Setting::ChildLimit(limit) => self.child_limit = limit,
+        }
+        self
+    }
+
+    /// Returns this config with the setting called `name` changed to `value`, as if by
+    /// `:set name=value` (or `:set name`, if `value` is [`None`])
+    pub fn with_setting(self, name: &str, value: Option<&str>) -> Result<Config, SettingsError> {
+        Setting::parse(name, value).map(|setting| self.apply(setting))
+    }
+
+    /// Returns the value of the setting called `name`, in the form that it would be set with
+    /// `:set` (e.g. `indent=4` or `nonumber`)
+    pub fn show(&self, name: &str) -> Result<String, SettingsError> {
+        Ok(match setting_name(name)? {
+            "indent" => match self.indent {
+                IndentStyle::Spaces(width) => format!("indent={}", width),
+                IndentStyle::Tabs => "indent=tab".to_string(),
+            },
+            "number" => if self.line_numbers {
+                "number"
+            } else {
+                "nonumber"
+            }
+            .to_string(),
+            "timeoutlen" => format!("timeoutlen={}", self.key_timeout.as_millis()),
+            "autosave" => format!(
+                "autosave={}",
+                self.auto_save_interval.map_or(0, |i| i.as_secs())
+            ),
+            "colors" => format!("colors={}", self.color_scheme.name()),
+            "maxchildren" => format!("maxchildren={}", self.child_limit.unwrap_or(0)),
+            name => unreachable!("'{}' is a setting but can't be shown", name),
+        })
+    }
+
+    /// Parse the text of a config file, starting from the default settings.  Returns an error
+    /// describing the first line that can't be parsed or applied.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || format!("line {}: expected `setting = value`", i + 1);
+            let (name, rest) = parse_key(line).ok_or_else(error)?;
+            let rest = rest.trim_start().strip_prefix('=').ok_or_else(error)?;
+            let rest = rest.trim_start();
+            // Values are either strings or bare words (which covers numbers and booleans)
+            let (value, rest) = match parse_string(rest) {
+                Some((value, rest)) => (value, rest),
+                None => {
+                    let len = rest
+                        .find(|c: char| c.is_whitespace() || c == '#')
+                        .unwrap_or(rest.len());
+                    (rest[..len].to_string(), &rest[len..])
+                }
+            };
+            let rest = rest.trim_start();
+            if value.is_empty() || !(rest.is_empty() || rest.starts_with('#')) {
+                return Err(error());
+            }
+            config = config
+                .with_setting(&name, Some(&value))
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Read and parse the config file at `path`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_text(&text)
+    }
+
+    /// Returns the default location of the config file (`$XDG_CONFIG_HOME/sapling/config.toml`,
+    /// or `~/.config/sapling/config.toml`), if it can be determined
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+}
+
+/// Returns the directory that Sapling's config files are kept in (`$XDG_CONFIG_HOME/sapling`, or
+/// `~/.config/sapling`), if it can be determined
+pub(super) fn config_dir() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("sapling"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorScheme, Config, Setting, SettingsError};
+    use crate::ast_spec::display_token::IndentStyle;
+    use std::time::Duration;
+
+    #[test]
+    fn with_setting() {
+        let config = Config::default()
+            .with_setting("indent", Some("tab"))
+            .and_then(|c| c.with_setting("number", None))
+            .and_then(|c| c.with_setting("autosave", Some("0")))
+            .and_then(|c| c.with_setting("colors", Some("rainbow")))
+            .and_then(|c| c.with_setting("maxchildren", Some("0")))
+            .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                indent: IndentStyle::Tabs,
+                line_numbers: true,
+                auto_save_interval: None,
+                color_scheme: ColorScheme::Rainbow,
+                child_limit: None,
+                ..Config::default()
+            }
+        );
+        assert_eq!(
+            config.clone().with_setting("nonumber", None),
+            Ok(Config {
+                line_numbers: false,
+                ..config.clone()
+            })
+        );
+        for (name, shown) in &[
+            ("indent", "indent=tab"),
+            ("number", "number"),
+            ("timeoutlen", "timeoutlen=500"),
+            ("autosave", "autosave=0"),
+            ("colors", "colors=rainbow"),
+            ("maxchildren", "maxchildren=0"),
+        ] {
+            assert_eq!(config.show(name).as_deref(), Ok(*shown));
+            // Every shown value can be set again
+            let (name, value) = match shown.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (*shown, None),
+            };
+            assert_eq!(
+                config.clone().with_setting(name, value).as_ref(),
+                Ok(&config)
+            );
+        }
+        assert_eq!(
+            Setting::parse("timeoutlen", Some("250")),
+            Ok(Setting::KeyTimeout(Duration::from_millis(250)))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for (name, value, error) in &[
+            ("colour", Some("red"), "Unknown setting 'colour'"),
+            (
+                "indent",
+                Some("wide"),
+                "'indent' must be a number of spaces or 'tab', not 'wide'",
+            ),
+            ("indent", None, "'indent' needs a value, like 'indent=4'"),
+            ("nonumber", Some("1"), "'nonumber' doesn't take a value"),
+            (
+                "number",
+                Some("1"),
+                "'number' must be 'true' or 'false', not '1'",
+            ),
+            (
+                "autosave",
+                Some("-5"),
+                "'autosave' must be a number of seconds (or 0), not '-5'",
+            ),
+            (
+                "colors",
+                Some("neon"),
+                "'colors' must be 'syntax', 'rainbow' or 'plain', not 'neon'",
+            ),
+        ] {
+            let result = Config::default().with_setting(name, *value);
+            assert_eq!(result.unwrap_err().to_string(), *error);
+        }
+        assert_eq!(
+            Config::default().show("colour"),
+            Err(SettingsError::Unknown("colour".to_string()))
+        );
+    }
+
+    #[test]
+    fn from_text() {
+        let config = Config::from_text(
+            "# A comment\n\nindent = 2\n  number = true  # trailing\ncolors=\"plain\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                indent: IndentStyle::Spaces(2),
+                line_numbers: true,
+                color_scheme: ColorScheme::Plain,
+                ..Config::default()
+            }
+        );
+        for (text, error) in &[
+            ("indent 2", "line 1: expected `setting = value`"),
+            ("indent =", "line 1: expected `setting = value`"),
+            ("\nindent = 2 3", "line 2: expected `setting = value`"),
+            ("[section]", "line 1: expected `setting = value`"),
+            ("colour = \"red\"", "line 1: Unknown setting 'colour'"),
+            (
+                "timeoutlen = soon",
+                "line 1: 'timeoutlen' must be a number of milliseconds, not 'soon'",
+            ),
+        ] {
+            assert_eq!(Config::from_text(text).unwrap_err(), *error);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! A cache of the flattened display tokens, so that moving the cursor doesn't regenerate the text
+
+use crate::ast_spec::display_token::{flat_tokens_for_lines, subtree_token_range, DisplayToken};
+use crate::ast_spec::ASTSpec;
+use crate::node_map::{NodeMap, Reference};
+
+/// The flattened display tokens of the first lines of a tree, along with where each line starts.
+/// This only depends on the text of the tree and how it's displayed, so it stays valid while the
+/// cursor moves and must be thrown away after anything else changes.
+#[derive(Debug, Clone)]
+pub struct DisplayCache<Ref: Reference> {
+    /// The tokens, as generated by [`flat_tokens_for_lines`]
+    tokens: Vec<(Ref, DisplayToken<Ref>)>,
+    /// For each line, the index of its first token and the indentation that the line starts with
+    line_starts: Vec<(usize, usize)>,
+    /// The number of lines that were asked for when the tokens were generated
+    max_lines: usize,
+}
+
+impl<Ref: Reference> DisplayCache<Ref> {
+    /// Generate the tokens for (at least) the first `max_lines` lines of the tree rooted at
+    /// `root`.  The tokens of every node are generated by `tokens_for`, and each level of
+    /// indentation is `indent_width` columns wide.
+    pub fn new<Node: ASTSpec<Ref>>(
+        node_map: &impl NodeMap<Ref, Node>,
+        root: Ref,
+        tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+        max_lines: usize,
+        indent_width: usize,
+    ) -> Self {
+        let tokens = flat_tokens_for_lines(node_map, root, tokens_for, max_lines);
+        let mut line_starts = vec![(0, 0)];
+        let mut indentation = 0;
+        for (i, (_, tok)) in tokens.iter().enumerate() {
+            match tok {
+                DisplayToken::Newline => line_starts.push((i + 1, indentation)),
+                DisplayToken::Indent => indentation += indent_width,
+                DisplayToken::Dedent => indentation = indentation.saturating_sub(indent_width),
+                _ => {}
+            }
+        }
+        DisplayCache {
+            tokens,
+            line_starts,
+            max_lines,
+        }
+    }
+
+    /// Returns `true` if this cache contains every token of the first `num_lines` lines
+    pub fn covers(&self, num_lines: usize) -> bool {
+        // If fewer lines were generated than were asked for, then the whole tree was generated
+        num_lines <= self.max_lines || self.line_starts.len() < self.max_lines
+    }
+
+    /// Returns every token in the cache
+    pub fn tokens(&self) -> &[(Ref, DisplayToken<Ref>)] {
+        &self.tokens
+    }
+
+    /// Returns the index of the first token on `line` and the indentation of that line, or
+    /// [`None`] if the cache doesn't reach that line
+    pub fn line_start(&self, line: usize) -> Option<(usize, usize)> {
+        self.line_starts.get(line).copied()
+    }
+
+    /// Returns the line on which the node at `id` starts, or [`None`] if none of its tokens are
+    /// in the cache.  `tokens_for` must be the same as the one used to make the cache.
+    pub fn line_of_node<Node: ASTSpec<Ref>>(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        id: Ref,
+        tokens_for: &impl Fn(Ref, &Node) -> Vec<DisplayToken<Ref>>,
+    ) -> Option<usize> {
+        let start = subtree_token_range(node_map, &self.tokens, id, tokens_for).start;
+        if start == self.tokens.len() {
+            return None;
+        }
+        Some(self.line_starts.partition_point(|(i, _)| *i <= start) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisplayCache;
+    use crate::ast_spec::display_token::{line_of_node, DisplayToken, INDENT_WIDTH};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::NodeMap;
+
+    #[test]
+    fn lines_match_the_uncached_lines() {
+        let tree: VecNodeMap<JSON<Index>> = TestJSON::Array(
+            (0..300)
+                .map(|i| {
+                    if i % 3 == 0 {
+                        TestJSON::Object(vec![("key".to_string(), TestJSON::True)])
+                    } else {
+                        TestJSON::False
+                    }
+                })
+                .collect(),
+        )
+        .build_node_map();
+        let tokens_for = |_, node: &JSON<Index>| -> Vec<DisplayToken<Index>> {
+            node.display_tokens(&JSONFormat::Pretty)
+        };
+        let cache = DisplayCache::new(&tree, tree.root(), &tokens_for, 100, INDENT_WIDTH);
+        assert!(cache.covers(100));
+        assert!(!cache.covers(101));
+        for &child in tree.root_node().children() {
+            let expected = line_of_node(&tree, tree.root(), child, &tokens_for).unwrap();
+            match cache.line_of_node(&tree, child, &tokens_for) {
+                Some(line) => assert_eq!(line, expected),
+                // Only the nodes after the cached lines are missing
+                None => assert!(expected >= 100),
+            }
+        }
+        // Lines inside the objects are indented twice
+        let (first_token, indentation) = cache.line_start(2).unwrap();
+        assert_eq!(indentation, 8);
+        assert_eq!(
+            cache.tokens()[first_token].1,
+            DisplayToken::Text("\"key\"".to_string())
+        );
+
+        // Small trees are cached whole, however many lines are needed
+        let small: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![TestJSON::True]).build_node_map();
+        let cache = DisplayCache::new(&small, small.root(), &tokens_for, 100, INDENT_WIDTH);
+        assert!(cache.covers(1000));
+    }
+}
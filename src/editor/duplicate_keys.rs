@@ -0,0 +1,247 @@
+//! Finding the entries of records (e.g. JSON object fields) whose key is used more than once in
+//! the same record.  Duplicate keys are allowed, but are almost always a mistake.
+
+use crate::ast_spec::ASTSpec;
+use crate::node_map::{NodeMap, Reference};
+use std::collections::HashMap;
+
+/// Returns every entry of a record under `root` whose key is also the key of another entry of
+/// the same record, along with that key, in the order that the entries appear in the text
+pub fn duplicate_keys<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    root: Ref,
+) -> Vec<(Ref, String)> {
+    let mut keys = DuplicateKeys::new();
+    keys.entries(node_map, root)
+        .into_iter()
+        .map(|(_, entry, key)| (entry, key))
+        .collect()
+}
+
+/// Remembers how many entries with duplicate keys are in every subtree that has been checked, so
+/// that checking the tree again only looks at the keys of the records that have changed.  Nodes
+/// that an edit creates have new references, but the nodes above them are changed in place, so
+/// those have to be [forgotten](DuplicateKeys::forget_path) before the edit.  Edits that change
+/// nodes all over the tree (or moving through the undo history, garbage collection and replacing
+/// the tree) have to [clear](DuplicateKeys::clear) everything instead.
+#[derive(Debug, Clone)]
+pub struct DuplicateKeys<Ref: Reference> {
+    /// The number of entries with a duplicate key in the subtree under each node
+    counts: HashMap<Ref, usize>,
+}
+
+impl<Ref: Reference> DuplicateKeys<Ref> {
+    /// Creates a `DuplicateKeys` that hasn't checked any nodes
+    pub fn new() -> Self {
+        DuplicateKeys {
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Forget every node that has been checked
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+
+    /// Forget the nodes from `root` down to the end of `path`, because an edit of the node at
+    /// `path` is about to change them
+    pub fn forget_path<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        root: Ref,
+        path: &[usize],
+    ) {
+        let mut id = root;
+        self.counts.remove(&id);
+        for &index in path {
+            id = match node_map.get_node(id).and_then(|n| n.children().get(index)) {
+                Some(&child) => child,
+                None => return,
+            };
+            self.counts.remove(&id);
+        }
+    }
+
+    /// Returns the number of entries with a duplicate key in the subtree under `root`
+    pub fn count<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        root: Ref,
+    ) -> usize {
+        if let Some(&count) = self.counts.get(&root) {
+            return count;
+        }
+        let children = match node_map.get_node(root) {
+            Some(node) => node.children(),
+            None => return 0,
+        };
+        let count = duplicated_entries(node_map, children).len()
+            + children
+                .iter()
+                .map(|&child| self.count(node_map, child))
+                .sum::<usize>();
+        self.counts.insert(root, count);
+        count
+    }
+
+    /// Returns the number of entries with a duplicate key in the subtree under `root`, or
+    /// [`None`] if that subtree hasn't been [counted](DuplicateKeys::count)
+    pub fn checked_count(&self, root: Ref) -> Option<usize> {
+        self.counts.get(&root).copied()
+    }
+
+    /// Returns the path (from `root`), reference and key of every entry with a duplicate key
+    /// under `root`, in the order that they appear in the text.  Subtrees without any duplicates
+    /// aren't searched.
+    pub fn entries<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        root: Ref,
+    ) -> Vec<(Vec<usize>, Ref, String)> {
+        let mut entries = Vec::new();
+        self.find_entries(node_map, root, &mut Vec::new(), &mut entries);
+        entries
+    }
+
+    /// Adds the entries with duplicate keys under `id` (which is at `path`) to `entries`
+    fn find_entries<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        id: Ref,
+        path: &mut Vec<usize>,
+        entries: &mut Vec<(Vec<usize>, Ref, String)>,
+    ) {
+        if self.count(node_map, id) == 0 {
+            return;
+        }
+        let children = match node_map.get_node(id) {
+            Some(node) => node.children(),
+            None => return,
+        };
+        let mut duplicated = duplicated_entries(node_map, children)
+            .into_iter()
+            .peekable();
+        for (index, &child) in children.iter().enumerate() {
+            path.push(index);
+            if let Some((_, key)) = duplicated.next_if(|(i, _)| *i == index) {
+                entries.push((path.clone(), child, key));
+            }
+            self.find_entries(node_map, child, path, entries);
+            path.pop();
+        }
+    }
+}
+
+impl<Ref: Reference> Default for DuplicateKeys<Ref> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the key of an entry of a record, or [`None`] if `entry` isn't one
+fn entry_key<'m, Ref: Reference, Node: ASTSpec<Ref> + 'm>(
+    node_map: &'m impl NodeMap<Ref, Node>,
+    entry: Ref,
+) -> Option<&'m str> {
+    let entry = node_map.get_node(entry)?;
+    let key = *entry.children().get(entry.key_index()?)?;
+    node_map.get_node(key)?.literal()
+}
+
+/// Returns the index and key of every one of `children` that is a record entry whose key is
+/// shared with another of `children`, in order
+fn duplicated_entries<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    children: &[Ref],
+) -> Vec<(usize, String)> {
+    let keys: Vec<Option<&str>> = children
+        .iter()
+        .map(|&child| entry_key(node_map, child))
+        .collect();
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    for key in keys.iter().flatten() {
+        *uses.entry(key).or_default() += 1;
+    }
+    keys.iter()
+        .enumerate()
+        .filter_map(|(index, key)| {
+            key.filter(|key| uses[key] > 1)
+                .map(|key| (index, key.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{duplicate_keys, DuplicateKeys};
+    use crate::ast_spec::json::JSON;
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::NodeMap;
+
+    /// Builds an object with the given keys, whose values are all `true`
+    fn object(keys: &[&str]) -> TestJSON {
+        TestJSON::Object(
+            keys.iter()
+                .map(|k| (k.to_string(), TestJSON::True))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn finds_duplicates_at_every_depth() {
+        let node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+            object(&["a", "b", "a"]),
+            object(&["x", "y"]),
+            TestJSON::Object(vec![
+                ("inner".to_string(), object(&["c", "c", "d", "c"])),
+                ("a".to_string(), TestJSON::Null),
+                (
+                    "inner".to_string(),
+                    TestJSON::Array(vec![object(&["a", "a"])]),
+                ),
+            ]),
+        ])
+        .build_node_map();
+        let root = node_map.root();
+        let found: Vec<(Vec<usize>, String)> = DuplicateKeys::new()
+            .entries(&node_map, root)
+            .into_iter()
+            .map(|(path, _, key)| (path, key))
+            .collect();
+        let expected = [
+            (vec![0, 0], "a"),
+            (vec![0, 2], "a"),
+            (vec![2, 0], "inner"),
+            (vec![2, 0, 1, 0], "c"),
+            (vec![2, 0, 1, 1], "c"),
+            (vec![2, 0, 1, 3], "c"),
+            (vec![2, 2], "inner"),
+            (vec![2, 2, 1, 0, 0], "a"),
+            (vec![2, 2, 1, 0, 1], "a"),
+        ];
+        let expected: Vec<(Vec<usize>, String)> = expected
+            .iter()
+            .map(|(path, key)| (path.clone(), key.to_string()))
+            .collect();
+        assert_eq!(found, expected);
+        assert_eq!(DuplicateKeys::new().count(&node_map, root), 9);
+        // The library function gives the same entries
+        let keys: Vec<String> = duplicate_keys(&node_map, root)
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        assert_eq!(keys, ["a", "a", "inner", "c", "c", "c", "inner", "a", "a"]);
+    }
+
+    #[test]
+    fn no_duplicates() {
+        let node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+            object(&["a", "b"]),
+            object(&["a", "b"]),
+            TestJSON::Array(vec![TestJSON::Str("a".to_string()); 2]),
+        ])
+        .build_node_map();
+        assert!(duplicate_keys(&node_map, node_map.root()).is_empty());
+    }
+}
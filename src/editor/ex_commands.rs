@@ -0,0 +1,382 @@
+//! The named commands that can be typed into the `:` prompt (e.g. `:w out.json`).  Unlike the
+//! commands in the [`CommandTable`](super::command_table::CommandTable), these are whole words
+//! and can take arguments, so there's room for commands that don't deserve a key of their own.
+
+use super::config::{setting_name, Setting};
+use super::Action;
+use std::path::PathBuf;
+
+/// What an [`ExCommand`] expects to be typed after its name
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(super) enum Argument {
+    /// Nothing
+    None,
+    /// A file path, which can be left out
+    OptionalPath,
+    /// A file path, which must be given
+    Path,
+    /// A [`Setting`], like `indent=4`, or the name of a setting followed by `?`
+    Setting,
+    /// A number, which can be left out
+    OptionalCount,
+    /// Two numbers: a minimum and then a maximum
+    Range,
+}
+
+/// A command that can be typed into the `:` prompt
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(super) struct ExCommand {
+    /// The name that is typed to run this command
+    pub name: &'static str,
+    /// What has to come after the name
+    pub argument: Argument,
+    /// A one-line description of what this command does, shown in the help screen
+    pub description: &'static str,
+}
+
+/// Every command that can be typed into the `:` prompt
+pub(super) const EX_COMMANDS: &[ExCommand] = &[
+    ExCommand {
+        name: "w",
+        argument: Argument::OptionalPath,
+        description: "Write the tree (to <path>, if given)",
+    },
+    ExCommand {
+        name: "w!",
+        argument: Argument::None,
+        description: "Write the tree, even if its file has changed on disk since it was read",
+    },
+    ExCommand {
+        name: "wq",
+        argument: Argument::OptionalPath,
+        description: "Write the tree (to <path>, if given) and quit",
+    },
+    ExCommand {
+        name: "q",
+        argument: Argument::None,
+        description: "Quit, unless there are unsaved changes",
+    },
+    ExCommand {
+        name: "q!",
+        argument: Argument::None,
+        description: "Quit, discarding unsaved changes",
+    },
+    ExCommand {
+        name: "mksession",
+        argument: Argument::OptionalPath,
+        description: "Write the whole session, including the undo history (to <path>, if given)",
+    },
+    ExCommand {
+        name: "e",
+        argument: Argument::Path,
+        description: "Open <path> in a new buffer",
+    },
+    ExCommand {
+        name: "e!",
+        argument: Argument::None,
+        description: "Reload the file from disk, discarding unsaved changes (after asking)",
+    },
+    ExCommand {
+        name: "sort",
+        argument: Argument::None,
+        description: "Sort the entries of the selected record by key",
+    },
+    ExCommand {
+        name: "sort!",
+        argument: Argument::None,
+        description: "Sort the children of the selected node by their text",
+    },
+    ExCommand {
+        name: "stats",
+        argument: Argument::None,
+        description: "Report the size and shape of the tree",
+    },
+    ExCommand {
+        name: "marks",
+        argument: Argument::None,
+        description: "List the marked nodes",
+    },
+    ExCommand {
+        name: "registers",
+        argument: Argument::None,
+        description: "List the registers that have been yanked into",
+    },
+    ExCommand {
+        name: "changes",
+        argument: Argument::OptionalCount,
+        description: "List the changes since the last save (or since <count> versions ago)",
+    },
+    ExCommand {
+        name: "clamp",
+        argument: Argument::Range,
+        description: "Clamp the selected number into the range from <min> to <max>",
+    },
+    ExCommand {
+        name: "set",
+        argument: Argument::Setting,
+        description: "Change a setting (like indent=2 or nonumber), or show one (like indent?)",
+    },
+    ExCommand {
+        name: "help",
+        argument: Argument::None,
+        description: "Show the help screen",
+    },
+];
+
+/// Parse the text typed into the `:` prompt (without the `:`) into the [`Action`] that it
+/// performs.  The text is the name of a command, followed by its argument (if it takes one)
+/// after some whitespace.  Returns a message for the user if the command doesn't exist or its
+/// argument is missing or invalid.
+pub(super) fn parse_ex_command(text: &str) -> Result<Action, String> {
+    let text = text.trim();
+    let (name, argument) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], Some(text[i..].trim_start())),
+        None => (text, None),
+    };
+    let command = EX_COMMANDS
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| format!("Unknown command ':{}'", name))?;
+    let path = match (command.argument, argument) {
+        (Argument::None, Some(_)) => {
+            return Err(format!("':{}' doesn't take an argument", name));
+        }
+        (Argument::Path, None) => return Err(format!("':{}' needs a file path", name)),
+        (Argument::Setting, None) => return Err(format!("':{}' needs a setting", name)),
+        (Argument::Setting, Some(setting)) => return parse_setting(setting),
+        (Argument::Range, range) => return parse_range(name, range.unwrap_or("")),
+        (Argument::OptionalCount, Some(count)) => {
+            return match count.parse() {
+                Ok(count) => Ok(Action::ShowChanges(Some(count))),
+                Err(_) => Err(format!("':{}' needs a number, not '{}'", name, count)),
+            }
+        }
+        (_, argument) => argument.map(PathBuf::from),
+    };
+    Ok(match (name, path) {
+        ("w", None) => Action::Write,
+        ("w", Some(path)) => Action::WriteTo(path),
+        ("w!", _) => Action::ForceWrite,
+        ("wq", None) => Action::WriteAndQuit,
+        ("wq", Some(path)) => Action::WriteToAndQuit(path),
+        ("mksession", None) => Action::WriteSession,
+        ("mksession", Some(path)) => Action::WriteSessionTo(path),
+        ("q", _) => Action::Quit,
+        ("q!", _) => Action::ForceQuit,
+        ("e", Some(path)) => Action::OpenFile(path),
+        ("e!", _) => Action::Reload,
+        ("sort", _) => Action::SortChildren,
+        ("sort!", _) => Action::ForceSortChildren,
+        ("stats", _) => Action::ReportStats,
+        ("marks", _) => Action::ListMarks,
+        ("registers", _) => Action::ListRegisters,
+        ("changes", _) => Action::ShowChanges(None),
+        ("help", _) => Action::ShowHelp,
+        _ => unreachable!("':{}' is in the table but can't be parsed", name),
+    })
+}
+
+/// Parse the argument of `:clamp` (a minimum and a maximum) into the [`Action::Clamp`] that
+/// clamps into that range.  The bounds are kept as they were typed, since that's how they'll be
+/// written into the tree.
+fn parse_range(name: &str, text: &str) -> Result<Action, String> {
+    let bounds: Vec<&str> = text.split_whitespace().collect();
+    let (min, max) = match bounds.as_slice() {
+        [min, max] => (*min, *max),
+        _ => {
+            return Err(format!(
+                "':{}' needs a minimum and a maximum, like ':{} 0 100'",
+                name, name
+            ))
+        }
+    };
+    let parse = |bound: &str| match bound.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("'{}' is not a number", bound)),
+    };
+    if parse(min)? > parse(max)? {
+        return Err(format!(
+            "The minimum ({}) is more than the maximum ({})",
+            min, max
+        ));
+    }
+    Ok(Action::Clamp(min.to_string(), max.to_string()))
+}
+
+/// Parse the argument of `:set` into the [`Setting`] it changes, or the setting it shows
+fn parse_setting(text: &str) -> Result<Action, String> {
+    if let Some(name) = text.strip_suffix('?') {
+        return setting_name(name.trim())
+            .map(Action::ShowSetting)
+            .map_err(|e| e.to_string());
+    }
+    let (name, value) = match text.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (text, None),
+    };
+    Setting::parse(name, value)
+        .map(Action::ChangeSetting)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ex_command, Setting, EX_COMMANDS};
+    use crate::ast_spec::display_token::IndentStyle;
+    use crate::editor::config::ColorScheme;
+    use crate::editor::Action;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn parsing() {
+        for (text, action) in vec![
+            ("w", Action::Write),
+            ("  w  ", Action::Write),
+            ("w out.json", Action::WriteTo(PathBuf::from("out.json"))),
+            (
+                "w   dir/my file.json ",
+                Action::WriteTo(PathBuf::from("dir/my file.json")),
+            ),
+            ("w!", Action::ForceWrite),
+            ("wq", Action::WriteAndQuit),
+            ("wq a", Action::WriteToAndQuit(PathBuf::from("a"))),
+            ("q", Action::Quit),
+            ("q!", Action::ForceQuit),
+            (
+                "e /tmp/x.json",
+                Action::OpenFile(PathBuf::from("/tmp/x.json")),
+            ),
+            ("e!", Action::Reload),
+            ("mksession", Action::WriteSession),
+            (
+                "mksession work.session",
+                Action::WriteSessionTo(PathBuf::from("work.session")),
+            ),
+            ("sort", Action::SortChildren),
+            ("sort!", Action::ForceSortChildren),
+            ("stats", Action::ReportStats),
+            ("marks", Action::ListMarks),
+            ("changes", Action::ShowChanges(None)),
+            ("changes 3", Action::ShowChanges(Some(3))),
+            (
+                "clamp 0 100",
+                Action::Clamp("0".to_string(), "100".to_string()),
+            ),
+            (
+                "clamp  -1.5   2e3 ",
+                Action::Clamp("-1.5".to_string(), "2e3".to_string()),
+            ),
+            ("clamp 7 7", Action::Clamp("7".to_string(), "7".to_string())),
+            ("help", Action::ShowHelp),
+            (
+                "set indent=4",
+                Action::ChangeSetting(Setting::Indent(IndentStyle::Spaces(4))),
+            ),
+            (
+                "set indent = tab",
+                Action::ChangeSetting(Setting::Indent(IndentStyle::Tabs)),
+            ),
+            (
+                "set number",
+                Action::ChangeSetting(Setting::LineNumbers(true)),
+            ),
+            (
+                "set nonumber",
+                Action::ChangeSetting(Setting::LineNumbers(false)),
+            ),
+            (
+                "set timeoutlen=250",
+                Action::ChangeSetting(Setting::KeyTimeout(Duration::from_millis(250))),
+            ),
+            (
+                "set number=false",
+                Action::ChangeSetting(Setting::LineNumbers(false)),
+            ),
+            (
+                "set autosave=0",
+                Action::ChangeSetting(Setting::AutoSaveInterval(None)),
+            ),
+            (
+                "set colors=plain",
+                Action::ChangeSetting(Setting::Colors(ColorScheme::Plain)),
+            ),
+            ("set indent?", Action::ShowSetting("indent")),
+            ("set number ?", Action::ShowSetting("number")),
+        ] {
+            assert_eq!(parse_ex_command(text), Ok(action), "parsing {:?}", text);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for (text, error) in &[
+            ("", "Unknown command ':'"),
+            ("wrte", "Unknown command ':wrte'"),
+            ("W", "Unknown command ':W'"),
+            ("sort keys", "':sort' doesn't take an argument"),
+            ("q! now", "':q!' doesn't take an argument"),
+            ("e", "':e' needs a file path"),
+            ("set", "':set' needs a setting"),
+            ("changes few", "':changes' needs a number, not 'few'"),
+            (
+                "clamp",
+                "':clamp' needs a minimum and a maximum, like ':clamp 0 100'",
+            ),
+            (
+                "clamp 1 2 3",
+                "':clamp' needs a minimum and a maximum, like ':clamp 0 100'",
+            ),
+            ("clamp 0 lots", "'lots' is not a number"),
+            ("clamp inf 1", "'inf' is not a number"),
+            (
+                "clamp 100 0",
+                "The minimum (100) is more than the maximum (0)",
+            ),
+            ("set colour=red", "Unknown setting 'colour'"),
+            (
+                "set indent=wide",
+                "'indent' must be a number of spaces or 'tab', not 'wide'",
+            ),
+            (
+                "set indent=-1",
+                "'indent' must be a number of spaces or 'tab', not '-1'",
+            ),
+            ("set indent", "'indent' needs a value, like 'indent=4'"),
+            (
+                "set number=1",
+                "'number' must be 'true' or 'false', not '1'",
+            ),
+            ("set nonumber=1", "'nonumber' doesn't take a value"),
+            ("set colour?", "Unknown setting 'colour'"),
+            (
+                "set timeoutlen=soon",
+                "'timeoutlen' must be a number of milliseconds, not 'soon'",
+            ),
+            (
+                "set timeoutlen",
+                "'timeoutlen' needs a value, like 'timeoutlen=500'",
+            ),
+        ] {
+            assert_eq!(
+                parse_ex_command(text),
+                Err(error.to_string()),
+                "parsing {:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn every_command_parses() {
+        // Every command in the table must be handled by the parser (rather than hitting the
+        // `unreachable!`), given the argument it asks for
+        for command in EX_COMMANDS {
+            let text = format!("{} indent=2", command.name);
+            let result = parse_ex_command(&text)
+                .or_else(|_| parse_ex_command(command.name))
+                .or_else(|_| parse_ex_command(&format!("{} 0 1", command.name)));
+            assert!(result.is_ok(), "':{}' couldn't be parsed", command.name);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Conversion of flat records into formats used by other tools
+
+/// The formats that a record can be exported to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// A URL query string, e.g. `key=value&key2=value2`
+    QueryString,
+    /// Environment variable assignments, one per line, e.g. `KEY=value`
+    EnvLines,
+}
+
+/// Render the key-value `entries` of a record in a given [`ExportFormat`]
+pub fn export_record(entries: &[(String, String)], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::QueryString => entries
+            .iter()
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&"),
+        ExportFormat::EnvLines => entries
+            .iter()
+            .map(|(key, value)| format!("{}={}", env_var_name(key), value))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Percent-encode every byte of `s` that isn't an unreserved URL character
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// Convert a key into the conventional form of an environment variable name, i.e. upper case
+/// with every other character replaced by `_`
+fn env_var_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_record, ExportFormat};
+    use crate::ast_spec::json::JSON;
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::NodeMap;
+
+    fn sample_entries() -> Vec<(String, String)> {
+        vec![
+            ("name".to_string(), "sapling".to_string()),
+            ("log-level".to_string(), "very verbose".to_string()),
+            ("enabled".to_string(), "true".to_string()),
+        ]
+    }
+
+    #[test]
+    fn query_string() {
+        assert_eq!(
+            export_record(&sample_entries(), ExportFormat::QueryString),
+            "name=sapling&log-level=very%20verbose&enabled=true"
+        );
+        // Nested values are JSON-encoded inline
+        let node_map: VecNodeMap<JSON<Index>> = TestJSON::Object(vec![
+            ("flag".to_string(), TestJSON::False),
+            ("list".to_string(), TestJSON::Array(vec![TestJSON::True])),
+        ])
+        .build_node_map();
+        let entries = node_map.root_node().record_entries(&node_map).unwrap();
+        assert_eq!(
+            export_record(&entries, ExportFormat::QueryString),
+            "flag=false&list=%5Btrue%5D"
+        );
+        assert_eq!(export_record(&[], ExportFormat::QueryString), "");
+    }
+
+    #[test]
+    fn env_lines() {
+        assert_eq!(
+            export_record(&sample_entries(), ExportFormat::EnvLines),
+            "NAME=sapling\nLOG_LEVEL=very verbose\nENABLED=true"
+        );
+    }
+}
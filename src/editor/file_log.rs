@@ -0,0 +1,110 @@
+//! A [`log`] backend that appends every record to a file, for debugging Sapling while its UI
+//! owns the terminal
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// A [`Log`]ger that writes one line per record to a file.  If writing ever fails (e.g. because
+/// the disk is full), the file is dropped and every later record is silently ignored.
+#[derive(Debug)]
+pub struct FileLogger {
+    file: Mutex<Option<File>>,
+}
+
+impl FileLogger {
+    /// Opens the file at `path` for appending, creating it if it doesn't exist
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileLogger {
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Opens the file at `path` and installs it as the global logger, recording every level of
+    /// log.  This fails if the file can't be opened, or if a logger has already been installed.
+    pub fn install(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        log::set_boxed_logger(Box::new(Self::open(path)?))?;
+        log::set_max_level(LevelFilter::Trace);
+        Ok(())
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        // A poisoned lock means that another thread panicked while writing, so give up logging
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        if let Some(f) = file.as_mut() {
+            let written = writeln!(
+                f,
+                "[{}] {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            if written.is_err() {
+                *file = None;
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(f) = file.as_mut() {
+                if f.flush().is_err() {
+                    *file = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileLogger;
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::editable_tree::spec::Spec;
+    use crate::editor::frontend::TestFrontend;
+    use crate::editor::Editor;
+    use crate::node_map::vec::Index;
+    use std::sync::Arc;
+
+    #[test]
+    fn logs_commands_and_edits() {
+        let path = std::env::temp_dir().join(format!("sapling-log-{}.txt", std::process::id()));
+        // This is the only test that installs a logger, since there can only be one per process
+        FileLogger::install(&path).unwrap();
+
+        let tree: Spec<JSON<Index>> =
+            Spec::from_tree(TestJSON::Array(vec![TestJSON::True]).build_node_map());
+        let frontend = Arc::new(TestFrontend::with_keys(40, 8, "crfuZq!"));
+        Editor::with_frontend(tree, JSONFormat::Pretty, frontend)
+            .run()
+            .unwrap();
+        log::logger().flush();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let has_line = |expected: &str| lines.iter().any(|l| l.ends_with(expected));
+        assert!(has_line("Command 'c' is MoveToFirstChild"));
+        assert!(has_line("Command 'rf' is Replace('f')"));
+        assert!(has_line("Applying Replace('f') to Index(1)"));
+        assert!(has_line("Command 'u' is Undo"));
+        assert!(has_line("Undo successful"));
+        // Errors that are shown to the user are logged too
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("[WARN]") && l.ends_with("Unknown command 'Z'")));
+    }
+}
@@ -0,0 +1,685 @@
+//! Display-only state that controls which nodes have their contents hidden
+
+use crate::ast_spec::display_token::DisplayToken;
+use crate::ast_spec::ASTSpec;
+use crate::node_map::{NodeMap, Reference};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The decorations that a [`FoldState`] adds to the display
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Markers {
+    /// The text appended to the summary of a folded node to show that its contents are hidden
+    pub fold: &'static str,
+    /// The text rendered in front of every pinned node
+    pub pin: &'static str,
+}
+
+impl Markers {
+    /// The default markers, which use Unicode symbols
+    pub const UNICODE: Markers = Markers {
+        fold: "…",
+        pin: "•",
+    };
+    /// Markers that only use ASCII, for terminals that can't display Unicode symbols
+    pub const ASCII: Markers = Markers {
+        fold: "...",
+        pin: "*",
+    };
+}
+
+/// The set of nodes that are folded (i.e. rendered as a one-line summary), collapsed (i.e. only
+/// the first child is rendered, e.g. the key of a JSON object field) or pinned (i.e. never folded)
+/// in the display.  This has no effect on the [`EditableTree`] - it is only used to decide
+/// how the tree is rendered to the screen.
+///
+/// Nodes with more children than the [child limit](FoldState::set_child_limit) are truncated:
+/// only a window of their children is rendered, and the children either side of it are replaced
+/// by markers like `… 499,000 more (press za to expand)`.  This keeps the cost of drawing a huge
+/// node proportional to the size of the window, rather than to the number of its children.
+///
+/// Nodes are identified by their references, so if an edit replaces a node then the fold/pin on
+/// the old node is simply ignored.
+///
+/// [`EditableTree`]: crate::editable_tree::EditableTree
+#[derive(Debug, Clone)]
+pub struct FoldState<Ref: Reference> {
+    folded: HashSet<Ref>,
+    collapsed: HashSet<Ref>,
+    pinned: HashSet<Ref>,
+    /// The index of the first displayed child of each truncated node that isn't displaying its
+    /// first `child_limit` children
+    windows: HashMap<Ref, usize>,
+    /// Nodes that display all of their children, however many there are
+    untruncated: HashSet<Ref>,
+    child_limit: Option<usize>,
+    markers: Markers,
+}
+
+impl<Ref: Reference> Default for FoldState<Ref> {
+    fn default() -> Self {
+        FoldState {
+            folded: HashSet::new(),
+            collapsed: HashSet::new(),
+            pinned: HashSet::new(),
+            windows: HashMap::new(),
+            untruncated: HashSet::new(),
+            child_limit: None,
+            markers: Markers::UNICODE,
+        }
+    }
+}
+
+impl<Ref: Reference> FoldState<Ref> {
+    /// Creates a `FoldState` where every node is expanded, no nodes are pinned and every child is
+    /// displayed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the node at `node` is currently folded
+    pub fn is_folded(&self, node: Ref) -> bool {
+        self.folded.contains(&node)
+    }
+
+    /// Returns `true` if the node at `node` is collapsed to its first child
+    pub fn is_collapsed(&self, node: Ref) -> bool {
+        self.collapsed.contains(&node)
+    }
+
+    /// Collapse or expand a node, returning `true` if the node is now collapsed.  A collapsed
+    /// node is rendered as normal except that every child apart from the first is replaced by a
+    /// placeholder showing the kind of that child.  For example, a collapsed JSON object field
+    /// renders as `"key": <bool>`.
+    pub fn toggle_collapse(&mut self, node: Ref) -> bool {
+        if self.collapsed.remove(&node) {
+            false
+        } else {
+            self.collapsed.insert(node);
+            true
+        }
+    }
+
+    /// Returns `true` if the node at `node` is pinned
+    pub fn is_pinned(&self, node: Ref) -> bool {
+        self.pinned.contains(&node)
+    }
+
+    /// Fold a node, returning `false` (and leaving the node expanded) if the node is pinned
+    pub fn fold(&mut self, node: Ref) -> bool {
+        if self.is_pinned(node) {
+            return false;
+        }
+        self.folded.insert(node);
+        true
+    }
+
+    /// Expand a node if it is folded
+    pub fn unfold(&mut self, node: Ref) {
+        self.folded.remove(&node);
+    }
+
+    /// Fold a node if it is expanded, or expand it if it is folded.  Returns `true` if the node
+    /// is now folded, which won't be the case for pinned nodes.
+    pub fn toggle_fold(&mut self, node: Ref) -> bool {
+        if self.folded.remove(&node) {
+            return false;
+        }
+        self.fold(node)
+    }
+
+    /// Set how many children of a node are displayed at once, or [`None`] to always display every
+    /// child.  This is not reset by [`FoldState::clear`].
+    pub fn set_child_limit(&mut self, limit: Option<usize>) {
+        self.child_limit = limit;
+    }
+
+    /// Returns the indices of the children of `node` (whose reference is `id`) that are
+    /// displayed, or [`None`] if the node isn't truncated and so displays all of its children
+    pub fn shown_children<Node: ASTSpec<Ref>>(&self, id: Ref, node: &Node) -> Option<Range<usize>> {
+        let limit = self.child_limit?;
+        let num_children = node.children().len();
+        if num_children <= limit || self.untruncated.contains(&id) {
+            return None;
+        }
+        let start = self
+            .windows
+            .get(&id)
+            .map_or(0, |&s| s.min(num_children - limit));
+        Some(start..start + limit)
+    }
+
+    /// Display every child of `node`, even if it has more than the child limit
+    pub fn show_all_children(&mut self, node: Ref) {
+        self.windows.remove(&node);
+        self.untruncated.insert(node);
+    }
+
+    /// Expand every node that hides the node at `path` (given as child indices from `root`), so
+    /// that it is visible.  Folded nodes are expanded, collapsed nodes are expanded if the path
+    /// leads through any child but their first, and the window of a truncated node is moved just
+    /// far enough to include the child that the path leads through.  Returns `true` if any node
+    /// was expanded or moved its window.
+    pub fn reveal<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        root: Ref,
+        path: &[usize],
+    ) -> bool {
+        let mut expanded = false;
+        let mut node = root;
+        for &index in path {
+            expanded |= self.folded.remove(&node);
+            if index > 0 {
+                expanded |= self.collapsed.remove(&node);
+            }
+            let n = match node_map.get_node(node) {
+                Some(n) => n,
+                None => break,
+            };
+            if let Some(shown) = self.shown_children(node, n) {
+                if !shown.contains(&index) {
+                    let start = if index < shown.start {
+                        index
+                    } else {
+                        index + 1 - shown.len()
+                    };
+                    self.windows.insert(node, start);
+                    expanded = true;
+                }
+            }
+            match n.children().get(index) {
+                Some(&child) => node = child,
+                None => break,
+            }
+        }
+        expanded
+    }
+
+    /// Pin or unpin a node, returning `true` if the node is now pinned.  Pinning a node also
+    /// expands it.
+    pub fn toggle_pin(&mut self, node: Ref) -> bool {
+        if self.pinned.remove(&node) {
+            false
+        } else {
+            self.pinned.insert(node);
+            self.unfold(node);
+            true
+        }
+    }
+
+    /// Fold every node in the tree under `root` that has children, except pinned nodes and their
+    /// ancestors (which must stay expanded for the pinned nodes to remain visible).
+    pub fn fold_all<Node: ASTSpec<Ref>>(&mut self, node_map: &impl NodeMap<Ref, Node>, root: Ref) {
+        self.fold_all_rec(node_map, root);
+    }
+
+    /// Expand `node` and its descendants down to `depth` levels below `node`, then fold everything
+    /// deeper than that (except pinned nodes and their ancestors).  So with `depth = 1`, `node`
+    /// and its children are displayed, but its children's contents are folded.
+    pub fn fold_below_depth<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        node: Ref,
+        depth: usize,
+    ) {
+        if depth == 0 {
+            self.fold_all_rec(node_map, node);
+            return;
+        }
+        self.unfold(node);
+        if let Some(n) = node_map.get_node(node) {
+            for c in n.children() {
+                self.fold_below_depth(node_map, *c, depth - 1);
+            }
+        }
+    }
+
+    /// Recursive function used by [`FoldState::fold_all`].  Returns `true` if the subtree rooted
+    /// at `node` contains a pinned node.
+    fn fold_all_rec<Node: ASTSpec<Ref>>(
+        &mut self,
+        node_map: &impl NodeMap<Ref, Node>,
+        node: Ref,
+    ) -> bool {
+        let children = match node_map.get_node(node) {
+            Some(n) => n.children(),
+            None => return false,
+        };
+        // We can't short-circuit this, because every child has to be folded
+        let mut contains_pin = self.is_pinned(node);
+        for c in children {
+            contains_pin |= self.fold_all_rec(node_map, *c);
+        }
+        if !contains_pin && !children.is_empty() {
+            self.folded.insert(node);
+        }
+        contains_pin
+    }
+
+    /// Expand every node
+    pub fn unfold_all(&mut self) {
+        self.folded.clear();
+    }
+
+    /// Set the [`Markers`] used to decorate folded and pinned nodes.  These are not reset by
+    /// [`FoldState::clear`].
+    pub fn set_markers(&mut self, markers: Markers) {
+        self.markers = markers;
+    }
+
+    /// Replace the reference of every folded, collapsed, pinned or truncated node with
+    /// `remap(reference)`, e.g. after the nodes have been moved by garbage collection
+    pub fn remap(&mut self, remap: impl Fn(Ref) -> Ref) {
+        for set in [
+            &mut self.folded,
+            &mut self.collapsed,
+            &mut self.pinned,
+            &mut self.untruncated,
+        ] {
+            *set = set.drain().map(&remap).collect();
+        }
+        self.windows = self.windows.drain().map(|(r, s)| (remap(r), s)).collect();
+    }
+
+    /// Remove all folds, collapses and pins, and truncate every node with too many children
+    /// again
+    pub fn clear(&mut self) {
+        self.folded.clear();
+        self.collapsed.clear();
+        self.pinned.clear();
+        self.windows.clear();
+        self.untruncated.clear();
+    }
+
+    /// Returns the set of nodes that will be visible on screen, i.e. all the nodes that aren't
+    /// inside a folded node or hidden by a truncated one.  Folded nodes themselves are visible
+    /// (as their summary).
+    pub fn visible_nodes<Node: ASTSpec<Ref>>(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        root: Ref,
+    ) -> HashSet<Ref> {
+        let mut visible = HashSet::new();
+        let mut refs_to_visit = vec![root];
+        while let Some(r) = refs_to_visit.pop() {
+            visible.insert(r);
+            if self.is_folded(r) {
+                continue;
+            }
+            if let Some(node) = node_map.get_node(r) {
+                // Collapsed nodes only display their first child
+                let shown = match self.shown_children(r, node) {
+                    _ if self.is_collapsed(r) => 0..1,
+                    Some(shown) => shown,
+                    None => 0..usize::MAX,
+                };
+                refs_to_visit.extend(
+                    node.children()
+                        .iter()
+                        .skip(shown.start)
+                        .take(shown.len())
+                        .copied(),
+                );
+            }
+        }
+        visible
+    }
+
+    /// Generates the [`DisplayToken`]s that should be displayed for a given node, taking folds,
+    /// collapses and pins into account.  This is intended to be passed to
+    /// [`flat_tokens_with`](crate::ast_spec::display_token::flat_tokens_with).
+    pub fn display_tokens<Node: ASTSpec<Ref>>(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        id: Ref,
+        node: &Node,
+        format_style: &Node::FormatStyle,
+    ) -> Vec<DisplayToken<Ref>> {
+        self.decorate_tokens(node_map, id, node, |n| {
+            n.display_tokens_in(node_map, format_style)
+        })
+    }
+
+    /// Like [`FoldState::display_tokens`], but generates the tokens of a node with `tokens_for`
+    /// rather than [`ASTSpec::display_tokens_in`].  This allows other display-only state to change
+    /// the tokens of a node before folds, collapses, pins and truncation are applied.
+    /// `tokens_for` isn't called for folded nodes, and is only given the displayed children of
+    /// truncated nodes.
+    pub fn decorate_tokens<Node: ASTSpec<Ref>>(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        id: Ref,
+        node: &Node,
+        tokens_for: impl FnOnce(&Node) -> Vec<DisplayToken<Ref>>,
+    ) -> Vec<DisplayToken<Ref>> {
+        let mut tokens = Vec::new();
+        if self.is_pinned(id) {
+            tokens.push(DisplayToken::Text(self.markers.pin.to_string()));
+        }
+        if self.is_folded(id) {
+            tokens.push(DisplayToken::Text(
+                node.fold_summary(node_map, self.markers.fold),
+            ));
+            return tokens;
+        }
+        let node_tokens = match self.shown_children(id, node) {
+            Some(shown) => match self.truncated_tokens(node, shown, tokens_for) {
+                Ok(node_tokens) => node_tokens,
+                Err(tokens_for) => tokens_for(node),
+            },
+            None => tokens_for(node),
+        };
+        if self.is_collapsed(id) {
+            let first_child = node.children().first().copied();
+            tokens.extend(node_tokens.into_iter().map(|tok| match tok {
+                DisplayToken::Child(c) if Some(c) != first_child => {
+                    let kind_name = node_map.get_node(c).map_or("?", |n| n.kind_name());
+                    DisplayToken::Text(format!("<{}>", kind_name))
+                }
+                t => t,
+            }));
+        } else {
+            tokens.extend(node_tokens);
+        }
+        tokens
+    }
+
+    /// Generate the tokens of a truncated node, of which only the children in `shown` are
+    /// displayed.  `tokens_for` is given a copy of the node that only has the displayed children
+    /// and the children either side of them, and then the tokens of those outer children are
+    /// replaced with markers saying how many children are hidden.  This gives `tokens_for` back if
+    /// the copy can't be made, because the node doesn't allow its children to be removed.
+    fn truncated_tokens<Node: ASTSpec<Ref>, F: FnOnce(&Node) -> Vec<DisplayToken<Ref>>>(
+        &self,
+        node: &Node,
+        shown: Range<usize>,
+        tokens_for: F,
+    ) -> Result<Vec<DisplayToken<Ref>>, F> {
+        let children = node.children();
+        let first = shown.start.saturating_sub(1);
+        let last = (shown.end + 1).min(children.len());
+        let mut copy = node.clone();
+        copy.children_mut()[..last - first].copy_from_slice(&children[first..last]);
+        // Removing from the end means that no children have to be shifted along
+        for index in (last - first..children.len()).rev() {
+            if !copy.remove_child(index) {
+                return Err(tokens_for);
+            }
+        }
+
+        let mut tokens = tokens_for(&copy);
+        let marker = |num_hidden: usize, direction: &str| {
+            DisplayToken::Text(format!(
+                "{} {} {} (press za to expand)",
+                self.markers.fold,
+                group_digits(num_hidden),
+                direction
+            ))
+        };
+        let is_child = |tok: &DisplayToken<Ref>| matches!(tok, DisplayToken::Child(_));
+        if shown.start > 0 {
+            if let Some(tok) = tokens.iter_mut().find(|tok| is_child(tok)) {
+                *tok = marker(shown.start, "earlier");
+            }
+        }
+        if shown.end < children.len() {
+            if let Some(tok) = tokens.iter_mut().rev().find(|tok| is_child(tok)) {
+                *tok = marker(children.len() - shown.end, "more");
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+/// Format `n` with a comma between every group of three digits, e.g. `499,950`
+fn group_digits(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_digits, FoldState, Markers};
+    use crate::ast_spec::display_token::{flat_tokens_with, DisplayToken};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::NodeMap;
+
+    /// Builds the sample tree that Sapling starts up with
+    fn sample_node_map() -> VecNodeMap<JSON<Index>> {
+        TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::False,
+            TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
+        ])
+        .build_node_map()
+    }
+
+    /// Render a tree as a compact string, respecting folds
+    fn render(node_map: &VecNodeMap<JSON<Index>>, folds: &FoldState<Index>) -> String {
+        flat_tokens_with(node_map, node_map.root(), &|id, node: &JSON<Index>| {
+            folds.display_tokens(node_map, id, node, &JSONFormat::Compact)
+        })
+        .into_iter()
+        .map(|(_, tok)| match tok {
+            DisplayToken::Text(s) => s,
+            DisplayToken::Whitespace(n) => " ".repeat(n),
+            _ => String::new(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn fold_all_and_unfold_all() {
+        let node_map = sample_node_map();
+        let root = node_map.root();
+        let object = node_map.root_node().children()[2];
+        let mut folds = FoldState::new();
+
+        folds.fold_all(&node_map, node_map.root());
+        assert!(folds.is_folded(root));
+        assert!(folds.is_folded(object));
+        assert_eq!(folds.visible_nodes(&node_map, root).len(), 1);
+        assert_eq!(render(&node_map, &folds), "[…] 3 items");
+
+        folds.unfold(root);
+        assert_eq!(folds.visible_nodes(&node_map, root).len(), 4);
+        assert_eq!(render(&node_map, &folds), "[true, false, {…} 1 entry]");
+
+        folds.unfold_all();
+        assert_eq!(folds.visible_nodes(&node_map, root).len(), 7);
+        assert_eq!(
+            render(&node_map, &folds),
+            r#"[true, false, {"value": true}]"#
+        );
+    }
+
+    #[test]
+    fn fold_below_relative_depth() {
+        let node_map = sample_node_map();
+        let root = node_map.root();
+        let object = node_map.root_node().children()[2];
+        let field = node_map.get_node(object).unwrap().children()[0];
+        let mut folds = FoldState::new();
+
+        folds.fold_all(&node_map, root);
+        // Folding below depth 1 from the object should expand the object but fold its field
+        folds.fold_below_depth(&node_map, object, 1);
+        assert!(!folds.is_folded(object));
+        assert!(folds.is_folded(field));
+        // The root is outside the cursor's subtree, so shouldn't be changed
+        assert!(folds.is_folded(root));
+        folds.unfold(root);
+        assert_eq!(render(&node_map, &folds), r#"[true, false, {"value": …}]"#);
+
+        // Folding below depth 1 from the root should fold everything inside the root's children
+        folds.unfold_all();
+        folds.fold_below_depth(&node_map, root, 1);
+        assert_eq!(render(&node_map, &folds), "[true, false, {…} 1 entry]");
+        assert!(folds.is_folded(field));
+        folds.fold_below_depth(&node_map, root, 3);
+        assert_eq!(
+            render(&node_map, &folds),
+            r#"[true, false, {"value": true}]"#
+        );
+    }
+
+    #[test]
+    fn pinned_node_stays_visible() {
+        let node_map = sample_node_map();
+        let root = node_map.root();
+        let object = node_map.root_node().children()[2];
+        let mut folds = FoldState::new();
+
+        assert!(folds.toggle_pin(object));
+        folds.fold_all(&node_map, root);
+        // Neither the pinned node nor its ancestors should be folded
+        assert!(!folds.is_folded(object));
+        assert!(!folds.is_folded(root));
+        assert!(folds.visible_nodes(&node_map, root).contains(&object));
+        // The pinned node's descendants are still folded
+        assert_eq!(render(&node_map, &folds), r#"[true, false, •{"value": …}]"#);
+        // Pinned nodes can't be folded directly either
+        assert!(!folds.fold(object));
+        assert!(!folds.is_folded(object));
+
+        // Unpinning the node should allow it to be folded again
+        assert!(!folds.toggle_pin(object));
+        folds.fold_all(&node_map, root);
+        assert!(!folds.visible_nodes(&node_map, root).contains(&object));
+    }
+
+    #[test]
+    fn ascii_markers() {
+        let node_map = sample_node_map();
+        let root = node_map.root();
+        let object = node_map.root_node().children()[2];
+        let mut folds = FoldState::new();
+        folds.set_markers(Markers::ASCII);
+
+        assert!(folds.toggle_pin(object));
+        folds.fold_all(&node_map, root);
+        assert_eq!(
+            render(&node_map, &folds),
+            r#"[true, false, *{"value": ...}]"#
+        );
+        assert!(render(&node_map, &folds).is_ascii());
+        // Clearing the folds shouldn't change the markers
+        folds.clear();
+        folds.fold_all(&node_map, root);
+        assert_eq!(render(&node_map, &folds), "[...] 3 items");
+
+        folds.set_markers(Markers::UNICODE);
+        assert_eq!(render(&node_map, &folds), "[…] 3 items");
+    }
+
+    #[test]
+    fn toggling_and_revealing() {
+        let node_map = sample_node_map();
+        let root = node_map.root();
+        let object = node_map.root_node().children()[2];
+        let field = node_map.get_node(object).unwrap().children()[0];
+        let mut folds = FoldState::new();
+
+        assert!(folds.toggle_fold(object));
+        assert_eq!(render(&node_map, &folds), "[true, false, {…} 1 entry]");
+        assert!(!folds.toggle_fold(object));
+        assert!(!folds.is_folded(object));
+
+        // Revealing a node expands the folds above it, but not the node itself
+        folds.fold_all(&node_map, root);
+        assert!(folds.reveal(&node_map, root, &[2, 0]));
+        assert!(folds.is_folded(field));
+        assert_eq!(render(&node_map, &folds), r#"[true, false, {"value": …}]"#);
+        assert!(!folds.reveal(&node_map, root, &[2, 0]));
+        // Collapsed nodes are only expanded if the node is hidden
+        folds.unfold_all();
+        folds.toggle_collapse(field);
+        assert!(!folds.reveal(&node_map, root, &[2, 0, 0]));
+        assert!(folds.reveal(&node_map, root, &[2, 0, 1]));
+        assert!(!folds.is_collapsed(field));
+
+        // Pinned nodes can't be folded
+        folds.toggle_pin(object);
+        assert!(!folds.toggle_fold(object));
+        assert!(!folds.is_folded(object));
+    }
+
+    #[test]
+    fn collapsed_field() {
+        let node_map = sample_node_map();
+        let root = node_map.root();
+        let object = node_map.root_node().children()[2];
+        let field = node_map.get_node(object).unwrap().children()[0];
+        let value = node_map.get_node(field).unwrap().children()[1];
+        let mut folds = FoldState::new();
+
+        assert!(folds.toggle_collapse(field));
+        assert_eq!(
+            render(&node_map, &folds),
+            r#"[true, false, {"value": <bool>}]"#
+        );
+        // The key is still visible, but the value isn't
+        let visible = folds.visible_nodes(&node_map, root);
+        assert!(visible.contains(&field));
+        assert!(!visible.contains(&value));
+
+        assert!(!folds.toggle_collapse(field));
+        assert_eq!(
+            render(&node_map, &folds),
+            r#"[true, false, {"value": true}]"#
+        );
+    }
+
+    #[test]
+    fn truncated_children() {
+        let node_map: VecNodeMap<JSON<Index>> =
+            TestJSON::Array((0..8).map(|i| TestJSON::Number(i.to_string())).collect())
+                .build_node_map();
+        let root = node_map.root();
+        let mut folds = FoldState::new();
+        folds.set_child_limit(Some(3));
+
+        assert_eq!(folds.shown_children(root, node_map.root_node()), Some(0..3));
+        assert_eq!(
+            render(&node_map, &folds),
+            "[0, 1, 2, … 5 more (press za to expand)]"
+        );
+        assert_eq!(folds.visible_nodes(&node_map, root).len(), 4);
+        // Revealing a hidden child moves the window just far enough to show it
+        assert!(folds.reveal(&node_map, root, &[4]));
+        assert!(!folds.reveal(&node_map, root, &[3]));
+        assert_eq!(
+            render(&node_map, &folds),
+            "[… 2 earlier (press za to expand), 2, 3, 4, … 3 more (press za to expand)]"
+        );
+        assert!(folds.reveal(&node_map, root, &[7]));
+        assert_eq!(
+            render(&node_map, &folds),
+            "[… 5 earlier (press za to expand), 5, 6, 7]"
+        );
+
+        folds.show_all_children(root);
+        assert_eq!(folds.shown_children(root, node_map.root_node()), None);
+        assert_eq!(render(&node_map, &folds), "[0, 1, 2, 3, 4, 5, 6, 7]");
+        // Clearing the folds truncates the node again, but keeps the limit
+        folds.clear();
+        assert_eq!(folds.visible_nodes(&node_map, root).len(), 4);
+        folds.set_child_limit(None);
+        assert_eq!(folds.visible_nodes(&node_map, root).len(), 9);
+
+        assert_eq!(group_digits(7), "7");
+        assert_eq!(group_digits(499_950), "499,950");
+        assert_eq!(group_digits(1_000_000), "1,000,000");
+    }
+}
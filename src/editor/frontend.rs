@@ -0,0 +1,328 @@
+//! The interface between the [`Editor`](super::Editor) and the terminal that it's drawn in
+
+use super::text_width::display_width;
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tuikit::prelude::*;
+
+/// The operations that the [`Editor`](super::Editor) uses to draw itself and read the user's
+/// input.  This is implemented for a real [`Term`], and for a [`TestFrontend`] that lets the
+/// editor run without a terminal.
+///
+/// Frontends must be [`Send`] and [`Sync`] so that they can be restored by a panic hook.
+pub trait Frontend: Send + Sync {
+    /// Take over the screen, before the editor draws anything.  Frontends that are ready as soon
+    /// as they're created don't need to do anything, which is the default.
+    fn start(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the size of the screen as `(width, height)`
+    fn term_size(&self) -> Result<(usize, usize)>;
+
+    /// Clear the contents of the screen
+    fn clear(&self) -> Result<()>;
+
+    /// Draw `text` starting at (`row`, `col`) with the default attributes, returning the number
+    /// of columns drawn
+    fn print(&self, row: usize, col: usize, text: &str) -> Result<usize> {
+        self.print_with_attr(row, col, text, Attr::default())
+    }
+
+    /// Draw `text` starting at (`row`, `col`) with some [`Attr`], returning the number of
+    /// columns drawn
+    fn print_with_attr(&self, row: usize, col: usize, text: &str, attr: Attr) -> Result<usize>;
+
+    /// Show everything drawn since the last call to `present`
+    fn present(&self) -> Result<()>;
+
+    /// Wait for the next [`Event`] of user input
+    fn poll_event(&self) -> Result<Event>;
+
+    /// Wait at most `timeout` for the next [`Event`] of user input, returning [`None`] if there
+    /// wasn't one in time
+    fn poll_event_timeout(&self, timeout: Duration) -> Result<Option<Event>>;
+
+    /// Give the screen back to whatever was using it before the editor started
+    fn pause(&self) -> Result<()>;
+}
+
+impl Frontend for Term {
+    fn term_size(&self) -> Result<(usize, usize)> {
+        Term::term_size(self)
+    }
+
+    fn clear(&self) -> Result<()> {
+        Term::clear(self)
+    }
+
+    fn print_with_attr(&self, row: usize, col: usize, text: &str, attr: Attr) -> Result<usize> {
+        Term::print_with_attr(self, row, col, text, attr)
+    }
+
+    fn present(&self) -> Result<()> {
+        Term::present(self)
+    }
+
+    fn poll_event(&self) -> Result<Event> {
+        Term::poll_event(self)
+    }
+
+    fn poll_event_timeout(&self, timeout: Duration) -> Result<Option<Event>> {
+        // `peek_event` fails in the same way whether it timed out or the input stopped, so an
+        // error is taken as a timeout.  If the input has stopped, the next `poll_event` fails.
+        Ok(Term::peek_event(self, timeout).ok())
+    }
+
+    fn pause(&self) -> Result<()> {
+        Term::pause(self)
+    }
+}
+
+/// A [`Frontend`] that opens the terminal when the editor [starts](Frontend::start), rather than
+/// when the editor is created.  This lets an editor be set up (e.g. by an
+/// [`EditorBuilder`](super::builder::EditorBuilder)) in a program that doesn't have a terminal
+/// yet, or that wants to use the terminal for something else first.
+#[derive(Default)]
+pub struct DeferredTerm {
+    term: OnceLock<Term>,
+}
+
+impl DeferredTerm {
+    /// Creates a `DeferredTerm` that hasn't opened the terminal yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the terminal, or an error if it hasn't been opened by [`start`](Frontend::start)
+    fn term(&self) -> Result<&Term> {
+        self.term
+            .get()
+            .ok_or_else(|| "the terminal hasn't been opened yet".into())
+    }
+}
+
+impl Frontend for DeferredTerm {
+    fn start(&self) -> Result<()> {
+        if self.term.get().is_none() {
+            let term = Term::with_options(TermOptions::default().mouse_enabled(true))?;
+            // Only the editor starts its frontend, so nothing else can have opened it meanwhile
+            let _ = self.term.set(term);
+        }
+        Ok(())
+    }
+
+    fn term_size(&self) -> Result<(usize, usize)> {
+        self.term()?.term_size()
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.term()?.clear()
+    }
+
+    fn print_with_attr(&self, row: usize, col: usize, text: &str, attr: Attr) -> Result<usize> {
+        self.term()?.print_with_attr(row, col, text, attr)
+    }
+
+    fn present(&self) -> Result<()> {
+        self.term()?.present()
+    }
+
+    fn poll_event(&self) -> Result<Event> {
+        self.term()?.poll_event()
+    }
+
+    fn poll_event_timeout(&self, timeout: Duration) -> Result<Option<Event>> {
+        Frontend::poll_event_timeout(self.term()?, timeout)
+    }
+
+    fn pause(&self) -> Result<()> {
+        // A terminal that was never opened has nothing to restore
+        match self.term.get() {
+            Some(term) => term.pause(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The contents of the cells covered by the end of a wide character
+const WIDE_CONTINUATION: char = '\0';
+
+/// The contents of a screen, where each cell contains a [`char`] and its [`Attr`]
+type Cells = Vec<Vec<(char, Attr)>>;
+
+/// The mutable state of a [`TestFrontend`]
+#[derive(Debug)]
+struct TestState {
+    /// The cells that are being drawn, which become visible when they are presented
+    drawing: Cells,
+    /// The cells that were most recently presented
+    screen: Cells,
+    /// The events that haven't been read by the editor yet
+    events: VecDeque<Event>,
+    /// Whether the frontend has been paused
+    paused: bool,
+}
+
+/// A [`Frontend`] with a fixed size, which records what is drawn into a grid of cells and feeds
+/// the editor a scripted sequence of [`Event`]s.  Once every event has been read,
+/// [`poll_event`](Frontend::poll_event) returns an error.
+#[derive(Debug)]
+pub struct TestFrontend {
+    width: usize,
+    height: usize,
+    state: Mutex<TestState>,
+}
+
+impl TestFrontend {
+    /// Creates a blank `TestFrontend` of a given size, which will produce `events` in order
+    pub fn new(width: usize, height: usize, events: impl IntoIterator<Item = Event>) -> Self {
+        let blank = vec![vec![(' ', Attr::default()); width]; height];
+        TestFrontend {
+            width,
+            height,
+            state: Mutex::new(TestState {
+                drawing: blank.clone(),
+                screen: blank,
+                events: events.into_iter().collect(),
+                paused: false,
+            }),
+        }
+    }
+
+    /// Creates a blank `TestFrontend` of a given size, which will produce a keypress for every
+    /// [`char`] in `keys`
+    pub fn with_keys(width: usize, height: usize, keys: &str) -> Self {
+        Self::new(
+            width,
+            height,
+            keys.chars().map(|c| Event::Key(Key::Char(c))),
+        )
+    }
+
+    /// Returns every row of the most recently presented screen, without trailing spaces.  Wide
+    /// characters only appear once, even though they cover more than one cell.
+    pub fn screen(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .screen
+            .iter()
+            .map(|row| {
+                let line = row
+                    .iter()
+                    .map(|(c, _)| *c)
+                    .filter(|c| *c != WIDE_CONTINUATION)
+                    .collect::<String>();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// Returns the [`Attr`] of a cell of the most recently presented screen, or [`None`] if the
+    /// cell is off the screen
+    pub fn attr_at(&self, row: usize, col: usize) -> Option<Attr> {
+        let state = self.state.lock().unwrap();
+        state.screen.get(row)?.get(col).map(|(_, attr)| *attr)
+    }
+
+    /// Returns the number of events that the editor hasn't read yet
+    pub fn events_left(&self) -> usize {
+        self.state.lock().unwrap().events.len()
+    }
+
+    /// Returns `true` if the frontend has been [paused](Frontend::pause)
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+}
+
+impl Frontend for TestFrontend {
+    fn term_size(&self) -> Result<(usize, usize)> {
+        Ok((self.width, self.height))
+    }
+
+    fn clear(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for row in &mut state.drawing {
+            for cell in row {
+                *cell = (' ', Attr::default());
+            }
+        }
+        Ok(())
+    }
+
+    fn print_with_attr(&self, row: usize, col: usize, text: &str, attr: Attr) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let cells = match state.drawing.get_mut(row) {
+            Some(cells) => cells,
+            None => return Ok(0),
+        };
+        // Wide characters fill their first cell, and the rest are marked as part of the character
+        let mut c = col;
+        for ch in text.chars() {
+            let width = display_width(ch.encode_utf8(&mut [0; 4]));
+            if width == 0 {
+                continue;
+            }
+            for (i, cell) in cells.iter_mut().skip(c).take(width).enumerate() {
+                *cell = (if i == 0 { ch } else { WIDE_CONTINUATION }, attr);
+            }
+            c += width;
+        }
+        Ok(c.min(self.width).saturating_sub(col))
+    }
+
+    fn present(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.screen = state.drawing.clone();
+        Ok(())
+    }
+
+    fn poll_event(&self) -> Result<Event> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .events
+            .pop_front()
+            .ok_or_else(|| "no more events".into())
+    }
+
+    /// The events come all at once, so this only times out (straight away) once they've run out
+    fn poll_event_timeout(&self, _timeout: Duration) -> Result<Option<Event>> {
+        Ok(self.state.lock().unwrap().events.pop_front())
+    }
+
+    fn pause(&self) -> Result<()> {
+        self.state.lock().unwrap().paused = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frontend, TestFrontend};
+    use std::time::Duration;
+    use tuikit::prelude::*;
+
+    #[test]
+    fn draws_into_cells() {
+        let frontend = TestFrontend::with_keys(6, 2, "x");
+        frontend.print(0, 1, "ab好cdef").unwrap();
+        frontend
+            .print_with_attr(1, 0, "z", Attr::default().fg(Color::RED))
+            .unwrap();
+        // Nothing is visible until it's presented
+        assert_eq!(frontend.screen(), vec!["", ""]);
+        frontend.present().unwrap();
+        assert_eq!(frontend.screen(), vec![" ab好c", "z"]);
+        assert_eq!(frontend.attr_at(1, 0).unwrap().fg, Color::RED);
+        assert_eq!(frontend.attr_at(2, 0), None);
+        frontend.clear().unwrap();
+        frontend.present().unwrap();
+        assert_eq!(frontend.screen(), vec!["", ""]);
+
+        assert_eq!(frontend.poll_event().unwrap(), Event::Key(Key::Char('x')));
+        assert!(frontend.poll_event().is_err());
+        assert_eq!(frontend.poll_event_timeout(Duration::ZERO).unwrap(), None);
+    }
+}
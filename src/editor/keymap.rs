@@ -0,0 +1,141 @@
+//! User-configurable key bindings, loaded from a keymap file
+//!
+//! A keymap file is a small subset of TOML, where each line binds a key sequence to the name
+//! of a command:
+//!
+//! ```toml
+//! # Comments start with '#'
+//! k = "quit"
+//! "gP" = "ancestor"
+//! ```
+//!
+//! Commands that aren't mentioned keep their default keys.
+
+use super::config::config_dir;
+use std::path::{Path, PathBuf};
+
+/// A set of key bindings, each mapping a key sequence to the name of a command.  A command can
+/// be bound to several key sequences.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct KeyMap {
+    bindings: Vec<(String, String)>,
+}
+
+/// Parse a TOML basic string (e.g. `"ab\"c"`), returning its contents and the text after it
+pub(super) fn parse_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut contents = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((contents, &text[i + 2..])),
+            '\\' => contents.push(match chars.next()?.1 {
+                '"' => '"',
+                '\\' => '\\',
+                't' => '\t',
+                _ => return None,
+            }),
+            c => contents.push(c),
+        }
+    }
+    None
+}
+
+/// Parse a TOML key, which is either a bare key or a basic string
+pub(super) fn parse_key(text: &str) -> Option<(String, &str)> {
+    if text.starts_with('"') {
+        return parse_string(text);
+    }
+    let len = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(text.len());
+    if len == 0 {
+        return None;
+    }
+    Some((text[..len].to_string(), &text[len..]))
+}
+
+impl KeyMap {
+    /// Creates a `KeyMap` with no bindings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind the key sequence `keys` to the command called `action_name`
+    pub fn bind(&mut self, keys: &str, action_name: &str) {
+        self.bindings
+            .push((keys.to_string(), action_name.to_string()));
+    }
+
+    /// Returns every binding, as `(keys, action name)` pairs in the order they were added
+    pub fn bindings(&self) -> &[(String, String)] {
+        &self.bindings
+    }
+
+    /// Parse the text of a keymap file.  Returns an error describing the first line that can't
+    /// be parsed.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut keymap = KeyMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || format!("line {}: expected `keys = \"command\"`", i + 1);
+            let (keys, rest) = parse_key(line).ok_or_else(error)?;
+            let rest = rest.trim_start().strip_prefix('=').ok_or_else(error)?;
+            let (action_name, rest) = parse_string(rest.trim_start()).ok_or_else(error)?;
+            let rest = rest.trim_start();
+            if !(rest.is_empty() || rest.starts_with('#')) {
+                return Err(error());
+            }
+            keymap.bind(&keys, &action_name);
+        }
+        Ok(keymap)
+    }
+
+    /// Read and parse the keymap file at `path`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_text(&text)
+    }
+
+    /// Returns the default location of the keymap file (`$XDG_CONFIG_HOME/sapling/keymap.toml`,
+    /// or `~/.config/sapling/keymap.toml`), if it can be determined
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("keymap.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyMap;
+
+    #[test]
+    fn from_text() {
+        let keymap = KeyMap::from_text(
+            "# A comment\n\nk = \"quit\"\n  \"g P\" = \"ancestor\"  # trailing\n\"\\\"\"=\"undo\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            keymap.bindings(),
+            &[
+                ("k".to_string(), "quit".to_string()),
+                ("g P".to_string(), "ancestor".to_string()),
+                ("\"".to_string(), "undo".to_string()),
+            ][..]
+        );
+        for (text, line) in &[
+            ("k quit", 1),
+            ("k = quit", 1),
+            ("\n= \"quit\"", 2),
+            ("k = \"quit\" extra", 1),
+            ("k = \"unterminated", 1),
+            ("[section]", 1),
+        ] {
+            assert_eq!(
+                KeyMap::from_text(text).unwrap_err(),
+                format!("line {}: expected `keys = \"command\"`", line)
+            );
+        }
+    }
+}
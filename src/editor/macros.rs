@@ -0,0 +1,144 @@
+//! Recording and playback of keystroke macros
+
+use std::collections::HashMap;
+use tuikit::prelude::Key;
+
+/// The state of the user's macros.  Each macro is stored in a register named by a lowercase ASCII
+/// letter, and contains the raw keys that the user typed while it was being recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    /// The keys of every macro that has been recorded
+    registers: HashMap<char, Vec<Key>>,
+    /// The register being recorded into, along with the keys recorded so far
+    recording: Option<(char, Vec<Key>)>,
+    /// The registers whose macros are currently being played, outermost first.  This is used to
+    /// stop macros that (directly or indirectly) play themselves.
+    playing: Vec<char>,
+    /// Set when a macro tries to play itself, so that every enclosing playback stops
+    aborted: bool,
+}
+
+impl Macros {
+    /// Creates a `Macros` with no macros recorded
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if `register` is a valid name for a macro register
+    pub fn is_register(register: char) -> bool {
+        register.is_ascii_lowercase()
+    }
+
+    /// Start recording keys into `register`, discarding any recording that was in progress.
+    /// Returns `false` if `register` isn't a valid register name.
+    pub fn start_recording(&mut self, register: char) -> bool {
+        if !Self::is_register(register) {
+            return false;
+        }
+        self.recording = Some((register, Vec::new()));
+        true
+    }
+
+    /// Stop recording, saving the recorded keys to their register.  Returns the register that
+    /// was recorded into, or [`None`] if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Option<char> {
+        let (register, keys) = self.recording.take()?;
+        self.registers.insert(register, keys);
+        Some(register)
+    }
+
+    /// Returns the register being recorded into, if a macro is being recorded
+    pub fn recording_register(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    /// Record a key that was typed by the user.  Keys are only recorded while a macro is being
+    /// recorded, and not while another macro is playing (since the key that started the playback
+    /// is recorded instead).
+    pub fn record_key(&mut self, key: Key) {
+        if !self.playing.is_empty() {
+            return;
+        }
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+    }
+
+    /// Returns the keys recorded in `register`, if a macro has been recorded there
+    pub fn get(&self, register: char) -> Option<&[Key]> {
+        self.registers.get(&register).map(Vec::as_slice)
+    }
+
+    /// Record that the macro in `register` has started playing.  Returns `false` (and aborts all
+    /// playback) if that macro is already playing, since playing it again would never finish.
+    pub fn start_playing(&mut self, register: char) -> bool {
+        if self.playing.contains(&register) {
+            self.aborted = true;
+            return false;
+        }
+        self.playing.push(register);
+        true
+    }
+
+    /// Record that the innermost playing macro has finished
+    pub fn stop_playing(&mut self) {
+        self.playing.pop();
+        if self.playing.is_empty() {
+            self.aborted = false;
+        }
+    }
+
+    /// Returns `true` if playback has been aborted, so that every playing macro should stop
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Macros;
+    use tuikit::prelude::Key;
+
+    #[test]
+    fn record() {
+        let mut macros = Macros::new();
+        // Keys typed while nothing is recorded should be ignored
+        macros.record_key(Key::Char('x'));
+        assert_eq!(macros.stop_recording(), None);
+        assert!(!macros.start_recording('A'));
+        assert!(macros.start_recording('a'));
+        assert_eq!(macros.recording_register(), Some('a'));
+        macros.record_key(Key::Char('r'));
+        macros.record_key(Key::Char('t'));
+        assert_eq!(macros.get('a'), None);
+        assert_eq!(macros.stop_recording(), Some('a'));
+        assert_eq!(macros.recording_register(), None);
+        assert_eq!(macros.get('a'), Some(&[Key::Char('r'), Key::Char('t')][..]));
+
+        // Keys replayed by a macro shouldn't be recorded again
+        assert!(macros.start_recording('b'));
+        macros.record_key(Key::Char('@'));
+        assert!(macros.start_playing('a'));
+        macros.record_key(Key::Char('r'));
+        macros.stop_playing();
+        macros.stop_recording();
+        assert_eq!(macros.get('b'), Some(&[Key::Char('@')][..]));
+    }
+
+    #[test]
+    fn recursive_playback() {
+        let mut macros = Macros::new();
+        assert!(macros.start_playing('a'));
+        assert!(macros.start_playing('b'));
+        assert!(!macros.is_aborted());
+        // `b` playing `a` would loop forever
+        assert!(!macros.start_playing('a'));
+        assert!(macros.is_aborted());
+        macros.stop_playing();
+        assert!(macros.is_aborted());
+        // Once the outermost macro stops, macros can be played again
+        macros.stop_playing();
+        assert!(!macros.is_aborted());
+        assert!(macros.start_playing('a'));
+    }
+}
@@ -0,0 +1,6557 @@
+//! The top-level functionality of Sapling
+
+pub mod auto_save;
+mod buffer;
+pub mod builder;
+pub mod clipboard;
+mod command_table;
+pub mod completion;
+pub mod config;
+mod display_cache;
+pub mod duplicate_keys;
+mod ex_commands;
+pub mod export;
+pub mod file_log;
+pub mod folds;
+pub mod frontend;
+pub mod keymap;
+pub mod macros;
+pub mod script;
+pub mod session;
+mod text_width;
+pub mod value_formatters;
+
+use crate::ast_spec::display_token::{
+    flat_tokens_for_lines, line_of_node, subtree_token_range, DisplayToken, IndentStyle,
+};
+use crate::ast_spec::{display_path, size, ASTSpec, LiteralValidity, SyntaxCategory};
+use crate::editable_tree::{EditError, EditableTree, PastePosition};
+use crate::node_map::subtree::Subtree;
+use crate::node_map::{NodeMap, Reference};
+use crate::script::ScriptError;
+use auto_save::{write_atomically, AutoSave};
+use buffer::Buffer;
+use builder::EditorBuilder;
+use clipboard::{Clipboard, SystemClipboard};
+use command_table::{CommandTable, Pending, Resolution};
+use completion::{complete, complete_path, FileSystem, PathSource};
+use config::{ColorScheme, Config, Setting};
+use display_cache::DisplayCache;
+use ex_commands::{parse_ex_command, Argument, EX_COMMANDS};
+use export::ExportFormat;
+use folds::{FoldState, Markers};
+use frontend::Frontend;
+use keymap::KeyMap;
+use macros::Macros;
+use script::Script;
+use session::Session;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hasher;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use text_width::{
+    display_width, last_line_width, slice_columns, truncate_start_to_width, truncate_to_width,
+};
+use tuikit::prelude::*;
+use value_formatters::ValueFormatters;
+
+/// The possible log levels
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LogLevel {
+    /// Logs that give lots of minute details.  Intended to be used only when debugging Sapling.
+    VerboseDebug = 0,
+    /// Less verbose debugging, intended to be used only when debugging Sapling.
+    Debug = 1,
+    /// Logs that just give information to the user
+    Info = 2,
+    /// Logs that represent warnings about an Error that is either going to happen or might have
+    /// already happened.
+    Warning = 3,
+    /// Logs that will always be logged.
+    Error = 4,
+}
+
+impl LogLevel {
+    /// Returns a [`Color`] with which to display this log entry
+    pub fn to_color(&self) -> Color {
+        match self {
+            LogLevel::VerboseDebug => Color::BLUE,
+            LogLevel::Debug => Color::LIGHT_BLUE,
+            LogLevel::Info => Color::GREEN,
+            LogLevel::Warning => Color::YELLOW,
+            LogLevel::Error => Color::RED,
+        }
+    }
+
+    /// Returns the [`log::Level`] that entries of this level are forwarded to the [`log`] facade
+    /// with
+    pub fn to_log_level(&self) -> log::Level {
+        match self {
+            LogLevel::VerboseDebug => log::Level::Trace,
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Warning => log::Level::Warn,
+            LogLevel::Error => log::Level::Error,
+        }
+    }
+}
+
+/// The errors that can occur when creating an [`Editor`]
+#[derive(Debug)]
+pub enum EditorError {
+    /// The terminal couldn't be opened or restored (e.g. because Sapling isn't being run in a
+    /// TTY)
+    Terminal(Box<dyn Error>),
+    /// The terminal stopped delivering input while the editor was running
+    Input(Box<dyn Error>),
+    /// The [`KeyMap`] given to an [`EditorBuilder`] couldn't be applied, for the reason given
+    Keymap(String),
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditorError::Terminal(e) => write!(f, "Couldn't open the terminal: {}", e),
+            EditorError::Input(e) => write!(f, "Couldn't read from the terminal: {}", e),
+            EditorError::Keymap(reason) => write!(f, "Couldn't apply the keymap: {}", reason),
+        }
+    }
+}
+
+impl Error for EditorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EditorError::Terminal(e) | EditorError::Input(e) => Some(e.as_ref()),
+            EditorError::Keymap(_) => None,
+        }
+    }
+}
+
+/// What the user left behind when they quit, as returned by [`Editor::run`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExitSummary {
+    /// `true` if every buffer's changes had been written when the user quit, or `false` if some
+    /// were discarded (e.g. by `q!`)
+    pub saved: bool,
+    /// The file of the buffer that was being edited when the user quit, if it has one
+    pub path: Option<PathBuf>,
+}
+
+/// The possible meanings of a user-typed command
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Action {
+    /// The user typed a command that isn't defined, but the command box should still be cleared
+    Undefined,
+    /// Show the help screen, which lists every command
+    ShowHelp,
+    /// Quit Sapling, unless any buffer has unsaved changes
+    Quit,
+    /// Quit Sapling, discarding any unsaved changes
+    ForceQuit,
+    /// Write the tree to the editor's file
+    Write,
+    /// Write the tree to the editor's file, and then quit if that succeeded (and no other buffer
+    /// has unsaved changes)
+    WriteAndQuit,
+    /// Replace the selected node with a node represented by some [`char`]
+    Replace(char),
+    /// Insert a new node (given by some [`char`]) as the child of the selected node at a given
+    /// index
+    InsertChild(char, usize),
+    /// Insert a new node (given by some [`char`]) as the last child of the selected node
+    AppendChild(char),
+    /// Insert a new node (given by some [`char`]) as the next sibling of the selected node
+    InsertNextSibling(char),
+    /// Insert a new node (given by some [`char`]) as the previous sibling of the selected node
+    InsertPrevSibling(char),
+    /// Undo the last change
+    Undo,
+    /// Redo a change
+    Redo,
+    /// Fold every node that isn't pinned
+    FoldAll,
+    /// Expand every folded node
+    UnfoldAll,
+    /// Fold or unfold the selected node, so that it's displayed as a one-line summary
+    ToggleFold,
+    /// Pin or unpin the selected node, so that it is never folded
+    TogglePin,
+    /// Collapse or expand the selected node so that only its first child (e.g. the key of an
+    /// object field) is displayed
+    ToggleCollapse,
+    /// Switch to the next way of displaying the tree
+    CycleFormatStyle,
+    /// Show or hide the line numbers to the left of the tree
+    ToggleLineNumbers,
+    /// Switch read-only mode on or off
+    ToggleReadOnly,
+    /// Report how many bytes and lines the text of the selected node occupies
+    ReportSize,
+    /// Move the cursor to the nearest ancestor with the same kind as the node represented by some
+    /// [`char`]
+    MoveToAncestor(char),
+    /// Write the commands of this session to a replayable script file
+    ExportScript,
+    /// Write the whole editing session, including the undo history, to a file so that it can be
+    /// resumed later
+    WriteSession,
+    /// Write the whole editing session (like [`Action::WriteSession`]) to a given file
+    WriteSessionTo(PathBuf),
+    /// Report how many nodes of each kind are in the tree
+    ReportKindHistogram,
+    /// Free the nodes that the tree and its undo history no longer use
+    CollectGarbage,
+    /// Merge the selected node with its next sibling
+    MergeWithNextSibling,
+    /// Replace the selected node with a new node (given by some [`char`]) that contains it
+    Wrap(char),
+    /// Replace the selected node with the single node that it contains, or else splice its
+    /// children into its parent
+    Unwrap,
+    /// Swap the selected node with its next sibling, keeping the cursor on it
+    SwapWithNextSibling,
+    /// Swap the selected node with its previous sibling, keeping the cursor on it
+    SwapWithPrevSibling,
+    /// Move to the node under the same key in the next record (e.g. the next object in an array)
+    MoveToSameKeyInNextRecord,
+    /// Replace every node equal to the node represented by the first [`char`] with the node
+    /// represented by the second [`char`]
+    ReplaceAll(char, char),
+    /// Report what [`Action::ReplaceAll`] would change, without changing the tree
+    ReplaceAllDryRun(char, char),
+    /// Copy the selected record to the clipboard in a given [`ExportFormat`]
+    CopyRecord(ExportFormat),
+    /// Display the selected node's subtree to a given depth, folding everything below that
+    FoldBelowDepth(usize),
+    /// Scroll the tree left by half of the screen's width
+    ScrollLeft,
+    /// Scroll the tree right by half of the screen's width
+    ScrollRight,
+    /// Rewrite every node in the tree into its [canonical form](ASTSpec::normalized) (e.g.
+    /// minimising the escape sequences in strings)
+    NormalizeAll,
+    /// Move the cursor to the root, selecting the whole document
+    MoveToRoot,
+    /// Invert every boolean in the selected node's subtree
+    InvertBooleans,
+    /// Sort the entries of the selected record by their keys
+    SortChildren,
+    /// Sort the children of the selected node by their text, even if it isn't a record (whose
+    /// entries are still sorted by key)
+    ForceSortChildren,
+    /// Move the cursor to the first child of the selected node
+    MoveToFirstChild,
+    /// Move the cursor to the parent of the selected node
+    MoveToParent,
+    /// Move the cursor forwards through its siblings a given number of times
+    MoveToNextSibling(usize),
+    /// Move the cursor backwards through its siblings a given number of times
+    MoveToPrevSibling(usize),
+    /// Move the cursor to the sibling with a given index, or the last sibling if there aren't
+    /// that many
+    MoveToSibling(usize),
+    /// Copy the subtrees of the selected node and some number of its following siblings (so that
+    /// a given number of subtrees are copied in total) into the yank register
+    Yank(usize),
+    /// Start selecting a range of siblings, anchored at the selected node
+    StartVisual,
+    /// Add copies of the subtrees in the yank register to the tree
+    Paste(PastePosition),
+    /// Copy the subtrees of the selected node and some number of its following siblings into the
+    /// named register given by some [`char`] (and the yank register)
+    YankInto(char, usize),
+    /// Paste the subtrees in the named register given by some [`char`] after the selected node
+    PasteFrom(char),
+    /// List the contents of every register that isn't empty
+    ListRegisters,
+    /// Insert a copy of the selected node's subtree as its next sibling
+    Duplicate,
+    /// Copy the text of the selected node's subtree to the system clipboard
+    CopyToClipboard,
+    /// Parse the text on the system clipboard, and paste it after the selected node
+    PasteFromClipboard,
+    /// Delete the selected node and its subtree, along with some number of its following siblings
+    /// (so that a given number of nodes are deleted in total)
+    Delete(usize),
+    /// Repeat the most recent [repeatable](Action::is_repeatable) edit a given number of times
+    RepeatLastEdit(usize),
+    /// Open the prompt for a search query
+    StartSearch,
+    /// Open the prompt for the path of a file to open in a new buffer
+    StartOpenFile,
+    /// Open the prompt for a named command (see [`ex_commands`])
+    StartExCommand,
+    /// Open the file at a given path in a new buffer
+    OpenFile(PathBuf),
+    /// Read the buffer's file again, replacing the tree (after asking, if that would discard
+    /// unsaved changes)
+    Reload,
+    /// Write the tree to its file, even if another program has changed the file since it was
+    /// read
+    ForceWrite,
+    /// Write the tree to a given file.  If the buffer has no file yet, this becomes its file.
+    WriteTo(PathBuf),
+    /// Write the tree to a given file (like [`Action::WriteTo`]), and then quit if that
+    /// succeeded (and no buffer has unsaved changes)
+    WriteToAndQuit(PathBuf),
+    /// Change one of the editor's settings
+    ChangeSetting(Setting),
+    /// Show the value of the setting with a given name
+    ShowSetting(&'static str),
+    /// Report the size and shape of the tree, in one line
+    ReportStats,
+    /// Switch to the next buffer, wrapping round to the first
+    NextBuffer,
+    /// Switch to the previous buffer, wrapping round to the last
+    PrevBuffer,
+    /// Close the current buffer, unless it has unsaved changes
+    CloseBuffer,
+    /// Close the current buffer, discarding any unsaved changes
+    ForceCloseBuffer,
+    /// Move to the next node that matches the last search query
+    SearchNext,
+    /// Move to the previous node that matches the last search query
+    SearchPrev,
+    /// Move to the next record entry whose key is shared with another entry of its record
+    NextDuplicateKey,
+    /// Move to the previous record entry whose key is shared with another entry of its record
+    PrevDuplicateKey,
+    /// Bookmark the selected node with a given letter
+    SetMark(char),
+    /// Move the cursor to the node bookmarked with a given letter
+    JumpToMark(char),
+    /// Report every mark in the current buffer, along with where it points
+    ListMarks,
+    /// Start recording the user's keys into the macro register given by some [`char`]
+    StartRecording(char),
+    /// Play the keys in the macro register given by some [`char`] a given number of times
+    PlayMacro(char, usize),
+    /// Enter insert mode to edit the [literal value](ASTSpec::literal) of the selected node
+    EditLiteral,
+    /// Move to the key of the selected record entry (or of the entry containing the selected
+    /// node), and enter insert mode to rename it
+    RenameKey,
+    /// Replace the [literal value](ASTSpec::literal) of the selected node with the given text
+    SetLiteral(String),
+    /// Clamp the number in the selected node into the range between a minimum and a maximum
+    /// (written as they should appear in the tree), leaving it alone if it's already in range
+    Clamp(String, String),
+    /// Show what has changed since a given number of versions ago in the undo history, or since
+    /// the tree was last saved
+    ShowChanges(Option<usize>),
+}
+
+impl Action {
+    /// Returns `true` if this action can change the tree, the cursor position or the yank
+    /// register, and therefore must be recorded for the session to be replayed
+    fn affects_tree(&self) -> bool {
+        matches!(
+            self,
+            Action::Replace(_)
+                | Action::InsertChild(_, _)
+                | Action::AppendChild(_)
+                | Action::InsertNextSibling(_)
+                | Action::InsertPrevSibling(_)
+                | Action::Undo
+                | Action::Redo
+                | Action::MoveToAncestor(_)
+                | Action::MergeWithNextSibling
+                | Action::SwapWithNextSibling
+                | Action::SwapWithPrevSibling
+                | Action::Wrap(_)
+                | Action::Unwrap
+                | Action::MoveToSameKeyInNextRecord
+                | Action::ReplaceAll(_, _)
+                | Action::NormalizeAll
+                | Action::MoveToRoot
+                | Action::InvertBooleans
+                | Action::SortChildren
+                | Action::ForceSortChildren
+                | Action::MoveToFirstChild
+                | Action::MoveToParent
+                | Action::MoveToNextSibling(_)
+                | Action::MoveToPrevSibling(_)
+                | Action::MoveToSibling(_)
+                | Action::Delete(_)
+                | Action::Yank(_)
+                | Action::Paste(_)
+                | Action::YankInto(_, _)
+                | Action::PasteFrom(_)
+                | Action::Duplicate
+                | Action::RepeatLastEdit(_)
+                | Action::SetLiteral(_)
+                | Action::RenameKey
+        )
+    }
+
+    /// Returns `true` if this action can change the tree itself (rather than just the cursor
+    /// position), and therefore counts towards an [`AutoSave`]
+    fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            Action::Replace(_)
+                | Action::InsertChild(_, _)
+                | Action::AppendChild(_)
+                | Action::InsertNextSibling(_)
+                | Action::InsertPrevSibling(_)
+                | Action::Undo
+                | Action::Redo
+                | Action::MergeWithNextSibling
+                | Action::SwapWithNextSibling
+                | Action::SwapWithPrevSibling
+                | Action::Wrap(_)
+                | Action::Unwrap
+                | Action::ReplaceAll(_, _)
+                | Action::NormalizeAll
+                | Action::InvertBooleans
+                | Action::SortChildren
+                | Action::ForceSortChildren
+                | Action::Delete(_)
+                | Action::Paste(_)
+                | Action::PasteFrom(_)
+                | Action::Duplicate
+                | Action::PasteFromClipboard
+                | Action::SetLiteral(_)
+                | Action::Clamp(_, _)
+        )
+    }
+
+    /// Returns `true` if this action changes the tree (or writes it to its file), and so isn't
+    /// allowed in [read-only mode](Editor::set_read_only)
+    fn is_mutating(&self) -> bool {
+        self.is_edit()
+            || matches!(
+                self,
+                Action::EditLiteral
+                    | Action::RenameKey
+                    | Action::Write
+                    | Action::ForceWrite
+                    | Action::WriteAndQuit
+                    | Action::WriteTo(_)
+                    | Action::WriteToAndQuit(_)
+            )
+    }
+
+    /// Returns `true` if this action only moves the cursor, and so can't change what the text of
+    /// the tree looks like
+    fn is_motion(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveToAncestor(_)
+                | Action::MoveToSameKeyInNextRecord
+                | Action::MoveToRoot
+                | Action::MoveToFirstChild
+                | Action::MoveToParent
+                | Action::MoveToNextSibling(_)
+                | Action::MoveToPrevSibling(_)
+                | Action::MoveToSibling(_)
+                | Action::SearchNext
+                | Action::SearchPrev
+                | Action::NextDuplicateKey
+                | Action::PrevDuplicateKey
+                | Action::JumpToMark(_)
+        )
+    }
+
+    /// Returns `true` if this action is an edit that can be repeated with `.`.  Undo and redo
+    /// aren't repeatable, since repeating them would just step further through the history.
+    fn is_repeatable(&self) -> bool {
+        self.is_edit() && !matches!(self, Action::Undo | Action::Redo)
+    }
+}
+
+/// What the text typed into the prompt in the bottom bar is for
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum PromptKind {
+    /// A search query, which moves the cursor to the next matching node when submitted
+    Search,
+    /// The path of a file, which is opened in a new buffer when submitted
+    OpenFile,
+    /// A named command (like `w out.json`), which is run when submitted
+    ExCommand,
+    /// The answer to whether unsaved changes should be discarded by reloading the file, which
+    /// reloads it if the answer is `y`
+    ConfirmReload,
+}
+
+impl PromptKind {
+    /// Returns the text shown before whatever the user has typed
+    fn label(self) -> &'static str {
+        match self {
+            PromptKind::Search => "/",
+            PromptKind::OpenFile => "Open: ",
+            PromptKind::ExCommand => ":",
+            PromptKind::ConfirmReload => "Discard unsaved changes and reload? (y/n) ",
+        }
+    }
+}
+
+/// The candidates that pressing `Tab` again in the prompt will cycle through
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct CompletionCycle {
+    /// The text of the prompt before the word being completed
+    base: String,
+    /// Every candidate that the word could be completed to
+    candidates: Vec<String>,
+    /// The candidate currently shown in the prompt, or [`None`] if none of them have been shown
+    index: Option<usize>,
+}
+
+/// The ways that the [`Editor`] can interpret keypresses
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Mode<Node> {
+    /// Keys are typed into the command buffer
+    Normal,
+    /// Keys edit the [literal value](ASTSpec::literal) of the selected node.  The edited copy of
+    /// the node is displayed in place of the selected node until the edit is committed.
+    Insert(Node),
+    /// A range of siblings is selected, between the one at the given index and the selected node.
+    /// Only sibling motions (which extend the range) and commands that act on the whole range are
+    /// allowed, so the cursor can't leave the siblings.
+    Visual(usize),
+}
+
+/// Returns the colour that text of a given [`SyntaxCategory`] is displayed in, or [`None`] if
+/// each node should be given its own colour
+fn syntax_color(category: SyntaxCategory) -> Option<Color> {
+    match category {
+        SyntaxCategory::Keyword => Some(Color::MAGENTA),
+        SyntaxCategory::Literal => Some(Color::CYAN),
+        SyntaxCategory::String => Some(Color::GREEN),
+        SyntaxCategory::Punctuation => Some(Color::WHITE),
+        SyntaxCategory::Other => None,
+    }
+}
+
+/// The number of lines kept visible above and below the selected node when scrolling, if the
+/// screen is tall enough
+const SCROLL_MARGIN: usize = 3;
+
+/// Returns the scroll offset (the first line shown) that is closest to `offset` but which keeps
+/// the line `cursor_line` on a screen `view_height` lines tall, with [`SCROLL_MARGIN`] lines
+/// around it where possible
+fn clamp_scroll_offset(offset: usize, cursor_line: usize, view_height: usize) -> usize {
+    let view_height = view_height.max(1);
+    let margin = SCROLL_MARGIN.min((view_height - 1) / 2);
+    let min_offset = (cursor_line + margin + 1).saturating_sub(view_height);
+    let max_offset = cursor_line.saturating_sub(margin);
+    offset.clamp(min_offset, max_offset)
+}
+
+/// The minimum number of columns kept between the start of the selected node and the right edge
+/// of the screen, when the tree has to be scrolled horizontally to show it
+const SIDE_SCROLL_MARGIN: usize = 8;
+
+/// Returns the horizontal scroll offset (the first column shown) that is closest to `offset` but
+/// which keeps some of `node_cols` (the columns that the first line of the selected node covers)
+/// on a screen `view_width` columns wide.  If the node starts past the right edge, the screen is
+/// scrolled to show [`SIDE_SCROLL_MARGIN`] columns of it where possible.  The screen can be
+/// scrolled along a node that is wider than it, until fewer than that many columns of the node
+/// are left on the screen.
+fn clamp_horizontal_scroll(offset: usize, node_cols: Range<usize>, view_width: usize) -> usize {
+    let view_width = view_width.max(1);
+    let margin = SIDE_SCROLL_MARGIN.min((view_width - 1) / 2);
+    let min_offset = (node_cols.start + margin + 1).saturating_sub(view_width);
+    let max_offset = node_cols.end.saturating_sub(margin + 1).max(min_offset);
+    offset.clamp(min_offset, max_offset)
+}
+
+/// The part of the screen that the tree is drawn in
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct TreeView {
+    /// The screen row on which the first line is drawn
+    top_row: usize,
+    /// The screen column that every line is indented relative to
+    left: usize,
+    /// The screen column after the last one that the tree can be drawn in
+    right: usize,
+    /// The line of the text that is drawn at `top_row`
+    first_line: usize,
+    /// The column of the text that is drawn at `left`, which is more than 0 if the tree has been
+    /// scrolled horizontally
+    first_col: usize,
+    /// The maximum number of lines that are drawn
+    max_lines: usize,
+}
+
+impl TreeView {
+    /// Returns the number of columns of text that fit in this view
+    fn width(&self) -> usize {
+        self.right.saturating_sub(self.left)
+    }
+}
+
+/// The token at the start of a line of text, from which the tokens can be positioned
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+struct LineStart {
+    /// The index of the token
+    token: usize,
+    /// The line (of the whole text) that the token starts
+    line: usize,
+    /// The indentation of the line
+    indentation: usize,
+}
+
+/// The flattened tokens of a tree, as generated by [`flat_tokens_for_lines`]
+type FlatTokens<Ref> = [(Ref, DisplayToken<Ref>)];
+
+/// A piece of text that is drawn in the tree view
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct ScreenText<Ref> {
+    /// The screen row that the text is drawn on
+    row: usize,
+    /// The screen column of the first character of the text
+    col: usize,
+    text: String,
+    /// The node whose tokens contain this text
+    node: Ref,
+    /// The index of the token that the text came from
+    token_index: usize,
+}
+
+/// Work out where the text of `tokens` is drawn in `view`, starting from the token given by
+/// `start`, where each level of indentation is `indent_width` columns wide.  Returns the text
+/// that is on the screen, along with the number of lines that the text reached (which is at most
+/// `view.first_line + view.max_lines`).  Each [`ScreenText`] is on a single row, and lines that
+/// are wider than the view are [clipped](clip_line) to fit it.
+fn position_tokens<Ref: Reference>(
+    tokens: &FlatTokens<Ref>,
+    view: &TreeView,
+    start: LineStart,
+    indent_width: usize,
+) -> (Vec<ScreenText<Ref>>, usize) {
+    let end_line = view.first_line + view.max_lines;
+    let mut texts = Vec::new();
+    // `row` counts lines from the start of the text, and is only converted to a screen row once
+    // the text is known to be on the screen.  Likewise, `col` counts columns from the start of
+    // the line, ignoring the view.
+    let mut row = start.line;
+    let mut col = start.indentation;
+    let mut indentation = start.indentation;
+    for (i, (r, t)) in tokens.iter().enumerate().skip(start.token) {
+        if row >= end_line {
+            break;
+        }
+        let text = match t {
+            DisplayToken::Text(s) => s.clone(),
+            DisplayToken::InvalidRef => format!("<INVALID REF {:?}>", r),
+            DisplayToken::Whitespace(n) => {
+                col += n;
+                continue;
+            }
+            DisplayToken::Newline => {
+                row += 1;
+                col = indentation;
+                continue;
+            }
+            DisplayToken::Indent => {
+                indentation += indent_width;
+                continue;
+            }
+            DisplayToken::Dedent => {
+                indentation = indentation.saturating_sub(indent_width);
+                continue;
+            }
+            DisplayToken::Child(_) => unreachable!(),
+        };
+        // The lines of a multi-line text after the first start at the left edge
+        for (line_index, line) in text.split('\n').enumerate() {
+            let line_row = row + line_index;
+            if line.is_empty() || line_row < view.first_line || line_row >= end_line {
+                continue;
+            }
+            texts.push(ScreenText {
+                row: view.top_row + line_row - view.first_line,
+                col: if line_index == 0 { col } else { 0 },
+                text: line.to_string(),
+                node: *r,
+                token_index: i,
+            });
+        }
+        // Move the cursor to the end of the text
+        let size = size::Size::from(text.as_str());
+        if size.lines() == 0 {
+            col += last_line_width(&text);
+        } else {
+            row += size.lines();
+            col = last_line_width(&text);
+        }
+    }
+    // The texts are in order, so the texts on each row are next to each other
+    let mut clipped = Vec::with_capacity(texts.len());
+    let mut rest = texts.as_slice();
+    while let Some(first) = rest.first() {
+        let (line, next) = rest.split_at(rest.iter().take_while(|t| t.row == first.row).count());
+        clipped.extend(clip_line(line, view));
+        rest = next;
+    }
+    (clipped, (row + 1).min(end_line))
+}
+
+/// The character drawn at the edge of the tree view in place of text that doesn't fit
+const CLIPPED_MARKER: &str = "…";
+
+/// Cut the texts of a single line down to the columns of the line that are shown in `view`,
+/// moving them to their screen columns.  The texts' columns are counted from the start of the
+/// line.  If some of the line is hidden past the right edge of the view, the last column shows a
+/// [`CLIPPED_MARKER`] instead, and so does the first column if some of the line is hidden past
+/// the left edge.  Each marker belongs to the text that it hides the rest of, so clicking on it
+/// selects that text's node.  Wide characters are never split, so a wide character that would be
+/// cut in half by an edge is left out entirely.
+fn clip_line<Ref: Reference>(line: &[ScreenText<Ref>], view: &TreeView) -> Vec<ScreenText<Ref>> {
+    let width = view.width();
+    let (first, last) = match (line.first(), line.last()) {
+        (Some(first), Some(last)) if width > 0 => (first, last),
+        _ => return Vec::new(),
+    };
+    let view_end = view.first_col + width;
+    let hidden_right = last.col + display_width(&last.text) > view_end;
+    // There's no room for both markers in a single column, so only the right one is drawn
+    let hidden_left = first.col < view.first_col && !(hidden_right && width == 1);
+    // The columns of the line that its text can be drawn in, between the markers
+    let start = view.first_col + hidden_left as usize;
+    let end = view_end - hidden_right as usize;
+    let marker = |col: usize, text: &ScreenText<Ref>| ScreenText {
+        row: text.row,
+        col: view.left + col - view.first_col,
+        text: CLIPPED_MARKER.to_string(),
+        node: text.node,
+        token_index: text.token_index,
+    };
+
+    let mut clipped = Vec::new();
+    if hidden_left {
+        // The rightmost text that starts past the left edge is the one being cut off
+        let owner = line
+            .iter()
+            .take_while(|t| t.col < start)
+            .last()
+            .unwrap_or(first);
+        clipped.push(marker(view.first_col, owner));
+    }
+    for text in line {
+        let (offset, visible) = slice_columns(
+            &text.text,
+            start.saturating_sub(text.col),
+            end.saturating_sub(text.col),
+        );
+        if !visible.is_empty() {
+            clipped.push(ScreenText {
+                col: view.left + text.col + offset - view.first_col,
+                text: visible.to_string(),
+                ..text.clone()
+            });
+        }
+    }
+    if hidden_right {
+        let owner = line
+            .iter()
+            .find(|t| t.col + display_width(&t.text) > end)
+            .unwrap_or(last);
+        clipped.push(marker(end, owner));
+    }
+    clipped
+}
+
+/// Returns the node whose text is drawn at (`row`, `col`), or [`None`] if no text is drawn
+/// there.  Every piece of text belongs to the innermost node that generated it, so this is the
+/// innermost node at that position.
+fn node_at<Ref: Reference>(texts: &[ScreenText<Ref>], row: usize, col: usize) -> Option<Ref> {
+    texts
+        .iter()
+        .find(|t| t.row == row && t.col <= col && col < t.col + display_width(&t.text))
+        .map(|t| t.node)
+}
+
+/// The number of lines scrolled by each click of the mouse wheel
+const WHEEL_SCROLL_LINES: usize = 3;
+
+/// Returns how many columns the line number gutter takes up when the last line shown on the
+/// screen is given by `last_line` (counting from 1).  This includes the space between the numbers
+/// and the text.
+fn gutter_width(last_line: usize) -> usize {
+    last_line.max(1).to_string().len() + 1
+}
+
+/// Returns the [`Attr`] with which a message of a given [`LogLevel`] is shown in the bottom bar.
+/// Only warnings and errors are coloured, so that they stand out from normal feedback.
+fn message_attr(level: &LogLevel) -> Attr {
+    if *level >= LogLevel::Warning {
+        Attr::default().fg(level.to_color())
+    } else {
+        Attr::default()
+    }
+}
+
+/// The smallest terminal (in columns and rows) that the editor will draw its normal display in
+const MIN_TERM_SIZE: (usize, usize) = (12, 2);
+
+/// Where each part of the main display is drawn
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Layout {
+    /// The number of rows at the top of the screen that show the tree
+    tree_height: usize,
+    /// The number of columns that the tree (and the line numbers next to it) can use
+    tree_width: usize,
+    /// The column where the log messages start
+    log_col: usize,
+    /// The row of the status bar and command box
+    bar_row: usize,
+    /// The maximum number of columns that the status bar can use
+    bar_width: usize,
+    /// The column where the command box starts
+    command_col: usize,
+}
+
+/// Work out where the parts of the main display go on a `width` by `height` terminal, when the
+/// command box contains `command_len` [`char`]s.  Returns [`None`] if the terminal is smaller than
+/// [`MIN_TERM_SIZE`].
+fn layout(width: usize, height: usize, command_len: usize) -> Option<Layout> {
+    if width < MIN_TERM_SIZE.0 || height < MIN_TERM_SIZE.1 {
+        return None;
+    }
+    // The command box ends 5 columns before the edge of the screen (or starts at the left edge,
+    // if it's too long for that), and the status bar stops a column before it
+    let command_col = width.saturating_sub(5 + command_len);
+    Some(Layout {
+        tree_height: height - 1,
+        tree_width: width,
+        log_col: width / 2,
+        bar_row: height - 1,
+        bar_width: command_col.saturating_sub(1),
+        command_col,
+    })
+}
+
+/// Join `prefix`, `path` and `suffix` into a status bar that is at most `max_width` [`char`]s
+/// wide.  If it would be too wide, the start of `path` is cut off first (so that its most specific
+/// part stays visible), and then the end of the whole bar.
+fn fit_status_bar(prefix: &str, path: &str, suffix: &str, max_width: usize) -> String {
+    const ELLIPSIS: &str = "...";
+    let len = display_width;
+    if len(prefix) + len(path) + len(suffix) <= max_width {
+        return format!("{}{}{}", prefix, path, suffix);
+    }
+    let path_width = max_width.saturating_sub(len(prefix) + len(suffix) + len(ELLIPSIS));
+    if path_width > 0 {
+        let path_end = truncate_start_to_width(path, path_width);
+        return format!("{}{}{}{}", prefix, ELLIPSIS, path_end, suffix);
+    }
+    truncate_to_width(&format!("{}{}{}", prefix, path, suffix), max_width).to_string()
+}
+
+/// The file that the session script is written to by the `gw` command
+const SCRIPT_PATH: &str = "sapling-session.txt";
+/// The file that the whole editing session is written to by the `gS` command
+const SESSION_PATH: &str = "sapling-session.json";
+
+/// Generate the text of the tree rooted at `node_map`'s root, as it should be written to a file
+fn text_to_save<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    format_style: &Node::FormatStyle,
+    indent_style: &IndentStyle,
+) -> String {
+    let mut text = String::new();
+    Node::write_text_indented(
+        node_map.root(),
+        node_map,
+        &mut text,
+        format_style,
+        indent_style,
+    );
+    text
+}
+
+/// Returns the order that the children of `tree`'s selected node are in once sorted, as the
+/// indices of the children in their new order (for [`EditableTree::reorder_children`]).  The
+/// entries of a record are sorted by key, and if `by_text` is `true` then any other node's
+/// children are sorted by their text in `format_style`.  The sort is stable, so children that
+/// compare equal keep their order.  Returns [`None`] if the children shouldn't be sorted.
+pub(super) fn sorted_order<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>>(
+    tree: &E,
+    format_style: &Node::FormatStyle,
+    by_text: bool,
+) -> Option<Vec<usize>> {
+    let node = tree.cursor_node();
+    let children = node.children();
+    // XML elements have their attributes as entries, so a record only has its entries sorted if
+    // they are its children
+    let entries = node.record_entries(tree).filter(|entries| {
+        entries.len() == children.len()
+            && children
+                .iter()
+                .all(|&c| tree.get_node(c).is_some_and(|c| c.key_index().is_some()))
+    });
+    let keys: Vec<String> = match entries {
+        Some(entries) => entries.into_iter().map(|(key, _)| key).collect(),
+        None if by_text => children
+            .iter()
+            .map(|&c| Node::to_text(c, tree, format_style))
+            .collect(),
+        None => return None,
+    };
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+    Some(order)
+}
+
+/// Generate the lines of the help screen, which lists every command in `command_table` and every
+/// node [`char`] of the language being edited
+fn help_lines<Ref: Reference, Node: ASTSpec<Ref>>(command_table: &CommandTable) -> Vec<String> {
+    let mut lines = vec!["Commands:".to_string()];
+    for (keys, description) in command_table.help_entries() {
+        lines.push(format!("  {:<16}{}", keys, description));
+    }
+    lines.push(String::new());
+    lines.push("Named commands (after ':', where Tab completes names and paths):".to_string());
+    for command in EX_COMMANDS {
+        let usage = match command.argument {
+            Argument::None => command.name.to_string(),
+            Argument::OptionalPath => format!("{} [<path>]", command.name),
+            Argument::Path => format!("{} <path>", command.name),
+            Argument::Setting => format!("{} <setting>", command.name),
+            Argument::OptionalCount => format!("{} [<count>]", command.name),
+            Argument::Range => format!("{} <min> <max>", command.name),
+        };
+        lines.push(format!("  {:<16}{}", usage, command.description));
+    }
+    lines.push(String::new());
+    lines.push("Node chars (the <c> of 'r', 'i' and 'a'):".to_string());
+    for (c, description) in Node::char_descriptions() {
+        lines.push(format!("  {:<16}{}", c, description));
+    }
+    lines
+}
+
+/// Generate the hint that lists what could be typed next to finish `command`, when `node` is
+/// the selected node (e.g. `t:true f:false` after `r`).  Returns [`None`] if `command` isn't
+/// waiting for anything.
+fn command_hint<Ref: Reference, Node: ASTSpec<Ref>>(
+    command_table: &CommandTable,
+    command: &str,
+    node: &Node,
+) -> Option<String> {
+    if command.is_empty() {
+        return None;
+    }
+    let entries: Vec<String> = match command_table.pending(command)? {
+        Pending::Name(names) => names
+            .iter()
+            .map(|(name, action_name)| format!("{}:{}", name, action_name))
+            .collect(),
+        Pending::CharArg(action_name) => {
+            // Only list the chars that would work on the selected node
+            let is_valid = |c: char| match action_name {
+                "replace" => node.is_replace_char(c),
+                "insert-child" | "append-child" => node.is_insert_char(c),
+                _ => true,
+            };
+            if matches!(action_name, "record-macro" | "play-macro") {
+                return Some("<register>".to_string());
+            }
+            Node::char_descriptions()
+                .into_iter()
+                .filter(|(c, _)| is_valid(*c))
+                .map(|(c, description)| format!("{}:{}", c, description))
+                .collect()
+        }
+    };
+    Some(entries.join(" "))
+}
+
+/// Lines of text that are shown over the whole screen instead of the tree (like the help
+/// screen), a page at a time
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Overlay {
+    /// What the overlay shows, which finishes the footer's "Press any key to close ..."
+    name: &'static str,
+    lines: Vec<String>,
+    /// The index of the page that is being shown
+    page: usize,
+}
+
+impl Overlay {
+    /// Makes an `Overlay` that shows `lines`, starting from the first page
+    fn new(name: &'static str, lines: Vec<String>) -> Self {
+        Overlay {
+            name,
+            lines,
+            page: 0,
+        }
+    }
+}
+
+/// Returns the bound (`min` or `max`) that the number in `node` should be clamped to, or `None`
+/// if it's already in range.  Returns a message for the user if `node` isn't a number.
+pub(super) fn clamp_bound<'a, Ref: Reference, Node: ASTSpec<Ref>>(
+    node: &Node,
+    min: &'a str,
+    max: &'a str,
+) -> std::result::Result<Option<&'a str>, String> {
+    // Strings can look like numbers, but they aren't ones
+    let value = node
+        .literal()
+        .filter(|_| node.syntax_category() == SyntaxCategory::Literal)
+        .and_then(|text| text.parse::<f64>().ok())
+        .ok_or_else(|| format!("'{}' isn't a number", node.summary()))?;
+    // The bounds were checked when the command was parsed
+    let parse = |bound: &str| bound.parse::<f64>().unwrap_or(f64::NAN);
+    Ok(if value < parse(min) {
+        Some(min)
+    } else if value > parse(max) {
+        Some(max)
+    } else {
+        None
+    })
+}
+
+/// How many characters of a register's contents are shown by `:registers`
+const PREVIEW_LEN: usize = 60;
+
+/// Returns `true` if subtrees can be yanked into the register called `c` with `"<c>y`
+pub(super) fn is_named_register(c: char) -> bool {
+    c.is_ascii_lowercase()
+}
+
+/// Cut `preview` down to [`PREVIEW_LEN`] characters, ending it with `…` if anything was cut
+fn truncate_preview(preview: String) -> String {
+    if preview.chars().count() <= PREVIEW_LEN {
+        return preview;
+    }
+    let mut truncated: String = preview.chars().take(PREVIEW_LEN - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Returns the number of pages needed to show `num_lines` lines of an [`Overlay`], `page_height`
+/// at a time
+fn overlay_page_count(num_lines: usize, page_height: usize) -> usize {
+    let page_height = page_height.max(1);
+    num_lines.div_ceil(page_height).max(1)
+}
+
+/// Attempt to convert a command as a `&`[`str`] into an [`Action`], using the built-in commands.
+/// See [`CommandTable::parse`] for the details.
+fn parse_command(command: &str) -> Option<Action> {
+    CommandTable::default().parse(command)
+}
+
+/// A function that reads the file at a path into a new tree, returning an error message if the
+/// file can't be read or parsed
+pub type FileLoader<E> = Box<dyn Fn(&Path) -> std::result::Result<E, String>>;
+
+/// A function that parses text from the system clipboard into a subtree that can be pasted,
+/// returning an error message (including where the text is invalid) if it can't be parsed
+pub type ClipboardParser<T> = Box<dyn Fn(&str) -> std::result::Result<Subtree<T>, String>>;
+
+/// A struct to hold the top-level components of the editor.
+pub struct Editor<R: Reference, T: ASTSpec<R>, E: EditableTree<R, T>> {
+    /// The log as a [`Vec`] of logged messages
+    log: Vec<(LogLevel, String)>,
+    /// The documents that are open, which there is always at least one of
+    buffers: Vec<Buffer<R, T, E>>,
+    /// The index in `buffers` of the document being edited
+    active_buffer: usize,
+    /// Reads the file at a path into a new tree, for opening files in new buffers
+    file_loader: Option<FileLoader<E>>,
+    /// The style that the tree is being printed to the screen
+    format_style: T::FormatStyle,
+    /// The `tuikit` terminal that the `Editor` is rendering to
+    term: Arc<dyn Frontend>,
+    /// The target of every record that the editor sends to the [`log`] crate
+    log_target: String,
+    /// The current contents of the command buffer
+    command: String,
+    /// When the last key of `command` was typed, which is used to time out commands that could
+    /// be the start of longer ones
+    command_typed_at: Instant,
+    /// The commands that the user can type
+    command_table: CommandTable,
+    /// The settings that can be changed with `:set`
+    config: Config,
+    /// Custom functions used to display leaf nodes of particular kinds
+    value_formatters: ValueFormatters<T>,
+    /// The text placed between the contents of two nodes when they are merged
+    merge_separator: String,
+    /// The text most recently copied by the user
+    clipboard: String,
+    /// The system clipboard that subtrees are copied to by `"+y`, or [`None`] if there isn't one
+    system_clipboard: Option<Box<dyn Clipboard>>,
+    /// The style that subtrees are written in when they're copied to the system clipboard, or
+    /// [`None`] to use the display style
+    clipboard_format_style: Option<T::FormatStyle>,
+    /// Parses the text on the system clipboard for `"+p`, or [`None`] if pasting text isn't
+    /// supported
+    clipboard_parser: Option<ClipboardParser<T>>,
+    /// The subtrees most recently yanked by the user (which is empty if nothing has been
+    /// yanked).  These are independent of the tree, so that later edits can't change them.
+    register: Vec<Subtree<T>>,
+    /// The subtrees yanked into each named register (`a` to `z`) with `"<c>y`.  Like the yank
+    /// register, these belong to the editor rather than a buffer.
+    named_registers: BTreeMap<char, Vec<Subtree<T>>>,
+    /// Whether every action that would change the tree is rejected
+    read_only: bool,
+    /// The text shown over the whole screen (e.g. the help screen), or [`None`] if the tree is
+    /// being shown
+    overlay: Option<Overlay>,
+    /// The most recent [repeatable](Action::is_repeatable) edit, which is repeated by `.`
+    last_edit: Option<Action>,
+    /// The macros that the user has recorded
+    macros: Macros,
+    /// The kind of text being typed into the prompt and the text typed so far, or [`None`] if
+    /// the prompt isn't open
+    prompt: Option<(PromptKind, String)>,
+    /// The most recently submitted search query, which is used by `m` and `M`
+    last_search: Option<String>,
+    /// Lists the files that paths typed into the prompt are completed to by `Tab`
+    path_source: Box<dyn PathSource>,
+    /// The completions that repeated presses of `Tab` cycle through, or [`None`] if the last key
+    /// wasn't a `Tab` that completed to several candidates
+    completion_cycle: Option<CompletionCycle>,
+    /// Whether keys are being typed as commands or into the selected node's value
+    mode: Mode<T>,
+    /// The message shown in the bottom bar until the next keypress, if there is one
+    message: Option<(LogLevel, String)>,
+    /// The buffer that the text of the tree is written into when it's saved.  This is kept
+    /// between saves, so that frequently (auto-)saving a large tree doesn't reallocate its text
+    /// every time.
+    save_buffer: String,
+    /// The style that the tree is written to stdout in when Sapling is being used as a filter in
+    /// a pipeline, or [`None`] if it's editing a file
+    pipe_format_style: Option<T::FormatStyle>,
+    /// The text most recently written with `w` in a pipeline, which should be printed to stdout
+    /// when Sapling quits
+    piped_text: Option<String>,
+    /// Whether keys are being typed by [`Editor::run_commands`] rather than by the user.  The
+    /// edited tree is handed back to whoever ran the script, so quitting never loses changes.
+    running_script: bool,
+}
+
+impl<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>> Editor<Ref, Node, E> {
+    /// Create a new [`Editor`] with the default options.  The terminal isn't opened until the
+    /// editor is [run](Editor::run).
+    pub fn new(tree: E, format_style: Node::FormatStyle) -> Editor<Ref, Node, E> {
+        // Only a keymap can stop an editor from being built
+        Self::builder(tree)
+            .format_style(format_style)
+            .build()
+            .unwrap()
+    }
+
+    /// Start building an [`Editor`] for `tree`, with more options than [`Editor::new`]
+    pub fn builder(tree: E) -> EditorBuilder<Ref, Node, E> {
+        EditorBuilder::new(tree)
+    }
+
+    /// Create a new [`Editor`], opening the terminal straight away.  Returns an [`EditorError`] if
+    /// the terminal can't be opened.
+    pub fn try_new(
+        tree: E,
+        format_style: Node::FormatStyle,
+    ) -> std::result::Result<Editor<Ref, Node, E>, EditorError> {
+        let term = Term::with_options(TermOptions::default().mouse_enabled(true))
+            .map_err(EditorError::Terminal)?;
+        let mut editor = Self::with_frontend(tree, format_style, Arc::new(term));
+        editor.system_clipboard = SystemClipboard::detect().map(|c| Box::new(c) as Box<_>);
+        Ok(editor)
+    }
+
+    /// Create a new [`Editor`] that draws itself to, and reads its input from, some [`Frontend`]
+    /// instead of the terminal.  This is mostly useful for running the editor in tests, using a
+    /// [`TestFrontend`](frontend::TestFrontend).
+    pub fn with_frontend(
+        tree: E,
+        format_style: Node::FormatStyle,
+        term: Arc<dyn Frontend>,
+    ) -> Editor<Ref, Node, E> {
+        let config = Config::default();
+        let mut folds = FoldState::new();
+        folds.set_child_limit(config.child_limit);
+        Editor {
+            log: Vec::new(),
+            buffers: vec![Buffer::new(tree, folds)],
+            active_buffer: 0,
+            file_loader: None,
+            term,
+            log_target: module_path!().to_string(),
+            format_style,
+            command: String::new(),
+            command_typed_at: Instant::now(),
+            command_table: CommandTable::default(),
+            config,
+            value_formatters: ValueFormatters::new(),
+            merge_separator: " ".to_string(),
+            clipboard: String::new(),
+            system_clipboard: None,
+            clipboard_format_style: None,
+            clipboard_parser: None,
+            register: Vec::new(),
+            named_registers: BTreeMap::new(),
+            read_only: false,
+            overlay: None,
+            last_edit: None,
+            macros: Macros::new(),
+            prompt: None,
+            last_search: None,
+            path_source: Box::new(FileSystem),
+            completion_cycle: None,
+            mode: Mode::Normal,
+            message: None,
+            save_buffer: String::new(),
+            pipe_format_style: None,
+            piped_text: None,
+            running_script: false,
+        }
+    }
+
+    /// Returns the buffer that is being edited
+    fn buffer(&self) -> &Buffer<Ref, Node, E> {
+        &self.buffers[self.active_buffer]
+    }
+
+    /// Returns the buffer that is being edited, mutably
+    fn buffer_mut(&mut self) -> &mut Buffer<Ref, Node, E> {
+        &mut self.buffers[self.active_buffer]
+    }
+
+    /// Set the indentation of the tree, both on the screen and whenever it's written to a file
+    pub fn set_save_indent_style(&mut self, indent_style: IndentStyle) {
+        self.set_config(Config {
+            indent: indent_style,
+            ..self.config.clone()
+        });
+    }
+
+    /// Returns the settings that the editor is using
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Change every setting at once.  Like the changes made by `:set`, these take effect
+    /// straight away: the tree is redisplayed with the new indentation, and every buffer that is
+    /// being auto-saved uses the new interval.
+    pub fn set_config(&mut self, config: Config) {
+        for buffer in &mut self.buffers {
+            buffer.display_cache = None;
+            buffer.folds.set_child_limit(config.child_limit);
+            if let Some(auto_save) = &mut buffer.auto_save {
+                auto_save.set_interval(config.auto_save_interval);
+            }
+        }
+        self.config = config;
+    }
+
+    /// Display every leaf node of the kind `kind_name` (as returned by [`ASTSpec::kind_name`])
+    /// using `formatter` instead of its usual text.  This only changes how the tree is displayed;
+    /// saved text is unaffected.  Returns `true` if this replaced an existing formatter.
+    pub fn register_value_formatter(
+        &mut self,
+        kind_name: &'static str,
+        formatter: impl Fn(&Node) -> String + 'static,
+    ) -> bool {
+        self.buffer_mut().display_cache = None;
+        self.value_formatters.register(kind_name, formatter)
+    }
+
+    /// Set the text that is placed between the contents of two nodes when they are merged.  This
+    /// defaults to a single space.
+    pub fn set_merge_separator(&mut self, separator: String) {
+        self.merge_separator = separator;
+    }
+
+    /// Set whether the display decorations (e.g. the markers of folded nodes) should only use
+    /// ASCII characters, for terminals that can't display Unicode symbols.  This defaults to
+    /// `false`.
+    pub fn set_ascii_only(&mut self, ascii_only: bool) {
+        let markers = if ascii_only {
+            Markers::ASCII
+        } else {
+            Markers::UNICODE
+        };
+        for buffer in &mut self.buffers {
+            buffer.display_cache = None;
+            buffer.folds.set_markers(markers);
+        }
+    }
+
+    /// Set whether each line of the display is prefixed by its line number.  This only changes
+    /// how the tree is displayed; saved text is unaffected.  This defaults to `false`.
+    pub fn set_line_numbers(&mut self, line_numbers: bool) {
+        self.config.line_numbers = line_numbers;
+    }
+
+    /// Set how long to wait for the next key of a command that could be the start of a longer one
+    /// (e.g. `q`, which could become `q!`) before performing it.  Part of a command name that
+    /// isn't a command on its own is discarded after this long.  This defaults to 500ms.
+    pub fn set_key_timeout(&mut self, timeout: Duration) {
+        self.config.key_timeout = timeout;
+    }
+
+    /// Enable auto-saving of the current buffer with the given settings, or disable it if
+    /// `auto_save` is [`None`].  Auto-saving is disabled by default.  Whether an auto-save is due is checked after every
+    /// input, so an interval is only a lower bound on the time between saves.
+    pub fn set_auto_save(&mut self, auto_save: Option<AutoSave>) {
+        self.buffer_mut().auto_save = auto_save;
+    }
+
+    /// Generate the text that should be written when the tree is saved.  This is the same as the
+    /// displayed text, except that the indentation is normalised to `self.config.indent`.
+    pub fn text_to_save(&self) -> String {
+        text_to_save(&self.buffer().tree, &self.format_style, &self.config.indent)
+    }
+
+    /// Returns every entry of a record in the current tree whose key is used by another entry of
+    /// the same record (see [`duplicate_keys::duplicate_keys`]), along with its key, in the order
+    /// they appear in the text.  Only the records that have changed since the last check are
+    /// looked at again.
+    pub fn duplicate_keys(&mut self) -> Vec<(Ref, String)> {
+        let buffer = &mut self.buffers[self.active_buffer];
+        let root = buffer.tree.root();
+        buffer
+            .duplicate_keys
+            .entries(&buffer.tree, root)
+            .into_iter()
+            .map(|(_, entry, key)| (entry, key))
+            .collect()
+    }
+
+    /// Replace the contents of `self.save_buffer` with the [text to save](Editor::text_to_save),
+    /// reusing its allocation
+    fn fill_save_buffer(&mut self) {
+        self.save_buffer.clear();
+        let tree = &self.buffers[self.active_buffer].tree;
+        Node::write_text_indented(
+            tree.root(),
+            tree,
+            &mut self.save_buffer,
+            &self.format_style,
+            &self.config.indent,
+        );
+    }
+
+    /// Set whether the editor is in read-only mode, where the tree can be browsed (moving the
+    /// cursor, searching and folding) but any action that would change it or write it is rejected
+    /// with a message.  This defaults to `false`.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Returns `true` if the editor is in [read-only mode](Editor::set_read_only)
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Set the file that the current buffer is written to by the `w` command.  If this is
+    /// [`None`] (the default), `w` reports an error instead of writing anything.  The file is
+    /// assumed to hold the tree as it is now, so `w` refuses to overwrite it if another program
+    /// changes it after this.
+    pub fn set_file_path(&mut self, path: Option<PathBuf>) {
+        self.buffer_mut().file_path = path;
+        self.record_disk_stamp();
+    }
+
+    /// Record the [stamp](buffer::FileStamp) of the current buffer's file, reporting it if the
+    /// file can't be checked
+    fn record_disk_stamp(&mut self) {
+        if let Err(e) = self.buffer_mut().record_disk_stamp() {
+            let path = self
+                .buffer()
+                .file_path
+                .as_ref()
+                .unwrap()
+                .display()
+                .to_string();
+            self.log(
+                LogLevel::Warning,
+                format!("Couldn't check '{}' on disk: {}", path, e),
+            );
+        }
+    }
+
+    /// Set the function used to read files into trees, so that the user can open other files in
+    /// new buffers.  Until this is set, trying to open a file reports an error.
+    pub fn set_file_loader(&mut self, file_loader: FileLoader<E>) {
+        self.file_loader = Some(file_loader);
+    }
+
+    /// Open the file at `path` in a new buffer (after the current one) and switch to it, or
+    /// switch to the buffer that already has it open.  Returns `false` (and reports why) if the
+    /// file couldn't be loaded.
+    pub fn open_file(&mut self, path: &Path) -> bool {
+        if let Some(index) = self
+            .buffers
+            .iter()
+            .position(|b| b.file_path.as_deref() == Some(path))
+        {
+            self.switch_to_buffer(index);
+            return true;
+        }
+        let loaded = match &self.file_loader {
+            Some(load) => load(path),
+            None => Err("opening files isn't supported".to_string()),
+        };
+        let tree = match loaded {
+            Ok(tree) => tree,
+            Err(e) => {
+                self.log(
+                    LogLevel::Error,
+                    format!("Couldn't open '{}': {}", path.display(), e),
+                );
+                return false;
+            }
+        };
+        // The new buffer shares the display settings of the others, but none of their folds
+        let mut folds = self.buffer().folds.clone();
+        folds.clear();
+        let mut buffer = Buffer::new(tree, folds);
+        buffer.file_path = Some(path.to_path_buf());
+        self.buffers.insert(self.active_buffer + 1, buffer);
+        self.switch_to_buffer(self.active_buffer + 1);
+        self.record_disk_stamp();
+        true
+    }
+
+    /// Returns the number of buffers that are open, which is always at least one
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Use Sapling as a filter in a pipeline: instead of writing to a file, `w` records the text
+    /// of the tree in `format_style` (see [`Editor::piped_text`]), which the caller should print
+    /// to stdout once the editor has finished.  Passing [`None`] goes back to writing files.
+    pub fn set_pipe_output(&mut self, format_style: Option<Node::FormatStyle>) {
+        self.pipe_format_style = format_style;
+    }
+
+    /// Returns the text most recently written with `w` while [piping](Editor::set_pipe_output),
+    /// or [`None`] if the tree hasn't been written
+    pub fn piped_text(&self) -> Option<&str> {
+        self.piped_text.as_deref()
+    }
+
+    /// Returns the file that the tree is written to by the `w` command, if one has been set
+    pub fn file_path(&self) -> Option<&Path> {
+        self.buffer().file_path.as_deref()
+    }
+
+    /// Change the settings to those in the [`Config`] file at `path`.  Settings that the file
+    /// doesn't mention are reset to their defaults.  If the file can't be read or parsed, a
+    /// warning is logged and the current settings are kept.  Returns `true` if the config was
+    /// loaded.
+    pub fn load_config(&mut self, path: &Path) -> bool {
+        match Config::load(path) {
+            Ok(config) => {
+                self.set_config(config);
+                self.log(
+                    LogLevel::Info,
+                    format!("Loaded config '{}'", path.display()),
+                );
+                true
+            }
+            Err(e) => {
+                self.log(
+                    LogLevel::Warning,
+                    format!(
+                        "Couldn't load config '{}' (using the current settings): {}",
+                        path.display(),
+                        e
+                    ),
+                );
+                false
+            }
+        }
+    }
+
+    /// Rebind commands using the [`KeyMap`] file at `path`.  If the file can't be read or parsed,
+    /// or its bindings conflict, a warning is logged and the current bindings are kept.  Returns
+    /// `true` if the keymap was loaded.
+    pub fn load_keymap(&mut self, path: &Path) -> bool {
+        let result = KeyMap::load(path).and_then(|keymap| self.command_table.apply_keymap(&keymap));
+        match result {
+            Ok(()) => {
+                self.log(
+                    LogLevel::Info,
+                    format!("Loaded keymap '{}'", path.display()),
+                );
+                true
+            }
+            Err(e) => {
+                self.log(
+                    LogLevel::Warning,
+                    format!(
+                        "Couldn't load keymap '{}' (using default keys): {}",
+                        path.display(),
+                        e
+                    ),
+                );
+                false
+            }
+        }
+    }
+
+    /// Discard the document in the current buffer and replace it with `tree`.  Since the old
+    /// [`EditableTree`] is dropped, this also resets the cursor (to the root of `tree`) and the
+    /// undo history.  All folds, pins and marks are also removed, as is the recorded session
+    /// script.
+    pub fn load_tree(&mut self, tree: E) {
+        self.buffer_mut().tree = tree;
+        self.buffer_mut().folds.clear();
+        self.buffer_mut().duplicate_keys.clear();
+        self.buffer_mut().marks.clear();
+        self.buffer_mut().script.clear();
+        self.command.clear();
+        self.mode = Mode::Normal;
+        self.buffer_mut().node_count = None;
+        self.buffer_mut().display_cache = None;
+        self.buffer_mut().mark_saved();
+        self.log(LogLevel::Info, "Loaded new tree".to_string());
+    }
+
+    /// Returns `true` if the tree has been edited since it was last [saved](Editor::mark_saved)
+    /// or loaded.  Undoing or redoing back to the saved version counts as having no changes.
+    pub fn is_dirty(&self) -> bool {
+        self.buffer().dirty
+    }
+
+    /// Record that the tree has been saved, so that the user can quit without being warned about
+    /// unsaved changes.  Auto-saves don't do this, since they may be written to a different file
+    /// from the one being edited.
+    pub fn mark_saved(&mut self) {
+        self.buffer_mut().mark_saved();
+    }
+
+    /// Record that the tree has unsaved changes, e.g. because it was restored from a [`Session`]
+    /// that had them
+    pub fn mark_unsaved(&mut self) {
+        self.buffer_mut().dirty = true;
+        self.buffer_mut().saved_version = None;
+    }
+
+    /// Returns the text most recently copied by the user
+    pub fn clipboard(&self) -> &str {
+        &self.clipboard
+    }
+
+    /// Set the system clipboard used by `"+y` and `"+p`.  [`Editor::try_new`] uses the
+    /// [`SystemClipboard`] if one is available, and other editors start without one.
+    pub fn set_system_clipboard(&mut self, clipboard: Option<Box<dyn Clipboard>>) {
+        self.system_clipboard = clipboard;
+    }
+
+    /// Set what lists the files that paths in the prompt are completed to when `Tab` is
+    /// pressed.  By default, this is the real [`FileSystem`].
+    pub fn set_path_source(&mut self, source: Box<dyn PathSource>) {
+        self.path_source = source;
+    }
+
+    /// Set how subtrees are exchanged with the system clipboard: `format_style` is the style
+    /// they're copied in (e.g. compact JSON, so that they're easy to paste into other programs),
+    /// and `parser` reads pasted text back into a subtree.  Until this is set, subtrees are copied
+    /// in the display style and `"+p` reports an error.
+    pub fn set_clipboard_format(
+        &mut self,
+        format_style: Node::FormatStyle,
+        parser: ClipboardParser<Node>,
+    ) {
+        self.clipboard_format_style = Some(format_style);
+        self.clipboard_parser = Some(parser);
+    }
+
+    /// Returns the [`Script`] of every command that has changed the tree since it was loaded
+    pub fn script(&self) -> &Script {
+        &self.buffer().script
+    }
+
+    /// Write the commands that have changed the tree since it was loaded to the file at `path`,
+    /// in a form that can be replayed with [`Script::replay`]
+    pub fn export_script(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.buffer().script.to_text())
+    }
+
+    /// Returns the [`Session`] that would carry on exactly where this editor is: the text and
+    /// cursor of every version of the tree in the undo history, the file being edited, and
+    /// whether it has unsaved changes.
+    pub fn session(&mut self) -> Session {
+        // The history is read by undoing back to the oldest version and redoing to the newest,
+        // and then returning to the current version
+        let mut num_undos = 0;
+        while self.buffer_mut().tree.undo() {
+            num_undos += 1;
+        }
+        let mut history = vec![self.session_version()];
+        while self.buffer_mut().tree.redo() {
+            history.push(self.session_version());
+        }
+        for _ in num_undos + 1..history.len() {
+            self.buffer_mut().tree.undo();
+        }
+        Session {
+            history,
+            current: num_undos,
+            file_path: self.buffer().file_path.clone(),
+            dirty: self.buffer().dirty,
+        }
+    }
+
+    /// Returns the current tree as a version of a [`Session`]
+    fn session_version(&self) -> session::Version {
+        session::Version {
+            text: self.buffer().tree.to_text(&self.format_style),
+            cursor_path: self.buffer().tree.cursor_path(),
+        }
+    }
+
+    /// Show `message` in the bottom bar until the next keypress.  Messages of level
+    /// [`LogLevel::Warning`] and above are coloured by their level.
+    pub fn set_message(&mut self, message: String, level: LogLevel) {
+        self.message = Some((level, message));
+    }
+
+    /// Returns the message that is shown in the bottom bar, if there is one
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(|(_, m)| m.as_str())
+    }
+
+    /// Log a message to whatever console is appropriate.  Anything more important than debugging
+    /// information is also shown in the bottom bar, so that commands never fail silently.
+    fn log(&mut self, level: LogLevel, message: String) {
+        log::log!(target: &self.log_target, level.to_log_level(), "{}", message);
+        if level >= LogLevel::Info {
+            self.set_message(message.clone(), level.clone());
+        }
+        self.log.push((level, message));
+    }
+
+    /// Log why an edit of the tree failed, as "Cannot <action>: <error>".  Every failed tree edit
+    /// is reported through here, so that the messages read the same way.  Running out of siblings
+    /// (e.g. swapping the last node forwards) is routine rather than a mistake, so it's only
+    /// logged at [`LogLevel::Debug`].
+    fn report_edit_error(&mut self, action: &str, error: EditError) {
+        let level = match error {
+            EditError::NoSibling { .. } => LogLevel::Debug,
+            _ => LogLevel::Warning,
+        };
+        self.log(level, format!("Cannot {}: {}", action, error));
+    }
+
+    /* ===== COMMAND FUNCTIONS ===== */
+
+    /// Replace the node under the cursor with the node represented by a given [`char`], keeping
+    /// the children of the old node if the new node can hold them
+    fn replace_cursor(&mut self, c: char) {
+        match self.buffer_mut().tree.replace_from_char(c) {
+            Ok(()) => {
+                let new_node = format!("{:?}", self.buffer().tree.cursor_node());
+                self.log(
+                    LogLevel::Debug,
+                    format!("Replacing with '{}'/{}", c, new_node),
+                );
+            }
+            Err(EditError::CannotReplace { .. }) => self.log(
+                LogLevel::Warning,
+                format!("'{}' is not a valid node type", c),
+            ),
+            // The parent of the selected node might not be able to contain the new node
+            Err(e) => self.log(
+                LogLevel::Warning,
+                format!("Cannot replace with '{}': {}", c, e),
+            ),
+        }
+    }
+
+    /// Copy the subtree under the cursor, along with up to `count - 1` of its following siblings,
+    /// into the yank register
+    fn yank(&mut self, count: usize) {
+        self.register = self.buffer().tree.copy_siblings(count);
+        let num_nodes: usize = self.register.iter().map(Subtree::len).sum();
+        self.log(LogLevel::Debug, format!("Yanked {} nodes", num_nodes));
+    }
+
+    /// Copy the subtree under the cursor, along with up to `count - 1` of its following siblings,
+    /// into the named `register` as well as the yank register
+    fn yank_into(&mut self, register: char, count: usize) {
+        if !is_named_register(register) {
+            self.log(
+                LogLevel::Warning,
+                format!("'{}' is not a register (use a to z)", register),
+            );
+            return;
+        }
+        self.yank(count);
+        self.named_registers.insert(register, self.register.clone());
+        self.log(
+            LogLevel::Debug,
+            format!("Yanked into register {}", register),
+        );
+    }
+
+    /// Paste the subtrees in the named `register` after the cursor
+    fn paste_from(&mut self, register: char) {
+        let subtrees = match self.named_registers.get(&register) {
+            Some(subtrees) => subtrees,
+            None if is_named_register(register) => {
+                self.log(LogLevel::Warning, format!("register {} is empty", register));
+                return;
+            }
+            None => {
+                self.log(
+                    LogLevel::Warning,
+                    format!("'{}' is not a register (use a to z)", register),
+                );
+                return;
+            }
+        };
+        let result = self.buffers[self.active_buffer]
+            .tree
+            .paste_siblings(subtrees, PastePosition::After);
+        if let Err(e) = result {
+            self.report_edit_error(&format!("paste register {} after this node", register), e);
+        }
+    }
+
+    /// Show the contents of the yank register and every named register that isn't empty, with a
+    /// one-line preview of each
+    fn list_registers(&mut self) {
+        let format_style = self
+            .clipboard_format_style
+            .as_ref()
+            .unwrap_or(&self.format_style);
+        let unnamed = Some(('"', &self.register)).filter(|(_, r)| !r.is_empty());
+        let lines: Vec<String> = unnamed
+            .into_iter()
+            .chain(self.named_registers.iter().map(|(c, r)| (*c, r)))
+            .map(|(c, subtrees)| {
+                let mut preview = String::new();
+                for (i, subtree) in subtrees.iter().enumerate() {
+                    if i > 0 {
+                        preview.push_str(", ");
+                    }
+                    Self::write_preview(subtree, format_style, &mut preview);
+                }
+                format!("\"{}  {}", c, truncate_preview(preview))
+            })
+            .collect();
+        if lines.is_empty() {
+            self.log(LogLevel::Info, "Every register is empty".to_string());
+            return;
+        }
+        let mut overlay_lines = vec!["Registers:".to_string()];
+        overlay_lines.extend(lines.into_iter().map(|line| format!("  {}", line)));
+        self.overlay = Some(Overlay::new("list of registers", overlay_lines));
+    }
+
+    /// Write the text of `subtree` in `format_style` onto the end of `text`, all on one line.
+    /// This stops once `text` is longer than [`PREVIEW_LEN`], so that huge subtrees are cheap
+    /// to preview.
+    fn write_preview(subtree: &Subtree<Node>, format_style: &Node::FormatStyle, text: &mut String) {
+        for token in subtree.node().display_tokens(format_style) {
+            if text.len() > PREVIEW_LEN {
+                return;
+            }
+            match token {
+                DisplayToken::Text(s) => text.push_str(&s),
+                DisplayToken::Whitespace(n) => text.push_str(&" ".repeat(n)),
+                DisplayToken::Newline if !text.ends_with(' ') => text.push(' '),
+                DisplayToken::Child(c) => {
+                    let index = subtree.node().children().iter().position(|r| *r == c);
+                    if let Some(child) = index.and_then(|i| subtree.children().get(i)) {
+                        Self::write_preview(child, format_style, text);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Paste the yanked subtrees at `position` relative to the cursor
+    fn paste(&mut self, position: PastePosition) {
+        if self.register.is_empty() {
+            self.log(LogLevel::Warning, "Nothing has been yanked".to_string());
+            return;
+        }
+        let result = self.buffers[self.active_buffer]
+            .tree
+            .paste_siblings(&self.register, position);
+        if let Err(e) = result {
+            self.report_edit_error(&format!("paste {:?} this node", position), e);
+        }
+    }
+
+    /// Insert a copy of the subtree under the cursor after it, and select the copy
+    fn duplicate(&mut self) {
+        match self.buffer_mut().tree.duplicate_cursor() {
+            Ok(()) => self.log(LogLevel::Debug, "Duplicated the selected node".to_string()),
+            Err(e) => self.report_edit_error("duplicate this node", e),
+        }
+    }
+
+    /// Copy the text of the subtree under the cursor to the system clipboard
+    fn copy_to_clipboard(&mut self) {
+        let tree = &self.buffers[self.active_buffer].tree;
+        let format_style = self
+            .clipboard_format_style
+            .as_ref()
+            .unwrap_or(&self.format_style);
+        let mut text = String::new();
+        Node::write_text_indented(
+            tree.cursor(),
+            tree,
+            &mut text,
+            format_style,
+            &self.config.indent,
+        );
+        let result = match &mut self.system_clipboard {
+            Some(clipboard) => clipboard.set_text(&text),
+            None => {
+                self.log(
+                    LogLevel::Warning,
+                    "No system clipboard is available".to_string(),
+                );
+                return;
+            }
+        };
+        match result {
+            Ok(()) => self.log(
+                LogLevel::Info,
+                format!(
+                    "Copied {} characters to the clipboard",
+                    text.chars().count()
+                ),
+            ),
+            Err(e) => self.log(
+                LogLevel::Error,
+                format!("Couldn't copy to the clipboard: {}", e),
+            ),
+        }
+    }
+
+    /// Parse the text on the system clipboard and paste it after the cursor.  The tree is left
+    /// unchanged if the text can't be parsed.
+    fn paste_from_clipboard(&mut self) {
+        let text = match &mut self.system_clipboard {
+            Some(clipboard) => clipboard.get_text(),
+            None => {
+                self.log(
+                    LogLevel::Warning,
+                    "No system clipboard is available".to_string(),
+                );
+                return;
+            }
+        };
+        let parsed = match (text, &self.clipboard_parser) {
+            (Ok(text), Some(parse)) => parse(&text),
+            (Ok(_), None) => Err("pasting text isn't supported for this language".to_string()),
+            (Err(e), _) => Err(e),
+        };
+        let subtree = match parsed {
+            Ok(subtree) => subtree,
+            Err(e) => {
+                self.log(
+                    LogLevel::Error,
+                    format!("Couldn't paste the clipboard: {}", e),
+                );
+                return;
+            }
+        };
+        if let Err(e) = self.buffer_mut().tree.paste(&subtree, PastePosition::After) {
+            self.report_edit_error("paste After this node", e);
+        }
+    }
+
+    /// Delete the node under the cursor, along with up to `count - 1` of its following siblings
+    fn delete_siblings(&mut self, count: usize) {
+        match self.buffer_mut().tree.delete_siblings(count) {
+            Ok(num_deleted) => self.log(LogLevel::Debug, format!("Deleted {} nodes", num_deleted)),
+            Err(e) => self.report_edit_error("delete this node", e),
+        }
+    }
+
+    /// Insert a new node as the next sibling of the selected node (or the previous sibling if
+    /// `after` is `false`)
+    fn insert_sibling(&mut self, c: char, after: bool) {
+        match self.buffer_mut().tree.insert_sibling_from_char(c, after) {
+            Ok(()) => self.log(LogLevel::Debug, format!("Inserted sibling '{}'", c)),
+            Err(e) => self.report_edit_error(&format!("insert '{}' next to this node", c), e),
+        }
+    }
+
+    /// Insert new child as the `index`th child of the selected node
+    fn insert_child(&mut self, c: char, index: usize) {
+        match self.buffer_mut().tree.insert_child_from_char(c, index) {
+            Ok(()) => self.log(
+                LogLevel::Debug,
+                format!("Inserted '{}' at index {}", c, index),
+            ),
+            Err(e) => self.report_edit_error(&format!("insert '{}' into this node", c), e),
+        }
+    }
+
+    /// Undo the latest change
+    fn undo(&mut self) {
+        if self.buffer_mut().tree.undo() {
+            self.log(LogLevel::Debug, "Undo successful".to_string());
+        } else {
+            self.log(LogLevel::Info, "No changes to undo".to_string());
+        }
+    }
+
+    /// Move one change forward in the history
+    fn redo(&mut self) {
+        if self.buffer_mut().tree.redo() {
+            self.log(LogLevel::Debug, "Redo successful".to_string());
+        } else {
+            self.log(LogLevel::Info, "No changes to redo".to_string());
+        }
+    }
+
+    /// Fold every node in the tree, except the pinned nodes
+    fn fold_all(&mut self) {
+        let buffer = self.buffer_mut();
+        buffer.folds.fold_all(&buffer.tree, buffer.tree.root());
+        self.log(LogLevel::Debug, "Folded all nodes".to_string());
+    }
+
+    /// Expand every node in the tree
+    fn unfold_all(&mut self) {
+        self.buffer_mut().folds.unfold_all();
+        self.log(LogLevel::Debug, "Expanded all nodes".to_string());
+    }
+
+    /// Display the subtree under the cursor down to `depth` levels, and fold everything below that
+    fn fold_below_depth(&mut self, depth: usize) {
+        let buffer = self.buffer_mut();
+        buffer
+            .folds
+            .fold_below_depth(&buffer.tree, buffer.tree.cursor(), depth);
+        self.log(
+            LogLevel::Debug,
+            format!("Folded nodes more than {} levels below the cursor", depth),
+        );
+    }
+
+    /// Fold or unfold the node under the cursor.  Truncated nodes (i.e. those with more children
+    /// than the child limit) are expanded to show all their children instead of being folded.
+    fn toggle_fold(&mut self) {
+        let cursor = self.buffer().tree.cursor();
+        let is_truncated = {
+            let buffer = self.buffer();
+            buffer
+                .folds
+                .shown_children(cursor, buffer.tree.cursor_node())
+                .is_some()
+        };
+        if self.buffer().folds.is_folded(cursor) {
+            self.buffer_mut().folds.unfold(cursor);
+            self.log(LogLevel::Debug, format!("Unfolded {:?}", cursor));
+        } else if is_truncated {
+            self.buffer_mut().folds.show_all_children(cursor);
+            let num_children = self.buffer().tree.cursor_node().children().len();
+            self.log(
+                LogLevel::Info,
+                format!("Showing all {} children", num_children),
+            );
+        } else if self.buffer().tree.cursor_node().children().is_empty() {
+            self.log(
+                LogLevel::Warning,
+                "Cannot fold a node with no children".to_string(),
+            );
+        } else if self.buffer_mut().folds.toggle_fold(cursor) {
+            self.log(LogLevel::Debug, format!("Folded {:?}", cursor));
+        } else {
+            self.log(
+                LogLevel::Warning,
+                "Cannot fold a pinned node (press zp to unpin it)".to_string(),
+            );
+        }
+    }
+
+    /// Expand any folded or collapsed nodes that hide the selected node, so that moving into a
+    /// folded node (or searching for something inside one) opens it up
+    fn reveal_cursor(&mut self) {
+        let buffer = &mut self.buffers[self.active_buffer];
+        let path = buffer.tree.cursor_path();
+        if buffer.folds.reveal(&buffer.tree, buffer.tree.root(), &path) {
+            buffer.display_cache = None;
+        }
+    }
+
+    /// Pin or unpin the node under the cursor
+    fn toggle_pin(&mut self) {
+        let cursor = self.buffer().tree.cursor();
+        if self.buffer_mut().folds.toggle_pin(cursor) {
+            self.log(LogLevel::Debug, format!("Pinned {:?}", cursor));
+        } else {
+            self.log(LogLevel::Debug, format!("Unpinned {:?}", cursor));
+        }
+    }
+
+    /// Report the size of the text of the node under the cursor
+    fn report_size(&mut self) {
+        let (bytes, lines) = self
+            .buffer()
+            .tree
+            .text_size(self.buffer().tree.cursor(), &self.format_style);
+        self.log(
+            LogLevel::Info,
+            format!(
+                "Selected node is {} byte{} and {} line{}",
+                bytes,
+                if bytes == 1 { "" } else { "s" },
+                lines,
+                if lines == 1 { "" } else { "s" }
+            ),
+        );
+    }
+
+    /// Move the cursor using `movement`, which should return `false` if the cursor couldn't move
+    /// (e.g. there is no `description` to move to)
+    fn move_cursor(&mut self, movement: impl FnOnce(&mut E) -> bool, description: &str) {
+        if movement(&mut self.buffer_mut().tree) {
+            self.log(LogLevel::Debug, format!("Moved to the {}", description));
+        } else {
+            self.log(LogLevel::Info, format!("There is no {}", description));
+        }
+    }
+
+    /// Move the cursor to the nearest ancestor that has the same kind as the node represented by
+    /// `c`
+    fn move_to_ancestor(&mut self, c: char) {
+        let kind_name = match self.buffer().tree.cursor_node().from_char(c) {
+            Some(node) => node.kind_name(),
+            None => {
+                self.log(LogLevel::Warning, format!("'{}' is not a node kind", c));
+                return;
+            }
+        };
+        if self
+            .buffer_mut()
+            .tree
+            .move_to_ancestor(|node| node.kind_name() == kind_name)
+        {
+            self.log(
+                LogLevel::Debug,
+                format!("Moved to the enclosing {}", kind_name),
+            );
+        } else {
+            self.log(LogLevel::Info, format!("No enclosing {} found", kind_name));
+        }
+    }
+
+    /// Write the tree to the auto-save file, if an auto-save is due
+    fn auto_save_if_due(&mut self) {
+        let now = Instant::now();
+        if !self
+            .buffer()
+            .auto_save
+            .as_ref()
+            .is_some_and(|a| a.is_due(now))
+        {
+            return;
+        }
+        self.fill_save_buffer();
+        let auto_save = self.buffers[self.active_buffer].auto_save.as_mut().unwrap();
+        let path = auto_save.path().display().to_string();
+        match auto_save.save(&self.save_buffer, now) {
+            Ok(()) => self.log(LogLevel::Debug, format!("Auto-saved to '{}'", path)),
+            Err(e) => self.log(
+                LogLevel::Error,
+                format!("Couldn't auto-save to '{}': {}", path, e),
+            ),
+        }
+    }
+
+    /// [Atomically write](write_atomically) the tree to the buffer's file (or record it for
+    /// stdout, when [piping](Editor::set_pipe_output)), returning `true` if the tree was written.
+    /// Unless `force` is `true`, the file isn't overwritten if another program has changed it
+    /// since it was read or written.
+    fn write_to_file(&mut self, force: bool) -> bool {
+        // In a pipeline, stdout is only written when Sapling quits, so that the text doesn't get
+        // mixed up with the UI and the next program only sees the final version.  Other files
+        // opened while piping are written as usual.
+        if let (Some(format_style), None) = (&self.pipe_format_style, &self.buffer().file_path) {
+            let text = text_to_save(&self.buffer().tree, format_style, &self.config.indent);
+            self.buffer_mut().mark_saved();
+            self.log(
+                LogLevel::Info,
+                format!("Will write {} bytes to stdout on exit", text.len()),
+            );
+            self.piped_text = Some(text);
+            return true;
+        }
+        let path = match &self.buffer().file_path {
+            Some(p) => p.clone(),
+            None => {
+                self.log(LogLevel::Error, "No file name to write to".to_string());
+                return false;
+            }
+        };
+        if !force {
+            match self.buffer().changed_on_disk() {
+                Ok(false) => {}
+                Ok(true) => {
+                    self.log(
+                        LogLevel::Warning,
+                        format!(
+                            "'{}' changed on disk since it was read (use :w! to overwrite it)",
+                            path.display()
+                        ),
+                    );
+                    return false;
+                }
+                Err(e) => {
+                    self.log(
+                        LogLevel::Error,
+                        format!(
+                            "Couldn't check '{}' on disk (use :w! to write anyway): {}",
+                            path.display(),
+                            e
+                        ),
+                    );
+                    return false;
+                }
+            }
+        }
+        if !self.write_copy(&path) {
+            return false;
+        }
+        self.buffer_mut().mark_saved();
+        self.record_disk_stamp();
+        true
+    }
+
+    /// Read the current buffer's file into a new tree, replacing the tree being edited (see
+    /// [`Editor::load_tree`]).  The undo history starts again from the reloaded tree rather than
+    /// keeping it as a new version, since the old versions were never in the file as it is now.
+    /// Returns `false` (and reports why) if the file couldn't be reloaded.
+    fn reload_file(&mut self) -> bool {
+        let path = match &self.buffer().file_path {
+            Some(path) => path.clone(),
+            None => {
+                self.log(LogLevel::Error, "No file to reload".to_string());
+                return false;
+            }
+        };
+        let loaded = match &self.file_loader {
+            Some(load) => load(&path),
+            None => Err("reading files isn't supported".to_string()),
+        };
+        match loaded {
+            Ok(tree) => {
+                self.load_tree(tree);
+                self.record_disk_stamp();
+                self.log(LogLevel::Info, format!("Reloaded '{}'", path.display()));
+                true
+            }
+            Err(e) => {
+                self.log(
+                    LogLevel::Error,
+                    format!("Couldn't reload '{}': {}", path.display(), e),
+                );
+                false
+            }
+        }
+    }
+
+    /// Write the tree to the file at `path`, returning `true` if it was written.  If the buffer
+    /// doesn't have a file yet then `path` becomes its file, but otherwise this only writes a
+    /// copy, so the buffer's file and whether it has unsaved changes stay the same.
+    fn write_to_path(&mut self, path: PathBuf) -> bool {
+        match &self.buffer().file_path {
+            Some(file_path) if *file_path != path => self.write_copy(&path),
+            _ => {
+                self.buffer_mut().file_path = Some(path);
+                self.write_to_file(false)
+            }
+        }
+    }
+
+    /// [Atomically write](write_atomically) the tree to `path`, returning `true` if that
+    /// succeeded
+    fn write_copy(&mut self, path: &Path) -> bool {
+        self.fill_save_buffer();
+        match write_atomically(path, &self.save_buffer) {
+            Ok(()) => {
+                self.log(
+                    LogLevel::Info,
+                    format!(
+                        "Wrote {} bytes to '{}'",
+                        self.save_buffer.len(),
+                        path.display()
+                    ),
+                );
+                true
+            }
+            Err(e) => {
+                self.log(
+                    LogLevel::Error,
+                    format!("Couldn't write '{}': {}", path.display(), e),
+                );
+                false
+            }
+        }
+    }
+
+    /// Change one of the editor's settings, and report its new value
+    fn change_setting(&mut self, setting: Setting) {
+        let name = setting.name();
+        self.set_config(self.config.clone().apply(setting));
+        self.show_setting(name);
+    }
+
+    /// Report the value of the setting called `name`
+    fn show_setting(&mut self, name: &str) {
+        match self.config.show(name) {
+            Ok(value) => self.log(LogLevel::Info, value),
+            Err(e) => self.log(LogLevel::Warning, e.to_string()),
+        }
+    }
+
+    /// Switch to the buffer at `index` in the list of buffers
+    fn switch_to_buffer(&mut self, index: usize) {
+        self.active_buffer = index;
+        self.log(
+            LogLevel::Info,
+            format!(
+                "Switched to '{}' ({}/{})",
+                self.buffer().name(),
+                self.active_buffer + 1,
+                self.buffers.len()
+            ),
+        );
+    }
+
+    /// Close the current buffer and switch to the one after it, unless it has unsaved changes
+    /// and `force` is `false`.  The last buffer can't be closed, since there'd be nothing to show.
+    fn close_buffer(&mut self, force: bool) {
+        if self.buffers.len() == 1 {
+            self.log(
+                LogLevel::Warning,
+                "Can't close the only buffer (press q to quit)".to_string(),
+            );
+            return;
+        }
+        if self.buffer().dirty && !force {
+            self.log(
+                LogLevel::Warning,
+                "Unsaved changes (press bD to close anyway)".to_string(),
+            );
+            return;
+        }
+        let buffer = self.buffers.remove(self.active_buffer);
+        self.active_buffer %= self.buffers.len();
+        self.log(LogLevel::Info, format!("Closed '{}'", buffer.name()));
+    }
+
+    /// Returns `true` if no buffer has unsaved changes, so that Sapling can quit without losing
+    /// any work.  Otherwise, warns the user about the changes.
+    fn can_quit(&mut self) -> bool {
+        if self.running_script {
+            return true;
+        }
+        let dirty_buffer = match self.buffers.iter().find(|b| b.dirty) {
+            Some(buffer) => buffer,
+            None => return true,
+        };
+        let message = if self.buffer().dirty || self.buffers.len() == 1 {
+            "Unsaved changes (press q! to force quit)".to_string()
+        } else {
+            format!(
+                "Unsaved changes in '{}' (press q! to force quit)",
+                dirty_buffer.name()
+            )
+        };
+        self.log(LogLevel::Warning, message);
+        false
+    }
+
+    /// Write the session script to [`SCRIPT_PATH`]
+    fn export_script_to_default_path(&mut self) {
+        match self.export_script(SCRIPT_PATH) {
+            Ok(()) => self.log(
+                LogLevel::Info,
+                format!(
+                    "Wrote {} commands to '{}'",
+                    self.buffer().script.steps().len(),
+                    SCRIPT_PATH
+                ),
+            ),
+            Err(e) => self.log(
+                LogLevel::Error,
+                format!("Couldn't write '{}': {}", SCRIPT_PATH, e),
+            ),
+        }
+    }
+
+    /// [Atomically write](write_atomically) the [`Session`] to `path`
+    fn write_session(&mut self, path: &Path) {
+        let session = self.session();
+        match write_atomically(path, &session.to_text()) {
+            Ok(()) => self.log(
+                LogLevel::Info,
+                format!(
+                    "Wrote {} versions of the tree to '{}'",
+                    session.history.len(),
+                    path.display()
+                ),
+            ),
+            Err(e) => self.log(
+                LogLevel::Error,
+                format!("Couldn't write '{}': {}", path.display(), e),
+            ),
+        }
+    }
+
+    /// Perform (or, if `dry_run` is `true`, report the effect of) replacing every node like the
+    /// node represented by `from` with the node represented by `to`
+    fn replace_all(&mut self, from: char, to: char, dry_run: bool) {
+        let cursor_node = self.buffer().tree.cursor_node();
+        let (from_node, to_node) = match (cursor_node.from_char(from), cursor_node.from_char(to)) {
+            (Some(f), Some(t)) => (f, t),
+            _ => {
+                self.log(
+                    LogLevel::Warning,
+                    format!("Cannot replace '{}' with '{}'", from, to),
+                );
+                return;
+            }
+        };
+        let is_match = |node: &Node| node == &from_node;
+        if dry_run {
+            let mut paths = self.buffer().tree.paths_to_matching(is_match);
+            paths.retain(|path| {
+                self.buffer()
+                    .tree
+                    .check_replacement_at(path, &to_node)
+                    .is_ok()
+            });
+            self.log(
+                LogLevel::Info,
+                format!("Would replace {} nodes: {:?}", paths.len(), paths),
+            );
+        } else {
+            let count = self.buffer_mut().tree.replace_all(is_match, to_node);
+            self.log(LogLevel::Info, format!("Replaced {} nodes", count));
+        }
+    }
+
+    /// Copy the record under the cursor to the clipboard, in a given [`ExportFormat`]
+    fn copy_record(&mut self, format: ExportFormat) {
+        match self
+            .buffer()
+            .tree
+            .cursor_node()
+            .record_entries(&self.buffer().tree)
+        {
+            Some(entries) => {
+                self.clipboard = export::export_record(&entries, format);
+                self.log(
+                    LogLevel::Info,
+                    format!("Copied {} entries as {:?}", entries.len(), format),
+                );
+            }
+            None => {
+                self.log(
+                    LogLevel::Warning,
+                    "Only records can be copied in this format".to_string(),
+                );
+            }
+        }
+    }
+
+    /// Move the cursor to the node under the same key in the next record
+    fn move_to_same_key_in_next_record(&mut self) {
+        if self.buffer_mut().tree.move_to_same_key_in_next_record() {
+            self.log(
+                LogLevel::Debug,
+                "Moved to the same key in the next record".to_string(),
+            );
+        } else {
+            self.log(
+                LogLevel::Info,
+                "No later record has the same key".to_string(),
+            );
+        }
+    }
+
+    /// Merge the node under the cursor with its next sibling
+    fn merge_with_next_sibling(&mut self) {
+        let separator = self.merge_separator.clone();
+        match self.buffer_mut().tree.merge_with_next_sibling(&separator) {
+            Ok(()) => self.log(LogLevel::Debug, "Merged with next sibling".to_string()),
+            Err(e) => self.report_edit_error("merge with next sibling", e),
+        }
+    }
+
+    /// Switch to the next [format style](ASTSpec::next_format_style).  The display is regenerated
+    /// from the tree on every frame, so nothing else has to be updated.
+    fn cycle_format_style(&mut self) {
+        match Node::next_format_style(&self.format_style) {
+            Some(style) => {
+                self.format_style = style;
+                self.log(LogLevel::Info, "Switched format style".to_string());
+            }
+            None => self.log(
+                LogLevel::Warning,
+                "This language only has one format style".to_string(),
+            ),
+        }
+    }
+
+    /// Wrap the selected node in a new node represented by a given [`char`]
+    fn wrap_cursor(&mut self, c: char) {
+        match self.buffer_mut().tree.wrap_cursor(c) {
+            Ok(()) => self.log(LogLevel::Debug, format!("Wrapped with '{}'", c)),
+            Err(e) => self.report_edit_error(&format!("wrap node with '{}'", c), e),
+        }
+    }
+
+    /// Replace the selected node with the node it wraps, or with all of its children if it wraps
+    /// more than one
+    fn unwrap_cursor(&mut self) {
+        match self.buffer_mut().tree.unwrap_cursor() {
+            Ok(()) => self.log(LogLevel::Debug, "Unwrapped node".to_string()),
+            Err(EditError::CannotUnwrap(_)) => match self.buffer_mut().tree.splice_cursor() {
+                Ok(()) => self.log(LogLevel::Debug, "Spliced children".to_string()),
+                Err(e) => self.report_edit_error("splice children into the parent", e),
+            },
+            Err(e) => self.report_edit_error("unwrap node", e),
+        }
+    }
+
+    /// Swap the selected node with the sibling `offset` places after it.  Nothing happens if
+    /// there is no such sibling.
+    fn swap_with_sibling(&mut self, offset: isize) {
+        match self.buffer_mut().tree.swap_with_sibling(offset) {
+            Ok(()) => self.log(LogLevel::Debug, format!("Swapped with sibling {}", offset)),
+            Err(e) => self.report_edit_error("swap with sibling", e),
+        }
+    }
+
+    /// Rewrite every node in the tree into its canonical form, as one undoable change
+    fn normalize_all(&mut self) {
+        let count = self.buffer_mut().tree.map_nodes(|node| node.normalized());
+        self.log(LogLevel::Info, format!("Normalized {} nodes", count));
+    }
+
+    /// Invert every boolean in the subtree of the selected node, as one undoable change
+    fn invert_booleans(&mut self) {
+        let count = self.buffer_mut().tree.map_subtree(|node| node.inverted());
+        self.log(LogLevel::Info, format!("Inverted {} nodes", count));
+    }
+
+    /// Clamp the number in the selected node into the range from `min` to `max`, reporting
+    /// whether it had to be changed
+    fn clamp_number(&mut self, min: &str, max: &str) {
+        let node = self.buffer().tree.cursor_node();
+        let bound = match clamp_bound(node, min, max) {
+            Ok(Some(bound)) => bound,
+            Ok(None) => {
+                let message = format!(
+                    "{} is already between {} and {}",
+                    node.literal().unwrap_or_default(),
+                    min,
+                    max
+                );
+                self.log(LogLevel::Info, message);
+                return;
+            }
+            Err(message) => {
+                self.log(LogLevel::Warning, message);
+                return;
+            }
+        };
+        let new_node = match node.with_literal(bound) {
+            Some(new_node) => new_node,
+            None => {
+                let message = format!("'{}' can't be written as a {}", bound, node.kind_name());
+                self.log(LogLevel::Warning, message);
+                return;
+            }
+        };
+        let old = node.literal().unwrap_or_default().to_string();
+        self.buffer_mut().tree.replace_cursor(new_node);
+        self.log(LogLevel::Info, format!("Clamped {} to {}", old, bound));
+    }
+
+    /// Sort the children of the selected node as a single change (see [`sorted_order`]), leaving
+    /// the cursor where it is.  Only records are sorted unless `by_text` is `true`, since the order
+    /// of other nodes' children (like the elements of an array) may well matter.
+    fn sort_children(&mut self, by_text: bool) {
+        let order = match sorted_order(&self.buffer().tree, &self.format_style, by_text) {
+            Some(order) => order,
+            None => {
+                self.log(
+                    LogLevel::Warning,
+                    "The order of these children may matter (press =S to sort them by their text)"
+                        .to_string(),
+                );
+                return;
+            }
+        };
+        if order.iter().enumerate().all(|(i, &j)| i == j) {
+            self.log(LogLevel::Info, "Already sorted".to_string());
+            return;
+        }
+        let path = self.buffer().tree.cursor_path();
+        match self.buffer_mut().tree.reorder_children(&path, &order) {
+            Ok(()) => self.log(LogLevel::Info, format!("Sorted {} children", order.len())),
+            Err(e) => self.report_edit_error("sort these children", e),
+        }
+    }
+
+    /// Report how many nodes of each kind are in the tree
+    fn report_kind_histogram(&mut self) {
+        let summary = self
+            .buffer()
+            .tree
+            .kind_histogram()
+            .into_iter()
+            .map(|(kind_name, count)| format!("{}: {}", kind_name, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.log(LogLevel::Info, summary);
+    }
+
+    /// Report the size and shape of the tree, and how much memory it uses, in one line
+    fn report_stats(&mut self) {
+        let stats = self.buffer().tree.stats();
+        let memory = self.buffer().tree.memory_usage();
+        self.log(LogLevel::Info, format!("{}; {}", stats, memory));
+    }
+
+    /// Free the nodes that the tree and its undo history no longer use, keeping the folds and
+    /// marks on the nodes that move.  Marks on nodes that aren't in the tree are forgotten, since
+    /// their nodes may be freed and their references reused.
+    fn collect_garbage(&mut self) {
+        let buffer = &mut self.buffers[self.active_buffer];
+        let tree = &buffer.tree;
+        buffer.marks.retain(|_, &mut r| tree.path_to(r).is_some());
+        let stats = buffer.tree.collect_garbage();
+        buffer.folds.remap(|r| stats.remap(r));
+        // Freed references can be reused, so every subtree has to be checked again
+        buffer.duplicate_keys.clear();
+        for r in buffer.marks.values_mut() {
+            *r = stats.remap(*r);
+        }
+        self.log(
+            LogLevel::Info,
+            format!("Freed {} nodes, kept {}", stats.freed, stats.kept),
+        );
+    }
+
+    /// Move the folds and marks on the nodes that the tree has copied since history position
+    /// `position` (see [`EditableTree::copies_since`]) onto the copies
+    fn follow_copies(&mut self, position: usize) {
+        let buffer = &mut self.buffers[self.active_buffer];
+        let copies = buffer.tree.copies_since(position);
+        if copies.is_empty() {
+            return;
+        }
+        let remap = |r| copies.get(&r).copied().unwrap_or(r);
+        buffer.folds.remap(remap);
+        for r in buffer.marks.values_mut() {
+            *r = remap(*r);
+        }
+    }
+
+    /// Move the cursor one page (i.e. the height of the tree view) forwards or backwards through
+    /// its siblings
+    fn move_by_page(&mut self, forwards: bool) {
+        let page_size = self.tree_view_height() as isize;
+        let offset = if forwards { page_size } else { -page_size };
+        let siblings_moved = self.buffer_mut().tree.move_by_siblings(offset);
+        self.buffer_mut()
+            .script
+            .record_sibling_move(offset.signum() * siblings_moved as isize);
+        self.log(
+            LogLevel::Debug,
+            format!("Moved {} siblings by page", siblings_moved),
+        );
+    }
+
+    /// Returns the size of the terminal as `(width, height)`, or `(0, 0)` if it can't be read
+    fn term_size(&self) -> (usize, usize) {
+        self.term.term_size().unwrap_or((0, 0))
+    }
+
+    /// Returns the number of lines on the screen that are available for rendering the tree
+    fn tree_view_height(&self) -> usize {
+        let (width, height) = self.term_size();
+        layout(width, height, display_width(&self.command)).map_or(1, |l| l.tree_height)
+    }
+
+    /// Returns the number of lines of an [`Overlay`] that fit on each of its pages
+    fn overlay_page_height(&self) -> usize {
+        let (_width, height) = self.term_size();
+        // The bottom line shows which page is open
+        height.saturating_sub(1).max(1)
+    }
+
+    /// Respond to a keypress while an [`Overlay`] is open.  Space shows the next page (if there
+    /// is one), and any other key closes the overlay.
+    fn turn_overlay_page(&mut self, key: Key) {
+        let page_height = self.overlay_page_height();
+        self.overlay = self.overlay.take().and_then(|mut overlay| {
+            let num_pages = overlay_page_count(overlay.lines.len(), page_height);
+            if key == Key::Char(' ') && overlay.page + 1 < num_pages {
+                overlay.page += 1;
+                Some(overlay)
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Render the current page of `overlay` over the whole terminal
+    fn render_overlay(&self, overlay: &Overlay) -> Result<()> {
+        let (width, height) = self.term.term_size()?;
+        let page_height = self.overlay_page_height();
+        let num_pages = overlay_page_count(overlay.lines.len(), page_height);
+        for (row, line) in overlay
+            .lines
+            .iter()
+            .skip(overlay.page * page_height)
+            .take(page_height)
+            .enumerate()
+        {
+            self.term.print(row, 0, truncate_to_width(line, width))?;
+        }
+        let footer = if overlay.page + 1 < num_pages {
+            format!(
+                "Page {}/{}: press space for the next page, or any other key to close",
+                overlay.page + 1,
+                num_pages
+            )
+        } else {
+            format!("Press any key to close the {}", overlay.name)
+        };
+        self.term.print_with_attr(
+            height.saturating_sub(1),
+            0,
+            truncate_to_width(&footer, width),
+            Attr::default().effect(Effect::REVERSE),
+        )?;
+        Ok(())
+    }
+
+    /// Collapse or expand the node under the cursor so that only its first child is displayed
+    fn toggle_collapse(&mut self) {
+        let cursor = self.buffer().tree.cursor();
+        if self.buffer().tree.cursor_node().children().is_empty() {
+            self.log(
+                LogLevel::Warning,
+                "Cannot collapse a node with no children".to_string(),
+            );
+        } else if self.buffer_mut().folds.toggle_collapse(cursor) {
+            self.log(LogLevel::Debug, format!("Collapsed {:?}", cursor));
+        } else {
+            self.log(LogLevel::Debug, format!("Expanded {:?}", cursor));
+        }
+    }
+
+    /// Generate the tokens that are displayed for the node at `id`, taking into account folds,
+    /// value formatters and the value being typed in insert mode
+    fn display_tokens_for(&self, id: Ref, node: &Node) -> Vec<DisplayToken<Ref>> {
+        // The value being typed in insert mode is shown live
+        let node = match &self.mode {
+            Mode::Insert(edited) if id == self.buffer().tree.cursor() => edited,
+            _ => node,
+        };
+        self.buffer()
+            .folds
+            .decorate_tokens(&self.buffer().tree, id, node, |shown| {
+                self.value_formatters
+                    .display_tokens(&self.buffer().tree, shown, &self.format_style)
+            })
+    }
+
+    /// Returns the line of the display on which the selected node starts
+    fn cursor_line(&self) -> usize {
+        let tokens_for = |id, node: &Node| self.display_tokens_for(id, node);
+        let cursor = self.buffer().tree.cursor();
+        self.buffer()
+            .display_cache
+            .as_ref()
+            .and_then(|cache| cache.line_of_node(&self.buffer().tree, cursor, &tokens_for))
+            .or_else(|| {
+                line_of_node(
+                    &self.buffer().tree,
+                    self.buffer().tree.root(),
+                    cursor,
+                    &tokens_for,
+                )
+            })
+            .unwrap_or(0)
+    }
+
+    /// Make sure that [`Self::display_cache`] contains every line that is on the screen
+    fn refresh_display_cache(&mut self) {
+        let num_lines = self.buffer().scroll_offset + self.tree_view_height();
+        if self
+            .buffer()
+            .display_cache
+            .as_ref()
+            .is_some_and(|cache| cache.covers(num_lines))
+        {
+            return;
+        }
+        // Generate more lines than are needed, so that scrolling down doesn't regenerate the
+        // tokens on every line
+        let tokens_for = |id, node: &Node| self.display_tokens_for(id, node);
+        let cache = DisplayCache::new(
+            &self.buffer().tree,
+            self.buffer().tree.root(),
+            &tokens_for,
+            num_lines * 2,
+            self.config.indent.width(),
+        );
+        self.buffer_mut().display_cache = Some(cache);
+    }
+
+    /// Adjust the scroll offsets so that the selected node stays on the screen
+    fn scroll_to_cursor(&mut self) {
+        self.buffer_mut().scroll_offset = clamp_scroll_offset(
+            self.buffer().scroll_offset,
+            self.cursor_line(),
+            self.tree_view_height(),
+        );
+        let (width, height) = self.term_size();
+        let view = match layout(width, height, display_width(&self.command)) {
+            Some(layout) => self.tree_view(&layout),
+            None => return,
+        };
+        if let Some(cols) = self.cursor_columns() {
+            self.buffer_mut().horizontal_scroll =
+                clamp_horizontal_scroll(self.buffer().horizontal_scroll, cols, view.width());
+        }
+    }
+
+    /// Returns the columns (counted from the start of the line, ignoring horizontal scrolling)
+    /// that the first line of the selected node's text covers, or [`None`] if it has no text
+    fn cursor_columns(&self) -> Option<Range<usize>> {
+        let line_view = TreeView {
+            top_row: 0,
+            left: 0,
+            right: usize::MAX,
+            first_line: self.cursor_line(),
+            first_col: 0,
+            max_lines: 1,
+        };
+        let tokens_for = |id, node: &Node| self.display_tokens_for(id, node);
+        let (tokens, start) = self.visible_tokens(&line_view);
+        let range = subtree_token_range(
+            &self.buffer().tree,
+            &tokens,
+            self.buffer().tree.cursor(),
+            &tokens_for,
+        );
+        let (texts, _) = position_tokens(&tokens, &line_view, start, self.config.indent.width());
+        let mut cols = texts
+            .iter()
+            .filter(|t| range.contains(&t.token_index))
+            .map(|t| t.col..t.col + display_width(&t.text));
+        let first = cols.next()?;
+        Some(cols.fold(first, |a, b| a.start.min(b.start)..a.end.max(b.end)))
+    }
+
+    /// Scroll the tree horizontally by `cols` columns, to the right if `right` is `true`.  The
+    /// scrolling is limited by [`Self::scroll_to_cursor`], so the cursor never leaves the screen.
+    fn scroll_horizontally(&mut self, cols: usize, right: bool) {
+        let buffer = self.buffer_mut();
+        buffer.horizontal_scroll = if right {
+            buffer.horizontal_scroll + cols
+        } else {
+            buffer.horizontal_scroll.saturating_sub(cols)
+        };
+    }
+
+    /// Returns the number of columns scrolled by [`Action::ScrollLeft`] and
+    /// [`Action::ScrollRight`], which is half of the width of the tree view
+    fn half_view_width(&self) -> usize {
+        let (width, height) = self.term_size();
+        layout(width, height, display_width(&self.command))
+            .map_or(1, |layout| (self.tree_view(&layout).width() / 2).max(1))
+    }
+
+    /// Returns the part of the screen that the tree is drawn in, given the [`Layout`] of the
+    /// whole screen
+    fn tree_view(&self, layout: &Layout) -> TreeView {
+        let left = if self.config.line_numbers {
+            // The gutter is sized to fit the last line that could be on screen
+            gutter_width(self.buffer().scroll_offset + layout.tree_height)
+        } else {
+            0
+        };
+        TreeView {
+            top_row: 0,
+            left,
+            right: layout.tree_width,
+            first_line: self.buffer().scroll_offset,
+            first_col: self.buffer().horizontal_scroll,
+            max_lines: layout.tree_height,
+        }
+    }
+
+    /// Returns the tokens needed to draw `view`, along with where drawing should start.  The
+    /// cached tokens are used if they reach the bottom of the view, starting from the first line
+    /// on the screen.  Otherwise, only the tokens that will actually fit on the screen are
+    /// generated, so that huge containers don't have every one of their children rendered.
+    fn visible_tokens(&self, view: &TreeView) -> (Cow<'_, FlatTokens<Ref>>, LineStart) {
+        let num_lines = view.first_line + view.max_lines;
+        match &self.buffer().display_cache {
+            Some(cache) if cache.covers(num_lines) => {
+                let tokens = cache.tokens();
+                let (token, indentation) = cache
+                    .line_start(view.first_line)
+                    .unwrap_or((tokens.len(), 0));
+                let start = LineStart {
+                    token,
+                    line: view.first_line,
+                    indentation,
+                };
+                (Cow::Borrowed(tokens), start)
+            }
+            _ => {
+                let tokens_for = |id, node: &Node| self.display_tokens_for(id, node);
+                let tokens = flat_tokens_for_lines(
+                    &self.buffer().tree,
+                    self.buffer().tree.root(),
+                    &tokens_for,
+                    num_lines,
+                );
+                (Cow::Owned(tokens), LineStart::default())
+            }
+        }
+    }
+
+    /// Render the tree into `view`, returning the number of lines that the text reached (which
+    /// is at most `view.first_line + view.max_lines`)
+    fn render_tree(&self, view: &TreeView) -> Result<usize> {
+        let cols = [
+            Color::MAGENTA,
+            Color::RED,
+            Color::YELLOW,
+            Color::GREEN,
+            Color::CYAN,
+            Color::BLUE,
+            Color::WHITE,
+            Color::LIGHT_RED,
+            Color::LIGHT_BLUE,
+            Color::LIGHT_CYAN,
+            Color::LIGHT_GREEN,
+            Color::LIGHT_YELLOW,
+            Color::LIGHT_MAGENTA,
+            Color::LIGHT_WHITE,
+        ];
+
+        let tokens_for = |id, node: &Node| self.display_tokens_for(id, node);
+        let (tokens, start) = self.visible_tokens(view);
+        // Highlight the entire selected subtree, rather than just the selected node's own tokens
+        // (and everything from the first to the last subtree of a visual selection)
+        let selected_range = self
+            .selected_nodes()
+            .into_iter()
+            .map(|id| subtree_token_range(&self.buffer().tree, &tokens, id, &tokens_for))
+            .filter(|range| !range.is_empty())
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+            .unwrap_or(0..0);
+        let (texts, num_lines) = position_tokens(&tokens, view, start, self.config.indent.width());
+        for text in texts {
+            // Colour the text by its syntax category (if the colour scheme uses them), falling
+            // back on hashing the ref to decide on the colour
+            let category = match self.config.color_scheme {
+                ColorScheme::Syntax => self
+                    .buffer()
+                    .tree
+                    .get_node(text.node)
+                    .map(|node| node.syntax_category()),
+                ColorScheme::Rainbow | ColorScheme::Plain => None,
+            };
+            let col = category.and_then(syntax_color).unwrap_or_else(|| {
+                if self.config.color_scheme == ColorScheme::Plain {
+                    return Color::WHITE;
+                }
+                let mut hasher = DefaultHasher::new();
+                text.node.hash(&mut hasher);
+                let hash = hasher.finish();
+                cols[hash as usize % cols.len()]
+            });
+            // Generate the display attributes depending on if the node is selected
+            let attr = if selected_range.contains(&text.token_index) {
+                Attr::default().fg(Color::BLACK).bg(col)
+            } else {
+                Attr::default().fg(col)
+            };
+            self.term
+                .print_with_attr(text.row, text.col, &text.text, attr)?;
+        }
+        Ok(num_lines)
+    }
+
+    /// Draw the line numbers of the lines from `view.first_line` up to (but not including)
+    /// `num_lines` in the gutter to the left of `view`.  The gutter is drawn separately from the
+    /// tree, so the highlight of the selected node can't reach it.
+    fn render_line_numbers(&self, view: &TreeView, num_lines: usize) -> Result<()> {
+        for line in view.first_line..num_lines {
+            let number = format!("{:>width$}", line + 1, width = view.left.saturating_sub(1));
+            self.term.print_with_attr(
+                view.top_row + line - view.first_line,
+                0,
+                &number,
+                Attr::default().effect(Effect::DIM),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Respond to a mouse button being pressed at a given position on the screen.  Clicking on
+    /// the text of a node selects it, and the scroll wheel scrolls the tree.
+    fn handle_mouse(&mut self, button: MouseButton, row: usize, col: usize) {
+        if self.overlay.is_some() || self.prompt.is_some() || self.mode != Mode::Normal {
+            return;
+        }
+        match button {
+            MouseButton::Left => self.click(row, col),
+            // Scrolling is limited by `scroll_to_cursor`, so the cursor never leaves the screen
+            MouseButton::WheelDown => self.buffer_mut().scroll_offset += WHEEL_SCROLL_LINES,
+            MouseButton::WheelUp => {
+                self.buffer_mut().scroll_offset = self
+                    .buffer_mut()
+                    .scroll_offset
+                    .saturating_sub(WHEEL_SCROLL_LINES);
+            }
+            _ => {}
+        }
+    }
+
+    /// Move the cursor to the node whose text is drawn at a given position on the screen.
+    /// Clicks on empty space, or outside the tree view, do nothing.
+    fn click(&mut self, row: usize, col: usize) {
+        let (width, height) = self.term_size();
+        let layout = match layout(width, height, display_width(&self.command)) {
+            Some(l) => l,
+            None => return,
+        };
+        if row >= layout.tree_height {
+            return;
+        }
+        let view = self.tree_view(&layout);
+        let (tokens, start) = self.visible_tokens(&view);
+        let (texts, _) = position_tokens(&tokens, &view, start, self.config.indent.width());
+        let path = node_at(&texts, row, col).and_then(|node| self.buffer().tree.path_to(node));
+        if let Some(path) = path {
+            self.buffer_mut().tree.move_to_path(&path);
+            self.buffer_mut().script.record_path_move(path);
+        }
+    }
+
+    /* ===== MAIN FUNCTIONS ===== */
+
+    /// Update the terminal UI display
+    fn update_display(&self) -> Result<()> {
+        // Put the terminal size into some convenient variables
+        let (width, height) = self.term.term_size()?;
+
+        // Clear the terminal
+        self.term.clear()?;
+
+        // Overlays cover everything else
+        if let Some(overlay) = &self.overlay {
+            self.render_overlay(overlay)?;
+            return self.term.present();
+        }
+
+        let layout = match layout(width, height, display_width(&self.command)) {
+            Some(l) => l,
+            None => {
+                let message = truncate_to_width("Terminal too small", width);
+                self.term.print(0, 0, message)?;
+                return self.term.present();
+            }
+        };
+
+        /* RENDER MAIN TEXT VIEW */
+        let view = self.tree_view(&layout);
+        let num_lines = self.render_tree(&view)?;
+        if self.config.line_numbers {
+            self.render_line_numbers(&view, num_lines)?;
+        }
+
+        /* RENDER LOG SECTION */
+        for (i, (level, message)) in self.log.iter().take(layout.tree_height).enumerate() {
+            self.term.print_with_attr(
+                i,
+                layout.log_col,
+                message,
+                Attr::default().fg(level.to_color()),
+            )?;
+        }
+
+        /* RENDER BOTTOM BAR */
+        let max_width = layout.bar_width;
+        let mut bar_attr = Attr::default();
+        let hint = command_hint(
+            &self.command_table,
+            &self.command,
+            self.buffer().tree.cursor_node(),
+        );
+        let bar = match (&self.prompt, hint, &self.message) {
+            // The text being typed is all that matters while the prompt is open, apart from the
+            // candidates that `Tab` could complete it to
+            (Some((kind, text)), _, message) => {
+                let bar = fit_status_bar(kind.label(), text, "", max_width);
+                match message {
+                    Some((_, message)) => {
+                        let bar = format!("{}  {}", bar, message);
+                        truncate_to_width(&bar, max_width).to_string()
+                    }
+                    None => bar,
+                }
+            }
+            // Half-typed commands show what could come next
+            (None, Some(hint), _) => {
+                bar_attr = Attr::default().effect(Effect::DIM);
+                truncate_to_width(&hint, max_width).to_string()
+            }
+            (None, None, Some((level, message))) => {
+                bar_attr = message_attr(level);
+                truncate_to_width(message, max_width).to_string()
+            }
+            (None, None, None) => {
+                let mode = match self.macros.recording_register() {
+                    _ if matches!(self.mode, Mode::Insert(_)) => "-- INSERT -- ".to_string(),
+                    _ if matches!(self.mode, Mode::Visual(_)) => "-- VISUAL -- ".to_string(),
+                    Some(register) => format!("Recording '@{}' ", register),
+                    None => String::new(),
+                };
+                let prefix = format!("{}{} ", mode, self.buffer().tree.cursor_node().summary());
+                self.status_bar(&prefix, max_width)
+            }
+        };
+        self.term
+            .print_with_attr(layout.bar_row, 0, &bar, bar_attr)?;
+        self.term
+            .print(layout.bar_row, layout.command_col, &self.command)?;
+
+        // Update the terminal screen
+        self.term.present()
+    }
+
+    /// Generate the status bar, which describes the selected node and the state of the tree, with
+    /// `prefix` at the start
+    fn status_bar(&self, prefix: &str, max_width: usize) -> String {
+        let path = self.buffer().tree.cursor_path();
+        let path_text = display_path(&self.buffer().tree, &path).unwrap_or_default();
+        // Find how many siblings the selected node has
+        let num_siblings = match path.split_last() {
+            Some((_, parent_path)) => self
+                .buffer()
+                .tree
+                .node_at_path(parent_path)
+                .map_or(0, |parent| parent.children().len()),
+            None => 1,
+        };
+        let index = path.last().map_or(0, |i| *i);
+        // The buffer is only worth naming if there's more than one
+        let buffer = if self.buffers.len() > 1 {
+            format!(
+                "  {} [{}/{}]",
+                self.buffer().name(),
+                self.active_buffer + 1,
+                self.buffers.len()
+            )
+        } else {
+            String::new()
+        };
+        let duplicates = match self.buffer().duplicate_key_count() {
+            0 => String::new(),
+            1 => "  1 duplicate key".to_string(),
+            n => format!("  {} duplicate keys", n),
+        };
+        let suffix = format!(
+            " ({}/{})  {} nodes{}{}{}{}  ? for help",
+            index + 1,
+            num_siblings,
+            self.buffer().node_count.unwrap_or(0),
+            duplicates,
+            if self.buffer().dirty { " [+]" } else { "" },
+            if self.read_only { " [RO]" } else { "" },
+            buffer
+        );
+        fit_status_bar(prefix, &path_text, &suffix, max_width)
+    }
+
+    /// Respond to a keypress while the prompt is open.  Enter submits the text and ESC closes the
+    /// prompt without doing anything.
+    fn type_prompt_key(&mut self, key: Key) -> bool {
+        if key == Key::Tab {
+            self.complete_prompt();
+            return false;
+        }
+        // Typing anything else means that the candidates no longer match the prompt
+        self.completion_cycle = None;
+        let text = match &mut self.prompt {
+            Some((_, text)) => text,
+            None => return false,
+        };
+        match key {
+            Key::Char(c) => text.push(c),
+            Key::Backspace => {
+                text.pop();
+            }
+            Key::Ctrl('u') => text.clear(),
+            Key::Enter => match self.prompt.take() {
+                Some((PromptKind::Search, query)) => {
+                    self.last_search = Some(query);
+                    self.search(true);
+                }
+                Some((PromptKind::OpenFile, path)) => {
+                    self.open_file(Path::new(&path));
+                }
+                Some((PromptKind::ExCommand, command)) => {
+                    return self.run_ex_command(&command);
+                }
+                Some((PromptKind::ConfirmReload, answer)) => {
+                    if matches!(answer.trim(), "y" | "Y" | "yes") {
+                        self.reload_file();
+                    } else {
+                        self.log(LogLevel::Info, "Kept the unsaved changes".to_string());
+                    }
+                }
+                None => {}
+            },
+            Key::ESC => self.prompt = None,
+            _ => {}
+        }
+        false
+    }
+
+    /// Complete the word at the end of the prompt: the name of a named command, or a file path.
+    /// If there are several candidates, the word is completed as far as they all agree and they
+    /// are listed in the bottom bar, and pressing `Tab` again cycles through them.
+    fn complete_prompt(&mut self) {
+        let (kind, text) = match &self.prompt {
+            Some((kind, text)) => (*kind, text.clone()),
+            None => return,
+        };
+        if let Some(cycle) = &mut self.completion_cycle {
+            let index = cycle.index.map_or(0, |i| (i + 1) % cycle.candidates.len());
+            cycle.index = Some(index);
+            let text = format!("{}{}", cycle.base, cycle.candidates[index]);
+            self.prompt = Some((kind, text));
+            return;
+        }
+        // Where the word being completed starts, and whether it's the name of a named command
+        let (word_start, is_name) = match kind {
+            PromptKind::Search | PromptKind::ConfirmReload => return,
+            PromptKind::OpenFile => (0, false),
+            PromptKind::ExCommand => {
+                let name_start = text.len() - text.trim_start().len();
+                match text[name_start..].find(char::is_whitespace) {
+                    None => (name_start, true),
+                    Some(i) => {
+                        // Only the argument of a command that takes a path can be completed
+                        let name = &text[name_start..name_start + i];
+                        let takes_path = EX_COMMANDS.iter().any(|c| {
+                            c.name == name
+                                && matches!(c.argument, Argument::Path | Argument::OptionalPath)
+                        });
+                        if !takes_path {
+                            return;
+                        }
+                        (
+                            text.len() - text[name_start + i..].trim_start().len(),
+                            false,
+                        )
+                    }
+                }
+            }
+        };
+        let (base, word) = text.split_at(word_start);
+        let completion = if is_name {
+            complete(word, EX_COMMANDS.iter().map(|c| c.name))
+        } else {
+            complete_path(&*self.path_source, word)
+        };
+        let text = format!("{}{}", base, completion.common_prefix);
+        match completion.candidates.len() {
+            0 => self.set_message("No completions".to_string(), LogLevel::Info),
+            1 => {}
+            _ => {
+                // Paths are listed without the directories that they all share
+                let dir_len = word.rfind('/').map_or(0, |i| i + 1);
+                let names: Vec<&str> = completion
+                    .candidates
+                    .iter()
+                    .map(|c| &c[dir_len..])
+                    .collect();
+                self.set_message(names.join("  "), LogLevel::Info);
+                self.completion_cycle = Some(CompletionCycle {
+                    base: base.to_string(),
+                    candidates: completion.candidates,
+                    index: None,
+                });
+            }
+        }
+        self.prompt = Some((kind, text));
+    }
+
+    /// Run a command typed into the `:` prompt, returning `true` if Sapling should quit.
+    /// Commands that do the same as a key command are recorded in the script as that command,
+    /// and `:clamp` (which has no key) is recorded as a step of its own.
+    fn run_ex_command(&mut self, command: &str) -> bool {
+        if command.trim().is_empty() {
+            return false;
+        }
+        let action = match parse_ex_command(command) {
+            Ok(action) => action,
+            Err(e) => {
+                self.log(LogLevel::Warning, e);
+                return false;
+            }
+        };
+        log::debug!("Command ':{}' is {:?}", command, action);
+        if let Some(keys) = self.command_table.canonical_for_action(&action) {
+            let keys = keys.to_string();
+            self.buffer_mut().script.record_command(&keys);
+        } else if let Action::Clamp(min, max) = &action {
+            let (min, max) = (min.clone(), max.clone());
+            self.buffer_mut().script.record_clamp(&min, &max);
+        }
+        let should_quit = self.perform_action(action);
+        self.check_cursor();
+        should_quit
+    }
+
+    /// Returns the nodes that are selected: the siblings in a visual selection (in order), or
+    /// just the node under the cursor
+    fn selected_nodes(&self) -> Vec<Ref> {
+        let tree = &self.buffer().tree;
+        let mut path = tree.cursor_path();
+        match (&self.mode, path.pop()) {
+            (Mode::Visual(anchor), Some(index)) => {
+                let siblings = tree.node_at_path(&path).unwrap().children();
+                siblings[index.min(*anchor)..=index.max(*anchor)].to_vec()
+            }
+            _ => vec![tree.cursor()],
+        }
+    }
+
+    /// Respond to a keypress while a range of siblings is selected, returning `true` if Sapling
+    /// should quit.  Sibling motions extend the selection, `y` (the first key of `yy`) and `x`
+    /// act on the whole selection, and ESC cancels it.
+    fn type_visual_key(&mut self, anchor: usize, key: Key) -> bool {
+        let c = match key {
+            Key::Char(c) => c,
+            Key::ESC => {
+                self.command.clear();
+                self.mode = Mode::Normal;
+                return false;
+            }
+            _ => return false,
+        };
+        self.command.push(c);
+        let yank_key = self
+            .command_table
+            .keys_for("yank")
+            .and_then(|keys| keys.chars().next());
+        let action = if yank_key.is_some_and(|k| self.command == k.to_string()) {
+            Action::Yank(1)
+        } else {
+            match self.command_table.parse(&self.command) {
+                Some(action) => action,
+                None => return false,
+            }
+        };
+        let command = std::mem::take(&mut self.command);
+        let cursor_index = self.buffer().tree.sibling_index().unwrap();
+        let (start, end) = (anchor.min(cursor_index), anchor.max(cursor_index));
+        let (action_name, action) = match action {
+            Action::MoveToNextSibling(_)
+            | Action::MoveToPrevSibling(_)
+            | Action::MoveToSibling(_) => {
+                let canonical = self.command_table.canonical(&command);
+                self.buffer_mut()
+                    .script
+                    .record_command(canonical.as_deref().unwrap_or(&command));
+                return self.perform_action(action);
+            }
+            Action::Yank(_) => ("yank", Action::Yank(end - start + 1)),
+            Action::Delete(_) => ("delete", Action::Delete(end - start + 1)),
+            _ => {
+                self.log(
+                    LogLevel::Warning,
+                    "Only sibling motions, x and y work on a selection (press ESC to cancel)"
+                        .to_string(),
+                );
+                return false;
+            }
+        };
+        // The selection is acted on like the same command with a count, starting from its first
+        // sibling, which is also how it's recorded so that scripts don't need visual mode
+        self.mode = Mode::Normal;
+        let offset = start as isize - cursor_index as isize;
+        self.buffer_mut().tree.move_by_siblings(offset);
+        self.buffer_mut().script.record_sibling_move(offset);
+        let counted = self.command_table.keys_for(action_name).and_then(|keys| {
+            self.command_table
+                .canonical(&format!("{}{}", end - start + 1, keys))
+        });
+        if let Some(counted) = counted {
+            self.buffer_mut().script.record_command(&counted);
+        }
+        let should_quit = self.perform_action(action);
+        self.check_cursor();
+        should_quit
+    }
+
+    /// Respond to a keypress in insert mode, returning `true` if Sapling should quit.  Characters
+    /// that would make the value invalid are rejected, and ESC commits the edit as a single
+    /// [`Action::SetLiteral`].
+    fn type_literal_key(&mut self, key: Key) -> bool {
+        let edited = match &self.mode {
+            Mode::Insert(node) => node,
+            _ => return false,
+        };
+        let mut text = edited.literal().unwrap_or_default().to_string();
+        match key {
+            Key::Char(c) => {
+                text.push(c);
+                if edited.check_literal(&text) == LiteralValidity::Invalid {
+                    self.log(LogLevel::Warning, format!("Can't type '{}' here", c));
+                    return false;
+                }
+            }
+            Key::Backspace => {
+                text.pop();
+            }
+            Key::ESC => {
+                if edited.check_literal(&text) != LiteralValidity::Valid {
+                    self.log(
+                        LogLevel::Warning,
+                        format!("'{}' is incomplete (finish it to leave insert mode)", text),
+                    );
+                    return false;
+                }
+                self.mode = Mode::Normal;
+                // Leaving the value unchanged shouldn't create an undo step
+                if self.buffer().tree.cursor_node().literal() == Some(text.as_str()) {
+                    return false;
+                }
+                self.buffer_mut().script.record_literal(&text);
+                let should_quit = self.perform_action(Action::SetLiteral(text));
+                self.warn_about_duplicate_key();
+                return should_quit;
+            }
+            _ => return false,
+        }
+        if let Some(node) = edited.with_literal(&text) {
+            self.mode = Mode::Insert(node);
+        }
+        false
+    }
+
+    /// Warn the user if the selected node is the key of a record entry, and another entry of the
+    /// same record has the same key.  Duplicate keys are allowed (e.g. in JSON), but are almost
+    /// always a mistake.
+    fn warn_about_duplicate_key(&mut self) {
+        let path = self.buffer().tree.cursor_path();
+        let (entry_path, key_index) = match path.split_last() {
+            Some((key_index, entry_path)) if !entry_path.is_empty() => (entry_path, *key_index),
+            _ => return,
+        };
+        let is_key = self
+            .buffer()
+            .tree
+            .node_at_path(entry_path)
+            .and_then(|entry| entry.key_index())
+            == Some(key_index);
+        let entries = self
+            .buffer()
+            .tree
+            .node_at_path(&entry_path[..entry_path.len() - 1])
+            .and_then(|record| record.record_entries(&self.buffer().tree));
+        let key = self
+            .buffer()
+            .tree
+            .cursor_node()
+            .literal()
+            .unwrap_or_default();
+        if let (true, Some(entries)) = (is_key, entries) {
+            if entries.iter().filter(|(k, _)| k == key).count() > 1 {
+                self.log(
+                    LogLevel::Warning,
+                    format!("The key '{}' is used more than once in this record", key),
+                );
+            }
+        }
+    }
+
+    /// Move the cursor to the next (or previous, if `forwards` is `false`) node whose display
+    /// name, or [name](ASTSpec::child_name) in its parent, contains the last search query.  This
+    /// means that searching for a key finds its JSON field before the key itself.
+    fn search(&mut self, forwards: bool) {
+        let query = match &self.last_search {
+            Some(q) => q.clone(),
+            None => {
+                self.log(LogLevel::Warning, "No previous search".to_string());
+                return;
+            }
+        };
+        let tree = &self.buffer().tree;
+        let is_named = |path: &[usize]| {
+            let (index, parent_path) = match path.split_last() {
+                Some(split) => split,
+                None => return false,
+            };
+            tree.node_at_path(parent_path)
+                .and_then(|parent| parent.child_name(*index, tree))
+                .is_some_and(|name| name.contains(&query))
+        };
+        match tree.find_matching_path(
+            |path, node| node.display_name().contains(&query) || is_named(path),
+            forwards,
+        ) {
+            Some(path) => {
+                self.buffer_mut().tree.move_to_path(&path);
+                self.buffer_mut().script.record_path_move(path);
+            }
+            None => self.log(LogLevel::Warning, format!("Pattern not found: '{}'", query)),
+        }
+    }
+
+    /// Bookmark the selected node as mark `c`, replacing any node that `c` marked before
+    fn set_mark(&mut self, c: char) {
+        if !c.is_alphabetic() {
+            self.log(
+                LogLevel::Warning,
+                format!("Marks must be letters, not '{}'", c),
+            );
+            return;
+        }
+        let cursor = self.buffer().tree.cursor();
+        self.buffer_mut().marks.insert(c, cursor);
+        self.log(LogLevel::Info, format!("Set mark '{}'", c));
+    }
+
+    /// Move the cursor to the node marked by `c`, unless that node is no longer in the tree
+    fn jump_to_mark(&mut self, c: char) {
+        let mark = match self.buffer().marks.get(&c) {
+            Some(&mark) => mark,
+            None => {
+                self.log(LogLevel::Warning, format!("Mark '{}' isn't set", c));
+                return;
+            }
+        };
+        match self.buffer().tree.path_to(mark) {
+            Some(path) => {
+                self.buffer_mut().tree.move_to_path(&path);
+                self.buffer_mut().script.record_path_move(path);
+            }
+            None => self.log(
+                LogLevel::Warning,
+                format!("Mark '{}' points to a deleted node", c),
+            ),
+        }
+    }
+
+    /// Move the cursor to the next (or previous, if `forwards` is `false`) record entry whose key
+    /// is used by another entry of the same record, wrapping round at the end of the tree like
+    /// searching does
+    fn jump_to_duplicate_key(&mut self, forwards: bool) {
+        let cursor_path = self.buffer().tree.cursor_path();
+        let buffer = &mut self.buffers[self.active_buffer];
+        let root = buffer.tree.root();
+        let paths: Vec<Vec<usize>> = buffer
+            .duplicate_keys
+            .entries(&buffer.tree, root)
+            .into_iter()
+            .map(|(path, _, _)| path)
+            .collect();
+        // Paths are ordered the same way as the text
+        let path = if forwards {
+            paths.iter().find(|p| **p > cursor_path).or(paths.first())
+        } else {
+            paths
+                .iter()
+                .rev()
+                .find(|p| **p < cursor_path)
+                .or(paths.last())
+        };
+        match path.cloned() {
+            Some(path) => {
+                self.buffer_mut().tree.move_to_path(&path);
+                self.buffer_mut().script.record_path_move(path);
+            }
+            None => self.log(LogLevel::Info, "No keys are duplicated".to_string()),
+        }
+    }
+
+    /// Report where every mark in the current buffer points, in alphabetical order
+    fn list_marks(&mut self) {
+        let tree = &self.buffer().tree;
+        let marks: Vec<String> = self
+            .buffer()
+            .marks
+            .iter()
+            .map(|(c, &mark)| {
+                let place = tree
+                    .path_to(mark)
+                    .and_then(|path| display_path(tree, &path))
+                    .unwrap_or_else(|| "(deleted)".to_string());
+                format!("'{} {}", c, place)
+            })
+            .collect();
+        if marks.is_empty() {
+            self.log(LogLevel::Info, "No marks are set".to_string());
+        } else {
+            self.log(LogLevel::Info, format!("Marks: {}", marks.join(", ")));
+        }
+    }
+
+    /// Show the changes that turn an older version of the tree into the current one, in an
+    /// [`Overlay`].  The older version is `versions_back` versions before the current one in the
+    /// undo history, or the version that was last saved if `versions_back` is [`None`].
+    fn show_changes(&mut self, versions_back: Option<usize>) {
+        let position = self.buffer().tree.history_position();
+        let (old_position, since) = match versions_back {
+            Some(n) => match position.checked_sub(n) {
+                Some(old_position) => (
+                    old_position,
+                    format!("{} version{} ago", n, if n == 1 { "" } else { "s" }),
+                ),
+                None => {
+                    self.log(
+                        LogLevel::Warning,
+                        format!("The undo history only goes back {} versions", position),
+                    );
+                    return;
+                }
+            },
+            None => match self.buffer().saved_version {
+                Some(old_position) => (old_position, "the last save".to_string()),
+                None => {
+                    self.log(
+                        LogLevel::Warning,
+                        "The saved version is no longer in the undo history".to_string(),
+                    );
+                    return;
+                }
+            },
+        };
+        let changes = self
+            .buffer()
+            .tree
+            .changes_since(old_position)
+            .unwrap_or_default();
+        if changes.is_empty() {
+            self.log(LogLevel::Info, format!("No changes since {}", since));
+            return;
+        }
+        let mut lines = vec![format!(
+            "{} change{} since {}:",
+            changes.len(),
+            if changes.len() == 1 { "" } else { "s" },
+            since
+        )];
+        lines.extend(changes.iter().map(|change| format!("  {}", change)));
+        self.overlay = Some(Overlay::new("list of changes", lines));
+    }
+
+    /// Replay the keys of the macro in `register` `count` times, returning `true` if the macro
+    /// made Sapling quit
+    fn play_macro(&mut self, register: char, count: usize) -> bool {
+        let keys = match self.macros.get(register) {
+            Some(keys) => keys.to_vec(),
+            None => {
+                self.log(
+                    LogLevel::Warning,
+                    format!("No macro recorded in '@{}'", register),
+                );
+                return false;
+            }
+        };
+        if !self.macros.start_playing(register) {
+            self.log(
+                LogLevel::Error,
+                format!(
+                    "Macro '@{}' plays itself, so playback was stopped",
+                    register
+                ),
+            );
+            return false;
+        }
+        let mut should_quit = false;
+        'repeats: for _ in 0..count {
+            for key in &keys {
+                if self.macros.is_aborted() {
+                    break 'repeats;
+                }
+                if self.handle_key(*key) {
+                    should_quit = true;
+                    break 'repeats;
+                }
+            }
+        }
+        self.macros.stop_playing();
+        should_quit
+    }
+
+    /// Respond to a single keypress, returning `true` if Sapling should quit.  Keys typed by the
+    /// user and keys replayed by macros both go through here.
+    fn handle_key(&mut self, key: Key) -> bool {
+        let should_quit = self.dispatch_key(key);
+        // However the cursor got there, it should never be hidden inside a fold
+        self.reveal_cursor();
+        should_quit
+    }
+
+    /// Pass a keypress to whatever is handling keys at the moment, i.e. the help page, the prompt
+    /// or the current [`Mode`]
+    fn dispatch_key(&mut self, key: Key) -> bool {
+        // The key that starts a recording (`Q` by default) also stops it, but only at the start of
+        // a command so that it can still be typed as an argument
+        let stop_key = self
+            .command_table
+            .keys_for("record-macro")
+            .and_then(|keys| keys.chars().next())
+            .map(Key::Char);
+        // Messages only describe the previous keypress
+        self.message = None;
+        if Some(key) == stop_key
+            && self.command.is_empty()
+            && self.overlay.is_none()
+            && !matches!(self.mode, Mode::Insert(_))
+        {
+            if let Some(register) = self.macros.stop_recording() {
+                self.log(LogLevel::Info, format!("Recorded macro '@{}'", register));
+                return false;
+            }
+        }
+        self.macros.record_key(key);
+
+        if self.overlay.is_some() {
+            self.turn_overlay_page(key);
+            return false;
+        }
+        if self.prompt.is_some() {
+            return self.type_prompt_key(key);
+        }
+        match self.mode {
+            Mode::Normal => {}
+            Mode::Insert(_) => {
+                // The value being typed is displayed as it changes
+                self.buffer_mut().display_cache = None;
+                return self.type_literal_key(key);
+            }
+            Mode::Visual(anchor) => return self.type_visual_key(anchor, key),
+        }
+        match key {
+            Key::Char(c) => {
+                // Add the new keypress to the command
+                self.command.push(c);
+                self.command_typed_at = Instant::now();
+                // Attempt to parse the command, and take action if the command is complete.  The
+                // command box is cleared first, so that macros can type their own commands.
+                if let Some(action) = self.command_table.parse(&self.command) {
+                    let command = std::mem::take(&mut self.command);
+                    // Scripts use the default keys, so they can be replayed with any keymap
+                    let canonical = self.command_table.canonical(&command);
+                    return self.run_command(&command, canonical.as_deref(), action);
+                }
+            }
+            Key::ESC => {
+                self.command.clear();
+            }
+            Key::PageDown => {
+                self.move_by_page(true);
+            }
+            Key::PageUp => {
+                self.move_by_page(false);
+            }
+            // Scrolling is limited by `scroll_to_cursor`, so the cursor never leaves the screen
+            Key::Ctrl('e') => {
+                self.buffer_mut().scroll_offset += 1;
+            }
+            Key::Ctrl('y') => {
+                self.buffer_mut().scroll_offset = self.buffer_mut().scroll_offset.saturating_sub(1);
+            }
+            Key::Right => self.scroll_horizontally(1, true),
+            Key::Left => self.scroll_horizontally(1, false),
+            Key::Ctrl('o') => {
+                self.perform_action(Action::StartOpenFile);
+            }
+            Key::Ctrl('n') => {
+                self.perform_action(Action::NextBuffer);
+            }
+            // Like vim's `ctrl-g`, which shows the size of the file
+            Key::Ctrl('g') => {
+                self.perform_action(Action::ReportStats);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Perform the `action` of a complete `command`, recording it in the script as `canonical`
+    /// (or as `command`, if it has no canonical form).  Returns `true` if Sapling should quit.
+    fn run_command(&mut self, command: &str, canonical: Option<&str>, action: Action) -> bool {
+        self.buffer_mut()
+            .script
+            .record_command(canonical.unwrap_or(command));
+        log::debug!("Command '{}' is {:?}", command, action);
+        if action == Action::Undefined {
+            self.log(LogLevel::Warning, format!("Unknown command '{}'", command));
+        }
+        let should_quit = self.perform_action(action);
+        self.check_cursor();
+        should_quit
+    }
+
+    /// Returns how long the unfinished command can wait for its next key at `now`, or [`None`]
+    /// if it can wait forever (or there isn't one)
+    fn command_timeout(&self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.command_typed_at);
+        match self
+            .command_table
+            .resolve_timeout(&self.command, elapsed, self.config.key_timeout)
+        {
+            Resolution::Wait(remaining) => remaining,
+            // The command has already timed out
+            _ => Some(Duration::ZERO),
+        }
+    }
+
+    /// Finish the unfinished command if the user hasn't typed its next key in time by `now`,
+    /// either by performing the complete command it starts with or by discarding it.  Returns
+    /// `true` if Sapling should quit.
+    fn time_out_command(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.command_typed_at);
+        let resolution =
+            self.command_table
+                .resolve_timeout(&self.command, elapsed, self.config.key_timeout);
+        let should_quit = match resolution {
+            Resolution::Wait(_) => return false,
+            Resolution::Perform(action, canonical) => {
+                let command = std::mem::take(&mut self.command);
+                self.run_command(&command, Some(&canonical), action)
+            }
+            Resolution::Discard => {
+                let command = std::mem::take(&mut self.command);
+                self.log(
+                    LogLevel::Warning,
+                    format!("'{}' isn't a command on its own", command),
+                );
+                false
+            }
+        };
+        self.reveal_cursor();
+        should_quit
+    }
+
+    /// Wait for the next event, or until the unfinished command times out (in which case this
+    /// returns [`None`])
+    fn next_event(&self) -> std::result::Result<Option<Event>, EditorError> {
+        match self.command_timeout(Instant::now()) {
+            Some(timeout) => self.term.poll_event_timeout(timeout),
+            None => self.term.poll_event().map(Some),
+        }
+        .map_err(EditorError::Input)
+    }
+
+    /// Report a bug if the cursor doesn't refer to a node (e.g. because an edit left a stale
+    /// reference behind), moving the cursor back to the root so that editing can carry on rather
+    /// than crashing on the next lookup of the selected node
+    fn check_cursor(&mut self) {
+        let cursor = self.buffer().tree.cursor();
+        if self.buffer().tree.get_node(cursor).is_none() {
+            self.buffer_mut().tree.move_to_root();
+            self.log(
+                LogLevel::Error,
+                format!(
+                    "Bug: the cursor referred to a missing node ({:?}), so moved to the root",
+                    cursor
+                ),
+            );
+        }
+    }
+
+    /// Respond to an [`Action`], returning `true` if Sapling should quit
+    fn perform_action(&mut self, action: Action) -> bool {
+        // Every command goes through here, so this covers new commands without them having to
+        // check for themselves
+        if self.read_only && action.is_mutating() {
+            self.log(
+                LogLevel::Warning,
+                "The document is read-only (press gR to allow edits)".to_string(),
+            );
+            return false;
+        }
+        if action.is_edit() {
+            log::debug!("Applying {:?} to {:?}", action, self.buffer().tree.cursor());
+            self.buffer_mut().node_count = None;
+            let buffer = &mut self.buffers[self.active_buffer];
+            let root = buffer.tree.root();
+            match action {
+                Action::Undo
+                | Action::Redo
+                | Action::ReplaceAll(_, _)
+                | Action::NormalizeAll
+                | Action::InvertBooleans => buffer.duplicate_keys.clear(),
+                // Every other edit only changes the selected node and the nodes above it
+                _ => {
+                    let path = buffer.tree.cursor_path();
+                    buffer.duplicate_keys.forget_path(&buffer.tree, root, &path);
+                }
+            }
+        }
+        if action.is_repeatable() {
+            self.last_edit = Some(action.clone());
+        }
+        // Moving the cursor is the only thing that can't change the displayed text
+        if !action.is_motion() {
+            self.buffer_mut().display_cache = None;
+        }
+        // Every action is a single undo step, however many changes it's made of (e.g. an edit
+        // repeated with a count, or a macro played several times)
+        let is_transaction = (action.is_edit() && !matches!(action, Action::Undo | Action::Redo))
+            || matches!(action, Action::RepeatLastEdit(_) | Action::PlayMacro(_, _));
+        // Actions made of other actions keep the steps that worked, since every step that fails
+        // rolls itself back
+        let is_compound = matches!(action, Action::RepeatLastEdit(_) | Action::PlayMacro(_, _));
+        let is_edit = action.is_edit();
+        let is_new_version = !matches!(action, Action::Undo | Action::Redo);
+        let active_buffer = self.active_buffer;
+        let root = self.buffer().tree.root();
+        let position = self.buffer().tree.history_position();
+        let log_len = self.log.len();
+        let should_quit = if is_transaction {
+            self.buffer_mut().tree.begin_edit();
+            let should_quit = self.apply_action(action);
+            // An action that reports a problem doesn't leave half of its changes behind
+            let failed = !is_compound
+                && self.log[log_len..]
+                    .iter()
+                    .any(|(level, _)| *level >= LogLevel::Warning);
+            // The transaction belongs to the buffer it began in, even if the action (e.g. a
+            // macro) has switched to another one
+            if let Some(buffer) = self.buffers.get_mut(active_buffer) {
+                if failed {
+                    buffer.tree.rollback_edit();
+                } else {
+                    buffer.tree.commit_edit();
+                }
+            }
+            should_quit
+        } else {
+            self.apply_action(action)
+        };
+        // Edits that fail, are rolled back, or have nothing to undo leave the tree as it was
+        if let Some(buffer) = self.buffers.get_mut(active_buffer).filter(|_| is_edit) {
+            let new_position = buffer.tree.history_position();
+            if new_position != position || buffer.tree.root() != root {
+                if is_new_version {
+                    // A new version replaces every version that has been undone, which may
+                    // include the saved one
+                    if buffer.saved_version.is_some_and(|v| v > position) {
+                        buffer.saved_version = None;
+                    }
+                    buffer.dirty = true;
+                } else {
+                    // Undoing back to the saved version undoes the unsaved changes
+                    buffer.dirty = buffer.saved_version != Some(new_position);
+                }
+                if let Some(auto_save) = &mut buffer.auto_save {
+                    auto_save.record_edit();
+                }
+            }
+        }
+        // Changes (and undoing them) can copy the nodes above the changed ones to new references
+        if self.active_buffer == active_buffer && self.buffer().tree.root() != root {
+            self.follow_copies(position);
+        }
+        should_quit
+    }
+
+    /// Carry out an `action` that [`Editor::perform_action`] has allowed, returning `true` if
+    /// Sapling should quit
+    fn apply_action(&mut self, action: Action) -> bool {
+        match action {
+            // Undefined commands are reported by `handle_key`, which knows what was typed
+            Action::Undefined => {}
+            Action::StartSearch => {
+                self.prompt = Some((PromptKind::Search, String::new()));
+            }
+            Action::StartOpenFile => {
+                self.prompt = Some((PromptKind::OpenFile, String::new()));
+            }
+            Action::StartExCommand => {
+                self.prompt = Some((PromptKind::ExCommand, String::new()));
+            }
+            Action::Reload => {
+                // Reloading throws away the unsaved changes, so the user has to agree to that
+                if self.buffer().dirty {
+                    self.prompt = Some((PromptKind::ConfirmReload, String::new()));
+                } else {
+                    self.reload_file();
+                }
+            }
+            Action::OpenFile(path) => {
+                self.open_file(&path);
+            }
+            Action::WriteTo(path) => {
+                self.write_to_path(path);
+            }
+            Action::WriteToAndQuit(path) => {
+                return self.write_to_path(path) && self.can_quit();
+            }
+            Action::ChangeSetting(setting) => {
+                self.change_setting(setting);
+            }
+            Action::ShowSetting(name) => {
+                self.show_setting(name);
+            }
+            Action::ReportStats => {
+                self.report_stats();
+            }
+            Action::NextBuffer => {
+                self.switch_to_buffer((self.active_buffer + 1) % self.buffers.len());
+            }
+            Action::PrevBuffer => {
+                let count = self.buffers.len();
+                self.switch_to_buffer((self.active_buffer + count - 1) % count);
+            }
+            Action::CloseBuffer => {
+                self.close_buffer(false);
+            }
+            Action::ForceCloseBuffer => {
+                self.close_buffer(true);
+            }
+            Action::EditLiteral => {
+                let node = self.buffer().tree.cursor_node();
+                if node.literal().is_some() {
+                    self.mode = Mode::Insert(node.clone());
+                } else {
+                    let name = node.summary();
+                    self.log(
+                        LogLevel::Warning,
+                        format!("Cannot edit the value of '{}'", name),
+                    );
+                }
+            }
+            Action::SetLiteral(text) => {
+                if let Some(node) = self.buffer().tree.cursor_node().with_literal(&text) {
+                    self.buffer_mut().tree.replace_cursor(node);
+                }
+            }
+            Action::Clamp(min, max) => {
+                self.clamp_number(&min, &max);
+            }
+            Action::RenameKey => {
+                if self.buffer_mut().tree.move_to_key() {
+                    return self.perform_action(Action::EditLiteral);
+                }
+                let name = self.buffer().tree.cursor_node().summary();
+                self.log(
+                    LogLevel::Warning,
+                    format!("'{}' isn't part of an entry with a key", name),
+                );
+            }
+            Action::SearchNext => {
+                self.search(true);
+            }
+            Action::SetMark(c) => {
+                self.set_mark(c);
+            }
+            Action::JumpToMark(c) => {
+                self.jump_to_mark(c);
+            }
+            Action::ListMarks => {
+                self.list_marks();
+            }
+            Action::ShowChanges(versions_back) => {
+                self.show_changes(versions_back);
+            }
+            Action::SearchPrev => {
+                self.search(false);
+            }
+            Action::NextDuplicateKey => {
+                self.jump_to_duplicate_key(true);
+            }
+            Action::PrevDuplicateKey => {
+                self.jump_to_duplicate_key(false);
+            }
+            Action::StartRecording(register) => {
+                if self.macros.start_recording(register) {
+                    self.log(LogLevel::Info, format!("Recording macro '@{}'", register));
+                } else {
+                    self.log(
+                        LogLevel::Warning,
+                        format!("'{}' is not a macro register", register),
+                    );
+                }
+            }
+            Action::PlayMacro(register, count) => {
+                return self.play_macro(register, count);
+            }
+            Action::RepeatLastEdit(count) => match self.last_edit.clone() {
+                Some(edit) => {
+                    for _ in 0..count {
+                        self.perform_action(edit.clone());
+                    }
+                }
+                None => self.log(LogLevel::Warning, "No edit to repeat".to_string()),
+            },
+            Action::ShowHelp => {
+                let lines = help_lines::<Ref, Node>(&self.command_table);
+                self.overlay = Some(Overlay::new("help", lines));
+            }
+            Action::Quit => {
+                return self.can_quit();
+            }
+            Action::ForceQuit => {
+                return true;
+            }
+            Action::Write => {
+                self.write_to_file(false);
+            }
+            Action::ForceWrite => {
+                self.write_to_file(true);
+            }
+            Action::WriteAndQuit => {
+                return self.write_to_file(false) && self.can_quit();
+            }
+            Action::Replace(c) => {
+                self.replace_cursor(c);
+            }
+            Action::InsertChild(c, index) => {
+                self.insert_child(c, index);
+            }
+            Action::AppendChild(c) => {
+                self.insert_child(c, usize::MAX);
+            }
+            Action::InsertNextSibling(c) => {
+                self.insert_sibling(c, true);
+            }
+            Action::InsertPrevSibling(c) => {
+                self.insert_sibling(c, false);
+            }
+            Action::Undo => {
+                self.undo();
+            }
+            Action::Redo => {
+                self.redo();
+            }
+            Action::FoldAll => {
+                self.fold_all();
+            }
+            Action::UnfoldAll => {
+                self.unfold_all();
+            }
+            Action::ToggleFold => {
+                self.toggle_fold();
+            }
+            Action::TogglePin => {
+                self.toggle_pin();
+            }
+            Action::ToggleCollapse => {
+                self.toggle_collapse();
+            }
+            Action::MoveToAncestor(c) => {
+                self.move_to_ancestor(c);
+            }
+            Action::ReportSize => {
+                self.report_size();
+            }
+            Action::ExportScript => {
+                self.export_script_to_default_path();
+            }
+            Action::WriteSession => {
+                self.write_session(Path::new(SESSION_PATH));
+            }
+            Action::WriteSessionTo(path) => {
+                self.write_session(&path);
+            }
+            Action::ReportKindHistogram => {
+                self.report_kind_histogram();
+            }
+            Action::CollectGarbage => {
+                self.collect_garbage();
+            }
+            Action::MergeWithNextSibling => {
+                self.merge_with_next_sibling();
+            }
+            Action::Wrap(c) => {
+                self.wrap_cursor(c);
+            }
+            Action::Unwrap => {
+                self.unwrap_cursor();
+            }
+            Action::SwapWithNextSibling => {
+                self.swap_with_sibling(1);
+            }
+            Action::CycleFormatStyle => {
+                self.cycle_format_style();
+            }
+            Action::ToggleLineNumbers => {
+                self.config.line_numbers = !self.config.line_numbers;
+            }
+            Action::ToggleReadOnly => {
+                self.read_only = !self.read_only;
+                let state = if self.read_only { "on" } else { "off" };
+                self.log(LogLevel::Info, format!("Read-only mode {}", state));
+            }
+            Action::SwapWithPrevSibling => {
+                self.swap_with_sibling(-1);
+            }
+            Action::MoveToSameKeyInNextRecord => {
+                self.move_to_same_key_in_next_record();
+            }
+            Action::ReplaceAll(from, to) => {
+                self.replace_all(from, to, false);
+            }
+            Action::ReplaceAllDryRun(from, to) => {
+                self.replace_all(from, to, true);
+            }
+            Action::CopyRecord(format) => {
+                self.copy_record(format);
+            }
+            Action::FoldBelowDepth(depth) => {
+                self.fold_below_depth(depth);
+            }
+            Action::ScrollLeft => {
+                self.scroll_horizontally(self.half_view_width(), false);
+            }
+            Action::ScrollRight => {
+                self.scroll_horizontally(self.half_view_width(), true);
+            }
+            Action::NormalizeAll => {
+                self.normalize_all();
+            }
+            Action::MoveToRoot => {
+                self.buffer_mut().tree.move_to_root();
+            }
+            Action::InvertBooleans => {
+                self.invert_booleans();
+            }
+            Action::SortChildren => {
+                self.sort_children(false);
+            }
+            Action::ForceSortChildren => {
+                self.sort_children(true);
+            }
+            Action::Yank(count) => {
+                self.yank(count);
+            }
+            Action::StartVisual => match self.buffer().tree.sibling_index() {
+                Some(index) => self.mode = Mode::Visual(index),
+                None => self.log(
+                    LogLevel::Warning,
+                    "The root has no siblings to select".to_string(),
+                ),
+            },
+            Action::Paste(position) => {
+                self.paste(position);
+            }
+            Action::YankInto(register, count) => {
+                self.yank_into(register, count);
+            }
+            Action::PasteFrom(register) => {
+                self.paste_from(register);
+            }
+            Action::ListRegisters => {
+                self.list_registers();
+            }
+            Action::Duplicate => {
+                self.duplicate();
+            }
+            Action::CopyToClipboard => {
+                self.copy_to_clipboard();
+            }
+            Action::PasteFromClipboard => {
+                self.paste_from_clipboard();
+            }
+            Action::Delete(count) => {
+                self.delete_siblings(count);
+            }
+            Action::MoveToFirstChild => {
+                self.move_cursor(|tree| tree.move_to_child(0), "first child");
+            }
+            Action::MoveToParent => {
+                self.move_cursor(|tree| tree.move_to_parent(), "parent");
+            }
+            Action::MoveToNextSibling(count) => {
+                self.move_cursor(
+                    |tree| tree.move_by_siblings(count as isize) > 0,
+                    "next sibling",
+                );
+            }
+            Action::MoveToPrevSibling(count) => {
+                self.move_cursor(
+                    |tree| tree.move_by_siblings(-(count as isize)) > 0,
+                    "previous sibling",
+                );
+            }
+            Action::MoveToSibling(index) => {
+                // Already being at the sibling isn't worth a message, but being at the root is
+                if self.buffer().tree.sibling_index().is_none() {
+                    self.log(LogLevel::Info, "The root has no siblings".to_string());
+                } else if self.buffer_mut().tree.move_to_sibling(index) {
+                    let index = self.buffer().tree.sibling_index().unwrap();
+                    self.log(LogLevel::Debug, format!("Moved to sibling {}", index));
+                }
+            }
+        }
+        false
+    }
+
+    /// Respond to events until the user quits, returning an error if the terminal stops
+    /// delivering input
+    fn mainloop(&mut self) -> std::result::Result<(), EditorError> {
+        // Sit in the infinte mainloop
+        loop {
+            let event = self.next_event()?;
+            /* RESPOND TO THE USER'S INPUT */
+            let log_len = self.log.len();
+            let mut redraw = match event {
+                // The command is finished or discarded, which changes what is drawn
+                None if self.time_out_command(Instant::now()) => break,
+                None => true,
+                Some(Event::Key(Key::MousePress(button, row, col))) => {
+                    self.handle_mouse(button, row as usize, col as usize);
+                    true
+                }
+                Some(Event::Key(Key::MouseRelease(..))) | Some(Event::Key(Key::MouseHold(..))) => {
+                    false
+                }
+                Some(Event::Key(key)) if self.handle_key(key) => break,
+                // Any key can change the command, so always causes a redraw
+                Some(Event::Key(_)) => true,
+                // The new size is read from the terminal when redrawing
+                Some(Event::Resize { .. }) | Some(Event::Restarted) => true,
+                Some(_) => false,
+            };
+            self.auto_save_if_due();
+            // Auto-saving (or anything else) that logs a message has to show it
+            redraw |= self.log.len() != log_len;
+            if !redraw {
+                continue;
+            }
+            // This also handles the terminal being resized
+            self.scroll_to_cursor();
+            self.refresh_display_cache();
+            if self.buffer().node_count.is_none() {
+                self.buffer_mut().node_count =
+                    Some(self.buffer_mut().tree.count_matching(|_| true));
+            }
+            self.buffer_mut().check_duplicate_keys();
+
+            // Failing to draw one frame isn't fatal, since the next one might work
+            if let Err(e) = self.update_display() {
+                self.log(LogLevel::Error, format!("Couldn't draw the screen: {}", e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Type every key of `commands` into the editor, as if the user had typed them without a
+    /// terminal (see [`crate::script`]).  Whitespace between commands finishes the command
+    /// before it, in the same way that the key timeout would, and is otherwise ignored.  Stops
+    /// at the first command that fails or needs the user to type into it, or once a command
+    /// quits.
+    pub fn run_commands(&mut self, commands: &str) -> std::result::Result<(), ScriptError> {
+        self.running_script = true;
+        let result = self.type_commands(commands);
+        self.running_script = false;
+        result
+    }
+
+    /// Type the keys of `commands` for [`Editor::run_commands`]
+    fn type_commands(&mut self, commands: &str) -> std::result::Result<(), ScriptError> {
+        // The keys of the command being typed, and the position of its first key
+        let mut command = String::new();
+        let mut position = 0;
+        for (i, c) in commands.chars().enumerate() {
+            if c.is_whitespace() && !self.command.is_empty() {
+                let should_quit = self.finish_script_command(&command, position)?;
+                if should_quit {
+                    return Ok(());
+                }
+            }
+            // Whitespace is only typed if it's the argument of a command (e.g. `f `)
+            if c.is_whitespace() && self.command.is_empty() {
+                continue;
+            }
+            if self.command.is_empty() {
+                command.clear();
+                position = i;
+            }
+            command.push(c);
+            let should_quit = self.handle_key(Key::Char(c));
+            self.check_script_command(&command, position)?;
+            if should_quit {
+                return Ok(());
+            }
+        }
+        if !self.command.is_empty() {
+            self.finish_script_command(&command, position)?;
+            if !self.command.is_empty() {
+                return Err(ScriptError::Unfinished { command, position });
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the unfinished command of a script as if the key timeout had passed, returning
+    /// `true` if Sapling should quit.  Commands waiting for an argument are left unfinished.
+    fn finish_script_command(
+        &mut self,
+        command: &str,
+        position: usize,
+    ) -> std::result::Result<bool, ScriptError> {
+        self.message = None;
+        let should_quit = self.time_out_command(self.command_typed_at + self.config.key_timeout);
+        self.check_script_command(command, position)?;
+        Ok(should_quit)
+    }
+
+    /// Returns an error if the `command` of a script (starting at `position`) failed, or left
+    /// the editor waiting for the user to type something other than a command
+    fn check_script_command(
+        &self,
+        command: &str,
+        position: usize,
+    ) -> std::result::Result<(), ScriptError> {
+        if let Some((level, message)) = &self.message {
+            if *level >= LogLevel::Warning {
+                return Err(ScriptError::Failed {
+                    command: command.to_string(),
+                    position,
+                    message: message.clone(),
+                });
+            }
+        }
+        if self.prompt.is_some() || self.overlay.is_some() || matches!(self.mode, Mode::Insert(_)) {
+            return Err(ScriptError::Interactive {
+                command: command.to_string(),
+                position,
+            });
+        }
+        Ok(())
+    }
+
+    /// Start the editor and enter the mainloop, returning what the user left behind once they
+    /// quit.  The terminal is restored when this returns, and also before the message of any
+    /// panic is printed (so that it isn't lost in the alternate screen).  The panic hook that was
+    /// set before this was called is put back when it returns.  Returns an error if the terminal
+    /// fails.
+    pub fn run(&mut self) -> std::result::Result<ExitSummary, EditorError> {
+        self.term.start().map_err(EditorError::Terminal)?;
+        let term = Arc::clone(&self.term);
+        // The hook that was there before (e.g. one set by a program embedding Sapling) is shared
+        // with ours, so that it can be put back afterwards
+        let previous_hook = Arc::new(std::panic::take_hook());
+        let hook = Arc::clone(&previous_hook);
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = term.pause();
+            hook(info);
+        }));
+        // Log the startup of the code
+        self.log(LogLevel::Debug, "Starting Up...".to_string());
+        // Start the mainloop
+        let result = self.mainloop();
+        // Log that the editor is closing
+        self.log(LogLevel::Debug, "Closing...".to_string());
+        // Go back to the previous panic hook, which doesn't keep the terminal alive.  Dropping
+        // ours leaves the previous hook with only one owner, so it can be moved back.
+        drop(std::panic::take_hook());
+        match Arc::try_unwrap(previous_hook) {
+            Ok(hook) => std::panic::set_hook(hook),
+            Err(hook) => std::panic::set_hook(Box::new(move |info| hook(info))),
+        }
+        self.term.pause().map_err(EditorError::Terminal)?;
+        result?;
+        Ok(ExitSummary {
+            saved: self.buffers.iter().all(|buffer| !buffer.dirty),
+            path: self.buffer().file_path.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command_table::CommandTable;
+    use super::config::{ColorScheme, Config, Setting};
+    use super::frontend::TestFrontend;
+    use super::{
+        clamp_horizontal_scroll, clamp_scroll_offset, command_hint, fit_status_bar, gutter_width,
+        help_lines, layout, message_attr, node_at, overlay_page_count, parse_command,
+        position_tokens, text_to_save, text_width::display_width, write_atomically, Action, Editor,
+        EditorError, ExitSummary, ExportFormat, Layout, LineStart, LogLevel, PastePosition,
+        PromptKind, ScreenText, Session, TreeView,
+    };
+    use crate::ast_spec::display_token::{
+        flat_tokens_for_lines, line_of_node, IndentStyle, INDENT_WIDTH,
+    };
+    use crate::ast_spec::expr::{Expr, ExprFormat};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::lisp::{Lisp, LispFormat};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::test_lisp::TestLisp;
+    use crate::ast_spec::ASTSpec;
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use crate::node_map::subtree::Subtree;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, Reference};
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tuikit::prelude::{Attr, Color, Key};
+
+    /// Make an [`Editor`] for `tree` that draws to an 80x24 [`TestFrontend`] with no keys to read
+    fn test_editor<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>>(
+        tree: E,
+        format_style: Node::FormatStyle,
+    ) -> Editor<Ref, Node, E> {
+        let frontend = Arc::new(TestFrontend::new(80, 24, vec![]));
+        Editor::with_frontend(tree, format_style, frontend)
+    }
+
+    /// Send every [`char`] of `keys` to `editor`, as though they had been typed
+    fn type_keys<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>>(
+        editor: &mut Editor<Ref, Node, E>,
+        keys: &str,
+    ) {
+        for c in keys.chars() {
+            editor.handle_key(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn try_new_without_terminal() {
+        let result = Editor::try_new(Spec::<JSON<Index>>::new(), JSONFormat::Pretty);
+        // We can only check for the error if the tests aren't being run in a terminal
+        if std::fs::File::open("/dev/tty").is_err() {
+            match result {
+                Err(e @ EditorError::Terminal(_)) => {
+                    // The error should explain itself and keep its cause
+                    assert!(e.to_string().starts_with("Couldn't open the terminal"));
+                    assert!(e.source().is_some());
+                }
+                Err(e) => panic!("Expected a terminal error, got {}", e),
+                Ok(_) => panic!("Editor was created without a terminal"),
+            }
+        }
+    }
+
+    /// Run an [`Editor`] on a [`TestFrontend`] that types `keys`, returning the frontend and the
+    /// result of the editor's mainloop
+    fn run_headless(
+        tree: TestJSON,
+        keys: &str,
+    ) -> (
+        Arc<TestFrontend>,
+        std::result::Result<ExitSummary, EditorError>,
+    ) {
+        let frontend = Arc::new(TestFrontend::with_keys(40, 8, keys));
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = Editor::with_frontend(tree, JSONFormat::Pretty, frontend.clone());
+        let result = editor.run();
+        (frontend, result)
+    }
+
+    #[test]
+    fn headless_sessions() {
+        let tree = || TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        // Quitting ends the mainloop straight away, and restores the terminal
+        let (frontend, result) = run_headless(tree(), "qx");
+        assert!(result.is_ok());
+        assert!(frontend.is_paused());
+        let screen = frontend.screen();
+        // The last frame was drawn before the final key, with the half-typed command's hint
+        assert_eq!(&screen[1..4], &["    true,", "    false", "]"]);
+        assert!(screen[7].starts_with("q:quit q!:force-quit"));
+        assert!(screen[7].ends_with('q'));
+
+        // Edits are drawn, and the loop ends on the last key
+        let (frontend, result) = run_headless(tree(), "rtq!");
+        assert!(result.is_ok());
+        assert_eq!(frontend.events_left(), 0);
+        let screen = frontend.screen();
+        assert!(screen[0].starts_with("true"));
+        assert!(screen[1].ends_with("Replacing with 't'/T"));
+
+        // Running out of input is an error
+        let (frontend, result) = run_headless(tree(), "cr");
+        assert!(matches!(result, Err(EditorError::Input(_))));
+        let screen = frontend.screen();
+        // The log takes up the right half of the screen
+        let tree_text: Vec<&str> = screen[..4]
+            .iter()
+            .map(|l| l[..l.len().min(20)].trim_end())
+            .collect();
+        assert_eq!(tree_text, vec!["[", "    true,", "    false", "]"]);
+        // The selected node is highlighted, and the half-typed command shows a hint
+        assert_eq!(frontend.attr_at(1, 4).unwrap().bg, Color::CYAN);
+        assert_eq!(frontend.attr_at(2, 4).unwrap().bg, Color::Default);
+        assert!(screen[7].starts_with("t:true f:false"));
+    }
+
+    #[test]
+    fn unfinished_commands_time_out() {
+        let tree = || TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        // When the keys run out, `q` stops waiting to become `q!` and quits
+        let (frontend, result) = run_headless(tree(), "cq");
+        assert!(result.is_ok());
+        assert!(frontend.is_paused());
+
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree().build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let later = |editor: &Editor<_, _, _>, millis| {
+            editor.command_typed_at + Duration::from_millis(millis)
+        };
+        // Part of a command name is discarded once the timeout has passed
+        editor.handle_key(Key::Char('g'));
+        assert_eq!(
+            editor.command_timeout(later(&editor, 100)),
+            Some(Duration::from_millis(400))
+        );
+        assert!(!editor.time_out_command(later(&editor, 100)));
+        assert_eq!(editor.command, "g");
+        assert!(!editor.time_out_command(later(&editor, 500)));
+        assert_eq!(editor.command, "");
+        assert_eq!(editor.message(), Some("'g' isn't a command on its own"));
+        // A complete command is performed (so `u` undoes rather than waiting to become `uu`),
+        // and the timeout can be changed
+        editor
+            .command_table
+            .register_simple("uu", "test-redo", "", Action::Redo);
+        type_keys(&mut editor, "crf");
+        editor.perform_action(Action::ChangeSetting(Setting::KeyTimeout(
+            Duration::from_millis(50),
+        )));
+        editor.handle_key(Key::Char('u'));
+        assert_eq!(
+            editor.command_timeout(later(&editor, 40)),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(
+            editor.command_timeout(later(&editor, 60)),
+            Some(Duration::ZERO)
+        );
+        assert!(!editor.time_out_command(later(&editor, 60)));
+        assert_eq!(editor.command, "");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            "[true, false]"
+        );
+        // Counts and arguments wait for as long as they need
+        for keys in ["3", "r"] {
+            type_keys(&mut editor, keys);
+            assert_eq!(editor.command_timeout(later(&editor, 10_000)), None);
+            assert!(!editor.time_out_command(later(&editor, 10_000)));
+            assert_eq!(editor.command, keys);
+            editor.handle_key(Key::ESC);
+        }
+    }
+
+    #[test]
+    fn folded_subtree() {
+        let tree = || {
+            TestJSON::Array(vec![
+                TestJSON::Object(vec![
+                    ("a".to_string(), TestJSON::True),
+                    ("b".to_string(), TestJSON::Array(vec![TestJSON::Null])),
+                ]),
+                TestJSON::False,
+            ])
+        };
+        let tree_text = |frontend: &TestFrontend| -> Vec<String> {
+            frontend.screen()[..4]
+                .iter()
+                .map(|l| {
+                    l.chars()
+                        .take(20)
+                        .collect::<String>()
+                        .trim_end()
+                        .to_string()
+                })
+                .collect()
+        };
+        // The folded object is summarised on one line, which is highlighted like any other node
+        let (frontend, _) = run_headless(tree(), "czaqx");
+        assert_eq!(
+            tree_text(&frontend),
+            vec!["[", "    {…} 2 entries,", "    false", "]"]
+        );
+        assert_ne!(frontend.attr_at(1, 4).unwrap().bg, Color::Default);
+        assert_eq!(frontend.attr_at(2, 4).unwrap().bg, Color::Default);
+        assert_eq!(frontend.attr_at(2, 8).unwrap().bg, Color::Default);
+        // Moving into the folded node opens it up again
+        let (frontend, _) = run_headless(tree(), "czacqx");
+        assert_eq!(
+            tree_text(&frontend),
+            vec!["[", "    {", r#"        "a": true,"#, r#"        "b": ["#]
+        );
+        let (frontend, _) = run_headless(tree(), "czazaqx");
+        assert_eq!(tree_text(&frontend)[1], "    {");
+    }
+
+    #[test]
+    fn visual_selection() {
+        let tree = || {
+            TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+                TestJSON::Null,
+                TestJSON::Str("s".to_string()),
+                TestJSON::Array(vec![]),
+            ])
+        };
+        // Every sibling in the selection is highlighted, along with the text between them
+        let (frontend, _) = run_headless(tree(), "cnvn");
+        for (row, is_selected) in &[(1, false), (2, true), (3, true), (4, false)] {
+            let bg = frontend.attr_at(*row, 4).unwrap().bg;
+            assert_eq!(bg != Color::Default, *is_selected, "row {}", row);
+        }
+        assert_ne!(frontend.attr_at(2, 9).unwrap().bg, Color::Default);
+        assert!(frontend.screen()[7].starts_with("-- VISUAL -- "));
+
+        let start: Spec<JSON<Index>> = Spec::from_tree(tree().build_node_map());
+        let mut editor = test_editor(start.clone(), JSONFormat::Compact);
+        // Moving out of the siblings isn't allowed, and leaves the selection alone
+        type_keys(&mut editor, "cnnvNNp");
+        assert_eq!(
+            editor.message(),
+            Some("Only sibling motions, x and y work on a selection (press ESC to cancel)")
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        // The whole selection is deleted at once, whichever way it was made
+        type_keys(&mut editor, "x");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"["s", []]"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        assert!(!editor.status_bar("", 100).contains("VISUAL"));
+        type_keys(&mut editor, "u");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[true, false, null, "s", []]"#
+        );
+
+        // Yanked selections are pasted together, and ESC cancels a selection
+        type_keys(&mut editor, "vnyNvn");
+        editor.handle_key(Key::ESC);
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![1]);
+        type_keys(&mut editor, "x");
+        type_keys(&mut editor, "Pn");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[true, null, true, false, "s", []]"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2]);
+        // The root has no siblings
+        type_keys(&mut editor, "pv");
+        assert_eq!(editor.message(), Some("The root has no siblings to select"));
+
+        // Selections are recorded as counted commands, so the script replays without them
+        let mut replayed = start;
+        editor
+            .script()
+            .replay(&mut replayed, " ", &JSONFormat::Compact);
+        assert_eq!(
+            replayed.to_text(&JSONFormat::Compact),
+            editor.buffer().tree.to_text(&JSONFormat::Compact)
+        );
+        assert_eq!(replayed.cursor_path(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn status_bar_names_node() {
+        let tree = TestJSON::Array(vec![TestJSON::Str("hello".to_string())]);
+        let (frontend, _) = run_headless(tree, "cp");
+        assert!(frontend.screen()[7].starts_with("array root"));
+        let tree = TestJSON::Array(vec![TestJSON::Str("hello".to_string())]);
+        let (frontend, _) = run_headless(tree, "c");
+        assert!(frontend.screen()[7].starts_with(r#"string "hello" root[0]"#));
+    }
+
+    #[test]
+    fn rename_keys() {
+        let tree = TestJSON::Object(vec![
+            ("a".to_string(), TestJSON::True),
+            ("b".to_string(), TestJSON::False),
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let mut press = |keys: &[Key]| {
+            for k in keys {
+                editor.handle_key(*k);
+            }
+            (
+                editor.message().map(str::to_string),
+                editor.buffer().tree.to_text(&JSONFormat::Compact),
+            )
+        };
+        let chars = |s: &str| s.chars().map(Key::Char).collect::<Vec<_>>();
+        // Renaming from the field is a single undoable edit
+        press(&chars("ck"));
+        let (_, text) = press(&[Key::Backspace, Key::Char('x'), Key::ESC]);
+        assert_eq!(text, r#"{"x": true, "b": false}"#);
+        assert_eq!(press(&chars("u")).1, r#"{"a": true, "b": false}"#);
+        // Renaming from the value works too, and duplicate keys are allowed but warned about
+        press(&chars("cnk"));
+        assert_eq!(
+            press(&[Key::Backspace, Key::Char('b'), Key::ESC]),
+            (
+                Some("The key 'b' is used more than once in this record".to_string()),
+                r#"{"b": true, "b": false}"#.to_string()
+            )
+        );
+        // Only entries have keys
+        assert_eq!(
+            press(&chars("ggk")).0,
+            Some("'object' isn't part of an entry with a key".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_stats() {
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::Array(vec![])]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.handle_key(Key::Ctrl('g'));
+        // The size of the nodes depends on the platform, so the number of bytes isn't checked
+        let message = editor.message().unwrap();
+        assert!(message.starts_with(
+            "3 nodes, depth 1, 1 unreachable (array: 2, bool: 1); \
+             4 nodes stored (3 reachable) in about "
+        ));
+        // The tree isn't changed, and the message only lasts until the next keypress
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            "[true, []]"
+        );
+        editor.handle_key(Key::Char('c'));
+        assert_eq!(editor.message(), None);
+    }
+
+    #[test]
+    fn collects_garbage() {
+        let tree = TestJSON::Array(vec![TestJSON::Array(vec![TestJSON::True]), TestJSON::True]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        type_keys(&mut editor, "czkzp");
+        let cursor = editor.buffer().tree.cursor();
+        // Freeing the placeholder root that the tree was built around moves every other node
+        type_keys(&mut editor, "gc");
+        assert_eq!(editor.message(), Some("Freed 1 nodes, kept 4"));
+        assert_ne!(editor.buffer().tree.cursor(), cursor);
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        // ... but the folds move with them
+        assert!(editor
+            .buffer()
+            .folds
+            .is_collapsed(editor.buffer().tree.cursor()));
+        assert!(editor
+            .buffer()
+            .folds
+            .is_pinned(editor.buffer().tree.cursor()));
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            "[[true], true]"
+        );
+    }
+
+    #[test]
+    fn garbage_collection_keeps_undo_history() {
+        let tree = TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::False,
+            TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        type_keys(&mut editor, "cxnzpgmagcu");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[true, false, {"value": true}]"#
+        );
+        // The references moved by the collection still lead to the same nodes in the old version
+        let tree = &editor.buffer().tree;
+        let object = tree.ref_at_path(&[2]).unwrap();
+        assert!(editor.buffer().folds.is_pinned(object));
+        assert_eq!(editor.buffer().marks.get(&'a'), Some(&object));
+        let field = tree.ref_at_path(&[2, 0]).unwrap();
+        assert!(!editor.buffer().folds.is_pinned(field));
+    }
+
+    #[test]
+    fn resumed_sessions() {
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::Str("a\\b".to_string())]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let frontend = Arc::new(TestFrontend::new(80, 24, vec![]));
+        let mut editor = Editor::with_frontend(tree, JSONFormat::Compact, frontend.clone());
+        editor.set_file_path(Some(PathBuf::from("data.json")));
+        type_keys(&mut editor, "cranrou");
+        // Taking the session doesn't change the editor
+        let text = editor.buffer().tree.to_text(&JSONFormat::Compact);
+        let session = editor.session();
+        assert_eq!(editor.buffer().tree.to_text(&JSONFormat::Compact), text);
+        assert_eq!(session.history.len(), 3);
+        assert_eq!(session.current, 1);
+
+        // The resumed editor is in exactly the same state, including its undo history
+        let session = Session::from_text(&session.to_text()).unwrap();
+        let tree: Spec<JSON<Index>> = session
+            .build_tree(|text| JSON::parse_into(text).map_err(|e| e.to_string()))
+            .unwrap();
+        let mut resumed = Editor::with_frontend(tree, JSONFormat::Compact, frontend);
+        resumed.set_file_path(session.file_path.clone());
+        resumed.mark_unsaved();
+        assert_eq!(resumed.file_path(), Some(Path::new("data.json")));
+        assert!(resumed.is_dirty());
+        for c in "uuRR".chars() {
+            assert_eq!(
+                resumed.buffer().tree.to_text(&JSONFormat::Compact),
+                editor.buffer().tree.to_text(&JSONFormat::Compact)
+            );
+            assert_eq!(
+                resumed.buffer().tree.cursor_path(),
+                editor.buffer().tree.cursor_path()
+            );
+            editor.handle_key(Key::Char(c));
+            resumed.handle_key(Key::Char(c));
+        }
+        assert_eq!(
+            resumed.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[[], {}]"#
+        );
+    }
+
+    #[test]
+    fn piped_editing() {
+        // `main` reads the document from stdin and prints the piped text, so this covers
+        // everything in between: parsing, editing and writing the result
+        let run_piped = |keys: &str| {
+            let node_map = JSON::parse_into(r#"[true, {"k": false}]"#).unwrap();
+            let tree: Spec<JSON<Index>> = Spec::from_tree(node_map);
+            let frontend = Arc::new(TestFrontend::with_keys(40, 8, keys));
+            let mut editor = Editor::with_frontend(tree, JSONFormat::Pretty, frontend);
+            editor.set_pipe_output(Some(JSONFormat::Compact));
+            assert!(editor.run().is_ok());
+            editor.piped_text().map(str::to_string)
+        };
+        let edited = Some(r#"[false, {"k": false}]"#.to_string());
+        assert_eq!(run_piped("crfwq"), edited);
+        // Quitting without writing leaves nothing to print
+        assert_eq!(run_piped("crfq!"), None);
+        // Only the most recently written version is printed, and writing lets `q` quit
+        assert_eq!(run_piped("crfwrtq!"), edited);
+        assert_eq!(run_piped("wq"), Some(r#"[true, {"k": false}]"#.to_string()));
+    }
+
+    #[test]
+    fn ex_command_prompt() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let dir = std::env::temp_dir().join(format!("sapling-ex-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let original = dir.join("original.json");
+        let copy = dir.join("copy.json");
+        let tree = TestJSON::Object(vec![
+            ("b".to_string(), TestJSON::True),
+            ("a".to_string(), TestJSON::False),
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.set_file_path(Some(original.clone()));
+        let run = |editor: &mut JSONEditor, command: &str| {
+            editor.handle_key(Key::Char(':'));
+            type_keys(editor, command);
+            editor.handle_key(Key::Enter)
+        };
+
+        // Commands with key equivalents are recorded in the script as those keys
+        assert!(!run(&mut editor, "sort"));
+        assert_eq!(editor.message(), Some("Sorted 2 children"));
+        assert_eq!(editor.script().to_text(), "# Sapling session script\n=s\n");
+        // Writing to another path leaves the buffer's own file unsaved
+        assert!(!run(&mut editor, &format!("w {}", copy.display())));
+        assert_eq!(
+            std::fs::read_to_string(&copy).unwrap(),
+            r#"{"a": false, "b": true}"#
+        );
+        assert!(editor.is_dirty());
+        assert!(!original.exists());
+        assert!(!run(&mut editor, "q"));
+        assert!(run(&mut editor, "wq"));
+        assert!(original.exists());
+        assert!(!editor.is_dirty());
+        // Sessions can be written anywhere, and read back in with their history
+        let session_path = dir.join("work.session");
+        assert!(!run(
+            &mut editor,
+            &format!("mksession {}", session_path.display())
+        ));
+        assert_eq!(
+            editor.message(),
+            Some(
+                format!(
+                    "Wrote 2 versions of the tree to '{}'",
+                    session_path.display()
+                )
+                .as_str()
+            )
+        );
+        let session = Session::from_text(&std::fs::read_to_string(&session_path).unwrap());
+        assert_eq!(session, Ok(editor.session()));
+
+        // Mistakes are reported rather than run
+        run(&mut editor, "sort now");
+        assert_eq!(editor.message(), Some("':sort' doesn't take an argument"));
+        run(&mut editor, "frobnicate");
+        assert_eq!(editor.message(), Some("Unknown command ':frobnicate'"));
+        run(&mut editor, "set indent=4");
+        assert_eq!(editor.message(), Some("indent=4"));
+        // ctrl-u clears the line, and ESC gives up on the command
+        editor.handle_key(Key::Char(':'));
+        type_keys(&mut editor, "q!");
+        editor.handle_key(Key::Ctrl('u'));
+        assert_eq!(editor.prompt, Some((PromptKind::ExCommand, String::new())));
+        type_keys(&mut editor, "stats");
+        editor.handle_key(Key::ESC);
+        assert_eq!(editor.message(), None);
+        assert!(!run(&mut editor, ""));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clamp_numbers() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let tree = TestJSON::Array(vec![
+            TestJSON::Number("150".to_string()),
+            TestJSON::Number("42".to_string()),
+            TestJSON::True,
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let run = |editor: &mut JSONEditor, keys: &str| {
+            for c in keys.chars() {
+                editor.handle_key(if c == '\n' { Key::Enter } else { Key::Char(c) });
+            }
+            editor.buffer().tree.to_text(&JSONFormat::Compact)
+        };
+
+        // Out-of-range numbers are clamped, and the user is told that they were
+        assert_eq!(run(&mut editor, "c:clamp 0 100\n"), "[100, 42, true]");
+        assert_eq!(editor.message(), Some("Clamped 150 to 100"));
+        // ... as a change that can be undone
+        assert_eq!(run(&mut editor, "u"), "[150, 42, true]");
+        // Numbers in range are left alone
+        assert_eq!(run(&mut editor, "cn:clamp 0 100\n"), "[150, 42, true]");
+        assert_eq!(editor.message(), Some("42 is already between 0 and 100"));
+        assert!(editor.script().to_text().ends_with(":clamp 0 100\n"));
+        // Only numbers can be clamped
+        assert_eq!(run(&mut editor, "n:clamp 0 1\n"), "[150, 42, true]");
+        assert_eq!(editor.message(), Some("'true' isn't a number"));
+        run(&mut editor, ":clamp 1\n");
+        assert_eq!(
+            editor.message(),
+            Some("':clamp' needs a minimum and a maximum, like ':clamp 0 100'")
+        );
+    }
+
+    #[test]
+    fn files_changed_on_disk() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let path = std::env::temp_dir().join(format!("sapling-disk-{}.json", std::process::id()));
+        std::fs::write(&path, "[true, false]").unwrap();
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.set_file_path(Some(path.clone()));
+        editor.set_file_loader(Box::new(|path: &Path| {
+            let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let node_map = JSON::parse_into(&text).map_err(|e| e.to_string())?;
+            Ok(Spec::from_tree(node_map))
+        }));
+        let run = |editor: &mut JSONEditor, keys: &str| {
+            type_keys(editor, keys);
+            editor.handle_key(Key::Enter);
+            editor.message().map(str::to_string)
+        };
+        let text = |editor: &JSONEditor| editor.buffer().tree.to_text(&JSONFormat::Compact);
+
+        // Files that haven't changed are written as usual, and writing updates the stamp
+        assert!(run(&mut editor, ":w")
+            .unwrap()
+            .starts_with("Wrote 13 bytes"));
+        // Another program changes the file, so writing over it needs `:w!`
+        std::fs::write(&path, "[null, null, null]").unwrap();
+        run(&mut editor, "crf");
+        assert_eq!(
+            run(&mut editor, ":w"),
+            Some(format!(
+                "'{}' changed on disk since it was read (use :w! to overwrite it)",
+                path.display()
+            ))
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[null, null, null]"
+        );
+        assert!(editor.is_dirty());
+        assert!(run(&mut editor, ":w!")
+            .unwrap()
+            .starts_with("Wrote 14 bytes"));
+        assert!(!editor.is_dirty());
+        // Once it's been overwritten, the file is ours again
+        run(&mut editor, "crt");
+        assert!(run(&mut editor, ":w")
+            .unwrap()
+            .starts_with("Wrote 13 bytes"));
+
+        // Reloading an unchanged buffer doesn't need to ask
+        std::fs::write(&path, "[null]").unwrap();
+        assert_eq!(
+            run(&mut editor, ":e!"),
+            Some(format!("Reloaded '{}'", path.display()))
+        );
+        assert_eq!(text(&editor), "[null]");
+        // The undo history starts again, and the reloaded file is the saved version
+        assert!(!editor.is_dirty());
+        editor.handle_key(Key::Char('u'));
+        assert_eq!(text(&editor), "[null]");
+        assert!(run(&mut editor, ":w").unwrap().starts_with("Wrote 6 bytes"));
+
+        // Unsaved changes are only discarded if the user agrees
+        std::fs::write(&path, "[false]").unwrap();
+        run(&mut editor, "cro");
+        run(&mut editor, ":e!");
+        assert_eq!(
+            editor.prompt,
+            Some((PromptKind::ConfirmReload, String::new()))
+        );
+        assert_eq!(
+            run(&mut editor, "n").as_deref(),
+            Some("Kept the unsaved changes")
+        );
+        assert_eq!(text(&editor), "[{}]");
+        run(&mut editor, ":e!");
+        run(&mut editor, "y");
+        assert_eq!(text(&editor), "[false]");
+
+        // Files that can't be read are reported
+        std::fs::remove_file(&path).unwrap();
+        assert!(run(&mut editor, ":e!")
+            .unwrap()
+            .starts_with(&format!("Couldn't reload '{}'", path.display())));
+        assert_eq!(text(&editor), "[false]");
+    }
+
+    #[test]
+    fn config_round_trip() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let path = std::env::temp_dir().join(format!("sapling-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "indent = 2\nnumber = true\ncolors = \"plain\"\n").unwrap();
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let frontend = Arc::new(TestFrontend::new(40, 8, vec![]));
+        let mut editor = Editor::with_frontend(tree, JSONFormat::Pretty, frontend.clone());
+        let run = |editor: &mut JSONEditor, command: &str| {
+            editor.handle_key(Key::Char(':'));
+            type_keys(editor, command);
+            editor.handle_key(Key::Enter);
+            editor.message().map(str::to_string)
+        };
+
+        assert!(editor.load_config(&path));
+        assert_eq!(
+            editor.config(),
+            &Config {
+                indent: IndentStyle::Spaces(2),
+                line_numbers: true,
+                color_scheme: ColorScheme::Plain,
+                ..Config::default()
+            }
+        );
+        editor.update_display().unwrap();
+        assert_eq!(&frontend.screen()[1..3], &["2   true,", "3   false"]);
+        // The whole tree is selected, so the plain colour is its highlight
+        assert_eq!(frontend.attr_at(1, 6).unwrap().bg, Color::WHITE);
+
+        // Changes take effect straight away, including redrawing the tree
+        assert_eq!(
+            run(&mut editor, "set indent=6").as_deref(),
+            Some("indent=6")
+        );
+        assert_eq!(
+            run(&mut editor, "set nonumber").as_deref(),
+            Some("nonumber")
+        );
+        assert_eq!(
+            run(&mut editor, "set autosave=0").as_deref(),
+            Some("autosave=0")
+        );
+        editor.update_display().unwrap();
+        let screen = frontend.screen();
+        assert!(screen[1].starts_with("      true, "));
+        assert!(screen[2].starts_with("      false "));
+        assert_eq!(editor.text_to_save(), "[\n      true,\n      false\n]");
+        // Settings can be queried, and mistakes are reported rather than applied
+        assert_eq!(
+            run(&mut editor, "set colors?").as_deref(),
+            Some("colors=plain")
+        );
+        assert_eq!(
+            run(&mut editor, "set timeoutlen=soon").as_deref(),
+            Some("'timeoutlen' must be a number of milliseconds, not 'soon'")
+        );
+        assert_eq!(
+            run(&mut editor, "set colour=red").as_deref(),
+            Some("Unknown setting 'colour'")
+        );
+        assert_eq!(
+            editor.config(),
+            &Config {
+                indent: IndentStyle::Spaces(6),
+                auto_save_interval: None,
+                color_scheme: ColorScheme::Plain,
+                ..Config::default()
+            }
+        );
+
+        // A config file that can't be loaded leaves the settings alone
+        std::fs::write(&path, "indent = wide\n").unwrap();
+        assert!(!editor.load_config(&path));
+        assert_eq!(
+            editor.message(),
+            Some(
+                format!(
+                    "Couldn't load config '{}' (using the current settings): line 1: 'indent' \
+                     must be a number of spaces or 'tab', not 'wide'",
+                    path.display()
+                )
+                .as_str()
+            )
+        );
+        assert_eq!(editor.config().indent, IndentStyle::Spaces(6));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_children() {
+        let tree = TestJSON::Array((0..10).map(|i| TestJSON::Number(i.to_string())).collect());
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        // Wide enough that the log doesn't cover the tree, which is cut off where the log starts
+        let frontend = Arc::new(TestFrontend::new(160, 24, vec![]));
+        let mut editor = Editor::with_frontend(tree, JSONFormat::Compact, frontend.clone());
+        editor.set_config(Config {
+            child_limit: Some(3),
+            ..Config::default()
+        });
+        let screen = |editor: &mut Editor<Index, JSON<Index>, Spec<JSON<Index>>>| {
+            editor.update_display().unwrap();
+            let line = frontend.screen()[0].clone();
+            line.split("  ").next().unwrap().to_string()
+        };
+
+        assert_eq!(
+            screen(&mut editor),
+            "[0, 1, 2, … 7 more (press za to expand)]"
+        );
+        // Moving past the window moves the window along with the cursor
+        type_keys(&mut editor, "cnnn");
+        assert_eq!(
+            screen(&mut editor),
+            "[… 1 earlier (press za to expand), 1, 2, 3, … 6 more (press za to expand)]"
+        );
+        editor.handle_key(Key::Char('G'));
+        assert_eq!(
+            screen(&mut editor),
+            "[… 7 earlier (press za to expand), 7, 8, 9]"
+        );
+        editor.handle_key(Key::Char('H'));
+        assert_eq!(
+            screen(&mut editor),
+            "[0, 1, 2, … 7 more (press za to expand)]"
+        );
+
+        type_keys(&mut editor, "pza");
+        assert_eq!(editor.message(), Some("Showing all 10 children"));
+        assert_eq!(screen(&mut editor), "[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]");
+        // Once every child is shown, the node folds as usual
+        type_keys(&mut editor, "za");
+        assert_eq!(screen(&mut editor), "[…] 10 items");
+    }
+
+    /// Times drawing and moving around a 500,000 element array, which should take well under 50ms
+    /// per frame because only a window of the array's children is rendered.  Run with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn huge_array_speed() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let tree = TestJSON::Array(
+            (0..500_000)
+                .map(|i| TestJSON::Number(i.to_string()))
+                .collect(),
+        );
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let frontend = Arc::new(TestFrontend::new(80, 50, vec![]));
+        let mut editor = Editor::with_frontend(tree, JSONFormat::Pretty, frontend.clone());
+        let frame = |editor: &mut JSONEditor, keys: &str| {
+            let start = std::time::Instant::now();
+            type_keys(editor, keys);
+            editor.update_display().unwrap();
+            let elapsed = start.elapsed();
+            println!("{:>5}: {:?}", keys, elapsed);
+            elapsed
+        };
+
+        let mut slowest = frame(&mut editor, "");
+        for keys in &["c", "n", "250000G", "n", "G", "H", "p"] {
+            slowest = slowest.max(frame(&mut editor, keys));
+        }
+        assert!(frontend.screen()[1].starts_with("    0,"));
+        println!("slowest frame: {:?}", slowest);
+    }
+
+    /// A [`PathSource`](super::completion::PathSource) whose current directory holds a few files
+    struct FakeFiles;
+
+    impl super::completion::PathSource for FakeFiles {
+        fn entries(&self, dir: &Path) -> Vec<(String, bool)> {
+            match dir.to_str().unwrap() {
+                "." => vec![
+                    ("src".to_string(), true),
+                    ("data.json".to_string(), false),
+                    ("data.xml".to_string(), false),
+                ],
+                _ => vec![],
+            }
+        }
+    }
+
+    #[test]
+    fn prompt_completion() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let tree: Spec<JSON<Index>> = Spec::from_tree(TestJSON::True.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.set_path_source(Box::new(FakeFiles));
+        let prompt_text = |editor: &mut JSONEditor, keys: &str| {
+            for c in keys.chars() {
+                let key = if c == '\t' { Key::Tab } else { Key::Char(c) };
+                editor.handle_key(key);
+            }
+            editor.prompt.clone().map(|(_, text)| text)
+        };
+
+        // A unique prefix completes to the whole command
+        assert_eq!(prompt_text(&mut editor, ":so\t").as_deref(), Some("sort"));
+        editor.handle_key(Key::Ctrl('u'));
+        assert_eq!(prompt_text(&mut editor, "st\t").as_deref(), Some("stats"));
+        // An ambiguous one completes as far as it can and lists the candidates, which
+        // pressing Tab again cycles through
+        editor.handle_key(Key::Ctrl('u'));
+        assert_eq!(prompt_text(&mut editor, "w\t").as_deref(), Some("w"));
+        assert_eq!(editor.message(), Some("w  w!  wq"));
+        assert_eq!(prompt_text(&mut editor, "\t").as_deref(), Some("w"));
+        assert_eq!(prompt_text(&mut editor, "\t").as_deref(), Some("w!"));
+        assert_eq!(prompt_text(&mut editor, "\t").as_deref(), Some("wq"));
+        assert_eq!(prompt_text(&mut editor, "\t").as_deref(), Some("w"));
+        // Paths are completed after commands that take them
+        assert_eq!(prompt_text(&mut editor, " d\t").as_deref(), Some("w data."));
+        assert_eq!(editor.message(), Some("data.json  data.xml"));
+        assert_eq!(
+            prompt_text(&mut editor, "\t\t").as_deref(),
+            Some("w data.xml")
+        );
+        editor.handle_key(Key::ESC);
+        assert_eq!(
+            prompt_text(&mut editor, ":e   s\t").as_deref(),
+            Some("e   src/")
+        );
+        editor.handle_key(Key::Ctrl('u'));
+        assert_eq!(
+            prompt_text(&mut editor, "sort x\t").as_deref(),
+            Some("sort x")
+        );
+        editor.handle_key(Key::Ctrl('u'));
+        assert_eq!(prompt_text(&mut editor, "zz\t").as_deref(), Some("zz"));
+        assert_eq!(editor.message(), Some("No completions"));
+    }
+
+    #[test]
+    fn multiple_buffers() {
+        type JSONEditor = Editor<Index, JSON<Index>, Spec<JSON<Index>>>;
+        let tree: Spec<JSON<Index>> =
+            Spec::from_tree(TestJSON::Array(vec![TestJSON::True]).build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.set_file_path(Some(PathBuf::from("a.json")));
+        editor.set_file_loader(Box::new(|path| match path.to_str() {
+            Some("b.json") => Ok(Spec::from_tree(
+                TestJSON::Array(vec![TestJSON::Null, TestJSON::False]).build_node_map(),
+            )),
+            _ => Err("no such file".to_string()),
+        }));
+        let open = |editor: &mut JSONEditor, path: &str| {
+            editor.handle_key(Key::Ctrl('o'));
+            type_keys(editor, path);
+            editor.handle_key(Key::Enter);
+        };
+        let text = |editor: &JSONEditor| editor.buffer().tree.to_text(&JSONFormat::Compact);
+
+        // Files that can't be loaded don't make a buffer
+        open(&mut editor, "missing.json");
+        assert_eq!(
+            editor.message(),
+            Some("Couldn't open 'missing.json': no such file")
+        );
+        assert_eq!(editor.buffer_count(), 1);
+        assert!(!editor.status_bar("", 100).contains("[1/1]"));
+
+        open(&mut editor, "b.json");
+        assert_eq!(editor.buffer_count(), 2);
+        assert_eq!(editor.message(), Some("Switched to 'b.json' (2/2)"));
+        assert!(editor.status_bar("", 100).contains("b.json [2/2]"));
+        assert_eq!(text(&editor), "[null, false]");
+
+        // The yank register is shared, so subtrees can be pasted into other buffers
+        type_keys(&mut editor, "cyy");
+        editor.handle_key(Key::Ctrl('n'));
+        assert_eq!(editor.message(), Some("Switched to 'a.json' (1/2)"));
+        assert_eq!(editor.buffer().tree.cursor_path(), Vec::<usize>::new());
+        type_keys(&mut editor, "cPn");
+        assert_eq!(text(&editor), "[true, null]");
+        assert!(editor.is_dirty());
+        // ... and each buffer keeps its own cursor and unsaved changes
+        type_keys(&mut editor, "bn");
+        assert_eq!(text(&editor), "[null, false]");
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        assert!(!editor.is_dirty());
+
+        // Quitting checks every buffer for unsaved changes
+        type_keys(&mut editor, "qx");
+        assert_eq!(
+            editor.message(),
+            Some("Unsaved changes in 'a.json' (press q! to force quit)")
+        );
+        // Opening a file that's already open switches to its buffer
+        type_keys(&mut editor, "bp");
+        open(&mut editor, "b.json");
+        assert_eq!(editor.buffer_count(), 2);
+        assert_eq!(text(&editor), "[null, false]");
+
+        // Closing a buffer checks for unsaved changes too
+        type_keys(&mut editor, "rtbd");
+        assert_eq!(
+            editor.message(),
+            Some("Unsaved changes (press bD to close anyway)")
+        );
+        type_keys(&mut editor, "bD");
+        assert_eq!(editor.message(), Some("Closed 'b.json'"));
+        assert_eq!(editor.buffer_count(), 1);
+        assert_eq!(text(&editor), "[true, null]");
+        type_keys(&mut editor, "bd");
+        assert_eq!(
+            editor.message(),
+            Some("Can't close the only buffer (press q to quit)")
+        );
+    }
+
+    #[test]
+    fn read_only_mode() {
+        let tree = TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::Object(vec![("k".to_string(), TestJSON::Str("v".to_string()))]),
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.set_read_only(true);
+        let text = editor.buffer().tree.to_text(&JSONFormat::Compact);
+        assert!(editor.status_bar("", 100).contains("[RO]"));
+
+        // Every kind of edit is rejected, and moving the cursor still works
+        for keys in &[
+            "crf", "it", "at", "x", "yyPn", "J", ">", "Wa", "U", "u", "R", "s", "k", "ge", "g~",
+            "grtf", "wx", "wq", "\"+p",
+        ] {
+            for c in keys.chars() {
+                assert!(!editor.handle_key(Key::Char(c)));
+            }
+            assert_eq!(
+                editor.message(),
+                Some("The document is read-only (press gR to allow edits)"),
+                "{:?} wasn't rejected",
+                keys
+            );
+            assert_eq!(editor.buffer().tree.to_text(&JSONFormat::Compact), text);
+            assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        }
+        assert!(!editor.is_dirty());
+        assert!(!editor.buffer_mut().tree.undo());
+        // Quitting doesn't change the tree, so is allowed
+        assert!(!editor.handle_key(Key::Char('q')));
+        assert!(editor.handle_key(Key::Char('x')));
+
+        // Leaving read-only mode allows edits again
+        type_keys(&mut editor, "gRrf");
+        assert!(!editor.is_read_only());
+        assert!(!editor.status_bar("", 100).contains("[RO]"));
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[false, {"k": "v"}]"#
+        );
+    }
+
+    /// A [`Clipboard`](super::clipboard::Clipboard) that can be inspected by tests
+    struct FakeClipboard(Rc<RefCell<String>>);
+
+    impl super::clipboard::Clipboard for FakeClipboard {
+        fn set_text(&mut self, text: &str) -> Result<(), String> {
+            *self.0.borrow_mut() = text.to_string();
+            Ok(())
+        }
+
+        fn get_text(&mut self) -> Result<String, String> {
+            Ok(self.0.borrow().clone())
+        }
+    }
+
+    #[test]
+    fn system_clipboard() {
+        let tree = TestJSON::Array(vec![
+            TestJSON::Object(vec![("k".to_string(), TestJSON::True)]),
+            TestJSON::Null,
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Pretty);
+
+        // Without a clipboard, nothing can be copied or pasted
+        type_keys(&mut editor, "c\"+y");
+        assert_eq!(editor.message(), Some("No system clipboard is available"));
+        type_keys(&mut editor, "\"+p");
+        assert_eq!(editor.message(), Some("No system clipboard is available"));
+
+        let contents = Rc::new(RefCell::new(String::new()));
+        editor.set_system_clipboard(Some(Box::new(FakeClipboard(contents.clone()))));
+        editor.set_clipboard_format(
+            JSONFormat::Compact,
+            Box::new(|text| {
+                let node_map: VecNodeMap<JSON<Index>> =
+                    JSON::parse_into(text).map_err(|e| e.to_string())?;
+                Ok(Subtree::copy_from(&node_map, node_map.root()).unwrap())
+            }),
+        );
+        // Subtrees are copied compactly, whatever the display style is
+        type_keys(&mut editor, "\"+y");
+        assert_eq!(*contents.borrow(), r#"{"k": true}"#);
+        assert_eq!(
+            editor.message(),
+            Some("Copied 11 characters to the clipboard")
+        );
+        type_keys(&mut editor, "\"+p");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[{"k": true}, {"k": true}, null]"#
+        );
+        assert!(editor.is_dirty());
+
+        // Text that isn't valid is reported, and leaves the tree alone
+        *contents.borrow_mut() = "[true, }".to_string();
+        type_keys(&mut editor, "\"+p");
+        assert!(editor
+            .message()
+            .unwrap()
+            .starts_with("Couldn't paste the clipboard: line 1, column 8"));
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[{"k": true}, {"k": true}, null]"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![1]);
+    }
+
+    #[test]
+    fn sibling_jumps() {
+        // A wide array of the numbers 0 to 19, with an array of its own at index 3
+        let mut values: Vec<TestJSON> = (0..20).map(|i| TestJSON::Number(i.to_string())).collect();
+        values[3] = TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(TestJSON::Array(values).build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.handle_key(Key::Char('H'));
+        assert_eq!(editor.message(), Some("The root has no siblings"));
+        editor.handle_key(Key::Char('c'));
+        for (keys, cursor) in &[
+            ("}", vec![5]),
+            ("2}", vec![15]),
+            ("}", vec![19]),
+            ("{", vec![14]),
+            ("H", vec![0]),
+            ("L", vec![19]),
+            ("7G", vec![6]),
+            ("G", vec![19]),
+            ("99G", vec![19]),
+            ("0G", vec![0]),
+            ("3{", vec![0]),
+            // The jumps work among the siblings of nested nodes too
+            ("4Gc", vec![3, 0]),
+            ("L", vec![3, 1]),
+            ("}", vec![3, 1]),
+            ("gg", vec![]),
+        ] {
+            type_keys(&mut editor, keys);
+            assert_eq!(
+                editor.buffer().tree.cursor_path(),
+                *cursor,
+                "after {:?}",
+                keys
+            );
+        }
+        // Like the other sibling motions, jumps extend a visual selection
+        type_keys(&mut editor, "cvL");
+        assert_eq!(editor.selected_nodes().len(), 20);
+    }
+
+    #[test]
+    fn open_sibling_commands() {
+        let tree: Spec<JSON<Index>> =
+            Spec::from_tree(TestJSON::Array(vec![TestJSON::Null]).build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.handle_key(Key::Char('c'));
+        // Each new node is selected, so the next one goes after it
+        for (keys, text, cursor) in &[
+            ("ot", "[null, true]", 1),
+            ("of", "[null, true, false]", 2),
+            ("Oa", "[null, true, [], false]", 2),
+            ("Oo", "[null, true, {}, [], false]", 2),
+        ] {
+            type_keys(&mut editor, keys);
+            assert_eq!(editor.buffer().tree.to_text(&JSONFormat::Compact), *text);
+            assert_eq!(editor.buffer().tree.cursor_path(), vec![*cursor]);
+        }
+        // Values opened next to fields get a placeholder key
+        type_keys(&mut editor, "iiotOs");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[null, true, {"key": true, "key": "", "key": true}, [], false]"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2, 1]);
+
+        // Arrays can't contain fields
+        type_keys(&mut editor, "poi");
+        assert_eq!(
+            editor.message(),
+            Some("Cannot insert 'i' next to this node: 'i' isn't a node that array nodes can contain")
+        );
+        // The root has no siblings
+        type_keys(&mut editor, "pot");
+        assert_eq!(
+            editor.message(),
+            Some("Cannot insert 't' next to this node: the root has no siblings")
+        );
+    }
+
+    #[test]
+    fn duplicate_nodes() {
+        let tree = TestJSON::Array(vec![TestJSON::Object(vec![(
+            "value".to_string(),
+            TestJSON::Array(vec![TestJSON::True]),
+        )])]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        type_keys(&mut editor, "D");
+        assert_eq!(
+            editor.message(),
+            Some("Cannot duplicate this node: the root has no siblings")
+        );
+        // The copy is selected, and its key is changed so that it doesn't clash
+        type_keys(&mut editor, "ccD");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[{"value": [true], "value_copy": [true]}]"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0, 1]);
+        // Editing the copy leaves the original alone
+        type_keys(&mut editor, "cnrf");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[{"value": [true], "value_copy": false}]"#
+        );
+        // Duplicating is a single change
+        type_keys(&mut editor, "uu");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[{"value": [true]}]"#
+        );
+        type_keys(&mut editor, "ggcD");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[{"value": [true]}, {"value": [true]}]"#
+        );
+    }
+
+    #[test]
+    fn searching_names() {
+        let tree = TestJSON::Array(vec![
+            TestJSON::Str("enabled".to_string()),
+            TestJSON::Object(vec![("enabled".to_string(), TestJSON::False)]),
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        type_keys(&mut editor, "/enabled");
+        editor.handle_key(Key::Enter);
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        // Fields are found by their key's name before the key itself
+        editor.handle_key(Key::Char('m'));
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![1, 0]);
+        assert!(editor.status_bar("", 100).contains("root[1].enabled"));
+        editor.handle_key(Key::Char('m'));
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![1, 0, 0]);
+        editor.handle_key(Key::Char('M'));
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![1, 0]);
+    }
+
+    #[test]
+    fn marks() {
+        let tree = TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::Object(vec![("a".to_string(), TestJSON::False)]),
+            TestJSON::Null,
+        ]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        type_keys(&mut editor, "g'");
+        assert_eq!(editor.message(), Some("No marks are set"));
+        type_keys(&mut editor, "cngma");
+        assert_eq!(editor.message(), Some("Set mark 'a'"));
+        // Edits elsewhere in the tree don't lose the mark, even if they move it
+        type_keys(&mut editor, "nrtpcOf");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[false, true, {"a": false}, true]"#
+        );
+        type_keys(&mut editor, "'a");
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2]);
+        type_keys(&mut editor, "g'");
+        assert_eq!(editor.message(), Some("Marks: 'a root[2]"));
+
+        // Deleting the marked node stops the mark from jumping anywhere
+        type_keys(&mut editor, "xgg'a");
+        assert_eq!(editor.message(), Some("Mark 'a' points to a deleted node"));
+        assert_eq!(editor.buffer().tree.cursor_path(), Vec::<usize>::new());
+        type_keys(&mut editor, "g'");
+        assert_eq!(editor.message(), Some("Marks: 'a (deleted)"));
+        // ...until the deletion is undone, and then the mark survives garbage collection
+        type_keys(&mut editor, "uggc'a");
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2]);
+        // Editing inside the marked node copies it, and the mark follows it there and back
+        assert!(editor.buffer_mut().tree.move_to_path(&[2, 0, 1]));
+        type_keys(&mut editor, "rtgg'a");
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2]);
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"[false, true, {"a": true}, true]"#
+        );
+        type_keys(&mut editor, "ugg'a");
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2]);
+
+        type_keys(&mut editor, "'b");
+        assert_eq!(editor.message(), Some("Mark 'b' isn't set"));
+        type_keys(&mut editor, "gm1");
+        assert_eq!(editor.message(), Some("Marks must be letters, not '1'"));
+        // Loading a new tree clears the marks
+        editor.load_tree(Spec::from_tree(TestJSON::Null.build_node_map()));
+        type_keys(&mut editor, "'a");
+        assert_eq!(editor.message(), Some("Mark 'a' isn't set"));
+    }
+
+    #[test]
+    fn sort_children() {
+        let string = |s: &str| TestJSON::Str(s.to_string());
+        let tree = TestJSON::Object(vec![
+            ("b".to_string(), TestJSON::True),
+            ("a".to_string(), TestJSON::False),
+            (
+                "c".to_string(),
+                TestJSON::Array(vec![string("y"), string("x")]),
+            ),
+            ("a".to_string(), TestJSON::Null),
+        ]);
+        let original = r#"{"b": true, "a": false, "c": ["y", "x"], "a": null}"#;
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        // Entries with the same key keep their order
+        type_keys(&mut editor, "=s");
+        assert_eq!(editor.message(), Some("Sorted 4 children"));
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"{"a": false, "a": null, "b": true, "c": ["y", "x"]}"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), Vec::<usize>::new());
+        type_keys(&mut editor, "=s");
+        assert_eq!(editor.message(), Some("Already sorted"));
+        // The whole sort is undone at once
+        type_keys(&mut editor, "u");
+        assert_eq!(editor.buffer().tree.to_text(&JSONFormat::Compact), original);
+
+        // Arrays are only sorted when asked to
+        type_keys(&mut editor, "cnncn=s");
+        assert_eq!(
+            editor.message(),
+            Some("The order of these children may matter (press =S to sort them by their text)")
+        );
+        assert_eq!(editor.buffer().tree.to_text(&JSONFormat::Compact), original);
+        type_keys(&mut editor, "=S");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            r#"{"b": true, "a": false, "c": ["x", "y"], "a": null}"#
+        );
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![2, 1]);
+        // Nodes without children have nothing to sort
+        type_keys(&mut editor, "c=S");
+        assert_eq!(editor.message(), Some("Already sorted"));
+    }
+
+    #[test]
+    fn repeated_edits_undo_at_once() {
+        let tree = TestJSON::Array(vec![TestJSON::True; 5]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let text = |editor: &Editor<_, _, Spec<JSON<Index>>>| {
+            editor.buffer().tree.to_text(&JSONFormat::Compact)
+        };
+        type_keys(&mut editor, "cx3.");
+        assert_eq!(text(&editor), "[true]");
+        // Repeating an edit three times is one step of the undo history
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "[true, true, true, true]");
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "[true, true, true, true, true]");
+        type_keys(&mut editor, "R");
+        assert_eq!(text(&editor), "[true, true, true, true]");
+    }
+
+    #[test]
+    fn macros_undo_at_once() {
+        let tree = TestJSON::Array(vec![TestJSON::True; 5]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let text = |editor: &Editor<_, _, Spec<JSON<Index>>>| {
+            editor.buffer().tree.to_text(&JSONFormat::Compact)
+        };
+        type_keys(&mut editor, "cQaxQ");
+        assert_eq!(text(&editor), "[true, true, true, true]");
+        type_keys(&mut editor, "3@a");
+        assert_eq!(text(&editor), "[true]");
+        // Playing a macro three times is one step of the undo history
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "[true, true, true, true]");
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "[true, true, true, true, true]");
+        type_keys(&mut editor, "RR");
+        assert_eq!(text(&editor), "[true]");
+    }
+
+    #[test]
+    fn failed_edits_change_nothing() {
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        type_keys(&mut editor, "crzuc");
+        let position = editor.buffer().tree.history_position();
+        let log_len = editor.log.len();
+        // `true` can't have children, so this edit fails and is rolled back
+        type_keys(&mut editor, "it");
+        assert!(editor.log[log_len..]
+            .iter()
+            .any(|(level, _)| *level == LogLevel::Warning));
+        assert_eq!(editor.buffer().tree.history_position(), position);
+        assert_eq!(editor.buffer().tree.cursor_path(), vec![0]);
+        // The edit that was undone can still be redone
+        type_keys(&mut editor, "R");
+        assert_eq!(
+            editor.buffer().tree.to_text(&JSONFormat::Compact),
+            "[null, false]"
+        );
+    }
+
+    #[test]
+    fn unchanged_trees_are_not_dirty() {
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        // Nothing to undo or redo, and an edit that fails
+        for keys in &["u", "R", "cit"] {
+            type_keys(&mut editor, keys);
+            assert!(!editor.is_dirty(), "{}", keys);
+        }
+        type_keys(&mut editor, "rz");
+        assert!(editor.is_dirty());
+        // Undoing back to the saved version has no unsaved changes
+        type_keys(&mut editor, "u");
+        assert!(!editor.is_dirty());
+        type_keys(&mut editor, "R");
+        assert!(editor.is_dirty());
+        type_keys(&mut editor, "u");
+        assert!(editor.perform_action(Action::Quit));
+    }
+
+    #[test]
+    fn rejected_replacements() {
+        let tree = TestJSON::Object(vec![("k".to_string(), TestJSON::True)]);
+        let tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let mut edit = |keys: &str| {
+            type_keys(&mut editor, keys);
+            (
+                editor.message().map(str::to_string),
+                editor.buffer().tree.to_text(&JSONFormat::Compact),
+            )
+        };
+        // Select the key of the field
+        assert_eq!(edit("cc").1, r#"{"k": true}"#);
+        assert_eq!(
+            edit("rq"),
+            (
+                Some("'q' is not a valid node type".to_string()),
+                r#"{"k": true}"#.to_string()
+            )
+        );
+        assert_eq!(
+            edit("rt"),
+            (
+                Some("Cannot replace with 't': the key of a field must be a string".to_string()),
+                r#"{"k": true}"#.to_string()
+            )
+        );
+        // The value can be anything
+        assert_eq!(edit("nrn").1, r#"{"k": 0}"#);
+    }
+
+    #[test]
+    fn edits_lisp() {
+        let tree = TestLisp::List(vec![
+            TestLisp::Symbol("f".to_string()),
+            TestLisp::Number("1".to_string()),
+        ]);
+        let tree: Spec<Lisp<Index>> = Spec::from_tree(tree.build_node_map());
+        let mut editor = test_editor(tree, LispFormat::Compact);
+        let mut edit = |keys: &str| {
+            type_keys(&mut editor, keys);
+            editor.buffer().tree.to_text(&LispFormat::Compact)
+        };
+        // Replace the number with a string
+        assert_eq!(edit("cnrs"), r#"(f "")"#);
+        // Add a symbol and an empty list to the end of the root list
+        assert_eq!(edit("paapal"), r#"(f "" nil ())"#);
+        // Atoms can't have children
+        assert_eq!(edit("ggcia"), r#"(f "" nil ())"#);
+        assert_eq!(
+            editor.buffer().tree.to_text(&LispFormat::Indented),
+            r#"(f
+    ""
+    nil
+    ())"#
+        );
+    }
+
+    #[test]
+    fn edits_expr() {
+        let tree: Spec<Expr<Index>> = Spec::from_tree(Expr::parse_into("1 + 2").unwrap());
+        let frontend = Arc::new(TestFrontend::new(160, 24, vec![]));
+        let mut editor = Editor::with_frontend(tree, ExprFormat::Spaced, frontend.clone());
+        let edit = |editor: &mut Editor<_, _, Spec<Expr<Index>>>, keys: &str| {
+            type_keys(editor, keys);
+            editor.buffer().tree.to_text(&ExprFormat::Spaced)
+        };
+        // Replacing an operator keeps its operands, and brackets appear when they're needed
+        assert_eq!(edit(&mut editor, "r*"), "1 * 2");
+        assert_eq!(edit(&mut editor, "cW+"), "(1 + 0) * 2");
+        assert_eq!(edit(&mut editor, "cLri"), "(1 + x) * 2");
+        // Operands can be swapped, but not deleted
+        assert_eq!(edit(&mut editor, "x"), "(1 + x) * 2");
+        assert_eq!(edit(&mut editor, "p>"), "2 * (1 + x)");
+        assert_eq!(edit(&mut editor, "ggr-"), "2 - (1 + x)");
+        assert_eq!(edit(&mut editor, "r~"), "-0");
+        assert_eq!(edit(&mut editor, "u"), "2 - (1 + x)");
+        // The screen shows the same brackets as the text
+        editor.update_display().unwrap();
+        assert!(frontend.screen()[0].starts_with("2 - (1 + x)  "));
+    }
+
+    #[test]
+    fn click_positions() {
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::Object(vec![("好".to_string(), TestJSON::False)]),
+            ])
+            .build_node_map(),
+        );
+        let tokens_for = |_, node: &JSON<Index>| node.display_tokens(&JSONFormat::Pretty);
+        let tokens = flat_tokens_for_lines(&tree, tree.root(), &tokens_for, 100);
+        let children = tree.root_node().children().to_vec();
+        let field = tree.get_node(children[1]).unwrap().children()[0];
+        let [key, value] = match tree.get_node(field).unwrap().children() {
+            [key, value] => [*key, *value],
+            _ => unreachable!(),
+        };
+        // The text is:
+        // [
+        //     true,
+        //     {
+        //         "好": false
+        //     }
+        // ]
+        // but it's drawn scrolled by one line and shifted right by a gutter of 3 columns
+        let view = TreeView {
+            top_row: 0,
+            left: 3,
+            right: 80,
+            first_line: 1,
+            first_col: 0,
+            max_lines: 10,
+        };
+        let (texts, num_lines) =
+            position_tokens(&tokens, &view, LineStart::default(), INDENT_WIDTH);
+        assert_eq!(num_lines, 6);
+        assert_eq!(node_at(&texts, 0, 7), Some(children[0]));
+        assert_eq!(node_at(&texts, 0, 10), Some(children[0]));
+        // The comma belongs to the array, and the space after it to nothing
+        assert_eq!(node_at(&texts, 0, 11), Some(tree.root()));
+        assert_eq!(node_at(&texts, 0, 12), None);
+        assert_eq!(node_at(&texts, 0, 2), None);
+        assert_eq!(node_at(&texts, 1, 7), Some(children[1]));
+        // The wide character takes two columns, which pushes the rest of the line along
+        assert_eq!(node_at(&texts, 2, 11), Some(key));
+        assert_eq!(node_at(&texts, 2, 14), Some(key));
+        assert_eq!(node_at(&texts, 2, 15), Some(field));
+        assert_eq!(node_at(&texts, 2, 16), Some(field));
+        assert_eq!(node_at(&texts, 2, 17), Some(value));
+        assert_eq!(node_at(&texts, 4, 3), Some(tree.root()));
+        // The status bar and anything off the end of the text are empty
+        assert_eq!(node_at(&texts, 5, 3), None);
+    }
+
+    #[test]
+    fn clipping_long_lines() {
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Array(vec![
+                TestJSON::Str("好好".to_string()),
+                TestJSON::True,
+                TestJSON::Str("abcdefghij".to_string()),
+            ])
+            .build_node_map(),
+        );
+        let tokens_for = |_, node: &JSON<Index>| node.display_tokens(&JSONFormat::Compact);
+        let tokens = flat_tokens_for_lines(&tree, tree.root(), &tokens_for, 100);
+        let children = tree.root_node().children().to_vec();
+        // Returns the texts drawn when the view is `width` columns wide (after a gutter of 2
+        // columns) and scrolled by `first_col` columns
+        let draw = |first_col: usize, width: usize| {
+            let view = TreeView {
+                top_row: 0,
+                left: 2,
+                right: 2 + width,
+                first_line: 0,
+                first_col,
+                max_lines: 1,
+            };
+            position_tokens(&tokens, &view, LineStart::default(), INDENT_WIDTH).0
+        };
+        let line = |texts: &[ScreenText<Index>]| {
+            let mut line = String::new();
+            for text in texts {
+                line.push_str(&" ".repeat(text.col - 2 - display_width(&line)));
+                line.push_str(&text.text);
+            }
+            line
+        };
+        // The whole text is `["好好", true, "abcdefghij"]`, which is 28 columns wide
+        assert_eq!(line(&draw(0, 100)), r#"["好好", true, "abcdefghij"]"#);
+        for (first_col, expected) in &[
+            (0, r#"["好好", …"#),
+            (3, r#"…好", tru…"#),
+            // Half of a wide character isn't drawn, so it leaves a gap instead
+            (4, r#"… ", true…"#),
+            (20, r#"…fghij"]"#),
+            // A line that is scrolled entirely off the screen still shows that it's there
+            (30, "…"),
+        ] {
+            assert_eq!(
+                &line(&draw(*first_col, 10)),
+                expected,
+                "scrolled by {}",
+                first_col
+            );
+        }
+        // The markers belong to the nodes that they hide, and every other text is only as wide
+        // as what is drawn of it
+        assert_eq!(node_at(&draw(0, 10), 0, 11), Some(children[1]));
+        let texts = draw(4, 10);
+        assert_eq!(node_at(&texts, 0, 2), Some(children[0]));
+        assert_eq!(node_at(&texts, 0, 3), None);
+        assert_eq!(node_at(&texts, 0, 4), Some(children[0]));
+        assert_eq!(node_at(&texts, 0, 5), Some(tree.root()));
+        assert_eq!(node_at(&texts, 0, 6), None);
+        assert_eq!(node_at(&texts, 0, 7), Some(children[1]));
+        assert_eq!(node_at(&texts, 0, 10), Some(children[1]));
+        // This marker hides the comma after `true`
+        assert_eq!(node_at(&texts, 0, 11), Some(tree.root()));
+        assert_eq!(node_at(&texts, 0, 12), None);
+        // There's only room for one marker in a single column
+        assert_eq!(line(&draw(3, 1)), "…");
+        assert_eq!(node_at(&draw(3, 1), 0, 2), Some(children[0]));
+        assert!(draw(3, 0).is_empty());
+    }
+
+    #[test]
+    fn command_hints() {
+        let table = CommandTable::default();
+        let hint = |command: &str, node: &JSON<Index>| command_hint(&table, command, node);
+        assert_eq!(hint("", &JSON::True), None);
+        assert_eq!(hint("rt", &JSON::True), None);
+        assert_eq!(
+            hint("r", &JSON::True).unwrap(),
+            "t:true f:false z:null a:empty array o:empty object s:empty string n:zero"
+        );
+        // Fields can only be inserted into objects
+        assert_eq!(
+            hint("i", &JSON::Object(vec![])).unwrap(),
+            "i:object field (with a placeholder key)"
+        );
+        assert_eq!(hint("i", &JSON::True).unwrap(), "");
+        assert_eq!(hint("Q", &JSON::True).unwrap(), "<register>");
+        assert_eq!(hint("gp", &JSON::True).unwrap().matches(':').count(), 8);
+        assert!(hint("g", &JSON::True).unwrap().contains("gp:ancestor"));
+    }
+
+    #[test]
+    fn message_colours() {
+        assert_eq!(message_attr(&LogLevel::Info), Attr::default());
+        assert_eq!(message_attr(&LogLevel::Warning).fg, Color::YELLOW);
+        assert_eq!(message_attr(&LogLevel::Error).fg, Color::RED);
+    }
+
+    #[test]
+    fn gutter_widths() {
+        assert_eq!(gutter_width(0), 2);
+        assert_eq!(gutter_width(9), 2);
+        assert_eq!(gutter_width(10), 3);
+        assert_eq!(gutter_width(99), 3);
+        assert_eq!(gutter_width(1000), 5);
+    }
+
+    #[test]
+    fn layout_on_small_terminals() {
+        assert_eq!(
+            layout(80, 24, 2),
+            Some(Layout {
+                tree_height: 23,
+                tree_width: 80,
+                log_col: 40,
+                bar_row: 23,
+                bar_width: 72,
+                command_col: 73,
+            })
+        );
+        // Commands are measured in columns, so wide characters take up more space
+        assert_eq!(
+            layout(80, 24, display_width("r好")).unwrap().command_col,
+            72
+        );
+        // Long commands push the status bar out rather than going off the screen
+        assert_eq!(
+            layout(12, 2, 20),
+            Some(Layout {
+                tree_height: 1,
+                tree_width: 12,
+                log_col: 6,
+                bar_row: 1,
+                bar_width: 0,
+                command_col: 0,
+            })
+        );
+        // Nothing useful fits on tiny terminals, but nothing should panic either
+        for (width, height) in &[(5, 24), (80, 1), (0, 0), (11, 100)] {
+            assert_eq!(layout(*width, *height, 3), None);
+        }
+    }
+
+    #[test]
+    fn status_bar_truncation() {
+        let suffix = " (1/2) [+]";
+        assert_eq!(
+            fit_status_bar("true ", "root[2].value", suffix, 100),
+            "true root[2].value (1/2) [+]"
+        );
+        // Long paths lose their start first
+        assert_eq!(
+            fit_status_bar("true ", "root[2].value", suffix, 23),
+            "true ...value (1/2) [+]"
+        );
+        assert_eq!(
+            fit_status_bar("true ", "root[2].value", suffix, 19),
+            "true ...e (1/2) [+]"
+        );
+        // Then everything else is cut off from the end
+        assert_eq!(
+            fit_status_bar("true ", "root[2].value", suffix, 17),
+            "true root[2].valu"
+        );
+        assert_eq!(fit_status_bar("true ", "root", suffix, 0), "");
+        // Wide characters count as two columns, and are never split
+        assert_eq!(
+            fit_status_bar("\"s\" ", "root.好好好", "", 13),
+            "\"s\" ...好好好"
+        );
+        assert_eq!(
+            fit_status_bar("\"s\" ", "root.好好好", "", 12),
+            "\"s\" ...好好"
+        );
+        assert_eq!(fit_status_bar("好好 ", "root", " (1/1)", 4), "好好");
+        assert_eq!(fit_status_bar("好好 ", "root", " (1/1)", 3), "好");
+    }
+
+    #[test]
+    fn scroll_to_cursor() {
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Array((0..500).map(|_| TestJSON::True).collect()).build_node_map(),
+        );
+        let line_of_child = |index: usize| {
+            let child = tree.root_node().children()[index];
+            let tokens_for = |_, node: &JSON<Index>| node.display_tokens(&JSONFormat::Pretty);
+            line_of_node(&tree, tree.root(), child, &tokens_for).unwrap()
+        };
+        // The first line is the opening bracket
+        assert_eq!(line_of_child(0), 1);
+        assert_eq!(line_of_child(250), 251);
+
+        // Lines that are already visible (outside the margins) don't scroll the screen
+        assert_eq!(clamp_scroll_offset(0, 1, 40), 0);
+        assert_eq!(clamp_scroll_offset(230, 251, 40), 230);
+        // Moving down past the bottom margin scrolls just enough to keep the margin
+        assert_eq!(clamp_scroll_offset(0, 251, 40), 215);
+        assert_eq!(clamp_scroll_offset(0, 499, 40), 463);
+        // Moving up past the top margin does the same
+        assert_eq!(clamp_scroll_offset(300, 251, 40), 248);
+        assert_eq!(clamp_scroll_offset(300, 1, 40), 0);
+        // Shrinking the terminal should clamp the offset (and the margin, on tiny screens)
+        assert_eq!(clamp_scroll_offset(230, 251, 10), 245);
+        assert_eq!(clamp_scroll_offset(0, 251, 1), 251);
+        assert_eq!(clamp_scroll_offset(0, 251, 0), 251);
+        assert_eq!(clamp_scroll_offset(0, 251, 3), 250);
+    }
+
+    #[test]
+    fn horizontal_scrolling() {
+        // Nodes that are already on the screen (before the margin) don't scroll it
+        assert_eq!(clamp_horizontal_scroll(0, 10..14, 40), 0);
+        assert_eq!(clamp_horizontal_scroll(20, 30..34, 40), 20);
+        // Nodes past the right edge scroll the screen to show the margin
+        assert_eq!(clamp_horizontal_scroll(0, 50..54, 40), 19);
+        // Nodes past the left edge scroll it back to show the end of the node
+        assert_eq!(clamp_horizontal_scroll(100, 50..54, 40), 45);
+        assert_eq!(clamp_horizontal_scroll(100, 0..1, 40), 0);
+        // The screen can be scrolled along a node that is wider than it
+        assert_eq!(clamp_horizontal_scroll(100, 50..200, 40), 100);
+        assert_eq!(clamp_horizontal_scroll(0, 50..200, 40), 19);
+        // Tiny screens have smaller margins, but nothing panics
+        assert_eq!(clamp_horizontal_scroll(0, 50..54, 5), 48);
+        assert_eq!(clamp_horizontal_scroll(0, 50..54, 0), 50);
+
+        // Moving the cursor along a long line scrolls the screen to follow it
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Array((0..100).map(|i| TestJSON::Str(i.to_string())).collect())
+                .build_node_map(),
+        );
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.handle_key(Key::Char('c'));
+        editor.scroll_to_cursor();
+        assert_eq!(editor.buffer().horizontal_scroll, 0);
+        // `["0", "1", ...` puts the 50th child at column 1 + 5 * 10 + 6 * 39 = 285
+        type_keys(&mut editor, "50G");
+        editor.scroll_to_cursor();
+        assert_eq!(editor.buffer().horizontal_scroll, 214);
+        // The arrow keys and `zH`/`zL` scroll by hand, but not so far that the cursor's node
+        // leaves the screen
+        let mut scroll = |keys: &[Key]| {
+            for key in keys {
+                editor.handle_key(*key);
+            }
+            editor.scroll_to_cursor();
+            editor.buffer().horizontal_scroll
+        };
+        assert_eq!(scroll(&[Key::Right]), 215);
+        assert_eq!(scroll(&[Key::Char('z'), Key::Char('L')]), 255);
+        assert_eq!(scroll(&[Key::Char('z'), Key::Char('L')]), 280);
+        assert_eq!(scroll(&[Key::Char('z'), Key::Char('H')]), 240);
+        assert_eq!(scroll(&[Key::Left]), 239);
+        assert_eq!(scroll(&[Key::Char('z'), Key::Char('H')]), 214);
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Object(vec![
+                ("a".to_string(), TestJSON::Number("1".to_string())),
+                (
+                    "b".to_string(),
+                    TestJSON::Object(vec![
+                        ("x".to_string(), TestJSON::True),
+                        ("x".to_string(), TestJSON::False),
+                    ]),
+                ),
+                ("a".to_string(), TestJSON::Number("2".to_string())),
+            ])
+            .build_node_map(),
+        );
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let keys = |editor: &mut Editor<_, _, _>| -> Vec<String> {
+            editor
+                .duplicate_keys()
+                .into_iter()
+                .map(|(_, k)| k)
+                .collect()
+        };
+
+        assert_eq!(keys(&mut editor), ["a", "x", "x", "a"]);
+        editor.buffer_mut().check_duplicate_keys();
+        assert!(editor.status_bar("", 100).contains("4 duplicate keys"));
+        // Jumping visits every entry in the order of the text, wrapping round at either end
+        for (keys, path) in [
+            ("]d", vec![0]),
+            ("]d", vec![1, 1, 0]),
+            ("]d", vec![1, 1, 1]),
+            ("]d", vec![2]),
+            ("]d", vec![0]),
+            ("[d", vec![2]),
+            ("[d", vec![1, 1, 1]),
+        ] {
+            type_keys(&mut editor, keys);
+            assert_eq!(editor.buffer().tree.cursor_path(), path);
+        }
+
+        // Renaming one of the keys fixes both of its entries
+        type_keys(&mut editor, "k");
+        editor.handle_key(Key::Backspace);
+        type_keys(&mut editor, "y");
+        editor.handle_key(Key::ESC);
+        assert_eq!(keys(&mut editor), ["a", "a"]);
+        editor.buffer_mut().check_duplicate_keys();
+        assert!(editor.status_bar("", 100).contains("2 duplicate keys"));
+        type_keys(&mut editor, "]dx");
+        assert!(keys(&mut editor).is_empty());
+        editor.buffer_mut().check_duplicate_keys();
+        assert!(!editor.status_bar("", 100).contains("duplicate"));
+        type_keys(&mut editor, "]d");
+        assert_eq!(editor.message(), Some("No keys are duplicated"));
+        // Undoing brings the old records (and their duplicates) back
+        type_keys(&mut editor, "u");
+        assert_eq!(keys(&mut editor), ["a", "a"]);
+        // Pasting a copy of an entry duplicates its key
+        type_keys(&mut editor, "ggcncGcnyyPn");
+        assert_eq!(keys(&mut editor), ["a", "y", "y", "a"]);
+        // Garbage collection moves the nodes, so everything is checked again
+        type_keys(&mut editor, "gc");
+        assert_eq!(keys(&mut editor), ["a", "y", "y", "a"]);
+        // The keys that were checked incrementally are the same as those of a complete check
+        let tree = &editor.buffer().tree;
+        let expected = super::duplicate_keys::duplicate_keys(tree, tree.root());
+        assert_eq!(editor.duplicate_keys(), expected);
+    }
+
+    #[test]
+    fn named_registers() {
+        let numbers = (0..100).map(|i| TestJSON::Number(i.to_string())).collect();
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+                TestJSON::Array(numbers),
+            ])
+            .build_node_map(),
+        );
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        editor.set_clipboard_format(JSONFormat::Compact, Box::new(|_| Err(String::new())));
+        let overlay_lines =
+            |editor: &Editor<_, _, _>| editor.overlay.as_ref().map(|o| o.lines.clone());
+
+        type_keys(&mut editor, ":registers");
+        editor.handle_key(Key::Enter);
+        assert_eq!(editor.message(), Some("Every register is empty"));
+        type_keys(&mut editor, "c\"ay\"cp");
+        assert_eq!(editor.message(), Some("register c is empty"));
+        type_keys(&mut editor, "\"1y");
+        assert_eq!(editor.message(), Some("'1' is not a register (use a to z)"));
+        // Yanking into a register overwrites what was there
+        type_keys(&mut editor, "\"byn\"by");
+        type_keys(&mut editor, "\"ap");
+        assert_eq!(
+            editor.text_to_save(),
+            "[true, false, true, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, \
+             18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, \
+             39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, \
+             60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, \
+             81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98, 99]]"
+        );
+
+        // Only the preview of a huge subtree is cut short, not the subtree itself
+        type_keys(&mut editor, "n\"zyu");
+        type_keys(&mut editor, ":registers");
+        editor.handle_key(Key::Enter);
+        let preview = "[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, …";
+        assert_eq!(
+            overlay_lines(&editor).unwrap(),
+            vec![
+                "Registers:".to_string(),
+                format!("  \"\"  {}", preview),
+                "  \"a  true".to_string(),
+                "  \"b  false".to_string(),
+                format!("  \"z  {}", preview),
+            ]
+        );
+        // The registers don't refer to the tree, so survive garbage collection
+        type_keys(&mut editor, "qgcH\"zp");
+        let text = editor.text_to_save();
+        assert!(text.starts_with("[true, [0, 1, 2, 3, "));
+        assert_eq!(text.matches("98, 99]").count(), 2);
+    }
+
+    #[test]
+    fn showing_changes() {
+        let tree: Spec<JSON<Index>> = Spec::from_tree(
+            TestJSON::Array(vec![TestJSON::True, TestJSON::False]).build_node_map(),
+        );
+        let mut editor = test_editor(tree, JSONFormat::Compact);
+        let overlay_lines =
+            |editor: &Editor<_, _, _>| editor.overlay.as_ref().map(|o| o.lines.clone());
+        type_keys(&mut editor, "du");
+        assert_eq!(editor.message(), Some("No changes since the last save"));
+        assert_eq!(overlay_lines(&editor), None);
+
+        type_keys(&mut editor, "crfDdu");
+        assert_eq!(
+            overlay_lines(&editor).unwrap(),
+            vec![
+                "2 changes since the last save:",
+                "  replaced root[0]: true with false",
+                "  added root[1]: false",
+            ]
+        );
+        // Any key closes the list
+        type_keys(&mut editor, "q");
+        assert_eq!(overlay_lines(&editor), None);
+        // The duplicate is equal to its sibling, but is the node that was added
+        type_keys(&mut editor, "1du");
+        assert_eq!(
+            overlay_lines(&editor).unwrap(),
+            vec!["1 change since 1 version ago:", "  added root[1]: false"]
+        );
+        type_keys(&mut editor, "q3du");
+        assert_eq!(
+            editor.message(),
+            Some("The undo history only goes back 2 versions")
+        );
+
+        // Saving moves the version that is compared against
+        editor.mark_saved();
+        type_keys(&mut editor, "uudu");
+        assert_eq!(
+            overlay_lines(&editor).unwrap(),
+            vec![
+                "2 changes since the last save:",
+                "  replaced root[0]: false with true",
+                "  removed root[1]: false",
+            ]
+        );
+        // Editing after undoing past the save discards the saved version
+        type_keys(&mut editor, "qDdu");
+        assert_eq!(
+            editor.message(),
+            Some("The saved version is no longer in the undo history")
+        );
+    }
+
+    #[test]
+    fn help_screen() {
+        let lines = help_lines::<Index, JSON<Index>>(&CommandTable::default());
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("  ?") && l.contains("help")));
+        assert!(lines.iter().any(|l| l.starts_with("  [count]x")));
+        // The node chars should come from the JSON spec
+        assert!(lines
+            .iter()
+            .any(|l| l.trim() == "o               empty object"));
+
+        assert_eq!(overlay_page_count(0, 10), 1);
+        assert_eq!(overlay_page_count(10, 10), 1);
+        assert_eq!(overlay_page_count(11, 10), 2);
+        // Tiny terminals should still show one line per page
+        assert_eq!(overlay_page_count(3, 0), 3);
+    }
+
+    #[test]
+    fn write_to_file() {
+        let dir = std::env::temp_dir().join(format!("sapling-write-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tree.json");
+
+        let tree = Spec::<JSON<Index>>::new();
+        let text = text_to_save(&tree, &JSONFormat::Pretty, &IndentStyle::default());
+        write_atomically(&path, &text).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            tree.to_text(&JSONFormat::Pretty)
+        );
+        // Failures should be returned rather than panicking
+        assert!(write_atomically(&dir.join("missing").join("tree.json"), &text).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_command_complete() {
+        for (command, expected_effect) in &[
+            ("q!", Action::ForceQuit),
+            ("?", Action::ShowHelp),
+            ("s", Action::EditLiteral),
+            ("/", Action::StartSearch),
+            (":", Action::StartExCommand),
+            ("m", Action::SearchNext),
+            ("M", Action::SearchPrev),
+            ("]d", Action::NextDuplicateKey),
+            ("[d", Action::PrevDuplicateKey),
+            ("gmq", Action::SetMark('q')),
+            ("'q", Action::JumpToMark('q')),
+            ("g'", Action::ListMarks),
+            (".", Action::RepeatLastEdit(1)),
+            ("3.", Action::RepeatLastEdit(3)),
+            ("qx", Action::Quit),
+            ("q489flshb", Action::Quit),
+            ("wq", Action::WriteAndQuit),
+            ("wx", Action::Write),
+            ("x", Action::Delete(1)),
+            ("2x", Action::Delete(2)),
+            ("0x", Action::Delete(1)),
+            ("X", Action::Undefined),
+            ("pajlbsi", Action::MoveToParent),
+            ("Pxx", Action::Undefined),
+            ("Qsx", Action::StartRecording('s')),
+            ("@a", Action::PlayMacro('a', 1)),
+            ("12@q", Action::PlayMacro('q', 12)),
+            ("3Qa", Action::Undefined),
+            ("ra", Action::Replace('a')),
+            ("rg", Action::Replace('g')),
+            ("iX", Action::InsertChild('X', 0)),
+            ("iP", Action::InsertChild('P', 0)),
+            ("3it", Action::InsertChild('t', 3)),
+            ("12ia", Action::InsertChild('a', 12)),
+            ("at", Action::AppendChild('t')),
+            ("ai", Action::AppendChild('i')),
+            ("2at", Action::Undefined),
+            ("3u", Action::Undefined),
+            ("J", Action::MergeWithNextSibling),
+            (">", Action::SwapWithNextSibling),
+            ("Wa", Action::Wrap('a')),
+            ("U", Action::Unwrap),
+            ("F", Action::CycleFormatStyle),
+            ("#", Action::ToggleLineNumbers),
+            ("gR", Action::ToggleReadOnly),
+            ("<", Action::SwapWithPrevSibling),
+            ("gn", Action::MoveToSameKeyInNextRecord),
+            ("grtf", Action::ReplaceAll('t', 'f')),
+            ("gdtf", Action::ReplaceAllDryRun('t', 'f')),
+            ("yq", Action::CopyRecord(ExportFormat::QueryString)),
+            ("ye", Action::CopyRecord(ExportFormat::EnvLines)),
+            ("yx", Action::Undefined),
+            ("yy", Action::Yank(1)),
+            ("3yy", Action::Yank(3)),
+            ("ot", Action::InsertNextSibling('t')),
+            ("Oi", Action::InsertPrevSibling('i')),
+            ("v", Action::StartVisual),
+            ("Pn", Action::Paste(PastePosition::After)),
+            ("PN", Action::Paste(PastePosition::Before)),
+            ("Pc", Action::Paste(PastePosition::Child)),
+            ("D", Action::Duplicate),
+            ("\"+y", Action::CopyToClipboard),
+            ("\"ay", Action::YankInto('a', 1)),
+            ("2\"by", Action::YankInto('b', 2)),
+            ("\"ap", Action::PasteFrom('a')),
+            ("\"\"y", Action::Yank(1)),
+            ("\"\"p", Action::Paste(PastePosition::After)),
+            ("\"ax", Action::Undefined),
+            ("\"+p", Action::PasteFromClipboard),
+            ("zl", Action::FoldBelowDepth(1)),
+            ("2zl", Action::FoldBelowDepth(2)),
+            ("zM", Action::FoldAll),
+            ("zR", Action::UnfoldAll),
+            ("za", Action::ToggleFold),
+            ("zp", Action::TogglePin),
+            ("zk", Action::ToggleCollapse),
+            ("zx", Action::Undefined),
+            ("gs", Action::ReportSize),
+            ("gw", Action::ExportScript),
+            ("gS", Action::WriteSession),
+            ("gt", Action::ReportKindHistogram),
+            ("gc", Action::CollectGarbage),
+            ("ge", Action::NormalizeAll),
+            ("gg", Action::MoveToRoot),
+            ("c", Action::MoveToFirstChild),
+            ("p", Action::MoveToParent),
+            ("n", Action::MoveToNextSibling(1)),
+            ("N", Action::MoveToPrevSibling(1)),
+            ("3n", Action::MoveToNextSibling(3)),
+            ("12N", Action::MoveToPrevSibling(12)),
+            ("0n", Action::MoveToNextSibling(1)),
+            (
+                "99999999999999999999999n",
+                Action::MoveToNextSibling(isize::MAX as usize),
+            ),
+            ("}", Action::MoveToNextSibling(5)),
+            ("3{", Action::MoveToPrevSibling(15)),
+            (
+                "99999999999999999999999}",
+                Action::MoveToNextSibling(isize::MAX as usize),
+            ),
+            ("H", Action::MoveToSibling(0)),
+            ("L", Action::MoveToSibling(usize::MAX)),
+            ("G", Action::MoveToSibling(usize::MAX)),
+            ("4G", Action::MoveToSibling(3)),
+            ("0G", Action::MoveToSibling(0)),
+            ("3H", Action::Undefined),
+            ("4ra", Action::Replace('a')),
+            ("3p", Action::Undefined),
+            ("g~", Action::InvertBooleans),
+            ("=s", Action::SortChildren),
+            ("=S", Action::ForceSortChildren),
+            ("gx", Action::Undefined),
+            ("gpa", Action::MoveToAncestor('a')),
+            ("gpo", Action::MoveToAncestor('o')),
+        ] {
+            assert_eq!(parse_command(command), Some(expected_effect.clone()));
+        }
+    }
+
+    #[test]
+    fn parse_command_incomplete() {
+        for command in &[
+            "", "q", "w", "Q", "@", "4@", "r", "i", "z", "g", "gp", "3", "42i", "gr", "grt", "gdt",
+            "y", "a", "12", "4r", "W",
+        ] {
+            assert_eq!(parse_command(command), None);
+        }
+    }
+}
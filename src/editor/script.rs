@@ -0,0 +1,575 @@
+//! Recording of editing sessions as replayable scripts of commands
+
+use super::{clamp_bound, is_named_register, parse_command, sorted_order, Action};
+use crate::ast_spec::ASTSpec;
+use crate::editable_tree::{EditableTree, PastePosition};
+use crate::node_map::subtree::Subtree;
+use crate::node_map::Reference;
+use std::collections::BTreeMap;
+
+/// The prefix of script lines that move the cursor through its siblings.  Commands never start
+/// with this character, so these lines can't be confused with commands.
+const SIBLINGS_PREFIX: &str = ":siblings ";
+/// The prefix of script lines that move the cursor to a given path of child indices
+const PATH_PREFIX: &str = ":path";
+/// The prefix of script lines that set the literal value of the selected node
+const LITERAL_PREFIX: &str = ":literal ";
+/// The prefix of script lines that clamp the selected number into a range
+const CLAMP_PREFIX: &str = ":clamp ";
+/// The prefix of comment lines, which are ignored when a script is parsed
+const COMMENT_PREFIX: char = '#';
+
+/// A single step of a [`Script`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Step {
+    /// A command, exactly as it was typed by the user (e.g. `"ra"`)
+    Command(String),
+    /// Move the cursor a given number of siblings forwards (or backwards if negative).  This is
+    /// used for inputs which aren't commands (like `PageDown`), and stores how far the cursor
+    /// actually moved so that replaying doesn't depend on the size of the terminal.
+    MoveBySiblings(isize),
+    /// Move the cursor to the node at a given path of child indices from the root.  This is used
+    /// for jumps whose destination depends on state that isn't recorded (like the search query).
+    MoveToPath(Vec<usize>),
+    /// Set the [literal value](ASTSpec::literal) of the selected node to the given text, as typed
+    /// in insert mode
+    SetLiteral(String),
+    /// Clamp the number in the selected node into the range from a minimum to a maximum, as
+    /// typed into the `:clamp` command
+    Clamp(String, String),
+}
+
+/// The sequence of [`Step`]s that affected the tree during an editing session.  Display-only
+/// commands (like folding) are not recorded, since replaying them wouldn't change the tree.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// Creates an empty `Script`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the steps recorded in this `Script`
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Remove every recorded step
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// Record a complete command, returning `true` if the command was recorded (i.e. it can
+    /// change the tree or the cursor).
+    pub fn record_command(&mut self, command: &str) -> bool {
+        match parse_command(command) {
+            Some(action) if action.affects_tree() => {
+                self.steps.push(Step::Command(command.to_string()));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record that the cursor moved `offset` places through its siblings
+    pub fn record_sibling_move(&mut self, offset: isize) {
+        if offset != 0 {
+            self.steps.push(Step::MoveBySiblings(offset));
+        }
+    }
+
+    /// Record that the cursor jumped to the node at `path`
+    pub fn record_path_move(&mut self, path: Vec<usize>) {
+        self.steps.push(Step::MoveToPath(path));
+    }
+
+    /// Record that the selected node's literal value was set to `text`
+    pub fn record_literal(&mut self, text: &str) {
+        self.steps.push(Step::SetLiteral(text.to_string()));
+    }
+
+    /// Record that the selected number was clamped into the range from `min` to `max`
+    pub fn record_clamp(&mut self, min: &str, max: &str) {
+        self.steps
+            .push(Step::Clamp(min.to_string(), max.to_string()));
+    }
+
+    /// Generate the text of this script, with one step per line
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} Sapling session script\n", COMMENT_PREFIX);
+        for step in &self.steps {
+            match step {
+                Step::Command(command) => text.push_str(command),
+                Step::MoveBySiblings(offset) => {
+                    text.push_str(SIBLINGS_PREFIX);
+                    text.push_str(&offset.to_string());
+                }
+                Step::MoveToPath(path) => {
+                    text.push_str(PATH_PREFIX);
+                    for index in path {
+                        text.push(' ');
+                        text.push_str(&index.to_string());
+                    }
+                }
+                Step::SetLiteral(literal) => {
+                    text.push_str(LITERAL_PREFIX);
+                    text.push_str(literal);
+                }
+                Step::Clamp(min, max) => {
+                    text.push_str(CLAMP_PREFIX);
+                    text.push_str(min);
+                    text.push(' ');
+                    text.push_str(max);
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Parse a script generated by [`Script::to_text`].  Returns an error message naming the first
+    /// line that isn't a valid step.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut script = Script::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.is_empty() || line.starts_with(COMMENT_PREFIX) {
+                continue;
+            }
+            let step = if let Some(offset) = line.strip_prefix(SIBLINGS_PREFIX) {
+                offset.parse().ok().map(Step::MoveBySiblings)
+            } else if let Some(literal) = line.strip_prefix(LITERAL_PREFIX) {
+                Some(Step::SetLiteral(literal.to_string()))
+            } else if let Some(range) = line.strip_prefix(CLAMP_PREFIX) {
+                match range.split_whitespace().collect::<Vec<_>>().as_slice() {
+                    [min, max] => Some(Step::Clamp(min.to_string(), max.to_string())),
+                    _ => None,
+                }
+            } else if let Some(path) = line.strip_prefix(PATH_PREFIX) {
+                path.split_whitespace()
+                    .map(|index| index.parse().ok())
+                    .collect::<Option<Vec<_>>>()
+                    .map(Step::MoveToPath)
+            } else {
+                match parse_command(line) {
+                    Some(Action::Undefined) | None => None,
+                    Some(_) => Some(Step::Command(line.to_string())),
+                }
+            };
+            match step {
+                Some(step) => script.steps.push(step),
+                None => return Err(format!("Line {}: '{}' is not a valid step", i + 1, line)),
+            }
+        }
+        Ok(script)
+    }
+
+    /// Apply every step of this script to `tree`, in order.  `merge_separator` and
+    /// `format_style` should be the separator that the [`Editor`](super::Editor) was using for
+    /// merges and the style it was displaying the tree in (which children are sorted by) while
+    /// the script was recorded.  The yank register and the edit repeated by `.` both start empty, so the script
+    /// should yank before it pastes and edit before it repeats.
+    pub fn replay<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>>(
+        &self,
+        tree: &mut E,
+        merge_separator: &str,
+        format_style: &Node::FormatStyle,
+    ) {
+        let mut state = ReplayState::default();
+        for step in &self.steps {
+            match step {
+                Step::Command(command) => {
+                    if let Some(action) = parse_command(command) {
+                        apply_to_tree(tree, action, merge_separator, format_style, &mut state);
+                    }
+                }
+                Step::MoveBySiblings(offset) => {
+                    tree.move_by_siblings(*offset);
+                }
+                Step::MoveToPath(path) => {
+                    tree.move_to_path(path);
+                }
+                Step::SetLiteral(literal) => {
+                    let action = Action::SetLiteral(literal.clone());
+                    apply_to_tree(tree, action, merge_separator, format_style, &mut state);
+                }
+                Step::Clamp(min, max) => {
+                    let action = Action::Clamp(min.clone(), max.clone());
+                    apply_to_tree(tree, action, merge_separator, format_style, &mut state);
+                }
+            }
+        }
+    }
+}
+
+/// The state (other than the tree) that the [`Editor`](super::Editor) keeps between actions, and
+/// which therefore has to be tracked while replaying a script
+#[derive(Debug, Default)]
+struct ReplayState<Node> {
+    /// The most recently yanked subtrees
+    register: Vec<Subtree<Node>>,
+    /// The subtrees yanked into each named register
+    named_registers: BTreeMap<char, Vec<Subtree<Node>>>,
+    /// The edit that is repeated by `.`
+    last_edit: Option<Action>,
+}
+
+/// Make the same change to `tree` (and the replay `state`) that the [`Editor`](super::Editor)
+/// makes when it receives `action`
+fn apply_to_tree<Ref: Reference, Node: ASTSpec<Ref>, E: EditableTree<Ref, Node>>(
+    tree: &mut E,
+    action: Action,
+    merge_separator: &str,
+    format_style: &Node::FormatStyle,
+    state: &mut ReplayState<Node>,
+) {
+    if action.is_repeatable() {
+        state.last_edit = Some(action.clone());
+    }
+    // An edit that failed when it was recorded fails in the same way when it's replayed, so
+    // errors are ignored here (the editor will have reported them already)
+    match action {
+        Action::RepeatLastEdit(count) => {
+            if let Some(edit) = state.last_edit.clone() {
+                // The repeats are undone together, like they are in the editor
+                tree.begin_edit();
+                for _ in 0..count {
+                    apply_to_tree(tree, edit.clone(), merge_separator, format_style, state);
+                }
+                tree.commit_edit();
+            }
+        }
+        Action::SetLiteral(text) => {
+            if let Some(new_node) = tree.cursor_node().with_literal(&text) {
+                tree.replace_cursor(new_node);
+            }
+        }
+        Action::Clamp(min, max) => {
+            let bound = clamp_bound(tree.cursor_node(), &min, &max);
+            if let Some(new_node) = bound
+                .ok()
+                .flatten()
+                .and_then(|b| tree.cursor_node().with_literal(b))
+            {
+                tree.replace_cursor(new_node);
+            }
+        }
+        Action::Yank(count) => {
+            state.register = tree.copy_siblings(count);
+        }
+        Action::Paste(position) => {
+            let _ = tree.paste_siblings(&state.register, position);
+        }
+        Action::YankInto(register, count) if is_named_register(register) => {
+            state.register = tree.copy_siblings(count);
+            state
+                .named_registers
+                .insert(register, state.register.clone());
+        }
+        Action::PasteFrom(register) => {
+            if let Some(subtrees) = state.named_registers.get(&register) {
+                let _ = tree.paste_siblings(subtrees, PastePosition::After);
+            }
+        }
+        Action::Duplicate => {
+            let _ = tree.duplicate_cursor();
+        }
+        Action::Replace(c) => {
+            if let Some(new_node) = tree.cursor_node().from_char(c) {
+                tree.replace_node(new_node);
+            }
+        }
+        Action::InsertChild(c, index) => {
+            let _ = tree.insert_child_from_char(c, index);
+        }
+        Action::AppendChild(c) => {
+            let _ = tree.insert_child_from_char(c, usize::MAX);
+        }
+        Action::InsertNextSibling(c) => {
+            let _ = tree.insert_sibling_from_char(c, true);
+        }
+        Action::InsertPrevSibling(c) => {
+            let _ = tree.insert_sibling_from_char(c, false);
+        }
+        Action::Undo => {
+            tree.undo();
+        }
+        Action::Redo => {
+            tree.redo();
+        }
+        Action::Delete(count) => {
+            let _ = tree.delete_siblings(count);
+        }
+        Action::MergeWithNextSibling => {
+            let _ = tree.merge_with_next_sibling(merge_separator);
+        }
+        Action::Wrap(c) => {
+            let _ = tree.wrap_cursor(c);
+        }
+        Action::Unwrap => {
+            let _ = tree.unwrap_or_splice_cursor();
+        }
+        Action::SwapWithNextSibling => {
+            let _ = tree.swap_with_sibling(1);
+        }
+        Action::SwapWithPrevSibling => {
+            let _ = tree.swap_with_sibling(-1);
+        }
+        Action::MoveToSameKeyInNextRecord => {
+            tree.move_to_same_key_in_next_record();
+        }
+        // The new name is recorded separately, as a literal
+        Action::RenameKey => {
+            tree.move_to_key();
+        }
+        Action::ReplaceAll(from, to) => {
+            let cursor_node = tree.cursor_node();
+            if let (Some(from_node), Some(to_node)) =
+                (cursor_node.from_char(from), cursor_node.from_char(to))
+            {
+                tree.replace_all(|node| node == &from_node, to_node);
+            }
+        }
+        Action::MoveToFirstChild => {
+            tree.move_to_child(0);
+        }
+        Action::MoveToParent => {
+            tree.move_to_parent();
+        }
+        Action::MoveToNextSibling(count) => {
+            tree.move_by_siblings(count as isize);
+        }
+        Action::MoveToPrevSibling(count) => {
+            tree.move_by_siblings(-(count as isize));
+        }
+        Action::MoveToSibling(index) => {
+            tree.move_to_sibling(index);
+        }
+        Action::MoveToRoot => {
+            tree.move_to_root();
+        }
+        Action::NormalizeAll => {
+            tree.map_nodes(|node| node.normalized());
+        }
+        Action::InvertBooleans => {
+            tree.map_subtree(|node| node.inverted());
+        }
+        Action::SortChildren | Action::ForceSortChildren => {
+            let by_text = action == Action::ForceSortChildren;
+            if let Some(order) = sorted_order(tree, format_style, by_text) {
+                // An order that changes nothing isn't a change, just like in the editor
+                if order.iter().enumerate().any(|(i, &j)| i != j) {
+                    let _ = tree.reorder_children(&tree.cursor_path(), &order);
+                }
+            }
+        }
+        Action::MoveToAncestor(c) => {
+            if let Some(kind_name) = tree.cursor_node().from_char(c).map(|n| n.kind_name()) {
+                tree.move_to_ancestor(|node| node.kind_name() == kind_name);
+            }
+        }
+        // Every other action leaves the tree untouched
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_to_tree, ReplayState, Script, Step};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use crate::editor::parse_command;
+    use crate::node_map::vec::Index;
+
+    /// Builds a [`Spec`] containing the sample tree that Sapling starts up with
+    fn sample_tree() -> Spec<JSON<Index>> {
+        Spec::from_tree(
+            TestJSON::Array(vec![
+                TestJSON::True,
+                TestJSON::False,
+                TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
+            ])
+            .build_node_map(),
+        )
+    }
+
+    #[test]
+    fn replaying_exported_script() {
+        // Run a short editing session, recording the commands like the editor does
+        let mut tree = sample_tree();
+        let mut script = Script::new();
+        for command in &["rt", "zM", "u", "ro", "gs", "u", "R", "ra", "2it"] {
+            if script.record_command(command) {
+                apply_to_tree(
+                    &mut tree,
+                    parse_command(command).unwrap(),
+                    " ",
+                    &JSONFormat::Compact,
+                    &mut ReplayState::default(),
+                );
+            }
+        }
+        // Replacing the root with an object and then an array keeps its children
+        assert_eq!(
+            tree.to_text(&JSONFormat::Compact),
+            r#"[true, false, true, {"value": true}]"#
+        );
+        // Display-only commands shouldn't be recorded
+        assert_eq!(script.steps().len(), 7);
+
+        let text = script.to_text();
+        assert_eq!(
+            text,
+            "# Sapling session script\nrt\nu\nro\nu\nR\nra\n2it\n".to_string()
+        );
+
+        // Replaying the script on a fresh copy of the tree should reproduce the final state
+        let replayed_script = Script::from_text(&text).unwrap();
+        assert_eq!(replayed_script, script);
+        let mut replayed_tree = sample_tree();
+        replayed_script.replay(&mut replayed_tree, " ", &JSONFormat::Compact);
+        assert_eq!(
+            replayed_tree.to_text(&JSONFormat::Compact),
+            tree.to_text(&JSONFormat::Compact)
+        );
+        assert_eq!(replayed_tree.cursor(), tree.cursor());
+        // The history should also match
+        assert!(replayed_tree.undo());
+        assert_eq!(
+            replayed_tree.to_text(&JSONFormat::Compact),
+            r#"[true, false, {"value": true}]"#
+        );
+    }
+
+    #[test]
+    fn navigation_commands() {
+        let mut tree = sample_tree();
+        let mut run = |command: &str| {
+            apply_to_tree(
+                &mut tree,
+                parse_command(command).unwrap(),
+                " ",
+                &JSONFormat::Compact,
+                &mut ReplayState::default(),
+            );
+            tree.cursor_node().clone()
+        };
+        // Moving up from the root or sideways from the root should do nothing
+        assert_eq!(run("p").kind_name(), "array");
+        assert_eq!(run("n").kind_name(), "array");
+        assert_eq!(run("c"), JSON::True);
+        // Leaves have no children, and there's nothing before the first child
+        assert_eq!(run("c"), JSON::True);
+        assert_eq!(run("N"), JSON::True);
+        assert_eq!(run("n"), JSON::False);
+        assert_eq!(run("n").kind_name(), "object");
+        // There's nothing after the last child
+        assert_eq!(run("n").kind_name(), "object");
+        assert_eq!(run("c").kind_name(), "field");
+        assert_eq!(run("p").kind_name(), "object");
+        assert_eq!(run("N"), JSON::False);
+        assert_eq!(run("p").kind_name(), "array");
+    }
+
+    #[test]
+    fn repeat_last_edit() {
+        let replay = |tree: &TestJSON, text: &str| {
+            let mut tree: Spec<JSON<Index>> = Spec::from_tree(tree.build_node_map());
+            Script::from_text(text)
+                .unwrap()
+                .replay(&mut tree, " ", &JSONFormat::Compact);
+            tree.to_text(&JSONFormat::Compact)
+        };
+        let falses = TestJSON::Array(vec![
+            TestJSON::False,
+            TestJSON::False,
+            TestJSON::False,
+            TestJSON::Object(vec![]),
+        ]);
+        // Moving shouldn't replace the edit that is repeated
+        assert_eq!(replay(&falses, "c\nrt\nn\n.\n"), "[true, true, false, {}]");
+        // Counts repeat the whole edit
+        assert_eq!(replay(&falses, "c\nx\n2.\n"), "[{}]");
+        // ... and are undone in one go
+        assert_eq!(replay(&falses, "c\nx\n2.\nu\n"), "[false, false, {}]");
+        // Nothing happens if there's nothing to repeat
+        assert_eq!(replay(&falses, ".\nc\n5.\n"), "[false, false, false, {}]");
+
+        // Repeating an edit where it isn't valid should do nothing, just like typing it there
+        let tree = TestJSON::Array(vec![TestJSON::False]);
+        assert_eq!(replay(&tree, "at\nc\n.\n"), "[false, true]");
+        assert_eq!(replay(&tree, "at\nc\n.\np\n.\n"), "[false, true, true]");
+        // Undo isn't repeatable, so `.` repeats the edit before it
+        assert_eq!(replay(&tree, "at\nu\np\n.\n"), "[false, true]");
+        // Setting a literal can be repeated on other strings, but not on other nodes
+        let strings = TestJSON::Array(vec![
+            TestJSON::Str("a".to_string()),
+            TestJSON::Str("b".to_string()),
+            TestJSON::True,
+        ]);
+        assert_eq!(
+            replay(&strings, "c\n:literal new\nn\n.\nn\n.\n"),
+            r#"["new", "new", true]"#
+        );
+        // Clamping only changes numbers that are out of range, and can be repeated too
+        let numbers = TestJSON::Array(vec![
+            TestJSON::Number("150".to_string()),
+            TestJSON::Number("50".to_string()),
+            TestJSON::Number("-3".to_string()),
+            TestJSON::Str("999".to_string()),
+        ]);
+        assert_eq!(
+            replay(&numbers, "c\n:clamp 0 100\nn\n.\nn\n.\nn\n.\n"),
+            r#"[100, 50, 0, "999"]"#
+        );
+    }
+
+    #[test]
+    fn script_text_round_trip() {
+        let mut script = Script::new();
+        script.record_command("rf");
+        script.record_sibling_move(-40);
+        script.record_sibling_move(0);
+        script.record_command("gpa");
+        script.record_path_move(vec![2, 0, 1]);
+        script.record_path_move(vec![]);
+        script.record_literal(" spaced \\\" out ");
+        script.record_clamp("-1", "2.5");
+        assert_eq!(
+            script.steps(),
+            &[
+                Step::Command("rf".to_string()),
+                Step::MoveBySiblings(-40),
+                Step::Command("gpa".to_string()),
+                Step::MoveToPath(vec![2, 0, 1]),
+                Step::MoveToPath(vec![]),
+                Step::SetLiteral(r#" spaced \" out "#.to_string()),
+                Step::Clamp("-1".to_string(), "2.5".to_string()),
+            ]
+        );
+        assert!(script
+            .to_text()
+            .ends_with(":path 2 0 1\n:path\n:literal  spaced \\\" out \n:clamp -1 2.5\n"));
+        assert_eq!(Script::from_text(&script.to_text()), Ok(script));
+
+        assert_eq!(
+            Script::from_text("ra\n:siblings x\n"),
+            Err("Line 2: ':siblings x' is not a valid step".to_string())
+        );
+        assert!(Script::from_text(":path 1 -2\n").is_err());
+        assert!(Script::from_text(":clamp 1\n").is_err());
+
+        // Replaying a path should move the cursor there
+        let mut tree = sample_tree();
+        Script::from_text(":path 2 0 1\n")
+            .unwrap()
+            .replay(&mut tree, " ", &JSONFormat::Compact);
+        assert_eq!(tree.cursor_node(), &JSON::True);
+        assert_eq!(tree.cursor_path(), vec![2, 0, 1]);
+    }
+}
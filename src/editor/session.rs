@@ -0,0 +1,411 @@
+//! Saving and restoring whole editing sessions, including the undo history
+
+use crate::ast_spec::json::{escape_string, unescape_string, JSON};
+use crate::ast_spec::test_json::TestJSON;
+use crate::ast_spec::ASTSpec;
+use crate::editable_tree::spec::Spec;
+use crate::node_map::vec::{Index, VecNodeMap};
+use crate::node_map::{NodeMapMut, Reference};
+use std::path::PathBuf;
+
+/// The value of the `"format"` field of every session file, so that other JSON files aren't
+/// mistaken for sessions
+const FORMAT_NAME: &str = "sapling-session";
+/// The version of the session format written by this version of Sapling.  This must be increased
+/// whenever the format changes incompatibly, so that old versions of Sapling refuse to load
+/// sessions that they would misread.
+pub const FORMAT_VERSION: usize = 1;
+
+/// One version of the tree in the undo history of a [`Session`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Version {
+    /// The text of the tree
+    pub text: String,
+    /// The path (as child indices from the root) of the node that the cursor selected
+    #[cfg_attr(feature = "serde", serde(rename = "cursor"))]
+    pub cursor_path: Vec<usize>,
+}
+
+/// The state of an editing session that is needed to carry on exactly where it left off: every
+/// version of the tree in the undo history and which one is current, along with the file being
+/// edited and whether it has unsaved changes.
+///
+/// Sessions are stored as JSON, like:
+/// ```text
+/// {
+///     "format": "sapling-session",
+///     "version": 1,
+///     "file": "data.json",
+///     "dirty": true,
+///     "current": 1,
+///     "history": [
+///         {"cursor": [], "text": "[true]"},
+///         {"cursor": [0], "text": "[false]"}
+///     ]
+/// }
+/// ```
+///
+/// With the `serde` feature, sessions can also be serialized with serde, which gives the same
+/// fields (so [`Session::from_text`] can read a session that serde wrote as JSON, and vice versa).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(into = "SerializedSession", try_from = "SerializedSession")
+)]
+pub struct Session {
+    /// The versions of the tree, oldest first.  This is never empty.
+    pub history: Vec<Version>,
+    /// The index in `history` of the version that is being edited
+    pub current: usize,
+    /// The file that the tree is written to, if one has been set
+    pub file_path: Option<PathBuf>,
+    /// Whether the tree has been edited since it was last saved
+    pub dirty: bool,
+}
+
+impl Session {
+    /// Generate the text of this session, which can be read with [`Session::from_text`]
+    pub fn to_text(&self) -> String {
+        let string = |s: &str| format!("\"{}\"", escape_string(s));
+        let mut text = String::from("{\n");
+        text.push_str(&format!("    \"format\": {},\n", string(FORMAT_NAME)));
+        text.push_str(&format!("    \"version\": {},\n", FORMAT_VERSION));
+        let file = match &self.file_path {
+            Some(path) => string(&path.to_string_lossy()),
+            None => "null".to_string(),
+        };
+        text.push_str(&format!("    \"file\": {},\n", file));
+        text.push_str(&format!("    \"dirty\": {},\n", self.dirty));
+        text.push_str(&format!("    \"current\": {},\n", self.current));
+        text.push_str("    \"history\": [\n");
+        for (i, version) in self.history.iter().enumerate() {
+            let cursor_path: Vec<String> =
+                version.cursor_path.iter().map(usize::to_string).collect();
+            text.push_str(&format!(
+                "        {{\"cursor\": [{}], \"text\": {}}}{}\n",
+                cursor_path.join(", "),
+                string(&version.text),
+                if i + 1 < self.history.len() { "," } else { "" }
+            ));
+        }
+        text.push_str("    ]\n}\n");
+        text
+    }
+
+    /// Parse a session generated by [`Session::to_text`].  Returns an error message if `text`
+    /// isn't a session, or is a session in a format that this version of Sapling can't read.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let node_map: VecNodeMap<JSON<Index>> =
+            JSON::parse_into(text).map_err(|e| format!("Session isn't valid JSON: {}", e))?;
+        let fields = match TestJSON::from_node_map(&node_map) {
+            TestJSON::Object(fields) => fields,
+            _ => return Err("Session isn't a JSON object".to_string()),
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| format!("Session has no '{}' field", name))
+        };
+        if as_string(field("format")?).as_deref() != Some(FORMAT_NAME) {
+            return Err("File isn't a Sapling session".to_string());
+        }
+        // The version is checked before anything else is read, since the other fields may mean
+        // something different in other versions
+        match as_usize(field("version")?) {
+            Some(FORMAT_VERSION) => {}
+            _ => {
+                return Err(format!(
+                    "Session is in an unsupported format (Sapling can only read version {})",
+                    FORMAT_VERSION
+                ))
+            }
+        }
+        let invalid = |name: &str| format!("Session has an invalid '{}' field", name);
+        let file_path = match field("file")? {
+            TestJSON::Null => None,
+            file => Some(PathBuf::from(
+                as_string(file).ok_or_else(|| invalid("file"))?,
+            )),
+        };
+        let dirty = match field("dirty")? {
+            TestJSON::True => true,
+            TestJSON::False => false,
+            _ => return Err(invalid("dirty")),
+        };
+        let history = match field("history")? {
+            TestJSON::Array(versions) => versions
+                .iter()
+                .map(as_version)
+                .collect::<Option<Vec<Version>>>()
+                .ok_or_else(|| invalid("history"))?,
+            _ => return Err(invalid("history")),
+        };
+        let current = as_usize(field("current")?)
+            .filter(|&i| i < history.len())
+            .ok_or_else(|| invalid("current"))?;
+        Ok(Session {
+            history,
+            current,
+            file_path,
+            dirty,
+        })
+    }
+
+    /// Rebuild the tree of this session, along with its undo history, using `parse` to read the
+    /// text of each version.  Returns an error message if a version can't be parsed, or its
+    /// cursor path doesn't lead to a node.
+    pub fn build_tree<Node: ASTSpec<Ref>, Ref: Reference, M: NodeMapMut<Ref, Node> + Clone>(
+        &self,
+        parse: impl Fn(&str) -> Result<M, String>,
+    ) -> Result<Spec<Node, Ref, M>, String> {
+        let mut snapshots = Vec::with_capacity(self.history.len());
+        for (i, version) in self.history.iter().enumerate() {
+            let node_map = parse(&version.text)
+                .map_err(|e| format!("Version {} of the session is invalid: {}", i, e))?;
+            snapshots.push((node_map, version.cursor_path.clone()));
+        }
+        Spec::from_snapshots(snapshots, self.current)
+            .ok_or_else(|| "A cursor in the session doesn't select a node".to_string())
+    }
+}
+
+/// The fields of a [`Session`] as serde sees them, including the format name and version
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedSession {
+    format: String,
+    version: usize,
+    file: Option<PathBuf>,
+    dirty: bool,
+    current: usize,
+    history: Vec<Version>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Session> for SerializedSession {
+    fn from(session: Session) -> Self {
+        SerializedSession {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            file: session.file_path,
+            dirty: session.dirty,
+            current: session.current,
+            history: session.history,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<SerializedSession> for Session {
+    type Error = String;
+
+    fn try_from(session: SerializedSession) -> Result<Self, String> {
+        if session.format != FORMAT_NAME {
+            return Err("File isn't a Sapling session".to_string());
+        }
+        if session.version != FORMAT_VERSION {
+            return Err(format!(
+                "Session is in an unsupported format (Sapling can only read version {})",
+                FORMAT_VERSION
+            ));
+        }
+        if session.current >= session.history.len() {
+            return Err("Session has an invalid 'current' field".to_string());
+        }
+        Ok(Session {
+            history: session.history,
+            current: session.current,
+            file_path: session.file,
+            dirty: session.dirty,
+        })
+    }
+}
+
+/// Returns the contents of `value` if it's a valid JSON string
+fn as_string(value: &TestJSON) -> Option<String> {
+    match value {
+        TestJSON::Str(s) => unescape_string(s),
+        _ => None,
+    }
+}
+
+/// Returns the value of `value` if it's a non-negative integer
+fn as_usize(value: &TestJSON) -> Option<usize> {
+    match value {
+        TestJSON::Number(n) => n.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Reads a single entry of a session's history
+fn as_version(value: &TestJSON) -> Option<Version> {
+    let fields = match value {
+        TestJSON::Object(fields) => fields,
+        _ => return None,
+    };
+    let field = |name: &str| fields.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+    let cursor_path = match field("cursor")? {
+        TestJSON::Array(indices) => indices.iter().map(as_usize).collect::<Option<_>>()?,
+        _ => return None,
+    };
+    Some(Version {
+        text: as_string(field("text")?)?,
+        cursor_path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Session, Version};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use std::path::PathBuf;
+
+    fn sample_session() -> Session {
+        Session {
+            history: vec![
+                Version {
+                    text: r#"{"key": "a \"quoted\"\nline"}"#.to_string(),
+                    cursor_path: vec![],
+                },
+                Version {
+                    text: r#"{"key": []}"#.to_string(),
+                    cursor_path: vec![0, 1],
+                },
+            ],
+            current: 1,
+            file_path: Some(PathBuf::from("dir/data.json")),
+            dirty: true,
+        }
+    }
+
+    fn parse(text: &str) -> Result<VecNodeMap<JSON<Index>>, String> {
+        JSON::parse_into(text).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn round_trip() {
+        let session = sample_session();
+        assert_eq!(Session::from_text(&session.to_text()), Ok(session.clone()));
+        let session = Session {
+            file_path: None,
+            dirty: false,
+            current: 0,
+            ..session
+        };
+        assert_eq!(Session::from_text(&session.to_text()), Ok(session));
+    }
+
+    #[test]
+    fn building_trees() {
+        let tree: Spec<JSON<Index>> = sample_session().build_tree(parse).unwrap();
+        let mut tree = tree;
+        assert_eq!(tree.to_text(&JSONFormat::Compact), r#"{"key": []}"#);
+        assert_eq!(tree.cursor_path(), vec![0, 1]);
+        assert!(tree.undo());
+        assert_eq!(tree.cursor_path(), Vec::<usize>::new());
+        assert!(!tree.undo());
+
+        let mut session = sample_session();
+        session.history[1].cursor_path = vec![0, 2];
+        let tree: Result<Spec<JSON<Index>>, String> = session.build_tree(parse);
+        assert!(tree.is_err());
+        session.history[0].text = "[".to_string();
+        let tree: Result<Spec<JSON<Index>>, String> = session.build_tree(parse);
+        assert!(tree
+            .unwrap_err()
+            .starts_with("Version 0 of the session is invalid"));
+    }
+
+    #[test]
+    fn unreadable_sessions() {
+        let text = sample_session().to_text();
+        for (bad_text, error) in &[
+            ("[", "Session isn't valid JSON"),
+            ("[]", "Session isn't a JSON object"),
+            (r#"{"version": 1}"#, "Session has no 'format' field"),
+            (
+                r#"{"format": "other", "version": 1}"#,
+                "File isn't a Sapling session",
+            ),
+            (
+                text.replace("\"version\": 1", "\"version\": 2").as_str(),
+                "Session is in an unsupported format (Sapling can only read version 1)",
+            ),
+            (
+                text.replace("\"current\": 1", "\"current\": 2").as_str(),
+                "Session has an invalid 'current' field",
+            ),
+            (
+                text.replace("[0, 1]", "[-1]").as_str(),
+                "Session has an invalid 'history' field",
+            ),
+            (
+                text.replace("\"dirty\": true", "\"dirty\": 1").as_str(),
+                "Session has an invalid 'dirty' field",
+            ),
+        ] {
+            let result = Session::from_text(bad_text);
+            assert!(
+                matches!(&result, Err(e) if e.starts_with(error)),
+                "{:?} gave {:?}",
+                bad_text,
+                result
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let session = sample_session();
+        // serde writes the same fields as `to_text`, so either can read the other's sessions
+        let text = serde_json::to_string(&session).unwrap();
+        assert_eq!(Session::from_text(&text), Ok(session.clone()));
+        let read: Session = serde_json::from_str(&session.to_text()).unwrap();
+        assert_eq!(read, session);
+        // ... and the tree comes back with its history
+        let mut tree: Spec<JSON<Index>> = read.build_tree(parse).unwrap();
+        assert_eq!(tree.to_text(&JSONFormat::Compact), r#"{"key": []}"#);
+        assert_eq!(tree.cursor_path(), vec![0, 1]);
+        assert!(tree.undo());
+        assert_eq!(
+            tree.to_text(&JSONFormat::Compact),
+            r#"{"key": "a \"quoted\"\nline"}"#
+        );
+
+        // Sessions in other formats are refused rather than misread
+        for (bad_text, error) in &[
+            (
+                session
+                    .to_text()
+                    .replace("\"version\": 1", "\"version\": 2"),
+                "Session is in an unsupported format (Sapling can only read version 1)",
+            ),
+            (
+                session.to_text().replace("sapling-session", "other"),
+                "File isn't a Sapling session",
+            ),
+            (
+                session
+                    .to_text()
+                    .replace("\"current\": 1", "\"current\": 2"),
+                "Session has an invalid 'current' field",
+            ),
+        ] {
+            let result = serde_json::from_str::<Session>(bad_text);
+            assert!(
+                matches!(&result, Err(e) if e.to_string().starts_with(error)),
+                "{:?} gave {:?}",
+                bad_text,
+                result
+            );
+        }
+    }
+}
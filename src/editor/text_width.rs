@@ -0,0 +1,110 @@
+//! Measuring and truncating text by the number of terminal columns it occupies, rather than by its
+//! number of [`char`]s (which is wrong for wide characters, such as CJK or emoji, and for
+//! combining marks)
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Returns the number of terminal columns that `text` occupies
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// Returns the number of terminal columns that the last line of `text` occupies
+pub fn last_line_width(text: &str) -> usize {
+    display_width(text.rsplit('\n').next().unwrap_or(""))
+}
+
+/// Returns the longest prefix of `text` that fits in `max_width` columns.  Zero-width characters
+/// (e.g. combining marks) stay with the character before them, and wide characters are never
+/// split.
+pub fn truncate_to_width(text: &str, max_width: usize) -> &str {
+    let mut width = 0;
+    for (i, c) in text.char_indices() {
+        let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        if char_width > 0 && width + char_width > max_width {
+            return &text[..i];
+        }
+        width += char_width;
+    }
+    text
+}
+
+/// Returns the longest suffix of `text` that fits in `max_width` columns.  Like
+/// [`truncate_to_width`], this never starts with a combining mark that has been separated from
+/// the character it belongs to.
+pub fn truncate_start_to_width(text: &str, max_width: usize) -> &str {
+    let mut width = 0;
+    let mut start = text.len();
+    for (i, c) in text.char_indices().rev() {
+        let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        if width + char_width > max_width {
+            break;
+        }
+        width += char_width;
+        start = i;
+    }
+    let suffix = &text[start..];
+    // Drop any combining marks whose character was cut off
+    suffix.trim_start_matches(|c| UnicodeWidthChar::width(c) == Some(0))
+}
+
+/// Returns the part of `text` that is drawn between the columns `start` and `end` (counting from
+/// the start of `text`), along with the column that the part starts at.  Wide characters that only
+/// partly fit are left out rather than split, so the part can start after `start` and end before
+/// `end`.
+pub fn slice_columns(text: &str, start: usize, end: usize) -> (usize, &str) {
+    let mut col = 0;
+    let mut begin = text.len();
+    for (i, c) in text.char_indices() {
+        if col >= start {
+            begin = i;
+            break;
+        }
+        col += UnicodeWidthChar::width(c).unwrap_or(0);
+    }
+    // Drop any combining marks whose character was cut off
+    let rest = text[begin..].trim_start_matches(|c| UnicodeWidthChar::width(c) == Some(0));
+    (col, truncate_to_width(rest, end.saturating_sub(col)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        display_width, last_line_width, slice_columns, truncate_start_to_width, truncate_to_width,
+    };
+
+    #[test]
+    fn widths() {
+        assert_eq!(display_width("r"), 1);
+        assert_eq!(display_width("r好"), 3);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(last_line_width("好\n好x"), 3);
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate_to_width("abc", 5), "abc");
+        assert_eq!(truncate_to_width("a好b", 2), "a");
+        assert_eq!(truncate_to_width("a好b", 3), "a好");
+        // Combining marks are kept with their characters
+        assert_eq!(truncate_to_width("e\u{301}x", 1), "e\u{301}");
+        assert_eq!(truncate_to_width("好", 0), "");
+
+        assert_eq!(truncate_start_to_width("a好b", 2), "b");
+        assert_eq!(truncate_start_to_width("a好b", 3), "好b");
+        assert_eq!(truncate_start_to_width("xe\u{301}", 1), "e\u{301}");
+        assert_eq!(truncate_start_to_width("e\u{301}", 0), "");
+    }
+
+    #[test]
+    fn slicing() {
+        assert_eq!(slice_columns("abcdef", 2, 4), (2, "cd"));
+        assert_eq!(slice_columns("abc", 1, 10), (1, "bc"));
+        assert_eq!(slice_columns("abc", 5, 10), (3, ""));
+        // Wide characters on either edge are left out
+        assert_eq!(slice_columns("a好b好", 2, 5), (3, "b"));
+        assert_eq!(slice_columns("a好b好", 1, 4), (1, "好b"));
+        assert_eq!(slice_columns("xe\u{301}y", 1, 2), (1, "e\u{301}"));
+        assert_eq!(slice_columns("e\u{301}y", 1, 2), (1, "y"));
+    }
+}
@@ -0,0 +1,131 @@
+//! User-registered functions that override how leaf values are displayed
+
+use crate::ast_spec::display_token::DisplayToken;
+use crate::ast_spec::ASTSpec;
+use crate::node_map::{NodeMap, Reference};
+use std::collections::HashMap;
+
+/// A function that produces the text displayed for a leaf node
+pub type ValueFormatter<Node> = Box<dyn Fn(&Node) -> String>;
+
+/// A registry mapping node kinds (as returned by [`ASTSpec::kind_name`]) to functions that
+/// generate the displayed text for leaf nodes of that kind.  For example, a formatter could be
+/// registered for `"string"` that truncates long strings.
+///
+/// Like [`FoldState`](super::folds::FoldState), this only affects how the tree is rendered to the
+/// screen - the text that is saved is always generated by [`ASTSpec::write_text`].
+pub struct ValueFormatters<Node> {
+    formatters: HashMap<&'static str, ValueFormatter<Node>>,
+}
+
+impl<Node> Default for ValueFormatters<Node> {
+    fn default() -> Self {
+        ValueFormatters {
+            formatters: HashMap::new(),
+        }
+    }
+}
+
+impl<Node> ValueFormatters<Node> {
+    /// Creates a `ValueFormatters` with no formatters registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a formatter for every leaf node of the kind `kind_name`, replacing any formatter
+    /// that was previously registered for that kind.  Returns `true` if a formatter was replaced.
+    pub fn register(
+        &mut self,
+        kind_name: &'static str,
+        formatter: impl Fn(&Node) -> String + 'static,
+    ) -> bool {
+        self.formatters
+            .insert(kind_name, Box::new(formatter))
+            .is_some()
+    }
+
+    /// Remove the formatter for the kind `kind_name`, returning `true` if there was one
+    pub fn unregister(&mut self, kind_name: &str) -> bool {
+        self.formatters.remove(kind_name).is_some()
+    }
+
+    /// Returns the custom text for `node`, or [`None`] if `node` has children or no formatter is
+    /// registered for its kind
+    pub fn format<Ref: Reference>(&self, node: &Node) -> Option<String>
+    where
+        Node: ASTSpec<Ref>,
+    {
+        if !node.children().is_empty() {
+            return None;
+        }
+        self.formatters.get(node.kind_name()).map(|f| f(node))
+    }
+
+    /// Generates the [`DisplayToken`]s for `node` (which is in `node_map`), using a registered
+    /// formatter if one applies and falling back on [`ASTSpec::display_tokens_in`] otherwise
+    pub fn display_tokens<Ref: Reference>(
+        &self,
+        node_map: &impl NodeMap<Ref, Node>,
+        node: &Node,
+        format_style: &Node::FormatStyle,
+    ) -> Vec<DisplayToken<Ref>>
+    where
+        Node: ASTSpec<Ref>,
+    {
+        match self.format(node) {
+            Some(text) => vec![DisplayToken::Text(text)],
+            None => node.display_tokens_in(node_map, format_style),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValueFormatters;
+    use crate::ast_spec::display_token::{flat_tokens_with, DisplayToken};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::NodeMap;
+
+    #[test]
+    fn formatters_only_affect_display() {
+        let node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::False,
+            TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
+        ])
+        .build_node_map();
+        let mut formatters = ValueFormatters::new();
+        assert!(!formatters.register("bool", |node: &JSON<Index>| format!(
+            "${}",
+            node.display_name()
+        )));
+
+        let displayed: String =
+            flat_tokens_with(&node_map, node_map.root(), &|_, node: &JSON<Index>| {
+                formatters.display_tokens(&node_map, node, &JSONFormat::Compact)
+            })
+            .into_iter()
+            .map(|(_, tok)| match tok {
+                DisplayToken::Text(s) => s,
+                DisplayToken::Whitespace(n) => " ".repeat(n),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(displayed, r#"[$true, $false, {"value": $true}]"#);
+        // Saving the tree should ignore the formatters
+        assert_eq!(
+            JSON::to_text(node_map.root(), &node_map, &JSONFormat::Compact),
+            r#"[true, false, {"value": true}]"#
+        );
+
+        // Formatters are never applied to nodes with children
+        assert!(formatters.format(node_map.root_node()).is_none());
+        let first_bool = node_map.get_node(node_map.root_node().children()[0]);
+        assert_eq!(formatters.format(first_bool.unwrap()).unwrap(), "$true");
+        assert!(formatters.unregister("bool"));
+        assert!(formatters.format(first_bool.unwrap()).is_none());
+    }
+}
@@ -1,4 +1,29 @@
+/// Makes a copy of some tests for every [`NodeMap`](node_map::NodeMap) implementation, each in its
+/// own module.  Inside the tests, `Ref` and `Map<Node>` are the reference and map types of the
+/// implementation being tested.
+#[cfg(test)]
+macro_rules! for_each_node_map {
+    ($($tests:item)*) => {
+        mod vec_node_map {
+            #[allow(unused_imports)]
+            use super::*;
+            type Ref = crate::node_map::vec::Index;
+            type Map<Node> = crate::node_map::vec::VecNodeMap<Node>;
+            $($tests)*
+        }
+
+        mod slab_node_map {
+            #[allow(unused_imports)]
+            use super::*;
+            type Ref = crate::node_map::slab::Key;
+            type Map<Node> = crate::node_map::slab::SlabNodeMap<Node>;
+            $($tests)*
+        }
+    };
+}
+
 pub mod ast_spec;
 pub mod editable_tree;
 pub mod editor;
 pub mod node_map;
+pub mod script;
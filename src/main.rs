@@ -1,18 +1,710 @@
+use sapling::ast_spec::display_token::IndentStyle;
+use sapling::ast_spec::expr::{Expr, ExprFormat};
 use sapling::ast_spec::json::{JSONFormat, JSON};
+use sapling::ast_spec::lisp::{Lisp, LispFormat};
 use sapling::ast_spec::test_json::TestJSON;
+use sapling::ast_spec::test_lisp::TestLisp;
+use sapling::ast_spec::test_xml::TestXML;
+use sapling::ast_spec::xml::{start_tag, XMLFormat, XML};
+use sapling::ast_spec::ASTSpec;
 use sapling::editable_tree::spec::Spec;
-use sapling::editor::Editor;
+use sapling::editor::auto_save::{backup_path, recoverable_backup, AutoSave};
+use sapling::editor::config::{Config, Setting};
+use sapling::editor::file_log::FileLogger;
+use sapling::editor::keymap::KeyMap;
+use sapling::editor::session::Session;
+use sapling::editor::{ClipboardParser, Editor, FileLoader, LogLevel};
+use sapling::node_map::subtree::Subtree;
 use sapling::node_map::vec::{Index, VecNodeMap};
+use sapling::node_map::NodeMap;
+use sapling::script::{self, DocumentSpec};
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-fn main() {
-    // For the time being, start the editor with some pre-made JSON
-    let start_node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+/// Read the text of the file at `path`, or of stdin if `path` is `-`
+fn read_input(path: &str) -> std::io::Result<String> {
+    if path == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Read the JSON file at `path` into a tree, for opening files in new buffers
+fn load_json(path: &Path) -> Result<Spec<JSON<Index>>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let node_map = JSON::parse_into(&text).map_err(|e| e.to_string())?;
+    Ok(Spec::from_tree(node_map))
+}
+
+/// Read the expression in the file at `path` into a tree, for opening files in new buffers
+fn load_expr(path: &Path) -> Result<Spec<Expr<Index>>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let node_map = Expr::parse_into(&text).map_err(|e| e.to_string())?;
+    Ok(Spec::from_tree(node_map))
+}
+
+/// Parse JSON text from the system clipboard into a subtree that can be pasted
+fn parse_json_subtree(text: &str) -> Result<Subtree<JSON<Index>>, String> {
+    let node_map: VecNodeMap<JSON<Index>> = JSON::parse_into(text).map_err(|e| e.to_string())?;
+    Subtree::copy_from(&node_map, node_map.root()).ok_or_else(|| "the text is empty".to_string())
+}
+
+/// Ask the user a yes/no question on the terminal, returning `true` if they answered yes.  If
+/// stdin isn't a terminal then there's nobody to answer, so this returns `false`.
+fn confirm(question: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Parse the value of a command line option that takes a number, exiting if it isn't one.  `0`
+/// turns the option off, so gives [`None`].
+fn parse_count(option: &str, value: Option<String>) -> Option<usize> {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(0)) => None,
+        Some(Ok(n)) => Some(n),
+        _ => {
+            eprintln!("'{}' needs a number (or 0 to turn it off)", option);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The text printed by `--help`
+const USAGE: &str = "\
+Usage: sapling [OPTIONS] [FILE]
+
+Edits FILE (or stdin, if FILE is '-' or stdin isn't a terminal) as a tree.
+
+Options:
+  --language NAME           Edit the file as json, lisp, xml or expr, rather than guessing from
+                            its extension (--lisp and --xml are short for the last two)
+  --format STYLE            Show the tree as pretty, compact or minified text
+  --output-format STYLE     Write the tree in a different style to the one it's shown in
+  --indent N|tab            Indent by N spaces, or by tabs
+  --line-numbers            Show line numbers
+  --readonly                Open the file without allowing edits
+  --autosave-interval SECS  Write a backup every SECS seconds (0 turns this off)
+  --autosave-edits N        Write a backup every N edits (0 turns this off)
+  --keymap PATH             Read key bindings from PATH
+  --config PATH             Read settings from PATH
+  --log PATH                Write debugging messages to PATH
+  --session PATH            Restore the editing session saved in PATH
+  --pipe                    Edit stdin, then write the result to stdout
+  --script COMMANDS         Apply COMMANDS to the JSON in FILE without opening the editor, and
+                            write the result to stdout
+  -h, --help                Print this message
+";
+
+/// Returns `true` if the command line argument `arg` is an option rather than a file, where `-`
+/// on its own stands for stdin
+fn is_option(arg: &str) -> bool {
+    arg.starts_with('-') && arg != "-"
+}
+
+/// The languages that the editor can edit
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Language {
+    Json,
+    Lisp,
+    Xml,
+    Expr,
+}
+
+impl Language {
+    /// Every language, along with the name it's given by `--language`
+    const ALL: [(Language, &'static str); 4] = [
+        (Language::Json, "json"),
+        (Language::Lisp, "lisp"),
+        (Language::Xml, "xml"),
+        (Language::Expr, "expr"),
+    ];
+
+    /// Returns the name of this language, as given to `--language`
+    fn name(self) -> &'static str {
+        Self::ALL.iter().find(|(l, _)| *l == self).unwrap().1
+    }
+
+    /// Returns the language called `name` (as given to `--language`), or [`None`] if there isn't
+    /// one
+    fn from_name(name: &str) -> Option<Language> {
+        Self::ALL.iter().find(|(_, n)| *n == name).map(|(l, _)| *l)
+    }
+
+    /// Infers the language of the file at `path` from its extension, returning [`None`] if the
+    /// extension isn't recognised
+    fn from_path(path: &Path) -> Option<Language> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Language::Json),
+            "sexp" | "lisp" => Some(Language::Lisp),
+            "xml" => Some(Language::Xml),
+            "expr" => Some(Language::Expr),
+            _ => None,
+        }
+    }
+}
+
+/// The JSON tree that the editor starts with when it isn't given a file
+fn default_json() -> VecNodeMap<JSON<Index>> {
+    TestJSON::Array(vec![
         TestJSON::True,
         TestJSON::False,
         TestJSON::Object(vec![("value".to_string(), TestJSON::True)]),
     ])
-    .build_node_map();
-    let tree: Spec<JSON<Index>> = Spec::from_tree(start_node_map);
-    let editor = Editor::new(tree, JSONFormat::Pretty);
-    editor.run();
+    .build_node_map()
+}
+
+/// The Lisp tree that the editor starts with
+fn default_lisp() -> VecNodeMap<Lisp<Index>> {
+    let sym = |name: &str| TestLisp::Symbol(name.to_string());
+    TestLisp::List(vec![
+        sym("define"),
+        TestLisp::List(vec![sym("square"), sym("x")]),
+        TestLisp::List(vec![sym("*"), sym("x"), sym("x")]),
+    ])
+    .build_node_map()
+}
+
+/// The XML tree that the editor starts with
+fn default_xml() -> VecNodeMap<XML<Index>> {
+    let text = |text: &str| TestXML::Text(text.to_string());
+    TestXML::Element(
+        start_tag("note", &[("lang", "en")]),
+        vec![
+            TestXML::Element("to".to_string(), vec![text("Tove")]),
+            TestXML::Element("body".to_string(), vec![text("Don't forget me!")]),
+        ],
+    )
+    .build_node_map()
+}
+
+/// The expression that the editor starts with when it isn't given a file
+fn default_expr() -> VecNodeMap<Expr<Index>> {
+    Expr::parse_into("(x + 1) * 2 == y && !done").unwrap()
+}
+
+/// The options that apply to the editor regardless of the language being edited
+struct Options {
+    path: Option<String>,
+    keymap_path: Option<PathBuf>,
+    config_path: Option<PathBuf>,
+    /// The settings given on the command line, which override those in the config file
+    settings: Vec<Setting>,
+    /// Whether the tree can only be browsed, not edited
+    read_only: bool,
+    /// Whether the tree starts off with unsaved changes (because it was restored from a session)
+    dirty: bool,
+    /// Whether Sapling is a filter in a pipeline, so the tree is written to stdout when Sapling quits
+    pipe: bool,
+    /// How many edits are made between backups, or [`None`] to not count edits
+    auto_save_edits: Option<usize>,
+    /// A warning to show once the editor has started, e.g. because the language of the file
+    /// couldn't be inferred
+    warning: Option<String>,
+}
+
+/// Start an [`Editor`] on `tree`, and exit the process if it fails
+fn run_editor<Node: ASTSpec<Index>>(
+    tree: Spec<Node>,
+    format_style: Node::FormatStyle,
+    output_format_style: Node::FormatStyle,
+    file_loader: Option<FileLoader<Spec<Node>>>,
+    clipboard_format: Option<(Node::FormatStyle, ClipboardParser<Node>)>,
+    options: Options,
+) {
+    // Stdin can't be written back to, so only real files can be saved with `w` (or backed up)
+    let file_path = options.path.filter(|p| p != "-").map(PathBuf::from);
+    let mut builder = Editor::builder(tree)
+        .format_style(format_style)
+        .read_only(options.read_only);
+    if let Some(file_path) = &file_path {
+        builder = builder.file_path(file_path);
+    }
+    // Without a keymap the editor can always be built, so this can't fail
+    let mut editor = builder.build().unwrap();
+    // Like the keymap, the default config file is optional but an explicit one must exist
+    let config_path = options
+        .config_path
+        .or_else(|| Config::default_path().filter(|p| p.exists()));
+    if let Some(config_path) = config_path {
+        editor.load_config(&config_path);
+    }
+    let config = options
+        .settings
+        .into_iter()
+        .fold(editor.config().clone(), Config::apply);
+    editor.set_config(config);
+    if let Some(file_loader) = file_loader {
+        editor.set_file_loader(file_loader);
+    }
+    if let Some((format_style, parser)) = clipboard_format {
+        editor.set_clipboard_format(format_style, parser);
+    }
+    let backup = file_path.as_deref().and_then(backup_path);
+    if let Some(backup) = &backup {
+        let mut auto_save = AutoSave::new(backup);
+        auto_save.set_interval(editor.config().auto_save_interval);
+        auto_save.set_edit_threshold(options.auto_save_edits);
+        editor.set_auto_save(Some(auto_save));
+    }
+    if options.dirty {
+        editor.mark_unsaved();
+    }
+    if options.pipe {
+        editor.set_pipe_output(Some(output_format_style));
+    }
+    if let Some(warning) = options.warning {
+        editor.set_message(warning, LogLevel::Warning);
+    }
+    // The default keymap file is optional, but a warning is shown if an explicit one is missing
+    let keymap_path = options
+        .keymap_path
+        .or_else(|| KeyMap::default_path().filter(|p| p.exists()));
+    if let Some(keymap_path) = keymap_path {
+        editor.load_keymap(&keymap_path);
+    }
+    if let Err(e) = editor.run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    // Sapling closed properly, so any changes that weren't written were discarded on purpose
+    if let Some(backup) = &backup {
+        let _ = std::fs::remove_file(backup);
+    }
+    // The UI is drawn on `/dev/tty`, so stdout only ever contains the tree
+    if options.pipe {
+        match editor.piped_text() {
+            Some(text) => println!("{}", text),
+            None => {
+                eprintln!("The tree wasn't written, so nothing was sent to stdout");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut path = None;
+    let mut session_path = None;
+    let mut script = None;
+    let mut keymap_path = None;
+    let mut config_path = None;
+    let mut settings = Vec::new();
+    let mut log_path = std::env::var_os("SAPLING_LOG").map(PathBuf::from);
+    let mut format_style = JSONFormat::Pretty;
+    let mut output_format_style = None;
+    let mut pipe = false;
+    let mut read_only = false;
+    let mut auto_save_edits = Some(50);
+    // Unless a language is given, it's inferred from the file extension
+    let mut language = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--keymap" {
+            keymap_path = Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                eprintln!("'--keymap' needs a path");
+                std::process::exit(1);
+            })));
+        } else if arg == "--config" {
+            config_path = Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                eprintln!("'--config' needs a path");
+                std::process::exit(1);
+            })));
+        } else if arg == "--log" {
+            log_path = Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                eprintln!("'--log' needs a path");
+                std::process::exit(1);
+            })));
+        } else if arg == "--indent" {
+            let style = args.next().as_deref().and_then(IndentStyle::from_arg);
+            settings.push(Setting::Indent(style.unwrap_or_else(|| {
+                eprintln!("'--indent' needs a number of spaces or 'tab'");
+                std::process::exit(1);
+            })));
+        } else if arg == "--format" || arg == "--output-format" {
+            let style = match args.next().as_deref() {
+                Some("pretty") => JSONFormat::Pretty,
+                Some("compact") => JSONFormat::Compact,
+                Some("minified") => JSONFormat::Minified,
+                _ => {
+                    eprintln!("'{}' needs one of 'pretty', 'compact' or 'minified'", arg);
+                    std::process::exit(1);
+                }
+            };
+            if arg == "--format" {
+                format_style = style;
+            } else {
+                output_format_style = Some(style);
+            }
+        } else if arg == "--pipe" {
+            pipe = true;
+        } else if arg == "--session" {
+            session_path = Some(args.next().unwrap_or_else(|| {
+                eprintln!("'--session' needs a path");
+                std::process::exit(1);
+            }));
+        } else if arg == "--script" {
+            script = Some(args.next().unwrap_or_else(|| {
+                eprintln!("'--script' needs a string of commands");
+                std::process::exit(1);
+            }));
+        } else if arg == "--autosave-interval" {
+            let interval = parse_count(&arg, args.next()).map(|s| Duration::from_secs(s as u64));
+            settings.push(Setting::AutoSaveInterval(interval));
+        } else if arg == "--autosave-edits" {
+            auto_save_edits = parse_count(&arg, args.next());
+        } else if arg == "--line-numbers" {
+            settings.push(Setting::LineNumbers(true));
+        } else if arg == "--readonly" {
+            read_only = true;
+        } else if arg == "--language" {
+            let name = args.next();
+            language = Some(
+                name.as_deref()
+                    .and_then(Language::from_name)
+                    .unwrap_or_else(|| {
+                        let names: Vec<_> = Language::ALL.iter().map(|(_, n)| *n).collect();
+                        match name {
+                            Some(name) => eprintln!(
+                                "Unknown language '{}' (expected one of {})",
+                                name,
+                                names.join(", ")
+                            ),
+                            None => eprintln!("'--language' needs one of {}", names.join(", ")),
+                        }
+                        std::process::exit(1);
+                    }),
+            );
+        } else if arg == "--lisp" {
+            language = Some(Language::Lisp);
+        } else if arg == "--xml" {
+            language = Some(Language::Xml);
+        } else if arg == "--help" || arg == "-h" {
+            print!("{}", USAGE);
+            return;
+        } else if is_option(&arg) {
+            // Without this, a mistyped option would be opened as a file that doesn't exist
+            eprintln!("Unknown option '{}' (see 'sapling --help')", arg);
+            std::process::exit(1);
+        } else {
+            path = Some(arg);
+        }
+    }
+    // Logging is only for debugging, so nothing is recorded unless a log file is given
+    if let Some(log_path) = &log_path {
+        FileLogger::install(log_path).unwrap_or_else(|e| {
+            eprintln!("Couldn't open log file '{}': {}", log_path.display(), e);
+            std::process::exit(1);
+        });
+    }
+    // Scripts edit the document without a terminal, writing the result to stdout
+    if let Some(commands) = script {
+        if session_path.is_some() || pipe || matches!(language, Some(l) if l != Language::Json) {
+            eprintln!("'--script' can only edit JSON from stdin or a file");
+            std::process::exit(1);
+        }
+        let path = path.unwrap_or_else(|| "-".to_string());
+        let text = read_input(&path).unwrap_or_else(|e| {
+            eprintln!("Couldn't read '{}': {}", path, e);
+            std::process::exit(1);
+        });
+        let style = output_format_style.unwrap_or(format_style);
+        match script::apply(&text, &commands, DocumentSpec::json(style)) {
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // Being piped into with no file to open (like `curl ... | sapling | jq .`) always means
+    // filtering stdin, even without `--pipe`
+    if path.is_none()
+        && session_path.is_none()
+        && matches!(language, None | Some(Language::Json))
+        && !std::io::stdin().is_terminal()
+    {
+        pipe = true;
+    }
+    if pipe {
+        if path.is_some() {
+            eprintln!("'--pipe' reads from stdin, so can't be used with a file");
+            std::process::exit(1);
+        }
+        path = Some("-".to_string());
+    }
+    // The tree is written in the style it's displayed in, unless another style is asked for
+    let output_format_style = output_format_style.unwrap_or(format_style);
+    let mut warning = None;
+    let language = match (language, &path) {
+        (Some(language), _) => language,
+        (None, Some(path)) if path != "-" => {
+            Language::from_path(Path::new(path)).unwrap_or_else(|| {
+                warning = Some(format!(
+                    "Couldn't tell the language of '{}' from its extension, so it's being \
+                     edited as JSON (use --language to choose another)",
+                    path
+                ));
+                Language::Json
+            })
+        }
+        _ => Language::Json,
+    };
+    let mut options = Options {
+        path,
+        keymap_path,
+        config_path,
+        settings,
+        read_only,
+        dirty: false,
+        pipe,
+        auto_save_edits,
+        warning,
+    };
+    if let Some(session_path) = session_path {
+        if language != Language::Json || options.path.is_some() {
+            eprintln!("'--session' can't be used with a file, a pipe or another language");
+            std::process::exit(1);
+        }
+        let session = read_input(&session_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Session::from_text(&text))
+            .unwrap_or_else(|e| {
+                eprintln!("Couldn't read session '{}': {}", session_path, e);
+                std::process::exit(1);
+            });
+        let tree: Spec<JSON<Index>> = session
+            .build_tree(|text| JSON::parse_into(text).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                eprintln!("Couldn't restore session '{}': {}", session_path, e);
+                std::process::exit(1);
+            });
+        options.path = session
+            .file_path
+            .as_ref()
+            .map(|p| p.to_string_lossy().into_owned());
+        options.dirty = session.dirty;
+        run_editor(
+            tree,
+            format_style,
+            output_format_style,
+            Some(Box::new(load_json)),
+            Some((JSONFormat::Compact, Box::new(parse_json_subtree))),
+            options,
+        );
+        return;
+    }
+    if language == Language::Expr {
+        // Only the minified style leaves out whitespace, as the compact expression style does
+        let expr_format = |style| match style {
+            JSONFormat::Pretty | JSONFormat::Compact => ExprFormat::Spaced,
+            JSONFormat::Minified => ExprFormat::Compact,
+        };
+        let tree = match &options.path {
+            Some(path) => {
+                let text = read_input(path).unwrap_or_else(|e| {
+                    eprintln!("Couldn't read '{}': {}", path, e);
+                    std::process::exit(1);
+                });
+                Expr::parse_into(&text).unwrap_or_else(|e| {
+                    eprintln!("Couldn't parse '{}': {}", path, e);
+                    std::process::exit(1);
+                })
+            }
+            None => default_expr(),
+        };
+        run_editor(
+            Spec::from_tree(tree),
+            expr_format(format_style),
+            expr_format(output_format_style),
+            Some(Box::new(load_expr)),
+            None,
+            options,
+        );
+        return;
+    }
+    // There's no Lisp or XML parser yet, so those languages always start with a pre-made tree
+    if let Some(path) = options.path.as_ref().filter(|_| language != Language::Json) {
+        eprintln!(
+            "Only JSON files can be opened, so '{}' can't be edited as {}",
+            path,
+            language.name()
+        );
+        std::process::exit(1);
+    }
+    if language == Language::Lisp {
+        // Minified JSON has no Lisp equivalent, so it's shown compactly too
+        let lisp_format = |style| match style {
+            JSONFormat::Pretty => LispFormat::Indented,
+            JSONFormat::Compact | JSONFormat::Minified => LispFormat::Compact,
+        };
+        run_editor(
+            Spec::from_tree(default_lisp()),
+            lisp_format(format_style),
+            lisp_format(output_format_style),
+            None,
+            None,
+            options,
+        );
+        return;
+    }
+    if language == Language::Xml {
+        let xml_format = |style| match style {
+            JSONFormat::Pretty => XMLFormat::Pretty,
+            JSONFormat::Compact | JSONFormat::Minified => XMLFormat::Compact,
+        };
+        run_editor(
+            Spec::from_tree(default_xml()),
+            xml_format(format_style),
+            xml_format(output_format_style),
+            None,
+            None,
+            options,
+        );
+        return;
+    }
+    let start_node_map: VecNodeMap<JSON<Index>> = match &options.path {
+        Some(path) => {
+            // A backup that's newer than the file means that Sapling crashed before the changes
+            // were written
+            let backup = Some(Path::new(path))
+                .filter(|_| path != "-")
+                .and_then(recoverable_backup);
+            let recovered = backup.filter(|_| {
+                confirm(&format!(
+                    "'{}' has unsaved changes from a session that didn't close properly. \
+                     Recover them?",
+                    path
+                ))
+            });
+            let text = match &recovered {
+                Some(backup) => std::fs::read_to_string(backup),
+                None => read_input(path),
+            };
+            let text = text.unwrap_or_else(|e| {
+                eprintln!("Couldn't read '{}': {}", path, e);
+                std::process::exit(1);
+            });
+            // The recovered changes still need to be written to the file
+            options.dirty = recovered.is_some();
+            JSON::parse_into(&text).unwrap_or_else(|e| {
+                eprintln!("Couldn't parse '{}': {}", path, e);
+                std::process::exit(1);
+            })
+        }
+        // With no file to open, start the editor with some pre-made JSON
+        None => default_json(),
+    };
+    run_editor(
+        Spec::from_tree(start_node_map),
+        format_style,
+        output_format_style,
+        Some(Box::new(load_json)),
+        Some((JSONFormat::Compact, Box::new(parse_json_subtree))),
+        options,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_expr, default_json, default_lisp, default_xml, is_option, Language, USAGE,
+    };
+    use sapling::ast_spec::expr::ExprFormat;
+    use sapling::ast_spec::json::JSONFormat;
+    use sapling::ast_spec::lisp::LispFormat;
+    use sapling::ast_spec::xml::XMLFormat;
+    use sapling::ast_spec::ASTSpec;
+    use sapling::editable_tree::spec::Spec;
+    use sapling::editor::frontend::TestFrontend;
+    use sapling::editor::Editor;
+    use sapling::node_map::vec::Index;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tuikit::prelude::{Event, Key};
+
+    #[test]
+    fn language_from_path() {
+        for (path, language) in &[
+            ("data.json", Some(Language::Json)),
+            ("dir/CONFIG.JSON", Some(Language::Json)),
+            ("square.sexp", Some(Language::Lisp)),
+            ("init.lisp", Some(Language::Lisp)),
+            ("note.xml", Some(Language::Xml)),
+            ("sum.EXPR", Some(Language::Expr)),
+            ("notes.txt", None),
+            ("Makefile", None),
+            (".json", None),
+        ] {
+            assert_eq!(Language::from_path(Path::new(path)), *language, "{}", path);
+        }
+        for (language, name) in &Language::ALL {
+            assert_eq!(language.name(), *name);
+            assert_eq!(Language::from_name(name), Some(*language));
+        }
+        assert_eq!(Language::from_name("yaml"), None);
+    }
+
+    /// Run an editor on `tree` until it quits, returning the first line of the screen
+    fn first_line<Node: ASTSpec<Index>>(
+        tree: Spec<Node>,
+        format_style: Node::FormatStyle,
+    ) -> String {
+        let keys = [Key::Char(':'), Key::Char('q'), Key::Enter];
+        let frontend = Arc::new(TestFrontend::new(
+            160,
+            10,
+            keys.iter().map(|k| Event::Key(*k)),
+        ));
+        let mut editor = Editor::with_frontend(tree, format_style, frontend.clone());
+        editor.run().unwrap();
+        let line = frontend.screen()[0].clone();
+        // The log is drawn to the right of the tree
+        line.split("  ").next().unwrap().to_string()
+    }
+
+    #[test]
+    fn default_documents() {
+        assert_eq!(
+            first_line(Spec::from_tree(default_json()), JSONFormat::Compact),
+            r#"[true, false, {"value": true}]"#
+        );
+        assert_eq!(
+            first_line(Spec::from_tree(default_lisp()), LispFormat::Compact),
+            "(define (square x) (* x x))"
+        );
+        assert_eq!(
+            first_line(Spec::from_tree(default_xml()), XMLFormat::Compact),
+            r#"<note lang="en"><to>Tove</to><body>Don't forget me!</body></note>"#
+        );
+        assert_eq!(
+            first_line(Spec::from_tree(default_expr()), ExprFormat::Spaced),
+            "(x + 1) * 2 == y && !done"
+        );
+    }
+
+    #[test]
+    fn options() {
+        for arg in &["--help", "-h", "--bogus", "--", "-x"] {
+            assert!(is_option(arg), "{}", arg);
+        }
+        for arg in &["-", "data.json", "dir/-file", ""] {
+            assert!(!is_option(arg), "{}", arg);
+        }
+        // Every option that `main` accepts is listed
+        let source = include_str!("main.rs");
+        for option in source.split("arg == \"").skip(1) {
+            let option = option.split('"').next().unwrap();
+            assert!(USAGE.contains(option), "{} isn't in the usage", option);
+        }
+    }
 }
@@ -0,0 +1,270 @@
+//! Module containing a `NodeMap` that stores every distinct node only once
+
+use super::vec::{Index, VecNodeMap};
+use super::{GcStats, MapError, MemoryStats, NodeMap, NodeMapMut};
+use crate::ast_spec::ASTSpec;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// An import solely used by doc-comments
+#[allow(unused_imports)]
+use crate::editable_tree::{dag::DAG, spec::Spec};
+
+/// A [`NodeMap`] that hash-conses its nodes: adding a node that is identical to one that is
+/// already stored (i.e. it has the same contents and the same child references) returns the
+/// reference to the stored node instead of storing a copy.  Since children are added before
+/// their parents, identical subtrees end up as the same node, so a tree of thousands of copies of
+/// the same object only stores as many nodes as that object has.
+///
+/// This means that a reference no longer identifies a position in the tree: every copy of a
+/// subtree is the same node, reached from several parents.  Changing a node in place would
+/// change every copy at once, so this map refuses to do it:
+/// [`get_node_mut`](NodeMapMut::get_node_mut) returns [`None`], and
+/// [`overwrite_node`](NodeMapMut::overwrite_node) and
+/// [`supersede_node`](NodeMapMut::supersede_node) return
+/// [`MapError::InPlaceEditUnsupported`].  Trees have to be edited by path copying instead
+/// (adding new copies of the changed node and its ancestors), which is what the [`DAG`] and the
+/// [`Spec`] do.
+#[derive(Debug, Clone)]
+pub struct DedupNodeMap<Node> {
+    /// The nodes, which are all distinct
+    nodes: VecNodeMap<Node>,
+    /// The references of the stored nodes, by the hash of their contents
+    by_hash: HashMap<u64, Vec<Index>>,
+}
+
+/// Returns the structural hash of a node, which covers its contents and the references to its
+/// children
+fn hash_node<Node: Hash>(node: &Node) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    node.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<Node: ASTSpec<Index> + Hash> DedupNodeMap<Node> {
+    /// Returns the reference of the stored node that is identical to `node`, if there is one
+    fn find(&self, node: &Node, hash: u64) -> Option<Index> {
+        self.by_hash
+            .get(&hash)?
+            .iter()
+            .copied()
+            .find(|id| self.nodes.get_node(*id) == Some(node))
+    }
+
+    /// Rebuild `by_hash` from the nodes that are stored
+    fn reindex(&mut self) {
+        self.by_hash.clear();
+        for (id, node) in self.nodes.iter_all() {
+            self.by_hash.entry(hash_node(node)).or_default().push(id);
+        }
+    }
+}
+
+impl<Node: ASTSpec<Index> + Hash> NodeMap<Index, Node> for DedupNodeMap<Node> {
+    #[inline]
+    fn get_node(&self, id: Index) -> Option<&Node> {
+        self.nodes.get_node(id)
+    }
+
+    #[inline]
+    fn root(&self) -> Index {
+        self.nodes.root()
+    }
+
+    fn iter_all(&self) -> Box<dyn Iterator<Item = (Index, &Node)> + '_> {
+        self.nodes.iter_all()
+    }
+}
+
+impl<Node: ASTSpec<Index> + Hash> NodeMapMut<Index, Node> for DedupNodeMap<Node> {
+    fn with_root(root: Node) -> Self {
+        let mut map = DedupNodeMap {
+            nodes: VecNodeMap::with_root(root),
+            by_hash: HashMap::new(),
+        };
+        map.reindex();
+        map
+    }
+
+    fn set_root(&mut self, new_root: Index) -> Result<(), MapError<Index>> {
+        self.nodes.set_root(new_root)
+    }
+
+    /// Always returns [`None`], since the node may be shared
+    fn get_node_mut(&mut self, _id: Index) -> Option<&mut Node> {
+        None
+    }
+
+    fn add_node(&mut self, node: Node) -> Index {
+        let hash = hash_node(&node);
+        if let Some(id) = self.find(&node, hash) {
+            return id;
+        }
+        let id = self.nodes.add_node(node);
+        self.by_hash.entry(hash).or_default().push(id);
+        id
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        // Repetitive trees need far fewer nodes than they have, but the hint is still an upper
+        // bound
+        self.nodes.reserve(additional);
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+        self.by_hash.shrink_to_fit();
+    }
+
+    /// Counts the nodes like a [`VecNodeMap`] does, as well as the index used to find them
+    fn memory_usage(&self) -> MemoryStats {
+        let mut stats = self.nodes.memory_usage();
+        stats.bytes += self.by_hash.capacity() * std::mem::size_of::<(u64, Vec<Index>)>()
+            + self
+                .by_hash
+                .values()
+                .map(|ids| ids.capacity() * std::mem::size_of::<Index>())
+                .sum::<usize>();
+        stats
+    }
+
+    fn overwrite_node(&mut self, id: Index, _node: Node) -> Result<(), MapError<Index>> {
+        Err(MapError::InPlaceEditUnsupported(id))
+    }
+
+    fn supersede_node(&mut self, id: Index, _node: Node) -> Result<Index, MapError<Index>> {
+        Err(MapError::InPlaceEditUnsupported(id))
+    }
+
+    fn remove_node(&mut self, id: Index) -> Result<Node, MapError<Index>> {
+        let node = self.nodes.remove_node(id)?;
+        if let Some(ids) = self.by_hash.get_mut(&hash_node(&node)) {
+            ids.retain(|other| *other != id);
+        }
+        Ok(node)
+    }
+
+    /// Packs the kept nodes together like a [`VecNodeMap`] does.  Nodes that move have their
+    /// children remapped, which changes their hashes, so the whole index is rebuilt afterwards.
+    fn collect_garbage(&mut self, live_roots: &[Index]) -> GcStats<Index> {
+        let stats = self.nodes.collect_garbage(live_roots);
+        self.reindex();
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DedupNodeMap;
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{MapError, NodeMap, NodeMapMut};
+
+    type Map = DedupNodeMap<JSON<Index>>;
+
+    /// An array of `count` copies of `{"enabled": true}`
+    fn repetitive_tree(count: usize) -> TestJSON {
+        let object = TestJSON::Object(vec![("enabled".to_string(), TestJSON::True)]);
+        TestJSON::Array(vec![object; count])
+    }
+
+    /// The compact text of [`repetitive_tree`]
+    fn repetitive_text(count: usize) -> String {
+        format!("[{}]", vec![r#"{"enabled": true}"#; count].join(", "))
+    }
+
+    #[test]
+    fn identical_subtrees_are_stored_once() {
+        let tree = repetitive_tree(10_000);
+        let deduplicated: Map = tree.build_node_map();
+        let plain: VecNodeMap<JSON<Index>> = tree.build_node_map();
+        // The placeholder root, the array, and one object, field, key and value
+        assert_eq!(deduplicated.iter_all().count(), 6);
+        assert_eq!(plain.iter_all().count(), 40_002);
+        // Sharing the nodes doesn't change the tree or how it looks
+        assert_eq!(TestJSON::from_node_map(&deduplicated), tree);
+        for style in &[JSONFormat::Compact, JSONFormat::Pretty] {
+            assert_eq!(deduplicated.to_text(style), plain.to_text(style));
+        }
+        // Every distinct shape is stored once, however many times it appears
+        let mixed: Map =
+            TestJSON::Array(vec![repetitive_tree(3), repetitive_tree(5)]).build_node_map();
+        assert_eq!(mixed.iter_all().count(), 8);
+    }
+
+    #[test]
+    fn adding_nodes() {
+        let mut map = Map::with_root(JSON::Null);
+        let a = map.add_node(JSON::True);
+        assert_eq!(map.add_node(JSON::True), a);
+        assert_ne!(map.add_node(JSON::False), a);
+        // Nodes are identical only if their children are the same nodes
+        let array = map.add_node(JSON::Array(vec![a, a]));
+        assert_eq!(map.add_node(JSON::Array(vec![a, a])), array);
+        assert_ne!(map.add_node(JSON::Array(vec![a])), array);
+        // Removed nodes are added again from scratch
+        assert_eq!(map.remove_node(array), Ok(JSON::Array(vec![a, a])));
+        let new_array = map.add_node(JSON::Array(vec![a, a]));
+        assert_ne!(new_array, array);
+        assert_eq!(map.get_node(array), None);
+        assert_eq!(map.get_node(new_array), Some(&JSON::Array(vec![a, a])));
+    }
+
+    #[test]
+    fn garbage_collection_keeps_nodes_shared() {
+        let mut map = Map::with_root(JSON::Null);
+        let garbage = map.add_node(JSON::Str("unused".to_string()));
+        let leaf = map.add_node(JSON::True);
+        let root = map.add_node(JSON::Array(vec![leaf, leaf]));
+        map.set_root(root).unwrap();
+        let stats = map.collect_garbage(&[]);
+        assert_eq!((stats.freed, stats.kept), (2, 2));
+        assert_eq!(map.get_node(garbage), None);
+        // The moved nodes can still be found, so adding them again doesn't copy them
+        let leaf = stats.remap(leaf);
+        assert_eq!(map.add_node(JSON::True), leaf);
+        assert_eq!(map.add_node(JSON::Array(vec![leaf, leaf])), map.root());
+        assert_eq!(map.iter_all().count(), 2);
+    }
+
+    #[test]
+    fn nodes_cannot_be_changed_in_place() {
+        let mut map: Map = repetitive_tree(2).build_node_map();
+        let root = map.root();
+        let object = map.root_node().children()[0];
+        assert_eq!(map.get_node_mut(object), None);
+        assert_eq!(
+            map.overwrite_node(object, JSON::Null),
+            Err(MapError::InPlaceEditUnsupported(object))
+        );
+        assert_eq!(
+            map.supersede_node(root, JSON::Null),
+            Err(MapError::InPlaceEditUnsupported(root))
+        );
+        // Nothing was changed
+        assert_eq!(map.root(), root);
+        assert_eq!(map.to_text(&JSONFormat::Compact), repetitive_text(2));
+    }
+
+    #[test]
+    fn edited_by_spec() {
+        let mut tree: Spec<JSON<Index>, Index, Map> =
+            Spec::from_tree(repetitive_tree(3).build_node_map());
+        assert!(tree.move_to_path(&[1, 0, 1]));
+        tree.replace_cursor(JSON::False);
+        assert!(tree.move_to_path(&[2]));
+        tree.delete_cursor().unwrap();
+        assert_eq!(
+            tree.to_text(&JSONFormat::Compact),
+            r#"[{"enabled": true}, {"enabled": false}]"#
+        );
+        assert!(tree.undo());
+        assert!(tree.undo());
+        assert_eq!(tree.to_text(&JSONFormat::Compact), repetitive_text(3));
+    }
+}
@@ -0,0 +1,309 @@
+//! Comparing two versions of a tree, to report which nodes were added, removed or replaced
+
+use super::{NodeMap, Reference};
+use crate::ast_spec::{subtrees_equal, ASTSpec};
+use std::fmt;
+
+/// A difference between two versions of a tree.  Each node is described by its path from the
+/// root (e.g. `root[2].value`) and its [`display_name`](ASTSpec::display_name).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Change {
+    /// A node (along with its subtree) that is only in the new version
+    Added { path: String, name: String },
+    /// A node (along with its subtree) that is only in the old version.  Its path is where it
+    /// was in the old version.
+    Removed { path: String, name: String },
+    /// A node that was replaced by a different kind of node, or by the same kind of node with
+    /// different contents (e.g. a string with different text)
+    Replaced {
+        path: String,
+        before: String,
+        after: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { path, name } => write!(f, "added {}: {}", path, name),
+            Change::Removed { path, name } => write!(f, "removed {}: {}", path, name),
+            Change::Replaced {
+                path,
+                before,
+                after,
+            } => write!(f, "replaced {}: {} with {}", path, before, after),
+        }
+    }
+}
+
+/// Returns `true` if `a` and `b` are the same node apart from their children (e.g. two arrays,
+/// or two equal strings), so that their children can be compared one by one
+fn equal_apart_from_children<Ref: Reference, Node: ASTSpec<Ref>>(a: &Node, b: &Node) -> bool {
+    // Remove as many children as possible, since the two nodes may not have the same number
+    let without_children = |node: &Node| {
+        let mut node = node.clone();
+        while let Some(last) = node.children().len().checked_sub(1) {
+            if !node.remove_child(last) {
+                break;
+            }
+        }
+        node
+    };
+    let (mut a, b) = (without_children(a), without_children(b));
+    if a.children().len() != b.children().len() {
+        return false;
+    }
+    // Children that can't be removed (e.g. the key and value of a field) are compared separately
+    a.children_mut().copy_from_slice(b.children());
+    a == b
+}
+
+/// The work left to do while diffing, in the order that it will be done
+enum Step<Ref> {
+    /// Compare the old and new subtrees rooted at these nodes, whose path is the string
+    Compare(Ref, Ref, String),
+    /// Report a change that has already been found
+    Report(Change),
+}
+
+/// Compare the old version of a tree (the subtree of `old_map` rooted at `old_root`) with the
+/// new version (rooted at `new_root` in `new_map`), returning the changes in the order that they
+/// appear in the text.  Moves aren't detected, so moving a node is reported as removing it and
+/// adding it somewhere else.
+///
+/// Both versions are walked together, skipping every pair of subtrees that are equal.  The
+/// children of two matching nodes are lined up by skipping the equal children at the start and
+/// end of both, and then pairing the rest off in order (unless the next child shows that one
+/// child was added or removed).  Any that are left over were added or removed.
+///
+/// If both versions are stored in the same [`NodeMap`] (e.g. the versions of a
+/// [`DAG`](crate::editable_tree::dag::DAG)), subtrees are only equal if they are the same node,
+/// which is checked in constant time.  This relies on unchanged subtrees being shared between
+/// the versions, rather than copied.  Otherwise, subtrees are compared with [`subtrees_equal`].
+pub fn diff<Ref: Reference, Node: ASTSpec<Ref>>(
+    old_map: &impl NodeMap<Ref, Node>,
+    old_root: Ref,
+    new_map: &impl NodeMap<Ref, Node>,
+    new_root: Ref,
+) -> Vec<Change> {
+    let same_map = std::ptr::eq(
+        old_map as *const _ as *const (),
+        new_map as *const _ as *const (),
+    );
+    let equal = |old: Ref, new: Ref| {
+        if same_map {
+            old == new
+        } else {
+            subtrees_equal(old_map, old, new_map, new)
+        }
+    };
+    let name =
+        |node: Option<&Node>| node.map_or_else(|| "<invalid>".to_string(), Node::display_name);
+
+    let mut changes = Vec::new();
+    let mut steps = vec![Step::Compare(old_root, new_root, "root".to_string())];
+    while let Some(step) = steps.pop() {
+        let (old, new, path) = match step {
+            Step::Compare(old, new, path) => (old, new, path),
+            Step::Report(change) => {
+                changes.push(change);
+                continue;
+            }
+        };
+        if equal(old, new) {
+            continue;
+        }
+        let (old_node, new_node) = match (old_map.get_node(old), new_map.get_node(new)) {
+            (Some(old_node), Some(new_node)) if equal_apart_from_children(old_node, new_node) => {
+                (old_node, new_node)
+            }
+            (old_node, new_node) => {
+                changes.push(Change::Replaced {
+                    path,
+                    before: name(old_node),
+                    after: name(new_node),
+                });
+                continue;
+            }
+        };
+
+        let (old_children, new_children) = (old_node.children(), new_node.children());
+        let shortest = old_children.len().min(new_children.len());
+        let start = (0..shortest)
+            .take_while(|&i| equal(old_children[i], new_children[i]))
+            .count();
+        let end_len = (0..shortest - start)
+            .take_while(|&i| {
+                let old = old_children[old_children.len() - 1 - i];
+                equal(old, new_children[new_children.len() - 1 - i])
+            })
+            .count();
+        let (old_end, new_end) = (old_children.len() - end_len, new_children.len() - end_len);
+        let old_path = |index| format!("{}{}", path, old_node.child_path_segment(index, old_map));
+        let new_path = |index| format!("{}{}", path, new_node.child_path_segment(index, new_map));
+        let added = |index: usize| {
+            Step::Report(Change::Added {
+                path: new_path(index),
+                name: name(new_map.get_node(new_children[index])),
+            })
+        };
+        let removed = |index: usize| {
+            Step::Report(Change::Removed {
+                path: old_path(index),
+                name: name(old_map.get_node(old_children[index])),
+            })
+        };
+
+        // Line up the children in between, looking one child ahead for a single child that was
+        // added or removed
+        let mut child_steps = Vec::new();
+        let (mut i, mut j) = (start, start);
+        while i < old_end && j < new_end {
+            if equal(old_children[i], new_children[j]) {
+                i += 1;
+                j += 1;
+            } else if j + 1 < new_end && equal(old_children[i], new_children[j + 1]) {
+                child_steps.push(added(j));
+                j += 1;
+            } else if i + 1 < old_end && equal(old_children[i + 1], new_children[j]) {
+                child_steps.push(removed(i));
+                i += 1;
+            } else {
+                child_steps.push(Step::Compare(old_children[i], new_children[j], new_path(j)));
+                i += 1;
+                j += 1;
+            }
+        }
+        child_steps.extend((i..old_end).map(removed));
+        child_steps.extend((j..new_end).map(added));
+        // The steps are done from the end of the stack, so the first child comes last
+        steps.extend(child_steps.into_iter().rev());
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use crate::ast_spec::json::JSON;
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::editable_tree::{dag::DAG, EditableTree};
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::NodeMap;
+
+    /// Returns the changes between two trees, one per line
+    fn changes(before: TestJSON, after: TestJSON) -> Vec<String> {
+        let old: VecNodeMap<JSON<Index>> = before.build_node_map();
+        let new: VecNodeMap<JSON<Index>> = after.build_node_map();
+        diff(&old, old.root(), &new, new.root())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn object(fields: Vec<(&str, TestJSON)>) -> TestJSON {
+        TestJSON::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    fn string(s: &str) -> TestJSON {
+        TestJSON::Str(s.to_string())
+    }
+
+    #[test]
+    fn changes_between_trees() {
+        use TestJSON::{Array, False, Null, True};
+        let tree = || {
+            object(vec![
+                ("name", string("a")),
+                ("tags", Array(vec![True, Null])),
+            ])
+        };
+        assert!(changes(tree(), tree()).is_empty());
+        // Changed values are reported where they are
+        assert_eq!(
+            changes(
+                tree(),
+                object(vec![
+                    ("name", string("b")),
+                    ("tags", Array(vec![True, False]))
+                ])
+            ),
+            vec![
+                r#"replaced root.name: "a" with "b""#,
+                "replaced root.tags[1]: null with false"
+            ]
+        );
+        // Children added or removed in one place don't change their siblings
+        assert_eq!(
+            changes(
+                Array(vec![True, False, Null]),
+                Array(vec![True, string("x"), string("y"), False, Null])
+            ),
+            vec![r#"added root[1]: "x""#, r#"added root[2]: "y""#]
+        );
+        assert_eq!(
+            changes(Array(vec![True, False, Null]), Array(vec![Null])),
+            vec!["removed root[0]: true", "removed root[1]: false"]
+        );
+        // Renaming a key replaces it, and fields are named by the new key
+        assert_eq!(
+            changes(
+                tree(),
+                object(vec![
+                    ("title", string("a")),
+                    ("tags", Array(vec![True, Null]))
+                ])
+            ),
+            vec![r#"replaced root.title(key): "name" with "title""#]
+        );
+        // A different kind of node replaces the whole subtree, however much they have in common
+        assert_eq!(
+            changes(tree(), Array(vec![tree()])),
+            vec!["replaced root: object with array"]
+        );
+        // Moving a node removes it from one place and adds it to another
+        assert_eq!(
+            changes(
+                Array(vec![True, False, Null]),
+                Array(vec![Null, True, False])
+            ),
+            vec!["added root[0]: null", "removed root[2]: null"]
+        );
+        assert_eq!(
+            changes(Array(vec![tree(), True]), Array(vec![False, tree()])),
+            vec!["added root[0]: false", "removed root[1]: true"]
+        );
+    }
+
+    #[test]
+    fn shared_subtrees_are_skipped() {
+        // A big tree, with one edit to a node deep inside it
+        let big = TestJSON::Array(
+            (0..1_000)
+                .map(|i| {
+                    object(vec![
+                        ("id", TestJSON::Number(i.to_string())),
+                        ("ok", TestJSON::True),
+                    ])
+                })
+                .collect(),
+        );
+        let mut tree: DAG<JSON<Index>> = DAG::from_tree(big.build_node_map());
+        let old_root = tree.root();
+        assert!(tree.move_to_path(&[500, 1, 1]));
+        tree.replace_cursor(JSON::False);
+        // Only follows the path to the edit, since everything else is shared with the old version
+        assert_eq!(
+            diff(&tree, old_root, &tree, tree.root())
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["replaced root[500].ok: true with false"]
+        );
+    }
+}
@@ -1,8 +1,16 @@
 //! A module to house the traits and implementations for `NodeMap`s.
 
+pub mod dedup;
+pub mod diff;
+pub mod slab;
+pub mod subtree;
+pub mod traversal;
 pub mod vec;
 
 use crate::ast_spec::ASTSpec;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 
 // Imports used solely for doc-comments
 #[allow(unused_imports)]
@@ -11,6 +19,104 @@ use crate::editable_tree::EditableTree;
 /// A trait bound that specifies what types can be used as a reference to a Node in an [`NodeMap`]
 pub trait Reference: Copy + Eq + std::fmt::Debug + std::hash::Hash {}
 
+/// The reasons why [`NodeMapMut`] operations can fail
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MapError<Ref: Reference> {
+    /// The reference doesn't point to a node in the map: either it never did, or the node has
+    /// since been removed or [superseded](NodeMapMut::supersede_node), making the reference stale
+    InvalidReference(Ref),
+    /// The reference points to the root, which can't be removed because a map always has one
+    CannotRemoveRoot(Ref),
+    /// The map can't change the node at this reference in place, e.g. because it may be shared
+    /// by several parents (see [`DedupNodeMap`](dedup::DedupNodeMap))
+    InPlaceEditUnsupported(Ref),
+}
+
+impl<Ref: Reference> fmt::Display for MapError<Ref> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::InvalidReference(id) => {
+                write!(f, "{:?} doesn't refer to a node (it may be stale)", id)
+            }
+            MapError::CannotRemoveRoot(id) => {
+                write!(f, "{:?} is the root, which can't be removed", id)
+            }
+            MapError::InPlaceEditUnsupported(id) => {
+                write!(f, "{:?} can't be changed in place by this map", id)
+            }
+        }
+    }
+}
+
+impl<Ref: Reference> Error for MapError<Ref> {}
+
+/// The result of [`NodeMapMut::collect_garbage`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GcStats<Ref: Reference> {
+    /// The number of nodes that were freed
+    pub freed: usize,
+    /// The number of nodes that are still stored
+    pub kept: usize,
+    /// The new references of the kept nodes that had to move.  Kept nodes that aren't mentioned
+    /// keep their old references.
+    pub remapping: HashMap<Ref, Ref>,
+}
+
+impl<Ref: Reference> GcStats<Ref> {
+    /// Returns the reference that the node at `id` has after the garbage collection
+    pub fn remap(&self, id: Ref) -> Ref {
+        self.remapping.get(&id).copied().unwrap_or(id)
+    }
+}
+
+/// How much memory a [`NodeMap`] uses, as returned by [`NodeMapMut::memory_usage`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct MemoryStats {
+    /// The number of nodes that are stored, whether or not they are reachable from the root
+    pub nodes_stored: usize,
+    /// The number of distinct nodes that are reachable from the root
+    pub nodes_reachable: usize,
+    /// The approximate number of bytes used by the map, including the space reserved for more
+    /// nodes and the data that the nodes own on the heap (see [`ASTSpec::heap_size`])
+    pub bytes: usize,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes stored ({} reachable) in about ",
+            self.nodes_stored, self.nodes_reachable
+        )?;
+        let bytes = self.bytes as f64;
+        if self.bytes < 1 << 10 {
+            write!(f, "{} bytes", self.bytes)
+        } else if self.bytes < 1 << 20 {
+            write!(f, "{:.1} KiB", bytes / 1024.0)
+        } else {
+            write!(f, "{:.1} MiB", bytes / (1024.0 * 1024.0))
+        }
+    }
+}
+
+/// Returns every node reachable from any of `roots` in `node_map`.  References that don't point
+/// to nodes are ignored, as are cycles.
+fn reachable_nodes<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    roots: impl IntoIterator<Item = Ref>,
+) -> HashSet<Ref> {
+    let mut reachable = HashSet::new();
+    let mut refs_to_visit: Vec<Ref> = roots.into_iter().collect();
+    while let Some(r) = refs_to_visit.pop() {
+        if let Some(node) = node_map.get_node(r) {
+            if reachable.insert(r) {
+                refs_to_visit.extend(node.children().iter().copied());
+            }
+        }
+    }
+    reachable
+}
+
 /// A trait bound for a type that can be used to access nodes (used to give [`NodeMap`]-like
 /// attributes to [`EditableTree`]s).  If you need to be able to change nodes, see [`NodeMapMut`].
 pub trait NodeMap<Ref: Reference, Node: ASTSpec<Ref>> {
@@ -26,9 +132,36 @@ pub trait NodeMap<Ref: Reference, Node: ASTSpec<Ref>> {
         // We can unwrap here, because self.root() is required to be a valid reference.
         self.get_node(self.root()).unwrap()
     }
+
+    /// Returns a [`PreOrder`](traversal::PreOrder) traversal over every node in the current tree,
+    /// in the order that they appear in the text
+    fn preorder(&self) -> traversal::PreOrder<'_, Ref, Node, Self>
+    where
+        Self: Sized,
+    {
+        traversal::PreOrder::new(self, self.root())
+    }
+
+    /// Iterate over every node stored in the map (whether or not it is reachable from the root),
+    /// along with its reference.  The order is unspecified.
+    fn iter_all(&self) -> Box<dyn Iterator<Item = (Ref, &Node)> + '_>;
+
+    /// Returns a [`Reachable`](traversal::Reachable) traversal over every distinct node that can
+    /// be reached from `root`, in the order that they first appear in the text.  Unlike
+    /// [`preorder`](NodeMap::preorder), this visits shared nodes once and is safe to use on maps
+    /// that contain cycles.
+    fn iter_reachable(&self, root: Ref) -> traversal::Reachable<'_, Ref, Node, Self>
+    where
+        Self: Sized,
+    {
+        traversal::Reachable::new(self, root)
+    }
 }
 
-/// A trait bound for a type that can store `Node`s, accessible by references.
+/// A trait bound for a type that can store `Node`s, accessible by references.  This is the
+/// interface between the [`EditableTree`]s and whatever is used to store their nodes, so any type
+/// implementing it can be used as a storage backend (see [`VecNodeMap`](vec::VecNodeMap) for the
+/// default one).
 pub trait NodeMapMut<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> {
     /// Create a new `NodeMap` with a given `Node` as root
     fn with_root(root: Node) -> Self;
@@ -41,16 +174,15 @@ pub trait NodeMapMut<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> {
         Self::with_root(Node::default())
     }
 
-    /// Set the root of the tree to be the node at a given reference, returning `true` if the
-    /// reference was valid.  If the reference was invalid, the root will not be replaced and
-    /// `false` will be returned.
-    fn set_root(&mut self, new_root: Ref) -> bool;
+    /// Set the root of the tree to be the node at a given reference.  If the reference is
+    /// invalid, the root is not replaced and [`MapError::InvalidReference`] is returned.
+    fn set_root(&mut self, new_root: Ref) -> Result<(), MapError<Ref>>;
 
     /// Adds a new node and set it to the tree's root
     fn add_as_root(&mut self, new_root_node: Node) -> Ref {
         let r = self.add_node(new_root_node);
-        let is_valid = self.set_root(r);
-        debug_assert!(is_valid);
+        let result = self.set_root(r);
+        debug_assert_eq!(result, Ok(()));
         r
     }
 
@@ -84,7 +216,475 @@ pub trait NodeMapMut<Ref: Reference, Node: ASTSpec<Ref>>: NodeMap<Ref, Node> {
     /// Add a new `Node` to the tree, and return its reference
     fn add_node(&mut self, node: Node) -> Ref;
 
-    /// Overwrite a node currently in the tree with another one.  Returns 'true' if `id` points to
-    /// an existing node, if not it will return 'false' and not do the subsitution.
-    fn overwrite_node(&mut self, id: Ref, node: Node) -> bool;
+    /// Make space for at least `additional` more nodes to be added without reallocating.  This is
+    /// only a hint, so by default it does nothing.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Create a new `NodeMap` containing only the default node as root, with space for at least
+    /// `capacity` nodes (including the root) before it has to reallocate
+    fn with_capacity(capacity: usize) -> Self
+    where
+        Self: Sized,
+    {
+        let mut node_map = Self::with_default_root();
+        node_map.reserve(capacity.saturating_sub(1));
+        node_map
+    }
+
+    /// Free as much of the space reserved for more nodes as possible.  Like
+    /// [`reserve`](NodeMapMut::reserve), this does nothing by default.
+    fn shrink_to_fit(&mut self) {}
+
+    /// Returns how many nodes this map stores and roughly how much memory they use.  By default,
+    /// this counts the size of every stored node and the data it owns, but not the space that
+    /// the map reserves or uses to keep track of them.
+    fn memory_usage(&self) -> MemoryStats
+    where
+        Self: Sized,
+    {
+        let mut stats = MemoryStats {
+            nodes_reachable: reachable_nodes(self, Some(self.root())).len(),
+            ..MemoryStats::default()
+        };
+        for (_, node) in self.iter_all() {
+            stats.nodes_stored += 1;
+            stats.bytes += std::mem::size_of::<Node>() + node.heap_size();
+        }
+        stats
+    }
+
+    /// Overwrite a node currently in the tree with another one.  If `id` doesn't point to an
+    /// existing node, nothing is substituted and [`MapError::InvalidReference`] is returned.
+    fn overwrite_node(&mut self, id: Ref, node: Node) -> Result<(), MapError<Ref>>;
+
+    /// Replace the node at `id` with `node`, returning the reference to the new node.  Unlike
+    /// [`overwrite_node`](NodeMapMut::overwrite_node), which edits a node in place, this makes a
+    /// new node: maps that can tell references apart make the old references stale, so that
+    /// references held from before the replacement (e.g. across an edit) stop resolving rather
+    /// than silently reaching the new node.  If `id` is the root, the new node becomes the root.
+    /// Returns [`MapError::InvalidReference`] (and doesn't replace anything) if `id` is invalid.
+    fn supersede_node(&mut self, id: Ref, node: Node) -> Result<Ref, MapError<Ref>> {
+        self.overwrite_node(id, node).map(|()| id)
+    }
+
+    /// Remove a node from the map and return it.  References to the removed node become invalid,
+    /// but all other references stay valid.  Nothing is removed if `id` is invalid
+    /// ([`MapError::InvalidReference`]) or refers to the root ([`MapError::CannotRemoveRoot`]),
+    /// since the root must always be valid.
+    fn remove_node(&mut self, id: Ref) -> Result<Node, MapError<Ref>>;
+
+    /// Free every node that can't be reached from the root or from any of `live_roots` (e.g. the
+    /// roots of old versions of the tree that are kept for undoing).  The map may move the nodes
+    /// that it keeps to pack them together, in which case references to them must be updated with
+    /// [`GcStats::remap`].  References to the freed nodes become invalid.  Nodes are never
+    /// copied or merged, so every kept node is reached through its new reference with its
+    /// children (also remapped) in the same order.
+    ///
+    /// By default, this removes the garbage with [`remove_node`](NodeMapMut::remove_node), so
+    /// the kept nodes don't move.
+    fn collect_garbage(&mut self, live_roots: &[Ref]) -> GcStats<Ref>
+    where
+        Self: Sized,
+    {
+        let root = self.root();
+        let reachable = reachable_nodes(self, live_roots.iter().copied().chain(Some(root)));
+        let garbage: Vec<Ref> = self
+            .iter_all()
+            .map(|(id, _)| id)
+            .filter(|id| !reachable.contains(id))
+            .collect();
+        for id in &garbage {
+            // Garbage is never the root, and every reference comes from `iter_all`
+            self.remove_node(*id).unwrap();
+        }
+        GcStats {
+            freed: garbage.len(),
+            kept: reachable.len(),
+            remapping: HashMap::new(),
+        }
+    }
+
+    /// Add a deep copy of the subtree rooted at `root` to this map, returning the reference to
+    /// the copy of `root` (see [`copy_subtree`]).  The copy isn't attached to the tree.  Returns
+    /// [`None`] (and adds nothing) if the subtree contains invalid references or cycles.
+    fn clone_subtree(&mut self, root: Ref) -> Option<Ref>
+    where
+        Self: Sized,
+    {
+        let nodes = subtree_nodes(self, root)?;
+        Some(add_copies(self, nodes))
+    }
+
+    /// Add every node of an owned `tree` to this map in one go, returning the reference to its
+    /// root.  Children are added before their parents, so every node refers to nodes that already
+    /// exist.  The new subtree isn't attached to anything, but can be made the root with
+    /// [`set_root`](NodeMapMut::set_root).  This doesn't recurse, so works for arbitrarily deep
+    /// trees.
+    fn add_tree<T: TreeBuilder<Ref, Node>>(&mut self, tree: T) -> Ref
+    where
+        Self: Sized,
+    {
+        self.reserve(tree.node_count());
+        // A post-order traversal, where each entry is a subtree, its children that haven't been
+        // added yet, and the references to the children that have
+        let new_entry = |tree: T| {
+            let children = tree.children().into_iter();
+            (tree, children, Vec::new())
+        };
+        let mut stack = vec![new_entry(tree)];
+        loop {
+            // The stack is only empty once the root has been added, which returns
+            let (_, unadded_children, _) = stack.last_mut().unwrap();
+            if let Some(child) = unadded_children.next() {
+                stack.push(new_entry(child));
+                continue;
+            }
+            let (tree, _, children) = stack.pop().unwrap();
+            let id = self.add_node(tree.build_node(children));
+            match stack.last_mut() {
+                Some((_, _, siblings)) => siblings.push(id),
+                None => return id,
+            }
+        }
+    }
+}
+
+/// A tree which owns its nodes, rather than referring to them through a [`NodeMap`], and so can
+/// be added to a map with [`NodeMapMut::add_tree`].  This is implemented by the (sub)trees that
+/// make up the tree, and can be implemented by a lightweight view (e.g. a reference into a
+/// recursive `enum`), since subtrees are only ever inspected through `&self`.
+pub trait TreeBuilder<Ref: Reference, Node: ASTSpec<Ref>>: Sized {
+    /// Returns the number of nodes that this tree will add to a map, which is used to reserve
+    /// space for them.  This doesn't have to be exact.
+    fn node_count(&self) -> usize;
+
+    /// Returns the subtrees that will become the children of this tree's root node, in order
+    fn children(&self) -> Vec<Self>;
+
+    /// Builds this tree's root node, given the references to its (already added) children, in
+    /// the same order as [`children`](TreeBuilder::children) returned them
+    fn build_node(&self, children: Vec<Ref>) -> Node;
+}
+
+/// Add a deep copy of the subtree of `src` rooted at `src_root` to `dst`, returning the reference
+/// to the copy of `src_root`.  Every copied node refers to the copies of its children, so the
+/// copy is completely independent of `src`.  Nodes that are shared within the subtree are only
+/// copied once, so the copy is shared in the same way.  The copy isn't attached to the tree in
+/// `dst`.  Returns [`None`] (and adds nothing) if the subtree contains invalid references or
+/// cycles.  This doesn't recurse, so works for arbitrarily deep trees.
+pub fn copy_subtree<Ref: Reference, Node: ASTSpec<Ref>>(
+    src: &impl NodeMap<Ref, Node>,
+    src_root: Ref,
+    dst: &mut impl NodeMapMut<Ref, Node>,
+) -> Option<Ref> {
+    let nodes = subtree_nodes(src, src_root)?;
+    Some(add_copies(dst, nodes))
+}
+
+/// Returns a copy of every distinct node in the subtree rooted at `root`, along with their
+/// references, ordered so that every node comes after all of its descendants.  Returns [`None`]
+/// if the subtree contains invalid references or cycles.
+fn subtree_nodes<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &impl NodeMap<Ref, Node>,
+    root: Ref,
+) -> Option<Vec<(Ref, Node)>> {
+    let mut nodes = Vec::new();
+    let mut visited: HashSet<Ref> = HashSet::new();
+    let mut ancestors: HashSet<Ref> = HashSet::new();
+    // A post-order traversal, where each entry is a node and the index of its next unvisited
+    // child
+    let mut stack: Vec<(Ref, usize)> = vec![(root, 0)];
+    node_map.get_node(root)?;
+    ancestors.insert(root);
+    while let Some((id, child_index)) = stack.last_mut() {
+        let id = *id;
+        // Every node on the stack is known to exist
+        let node = node_map.get_node(id).unwrap();
+        if let Some(&child) = node.children().get(*child_index) {
+            *child_index += 1;
+            if visited.contains(&child) {
+                continue;
+            }
+            if ancestors.contains(&child) || node_map.get_node(child).is_none() {
+                return None;
+            }
+            ancestors.insert(child);
+            stack.push((child, 0));
+            continue;
+        }
+        ancestors.remove(&id);
+        visited.insert(id);
+        nodes.push((id, node.clone()));
+        stack.pop();
+    }
+    Some(nodes)
+}
+
+/// Add `nodes` (as returned by [`subtree_nodes`]) to `node_map`, making every node refer to the
+/// new copies of its children.  Returns the reference to the copy of the last node, i.e. the root.
+fn add_copies<Ref: Reference, Node: ASTSpec<Ref>>(
+    node_map: &mut impl NodeMapMut<Ref, Node>,
+    nodes: Vec<(Ref, Node)>,
+) -> Ref {
+    let mut new_refs: HashMap<Ref, Ref> = HashMap::new();
+    let mut new_root = None;
+    for (old_ref, mut node) in nodes {
+        for child in node.children_mut() {
+            *child = new_refs[child];
+        }
+        let new_ref = node_map.add_node(node);
+        new_refs.insert(old_ref, new_ref);
+        new_root = Some(new_ref);
+    }
+    // `subtree_nodes` always includes the root
+    new_root.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::traversal::PreOrder;
+    use super::vec::{Index, VecNodeMap};
+    use super::{copy_subtree, MapError, NodeMap, NodeMapMut, Reference, TreeBuilder};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::{TestJSON, TestRng};
+    use crate::ast_spec::{subtrees_equal, ASTSpec};
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use std::collections::HashMap;
+
+    /// A reference into a [`HashNodeMap`]
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+    struct Key(u64);
+
+    impl Reference for Key {}
+
+    /// A trivial alternative storage backend, which stores the nodes in a [`HashMap`]
+    #[derive(Debug, Clone)]
+    struct HashNodeMap<Node> {
+        nodes: HashMap<Key, Node>,
+        root: Key,
+        next_key: u64,
+    }
+
+    impl<Node: ASTSpec<Key>> NodeMap<Key, Node> for HashNodeMap<Node> {
+        fn get_node(&self, id: Key) -> Option<&Node> {
+            self.nodes.get(&id)
+        }
+
+        fn root(&self) -> Key {
+            self.root
+        }
+
+        fn iter_all(&self) -> Box<dyn Iterator<Item = (Key, &Node)> + '_> {
+            Box::new(self.nodes.iter().map(|(k, n)| (*k, n)))
+        }
+    }
+
+    impl<Node: ASTSpec<Key>> NodeMapMut<Key, Node> for HashNodeMap<Node> {
+        fn with_root(root: Node) -> Self {
+            let mut nodes = HashMap::new();
+            nodes.insert(Key(0), root);
+            HashNodeMap {
+                nodes,
+                root: Key(0),
+                next_key: 1,
+            }
+        }
+
+        fn set_root(&mut self, new_root: Key) -> Result<(), MapError<Key>> {
+            if !self.nodes.contains_key(&new_root) {
+                return Err(MapError::InvalidReference(new_root));
+            }
+            self.root = new_root;
+            Ok(())
+        }
+
+        fn get_node_mut(&mut self, id: Key) -> Option<&mut Node> {
+            self.nodes.get_mut(&id)
+        }
+
+        fn add_node(&mut self, node: Node) -> Key {
+            let key = Key(self.next_key);
+            self.next_key += 1;
+            self.nodes.insert(key, node);
+            key
+        }
+
+        fn overwrite_node(&mut self, id: Key, node: Node) -> Result<(), MapError<Key>> {
+            *self
+                .nodes
+                .get_mut(&id)
+                .ok_or(MapError::InvalidReference(id))? = node;
+            Ok(())
+        }
+
+        fn remove_node(&mut self, id: Key) -> Result<Node, MapError<Key>> {
+            if id == self.root {
+                return Err(MapError::CannotRemoveRoot(id));
+            }
+            self.nodes.remove(&id).ok_or(MapError::InvalidReference(id))
+        }
+    }
+
+    #[test]
+    fn copying_subtrees() {
+        TestJSON::check_property(100, 4, 4, |tree, seed| {
+            let mut rng = TestRng::new(seed);
+            let mut src: VecNodeMap<JSON<Index>> = tree.build_node_map();
+            let ids: Vec<Index> = src.preorder().collect();
+            let original = *rng.choose(&ids);
+            let original_text = JSON::to_text(original, &src, &JSONFormat::Compact);
+
+            // Copies in the same map and in a different map are both equal to the original
+            let same_map_copy = src.clone_subtree(original).unwrap();
+            let mut dst: VecNodeMap<JSON<Index>> =
+                TestJSON::Array(vec![TestJSON::True, TestJSON::False]).build_node_map();
+            let other_map_copy = copy_subtree(&src, original, &mut dst).unwrap();
+            if !subtrees_equal(&src, original, &src, same_map_copy) {
+                return Err("the copy in the same map is different".to_string());
+            }
+            if !subtrees_equal(&src, original, &dst, other_map_copy) {
+                return Err("the copy in the other map is different".to_string());
+            }
+
+            // Changing every node of the copies leaves the original alone
+            let copy_ids: Vec<Index> = PreOrder::new(&src, same_map_copy).collect();
+            for id in copy_ids {
+                if ids.contains(&id) {
+                    return Err(format!("{:?} is in both the original and the copy", id));
+                }
+                let node = src.get_node(id).unwrap().clone();
+                let changed = match node {
+                    JSON::Str(_) => JSON::Str("changed".to_string()),
+                    n if n.children().is_empty() => JSON::Number("0".to_string()),
+                    n => n,
+                };
+                src.overwrite_node(id, changed).unwrap();
+            }
+            if JSON::to_text(original, &src, &JSONFormat::Compact) != original_text {
+                return Err("changing the copy changed the original".to_string());
+            }
+            if !subtrees_equal(&src, original, &dst, other_map_copy) {
+                return Err("changing one copy changed the other".to_string());
+            }
+            Ok(())
+        });
+    }
+
+    /// A tree of `n` nested arrays around a `true`, which doesn't need to be stored anywhere
+    #[derive(Debug, Clone, Copy)]
+    struct Chain(usize);
+
+    impl<Ref: Reference> TreeBuilder<Ref, JSON<Ref>> for Chain {
+        fn node_count(&self) -> usize {
+            self.0 + 1
+        }
+
+        fn children(&self) -> Vec<Self> {
+            match self.0 {
+                0 => vec![],
+                n => vec![Chain(n - 1)],
+            }
+        }
+
+        fn build_node(&self, children: Vec<Ref>) -> JSON<Ref> {
+            match self.0 {
+                0 => JSON::True,
+                _ => JSON::Array(children),
+            }
+        }
+    }
+
+    #[test]
+    fn adding_trees() {
+        let mut node_map: VecNodeMap<JSON<Index>> = VecNodeMap::with_default_root();
+        let chain = node_map.add_tree(Chain(2));
+        assert_eq!(node_map.iter_all().count(), 4);
+        // The new tree isn't attached to anything until it's made the root
+        assert_eq!(node_map.root_node(), &JSON::default());
+        node_map.set_root(chain).unwrap();
+        assert_eq!(node_map.to_text(&JSONFormat::Compact), "[[true]]");
+        // Every node is added after its children
+        let positions: Vec<Index> = node_map.preorder().collect();
+        assert!(positions.windows(2).all(|pair| pair[0] > pair[1]));
+
+        // A chain of 100,000 nested arrays, which would overflow the stack if added recursively
+        let deep = node_map.add_tree(Chain(100_000));
+        node_map.set_root(deep).unwrap();
+        assert_eq!(node_map.preorder().count(), 100_001);
+        let mut src: VecNodeMap<JSON<Index>> = VecNodeMap::with_root(JSON::True);
+        for _ in 0..100_000 {
+            let root = src.root();
+            src.add_as_root(JSON::Array(vec![root]));
+        }
+        assert!(subtrees_equal(&src, src.root(), &node_map, deep));
+    }
+
+    #[test]
+    fn copying_deep_and_invalid_subtrees() {
+        // A chain of 100,000 nested arrays, which would overflow the stack if copied recursively
+        let mut src: VecNodeMap<JSON<Index>> = VecNodeMap::with_root(JSON::True);
+        for _ in 0..100_000 {
+            let root = src.root();
+            src.add_as_root(JSON::Array(vec![root]));
+        }
+        let mut dst: VecNodeMap<JSON<Index>> = VecNodeMap::with_default_root();
+        let copy = copy_subtree(&src, src.root(), &mut dst).unwrap();
+        assert!(subtrees_equal(&src, src.root(), &dst, copy));
+        assert_eq!(dst.iter_all().count(), 100_002);
+
+        // Nothing is copied from subtrees with cycles or invalid references
+        let mut cyclic = src.clone();
+        let inner = cyclic.root_node().children()[0];
+        cyclic
+            .overwrite_node(inner, JSON::Array(vec![cyclic.root()]))
+            .unwrap();
+        let num_nodes = cyclic.iter_all().count();
+        assert_eq!(cyclic.clone_subtree(cyclic.root()), None);
+        assert_eq!(cyclic.iter_all().count(), num_nodes);
+        let mut missing = src;
+        missing.remove_node(inner).unwrap();
+        assert_eq!(copy_subtree(&missing, missing.root(), &mut dst), None);
+        assert_eq!(dst.iter_all().count(), 100_002);
+    }
+
+    #[test]
+    fn alternative_backend() {
+        // Basic operations through the trait
+        let mut node_map: HashNodeMap<JSON<Key>> = HashNodeMap::with_default_root();
+        let t = node_map.add_node(JSON::True);
+        let f = node_map.add_node(JSON::False);
+        node_map.add_as_root(JSON::Array(vec![t, f]));
+        assert_eq!(node_map.to_text(&JSONFormat::Compact), "[true, false]");
+        assert_eq!(node_map.overwrite_node(f, JSON::True), Ok(()));
+        assert_eq!(node_map.to_text(&JSONFormat::Compact), "[true, true]");
+        assert_eq!(node_map.iter_all().count(), 4);
+        assert_eq!(node_map.remove_node(Key(0)), Ok(JSON::Object(vec![])));
+        assert_eq!(
+            node_map.remove_node(Key(0)),
+            Err(MapError::InvalidReference(Key(0)))
+        );
+        let root = node_map.root();
+        assert_eq!(
+            node_map.remove_node(root),
+            Err(MapError::CannotRemoveRoot(root))
+        );
+        assert_eq!(
+            node_map.overwrite_node(Key(0), JSON::Null),
+            Err(MapError::InvalidReference(Key(0)))
+        );
+        assert_eq!(node_map.iter_all().count(), 3);
+
+        // Editing a tree stored in the alternative backend
+        let mut tree: Spec<JSON<Key>, Key, HashNodeMap<JSON<Key>>> = Spec::from_tree(
+            TestJSON::Array(vec![TestJSON::True, TestJSON::False]).build_node_map(),
+        );
+        assert!(tree.move_to_child(1));
+        tree.replace_cursor(JSON::Array(vec![]));
+        tree.insert_child(JSON::True).unwrap();
+        assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, [true]]");
+        assert!(tree.undo());
+        assert!(tree.undo());
+        assert_eq!(tree.to_text(&JSONFormat::Compact), "[true, false]");
+    }
 }
@@ -0,0 +1,270 @@
+//! Module containing an implementation of `NodeMap` that stores the `Node`s in a slab, so that the
+//! space of removed nodes is reused by new ones
+
+use super::{MapError, NodeMap, NodeMapMut, Reference};
+use crate::ast_spec::ASTSpec;
+
+/// A reference into a [`SlabNodeMap`].  As well as the slot that the node is stored in, a `Key`
+/// records the generation of that slot, which changes every time the slot is emptied or its node
+/// is superseded.  This way, a `Key` to a removed node stays invalid even after its slot has been
+/// reused by another node.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Key {
+    slot: usize,
+    generation: u32,
+}
+
+impl Reference for Key {}
+
+/// A single slot of a [`SlabNodeMap`], which may or may not contain a node
+#[derive(Debug, Clone)]
+struct Slot<Node> {
+    /// The number of times that this slot has been emptied or had its node superseded
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// A [`NodeMap`] that stores the AST nodes in a [`Vec`] of slots, like
+/// [`VecNodeMap`](super::vec::VecNodeMap).  Unlike a `VecNodeMap`, removing a node frees its slot
+/// to be used by the next node that is added, so a map that has many nodes added and removed
+/// doesn't keep growing.
+#[derive(Debug, Clone)]
+pub struct SlabNodeMap<Node> {
+    slots: Vec<Slot<Node>>,
+    /// The indices of the empty slots, which are reused (most recently emptied first) before any
+    /// new slots are made
+    free_slots: Vec<usize>,
+    root: Key,
+}
+
+impl<Node> SlabNodeMap<Node> {
+    /// Returns the number of slots that this map has made, whether or not they contain a node
+    pub fn num_slots(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl<Node: ASTSpec<Key>> NodeMap<Key, Node> for SlabNodeMap<Node> {
+    #[inline]
+    fn root(&self) -> Key {
+        self.root
+    }
+
+    #[inline]
+    fn get_node(&self, id: Key) -> Option<&Node> {
+        self.slots
+            .get(id.slot)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    fn iter_all(&self) -> Box<dyn Iterator<Item = (Key, &Node)> + '_> {
+        Box::new(self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            let key = Key {
+                slot: i,
+                generation: slot.generation,
+            };
+            slot.node.as_ref().map(|n| (key, n))
+        }))
+    }
+}
+
+impl<Node: ASTSpec<Key>> NodeMapMut<Key, Node> for SlabNodeMap<Node> {
+    fn with_root(node: Node) -> Self {
+        SlabNodeMap {
+            slots: vec![Slot {
+                generation: 0,
+                node: Some(node),
+            }],
+            free_slots: Vec::new(),
+            root: Key {
+                slot: 0,
+                generation: 0,
+            },
+        }
+    }
+
+    #[inline]
+    fn get_node_mut(&mut self, id: Key) -> Option<&mut Node> {
+        self.slots
+            .get_mut(id.slot)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    fn set_root(&mut self, new_root: Key) -> Result<(), MapError<Key>> {
+        if self.get_node(new_root).is_none() {
+            return Err(MapError::InvalidReference(new_root));
+        }
+        self.root = new_root;
+        Ok(())
+    }
+
+    fn add_node(&mut self, node: Node) -> Key {
+        match self.free_slots.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                Key {
+                    slot: index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                Key {
+                    slot: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        // Free slots are used before any new ones are made
+        self.slots
+            .reserve(additional.saturating_sub(self.free_slots.len()));
+    }
+
+    fn shrink_to_fit(&mut self) {
+        // Empty slots can't be dropped, since they remember their generations
+        self.slots.shrink_to_fit();
+        self.free_slots.shrink_to_fit();
+    }
+
+    fn overwrite_node(&mut self, id: Key, node: Node) -> Result<(), MapError<Key>> {
+        *self
+            .get_node_mut(id)
+            .ok_or(MapError::InvalidReference(id))? = node;
+        Ok(())
+    }
+
+    fn supersede_node(&mut self, id: Key, node: Node) -> Result<Key, MapError<Key>> {
+        self.get_node(id).ok_or(MapError::InvalidReference(id))?;
+        let slot = &mut self.slots[id.slot];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.node = Some(node);
+        let new_id = Key {
+            slot: id.slot,
+            generation: slot.generation,
+        };
+        if id == self.root {
+            self.root = new_id;
+        }
+        Ok(new_id)
+    }
+
+    fn remove_node(&mut self, id: Key) -> Result<Node, MapError<Key>> {
+        if id == self.root {
+            return Err(MapError::CannotRemoveRoot(id));
+        }
+        self.get_node(id).ok_or(MapError::InvalidReference(id))?;
+        let slot = &mut self.slots[id.slot];
+        // Start a new generation, so that `id` (and any copies of it) can't refer to whatever
+        // node is stored here next
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(id.slot);
+        Ok(slot.node.take().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, SlabNodeMap};
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::editable_tree::spec::Spec;
+    use crate::editable_tree::EditableTree;
+    use crate::node_map::{MapError, NodeMap, NodeMapMut};
+
+    #[test]
+    fn slots_are_reused_after_removal() {
+        let mut node_map: SlabNodeMap<JSON<Key>> = SlabNodeMap::with_default_root();
+        let t = node_map.add_node(JSON::True);
+        let f = node_map.add_node(JSON::False);
+        node_map.add_as_root(JSON::Array(vec![t, f]));
+        assert_eq!(node_map.num_slots(), 4);
+
+        // Removing a node frees its slot for the next node
+        assert_eq!(node_map.remove_node(f), Ok(JSON::False));
+        assert_eq!(node_map.remove_node(f), Err(MapError::InvalidReference(f)));
+        let null = node_map.add_node(JSON::Null);
+        assert_eq!(null.slot, f.slot);
+        assert_eq!(node_map.num_slots(), 4);
+        // ... but the old key doesn't refer to the new node
+        assert_ne!(null, f);
+        assert_eq!(node_map.get_node(f), None);
+        assert_eq!(node_map.get_node_mut(f), None);
+        let stale = Err(MapError::InvalidReference(f));
+        assert_eq!(node_map.overwrite_node(f, JSON::True), stale);
+        assert_eq!(node_map.set_root(f), stale);
+        assert_eq!(node_map.remove_node(f).map(|_| ()), stale);
+        assert_eq!(node_map.get_node(null), Some(&JSON::Null));
+        let root = node_map.root();
+        assert_eq!(
+            node_map.overwrite_node(root, JSON::Array(vec![t, null])),
+            Ok(())
+        );
+        assert_eq!(node_map.to_text(&JSONFormat::Compact), "[true, null]");
+
+        // The most recently freed slot is used first, and new slots are only made when there are
+        // no free slots
+        assert_eq!(
+            node_map.remove_node(root),
+            Err(MapError::CannotRemoveRoot(root))
+        );
+        assert!(node_map.remove_node(null).is_ok());
+        assert!(node_map.remove_node(t).is_ok());
+        assert_eq!(node_map.add_node(JSON::True).slot, t.slot);
+        assert_eq!(node_map.add_node(JSON::True).slot, null.slot);
+        assert_eq!(node_map.add_node(JSON::True).slot, 4);
+        assert_eq!(node_map.iter_all().count(), 5);
+        for (key, node) in node_map.iter_all() {
+            assert_eq!(node_map.get_node(key), Some(node));
+        }
+    }
+
+    #[test]
+    fn removing_and_adding_nodes_doesnt_grow_the_map() {
+        let mut node_map: SlabNodeMap<JSON<Key>> =
+            TestJSON::Array(vec![TestJSON::True; 10]).build_node_map();
+        let num_slots = node_map.num_slots();
+        for i in 0..1000 {
+            let old_child = node_map.root_node().children()[i % 10];
+            let new_child = node_map.add_node(JSON::False);
+            let root = node_map.root();
+            node_map.get_node_mut(root).unwrap().children_mut()[i % 10] = new_child;
+            assert!(node_map.remove_node(old_child).is_ok());
+        }
+        assert_eq!(node_map.num_slots(), num_slots + 1);
+        assert_eq!(
+            node_map.to_text(&JSONFormat::Compact),
+            format!("[{}]", ["false"; 10].join(", "))
+        );
+
+        // The same happens when the map is edited through a tree.  The tree keeps the nodes of
+        // every version it makes, so the edits are rolled back and collected to free them.
+        let mut tree: Spec<JSON<Key>, Key, SlabNodeMap<JSON<Key>>> = Spec::from_tree(node_map);
+        assert!(tree.move_to_child(3));
+        tree.collect_garbage();
+        let stored_node_count = tree.stored_node_count();
+        for _ in 0..100 {
+            tree.begin_edit();
+            tree.replace_node(JSON::Array(vec![]));
+            tree.insert_child(JSON::Null).unwrap();
+            assert!(tree.move_to_parent());
+            tree.replace_node(JSON::True);
+            assert!(tree.rollback_edit());
+            tree.collect_garbage();
+            assert_eq!(tree.stored_node_count(), stored_node_count);
+        }
+        assert_eq!(
+            tree.to_text(&JSONFormat::Compact),
+            format!("[{}]", ["false"; 10].join(", "))
+        );
+    }
+}
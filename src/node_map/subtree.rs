@@ -0,0 +1,121 @@
+//! Copies of subtrees that don't depend on any [`NodeMap`]
+
+use super::{NodeMap, NodeMapMut, Reference};
+use crate::ast_spec::ASTSpec;
+
+/// An owned, deep copy of a subtree, which can be copied out of one [`NodeMap`] and added to any
+/// other.  Since it doesn't reference any nodes in the map it was copied from, later changes to
+/// that map can't affect it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Subtree<Node> {
+    /// The root of this subtree.  Its child references are meaningless, and are replaced when the
+    /// subtree is added to a [`NodeMap`].
+    node: Node,
+    /// The subtrees of each of `node`'s children, in order
+    children: Vec<Subtree<Node>>,
+}
+
+impl<Node> Subtree<Node> {
+    /// Returns the root node of this `Subtree`.  The references to its children are not valid in
+    /// any [`NodeMap`].
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// Replace the root node of this `Subtree`, keeping its children.  The child references of
+    /// `node` are ignored, so `node` must have the same number of children as the old root.
+    pub fn set_node(&mut self, node: Node) {
+        self.node = node;
+    }
+
+    /// Returns the subtrees of the root's children, in order
+    pub fn children(&self) -> &[Subtree<Node>] {
+        &self.children
+    }
+
+    /// Returns the subtree of the `index`th child of the root, or [`None`] if there is no such
+    /// child
+    pub fn child_mut(&mut self, index: usize) -> Option<&mut Subtree<Node>> {
+        self.children.get_mut(index)
+    }
+
+    /// Returns the number of nodes in this `Subtree`
+    pub fn len(&self) -> usize {
+        1 + self.children.iter().map(Subtree::len).sum::<usize>()
+    }
+
+    /// A `Subtree` always contains its root, so is never empty
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Deep-copy the subtree rooted at `id` out of `node_map`, returning [`None`] if `id` (or any
+    /// of its descendants) is not a valid reference.
+    pub fn copy_from<Ref: Reference>(node_map: &impl NodeMap<Ref, Node>, id: Ref) -> Option<Self>
+    where
+        Node: ASTSpec<Ref>,
+    {
+        let node = node_map.get_node(id)?;
+        let children = node
+            .children()
+            .iter()
+            .map(|c| Subtree::copy_from(node_map, *c))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Subtree {
+            node: node.clone(),
+            children,
+        })
+    }
+
+    /// Add a fresh copy of every node in this `Subtree` to `node_map`, returning the reference to
+    /// the new copy of the root.  The new nodes are not attached to the existing tree.
+    pub fn add_to<Ref: Reference>(&self, node_map: &mut impl NodeMapMut<Ref, Node>) -> Ref
+    where
+        Node: ASTSpec<Ref>,
+    {
+        let child_refs = self
+            .children
+            .iter()
+            .map(|c| c.add_to(node_map))
+            .collect::<Vec<_>>();
+        let mut node = self.node.clone();
+        for (child, new_ref) in node.children_mut().iter_mut().zip(child_refs) {
+            *child = new_ref;
+        }
+        node_map.add_node(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Subtree;
+    use crate::ast_spec::json::{JSONFormat, JSON};
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    #[test]
+    fn copy_between_maps() {
+        let mut node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::Object(vec![("value".to_string(), TestJSON::False)]),
+        ])
+        .build_node_map();
+        let object = node_map.root_node().children()[1];
+        let subtree = Subtree::copy_from(&node_map, object).unwrap();
+        assert_eq!(subtree.len(), 4);
+        assert_eq!(subtree.node().kind_name(), "object");
+
+        // Changing the original shouldn't affect the copy
+        let field = node_map.get_node(object).unwrap().children()[0];
+        node_map.overwrite_node(field, JSON::True).unwrap();
+        let mut other_map = VecNodeMap::<JSON<Index>>::with_default_root();
+        let root = subtree.add_to(&mut other_map);
+        other_map.set_root(root).unwrap();
+        assert_eq!(
+            other_map.to_text(&JSONFormat::Compact),
+            r#"{"value": false}"#
+        );
+    }
+}
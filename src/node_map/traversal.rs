@@ -0,0 +1,236 @@
+//! Traversals over the trees stored in [`NodeMap`]s
+
+use super::{NodeMap, Reference};
+use crate::ast_spec::ASTSpec;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+/// An [`Iterator`] over the references of every node in a tree, in the order that the nodes
+/// appear in the text (i.e. every node comes before its descendants, and children are visited in
+/// order).  Invalid references are skipped, along with their would-be descendants.  A node that
+/// is reachable along multiple paths from the root is visited once per path.
+///
+/// The traversal uses an explicit stack, so deep trees can't overflow the call stack.
+pub struct PreOrder<'m, Ref: Reference, Node: ASTSpec<Ref>, M: NodeMap<Ref, Node>> {
+    node_map: &'m M,
+    /// The nodes still to be visited, along with their depth and index within their siblings
+    to_visit: Vec<(Ref, usize, usize)>,
+    /// The path of the most recently visited node
+    path: Vec<usize>,
+    _node: PhantomData<Node>,
+}
+
+impl<'m, Ref: Reference, Node: ASTSpec<Ref>, M: NodeMap<Ref, Node>> PreOrder<'m, Ref, Node, M> {
+    /// Creates a `PreOrder` over the subtree of `node_map` rooted at `root`
+    pub fn new(node_map: &'m M, root: Ref) -> Self {
+        PreOrder {
+            node_map,
+            to_visit: vec![(root, 0, 0)],
+            path: Vec::new(),
+            _node: PhantomData,
+        }
+    }
+
+    /// Returns the path (as child indices from the root) of the node most recently returned by
+    /// [`next`](Iterator::next).  The path of the root is empty.
+    pub fn path(&self) -> &[usize] {
+        &self.path
+    }
+}
+
+impl<'m, Ref: Reference, Node: ASTSpec<Ref>, M: NodeMap<Ref, Node>> Iterator
+    for PreOrder<'m, Ref, Node, M>
+{
+    type Item = Ref;
+
+    fn next(&mut self) -> Option<Ref> {
+        loop {
+            let (id, depth, sibling_index) = self.to_visit.pop()?;
+            let node = match self.node_map.get_node(id) {
+                Some(n) => n,
+                None => continue,
+            };
+            if depth > 0 {
+                self.path.truncate(depth - 1);
+                self.path.push(sibling_index);
+            }
+            // Push the children in reverse order so that they are popped in order
+            for (i, c) in node.children().iter().enumerate().rev() {
+                self.to_visit.push((*c, depth + 1, i));
+            }
+            return Some(id);
+        }
+    }
+}
+
+/// An [`Iterator`] over the references of every distinct node that can be reached from a given
+/// root, in the order in which the nodes first appear in the text (i.e. the same order as
+/// [`PreOrder`], except that nodes that have already been visited are skipped along with their
+/// descendants).  Invalid references are skipped.  Every node is visited at most once, so this
+/// terminates even if the map contains cycles.
+pub struct Reachable<'m, Ref: Reference, Node: ASTSpec<Ref>, M: NodeMap<Ref, Node>> {
+    node_map: &'m M,
+    /// The nodes still to be visited, with the next one at the end
+    to_visit: Vec<Ref>,
+    visited: HashSet<Ref>,
+    _node: PhantomData<Node>,
+}
+
+impl<'m, Ref: Reference, Node: ASTSpec<Ref>, M: NodeMap<Ref, Node>> Reachable<'m, Ref, Node, M> {
+    /// Creates a `Reachable` over the nodes of `node_map` that can be reached from `root`
+    pub fn new(node_map: &'m M, root: Ref) -> Self {
+        Reachable {
+            node_map,
+            to_visit: vec![root],
+            visited: HashSet::new(),
+            _node: PhantomData,
+        }
+    }
+}
+
+impl<'m, Ref: Reference, Node: ASTSpec<Ref>, M: NodeMap<Ref, Node>> Iterator
+    for Reachable<'m, Ref, Node, M>
+{
+    type Item = Ref;
+
+    fn next(&mut self) -> Option<Ref> {
+        loop {
+            let id = self.to_visit.pop()?;
+            if self.visited.contains(&id) {
+                continue;
+            }
+            let node = match self.node_map.get_node(id) {
+                Some(n) => n,
+                None => continue,
+            };
+            self.visited.insert(id);
+            // Push the children in reverse order so that they are popped in order
+            let visited = &self.visited;
+            let unvisited_children = node.children().iter().rev();
+            self.to_visit
+                .extend(unvisited_children.filter(|c| !visited.contains(c)));
+            return Some(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreOrder;
+    use crate::ast_spec::json::JSON;
+    use crate::ast_spec::test_json::TestJSON;
+    use crate::ast_spec::ASTSpec;
+    use crate::editable_tree::dag::DAG;
+    use crate::editable_tree::EditableTree;
+    use crate::node_map::vec::{Index, VecNodeMap};
+    use crate::node_map::{NodeMap, NodeMapMut};
+
+    #[test]
+    fn document_order() {
+        let node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+            TestJSON::Array(vec![TestJSON::True]),
+            TestJSON::Object(vec![("k".to_string(), TestJSON::False)]),
+            TestJSON::Str("last".to_string()),
+        ])
+        .build_node_map();
+        let mut traversal = PreOrder::new(&node_map, node_map.root());
+        let mut visited = Vec::new();
+        while let Some(id) = traversal.next() {
+            let name = node_map.get_node(id).unwrap().display_name();
+            visited.push((name, traversal.path().to_vec()));
+        }
+        let expected: Vec<(&str, Vec<usize>)> = vec![
+            ("array", vec![]),
+            ("array", vec![0]),
+            ("true", vec![0, 0]),
+            ("object", vec![1]),
+            ("field", vec![1, 0]),
+            (r#""k""#, vec![1, 0, 0]),
+            ("false", vec![1, 0, 1]),
+            (r#""last""#, vec![2]),
+        ];
+        assert_eq!(
+            visited,
+            expected
+                .into_iter()
+                .map(|(name, path)| (name.to_string(), path))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reachable_nodes() {
+        let node_map: VecNodeMap<JSON<Index>> = TestJSON::Array(vec![
+            TestJSON::Object(vec![(
+                "k".to_string(),
+                TestJSON::Array(vec![TestJSON::Null]),
+            )]),
+            TestJSON::True,
+        ])
+        .build_node_map();
+        let names = |refs: &[Index]| {
+            refs.iter()
+                .map(|r| node_map.get_node(*r).unwrap().display_name())
+                .collect::<Vec<_>>()
+        };
+        let visited: Vec<Index> = node_map.iter_reachable(node_map.root()).collect();
+        assert_eq!(
+            names(&visited),
+            vec!["array", "object", "field", r#""k""#, "array", "null", "true"]
+        );
+        // On a tree without sharing, this is the same as a pre-order traversal
+        assert_eq!(visited, node_map.preorder().collect::<Vec<_>>());
+        // The traversal can start anywhere, and the placeholder root isn't reachable
+        let object = node_map.root_node().children()[0];
+        assert_eq!(node_map.iter_reachable(object).count(), 5);
+        assert_eq!(node_map.iter_all().count(), 8);
+    }
+
+    #[test]
+    fn reachable_nodes_with_sharing_and_cycles() {
+        let mut node_map: VecNodeMap<JSON<Index>> = VecNodeMap::with_root(JSON::True);
+        let shared = node_map.root();
+        let inner = node_map.add_node(JSON::Array(vec![shared, shared]));
+        node_map.add_as_root(JSON::Array(vec![shared, inner, shared]));
+        // Shared nodes are only visited the first time they're reached
+        let root = node_map.root();
+        let visited: Vec<Index> = node_map.iter_reachable(root).collect();
+        assert_eq!(visited, vec![root, shared, inner]);
+        assert_eq!(node_map.preorder().count(), 6);
+        // Cycles don't stop the traversal from finishing, and missing nodes are skipped
+        node_map
+            .overwrite_node(inner, JSON::Array(vec![root, inner, shared]))
+            .unwrap();
+        assert_eq!(
+            node_map.iter_reachable(inner).collect::<Vec<_>>(),
+            vec![inner, root, shared]
+        );
+        assert!(node_map.remove_node(shared).is_ok());
+        assert_eq!(
+            node_map.iter_reachable(root).collect::<Vec<_>>(),
+            vec![root, inner]
+        );
+    }
+
+    #[test]
+    fn all_nodes_include_garbage() {
+        let tree = TestJSON::Array(vec![TestJSON::True, TestJSON::False]);
+        let mut dag: DAG<JSON<Index>> = DAG::from_tree(tree.build_node_map());
+        assert!(dag.move_to_child(0));
+        dag.replace_cursor(JSON::Null);
+        // As well as the current tree, the map stores the placeholder root and the old version
+        let reachable: Vec<Index> = dag.iter_reachable(dag.root()).collect();
+        assert_eq!(reachable.len(), 3);
+        let all: Vec<Index> = dag.iter_all().map(|(id, _)| id).collect();
+        assert_eq!(all.len(), 6);
+        assert!(reachable.iter().all(|id| all.contains(id)));
+        let garbage: Vec<&JSON<Index>> = dag
+            .iter_all()
+            .filter(|(id, _)| !reachable.contains(id))
+            .map(|(_, node)| node)
+            .collect();
+        assert_eq!(garbage.len(), 3);
+        assert!(garbage.contains(&&JSON::True));
+        assert_eq!(dag.stored_node_count(), 6);
+    }
+}
@@ -1,7 +1,8 @@
 //! Module containing an implementation of `NodeMap` that stores the `Node`s in a simple vector
 
-use super::{NodeMap, NodeMapMut, Reference};
+use super::{reachable_nodes, GcStats, MapError, MemoryStats, NodeMap, NodeMapMut, Reference};
 use crate::ast_spec::ASTSpec;
+use std::collections::HashMap;
 
 // An import solely used by doc-comments
 #[allow(unused_imports)]
@@ -10,33 +11,118 @@ use crate::editable_tree::EditableTree;
 /// A small type used as a reference into Vec-powered [`EditableTree`]s.  `Index` acts as a type-safe
 /// alternative to just using [`usize`], and can only be created and used by [`VecNodeMap`]s - to the
 /// rest of the code `Indices` are essentially black boxes.
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-pub struct Index(usize);
+///
+/// As well as the position of its node, an `Index` stores the generation of that position, which
+/// changes whenever the node there is superseded or removed.  This means that a stale `Index`
+/// (e.g. one held across an edit) resolves to nothing, rather than to whatever node has since
+/// taken its place.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Index {
+    slot: usize,
+    generation: u32,
+}
 
 impl Reference for Index {}
 
 impl Index {
     #[inline]
-    fn new(val: usize) -> Index {
-        Index(val)
+    fn new(slot: usize, generation: u32) -> Index {
+        Index { slot, generation }
     }
 }
 
 impl Index {
     #[inline]
     fn as_usize(self) -> usize {
-        self.0
+        self.slot
+    }
+}
+
+impl std::fmt::Debug for Index {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Most nodes are never superseded, so the generation is only shown when it matters
+        if self.generation == 0 {
+            write!(f, "Index({})", self.slot)
+        } else {
+            write!(f, "Index({}, generation {})", self.slot, self.generation)
+        }
     }
 }
 
 /// A [`NodeMap`] that stores all the AST nodes in a [`Vec`] and uses indices into this [`Vec`] as IDs
-/// for the nodes.
+/// for the nodes.  With the `serde` feature, the whole map (including any nodes that aren't
+/// reachable from the root, e.g. those of old versions of a tree) can be serialized.  Deserializing
+/// a map checks that its root is one of its nodes, but not that the nodes form a valid tree.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "SerializedVecNodeMap<Node>",
+        bound(deserialize = "Node: serde::Deserialize<'de>")
+    )
+)]
 pub struct VecNodeMap<Node> {
-    nodes: Vec<Node>,
+    /// The nodes, where removed nodes are replaced with [`None`] so that the indices of the other
+    /// nodes don't change
+    nodes: Vec<Option<Node>>,
+    /// The current generation of each position that has ever been used.  This can be longer than
+    /// `nodes` (after [`collect_garbage`](NodeMapMut::collect_garbage) has shrunk it), so that
+    /// old references to the positions past the end stay stale when those positions are reused.
+    generations: Vec<u32>,
+    root: Index,
+}
+
+/// The fields of a [`VecNodeMap`] as they're deserialized, before being checked
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SerializedVecNodeMap<Node> {
+    nodes: Vec<Option<Node>>,
+    generations: Vec<u32>,
     root: Index,
 }
 
+#[cfg(feature = "serde")]
+impl<Node> std::convert::TryFrom<SerializedVecNodeMap<Node>> for VecNodeMap<Node> {
+    type Error = String;
+
+    fn try_from(map: SerializedVecNodeMap<Node>) -> Result<Self, String> {
+        if map.generations.len() < map.nodes.len() {
+            return Err("a node map has fewer generations than nodes".to_string());
+        }
+        let map = VecNodeMap {
+            nodes: map.nodes,
+            generations: map.generations,
+            root: map.root,
+        };
+        match map.position(map.root) {
+            Some(position) if map.nodes[position].is_some() => Ok(map),
+            _ => Err(format!(
+                "the root of a node map ({:?}) isn't in it",
+                map.root
+            )),
+        }
+    }
+}
+
+impl<Node> VecNodeMap<Node> {
+    /// Returns the position of the node referred to by `id`, if `id` is from the current
+    /// generation of that position
+    #[inline]
+    fn position(&self, id: Index) -> Option<usize> {
+        let generation = *self.generations.get(id.as_usize())?;
+        (generation == id.generation && id.as_usize() < self.nodes.len()).then_some(id.as_usize())
+    }
+
+    /// Start a new generation at the position of `id`, so that `id` (and any copies of it) no
+    /// longer refer to anything
+    fn invalidate(&mut self, id: Index) {
+        let generation = &mut self.generations[id.as_usize()];
+        *generation = generation.wrapping_add(1);
+    }
+}
+
 impl<Node: ASTSpec<Index>> NodeMap<Index, Node> for VecNodeMap<Node> {
     #[inline]
     fn root(&self) -> Index {
@@ -45,43 +131,152 @@ impl<Node: ASTSpec<Index>> NodeMap<Index, Node> for VecNodeMap<Node> {
 
     #[inline]
     fn get_node(&self, id: Index) -> Option<&Node> {
-        self.nodes.get(id.as_usize())
+        self.nodes[self.position(id)?].as_ref()
+    }
+
+    fn iter_all(&self) -> Box<dyn Iterator<Item = (Index, &Node)> + '_> {
+        Box::new(
+            self.nodes
+                .iter()
+                .zip(&self.generations)
+                .enumerate()
+                .filter_map(|(i, (n, g))| n.as_ref().map(|n| (Index::new(i, *g), n))),
+        )
     }
 }
 
 impl<Node: ASTSpec<Index>> NodeMapMut<Index, Node> for VecNodeMap<Node> {
     fn with_root(node: Node) -> Self {
         VecNodeMap {
-            nodes: vec![node],
-            root: Index::new(0),
+            nodes: vec![Some(node)],
+            generations: vec![0],
+            root: Index::new(0, 0),
         }
     }
 
     #[inline]
     fn get_node_mut(&mut self, id: Index) -> Option<&mut Node> {
-        self.nodes.get_mut(id.as_usize())
+        let position = self.position(id)?;
+        self.nodes[position].as_mut()
     }
 
-    fn set_root(&mut self, new_root: Index) -> bool {
-        let is_ref_valid = self.get_node(new_root).is_some();
-        if is_ref_valid {
-            self.root = new_root;
+    fn set_root(&mut self, new_root: Index) -> Result<(), MapError<Index>> {
+        if self.get_node(new_root).is_none() {
+            return Err(MapError::InvalidReference(new_root));
         }
-        is_ref_valid
+        self.root = new_root;
+        Ok(())
     }
 
     #[inline]
     fn add_node(&mut self, node: Node) -> Index {
-        self.nodes.push(node);
-        Index::new(self.nodes.len() - 1)
+        let position = self.nodes.len();
+        self.nodes.push(Some(node));
+        if position == self.generations.len() {
+            self.generations.push(0);
+        }
+        Index::new(position, self.generations[position])
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        // New positions need generations too, unless they were used before a garbage collection
+        let new_positions = (self.nodes.len() + additional).saturating_sub(self.generations.len());
+        self.nodes.reserve(additional);
+        self.generations.reserve(new_positions);
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+        self.generations.shrink_to_fit();
+    }
+
+    /// Counts every slot that has been allocated, and the generation of every position, as well
+    /// as the data owned by the stored nodes
+    fn memory_usage(&self) -> MemoryStats {
+        let reachable = reachable_nodes(self, Some(self.root)).len();
+        let nodes = self.nodes.iter().flatten();
+        MemoryStats {
+            nodes_stored: nodes.clone().count(),
+            nodes_reachable: reachable,
+            bytes: self.nodes.capacity() * std::mem::size_of::<Option<Node>>()
+                + self.generations.capacity() * std::mem::size_of::<u32>()
+                + nodes.map(ASTSpec::heap_size).sum::<usize>(),
+        }
     }
 
-    fn overwrite_node(&mut self, id: Index, node: Node) -> bool {
-        let is_ref_valid = self.get_node(id).is_some();
-        if is_ref_valid {
-            self.nodes[id.as_usize()] = node;
+    fn overwrite_node(&mut self, id: Index, node: Node) -> Result<(), MapError<Index>> {
+        *self
+            .get_node_mut(id)
+            .ok_or(MapError::InvalidReference(id))? = node;
+        Ok(())
+    }
+
+    fn supersede_node(&mut self, id: Index, node: Node) -> Result<Index, MapError<Index>> {
+        self.get_node(id).ok_or(MapError::InvalidReference(id))?;
+        self.invalidate(id);
+        let new_id = Index::new(id.as_usize(), self.generations[id.as_usize()]);
+        self.nodes[id.as_usize()] = Some(node);
+        if id == self.root {
+            self.root = new_id;
+        }
+        Ok(new_id)
+    }
+
+    fn remove_node(&mut self, id: Index) -> Result<Node, MapError<Index>> {
+        if id == self.root {
+            return Err(MapError::CannotRemoveRoot(id));
+        }
+        let node = self
+            .position(id)
+            .and_then(|position| self.nodes[position].take())
+            .ok_or(MapError::InvalidReference(id))?;
+        self.invalidate(id);
+        Ok(node)
+    }
+
+    /// Packs the kept nodes together at the start of the [`Vec`], freeing the space of the
+    /// garbage.  Nodes before the first piece of garbage don't move.
+    fn collect_garbage(&mut self, live_roots: &[Index]) -> GcStats<Index> {
+        let root = self.root;
+        let reachable = reachable_nodes(self, live_roots.iter().copied().chain(Some(root)));
+        let num_nodes = self.iter_all().count();
+        // Move every kept node to its new position, bumping the generation of every position
+        // whose contents change so that old references to it go stale
+        let mut remapping = HashMap::new();
+        let mut new_len = 0;
+        for position in 0..self.nodes.len() {
+            let id = Index::new(position, self.generations[position]);
+            if self.nodes[position].is_none() || !reachable.contains(&id) {
+                continue;
+            }
+            if position != new_len {
+                self.nodes.swap(position, new_len);
+                self.invalidate(Index::new(new_len, 0));
+                remapping.insert(id, Index::new(new_len, self.generations[new_len]));
+            }
+            new_len += 1;
+        }
+        for position in new_len..self.nodes.len() {
+            self.invalidate(Index::new(position, 0));
+        }
+        self.nodes.truncate(new_len);
+        self.nodes.shrink_to_fit();
+        // Point every kept node at the new positions of its children
+        for node in self.nodes.iter_mut().flatten() {
+            for child in node.children_mut() {
+                if let Some(new_child) = remapping.get(child) {
+                    *child = *new_child;
+                }
+            }
+        }
+        if let Some(new_root) = remapping.get(&root) {
+            self.root = *new_root;
+        }
+        GcStats {
+            freed: num_nodes - new_len,
+            kept: new_len,
+            remapping,
         }
-        is_ref_valid
     }
 }
 
@@ -89,9 +284,11 @@ impl<Node: ASTSpec<Index>> NodeMapMut<Index, Node> for VecNodeMap<Node> {
 mod tests {
     use super::{Index, VecNodeMap};
     use crate::ast_spec::display_token::DisplayToken;
+    use crate::ast_spec::json::JSON;
     use crate::ast_spec::size::Size;
+    use crate::ast_spec::test_json::TestJSON;
     use crate::ast_spec::ASTSpec;
-    use crate::node_map::{NodeMap, NodeMapMut, Reference};
+    use crate::node_map::{MapError, NodeMap, NodeMapMut, Reference};
 
     /// An extremely basic node type, used for testing [VecNodeMap].
     #[derive(Debug, Eq, PartialEq, Hash, Clone)]
@@ -168,6 +365,14 @@ mod tests {
             .to_string()
         }
 
+        fn kind_name(&self) -> &'static str {
+            match self {
+                ExampleNode::DefaultValue | ExampleNode::Value1 | ExampleNode::Value2 => "value",
+                ExampleNode::WithPayload(_) => "with_payload",
+                ExampleNode::Recursive(_) => "recursive",
+            }
+        }
+
         fn replace_chars(&self) -> Box<dyn Iterator<Item = char>> {
             Box::new(std::iter::empty())
         }
@@ -179,6 +384,14 @@ mod tests {
         fn insert_chars(&self) -> Box<dyn Iterator<Item = char>> {
             Box::new(std::iter::empty())
         }
+
+        fn insert_child(&mut self, _child: Ref, _index: usize) -> bool {
+            false
+        }
+
+        fn remove_child(&mut self, _index: usize) -> bool {
+            false
+        }
     }
 
     /// A useful type alias to make the unit tests terser
@@ -230,12 +443,226 @@ mod tests {
         assert_eq!(node_map.get_node(r2), Some(&ExampleNode::Value2));
     }
 
+    #[test]
+    fn remove_node() {
+        let mut node_map: TestNodeMap = VecNodeMap::with_default_root();
+        let r1 = node_map.add_node(ExampleNode::Value1);
+        let r2 = node_map.add_node(ExampleNode::Value2);
+
+        assert_eq!(node_map.remove_node(r1), Ok(ExampleNode::Value1));
+        assert_eq!(node_map.get_node(r1), None);
+        assert_eq!(
+            node_map.remove_node(r1),
+            Err(MapError::InvalidReference(r1))
+        );
+        // Removing a node shouldn't affect the other references
+        assert_eq!(node_map.get_node(r2), Some(&ExampleNode::Value2));
+        // The root can't be removed
+        let root = node_map.root();
+        assert_eq!(
+            node_map.remove_node(root),
+            Err(MapError::CannotRemoveRoot(root))
+        );
+        // Removed nodes shouldn't be iterated over
+        let mut nodes = node_map.iter_all().collect::<Vec<_>>();
+        nodes.sort_by_key(|(r, _)| *r);
+        assert_eq!(
+            nodes,
+            vec![
+                (node_map.root(), &ExampleNode::DefaultValue),
+                (r2, &ExampleNode::Value2)
+            ]
+        );
+    }
+
+    #[test]
+    fn superseded_references_go_stale() {
+        let mut node_map: TestNodeMap = VecNodeMap::with_default_root();
+        let r1 = node_map.add_node(ExampleNode::Value1);
+        let r2 = node_map.add_node(ExampleNode::Value2);
+
+        // The old reference no longer resolves, rather than resolving to the new node
+        let new_r1 = node_map
+            .supersede_node(r1, ExampleNode::WithPayload(1))
+            .unwrap();
+        assert_ne!(new_r1, r1);
+        assert_eq!(node_map.get_node(r1), None);
+        assert_eq!(node_map.get_node_mut(r1), None);
+        let stale = Err(MapError::InvalidReference(r1));
+        assert_eq!(node_map.overwrite_node(r1, ExampleNode::Value1), stale);
+        assert_eq!(
+            node_map.supersede_node(r1, ExampleNode::Value1),
+            Err(MapError::InvalidReference(r1))
+        );
+        assert_eq!(node_map.remove_node(r1).map(|_| ()), stale);
+        assert_eq!(node_map.set_root(r1), stale);
+        assert_eq!(
+            node_map.get_node(new_r1),
+            Some(&ExampleNode::WithPayload(1))
+        );
+        // References to other nodes are untouched
+        assert_eq!(node_map.get_node(r2), Some(&ExampleNode::Value2));
+        // Overwriting edits the node in place, so keeps its reference
+        assert_eq!(
+            node_map.overwrite_node(new_r1, ExampleNode::WithPayload(2)),
+            Ok(())
+        );
+        assert_eq!(
+            node_map.get_node(new_r1),
+            Some(&ExampleNode::WithPayload(2))
+        );
+        assert_eq!(
+            format!("{:?} {:?}", r1, new_r1),
+            "Index(1) Index(1, generation 1)"
+        );
+
+        // Superseding the root moves the root to the new node
+        let root = node_map.root();
+        let new_root = node_map
+            .supersede_node(root, ExampleNode::Recursive(r2))
+            .unwrap();
+        assert_eq!(node_map.root(), new_root);
+        assert_eq!(node_map.get_node(root), None);
+        // Removed nodes go stale too, and iteration only gives current references
+        assert_eq!(node_map.remove_node(r2), Ok(ExampleNode::Value2));
+        let mut refs = node_map.iter_all().map(|(r, _)| r).collect::<Vec<_>>();
+        refs.sort();
+        assert_eq!(refs, vec![new_root, new_r1]);
+    }
+
+    #[test]
+    fn collect_garbage() {
+        let mut node_map: TestNodeMap = VecNodeMap::with_default_root();
+        let kept = node_map.add_node(ExampleNode::Value1);
+        let garbage = node_map.add_node(ExampleNode::Value2);
+        let moved = node_map.add_node(ExampleNode::WithPayload(3));
+        let live_root = node_map.add_node(ExampleNode::Recursive(moved));
+        let root = node_map.add_node(ExampleNode::Recursive(kept));
+        node_map.set_root(root).unwrap();
+
+        // The default root and `garbage` can't be reached from either root
+        let stats = node_map.collect_garbage(&[live_root]);
+        assert_eq!((stats.freed, stats.kept), (2, 4));
+        assert_eq!(node_map.iter_all().count(), 4);
+        // Removing the default root moves `kept` to the front
+        let new_kept = stats.remap(kept);
+        assert_eq!(node_map.get_node(new_kept), Some(&ExampleNode::Value1));
+        assert_eq!(node_map.get_node(kept), None);
+        // Nodes that moved have their old references go stale, and the nodes that referred to
+        // them now refer to their new positions
+        let new_moved = stats.remap(moved);
+        let new_live_root = stats.remap(live_root);
+        assert_ne!(new_moved, moved);
+        assert_eq!(node_map.get_node(moved), None);
+        assert_eq!(
+            node_map.get_node(new_moved),
+            Some(&ExampleNode::WithPayload(3))
+        );
+        assert_eq!(
+            node_map.get_node(new_live_root),
+            Some(&ExampleNode::Recursive(new_moved))
+        );
+        assert_eq!(node_map.root(), stats.remap(root));
+        assert_eq!(node_map.root_node(), &ExampleNode::Recursive(new_kept));
+        // References to the freed nodes don't resolve, even once their positions are reused
+        assert_eq!(node_map.get_node(garbage), None);
+        for _ in 0..3 {
+            node_map.add_node(ExampleNode::DefaultValue);
+        }
+        for r in &[kept, garbage, moved, live_root, root] {
+            assert_eq!(node_map.get_node(*r), None);
+        }
+
+        // With nothing to free, nothing moves
+        let root = node_map.root();
+        let stats = node_map.collect_garbage(&[new_live_root]);
+        assert_eq!((stats.freed, stats.kept), (3, 4));
+        assert!(stats.remapping.is_empty());
+        assert_eq!(node_map.root(), root);
+        let stats = node_map.collect_garbage(&[new_live_root]);
+        assert_eq!((stats.freed, stats.kept), (0, 4));
+    }
+
+    #[test]
+    fn adding_trees_reserves_space() {
+        let tree = TestJSON::Array(vec![TestJSON::True; 10]);
+        let node_map: VecNodeMap<JSON<Index>> = tree.build_node_map();
+        // Space is made for every node at once, rather than growing the vector as they're added
+        assert_eq!(node_map.nodes.len(), 12);
+        assert_eq!(node_map.nodes.capacity(), 12);
+    }
+
+    #[test]
+    fn capacity_and_memory_usage() {
+        type Map = VecNodeMap<JSON<Index>>;
+        let mut node_map = Map::with_capacity(100);
+        assert!(node_map.nodes.capacity() >= 100 && node_map.generations.capacity() >= 100);
+        let string = node_map.add_node(JSON::Str("x".repeat(1000)));
+        let array = node_map.add_node(JSON::Array(vec![string, string]));
+        node_map.add_node(JSON::Null);
+        node_map.set_root(array).unwrap();
+
+        let stats = node_map.memory_usage();
+        assert_eq!((stats.nodes_stored, stats.nodes_reachable), (4, 2));
+        // The reserved space is counted as well as the strings and child vectors
+        let slot_size = std::mem::size_of::<Option<JSON<Index>>>() + 4;
+        let owned_size = 1000 + 2 * std::mem::size_of::<Index>();
+        assert!(stats.bytes >= 100 * slot_size + owned_size);
+        // Shrinking frees the reserved space, but not the nodes
+        node_map.shrink_to_fit();
+        assert_eq!(node_map.nodes.capacity(), 4);
+        assert_eq!(node_map.memory_usage().bytes, 4 * slot_size + owned_size);
+        assert_eq!(
+            node_map.memory_usage().to_string(),
+            format!(
+                "4 nodes stored (2 reachable) in about {:.1} KiB",
+                (4 * slot_size + owned_size) as f64 / 1024.0
+            )
+        );
+    }
+
+    /// Compares building a tree of a million nodes with and without reserving space for them
+    /// first.  Run with `cargo test --release -- --ignored --nocapture` to see the timings.
+    #[test]
+    #[ignore]
+    fn building_a_million_nodes() {
+        type Map = VecNodeMap<JSON<Index>>;
+        // 1000 arrays of 999 strings, in one array
+        let build = |mut node_map: Map| {
+            let start = std::time::Instant::now();
+            let rows = (0..1000)
+                .map(|_| {
+                    let values = (0..999)
+                        .map(|_| node_map.add_node(JSON::Str("value".to_string())))
+                        .collect();
+                    node_map.add_node(JSON::Array(values))
+                })
+                .collect();
+            node_map.add_as_root(JSON::Array(rows));
+            (start.elapsed(), node_map)
+        };
+        let (grown_time, grown) = build(Map::with_default_root());
+        let (reserved_time, reserved) = build(Map::with_capacity(1_000_002));
+        assert_eq!(reserved.nodes.len(), 1_000_002);
+        assert_eq!(reserved.nodes.capacity(), 1_000_002);
+        println!(
+            "Without reserving: {:?} ({})",
+            grown_time,
+            grown.memory_usage()
+        );
+        println!(
+            "Reserving: {:?} ({})",
+            reserved_time,
+            reserved.memory_usage()
+        );
+    }
+
     #[test]
     fn manual_set_root() {
         let mut node_map: TestNodeMap = VecNodeMap::with_root(ExampleNode::WithPayload(42));
 
         let r = node_map.add_node(ExampleNode::Recursive(node_map.root()));
-        assert!(node_map.set_root(r));
+        assert_eq!(node_map.set_root(r), Ok(()));
 
         if let Some(node) = node_map.get_node(node_map.root()) {
             match node {
@@ -275,4 +702,45 @@ mod tests {
             panic!("New root node not valid.");
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use crate::ast_spec::json::JSONFormat;
+
+        let tree = TestJSON::Array(vec![
+            TestJSON::True,
+            TestJSON::Object(vec![(
+                "key".to_string(),
+                TestJSON::Number("1.5".to_string()),
+            )]),
+        ]);
+        let mut node_map: VecNodeMap<JSON<Index>> = tree.build_node_map();
+        // Removed nodes and unreachable nodes are kept as they are, so that references into the
+        // map stay valid (or stale) after it's read back
+        let first = node_map.root_node().children()[0];
+        let garbage = node_map.add_node(JSON::Null);
+        node_map
+            .get_node_mut(node_map.root())
+            .unwrap()
+            .children_mut()[0] = garbage;
+        node_map.remove_node(first).unwrap();
+        let text = serde_json::to_string(&node_map).unwrap();
+        let read: VecNodeMap<JSON<Index>> = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            read.to_text(&JSONFormat::Compact),
+            r#"[null, {"key": 1.5}]"#
+        );
+        assert_eq!(read.get_node(garbage), Some(&JSON::Null));
+        assert_eq!(read.get_node(first), None);
+        assert_eq!(read.iter_all().count(), node_map.iter_all().count());
+
+        // A map whose root isn't one of its nodes is refused
+        let bad_text = text.replace(
+            &serde_json::to_string(&node_map.root()).unwrap(),
+            &serde_json::to_string(&first).unwrap(),
+        );
+        let error = serde_json::from_str::<VecNodeMap<JSON<Index>>>(&bad_text).unwrap_err();
+        assert!(error.to_string().contains("isn't in it"), "{}", error);
+    }
 }
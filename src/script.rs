@@ -0,0 +1,180 @@
+//! Editing documents without a terminal, by typing a string of commands into an [`Editor`] that
+//! nobody is looking at.  This is what `sapling --script` does, e.g.
+//! `sapling --script 'rt q' < in.json > out.json`.
+//!
+//! The commands are the keys that would be typed into the editor, and they're interpreted by
+//! exactly the same code.  Whitespace separates commands, in the same way that pausing between
+//! keys does (so `q q!` is two commands rather than waiting for more of `q`).  Only the default
+//! keys and settings are used, so a script does the same thing on every machine.
+
+use crate::ast_spec::json::{JSONFormat, JSON};
+use crate::ast_spec::ASTSpec;
+use crate::editable_tree::spec::Spec;
+use crate::editor::frontend::TestFrontend;
+use crate::editor::Editor;
+use crate::node_map::vec::{Index, VecNodeMap};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// The errors that can stop a script from being [applied](apply).  Positions count the
+/// [`char`]s of the script from 0, and refer to the first key of the offending command.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ScriptError {
+    /// The document couldn't be parsed, for the reason given
+    Parse(String),
+    /// A command was performed, but reported that it failed (e.g. an unknown command, or an
+    /// edit that the tree doesn't allow)
+    Failed {
+        command: String,
+        position: usize,
+        message: String,
+    },
+    /// A command needs someone to type into it, e.g. opening the prompt with `:` or entering
+    /// insert mode
+    Interactive { command: String, position: usize },
+    /// The script ended part of the way through a command (e.g. `r` without a node to replace
+    /// with)
+    Unfinished { command: String, position: usize },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Parse(reason) => write!(f, "Couldn't parse the document: {}", reason),
+            ScriptError::Failed {
+                command,
+                position,
+                message,
+            } => write!(
+                f,
+                "Command '{}' at character {} failed: {}",
+                command,
+                position + 1,
+                message
+            ),
+            ScriptError::Interactive { command, position } => write!(
+                f,
+                "Command '{}' at character {} needs a terminal, so can't be scripted",
+                command,
+                position + 1
+            ),
+            ScriptError::Unfinished { command, position } => write!(
+                f,
+                "Command '{}' at character {} is unfinished at the end of the script",
+                command,
+                position + 1
+            ),
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+/// How the documents given to [`apply`] are read and written
+pub struct DocumentSpec<Node: ASTSpec<Index>> {
+    /// Parses the text of a document into a tree
+    pub parse: fn(&str) -> Result<VecNodeMap<Node>, String>,
+    /// The style that the edited tree is written in
+    pub format_style: Node::FormatStyle,
+}
+
+impl DocumentSpec<JSON<Index>> {
+    /// Read and write JSON documents, writing them in the given style
+    pub fn json(format_style: JSONFormat) -> Self {
+        DocumentSpec {
+            parse: |text| JSON::parse_into(text).map_err(|e| e.to_string()),
+            format_style,
+        }
+    }
+}
+
+/// Parse `input_text` according to `spec`, type `commands` into an editor on it and return the
+/// text of the edited tree.  The script stops early if it quits (e.g. with `q`).
+pub fn apply<Node: ASTSpec<Index>>(
+    input_text: &str,
+    commands: &str,
+    spec: DocumentSpec<Node>,
+) -> Result<String, ScriptError> {
+    let tree = (spec.parse)(input_text).map_err(ScriptError::Parse)?;
+    // Nothing is drawn unless the editor is run, so the size of the screen doesn't matter
+    let frontend = Arc::new(TestFrontend::new(80, 24, Vec::new()));
+    let mut editor = Editor::with_frontend(Spec::from_tree(tree), spec.format_style, frontend);
+    editor.run_commands(commands)?;
+    Ok(editor.text_to_save())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = r#"[true, false, {"a": 1, "b": [null, "x"]}]"#;
+
+    fn run(commands: &str) -> Result<String, ScriptError> {
+        apply(INPUT, commands, DocumentSpec::json(JSONFormat::Minified))
+    }
+
+    #[test]
+    fn edits() {
+        for (commands, expected) in [
+            ("", r#"[true,false,{"a":1,"b":[null,"x"]}]"#),
+            // Navigation and replacement
+            ("c rn", r#"[0,false,{"a":1,"b":[null,"x"]}]"#),
+            ("cn rt q", r#"[true,true,{"a":1,"b":[null,"x"]}]"#),
+            ("cGcLcLcLrf", r#"[true,false,{"a":1,"b":[null,false]}]"#),
+            // Deletion, and undoing it
+            ("cx", r#"[false,{"a":1,"b":[null,"x"]}]"#),
+            ("cxx", r#"[{"a":1,"b":[null,"x"]}]"#),
+            ("cxxu", r#"[false,{"a":1,"b":[null,"x"]}]"#),
+            ("cxxuu", r#"[true,false,{"a":1,"b":[null,"x"]}]"#),
+            ("cGcLcLcLrf pcx", r#"[true,false,{"a":1,"b":[false]}]"#),
+            (
+                "cGcLcLcLrf pcxu",
+                r#"[true,false,{"a":1,"b":[null,false]}]"#,
+            ),
+            // Yanking into a register and pasting from it
+            ("c\"ay n\"ap", r#"[true,false,true,{"a":1,"b":[null,"x"]}]"#),
+            // Nothing after quitting is typed
+            ("cx q x", r#"[false,{"a":1,"b":[null,"x"]}]"#),
+        ] {
+            assert_eq!(run(commands).as_deref(), Ok(expected), "{:?}", commands);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            apply("[tru", "", DocumentSpec::json(JSONFormat::Minified)),
+            Err(ScriptError::Parse(_))
+        ));
+        // Commands that need someone to type into the prompt, an overlay or a value
+        for (commands, command, position) in
+            [("c :w", ":", 2), ("c?", "?", 1), ("cGcLcLcLs", "s", 8)]
+        {
+            assert_eq!(
+                run(commands),
+                Err(ScriptError::Interactive {
+                    command: command.to_string(),
+                    position
+                }),
+                "{:?}",
+                commands
+            );
+        }
+        assert_eq!(
+            run("c r"),
+            Err(ScriptError::Unfinished {
+                command: "r".to_string(),
+                position: 2
+            })
+        );
+        assert_eq!(
+            run("cn Z").unwrap_err().to_string(),
+            "Command 'Z' at character 4 failed: Unknown command 'Z'"
+        );
+        assert_eq!(
+            run("cs").unwrap_err().to_string(),
+            "Command 's' at character 2 failed: Cannot edit the value of 'true'"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Panic hooks are shared by the whole process, so this is tested on its own rather than
+//! alongside the unit tests (which run editors on other threads at the same time)
+
+use sapling::ast_spec::json::{JSONFormat, JSON};
+use sapling::ast_spec::test_json::TestJSON;
+use sapling::editable_tree::spec::Spec;
+use sapling::editor::frontend::TestFrontend;
+use sapling::editor::Editor;
+use sapling::node_map::vec::Index;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static PANICS_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+#[test]
+fn run_keeps_the_previous_panic_hook() {
+    std::panic::set_hook(Box::new(|_| {
+        PANICS_SEEN.fetch_add(1, Ordering::SeqCst);
+    }));
+    let tree: Spec<JSON<Index>> =
+        Spec::from_tree(TestJSON::Array(vec![TestJSON::True]).build_node_map());
+    let frontend = Arc::new(TestFrontend::with_keys(40, 8, "q"));
+    let mut editor = Editor::with_frontend(tree, JSONFormat::Pretty, frontend);
+    assert!(editor.run().is_ok());
+    // The hook set before the editor ran still sees panics once it has finished
+    assert!(std::panic::catch_unwind(|| panic!("after run")).is_err());
+    assert_eq!(PANICS_SEEN.load(Ordering::SeqCst), 1);
+    drop(std::panic::take_hook());
+}